@@ -0,0 +1,245 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+#![deny(warnings)]
+
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use digest::Digest;
+use futures::{stream, StreamExt};
+use protos::google::bytestream::byte_stream_client::ByteStreamClient;
+use protos::google::bytestream::{ReadRequest, WriteRequest};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::{Code, Request};
+
+/// How long to wait to connect to the endpoint.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of each `WriteRequest` sent when uploading a blob.
+const WRITE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Convert a `grpc://` or `grpcs://` (or `http://` or `https://`) endpoint into an Endpoint which
+/// uses TLS for `grpcs://` and `https://`.
+pub fn endpoint(endpoint: &str) -> Result<Endpoint, String> {
+    let (scheme, authority) = endpoint
+        .split_once("://")
+        .ok_or_else(|| format!("Endpoint `{endpoint}` did not start with a scheme"))?;
+    let use_tls = match scheme {
+        "grpc" | "http" => false,
+        "grpcs" | "https" => true,
+        _ => {
+            return Err(format!(
+                "Endpoint `{endpoint}` has unsupported scheme `{scheme}`: expected `grpc` or \
+                 `grpcs`"
+            ))
+        }
+    };
+    let uri = format!("{}://{authority}", if use_tls { "https" } else { "http" });
+    let endpoint = Endpoint::from_shared(uri)
+        .map_err(|e| format!("Invalid endpoint `{endpoint}`: {e}"))?
+        .connect_timeout(CONNECT_TIMEOUT);
+    if !use_tls {
+        return Ok(endpoint);
+    }
+
+    let domain_name = endpoint
+        .uri()
+        .host()
+        .ok_or_else(|| format!("Endpoint `{authority}` did not include a host"))?
+        .to_owned();
+    endpoint
+        .tls_config(ClientTlsConfig::new().domain_name(domain_name))
+        .map_err(|e| format!("Failed to configure TLS for `{authority}`: {e}"))
+}
+
+/// Format a digest as `HASH/SIZE`, as accepted by `parse_digest`.
+pub fn format_digest(digest: &Digest) -> String {
+    format!("{}/{}", digest.hex(), digest.size_bytes)
+}
+
+/// Parse a digest of the form `HASH/SIZE`.
+pub fn parse_digest(digest: &str) -> Result<Digest, String> {
+    let (hash, size) = digest
+        .split_once('/')
+        .ok_or_else(|| format!("Digest `{digest}` was not of the form HASH/SIZE"))?;
+    let size = size
+        .parse::<usize>()
+        .map_err(|_| format!("Digest `{digest}` had an invalid size"))?;
+    Digest::new(hash, size)
+}
+
+/// Uploads and downloads blobs for a single instance using the `ByteStream` API.
+pub struct CasClient {
+    bytestream: ByteStreamClient<Channel>,
+    instance: String,
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl CasClient {
+    /// Connect to the given endpoint, authenticating with the auth token as a bearer token (if
+    /// any).
+    pub async fn connect(
+        endpoint_address: &str,
+        instance: String,
+        auth_token: Option<&str>,
+    ) -> Result<Self, String> {
+        let authorization = auth_token
+            .map(|auth_token| {
+                format!("Bearer {auth_token}").parse().map_err(|_| {
+                    "Auth token contains characters which are not valid in a header".to_owned()
+                })
+            })
+            .transpose()?;
+        let channel = endpoint(endpoint_address)?
+            .connect()
+            .await
+            .map_err(|e| format!("Failed to connect to `{endpoint_address}`: {e}"))?;
+        Ok(CasClient {
+            bytestream: ByteStreamClient::new(channel),
+            instance,
+            authorization,
+        })
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        request
+    }
+
+    /// Prefix a resource name with the instance name, if it is not blank.
+    fn resource_name(&self, resource: String) -> String {
+        if self.instance.is_empty() {
+            resource
+        } else {
+            format!("{}/{resource}", self.instance)
+        }
+    }
+
+    /// Upload `content`, returning its digest.
+    pub async fn put(&mut self, content: Bytes) -> Result<Digest, String> {
+        let digest = Digest::of_bytes(&content)?;
+        let resource_name = self.resource_name(format!(
+            "uploads/{}/blobs/{}/{}",
+            uuid::Uuid::new_v4(),
+            digest.hex(),
+            digest.size_bytes
+        ));
+
+        // Always send at least one request, so that empty blobs are written too.
+        let chunk_count = content.len().div_ceil(WRITE_CHUNK_SIZE).max(1);
+        let requests = (0..chunk_count)
+            .map(|index| {
+                let start = index * WRITE_CHUNK_SIZE;
+                let end = (start + WRITE_CHUNK_SIZE).min(content.len());
+                WriteRequest {
+                    resource_name: if index == 0 {
+                        resource_name.clone()
+                    } else {
+                        String::new()
+                    },
+                    write_offset: start as i64,
+                    finish_write: index == chunk_count - 1,
+                    data: content.slice(start..end),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let request = self.request(stream::iter(requests));
+        let response = self
+            .bytestream
+            .write(request)
+            .await
+            .map_err(|status| format!("Failed to write {}: {status}", format_digest(&digest)))?
+            .into_inner();
+        if response.committed_size != digest.size_bytes as i64 {
+            return Err(format!(
+                "Write of {} committed {} bytes",
+                format_digest(&digest),
+                response.committed_size
+            ));
+        }
+        Ok(digest)
+    }
+
+    /// Download the content of `digest`, verifying that it matches the digest. Returns `None` if
+    /// the blob is not stored.
+    pub async fn get(&mut self, digest: Digest) -> Result<Option<Bytes>, String> {
+        let request = self.request(ReadRequest {
+            resource_name: self.resource_name(format!(
+                "blobs/{}/{}",
+                digest.hex(),
+                digest.size_bytes
+            )),
+            read_offset: 0,
+            read_limit: 0,
+        });
+        let mut responses = match self.bytestream.read(request).await {
+            Ok(response) => response.into_inner(),
+            Err(status) if status.code() == Code::NotFound => return Ok(None),
+            Err(status) => {
+                return Err(format!(
+                    "Failed to read {}: {status}",
+                    format_digest(&digest)
+                ))
+            }
+        };
+
+        let mut content = BytesMut::with_capacity(digest.size_bytes);
+        while let Some(response) = responses.next().await {
+            match response {
+                Ok(response) => content.extend_from_slice(&response.data),
+                Err(status) if status.code() == Code::NotFound => return Ok(None),
+                Err(status) => {
+                    return Err(format!(
+                        "Failed to read {}: {status}",
+                        format_digest(&digest)
+                    ))
+                }
+            }
+        }
+
+        let content = content.freeze();
+        let actual_digest = Digest::of_bytes(&content)?;
+        if actual_digest != digest {
+            return Err(format!(
+                "Read of {} returned content with digest {}",
+                format_digest(&digest),
+                format_digest(&actual_digest)
+            ));
+        }
+        Ok(Some(content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use digest::Digest;
+
+    use super::{format_digest, parse_digest};
+
+    #[test]
+    fn formats_and_parses_digests() {
+        let digest = Digest::of_bytes(&Bytes::from_static(b"foobar")).unwrap();
+        let formatted = format_digest(&digest);
+        assert_eq!(
+            formatted,
+            "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2/6"
+        );
+        assert_eq!(parse_digest(&formatted).unwrap(), digest);
+
+        assert!(
+            parse_digest("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2")
+                .unwrap_err()
+                .contains("HASH/SIZE")
+        );
+        assert!(parse_digest("abc/6").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+#![deny(warnings)]
+
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use clap::{Parser, Subcommand};
+
+use cas_cli::{format_digest, parse_digest, CasClient};
+
+#[derive(Parser)]
+#[command(name = "cas-cli")]
+#[command(author = "Toolchain Labs, Inc.")]
+#[command(version = "0.0.1")]
+#[command(about = "Uploads and downloads CAS blobs, for debugging.", long_about = None)]
+struct CasCommand {
+    /// The address of the CAS (e.g. a storage server or proxy), as `grpc://HOST:PORT` or
+    /// `grpcs://HOST:PORT`.
+    #[arg(long, env = "CAS_ENDPOINT")]
+    endpoint: String,
+    /// The REAPI instance name to read and write blobs under.
+    #[arg(long, env = "CAS_INSTANCE", default_value = "")]
+    instance: String,
+    /// An auth token to send as a bearer token, if the endpoint requires one.
+    #[arg(long, env = "AUTH_TOKEN")]
+    auth_token: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upload a file, and print its digest as `HASH/SIZE`.
+    Put { file: PathBuf },
+    /// Download the blob with the given `HASH/SIZE` digest to a file.
+    Get { digest: String, out: PathBuf },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cmd = CasCommand::parse();
+
+    let mut client =
+        CasClient::connect(&cmd.endpoint, cmd.instance, cmd.auth_token.as_deref()).await?;
+    match cmd.command {
+        Command::Put { file } => {
+            let content = tokio::fs::read(&file)
+                .await
+                .map_err(|err| format!("Failed to read {}: {err}", file.display()))?;
+            let digest = client.put(Bytes::from(content)).await?;
+            println!("{}", format_digest(&digest));
+        }
+        Command::Get { digest, out } => {
+            let digest = parse_digest(&digest)?;
+            let content = client
+                .get(digest)
+                .await?
+                .ok_or_else(|| format!("Blob {} was not found", format_digest(&digest)))?;
+            tokio::fs::write(&out, content)
+                .await
+                .map_err(|err| format!("Failed to write {}: {err}", out.display()))?;
+        }
+    }
+    Ok(())
+}
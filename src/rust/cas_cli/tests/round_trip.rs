@@ -0,0 +1,108 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::process::Output;
+
+use grpc_util::hyper::AddrIncomingWithStream;
+use hyper::server::conn::AddrIncoming;
+use storage::api::{Server, ServerConfig};
+use storage::driver::{BlobStorage, DriverState, Instance, MemoryStorage};
+use tokio::process::Command;
+use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
+
+/// Serve a storage server backed by `MemoryStorage` for the `main` instance, returning its
+/// address. The server is shut down when the returned sender is dropped.
+fn spawn_storage_server() -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let incoming = AddrIncoming::bind(&addr).expect("failed to bind port");
+    let local_addr = incoming.local_addr();
+
+    let instance = Instance::from("main");
+    let cas = MemoryStorage::new();
+    cas.ensure_instance(&instance, DriverState::default());
+    let action_cache = MemoryStorage::new();
+    action_cache.ensure_instance(&instance, DriverState::default());
+
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        Server::new(
+            Box::new(cas),
+            Box::new(action_cache),
+            ServerConfig::default(),
+        )
+        .serve_with_incoming_shutdown(
+            AddrIncomingWithStream(incoming),
+            async move {
+                let _ = shutdown_receiver.await;
+            },
+            None,
+            InFlightRequestsCounter::new(),
+        )
+        .await
+        .unwrap();
+    });
+    (local_addr, shutdown_sender)
+}
+
+async fn cas_cli(addr: SocketAddr, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cas-cli"))
+        .arg("--endpoint")
+        .arg(format!("grpc://{addr}"))
+        .arg("--instance")
+        .arg("main")
+        .args(args)
+        .output()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn puts_and_gets_a_file() {
+    let (addr, _shutdown_sender) = spawn_storage_server();
+    let dir = tempfile::tempdir().unwrap();
+
+    // Larger than a single write chunk.
+    let content = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let input = dir.path().join("input");
+    std::fs::write(&input, &content).unwrap();
+
+    let output = cas_cli(addr, &["put", input.to_str().unwrap()]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let digest = String::from_utf8(output.stdout).unwrap().trim().to_owned();
+    assert!(digest.ends_with("/200000"), "{digest}");
+
+    let out = dir.path().join("out");
+    let output = cas_cli(addr, &["get", &digest, out.to_str().unwrap()]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read(&out).unwrap(), content);
+}
+
+#[tokio::test]
+async fn get_fails_for_missing_blob() {
+    let (addr, _shutdown_sender) = spawn_storage_server();
+    let dir = tempfile::tempdir().unwrap();
+
+    let out = dir.path().join("out");
+    let output = cas_cli(
+        addr,
+        &[
+            "get",
+            "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2/6",
+            out.to_str().unwrap(),
+        ],
+    )
+    .await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("was not found"), "{stderr}");
+    assert!(!out.exists());
+}
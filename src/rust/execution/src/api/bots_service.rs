@@ -4,12 +4,27 @@
 use protos::google::devtools::remoteworkers::v1test2::{
     bots_server::Bots, BotSession, CreateBotSessionRequest, UpdateBotSessionRequest,
 };
+use tokio::time::Duration;
+use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
 use execution_util::{generate_session_name, instance_name_from_session_name};
+use grpc_util::services::grpc_timeout;
 
 use crate::api::ExecutionServer;
-use crate::BOT_POLL_TIMEOUT;
+
+/// Upper bound on how much earlier than the bot's deadline a poll stops waiting for new leases,
+/// which leaves time for the response to reach the bot before it gives up on the request.
+const MAX_DEADLINE_MARGIN: Duration = Duration::from_millis(100);
+
+/// How long to wait for new leases before responding to a bot: `max_timeout`, or less if the bot
+/// set a shorter deadline for its request.
+fn poll_timeout(metadata: &MetadataMap, max_timeout: Duration) -> Duration {
+    match grpc_timeout(metadata) {
+        Some(timeout) => (timeout - (timeout / 10).min(MAX_DEADLINE_MARGIN)).min(max_timeout),
+        None => max_timeout,
+    }
+}
 
 #[tonic::async_trait]
 impl Bots for ExecutionServer {
@@ -18,17 +33,18 @@ impl Bots for ExecutionServer {
         &self,
         request: Request<CreateBotSessionRequest>,
     ) -> Result<Response<BotSession>, Status> {
+        let poll_timeout = poll_timeout(request.metadata(), self.bot_poll_timeout);
         let request = request.into_inner();
         let instance_name = request.parent;
         let mut session = request
             .bot_session
             .ok_or_else(|| Status::invalid_argument("no `bot_session` was set."))?;
 
-        session.name = generate_session_name(&instance_name);
+        session.name = generate_session_name(self.instances.uuid_generator(), &instance_name);
 
         self.instances
             .instance(instance_name)
-            .poll(&mut session, BOT_POLL_TIMEOUT)
+            .poll(&mut session, poll_timeout)
             .await;
 
         Ok(Response::new(session))
@@ -39,6 +55,7 @@ impl Bots for ExecutionServer {
         &self,
         request: Request<UpdateBotSessionRequest>,
     ) -> Result<Response<BotSession>, Status> {
+        let poll_timeout = poll_timeout(request.metadata(), self.bot_poll_timeout);
         let request = request.into_inner();
         let mut session = request.bot_session.ok_or_else(|| {
             Status::invalid_argument("No bot_session in `UpdateBotSessionRequest`")
@@ -49,9 +66,68 @@ impl Bots for ExecutionServer {
 
         self.instances
             .instance(instance_name)
-            .poll(&mut session, BOT_POLL_TIMEOUT)
+            .poll(&mut session, poll_timeout)
             .await;
 
         Ok(Response::new(session))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use execution_util::DefaultUuidGenerator;
+    use protos::google::devtools::remoteworkers::v1test2::{BotSession, UpdateBotSessionRequest};
+    use tokio::time::{Duration, Instant};
+    use tonic::Request;
+
+    use crate::server::{
+        Instances, WorkerExpirationTimeouts, DEFAULT_BOT_POLL_TIMEOUT, DEFAULT_MAX_ATTEMPTS,
+        DEFAULT_OPERATION_RETENTION,
+    };
+
+    use super::poll_timeout;
+
+    #[test]
+    fn poll_timeout_defaults_to_max() {
+        let request = Request::new(UpdateBotSessionRequest::default());
+        assert_eq!(
+            poll_timeout(request.metadata(), DEFAULT_BOT_POLL_TIMEOUT),
+            DEFAULT_BOT_POLL_TIMEOUT
+        );
+
+        // A deadline longer than the max does not extend the poll.
+        let mut request = Request::new(UpdateBotSessionRequest::default());
+        request.set_timeout(Duration::from_secs(60));
+        assert_eq!(
+            poll_timeout(request.metadata(), DEFAULT_BOT_POLL_TIMEOUT),
+            DEFAULT_BOT_POLL_TIMEOUT
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_returns_before_short_deadline() {
+        let instances = Instances::new(
+            DEFAULT_OPERATION_RETENTION,
+            DEFAULT_MAX_ATTEMPTS,
+            WorkerExpirationTimeouts::default(),
+            Arc::new(DefaultUuidGenerator),
+        );
+        let instance = instances.instance("test".to_owned());
+
+        let deadline = Duration::from_millis(300);
+        let mut request = Request::new(UpdateBotSessionRequest::default());
+        request.set_timeout(deadline);
+        let timeout = poll_timeout(request.metadata(), DEFAULT_BOT_POLL_TIMEOUT);
+        assert!(timeout < deadline);
+
+        // With no queued actions, the poll waits until just before the bot's deadline, rather than
+        // for the full default timeout.
+        let poll_began = Instant::now();
+        instance.poll(&mut BotSession::default(), timeout).await;
+        let elapsed = poll_began.elapsed();
+        assert!(elapsed >= timeout);
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::Status;
+
+/// What to do with a CAS request once `max_concurrent_requests` are already in flight.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CasLimitBehavior {
+    /// Wait for an earlier request to complete.
+    #[default]
+    Wait,
+    /// Fail the request (and so the RPC which made it) with `ResourceExhausted`.
+    Reject,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CasConcurrencyLimitConfig {
+    /// Maximum number of requests to the CAS which may be in flight at once. Must be non-zero.
+    pub max_concurrent_requests: usize,
+
+    /// What to do with requests beyond the limit.
+    #[serde(default)]
+    pub when_exhausted: CasLimitBehavior,
+}
+
+/// Bounds the number of concurrent requests which the execution server makes to the CAS, so that
+/// a burst of `Execute` calls cannot overwhelm it.
+#[derive(Clone, Default)]
+pub struct CasLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    when_exhausted: CasLimitBehavior,
+}
+
+impl CasLimiter {
+    /// Create a limiter for the given config, or one which does not limit requests if `None`.
+    pub fn new(config: Option<&CasConcurrencyLimitConfig>) -> Result<Self, String> {
+        let Some(config) = config else {
+            return Ok(CasLimiter::default());
+        };
+        if config.max_concurrent_requests == 0 {
+            return Err(
+                "cas_concurrency_limit.max_concurrent_requests must be non-zero".to_owned(),
+            );
+        }
+        Ok(CasLimiter {
+            semaphore: Some(Arc::new(Semaphore::new(config.max_concurrent_requests))),
+            when_exhausted: config.when_exhausted,
+        })
+    }
+
+    /// Acquire a permit to make a request to the CAS, which should be held until the request
+    /// completes. Returns `None` if requests are not limited.
+    pub(crate) async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, Status> {
+        let Some(semaphore) = &self.semaphore else {
+            return Ok(None);
+        };
+        let permit = match self.when_exhausted {
+            CasLimitBehavior::Wait => semaphore.clone().acquire_owned().await.ok(),
+            CasLimitBehavior::Reject => semaphore.clone().try_acquire_owned().ok(),
+        };
+        match permit {
+            Some(permit) => Ok(Some(permit)),
+            None => {
+                metrics::increment_counter!("toolchain_execution_cas_requests_rejected_total");
+                Err(Status::resource_exhausted(
+                    "too many concurrent requests to the CAS",
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tokio::time::Duration;
+    use tonic::Code;
+
+    use super::{CasConcurrencyLimitConfig, CasLimitBehavior, CasLimiter};
+
+    fn limiter(max_concurrent_requests: usize, when_exhausted: CasLimitBehavior) -> CasLimiter {
+        CasLimiter::new(Some(&CasConcurrencyLimitConfig {
+            max_concurrent_requests,
+            when_exhausted,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn waits_for_permits_beyond_the_limit() {
+        let limiter = limiter(2, CasLimitBehavior::Wait);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let fetches = (0..10)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire().await.unwrap();
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        for fetch in fetches {
+            fetch.await.unwrap();
+        }
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_beyond_the_limit() {
+        let limiter = limiter(1, CasLimitBehavior::Reject);
+
+        let permit = limiter.acquire().await.unwrap();
+        assert_eq!(
+            limiter.acquire().await.unwrap_err().code(),
+            Code::ResourceExhausted
+        );
+
+        drop(permit);
+        assert!(limiter.acquire().await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn does_not_limit_by_default() {
+        let limiter = CasLimiter::new(None).unwrap();
+        assert!(limiter.acquire().await.unwrap().is_none());
+
+        let config = CasConcurrencyLimitConfig {
+            max_concurrent_requests: 0,
+            when_exhausted: CasLimitBehavior::Wait,
+        };
+        assert!(CasLimiter::new(Some(&config)).is_err());
+    }
+}
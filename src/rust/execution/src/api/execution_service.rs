@@ -7,8 +7,8 @@ use digest::{required_digest, Digest};
 use futures::Stream;
 use prost::Message;
 use protos::build::bazel::remote::execution::v2::{
-    execution_server::Execution, Action as ActionRequest, BatchReadBlobsRequest, ExecuteRequest,
-    ExecuteResponse, WaitExecutionRequest,
+    execution_server::Execution, Action as ActionRequest, BatchReadBlobsRequest, Command,
+    ExecuteRequest, ExecuteResponse, WaitExecutionRequest,
 };
 use protos::google::longrunning::{operation, Operation};
 use tokio::sync::watch;
@@ -17,6 +17,7 @@ use tonic::{Code, Request, Response, Status};
 use execution_util::{instance_name_from_operation_name, InstanceName, OperationName};
 
 use crate::any_proto_encode;
+use crate::api::validation::validate_action;
 use crate::api::ExecutionServer;
 use crate::server::ActionStatus;
 
@@ -36,9 +37,15 @@ impl Execution for ExecutionServer {
 
         let action_digest = required_digest("action_digest", request.action_digest)
             .map_err(Status::invalid_argument)?;
-        let action = self
-            .load_action(request.instance_name, action_digest)
+        let action: ActionRequest = self
+            .load_message(request.instance_name.clone(), action_digest, "action")
             .await?;
+        let command_digest = required_digest("command_digest", action.command_digest.clone())
+            .map_err(Status::invalid_argument)?;
+        let command: Command = self
+            .load_message(request.instance_name, command_digest, "command")
+            .await?;
+        validate_action(&action, &command).map_err(Status::invalid_argument)?;
 
         let (operation_name, receiver) = instance.execute(action_digest, action);
 
@@ -63,7 +70,9 @@ impl Execution for ExecutionServer {
             .instances
             .instance(instance_name)
             .wait(&operation_name)
-            .ok_or_else(|| Status::not_found("no known operation named {operation_name}"))?;
+            .ok_or_else(|| {
+                Status::not_found(format!("no known operation named {operation_name}"))
+            })?;
 
         Ok(Response::new(stream_from_receiver(
             operation_name,
@@ -74,17 +83,19 @@ impl Execution for ExecutionServer {
 
 impl ExecutionServer {
     // TODO: Add retry.
-    async fn load_action(
+    async fn load_message<T: Message + Default>(
         &self,
         instance_name: InstanceName,
-        action_digest: Digest,
-    ) -> Result<ActionRequest, Status> {
+        digest: Digest,
+        message_name: &str,
+    ) -> Result<T, Status> {
+        let _permit = self.cas_limiter.acquire().await?;
         let mut responses = self
             .cas_client
             .clone()
             .batch_read_blobs(BatchReadBlobsRequest {
                 instance_name,
-                digests: vec![action_digest.into()],
+                digests: vec![digest.into()],
             })
             .await?
             .into_inner();
@@ -104,74 +115,194 @@ impl ExecutionServer {
             _ => (),
         }
 
-        ActionRequest::decode(response.data)
-            .map_err(|e| Status::internal(format!("Could not decode action: {e}")))
+        T::decode(response.data)
+            .map_err(|e| Status::internal(format!("Could not decode {message_name}: {e}")))
     }
 }
 
+/// Stream the status of an operation, starting with its current status.
+///
+/// NB: Receivers handed out for an operation by `Instance::wait` are cloned from the one indexed
+/// for it, and so may not have seen the current status yet. Marking the current status as seen
+/// when yielding it prevents it from being reported twice to a client which reattaches.
 fn stream_from_receiver(
     name: OperationName,
     mut receiver: watch::Receiver<ActionStatus>,
 ) -> OperationStream {
     let stream = async_stream::stream! {
-      let item = loop {
-          let value = (*receiver.borrow()).clone();
-          match value {
-            ActionStatus::Running(eom) => {
-              yield Ok(Operation {
-                name: name.clone(),
-                done: false,
-                metadata: Some(any_proto_encode(&eom)),
-                ..Default::default()
-              });
-            },
-            ActionStatus::Completed(item) => break Some(item),
+      loop {
+          let value = (*receiver.borrow_and_update()).clone();
+          let done = matches!(value, ActionStatus::Completed(_));
+          yield Ok(operation_for_status(name.clone(), value));
+          if done {
+            break;
           }
 
           if let Err(_recv_error) = receiver.changed().await {
-            break None
+            let cancelled = ActionStatus::Completed(Err(Status::cancelled("")));
+            yield Ok(operation_for_status(name, cancelled));
+            break;
           }
-      };
-
-      let (status, result) = match item {
-        Some(Ok(action_result)) => {
-          let status = protos::google::rpc::Status {
-            code: Code::Ok as i32,
-            ..Default::default()
-          };
-          (status, Some(action_result))
+      }
+    };
+    Box::pin(stream)
+}
+
+/// Convert the status of an Action into the Operation reported for one of its operations.
+pub(super) fn operation_for_status(name: OperationName, status: ActionStatus) -> Operation {
+    let item = match status {
+        ActionStatus::Running(eom) => {
+            return Operation {
+                name,
+                done: false,
+                metadata: Some(any_proto_encode(&eom)),
+                ..Default::default()
+            }
         }
-        Some(Err(status)) => {
-          let status = protos::google::rpc::Status {
-            code: status.code() as i32,
-            message: status.message().to_owned(),
-            ..Default::default()
-          };
-          (status, None)
+        ActionStatus::Completed(item) => item,
+    };
+
+    let (status, result) = match item {
+        Ok(action_result) => {
+            let status = protos::google::rpc::Status {
+                code: Code::Ok as i32,
+                ..Default::default()
+            };
+            (status, Some(action_result))
         }
-        None => {
-          let status = protos::google::rpc::Status {
-            code: Code::Cancelled as i32,
-            ..Default::default()
-          };
-          (status, None)
+        Err(status) => {
+            let status = protos::google::rpc::Status {
+                code: status.code() as i32,
+                message: status.message().to_owned(),
+                ..Default::default()
+            };
+            (status, None)
         }
-      };
+    };
 
-      yield Ok(Operation {
+    Operation {
         name,
         done: true,
-        result: Some(
-          operation::Result::Response(any_proto_encode(
+        result: Some(operation::Result::Response(any_proto_encode(
             &ExecuteResponse {
-              result,
-              status: Some(status),
-              ..Default::default()
+                result,
+                status: Some(status),
+                ..Default::default()
             },
-          )),
-        ),
+        ))),
         ..Default::default()
-      });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use digest::Digest;
+    use execution_util::DefaultUuidGenerator;
+    use futures::StreamExt;
+    use protos::build::bazel::remote::execution::v2::{
+        execution_stage::Value as ExecutionStageValue, Action as ActionRequest, ActionResult,
+        ExecuteOperationMetadata, ExecuteResponse,
     };
-    Box::pin(stream)
+    use protos::google::devtools::remoteworkers::v1test2::{BotSession, LeaseState};
+    use protos::google::longrunning::{operation, Operation};
+    use tokio::time::{timeout, Duration};
+
+    use crate::server::{
+        Instances, WorkerExpirationTimeouts, DEFAULT_MAX_ATTEMPTS, DEFAULT_OPERATION_RETENTION,
+    };
+    use crate::{any_proto_decode, any_proto_encode};
+
+    use super::{stream_from_receiver, OperationStream};
+
+    async fn next_operation(stream: &mut OperationStream) -> Operation {
+        timeout(Duration::from_secs(10), stream.next())
+            .await
+            .expect("timed out waiting for an operation update")
+            .expect("operation stream ended early")
+            .unwrap()
+    }
+
+    fn stage(operation: &Operation) -> i32 {
+        assert!(!operation.done);
+        any_proto_decode::<ExecuteOperationMetadata>(operation.metadata.as_ref())
+            .unwrap()
+            .stage
+    }
+
+    #[tokio::test]
+    async fn wait_execution_reattaches_after_disconnect() {
+        let instances = Instances::new(
+            DEFAULT_OPERATION_RETENTION,
+            DEFAULT_MAX_ATTEMPTS,
+            WorkerExpirationTimeouts::default(),
+            Arc::new(DefaultUuidGenerator),
+        );
+        let instance = instances.instance("test".to_owned());
+
+        // Two clients execute the same action, and so share a single underlying action.
+        let (first_name, first_receiver) =
+            instance.execute(Digest::EMPTY, ActionRequest::default());
+        let (second_name, second_receiver) =
+            instance.execute(Digest::EMPTY, ActionRequest::default());
+        assert_ne!(first_name, second_name);
+        let mut first_stream = stream_from_receiver(first_name.clone(), first_receiver);
+
+        // The second client observes the queued action, and then disconnects.
+        let mut second_stream = stream_from_receiver(second_name.clone(), second_receiver);
+        assert_eq!(
+            stage(&next_operation(&mut second_stream).await),
+            ExecutionStageValue::Queued as i32
+        );
+        drop(second_stream);
+
+        // A worker starts executing the action while the second client is disconnected.
+        let mut session = BotSession::default();
+        instance.poll(&mut session, Duration::from_secs(10)).await;
+        assert_eq!(session.leases.len(), 1);
+
+        // On reattaching, the second client immediately observes the current stage...
+        let receiver = instance.wait(&second_name).unwrap();
+        let mut second_stream = stream_from_receiver(second_name.clone(), receiver);
+        let operation = next_operation(&mut second_stream).await;
+        assert_eq!(operation.name, second_name);
+        assert_eq!(stage(&operation), ExecutionStageValue::Executing as i32);
+        assert!(
+            timeout(Duration::from_millis(100), second_stream.next())
+                .await
+                .is_err(),
+            "the current stage should only be reported once"
+        );
+
+        // ...and then the result once the action completes.
+        for lease in &mut session.leases {
+            lease.result = Some(any_proto_encode(&ActionResult {
+                exit_code: 7,
+                ..Default::default()
+            }));
+            lease.state = LeaseState::Completed as i32;
+            lease.status = Some(protos::google::rpc::Status {
+                code: protos::google::rpc::Code::Ok as i32,
+                ..Default::default()
+            });
+        }
+        instance.poll(&mut session, Duration::from_millis(10)).await;
+
+        // The first client, which never polled its stream, also observes only the result.
+        for (name, stream) in [
+            (&second_name, &mut second_stream),
+            (&first_name, &mut first_stream),
+        ] {
+            let operation = next_operation(stream).await;
+            assert!(operation.done);
+            assert_eq!(&operation.name, name);
+            let Some(operation::Result::Response(response)) = operation.result.as_ref() else {
+                panic!("operation did not complete with a response: {operation:?}");
+            };
+            let response = any_proto_decode::<ExecuteResponse>(Some(response)).unwrap();
+            assert_eq!(response.result.unwrap().exit_code, 7);
+            assert!(stream.next().await.is_none());
+        }
+    }
 }
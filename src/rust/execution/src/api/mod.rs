@@ -3,30 +3,64 @@
 
 mod bots_service;
 mod capabilities_service;
+mod cas_limiter;
 mod execution_service;
 mod operations_service;
+mod validation;
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use execution_util::{DefaultUuidGenerator, InstanceName};
 use ginepro::LoadBalancedChannel;
+use tokio::time::Duration;
 
 use protos::build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
 
-use crate::server::Instances;
+use crate::server::{InstanceStats, Instances, WorkerExpirationTimeouts};
+
+pub use cas_limiter::{CasConcurrencyLimitConfig, CasLimitBehavior, CasLimiter};
 
 #[derive(Clone)]
 pub struct ExecutionServer {
     instances: Instances,
     cas_client: ContentAddressableStorageClient<LoadBalancedChannel>,
+    cas_limiter: CasLimiter,
+    bot_poll_timeout: Duration,
 }
 
 impl ExecutionServer {
-    pub fn new(cas_client: ContentAddressableStorageClient<LoadBalancedChannel>) -> Self {
+    /// Completed operations remain available to `WaitExecution` for `operation_retention`, and
+    /// actions are failed after being leased `max_attempts` times. Bots wait for new leases for
+    /// at most `bot_poll_timeout`, and expire after `worker_expiration_timeouts` without polling.
+    /// Requests made to the CAS are bounded by `cas_limiter`.
+    pub fn new(
+        cas_client: ContentAddressableStorageClient<LoadBalancedChannel>,
+        cas_limiter: CasLimiter,
+        operation_retention: Duration,
+        max_attempts: u32,
+        bot_poll_timeout: Duration,
+        worker_expiration_timeouts: WorkerExpirationTimeouts,
+    ) -> Self {
         Self {
-            instances: Instances::default(),
+            instances: Instances::new(
+                operation_retention,
+                max_attempts,
+                worker_expiration_timeouts,
+                Arc::new(DefaultUuidGenerator),
+            ),
             cas_client,
+            cas_limiter,
+            bot_poll_timeout,
         }
     }
 
     pub fn update_gauges(&self) {
         self.instances.update_gauges();
     }
+
+    /// The number of queued actions, executing actions and workers for each instance.
+    pub fn instance_stats(&self) -> BTreeMap<InstanceName, InstanceStats> {
+        self.instances.stats()
+    }
 }
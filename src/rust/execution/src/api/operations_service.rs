@@ -10,10 +10,18 @@ use tonic::{Request, Response, Status};
 
 use execution_util::instance_name_from_operation_name;
 
+use crate::api::execution_service::operation_for_status;
 use crate::api::ExecutionServer;
+use crate::server::OperationStage;
 
-/// NB: This interface is only implementated in order to support client side cancellation of
-/// operations, and so many methods are stubbed.
+/// Page size used for `ListOperations` when the request does not set one.
+const DEFAULT_LIST_OPERATIONS_PAGE_SIZE: usize = 100;
+
+/// Upper bound on the page size for `ListOperations`.
+const MAX_LIST_OPERATIONS_PAGE_SIZE: usize = 1000;
+
+/// NB: This interface is only implementated in order to support client side cancellation and
+/// listing of operations, and so many methods are stubbed.
 #[tonic::async_trait]
 impl Operations for ExecutionServer {
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
@@ -31,9 +39,42 @@ impl Operations for ExecutionServer {
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn list_operations(
         &self,
-        _request: Request<ListOperationsRequest>,
+        request: Request<ListOperationsRequest>,
     ) -> Result<Response<ListOperationsResponse>, Status> {
-        Err(Status::unimplemented("list_operations"))
+        let request = request.into_inner();
+        let stage = parse_stage_filter(&request.filter).map_err(Status::invalid_argument)?;
+        let page_size = match request.page_size {
+            page_size if page_size < 0 => {
+                return Err(Status::invalid_argument("page_size must not be negative"))
+            }
+            0 => DEFAULT_LIST_OPERATIONS_PAGE_SIZE,
+            page_size => (page_size as usize).min(MAX_LIST_OPERATIONS_PAGE_SIZE),
+        };
+        let page_token = if request.page_token.is_empty() {
+            None
+        } else {
+            Some(
+                request
+                    .page_token
+                    .parse::<u64>()
+                    .map_err(|_| Status::invalid_argument("invalid page_token"))?,
+            )
+        };
+
+        let (operations, next_page_token) = self
+            .instances
+            .instance(request.name)
+            .list_operations(stage, page_size, page_token);
+
+        Ok(Response::new(ListOperationsResponse {
+            operations: operations
+                .into_iter()
+                .map(|(operation_name, status)| operation_for_status(operation_name, status))
+                .collect(),
+            next_page_token: next_page_token
+                .map(|page_token| page_token.to_string())
+                .unwrap_or_default(),
+        }))
     }
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
@@ -60,3 +101,50 @@ impl Operations for ExecutionServer {
         Err(Status::unimplemented("wait_operation"))
     }
 }
+
+/// Parse a `ListOperations` filter, which is either empty or of the form `stage=<stage>` where
+/// `<stage>` is one of `queued`, `executing` or `completed`.
+fn parse_stage_filter(filter: &str) -> Result<Option<OperationStage>, String> {
+    let filter = filter.trim();
+    if filter.is_empty() {
+        return Ok(None);
+    }
+    let stage = match filter.split_once('=') {
+        Some((key, value)) if key.trim() == "stage" => value.trim(),
+        _ => {
+            return Err(format!(
+                "unsupported filter `{filter}`: expected `stage=<stage>`"
+            ))
+        }
+    };
+    match stage {
+        "queued" => Ok(Some(OperationStage::Queued)),
+        "executing" => Ok(Some(OperationStage::Executing)),
+        "completed" => Ok(Some(OperationStage::Completed)),
+        _ => Err(format!(
+            "unknown stage `{stage}`: expected `queued`, `executing` or `completed`"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::OperationStage;
+
+    use super::parse_stage_filter;
+
+    #[test]
+    fn parses_stage_filter() {
+        assert_eq!(parse_stage_filter(""), Ok(None));
+        assert_eq!(
+            parse_stage_filter("stage=queued"),
+            Ok(Some(OperationStage::Queued))
+        );
+        assert_eq!(
+            parse_stage_filter(" stage = completed "),
+            Ok(Some(OperationStage::Completed))
+        );
+        assert!(parse_stage_filter("stage=done").is_err());
+        assert!(parse_stage_filter("done=true").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use protos::build::bazel::remote::execution::v2::{Action as ActionRequest, Command, Platform};
+
+/// Check that an Action and its Command are structurally valid, so that malformed requests are
+/// rejected before they are queued rather than failing on a worker.
+pub(super) fn validate_action(action: &ActionRequest, command: &Command) -> Result<(), String> {
+    if command.arguments.is_empty() {
+        return Err("command must have at least one argument".to_owned());
+    }
+    if command.arguments[0].is_empty() {
+        return Err("command must not have an empty program name".to_owned());
+    }
+
+    if let Some(platform) = &action.platform {
+        validate_platform("action", platform)?;
+    }
+    if let Some(platform) = &command.platform {
+        validate_platform("command", platform)?;
+    }
+
+    if !command.working_directory.is_empty() {
+        validate_relative_path("working_directory", &command.working_directory)?;
+    }
+    for (field, paths) in [
+        ("output_files", &command.output_files),
+        ("output_directories", &command.output_directories),
+        ("output_paths", &command.output_paths),
+    ] {
+        for path in paths {
+            validate_relative_path(field, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Platform properties must have names, and be sorted by name and then value so that equivalent
+/// platforms have the same digest.
+fn validate_platform(owner: &str, platform: &Platform) -> Result<(), String> {
+    if let Some(property) = platform.properties.iter().find(|p| p.name.is_empty()) {
+        return Err(format!(
+            "{owner} platform has a property with an empty name (value `{}`)",
+            property.value
+        ));
+    }
+    let sorted = platform
+        .properties
+        .windows(2)
+        .all(|pair| (&pair[0].name, &pair[0].value) <= (&pair[1].name, &pair[1].value));
+    if !sorted {
+        return Err(format!(
+            "{owner} platform properties must be sorted by name and then value"
+        ));
+    }
+    Ok(())
+}
+
+/// Paths in a Command are relative to the input root (or working directory), and must not have
+/// leading or trailing slashes.
+fn validate_relative_path(field: &str, path: &str) -> Result<(), String> {
+    if path.is_empty() {
+        return Err(format!("{field} must not contain empty paths"));
+    }
+    if path.starts_with('/') {
+        return Err(format!("{field} path `{path}` must be relative"));
+    }
+    if path.ends_with('/') {
+        return Err(format!(
+            "{field} path `{path}` must not have a trailing slash"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use protos::build::bazel::remote::execution::v2::platform::Property;
+    use protos::build::bazel::remote::execution::v2::{Action as ActionRequest, Command, Platform};
+
+    use super::validate_action;
+
+    fn platform(properties: &[(&str, &str)]) -> Option<Platform> {
+        Some(Platform {
+            properties: properties
+                .iter()
+                .map(|(name, value)| Property {
+                    name: (*name).to_owned(),
+                    value: (*value).to_owned(),
+                })
+                .collect(),
+        })
+    }
+
+    fn valid_command() -> Command {
+        Command {
+            arguments: vec!["/bin/echo".to_owned(), "hello".to_owned()],
+            output_files: vec!["out/hello.txt".to_owned()],
+            output_directories: vec!["out/dir".to_owned()],
+            working_directory: "src".to_owned(),
+            platform: platform(&[("OSFamily", "linux"), ("gpu", "a"), ("gpu", "b")]),
+            ..Command::default()
+        }
+    }
+
+    #[test]
+    fn accepts_valid_action() {
+        let action = ActionRequest {
+            platform: platform(&[("OSFamily", "linux")]),
+            ..ActionRequest::default()
+        };
+        assert_eq!(validate_action(&action, &valid_command()), Ok(()));
+        assert_eq!(
+            validate_action(
+                &ActionRequest::default(),
+                &Command {
+                    arguments: vec!["true".to_owned()],
+                    ..Command::default()
+                }
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        let action = ActionRequest::default();
+        let invalid_commands = [
+            Command {
+                arguments: vec![],
+                ..valid_command()
+            },
+            Command {
+                arguments: vec!["".to_owned()],
+                ..valid_command()
+            },
+            Command {
+                platform: platform(&[("gpu", "a"), ("OSFamily", "linux")]),
+                ..valid_command()
+            },
+            Command {
+                platform: platform(&[("", "linux")]),
+                ..valid_command()
+            },
+            Command {
+                output_files: vec!["/tmp/out.txt".to_owned()],
+                ..valid_command()
+            },
+            Command {
+                output_paths: vec!["out/".to_owned()],
+                ..valid_command()
+            },
+            Command {
+                working_directory: "/src".to_owned(),
+                ..valid_command()
+            },
+        ];
+        for command in invalid_commands {
+            assert!(
+                validate_action(&action, &command).is_err(),
+                "{command:?} should be rejected"
+            );
+        }
+
+        let action = ActionRequest {
+            platform: platform(&[("b", ""), ("a", "")]),
+            ..ActionRequest::default()
+        };
+        assert_eq!(
+            validate_action(&action, &valid_command()),
+            Err("action platform properties must be sorted by name and then value".to_owned())
+        );
+    }
+}
@@ -5,6 +5,7 @@
 #![allow(clippy::new_without_default, clippy::len_without_is_empty)]
 
 pub mod api;
+pub mod readiness;
 pub mod server;
 
 use std::future::Future;
@@ -13,7 +14,6 @@ use bytes::BytesMut;
 use futures::Stream;
 use prost::Message;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::time::Duration;
 use tonic::transport::server::Connected;
 use tower::ServiceBuilder;
 use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
@@ -25,9 +25,6 @@ use grpc_util::services::GrpcMetrics;
 
 use crate::api::ExecutionServer;
 
-// TODO: This should be based on gRPC deadlines.
-const BOT_POLL_TIMEOUT: Duration = Duration::from_secs(5);
-
 fn any_proto_encode<T: Message>(message: &T) -> prost_types::Any {
     let rust_type_name = std::any::type_name::<T>();
     let proto_type_name = rust_type_name
@@ -63,25 +60,34 @@ where
     IE: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
     F: Future<Output = ()>,
 {
+    let grpc_config = grpc_config.unwrap_or_default();
+    let max_decoding_message_size = grpc_config.max_decoding_message_size();
+    let max_encoding_message_size = grpc_config.max_encoding_message_size();
+
     let bots_server =
         protos::google::devtools::remoteworkers::v1test2::bots_server::BotsServer::new(
             server.clone(),
-        );
+        )
+        .max_decoding_message_size(max_decoding_message_size)
+        .max_encoding_message_size(max_encoding_message_size);
     let capabilities_server =
         protos::build::bazel::remote::execution::v2::capabilities_server::CapabilitiesServer::new(
             server.clone(),
-        );
+        )
+        .max_decoding_message_size(max_decoding_message_size)
+        .max_encoding_message_size(max_encoding_message_size);
     let execution_server =
         protos::build::bazel::remote::execution::v2::execution_server::ExecutionServer::new(
             server.clone(),
-        );
+        )
+        .max_decoding_message_size(max_decoding_message_size)
+        .max_encoding_message_size(max_encoding_message_size);
     let operations_server =
-        protos::google::longrunning::operations_server::OperationsServer::new(server);
+        protos::google::longrunning::operations_server::OperationsServer::new(server)
+            .max_decoding_message_size(max_decoding_message_size)
+            .max_encoding_message_size(max_encoding_message_size);
 
-    let mut server = tonic::transport::Server::builder();
-    if let Some(c) = grpc_config.as_ref() {
-        server = c.apply_to_server(server);
-    }
+    let server = grpc_config.apply_to_server(tonic::transport::Server::builder());
 
     let in_flight_requests_layer = InFlightRequestsLayer::new(in_flight_requests_counter);
     let auth_header_sensitive_layer =
@@ -0,0 +1,119 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use protos::build::bazel::remote::execution::v2::capabilities_client::CapabilitiesClient;
+use protos::build::bazel::remote::execution::v2::GetCapabilitiesRequest;
+use tokio::time::{Duration, Instant};
+use tonic::codegen::{Body, Bytes, StdError};
+
+/// Delay before the first retry of an unreachable CAS.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Upper bound on the delay between two attempts to reach the CAS.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Wait until the CAS responds to `GetCapabilities`, retrying with exponential back-off between
+/// attempts. Fails if the CAS is still not reachable after `max_wait`.
+pub async fn wait_for_cas<T>(
+    mut client: CapabilitiesClient<T>,
+    max_wait: Duration,
+) -> Result<(), String>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let deadline = Instant::now() + max_wait;
+    let mut retry_delay = INITIAL_RETRY_DELAY;
+    loop {
+        let result = tokio::time::timeout_at(
+            deadline,
+            client.get_capabilities(GetCapabilitiesRequest::default()),
+        )
+        .await;
+        let status = match result {
+            Ok(Ok(_)) => return Ok(()),
+            Ok(Err(status)) => status,
+            Err(_) => return Err(format!("CAS was not reachable within {max_wait:?}")),
+        };
+
+        if Instant::now() + retry_delay >= deadline {
+            return Err(format!(
+                "CAS was not reachable within {max_wait:?}: {status}"
+            ));
+        }
+        log::warn!("CAS is not reachable yet, retrying in {retry_delay:?}: {status}");
+        tokio::time::sleep(retry_delay).await;
+        retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+
+    use protos::build::bazel::remote::execution::v2::capabilities_client::CapabilitiesClient;
+    use protos::build::bazel::remote::execution::v2::capabilities_server::{
+        Capabilities, CapabilitiesServer,
+    };
+    use protos::build::bazel::remote::execution::v2::{GetCapabilitiesRequest, ServerCapabilities};
+    use tokio::time::{Duration, Instant};
+    use tonic::transport::{Endpoint, Server};
+    use tonic::{Request, Response, Status};
+
+    use super::wait_for_cas;
+
+    struct MockCapabilitiesService;
+
+    #[tonic::async_trait]
+    impl Capabilities for MockCapabilitiesService {
+        async fn get_capabilities(
+            &self,
+            _request: Request<GetCapabilitiesRequest>,
+        ) -> Result<Response<ServerCapabilities>, Status> {
+            Ok(Response::new(ServerCapabilities::default()))
+        }
+    }
+
+    /// Find a port which nothing is listening on (yet).
+    fn unused_addr() -> SocketAddr {
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn make_client(addr: SocketAddr) -> CapabilitiesClient<tonic::transport::Channel> {
+        let endpoint = Endpoint::from_shared(format!("http://{addr}")).unwrap();
+        CapabilitiesClient::new(endpoint.connect_lazy())
+    }
+
+    #[tokio::test]
+    async fn waits_for_delayed_cas() {
+        let addr = unused_addr();
+        let startup_delay = Duration::from_millis(500);
+        tokio::spawn(async move {
+            tokio::time::sleep(startup_delay).await;
+            Server::builder()
+                .add_service(CapabilitiesServer::new(MockCapabilitiesService))
+                .serve(addr)
+                .await
+                .unwrap();
+        });
+
+        let start = Instant::now();
+        wait_for_cas(make_client(addr), Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= startup_delay);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_unreachable_cas() {
+        let err = wait_for_cas(make_client(unused_addr()), Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(err.contains("not reachable"));
+    }
+}
@@ -4,7 +4,8 @@
 #[cfg(test)]
 mod tests;
 
-use std::collections::{hash_map, HashMap, HashSet, VecDeque};
+use std::cell::Cell;
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Weak};
 
 use digest::Digest;
@@ -14,18 +15,92 @@ use protos::build::bazel::remote::execution::v2::{
     ExecuteOperationMetadata,
 };
 use protos::google::devtools::remoteworkers::v1test2::{BotSession, Lease, LeaseState};
+use serde::Serialize;
 use tokio::sync::watch;
 use tokio::time::{sleep_until, timeout_at, Duration, Instant};
 use tonic::{Code, Status};
 
 use execution_util::{
-    generate_operation_name, generate_uuid, InstanceName, OperationName, SessionName,
+    generate_operation_name, DefaultUuidGenerator, InstanceName, OperationName, SessionName,
+    UuidGenerator,
 };
 
 use crate::{any_proto_decode, any_proto_encode};
 
 pub(crate) type ActionDigest = Digest;
 
+pub(crate) type SharedUuidGenerator = Arc<dyn UuidGenerator + Send + Sync>;
+
+/// Default time for which completed operations remain available to `WaitExecution`.
+pub const DEFAULT_OPERATION_RETENTION: Duration = Duration::from_secs(600);
+
+/// Default number of times an Action may be leased before it is failed rather than re-queued.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Default maximum time for which a bot's poll waits for new leases. Polls end sooner if the bot
+/// set a shorter deadline.
+pub const DEFAULT_BOT_POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time after which the session of a worker which has stopped polling expires, and its
+/// leases are re-queued.
+pub const DEFAULT_WORKER_EXPIRATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The time after which the sessions of workers which have stopped polling expire, optionally
+/// overridden per instance for worker fleets with longer intervals between polls.
+#[derive(Clone, Debug)]
+pub struct WorkerExpirationTimeouts {
+    default: Duration,
+    per_instance: HashMap<InstanceName, Duration>,
+}
+
+impl Default for WorkerExpirationTimeouts {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_WORKER_EXPIRATION_TIMEOUT,
+            per_instance: HashMap::new(),
+        }
+    }
+}
+
+impl WorkerExpirationTimeouts {
+    /// Fails if any of the timeouts are zero, since workers would then expire between polls.
+    pub fn new(
+        default: Duration,
+        per_instance: HashMap<InstanceName, Duration>,
+    ) -> Result<Self, String> {
+        if default.is_zero() {
+            return Err("The worker expiration timeout must be non-zero".to_owned());
+        }
+        if let Some((instance_name, _)) = per_instance.iter().find(|(_, t)| t.is_zero()) {
+            return Err(format!(
+                "The worker expiration timeout for instance `{instance_name}` must be non-zero"
+            ));
+        }
+        Ok(Self {
+            default,
+            per_instance,
+        })
+    }
+
+    fn for_instance(&self, instance_name: &str) -> Duration {
+        self.per_instance
+            .get(instance_name)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// A snapshot of the state of an Instance, as reported by the `list_instances` admin action.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct InstanceStats {
+    /// Actions waiting to be leased to a worker.
+    pub queued_actions: usize,
+    /// Actions which have been leased to a worker.
+    pub executing_actions: usize,
+    /// Workers with a live session.
+    pub workers: usize,
+}
+
 type WorkerName = String;
 
 type LeaseId = String;
@@ -48,11 +123,52 @@ impl ActionStatus {
 }
 
 struct Action {
-    digest: ActionDigest,
     request: ActionRequest,
     sender: watch::Sender<ActionStatus>,
-    // TODO: Should (optionally) expire Operations.
     receivers: HashMap<OperationName, watch::Receiver<ActionStatus>>,
+    /// When the Action was queued, until it is first leased. NB: A `Cell` because Actions are
+    /// started via shared references under the Actions lock.
+    enqueued_at: Cell<Option<Instant>>,
+    /// When the Action was last queued, including when it is re-queued after losing a lease.
+    queued_at: Cell<Instant>,
+    /// The number of times the Action has been leased.
+    attempts: Cell<u32>,
+}
+
+/// The Action which an operation is waiting on.
+struct IndexedOperation {
+    action_digest: ActionDigest,
+    /// Increases with each operation created by an Instance, to order operations by age.
+    sequence: u64,
+}
+
+/// An operation whose Action has completed, retained so that clients can still wait on it.
+struct CompletedOperation {
+    receiver: watch::Receiver<ActionStatus>,
+    completed_at: Instant,
+    sequence: u64,
+}
+
+/// The stage of an operation, as used to filter listed operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OperationStage {
+    Queued,
+    Executing,
+    Completed,
+}
+
+impl OperationStage {
+    fn of(status: &ActionStatus) -> Self {
+        match status {
+            ActionStatus::Running(metadata)
+                if metadata.stage == ExecutionStageValue::Queued as i32 =>
+            {
+                OperationStage::Queued
+            }
+            ActionStatus::Running(_) => OperationStage::Executing,
+            ActionStatus::Completed(_) => OperationStage::Completed,
+        }
+    }
 }
 
 impl Action {
@@ -68,10 +184,12 @@ impl Action {
         receivers.insert(initial_operation_name, sender.subscribe());
 
         let action = Action {
-            digest,
             request,
             sender,
             receivers,
+            enqueued_at: Cell::new(Some(Instant::now())),
+            queued_at: Cell::new(Instant::now()),
+            attempts: Cell::new(0),
         };
         (action, receiver)
     }
@@ -82,7 +200,12 @@ impl Action {
         actions_ref: Arc<Mutex<Actions>>,
         action_digest: ActionDigest,
     ) -> (Lease, RunningAction) {
-        let lease = create_lease(&self.request);
+        if let Some(enqueued_at) = self.enqueued_at.take() {
+            metrics::histogram!("toolchain_execution_queue_wait_seconds", enqueued_at.elapsed(), "customer_id" => actions.instance_name.clone());
+        }
+        metrics::histogram!("toolchain_execution_assignment_latency_seconds", self.queued_at.get().elapsed(), "customer_id" => actions.instance_name.clone());
+        self.attempts.set(self.attempts.get() + 1);
+        let lease = create_lease(&self.request, actions.uuid_generator.generate_uuid());
         let running_action = RunningAction::new(lease.id.clone(), action_digest, actions_ref);
         running_action.update(actions, ExecutionStageValue::Executing);
         (lease, running_action)
@@ -144,32 +267,60 @@ impl RunningAction {
     /// NB: Will fail loudly if called more than once.
     fn complete(&mut self, result: Result<ActionResult, Status>) {
         let action_digest = self.digest.take().unwrap();
+        let result_label = match &result {
+            Ok(action_result) if action_result.exit_code == 0 => "ok",
+            Ok(_) => "failed",
+            Err(_) => "error",
+        };
         let instance_name = {
             let mut actions = self.actions.lock();
-            if let Some(action) = actions.all.remove(&action_digest) {
-                let _ = action.sender.send(ActionStatus::Completed(result));
-            }
+            actions.complete(&action_digest, result);
             actions.instance_name.clone()
         };
 
         let elapsed = self.start_time.elapsed();
-        metrics::histogram!("toolchain_execution_actions_duration_seconds", elapsed, "bucket" => "complete", "customer_id" => instance_name);
+        metrics::histogram!("toolchain_execution_actions_duration_seconds", elapsed, "bucket" => "complete", "customer_id" => instance_name.clone());
+        metrics::counter!("toolchain_execution_actions_result_total", 1, "result" => result_label, "customer_id" => instance_name);
     }
 }
 
 impl Drop for RunningAction {
     fn drop(&mut self) {
         let instance_name = {
-            let actions = self.actions.lock();
-
-            self.update(&actions, ExecutionStageValue::Queued);
+            let mut actions = self.actions.lock();
 
-            let Some(action_digest) = self.digest.take() else {
+            let Some(action_digest) = self.digest else {
                 return;
             };
-            actions
-                .queued
-                .send_modify(|queued| queued.push_front(action_digest));
+
+            // Fail Actions which have been leased too many times (e.g. because they repeatedly
+            // crash their workers) rather than re-queueing them forever.
+            let attempts = actions
+                .all
+                .get(&action_digest)
+                .map(|action| action.attempts.get())
+                .unwrap_or(0);
+            if actions.max_attempts > 0 && attempts >= actions.max_attempts {
+                log::warn!(
+                    "[{}] Failing action {action_digest:?} after {attempts} attempts",
+                    actions.instance_name
+                );
+                metrics::increment_counter!("toolchain_execution_actions_poisoned_total", "customer_id" => actions.instance_name.clone());
+                self.digest = None;
+                actions.complete(
+                    &action_digest,
+                    Err(Status::aborted("exceeded max execution attempts")),
+                );
+            } else {
+                self.update(&actions, ExecutionStageValue::Queued);
+                self.digest = None;
+                if let Some(action) = actions.all.get(&action_digest) {
+                    action.queued_at.set(Instant::now());
+                }
+                actions
+                    .queued
+                    .send_modify(|queued| queued.push_front(action_digest));
+            }
             actions.instance_name.clone()
         };
 
@@ -371,29 +522,172 @@ impl Workers {
         })
     }
 
-    fn update_gauges(&self) {
-        let count = self.workers.lock().len();
+    /// Returns the number of Workers, and the number of them which are idle. Workers with spare
+    /// capacity are only counted as idle while `actions_queued`, since they should otherwise have
+    /// been assigned the queued Actions.
+    fn counts(&self, actions_queued: bool) -> (usize, usize) {
+        let workers = self.workers.lock();
+        let idle = if actions_queued {
+            workers
+                .values()
+                .filter(|worker| worker.leases.len() < worker.capacity as usize)
+                .count()
+        } else {
+            0
+        };
+        (workers.len(), idle)
+    }
+
+    /// Records gauges for the Workers.
+    fn update_gauges(&self, actions_queued: bool) {
+        let (count, idle) = self.counts(actions_queued);
         metrics::gauge!("toolchain_execution_workers_state", count as f64, "bucket" => "ok", "customer_id" => self.instance_name.clone());
+        metrics::gauge!("toolchain_execution_idle_workers", idle as f64, "customer_id" => self.instance_name.clone());
     }
 }
 
 struct Actions {
     instance_name: InstanceName,
     all: HashMap<ActionDigest, Action>,
+    /// Index from each of the `receivers` of the Actions in `all` to the Action's digest.
+    operations: HashMap<OperationName, IndexedOperation>,
+    next_operation_sequence: u64,
+    /// Operations whose Actions have completed, until they are expired after
+    /// `operation_retention`.
+    completed: HashMap<OperationName, CompletedOperation>,
+    operation_retention: Duration,
+    /// The number of times an Action may be leased before it is failed. Zero disables the limit.
+    max_attempts: u32,
     queued: watch::Sender<VecDeque<ActionDigest>>,
+    uuid_generator: SharedUuidGenerator,
+    /// The number of Actions inspected while looking up operations, to allow tests to confirm
+    /// that lookups do not scan all Actions.
+    #[cfg(test)]
+    actions_visited: std::cell::Cell<usize>,
 }
 
 impl Actions {
-    fn new(instance_name: InstanceName) -> Arc<Mutex<Self>> {
+    fn new(
+        instance_name: InstanceName,
+        operation_retention: Duration,
+        max_attempts: u32,
+        uuid_generator: SharedUuidGenerator,
+    ) -> Arc<Mutex<Self>> {
         let (sender, _receiver) = watch::channel(VecDeque::new());
-        Arc::new(Mutex::new(Self {
+        let actions = Arc::new(Mutex::new(Self {
             instance_name,
             all: HashMap::default(),
+            operations: HashMap::default(),
+            next_operation_sequence: 0,
+            completed: HashMap::default(),
+            operation_retention,
+            max_attempts,
             queued: sender,
-        }))
+            uuid_generator,
+            #[cfg(test)]
+            actions_visited: std::cell::Cell::default(),
+        }));
+        if !operation_retention.is_zero() {
+            tokio::spawn(Self::operation_expiration_task(Arc::downgrade(&actions)));
+        }
+        actions
     }
 
-    fn update_gauges(&self) {
+    async fn operation_expiration_task(actions: Weak<Mutex<Actions>>) {
+        let mut next_deadline = Instant::now();
+        loop {
+            // Wait until the next operation expiration deadline.
+            sleep_until(next_deadline).await;
+
+            let Some(actions) = actions.upgrade() else {
+                // The Instance is shutting down.
+                return;
+            };
+
+            // Remove any completed operations which have outlived the retention window, while
+            // updating our next_deadline to the minimum deadline of surviving operations. Clients
+            // which are already waiting hold their own receivers, and so still observe the
+            // completed status.
+            let mut actions = actions.lock();
+            let now = Instant::now();
+            let retention = actions.operation_retention;
+            next_deadline = now + retention;
+            let retained_before = actions.completed.len();
+            actions.completed.retain(|_operation_name, operation| {
+                let expiration = operation.completed_at + retention;
+                if expiration <= now {
+                    false
+                } else {
+                    if expiration < next_deadline {
+                        next_deadline = expiration;
+                    }
+                    true
+                }
+            });
+
+            let expired = retained_before - actions.completed.len();
+            if expired > 0 {
+                log::debug!(
+                    "[{}] Expired {expired} completed operations",
+                    actions.instance_name
+                );
+                metrics::counter!("toolchain_execution_operations_expired_total", expired as u64, "customer_id" => actions.instance_name.clone());
+            }
+        }
+    }
+
+    /// Complete an Action with the given result, notifying all of its operations.
+    fn complete(&mut self, action_digest: &ActionDigest, result: Result<ActionResult, Status>) {
+        if let Some(action) = self.all.remove(action_digest) {
+            let _ = action.sender.send(ActionStatus::Completed(result));
+            self.retain_completed(action.receivers);
+        }
+    }
+
+    /// Unindex the operations of a completed Action, and retain them so that they may still be
+    /// waited on.
+    fn retain_completed(
+        &mut self,
+        receivers: HashMap<OperationName, watch::Receiver<ActionStatus>>,
+    ) {
+        let completed_at = Instant::now();
+        for (operation_name, receiver) in receivers {
+            let Some(indexed) = self.operations.remove(&operation_name) else {
+                continue;
+            };
+            if self.operation_retention.is_zero() {
+                continue;
+            }
+            self.completed.insert(
+                operation_name,
+                CompletedOperation {
+                    receiver,
+                    completed_at,
+                    sequence: indexed.sequence,
+                },
+            );
+        }
+    }
+
+    /// Find the Action which the given operation is waiting on.
+    fn action_for_operation(&self, operation_name: &OperationName) -> Option<&Action> {
+        let indexed = self.operations.get(operation_name)?;
+        #[cfg(test)]
+        self.actions_visited.set(self.actions_visited.get() + 1);
+        self.all.get(&indexed.action_digest)
+    }
+
+    /// Remove an Action, along with the index entries for all of its operations.
+    fn remove(&mut self, action_digest: &ActionDigest) -> Option<Action> {
+        let action = self.all.remove(action_digest)?;
+        for operation_name in action.receivers.keys() {
+            self.operations.remove(operation_name);
+        }
+        Some(action)
+    }
+
+    /// Returns the number of queued and executing Actions.
+    fn counts(&self) -> (usize, usize) {
         let queued_digests: HashSet<ActionDigest> = self.queued.borrow().iter().cloned().collect();
         let (mut queued, mut executing) = (0, 0);
         for action_digest in self.all.keys() {
@@ -403,6 +697,11 @@ impl Actions {
                 executing += 1;
             }
         }
+        (queued, executing)
+    }
+
+    fn update_gauges(&self) {
+        let (queued, executing) = self.counts();
         metrics::gauge!("toolchain_execution_actions_state", queued as f64, "bucket" => "queued", "customer_id" => self.instance_name.clone());
         metrics::gauge!("toolchain_execution_actions_state", executing as f64, "bucket" => "executing", "customer_id" => self.instance_name.clone());
     }
@@ -414,14 +713,27 @@ pub(crate) struct Instance {
     name: InstanceName,
     actions: Arc<Mutex<Actions>>,
     workers: Arc<Workers>,
+    uuid_generator: SharedUuidGenerator,
 }
 
 impl Instance {
-    fn new(name: InstanceName, expiration_timeout: Duration) -> Self {
+    fn new(
+        name: InstanceName,
+        expiration_timeout: Duration,
+        operation_retention: Duration,
+        max_attempts: u32,
+        uuid_generator: SharedUuidGenerator,
+    ) -> Self {
         Self {
             name: name.clone(),
-            actions: Actions::new(name.clone()),
+            actions: Actions::new(
+                name.clone(),
+                operation_retention,
+                max_attempts,
+                uuid_generator.clone(),
+            ),
             workers: Workers::new(name, expiration_timeout),
+            uuid_generator,
         }
     }
 
@@ -430,8 +742,17 @@ impl Instance {
         action_digest: Digest,
         action_request: ActionRequest,
     ) -> (OperationName, watch::Receiver<ActionStatus>) {
-        let operation_name = generate_operation_name(&self.name);
+        let operation_name = generate_operation_name(&*self.uuid_generator, &self.name);
         let mut actions = self.actions.lock();
+        let sequence = actions.next_operation_sequence;
+        actions.next_operation_sequence += 1;
+        actions.operations.insert(
+            operation_name.clone(),
+            IndexedOperation {
+                action_digest,
+                sequence,
+            },
+        );
         let receiver = match actions.all.entry(action_digest) {
             hash_map::Entry::Occupied(mut oe) => {
                 // Create a new receiver. Operation names must not collide, so we know that this
@@ -461,28 +782,83 @@ impl Instance {
         &self,
         operation_name: &OperationName,
     ) -> Option<watch::Receiver<ActionStatus>> {
-        // NB: Linear time. Consider indexing, or (encoding more information in the operation
-        // name) if it shows up in profiles.
-        self.actions
-            .lock()
-            .all
-            .values()
-            .find_map(|action| action.receivers.get(operation_name).cloned())
+        let actions = self.actions.lock();
+        match actions.action_for_operation(operation_name) {
+            Some(action) => action.receivers.get(operation_name).cloned(),
+            None => actions
+                .completed
+                .get(operation_name)
+                .map(|operation| operation.receiver.clone()),
+        }
+    }
+
+    /// List the operations of this Instance newest-first, optionally only those in the given
+    /// stage. Returns at most `page_size` operations older than the `page_token` of a previous
+    /// page, along with the token for the next page if there may be more.
+    pub(crate) fn list_operations(
+        &self,
+        stage: Option<OperationStage>,
+        page_size: usize,
+        page_token: Option<u64>,
+    ) -> (Vec<(OperationName, ActionStatus)>, Option<u64>) {
+        let mut operations: Vec<(u64, OperationName, ActionStatus)> = {
+            let actions = self.actions.lock();
+            let running = actions
+                .operations
+                .iter()
+                .filter_map(|(operation_name, indexed)| {
+                    let action = actions.all.get(&indexed.action_digest)?;
+                    let status = action.sender.borrow().clone();
+                    Some((indexed.sequence, operation_name.clone(), status))
+                });
+            let completed = actions.completed.iter().map(|(operation_name, operation)| {
+                let status = operation.receiver.borrow().clone();
+                (operation.sequence, operation_name.clone(), status)
+            });
+            running
+                .chain(completed)
+                .filter(|(sequence, _, status)| {
+                    let in_page = match page_token {
+                        Some(page_token) => *sequence < page_token,
+                        None => true,
+                    };
+                    let in_stage = match stage {
+                        Some(stage) => OperationStage::of(status) == stage,
+                        None => true,
+                    };
+                    in_page && in_stage
+                })
+                .collect()
+        };
+
+        operations.sort_unstable_by(|(a, _, _), (b, _, _)| b.cmp(a));
+        let next_page_token = if operations.len() > page_size {
+            operations.truncate(page_size);
+            operations.last().map(|(sequence, _, _)| *sequence)
+        } else {
+            None
+        };
+        let operations = operations
+            .into_iter()
+            .map(|(_, operation_name, status)| (operation_name, status))
+            .collect();
+        (operations, next_page_token)
     }
 
     pub(crate) fn cancel(&self, operation_name: OperationName) {
-        // NB: Linear time. Consider indexing, or (encoding more information in the operation
-        // name) if it shows up in profiles.
         let mut actions = self.actions.lock();
-        let mut actions_to_remove = Vec::new();
-        for action in actions.all.values_mut() {
-            action.receivers.remove(&operation_name);
-            if action.sender.is_closed() {
-                actions_to_remove.push(action.digest);
-            }
-        }
-        for action_digest in actions_to_remove {
-            actions.all.remove(&action_digest);
+        let Some(IndexedOperation { action_digest, .. }) =
+            actions.operations.remove(&operation_name)
+        else {
+            actions.completed.remove(&operation_name);
+            return;
+        };
+        let Some(action) = actions.all.get_mut(&action_digest) else {
+            return;
+        };
+        action.receivers.remove(&operation_name);
+        if action.sender.is_closed() {
+            actions.remove(&action_digest);
         }
     }
 
@@ -526,47 +902,109 @@ impl Instance {
     }
 
     fn update_gauges(&self) {
-        self.workers.update_gauges();
-        self.actions.lock().update_gauges();
+        let actions = self.actions.lock();
+        actions.update_gauges();
+        let actions_queued = !actions.queued.borrow().is_empty();
+        drop(actions);
+        self.workers.update_gauges(actions_queued);
+    }
+
+    pub(crate) fn stats(&self) -> InstanceStats {
+        let (queued_actions, executing_actions) = self.actions.lock().counts();
+        let (workers, _idle) = self.workers.counts(queued_actions > 0);
+        InstanceStats {
+            queued_actions,
+            executing_actions,
+            workers,
+        }
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Instances {
     instances: Arc<Mutex<HashMap<InstanceName, Instance>>>,
+    operation_retention: Duration,
+    max_attempts: u32,
+    worker_expiration_timeouts: WorkerExpirationTimeouts,
+    uuid_generator: SharedUuidGenerator,
+}
+
+impl Default for Instances {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_OPERATION_RETENTION,
+            DEFAULT_MAX_ATTEMPTS,
+            WorkerExpirationTimeouts::default(),
+            Arc::new(DefaultUuidGenerator),
+        )
+    }
 }
 
 impl Instances {
+    /// Create Instances which retain completed operations for `operation_retention`, fail
+    /// Actions after `max_attempts` leases, expire workers after `worker_expiration_timeouts`,
+    /// and whose operation, session and lease names are generated by the given generator.
+    pub(crate) fn new(
+        operation_retention: Duration,
+        max_attempts: u32,
+        worker_expiration_timeouts: WorkerExpirationTimeouts,
+        uuid_generator: SharedUuidGenerator,
+    ) -> Self {
+        Self {
+            instances: Arc::default(),
+            operation_retention,
+            max_attempts,
+            worker_expiration_timeouts,
+            uuid_generator,
+        }
+    }
+
     pub(crate) fn instance(&self, name: InstanceName) -> Instance {
         self.instances
             .lock()
             .entry(name.clone())
-            .or_insert_with(|| Instance::new(name, Duration::from_secs(60)))
+            .or_insert_with(|| {
+                let expiration_timeout = self.worker_expiration_timeouts.for_instance(&name);
+                Instance::new(
+                    name,
+                    expiration_timeout,
+                    self.operation_retention,
+                    self.max_attempts,
+                    self.uuid_generator.clone(),
+                )
+            })
             .clone()
     }
 
+    pub(crate) fn uuid_generator(&self) -> &dyn UuidGenerator {
+        &*self.uuid_generator
+    }
+
+    /// Clone all Instances, so that they can be inspected without holding the lock.
+    fn all(&self) -> Vec<Instance> {
+        self.instances.lock().values().cloned().collect()
+    }
+
     /// Updates metrics gauges for all Instances.
     pub(crate) fn update_gauges(&self) {
-        // Clone all Instances and then release the lock.
-        let instances: Vec<Instance> = {
-            let instances = self.instances.lock();
-            instances.values().cloned().collect()
-        };
-
-        for instance in instances {
+        for instance in self.all() {
             instance.update_gauges();
         }
     }
-}
 
-fn generate_lease_id() -> LeaseId {
-    generate_uuid()
+    /// Returns the state of each Instance.
+    pub(crate) fn stats(&self) -> BTreeMap<InstanceName, InstanceStats> {
+        self.all()
+            .into_iter()
+            .map(|instance| (instance.name.clone(), instance.stats()))
+            .collect()
+    }
 }
 
-fn create_lease(action: &ActionRequest) -> Lease {
+fn create_lease(action: &ActionRequest, lease_id: LeaseId) -> Lease {
     #[allow(deprecated)]
     Lease {
-        id: generate_lease_id(),
+        id: lease_id,
         payload: Some(any_proto_encode(action)),
         result: None,
         state: LeaseState::Pending as i32,
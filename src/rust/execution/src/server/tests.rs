@@ -1,13 +1,35 @@
 // Copyright 2023 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
+
+use bytes::Bytes;
 use digest::Digest;
+use execution_util::{DefaultUuidGenerator, UuidGenerator};
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
 use protos::build::bazel::remote::execution::v2::{Action as ActionRequest, ActionResult};
 use protos::google::devtools::remoteworkers::v1test2::{BotSession, Lease, LeaseState};
 use tokio::time::{sleep, timeout_at, Duration, Instant};
 
 use crate::any_proto_encode;
-use crate::server::{ActionStatus, Instance};
+use crate::server::{
+    ActionStatus, Instance, InstanceStats, Instances, OperationStage, WorkerExpirationTimeouts,
+    DEFAULT_MAX_ATTEMPTS, DEFAULT_OPERATION_RETENTION,
+};
+
+/// Generates `uuid-0`, `uuid-1`, ... so that tests can assert exact names.
+#[derive(Default)]
+struct SequentialUuidGenerator {
+    next: AtomicUsize,
+}
+
+impl UuidGenerator for SequentialUuidGenerator {
+    fn generate_uuid(&self) -> String {
+        format!("uuid-{}", self.next.fetch_add(1, Ordering::SeqCst))
+    }
+}
 
 async fn execute(instance: &Instance, action_request: ActionRequest) -> ActionResult {
     let (_, mut receiver) = instance.execute(Digest::EMPTY, action_request);
@@ -25,6 +47,13 @@ async fn execute(instance: &Instance, action_request: ActionRequest) -> ActionRe
     }
 }
 
+/// Installs a recorder which records metrics for the current thread only, so that tests which
+/// assert on metrics do not interfere with one another.
+fn install_per_thread_recorder() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| DebuggingRecorder::per_thread().install().unwrap());
+}
+
 fn complete_lease(lease: &mut Lease) {
     lease.result = Some(any_proto_encode(&ActionResult::default()));
     lease.state = LeaseState::Completed as i32;
@@ -36,7 +65,13 @@ fn complete_lease(lease: &mut Lease) {
 
 #[tokio::test]
 async fn test_basic() {
-    let instance = Instance::new("test".to_owned(), Duration::from_secs(60));
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
 
     // Spawn a worker that will execute the job.
     let instance2 = instance.clone();
@@ -65,7 +100,13 @@ async fn test_basic() {
 #[tokio::test]
 async fn test_worker_expiration() {
     let expiration_timeout = Duration::from_secs(3);
-    let instance = Instance::new("test".to_owned(), expiration_timeout);
+    let instance = Instance::new(
+        "test".to_owned(),
+        expiration_timeout,
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
 
     // Spawn a worker that will take a job with one session. Then, confirm that it takes longer
     // than the timeout for the work to be assigned to a second session.
@@ -106,7 +147,13 @@ async fn test_worker_expiration() {
 
 #[tokio::test]
 async fn test_action_cancellation() {
-    let instance = Instance::new("test".to_owned(), Duration::from_secs(60));
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
 
     // Spawn a worker that will take a job, then sleep briefly and confirm that it has been
     // cancelled.
@@ -135,3 +182,521 @@ async fn test_action_cancellation() {
 
     worker.await.unwrap();
 }
+
+#[tokio::test]
+async fn test_generated_names() {
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(SequentialUuidGenerator::default()),
+    );
+
+    let (operation_name, _receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+    assert_eq!(operation_name, "test/uuid-0");
+
+    let mut session = BotSession::default();
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+    assert_eq!(session.leases[0].id, "uuid-1");
+
+    let (operation_name, _receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+    assert_eq!(operation_name, "test/uuid-2");
+}
+
+#[tokio::test]
+async fn test_wait_and_cancel_many_operations() {
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
+
+    // Create many operations across many distinct actions, dropping the callers' receivers so
+    // that the index alone keeps the operations alive.
+    let operation_names: Vec<_> = (0..200u8)
+        .map(|i| {
+            let action_digest = Digest::from_slice(&[i; 32], i as usize).unwrap();
+            let (operation_name, _) = instance.execute(action_digest, ActionRequest::default());
+            (operation_name, action_digest)
+        })
+        .collect();
+
+    // Each lookup inspects only the action for the operation, rather than scanning them all.
+    for (operation_name, _) in &operation_names {
+        assert!(instance.wait(operation_name).is_some());
+    }
+    assert!(instance.wait(&"test/unknown".to_owned()).is_none());
+    assert_eq!(
+        instance.actions.lock().actions_visited.get(),
+        operation_names.len()
+    );
+
+    // Cancelling an operation removes it, and its action once nothing else is waiting on it.
+    let (cancelled_name, cancelled_digest) = operation_names[0].clone();
+    instance.cancel(cancelled_name.clone());
+    assert!(instance.wait(&cancelled_name).is_none());
+    assert!(!instance.actions.lock().all.contains_key(&cancelled_digest));
+    assert!(instance.wait(&operation_names[1].0).is_some());
+    assert_eq!(
+        instance.actions.lock().operations.len(),
+        operation_names.len() - 1
+    );
+}
+
+#[tokio::test]
+async fn test_completed_operations_are_unindexed() {
+    // Completed operations are not retained, so are dropped as soon as their action completes.
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        Duration::ZERO,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
+
+    // Two operations for the same action share its index entry's digest.
+    let (first_name, _first_receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+    let (second_name, _second_receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+    assert!(instance.wait(&first_name).is_some());
+    assert!(instance.wait(&second_name).is_some());
+
+    let mut session = BotSession::default();
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+    for lease in &mut session.leases {
+        complete_lease(lease)
+    }
+    instance.poll(&mut session, Duration::from_millis(10)).await;
+
+    assert!(instance.wait(&first_name).is_none());
+    assert!(instance.wait(&second_name).is_none());
+    assert!(instance.actions.lock().operations.is_empty());
+}
+
+#[tokio::test]
+async fn test_completed_operation_expiration() {
+    let operation_retention = Duration::from_millis(500);
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        operation_retention,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
+
+    // Submit a job, but stop waiting on it before it completes.
+    let (operation_name, receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+    drop(receiver);
+
+    // Obtain a receiver as an in-flight `WaitExecution` would.
+    let in_flight_receiver = instance.wait(&operation_name).unwrap();
+
+    let mut session = BotSession::default();
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+    for lease in &mut session.leases {
+        complete_lease(lease)
+    }
+    instance.poll(&mut session, Duration::from_millis(10)).await;
+
+    // The completed operation can still be waited on within the retention window.
+    let receiver = instance.wait(&operation_name).unwrap();
+    assert!(matches!(
+        &*receiver.borrow(),
+        ActionStatus::Completed(Ok(_))
+    ));
+    drop(receiver);
+
+    // But is eventually expired.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while instance.wait(&operation_name).is_some() {
+        assert!(Instant::now() < deadline, "operation was not expired");
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert!(instance.actions.lock().completed.is_empty());
+
+    // The in-flight waiter still observes the terminal status.
+    assert!(matches!(
+        &*in_flight_receiver.borrow(),
+        ActionStatus::Completed(Ok(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_list_operations() {
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
+    let action_digest = |i: u8| Digest::from_slice(&[i; 32], i as usize).unwrap();
+    let list = |stage, page_size, page_token| {
+        let (operations, next_page_token) = instance.list_operations(stage, page_size, page_token);
+        let names: Vec<_> = operations.into_iter().map(|(name, _)| name).collect();
+        (names, next_page_token)
+    };
+
+    // Complete one operation.
+    let (completed, _) = instance.execute(action_digest(0), ActionRequest::default());
+    let mut session = BotSession::default();
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    for lease in &mut session.leases {
+        complete_lease(lease)
+    }
+    instance.poll(&mut session, Duration::from_millis(10)).await;
+
+    // Start executing another, and leave the rest queued.
+    let (executing, _) = instance.execute(action_digest(1), ActionRequest::default());
+    let (queued1, _) = instance.execute(action_digest(2), ActionRequest::default());
+    let (queued2, _) = instance.execute(action_digest(3), ActionRequest::default());
+    let (queued3, _) = instance.execute(action_digest(2), ActionRequest::default());
+    let mut session = BotSession::default();
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+
+    // All operations are listed newest-first across pages.
+    let (page, next_page_token) = list(None, 2, None);
+    assert_eq!(page, vec![queued3.clone(), queued2.clone()]);
+    let (page, next_page_token) = list(None, 2, next_page_token);
+    assert_eq!(page, vec![queued1.clone(), executing.clone()]);
+    let (page, next_page_token) = list(None, 2, next_page_token);
+    assert_eq!(page, vec![completed.clone()]);
+    assert_eq!(next_page_token, None);
+
+    // And may be filtered by stage.
+    assert_eq!(
+        list(Some(OperationStage::Queued), 10, None),
+        (vec![queued3, queued2, queued1], None)
+    );
+    assert_eq!(
+        list(Some(OperationStage::Executing), 10, None),
+        (vec![executing], None)
+    );
+    assert_eq!(
+        list(Some(OperationStage::Completed), 10, None),
+        (vec![completed], None)
+    );
+}
+
+#[tokio::test]
+async fn test_queue_wait_time() {
+    install_per_thread_recorder();
+
+    let instance = Instance::new(
+        "queue-wait".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
+    let (_operation_name, _receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+
+    let queue_delay = Duration::from_millis(200);
+    sleep(queue_delay).await;
+    let mut session = BotSession::default();
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+
+    let samples: Vec<f64> = Snapshotter::current_thread_snapshot()
+        .unwrap()
+        .into_vec()
+        .into_iter()
+        .filter(|(key, _, _, _)| {
+            key.key().name() == "toolchain_execution_queue_wait_seconds"
+                && key
+                    .key()
+                    .labels()
+                    .any(|label| label.key() == "customer_id" && label.value() == "queue-wait")
+        })
+        .flat_map(|(_, _, _, value)| match value {
+            DebugValue::Histogram(samples) => samples.into_iter().map(|s| s.into_inner()).collect(),
+            _ => vec![],
+        })
+        .collect();
+    assert_eq!(samples.len(), 1);
+    assert!(samples[0] >= queue_delay.as_secs_f64());
+    assert!(samples[0] < 10.0);
+}
+
+#[tokio::test]
+async fn test_poisoned_action() {
+    let max_attempts = 3;
+    let instance = Instance::new(
+        "test".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        max_attempts,
+        Arc::new(DefaultUuidGenerator),
+    );
+    let (operation_name, mut receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+
+    // Simulate workers which crash while holding the lease, by acquiring the lease and then
+    // dropping it without completing it.
+    for attempt in 1..=max_attempts {
+        let mut session = BotSession {
+            name: format!("session-{attempt}"),
+            ..BotSession::default()
+        };
+        instance.poll(&mut session, Duration::from_secs(10)).await;
+        assert_eq!(session.leases.len(), 1, "attempt {attempt}");
+        instance.workers.workers.lock().clear();
+    }
+
+    // Once the cap is reached, the action is failed rather than re-queued.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let ActionStatus::Completed(result) = &*receiver.borrow() {
+            break result.clone().unwrap_err();
+        }
+        timeout_at(deadline, receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+    };
+    assert_eq!(status.code(), tonic::Code::Aborted);
+    assert_eq!(status.message(), "exceeded max execution attempts");
+    assert!(instance.actions.lock().queued.borrow().is_empty());
+    assert!(instance.wait(&operation_name).is_some());
+}
+
+#[tokio::test]
+async fn test_idle_workers() {
+    install_per_thread_recorder();
+
+    let instance = Instance::new(
+        "idle-workers".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
+    let metric_values = |name: &str| -> Vec<f64> {
+        Snapshotter::current_thread_snapshot()
+            .unwrap()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, _, _, _)| {
+                key.key().name() == name
+                    && key.key().labels().any(|label| {
+                        label.key() == "customer_id" && label.value() == "idle-workers"
+                    })
+            })
+            .flat_map(|(_, _, _, value)| match value {
+                DebugValue::Gauge(value) => vec![value.into_inner()],
+                DebugValue::Histogram(samples) => {
+                    samples.into_iter().map(|s| s.into_inner()).collect()
+                }
+                _ => vec![],
+            })
+            .collect()
+    };
+
+    // A worker with spare capacity is not idle while there is no queued work.
+    let mut session = BotSession::default();
+    instance.poll(&mut session, Duration::from_millis(10)).await;
+    assert!(session.leases.is_empty());
+    instance.update_gauges();
+    assert_eq!(metric_values("toolchain_execution_idle_workers"), vec![0.0]);
+
+    // But is once an action is queued without the worker polling for it.
+    let (_operation_name, _receiver) = instance.execute(Digest::EMPTY, ActionRequest::default());
+    let queue_delay = Duration::from_millis(200);
+    sleep(queue_delay).await;
+    instance.update_gauges();
+    assert_eq!(metric_values("toolchain_execution_idle_workers"), vec![1.0]);
+
+    // Once the worker is assigned the action, the time it spent queued is recorded.
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+    let samples = metric_values("toolchain_execution_assignment_latency_seconds");
+    assert_eq!(samples.len(), 1);
+    assert!(samples[0] >= queue_delay.as_secs_f64());
+    assert!(samples[0] < 10.0);
+    instance.update_gauges();
+    assert_eq!(metric_values("toolchain_execution_idle_workers"), vec![0.0]);
+
+    // When the worker goes away the action is re-queued, and its next assignment is recorded as
+    // well, but only its first lease counts towards the queue wait time.
+    instance.workers.workers.lock().clear();
+    let mut session = BotSession {
+        name: "second-session".to_owned(),
+        ..BotSession::default()
+    };
+    instance.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+    let samples = metric_values("toolchain_execution_assignment_latency_seconds");
+    assert_eq!(samples.len(), 2);
+    assert!(samples[1] < queue_delay.as_secs_f64());
+    assert_eq!(
+        metric_values("toolchain_execution_queue_wait_seconds").len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_instance_stats() {
+    let instances = Instances::default();
+
+    // An instance with an action which no worker has picked up.
+    let (_operation_name, _receiver) = instances
+        .instance("queued".to_owned())
+        .execute(Digest::EMPTY, ActionRequest::default());
+
+    // And one whose action has been leased to its worker.
+    let executing = instances.instance("executing".to_owned());
+    let (_operation_name, _receiver) = executing.execute(Digest::EMPTY, ActionRequest::default());
+    let mut session = BotSession::default();
+    executing.poll(&mut session, Duration::from_secs(10)).await;
+    assert_eq!(session.leases.len(), 1);
+
+    assert_eq!(
+        instances.stats().into_iter().collect::<Vec<_>>(),
+        vec![
+            (
+                "executing".to_owned(),
+                InstanceStats {
+                    queued_actions: 0,
+                    executing_actions: 1,
+                    workers: 1,
+                }
+            ),
+            (
+                "queued".to_owned(),
+                InstanceStats {
+                    queued_actions: 1,
+                    executing_actions: 0,
+                    workers: 0,
+                }
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_per_instance_worker_expiration() {
+    let instances = Instances::new(
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        WorkerExpirationTimeouts::new(
+            Duration::from_millis(500),
+            HashMap::from([("patient".to_owned(), Duration::from_secs(30))]),
+        )
+        .unwrap(),
+        Arc::new(DefaultUuidGenerator),
+    );
+
+    // A worker registers with each instance, and then stops polling for longer than the default
+    // timeout.
+    for instance_name in ["impatient", "patient"] {
+        let mut session = BotSession::default();
+        session.name = format!("{instance_name}/session");
+        instances
+            .instance(instance_name.to_owned())
+            .poll(&mut session, Duration::from_millis(10))
+            .await;
+    }
+    sleep(Duration::from_millis(1500)).await;
+
+    // Only the worker with the default timeout has been reaped.
+    let workers = instances
+        .stats()
+        .into_iter()
+        .map(|(instance_name, stats)| (instance_name, stats.workers))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        workers,
+        vec![("impatient".to_owned(), 0), ("patient".to_owned(), 1)]
+    );
+}
+
+#[test]
+fn test_worker_expiration_must_be_non_zero() {
+    assert!(WorkerExpirationTimeouts::new(Duration::ZERO, HashMap::new()).is_err());
+    assert!(WorkerExpirationTimeouts::new(
+        Duration::from_secs(60),
+        HashMap::from([("instance".to_owned(), Duration::ZERO)]),
+    )
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_action_result_metrics() {
+    install_per_thread_recorder();
+
+    let instance = Instance::new(
+        "action-results".to_owned(),
+        Duration::from_secs(60),
+        DEFAULT_OPERATION_RETENTION,
+        DEFAULT_MAX_ATTEMPTS,
+        Arc::new(DefaultUuidGenerator),
+    );
+
+    // Complete one action successfully, one with a non-zero exit code, and one with an error.
+    let results: [fn(&mut Lease); 3] = [
+        complete_lease,
+        |lease| {
+            complete_lease(lease);
+            lease.result = Some(any_proto_encode(&ActionResult {
+                exit_code: 1,
+                ..Default::default()
+            }));
+        },
+        |lease| {
+            complete_lease(lease);
+            lease.status = Some(protos::google::rpc::Status {
+                code: protos::google::rpc::Code::Internal as i32,
+                ..Default::default()
+            });
+        },
+    ];
+    for (i, set_result) in results.into_iter().enumerate() {
+        let digest = Digest::of_bytes(&Bytes::from(vec![i as u8])).unwrap();
+        let (_operation_name, _receiver) = instance.execute(digest, ActionRequest::default());
+        let mut session = BotSession::default();
+        instance.poll(&mut session, Duration::from_secs(10)).await;
+        assert_eq!(session.leases.len(), 1);
+        set_result(&mut session.leases[0]);
+        instance.poll(&mut session, Duration::from_millis(10)).await;
+    }
+
+    let mut counts = Snapshotter::current_thread_snapshot()
+        .unwrap()
+        .into_vec()
+        .into_iter()
+        .filter(|(key, _, _, _)| {
+            key.key().name() == "toolchain_execution_actions_result_total"
+                && key
+                    .key()
+                    .labels()
+                    .any(|label| label.key() == "customer_id" && label.value() == "action-results")
+        })
+        .map(|(key, _, _, value)| {
+            let result = key
+                .key()
+                .labels()
+                .find(|label| label.key() == "result")
+                .unwrap()
+                .value()
+                .to_owned();
+            let DebugValue::Counter(count) = value else {
+                panic!("Expected a counter");
+            };
+            (result, count)
+        })
+        .collect::<Vec<_>>();
+    counts.sort();
+    assert_eq!(
+        counts,
+        vec![
+            ("error".to_owned(), 1),
+            ("failed".to_owned(), 1),
+            ("ok".to_owned(), 1)
+        ]
+    );
+}
@@ -1,10 +1,18 @@
 // Copyright 2022 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::Duration;
 
+use execution::api::{CasConcurrencyLimitConfig, CasLimiter};
+use execution::server::{
+    WorkerExpirationTimeouts, DEFAULT_BOT_POLL_TIMEOUT, DEFAULT_MAX_ATTEMPTS,
+    DEFAULT_OPERATION_RETENTION, DEFAULT_WORKER_EXPIRATION_TIMEOUT,
+};
 use grpc_util::backend::BackendConfig;
 use grpc_util::infra::{GrpcConfig, InfraConfig};
+use grpc_util::secrets::SecretsConfig;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
@@ -18,8 +26,76 @@ pub struct Config {
     /// gRPC configuration.
     pub grpc: Option<GrpcConfig>,
 
+    /// Where secrets (the admin secret) are loaded from. Defaults to files.
+    pub secrets: Option<SecretsConfig>,
+
+    /// Path (as understood by the configured secrets provider) to the shared secret which
+    /// authenticates requests to the admin infra endpoints. If unset, the admin endpoints are
+    /// disabled.
+    pub admin_secret_path: Option<String>,
+
     /// Configuration for the connection to the CAS.
     pub cas: BackendConfig,
+
+    /// Bounds the number of requests made to the CAS at once. If unset, requests are not limited.
+    pub cas_concurrency_limit: Option<CasConcurrencyLimitConfig>,
+
+    /// How long completed operations remain available to `WaitExecution`, in seconds. Zero
+    /// drops operations as soon as they complete.
+    #[serde(default = "default_operation_retention_secs")]
+    pub operation_retention_secs: u64,
+
+    /// How many times an action may be leased to workers (e.g. because workers crashed while
+    /// executing it) before it is failed. Zero disables the limit.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Maximum time for which a bot's request waits for new work, in milliseconds. Requests
+    /// respond sooner if the bot set a shorter deadline.
+    #[serde(default = "default_bot_poll_timeout_ms")]
+    pub bot_poll_timeout_ms: u64,
+
+    /// How long a worker may go without polling before its session expires and its leases are
+    /// re-queued, in seconds. Must be non-zero.
+    #[serde(default = "default_worker_expiration_secs")]
+    pub worker_expiration_secs: u64,
+
+    /// Per-instance overrides of `worker_expiration_secs`, for worker fleets which poll less
+    /// frequently.
+    #[serde(default)]
+    pub per_instance_worker_expiration_secs: HashMap<String, u64>,
+}
+
+impl Config {
+    pub fn cas_limiter(&self) -> Result<CasLimiter, String> {
+        CasLimiter::new(self.cas_concurrency_limit.as_ref())
+    }
+
+    pub fn worker_expiration_timeouts(&self) -> Result<WorkerExpirationTimeouts, String> {
+        WorkerExpirationTimeouts::new(
+            Duration::from_secs(self.worker_expiration_secs),
+            self.per_instance_worker_expiration_secs
+                .iter()
+                .map(|(instance_name, secs)| (instance_name.clone(), Duration::from_secs(*secs)))
+                .collect(),
+        )
+    }
+}
+
+fn default_operation_retention_secs() -> u64 {
+    DEFAULT_OPERATION_RETENTION.as_secs()
+}
+
+fn default_max_attempts() -> u32 {
+    DEFAULT_MAX_ATTEMPTS
+}
+
+fn default_worker_expiration_secs() -> u64 {
+    DEFAULT_WORKER_EXPIRATION_TIMEOUT.as_secs()
+}
+
+fn default_bot_poll_timeout_ms() -> u64 {
+    DEFAULT_BOT_POLL_TIMEOUT.as_millis() as u64
 }
 
 impl FromStr for Config {
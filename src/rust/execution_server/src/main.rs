@@ -5,23 +5,65 @@
 
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::time::Duration;
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use grpc_util::backend::construct_channel;
 use grpc_util::hyper::AddrIncomingWithStream;
-use grpc_util::infra::setup_infra_endpoints;
+use grpc_util::infra::{
+    serve_with_drain_deadline, setup_infra_endpoints_with_readiness, wait_for_shutdown,
+    AdminActions, DebugInfo, Readiness,
+};
 use grpc_util::logging::setup_logging;
 use grpc_util::sentry::setup_sentry;
 use hyper::server::conn::AddrIncoming;
+use protos::build::bazel::remote::execution::v2::capabilities_client::CapabilitiesClient;
 use protos::build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
-use tokio::io::AsyncReadExt;
 use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
 
-use execution::api::ExecutionServer;
+use execution::api::{CasLimiter, ExecutionServer};
+use execution::readiness::wait_for_cas;
 use execution::serve_with_incoming_shutdown;
+use execution::server::WorkerExpirationTimeouts;
 
 pub mod config;
 
+/// What the server is set up from besides the config file.
+struct Startup {
+    address: SocketAddr,
+    admin_secret: Option<String>,
+    cas_limiter: CasLimiter,
+    worker_expiration_timeouts: WorkerExpirationTimeouts,
+}
+
+/// Load the secrets which the config refers to and check the rest of it, without binding any
+/// ports or connecting to the CAS.
+async fn prepare_startup(config: &config::Config) -> Result<Startup, String> {
+    let address = config
+        .listen_address
+        .parse::<SocketAddr>()
+        .map_err(|err| format!("Invalid listen_address {}: {err}", config.listen_address))?;
+    let secret_provider = config.secrets.clone().unwrap_or_default().provider();
+    let admin_secret = match &config.admin_secret_path {
+        Some(path) => Some(grpc_util::secrets::parse_secret(
+            secret_provider.get_secret(path).await?,
+        )?),
+        None => None,
+    };
+    let worker_expiration_timeouts = config.worker_expiration_timeouts()?;
+    let cas_limiter = config.cas_limiter()?;
+    config
+        .cas
+        .validate()
+        .map_err(|err| format!("Invalid cas backend: {err}"))?;
+    Ok(Startup {
+        address,
+        admin_secret,
+        cas_limiter,
+        worker_expiration_timeouts,
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("execution_server")
@@ -31,46 +73,123 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .required(true)
                 .value_name("FILE"),
         )
+        .arg(
+            Arg::new("max-startup-wait")
+                .long("max-startup-wait")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("60")
+                .help("Maximum time to wait for the CAS to become reachable at startup"),
+        )
+        .arg(
+            Arg::new("config-check")
+                .long("config-check")
+                .action(ArgAction::SetTrue)
+                .help("Validate the config file and exit without serving"),
+        )
         .get_matches();
 
-    let config = {
+    let (config, debug_info) = {
         let config_filename = matches.get_one::<String>("config").unwrap();
-        let mut file = tokio::fs::File::open(config_filename).await?;
-        let mut config_str = String::new();
-        file.read_to_string(&mut config_str).await?;
-        config::Config::from_str(&config_str).unwrap()
+        let config_str = tokio::fs::read_to_string(config_filename)
+            .await
+            .map_err(|err| format!("Failed to read config from {config_filename}: {err}"))?;
+        let debug_info = DebugInfo::new(
+            env!("CARGO_PKG_VERSION"),
+            option_env!("GIT_SHA"),
+            &config_str,
+        )?;
+        let config = config::Config::from_str(&config_str)
+            .map_err(|err| format!("Invalid config file {config_filename}: {err}"))?;
+        (config, debug_info)
     };
 
-    setup_logging(config.infra.as_ref(), "execution_server");
+    if matches.get_flag("config-check") {
+        prepare_startup(&config).await?;
+        println!("Config is valid.");
+        return Ok(());
+    }
+
+    let _tracing_guard = setup_logging(config.infra.as_ref(), "execution_server");
     log::info!("execution server config: {config:?}");
     let _sentry_guard = setup_sentry(config.infra.as_ref(), "execution_server");
 
-    let cas_client = ContentAddressableStorageClient::new(construct_channel(config.cas).await?);
-
-    let address: SocketAddr = config.listen_address.parse().unwrap();
-    let server = ExecutionServer::new(cas_client);
+    let Startup {
+        address,
+        admin_secret,
+        cas_limiter,
+        worker_expiration_timeouts,
+    } = prepare_startup(&config).await?;
+    let cas_channel = construct_channel(config.cas).await?;
 
-    let incoming = AddrIncoming::bind(&address).expect("failed to bind port");
-    log::info!("Serving execution on {}", &address);
+    let server = ExecutionServer::new(
+        ContentAddressableStorageClient::new(cas_channel.clone()),
+        cas_limiter,
+        Duration::from_secs(config.operation_retention_secs),
+        config.max_attempts,
+        Duration::from_millis(config.bot_poll_timeout_ms),
+        worker_expiration_timeouts,
+    );
 
-    // Setup infra endpoints.
+    // Setup infra endpoints. The server is reported as not ready until the CAS is reachable.
+    let readiness = Readiness::default();
     let in_flight_requests_counter = InFlightRequestsCounter::new();
-    let mut shutdown_receiver = {
+    let admin_actions = {
         let server = server.clone();
-        let in_flight_requests_counter = in_flight_requests_counter.clone();
-        setup_infra_endpoints(config.infra.unwrap_or_default(), move || {
-            server.update_gauges();
-            let count = in_flight_requests_counter.get();
-            metrics::gauge!("toolchain_grpc_inflight_requests", count as f64, "service" => "execution_server");
+        AdminActions::new(admin_secret).with_action("list_instances", move || {
+            let instance_stats = server.instance_stats();
+            async move {
+                serde_json::to_string(&instance_stats)
+                    .map_err(|err| format!("Failed to encode instance stats: {err}"))
+            }
         })
+    };
+    let shutdown_receiver = {
+        let server = server.clone();
+        let in_flight_requests_counter = in_flight_requests_counter.clone();
+        setup_infra_endpoints_with_readiness(
+            config.infra.unwrap_or_default(),
+            readiness.clone(),
+            debug_info,
+            admin_actions,
+            move || {
+                server.update_gauges();
+                let count = in_flight_requests_counter.get();
+                metrics::gauge!(
+                    "toolchain_grpc_inflight_requests",
+                    count as f64,
+                    "service" => "execution_server",
+                );
+            },
+        )
         .expect("setup infra endpoints")
     };
 
-    serve_with_incoming_shutdown(
-        server,
-        AddrIncomingWithStream(incoming),
-        async move { while shutdown_receiver.changed().await.is_ok() {} },
-        config.grpc,
+    // Only accept traffic once the CAS is reachable, so that early requests do not fail while
+    // the CAS channel is still connecting.
+    let max_startup_wait =
+        Duration::from_secs(*matches.get_one::<u64>("max-startup-wait").unwrap());
+    wait_for_cas(CapabilitiesClient::new(cas_channel), max_startup_wait).await?;
+    readiness.set_ready();
+
+    let incoming = AddrIncoming::bind(&address).expect("failed to bind port");
+    log::info!("Serving execution on {}", &address);
+
+    let drain_deadline = config
+        .grpc
+        .clone()
+        .unwrap_or_default()
+        .shutdown_drain_deadline();
+    serve_with_drain_deadline(
+        serve_with_incoming_shutdown(
+            server,
+            AddrIncomingWithStream(incoming),
+            wait_for_shutdown(shutdown_receiver.clone()),
+            config.grpc,
+            in_flight_requests_counter.clone(),
+        ),
+        shutdown_receiver,
+        drain_deadline,
         in_flight_requests_counter,
     )
     .await?;
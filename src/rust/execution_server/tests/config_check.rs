@@ -0,0 +1,81 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn config_check(dir: &Path, config: &str) -> Output {
+    let config_path = dir.join("config.yaml");
+    std::fs::write(&config_path, config).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_execution_server"))
+        .arg("-c")
+        .arg(&config_path)
+        .arg("--config-check")
+        .output()
+        .unwrap()
+}
+
+fn config(admin_secret: &Path, cas_address: &str, worker_expiration_secs: u64) -> String {
+    format!(
+        r#"
+listen_address: "127.0.0.1:0"
+admin_secret_path: "{}"
+cas:
+  address: "{cas_address}"
+worker_expiration_secs: {worker_expiration_secs}
+"#,
+        admin_secret.display()
+    )
+}
+
+#[test]
+fn config_check_accepts_valid_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let admin_secret = dir.path().join("admin-secret");
+    std::fs::write(&admin_secret, "hunter2").unwrap();
+
+    let output = config_check(dir.path(), &config(&admin_secret, "cas.invalid:8980", 60));
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn config_check_rejects_missing_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    let admin_secret = dir.path().join("admin-secret");
+
+    let output = config_check(dir.path(), &config(&admin_secret, "cas.invalid:8980", 60));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("admin-secret"), "{stderr}");
+}
+
+#[test]
+fn config_check_rejects_invalid_cas_address() {
+    let dir = tempfile::tempdir().unwrap();
+    let admin_secret = dir.path().join("admin-secret");
+    std::fs::write(&admin_secret, "hunter2").unwrap();
+
+    let output = config_check(dir.path(), &config(&admin_secret, "cas.invalid", 60));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid cas backend"), "{stderr}");
+}
+
+#[test]
+fn config_check_rejects_zero_worker_expiration() {
+    let dir = tempfile::tempdir().unwrap();
+    let admin_secret = dir.path().join("admin-secret");
+    std::fs::write(&admin_secret, "hunter2").unwrap();
+
+    let output = config_check(dir.path(), &config(&admin_secret, "cas.invalid:8980", 0));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("worker expiration timeout must be non-zero"),
+        "{stderr}"
+    );
+}
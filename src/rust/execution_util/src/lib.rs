@@ -10,18 +10,35 @@ pub type InstanceName = String;
 
 pub type SessionName = String;
 
+/// Generates a new UUID. Abstracted as a trait to allow overriding in tests.
+pub trait UuidGenerator {
+    fn generate_uuid(&self) -> String;
+}
+
+/// Generates random UUIDs.
+///
 /// NB: See `storage/src/uuid_gen.rs` for the reason for using `rand::thread_rng` here.
-pub fn generate_uuid() -> String {
-    let mut rng = rand::thread_rng();
-    Uuid::from_bytes(rng.gen()).to_string()
+pub struct DefaultUuidGenerator;
+
+impl UuidGenerator for DefaultUuidGenerator {
+    fn generate_uuid(&self) -> String {
+        let mut rng = rand::thread_rng();
+        Uuid::from_bytes(rng.gen()).to_string()
+    }
 }
 
-pub fn generate_session_name(instance_name: &InstanceName) -> SessionName {
-    format!("{instance_name}/{}", generate_uuid())
+pub fn generate_session_name(
+    uuid_generator: &dyn UuidGenerator,
+    instance_name: &InstanceName,
+) -> SessionName {
+    format!("{instance_name}/{}", uuid_generator.generate_uuid())
 }
 
-pub fn generate_operation_name(instance_name: &InstanceName) -> OperationName {
-    format!("{instance_name}/{}", generate_uuid())
+pub fn generate_operation_name(
+    uuid_generator: &dyn UuidGenerator,
+    instance_name: &InstanceName,
+) -> OperationName {
+    format!("{instance_name}/{}", uuid_generator.generate_uuid())
 }
 
 pub fn instance_name_from_operation_name(name: &OperationName) -> Result<InstanceName, String> {
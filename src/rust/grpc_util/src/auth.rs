@@ -16,18 +16,46 @@ use biscuit::{Presence, SingleOrMultiple};
 use chrono::Duration;
 use chrono::TimeDelta;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tonic::metadata::MetadataMap;
+use tonic::transport::Certificate;
 use tonic::Status;
+use x509_parser::extensions::GeneralName;
 
 #[derive(Copy, Clone, Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum AuthScheme {
     Jwt,
     AuthToken,
+    MutualTls,
+    ApiKey,
     DevOnlyNoAuth,
 }
 
-#[derive(strum_macros::Display)]
+impl AuthScheme {
+    /// The name of the scheme, as used in config and metric labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Jwt => "jwt",
+            Self::AuthToken => "auth_token",
+            Self::MutualTls => "mutual_tls",
+            Self::ApiKey => "api_key",
+            Self::DevOnlyNoAuth => "dev_only_no_auth",
+        }
+    }
+}
+
+/// Count a rejected request in the `toolchain_auth_failures_total` metric. `reason` is a short
+/// snake_case label, e.g. `missing_header` or `wrong_instance`.
+pub fn record_auth_failure(scheme: AuthScheme, reason: &'static str) {
+    metrics::increment_counter!(
+        "toolchain_auth_failures_total",
+        "scheme" => scheme.name(),
+        "reason" => reason,
+    );
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum_macros::Display)]
 pub enum Permissions {
     #[strum(serialize = "cache_ro")]
     Read,
@@ -35,6 +63,10 @@ pub enum Permissions {
     ReadWrite,
     #[strum(serialize = "exec")]
     Execute,
+    /// Destructive administrative operations (e.g. deleting blobs). Deliberately not implied by
+    /// any other permission.
+    #[strum(serialize = "admin")]
+    Admin,
 }
 
 impl Permissions {
@@ -47,6 +79,7 @@ impl Permissions {
             ],
             Self::ReadWrite => vec![Self::ReadWrite.to_string(), Self::Execute.to_string()],
             Self::Execute => vec![Self::Execute.to_string()],
+            Self::Admin => vec![Self::Admin.to_string()],
         };
         all_valid_audiences
             .iter()
@@ -56,8 +89,8 @@ impl Permissions {
 
 /// Extract the bearer auth token from the request's headers.
 ///
-/// Logs if there are any issues with the header.
-pub fn get_bearer_token(metadata: &MetadataMap) -> Result<String, Status> {
+/// Logs if there are any issues with the header, and records them as failures of `scheme`.
+pub fn get_bearer_token(metadata: &MetadataMap, scheme: AuthScheme) -> Result<String, Status> {
     fn get(metadata: &MetadataMap) -> Result<String, String> {
         let auth_value = metadata
             .get("authorization")
@@ -76,6 +109,7 @@ pub fn get_bearer_token(metadata: &MetadataMap) -> Result<String, Status> {
             err,
             metadata
         );
+        record_auth_failure(scheme, "missing_header");
         Status::unauthenticated("missing or invalid authorization header")
     })
 }
@@ -120,29 +154,167 @@ pub fn validate_auth_token(
 ) -> Result<(), Status> {
     let entry = token_mapping.get(&token).ok_or_else(|| {
         log::error!("auth_failure: token {}... not found", token.truncated());
+        record_auth_failure(AuthScheme::AuthToken, "unknown_token");
         Status::unauthenticated("auth token not valid")
     })?;
+    validate_auth_token_entry(
+        entry,
+        requested_instance_name,
+        &format!("token {}...", token.truncated()),
+        AuthScheme::AuthToken,
+    )
+}
+
+/// Check that an `AuthTokenEntry` is active and authorized for the requested instance.
+///
+/// `credential` describes the presented credential for logging, and must not contain the full
+/// secret.
+fn validate_auth_token_entry(
+    entry: &AuthTokenEntry,
+    requested_instance_name: &str,
+    credential: &str,
+    scheme: AuthScheme,
+) -> Result<(), Status> {
     if entry.instance_name != requested_instance_name {
         log::error!(
             "auth_failure: requested instance name {requested_instance_name} but only authorized \
-            for {} (customer: {}). token: {}...",
+            for {} (customer: {}). {credential}",
             entry.instance_name,
             entry.customer_slug,
-            token.truncated()
         );
+        record_auth_failure(scheme, "wrong_instance");
         return Err(Status::unauthenticated("auth token not valid"));
     };
     if !entry.is_active {
         log::error!(
-            "auth_failure: token {}... is not active (customer: {})",
-            token.truncated(),
+            "auth_failure: {credential} is not active (customer: {})",
             entry.customer_slug,
         );
+        record_auth_failure(scheme, "inactive");
         return Err(Status::unauthenticated("auth token not valid"));
     }
     Ok(())
 }
 
+// ---------------------------------------------------------------------------------------
+// API key
+// ---------------------------------------------------------------------------------------
+
+/// The SHA-256 hash of an API key. Only hashes of API keys are stored, never the keys themselves.
+pub type ApiKeyHash = [u8; 32];
+
+pub fn hash_api_key(key: &str) -> ApiKeyHash {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// Parse a JSON object mapping hex-encoded SHA-256 hashes of API keys to their auth metadata.
+pub fn deserialize_api_key_mapping(
+    json: &[u8],
+) -> Result<HashMap<ApiKeyHash, AuthTokenEntry>, String> {
+    let mapping: HashMap<String, AuthTokenEntry> =
+        serde_json::from_slice(json).map_err(|e| format!("{e}"))?;
+    mapping
+        .into_iter()
+        .map(|(hex_hash, entry)| {
+            let mut hash = ApiKeyHash::default();
+            hex::decode_to_slice(&hex_hash, &mut hash)
+                .map_err(|e| format!("Invalid API key hash for entry {}: {e}", entry.id))?;
+            Ok((hash, entry))
+        })
+        .collect()
+}
+
+pub fn validate_api_key(
+    key: &str,
+    requested_instance_name: &str,
+    key_mapping: &HashMap<ApiKeyHash, AuthTokenEntry>,
+) -> Result<(), Status> {
+    let entry = key_mapping.get(&hash_api_key(key)).ok_or_else(|| {
+        log::error!("auth_failure: API key not found");
+        record_auth_failure(AuthScheme::ApiKey, "unknown_token");
+        Status::unauthenticated("auth token not valid")
+    })?;
+    validate_auth_token_entry(
+        entry,
+        requested_instance_name,
+        &format!("API key {}", entry.id),
+        AuthScheme::ApiKey,
+    )
+}
+
+// ---------------------------------------------------------------------------------------
+// Mutual TLS auth
+// ---------------------------------------------------------------------------------------
+
+/// A mapping of client certificate identities to the instance name they are authorized for. An
+/// identity is either a common name (CN) in the certificate's subject, or a DNS name or URI in its
+/// subject alternative names.
+pub type ClientCertificateMapping = HashMap<String, String>;
+
+/// Extract the identities (subject common names and subject alternative names) from a
+/// DER-encoded X.509 certificate.
+fn certificate_identities(der: &[u8]) -> Result<Vec<String>, String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).map_err(|err| err.to_string())?;
+    let mut identities = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    if let Some(san) = cert
+        .subject_alternative_name()
+        .map_err(|err| err.to_string())?
+    {
+        identities.extend(
+            san.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) | GeneralName::URI(name) => Some((*name).to_owned()),
+                    _ => None,
+                }),
+        );
+    }
+    Ok(identities)
+}
+
+/// Validate that the client certificate presented during the TLS handshake is mapped to the
+/// requested instance name.
+///
+/// `peer_certs` is the client's certificate chain, with the client's own certificate first. The
+/// chain itself must already have been verified during the handshake.
+pub fn validate_client_certificate(
+    peer_certs: Option<&[Certificate]>,
+    requested_instance_name: &str,
+    certificate_mapping: &ClientCertificateMapping,
+) -> Result<(), Status> {
+    let cert = peer_certs.and_then(|certs| certs.first()).ok_or_else(|| {
+        log::error!("auth_failure: no client certificate presented");
+        record_auth_failure(AuthScheme::MutualTls, "missing_certificate");
+        Status::unauthenticated("client certificate required")
+    })?;
+    let identities = certificate_identities(cert.get_ref()).map_err(|err| {
+        log::error!("auth_failure: client certificate could not be parsed: {err}");
+        record_auth_failure(AuthScheme::MutualTls, "decode_error");
+        Status::unauthenticated("client certificate not valid")
+    })?;
+    let authorized = identities.iter().any(|identity| {
+        certificate_mapping
+            .get(identity)
+            .map(|instance_name| instance_name == requested_instance_name)
+            .unwrap_or(false)
+    });
+    if !authorized {
+        log::error!(
+            "auth_failure: requested instance name {requested_instance_name} but client \
+            certificate identities {identities:?} are not authorized for it",
+        );
+        record_auth_failure(AuthScheme::MutualTls, "wrong_instance");
+        return Err(Status::unauthenticated("client certificate not valid"));
+    }
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------------------
 // JWT auth
 // ---------------------------------------------------------------------------------------
@@ -150,6 +322,75 @@ pub fn validate_auth_token(
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PrivateClaims {
     toolchain_customer: String,
+
+    /// Any other claims, which may carry permissions (see `JwtPermissionsClaim`).
+    #[serde(flatten)]
+    other: HashMap<String, serde_json::Value>,
+}
+
+/// The claim from which a JWT's permissions (`cache_ro`, `cache_rw`, `exec`, `admin`) are read.
+#[derive(Clone, Debug, Deserialize)]
+pub struct JwtPermissionsClaim {
+    /// Name of the claim. Defaults to the `aud` (audience) claim.
+    #[serde(default = "default_permissions_claim_name")]
+    pub name: String,
+
+    /// If set, string values of the claim are split on this delimiter, e.g. a single space for
+    /// the space-delimited `scope` claim used by OAuth identity providers.
+    pub delimiter: Option<String>,
+}
+
+fn default_permissions_claim_name() -> String {
+    AUDIENCE_CLAIM.to_owned()
+}
+
+const AUDIENCE_CLAIM: &str = "aud";
+
+impl Default for JwtPermissionsClaim {
+    fn default() -> Self {
+        JwtPermissionsClaim {
+            name: default_permissions_claim_name(),
+            delimiter: None,
+        }
+    }
+}
+
+impl JwtPermissionsClaim {
+    fn is_audience(&self) -> bool {
+        self.name == AUDIENCE_CLAIM
+    }
+
+    /// The permissions granted by the claims, or `None` if the claim is missing or malformed.
+    fn permissions(&self, claims: &ClaimsSet) -> Option<SingleOrMultiple<String>> {
+        let values = if self.is_audience() {
+            claims
+                .registered
+                .audience
+                .as_ref()?
+                .iter()
+                .cloned()
+                .collect()
+        } else {
+            match claims.private.other.get(&self.name)? {
+                serde_json::Value::String(value) => vec![value.clone()],
+                serde_json::Value::Array(values) => values
+                    .iter()
+                    .map(|value| value.as_str().map(str::to_owned))
+                    .collect::<Option<Vec<_>>>()?,
+                _ => return None,
+            }
+        };
+        let values = match &self.delimiter {
+            Some(delimiter) => values
+                .iter()
+                .flat_map(|value| value.split(delimiter.as_str()))
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            None => values,
+        };
+        Some(SingleOrMultiple::Multiple(values))
+    }
 }
 
 pub type ClaimsSet = biscuit::ClaimsSet<PrivateClaims>;
@@ -160,7 +401,7 @@ pub fn deserialize_jwk_set(json: &str) -> Result<JWKSet, serde_json::Error> {
     serde_json::from_str(json)
 }
 
-/// Validate the JWT and its claims.
+/// Validate the JWT and its claims. The permissions it grants are read from `permissions_claim`.
 ///
 /// This intentionally returns vague messages for obfuscation/security, but it logs the full error.
 pub fn validate_jwt(
@@ -168,16 +409,23 @@ pub fn validate_jwt(
     requested_instance_name: &str,
     required_permissions: Permissions,
     jwk_set: &JWKSet,
+    permissions_claim: &JwtPermissionsClaim,
 ) -> Result<(), Status> {
     let jwt = JWT::new_encoded(&token);
     let claims = decode_jwt(jwk_set, jwt).map_err(|err| {
         log::error!(
             "auth_failure: token could not be decoded with our JWK Set: {err}. token: {token}"
         );
+        record_auth_failure(AuthScheme::Jwt, "decode_error");
         Status::unauthenticated("authorization failed")
     })?;
-    validate_claims_defined_and_not_expired(&claims).map_err(|err| {
+    validate_claims_defined_and_not_expired(&claims, permissions_claim).map_err(|err| {
         log::error!("auth_failure: token validation failed: {err}. token: {token}",);
+        let reason = match err {
+            ValidationError::Expired(_) => "expired",
+            _ => "invalid_claims",
+        };
+        record_auth_failure(AuthScheme::Jwt, reason);
         Status::unauthenticated("authorization failed")
     })?;
 
@@ -186,15 +434,20 @@ pub fn validate_jwt(
             "auth_failure: requested instance name {requested_instance_name} but only authorized for {}. token {token}",
             claims.private.toolchain_customer,
         );
+        record_auth_failure(AuthScheme::Jwt, "wrong_instance");
         return Err(Status::invalid_argument("unknown instance name"));
     };
 
-    let audience = claims.registered.audience.unwrap();
-    if !required_permissions.is_valid(&audience) {
+    let granted = permissions_claim
+        .permissions(&claims)
+        .unwrap_or(SingleOrMultiple::Multiple(Vec::new()));
+    if !required_permissions.is_valid(&granted) {
         log::error!(
-            "auth_failure: insufficient permissions in `audience`, needed {required_permissions} \
-            but given {audience:?}. token {token}",
+            "auth_failure: insufficient permissions in `{}`, needed {required_permissions} \
+            but given {granted:?}. token {token}",
+            permissions_claim.name,
         );
+        record_auth_failure(AuthScheme::Jwt, "insufficient_permissions");
         return Err(Status::permission_denied("insufficient permissions"));
     }
 
@@ -207,12 +460,20 @@ fn decode_jwt(jwk_set: &JWKSet, jwt: JWT) -> Result<ClaimsSet, BiscuitError> {
     decoded.payload().map(|payload| payload.to_owned())
 }
 
-fn validate_claims_defined_and_not_expired(claims_set: &ClaimsSet) -> Result<(), ValidationError> {
+fn validate_claims_defined_and_not_expired(
+    claims_set: &ClaimsSet,
+    permissions_claim: &JwtPermissionsClaim,
+) -> Result<(), ValidationError> {
     let validation_options = ValidationOptions {
         claim_presence_options: ClaimPresenceOptions {
             issued_at: Presence::Required,
             expiry: Presence::Required,
-            audience: Presence::Required,
+            // The audience is only required if it carries the permissions.
+            audience: if permissions_claim.is_audience() {
+                Presence::Required
+            } else {
+                Presence::Optional
+            },
             not_before: Presence::Optional,
             issuer: Presence::Optional,
             subject: Presence::Optional,
@@ -301,6 +562,23 @@ pub fn make_jwk_set_multiple() -> JWKSet {
 
 /// Generate a JWT string for tests.
 pub fn generate_jwt(audience: &str, instance_name: &str, key_id: &str, secret: &[u8]) -> String {
+    generate_jwt_with_claims(
+        Some(audience),
+        HashMap::new(),
+        instance_name,
+        key_id,
+        secret,
+    )
+}
+
+/// Generate a JWT string for tests, with an optional audience and additional private claims.
+pub fn generate_jwt_with_claims(
+    audience: Option<&str>,
+    other_claims: HashMap<String, serde_json::Value>,
+    instance_name: &str,
+    key_id: &str,
+    secret: &[u8],
+) -> String {
     let issued_at = Some(biscuit::Timestamp::from(
         chrono::Utc::now() - chrono::Duration::minutes(5),
     ));
@@ -319,11 +597,13 @@ pub fn generate_jwt(audience: &str, instance_name: &str, key_id: &str, secret: &
             registered: biscuit::RegisteredClaims {
                 issued_at,
                 expiry,
-                audience: Some(biscuit::SingleOrMultiple::Single(audience.to_owned())),
+                audience: audience
+                    .map(|audience| biscuit::SingleOrMultiple::Single(audience.to_owned())),
                 ..Default::default()
             },
             private: PrivateClaims {
                 toolchain_customer: instance_name.to_owned(),
+                other: other_claims,
             },
         },
     );
@@ -339,14 +619,17 @@ mod tests {
     use std::str::FromStr;
 
     use crate::auth::{
-        generate_jwt, get_bearer_token, make_jwk_set, validate_auth_token,
-        validate_claims_defined_and_not_expired, validate_jwt, AuthToken, AuthTokenEntry,
-        ClaimsSet, Permissions, PrivateClaims, TEST_INSTANCE_NAME, TEST_KEY_ID_1, TEST_SECRET_1,
+        deserialize_api_key_mapping, generate_jwt, generate_jwt_with_claims, get_bearer_token,
+        hash_api_key, make_jwk_set, validate_api_key, validate_auth_token,
+        validate_claims_defined_and_not_expired, validate_client_certificate, validate_jwt,
+        AuthScheme, AuthToken, AuthTokenEntry, ClaimsSet, JwtPermissionsClaim, Permissions,
+        PrivateClaims, TEST_INSTANCE_NAME, TEST_KEY_ID_1, TEST_SECRET_1,
     };
     use biscuit::errors::ValidationError;
     use biscuit::{RegisteredClaims, SingleOrMultiple, Timestamp};
     use chrono::Duration;
     use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+    use tonic::transport::Certificate;
     use tonic::{Code, Status};
 
     #[test]
@@ -393,6 +676,86 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_api_key() {
+        let key_mapping = deserialize_api_key_mapping(
+            format!(
+                r#"{{
+                  "{}": {{"id": "xyz", "is_active": true, "instance_name": "abc", "customer_slug": "my-customer"}},
+                  "{}": {{"id": "def", "is_active": false, "instance_name": "abc", "customer_slug": "my-customer"}}
+                }}"#,
+                hex::encode(hash_api_key("active-key")),
+                hex::encode(hash_api_key("inactive-key")),
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert!(validate_api_key("active-key", "abc", &key_mapping).is_ok());
+        assert_eq!(
+            validate_api_key("active-key", "xyz", &key_mapping)
+                .expect_err("")
+                .code(),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            validate_api_key("unknown-key", "abc", &key_mapping)
+                .expect_err("")
+                .code(),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            validate_api_key("inactive-key", "abc", &key_mapping)
+                .expect_err("")
+                .code(),
+            Code::Unauthenticated
+        );
+    }
+
+    #[test]
+    fn test_validate_client_certificate() {
+        let mut params = rcgen::CertificateParams::new(vec!["build-agent.example.com".to_owned()]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "build-agent");
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let peer_certs = vec![Certificate::from_pem(cert.serialize_der().unwrap())];
+
+        let validate = |mapping: &[(&str, &str)], requested_instance_name: &str| {
+            let mapping = mapping
+                .iter()
+                .map(|(identity, instance)| ((*identity).to_owned(), (*instance).to_owned()))
+                .collect();
+            validate_client_certificate(Some(&peer_certs), requested_instance_name, &mapping)
+        };
+
+        // Both the subject CN and the SAN may be mapped to an instance.
+        assert!(validate(&[("build-agent", "abc")], "abc").is_ok());
+        assert!(validate(&[("build-agent.example.com", "abc")], "abc").is_ok());
+
+        // A subject which is mapped to a different instance, or not mapped at all, is rejected.
+        assert_eq!(
+            validate(&[("build-agent", "xyz")], "abc")
+                .expect_err("")
+                .code(),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            validate(&[("other-agent", "abc")], "abc")
+                .expect_err("")
+                .code(),
+            Code::Unauthenticated
+        );
+
+        // No certificate at all is rejected.
+        assert_eq!(
+            validate_client_certificate(None, "abc", &HashMap::new())
+                .expect_err("")
+                .code(),
+            Code::Unauthenticated
+        );
+    }
+
     #[test]
     fn test_validate_jwt() {
         fn validate(
@@ -407,12 +770,13 @@ mod tests {
                     AsciiMetadataValue::try_from(token).unwrap(),
                 );
             }
-            let token = get_bearer_token(&metadata)?;
+            let token = get_bearer_token(&metadata, AuthScheme::Jwt)?;
             validate_jwt(
                 token,
                 requested_instance_name,
                 required_permissions,
                 &make_jwk_set(),
+                &JwtPermissionsClaim::default(),
             )
         }
 
@@ -498,6 +862,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_jwt_scope_claim() {
+        let scope_claim = JwtPermissionsClaim {
+            name: "scope".to_owned(),
+            delimiter: Some(" ".to_owned()),
+        };
+        let validate = |scope: Option<&str>, permissions_claim, required_permissions| {
+            let other_claims = scope
+                .map(|scope| HashMap::from([("scope".to_owned(), scope.into())]))
+                .unwrap_or_default();
+            let token = generate_jwt_with_claims(
+                None,
+                other_claims,
+                TEST_INSTANCE_NAME,
+                TEST_KEY_ID_1,
+                TEST_SECRET_1,
+            );
+            validate_jwt(
+                token,
+                TEST_INSTANCE_NAME,
+                required_permissions,
+                &make_jwk_set(),
+                permissions_claim,
+            )
+        };
+
+        assert!(validate(Some("cache_ro exec"), &scope_claim, Permissions::Read).is_ok());
+        assert!(validate(Some("cache_ro exec"), &scope_claim, Permissions::Execute).is_ok());
+        // `exec` does not imply `admin`.
+        assert_eq!(
+            validate(Some("cache_ro exec"), &scope_claim, Permissions::Admin)
+                .expect_err("")
+                .code(),
+            Code::PermissionDenied
+        );
+        assert_eq!(
+            validate(Some("cache_ro"), &scope_claim, Permissions::ReadWrite)
+                .expect_err("")
+                .code(),
+            Code::PermissionDenied
+        );
+        assert_eq!(
+            validate(None, &scope_claim, Permissions::Read)
+                .expect_err("")
+                .code(),
+            Code::PermissionDenied
+        );
+
+        // By default, permissions come from the audience, which the token lacks.
+        assert_eq!(
+            validate(
+                Some("cache_ro exec"),
+                &JwtPermissionsClaim::default(),
+                Permissions::Read
+            )
+            .expect_err("")
+            .code(),
+            Code::Unauthenticated
+        );
+    }
+
     #[test]
     fn test_validate_claims_defined_and_not_expired() {
         fn validate(
@@ -514,9 +939,10 @@ mod tests {
                 },
                 private: PrivateClaims {
                     toolchain_customer: TEST_INSTANCE_NAME.to_owned(),
+                    other: HashMap::new(),
                 },
             };
-            validate_claims_defined_and_not_expired(&claims)
+            validate_claims_defined_and_not_expired(&claims, &JwtPermissionsClaim::default())
         }
 
         // Missing required claims.
@@ -619,5 +1045,16 @@ mod tests {
         );
         assert!(!Permissions::Execute.is_valid(&SingleOrMultiple::Multiple(vec![])));
         assert!(!Permissions::Execute.is_valid(&SingleOrMultiple::Multiple(vec!["bad".to_owned()])));
+
+        // Admin permissions require admin, and admin does not imply any other permission.
+        assert!(Permissions::Admin.is_valid(&SingleOrMultiple::Single("admin".to_owned())));
+        assert!(!Permissions::Admin.is_valid(&SingleOrMultiple::Single("exec".to_owned())));
+        assert!(
+            !Permissions::Admin.is_valid(&SingleOrMultiple::Multiple(vec![
+                "cache_rw".to_owned(),
+                "exec".to_owned()
+            ]))
+        );
+        assert!(!Permissions::Read.is_valid(&SingleOrMultiple::Single("admin".to_owned())));
     }
 }
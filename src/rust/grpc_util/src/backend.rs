@@ -3,8 +3,9 @@
 
 use ginepro::LoadBalancedChannel;
 use serde::Deserialize;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 
-#[derive(Deserialize, Debug)]
+#[derive(Clone, Deserialize, Debug)]
 pub struct BackendConfig {
     /// ADDRESS:PORT of this backend.
     pub address: String,
@@ -12,6 +13,36 @@ pub struct BackendConfig {
     /// Number of concurrent connections to maintain to this backend.
     #[serde(default = "default_connections")]
     pub connections: usize,
+
+    /// Path to the PEM-encoded client certificate to present to this backend. Must be set
+    /// together with `client_key_path`.
+    #[serde(default)]
+    pub client_cert_path: Option<String>,
+
+    /// Path to the PEM-encoded private key for the client certificate.
+    #[serde(default)]
+    pub client_key_path: Option<String>,
+
+    /// Path to the PEM-encoded CA certificate(s) used to verify this backend. If unset while a
+    /// client certificate is configured, the system roots are used.
+    #[serde(default)]
+    pub ca_cert_path: Option<String>,
+
+    /// gRPC compression to negotiate with this backend. Unset disables compression.
+    #[serde(default)]
+    pub compression: Option<BackendCompression>,
+}
+
+/// How gRPC messages exchanged with a backend are compressed.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendCompression {
+    /// Advertise that gzip-compressed responses are accepted, but send requests uncompressed.
+    /// Safe for backends which do not support compression, since they simply respond
+    /// uncompressed.
+    AcceptGzip,
+    /// Additionally compress requests with gzip. The backend must support gzip.
+    Gzip,
 }
 
 fn default_connections() -> usize {
@@ -23,22 +54,71 @@ impl Default for BackendConfig {
         BackendConfig {
             address: String::new(),
             connections: default_connections(),
+            client_cert_path: None,
+            client_key_path: None,
+            ca_cert_path: None,
+            compression: None,
         }
     }
 }
 
-pub async fn construct_channel(config: BackendConfig) -> Result<LoadBalancedChannel, String> {
-    let (hostname, port_str) = match config.address.split_once(':') {
-        Some((h, p)) => (h, p),
-        None => return Err("Expected NAME:PORT".to_owned()),
-    };
-    if hostname.is_empty() || port_str.is_empty() {
-        return Err("Expected NAME:PORT".to_owned());
+impl BackendConfig {
+    /// Build the TLS config for connecting to this backend, or `None` if no TLS files are
+    /// configured and the connection should be plaintext.
+    pub fn to_client_tls_config(
+        &self,
+        domain_name: &str,
+    ) -> Result<Option<ClientTlsConfig>, String> {
+        let read = |path: &str| {
+            std::fs::read(path).map_err(|err| format!("Failed to read TLS file {path}: {err}"))
+        };
+        let identity = match (&self.client_cert_path, &self.client_key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                Some(Identity::from_pem(read(cert_path)?, read(key_path)?))
+            }
+            (None, None) => None,
+            _ => return Err("client_cert_path and client_key_path must be set together".to_owned()),
+        };
+        if identity.is_none() && self.ca_cert_path.is_none() {
+            return Ok(None);
+        }
+
+        let mut tls_config = ClientTlsConfig::new().domain_name(domain_name);
+        if let Some(identity) = identity {
+            tls_config = tls_config.identity(identity);
+        }
+        if let Some(ca_cert_path) = &self.ca_cert_path {
+            tls_config = tls_config.ca_certificate(Certificate::from_pem(read(ca_cert_path)?));
+        }
+        Ok(Some(tls_config))
     }
-    let port: u16 = match port_str.parse() {
-        Ok(p) => p,
-        Err(_) => return Err("Unable to parse port".into()),
-    };
+
+    /// Check that the address of this backend is valid and that its TLS files can be read,
+    /// without connecting to it.
+    pub fn validate(&self) -> Result<(), String> {
+        let (hostname, _) = self.host_and_port()?;
+        self.to_client_tls_config(hostname)?;
+        Ok(())
+    }
+
+    fn host_and_port(&self) -> Result<(&str, u16), String> {
+        let (hostname, port_str) = match self.address.split_once(':') {
+            Some((h, p)) => (h, p),
+            None => return Err("Expected NAME:PORT".to_owned()),
+        };
+        if hostname.is_empty() || port_str.is_empty() {
+            return Err("Expected NAME:PORT".to_owned());
+        }
+        let port: u16 = match port_str.parse() {
+            Ok(p) => p,
+            Err(_) => return Err("Unable to parse port".into()),
+        };
+        Ok((hostname, port))
+    }
+}
+
+pub async fn construct_channel(config: BackendConfig) -> Result<LoadBalancedChannel, String> {
+    let (hostname, port) = config.host_and_port()?;
     let service_definition = match ginepro::ServiceDefinition::from_parts(hostname, port) {
         Ok(sd) => sd,
         Err(err) => {
@@ -48,8 +128,91 @@ pub async fn construct_channel(config: BackendConfig) -> Result<LoadBalancedChan
         }
     };
 
-    ginepro::LoadBalancedChannel::builder(service_definition)
+    let tls_config = config.to_client_tls_config(hostname)?;
+
+    // TODO: Send HTTP/2 keep-alive pings, so that connections which a load balancer silently
+    // drops while idle are detected before a request is sent on them. ginepro 0.6 builds the
+    // endpoints of a `LoadBalancedChannel` itself and offers no way to configure keep-alive.
+    let mut builder = ginepro::LoadBalancedChannel::builder(service_definition);
+    if let Some(tls_config) = tls_config {
+        builder = builder.with_tls(tls_config);
+    }
+    builder
         .channel()
         .await
         .map_err(|err| format!("failed to initialize channel: {err}"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{construct_channel, BackendConfig};
+
+    fn write_client_cert(dir: &std::path::Path) -> (String, String) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_path = dir.join("client.pem");
+        let key_path = dir.join("client.key");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (
+            cert_path.to_str().unwrap().to_owned(),
+            key_path.to_str().unwrap().to_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn constructs_channel_with_client_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path) = write_client_cert(dir.path());
+        let config = BackendConfig {
+            address: "localhost:8980".to_owned(),
+            client_cert_path: Some(cert_path.clone()),
+            client_key_path: Some(key_path),
+            ca_cert_path: Some(cert_path),
+            ..BackendConfig::default()
+        };
+        construct_channel(config).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn requires_client_certificate_and_key_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, _) = write_client_cert(dir.path());
+        let config = BackendConfig {
+            address: "localhost:8980".to_owned(),
+            client_cert_path: Some(cert_path),
+            ..BackendConfig::default()
+        };
+        let err = construct_channel(config).await.unwrap_err();
+        assert!(err.contains("must be set together"));
+    }
+
+    #[test]
+    fn validates_address_and_tls_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path) = write_client_cert(dir.path());
+        let config = BackendConfig {
+            address: "localhost:8980".to_owned(),
+            client_cert_path: Some(cert_path),
+            client_key_path: Some(key_path),
+            ..BackendConfig::default()
+        };
+        config.validate().unwrap();
+
+        let config = BackendConfig {
+            address: "localhost".to_owned(),
+            ..BackendConfig::default()
+        };
+        assert_eq!(config.validate().unwrap_err(), "Expected NAME:PORT");
+
+        let missing_path = dir.path().join("missing.pem");
+        let config = BackendConfig {
+            address: "localhost:8980".to_owned(),
+            ca_cert_path: Some(missing_path.to_str().unwrap().to_owned()),
+            ..BackendConfig::default()
+        };
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .starts_with("Failed to read TLS file"));
+    }
+}
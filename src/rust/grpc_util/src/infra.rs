@@ -1,15 +1,23 @@
 // Copyright 2021 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashMap;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
+use futures::future::BoxFuture;
 use futures::FutureExt;
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use serde::Deserialize;
 use tokio::runtime::Builder;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::watch;
-use warp::Filter;
+use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
 
 /// Default Prometheus histogram buckets.
 /// These have been chosen to hopefully be better for latencies internal to the AWS cloud.
@@ -42,7 +50,7 @@ const DEFAULT_PROMETHEUS_BUCKETS: &[f64] = &[
 /// Admin endpoints configuration.
 #[derive(Clone, Debug, Deserialize)]
 pub struct InfraConfig {
-    /// Bind address for the metricsz endpoint.
+    /// Bind address for the Prometheus metrics endpoints (`/metrics` and `/metricsz`).
     #[serde(default = "default_metricsz_bind_addr")]
     pub metricsz_bind_addr: String,
 
@@ -55,6 +63,22 @@ pub struct InfraConfig {
 
     /// Tracing configuration
     pub tracing: Option<TracingConfig>,
+
+    /// Format of the log lines written to stdout.
+    #[serde(default)]
+    pub log_format: LogFormat,
+}
+
+/// Format of the log lines written by `setup_logging`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, with the `timestamp`, `level`, `target` (module), `fields`
+    /// (including the `message`) and the current `span` and `spans` of each event.
+    #[default]
+    Json,
 }
 
 impl Default for InfraConfig {
@@ -64,6 +88,7 @@ impl Default for InfraConfig {
             bind_addr: default_bind_addr(),
             sentry_dsn: None,
             tracing: None,
+            log_format: LogFormat::default(),
         }
     }
 }
@@ -71,13 +96,17 @@ impl Default for InfraConfig {
 /// Tracing configuration
 #[derive(Clone, Debug, Deserialize)]
 pub struct TracingConfig {
-    /// OpenTelemetry agent endpoint, e.g. `http://otel_collector:4317`
-    pub otel_agent: String,
+    /// OpenTelemetry agent endpoint, e.g. `http://otel_collector:4317`. Defaults to the value of
+    /// the `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable.
+    pub otel_agent: Option<String>,
 
     /// Sampling probability used by the OpenTelemetry subscriber.
     ///
     /// Expects a number from 0.0 to 1.0, where higher numbers mean more events will be sent.
     pub sampling_probability: f64,
+
+    /// Service name reported with exported spans. Defaults to the name of the binary.
+    pub service_name: Option<String>,
 }
 
 fn default_metricsz_bind_addr() -> String {
@@ -88,17 +117,58 @@ fn default_bind_addr() -> String {
     "0.0.0.0:8000".to_owned()
 }
 
+/// Default limit on the size of gRPC messages decoded or encoded by a service. This allows
+/// batches of up to the storage server's default `max_batch_total_size_bytes` (4 MiB) of blob
+/// content, plus headroom for the rest of the message (e.g., digests).
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 5 * 1024 * 1024;
+
+/// Default time to wait for in-flight requests to finish once shutdown has been signalled.
+pub const DEFAULT_SHUTDOWN_DRAIN_DEADLINE: Duration = Duration::from_secs(30);
+
 /// Configuration of gRPC-specific properties.
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
 pub struct GrpcConfig {
     /// Number of in-flight requests allowed inbound per connection.
     pub concurrency_limit_per_connection: Option<usize>,
 
     /// Max number of HTTP/2 concurrent streams.
     pub max_concurrent_streams: Option<u32>,
+
+    /// Max size in bytes of a message decoded by a service. Defaults to
+    /// `DEFAULT_MAX_MESSAGE_SIZE`.
+    pub max_decoding_message_size: Option<usize>,
+
+    /// Max size in bytes of a message encoded by a service. Defaults to
+    /// `DEFAULT_MAX_MESSAGE_SIZE`.
+    pub max_encoding_message_size: Option<usize>,
+
+    /// Seconds to wait for in-flight requests to finish once shutdown has been signalled, after
+    /// which they are abandoned. Defaults to `DEFAULT_SHUTDOWN_DRAIN_DEADLINE`.
+    pub shutdown_drain_deadline_secs: Option<u64>,
 }
 
 impl GrpcConfig {
+    /// Max size in bytes of a message decoded by a service. Must be applied to each service
+    /// since Tonic only supports configuring it per-service.
+    pub fn max_decoding_message_size(&self) -> usize {
+        self.max_decoding_message_size
+            .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Max size in bytes of a message encoded by a service. Must be applied to each service
+    /// since Tonic only supports configuring it per-service.
+    pub fn max_encoding_message_size(&self) -> usize {
+        self.max_encoding_message_size
+            .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Time to wait for in-flight requests to finish once shutdown has been signalled.
+    pub fn shutdown_drain_deadline(&self) -> Duration {
+        self.shutdown_drain_deadline_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SHUTDOWN_DRAIN_DEADLINE)
+    }
+
     pub fn apply_to_server(
         &self,
         mut server: tonic::transport::Server,
@@ -115,6 +185,198 @@ impl GrpcConfig {
     }
 }
 
+/// Wait until `shutdown_receiver` reports that shutdown has been signalled.
+pub async fn wait_for_shutdown(mut shutdown_receiver: watch::Receiver<()>) {
+    while shutdown_receiver.changed().await.is_ok() {}
+}
+
+/// Drive a server which stops accepting new requests once `shutdown_receiver` signals shutdown
+/// (i.e. it was started with `wait_for_shutdown` as its shutdown signal). In-flight requests are
+/// given `drain_deadline` to finish after shutdown has been signalled, after which the server is
+/// dropped and any remaining requests are abandoned.
+pub async fn serve_with_drain_deadline<E>(
+    server: impl Future<Output = Result<(), E>>,
+    shutdown_receiver: watch::Receiver<()>,
+    drain_deadline: Duration,
+    in_flight_requests_counter: InFlightRequestsCounter,
+) -> Result<(), E> {
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => return result,
+        _ = wait_for_shutdown(shutdown_receiver) => {}
+    }
+
+    log::info!(
+        "Waiting up to {drain_deadline:?} for {} in-flight requests to finish ...",
+        in_flight_requests_counter.get()
+    );
+    match tokio::time::timeout(drain_deadline, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            log::warn!(
+                "Abandoning {} in-flight requests which did not finish within {drain_deadline:?}.",
+                in_flight_requests_counter.get()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Whether a server is ready to serve traffic, as reported by the `readyz` infra endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    /// A `Readiness` for servers which are ready as soon as they start.
+    pub fn ready() -> Self {
+        let readiness = Readiness::default();
+        readiness.set_ready();
+        readiness
+    }
+
+    pub fn set_ready(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Substrings of config field names whose values are redacted by the `/debug/config` endpoint.
+const REDACTED_FIELD_PATTERNS: &[&str] = &[
+    "password",
+    "secret",
+    "token",
+    "key",
+    "jwk",
+    "dsn",
+    "credential",
+];
+
+const REDACTED_VALUE: &str = "<redacted>";
+
+/// Build and config information reported by the `/debug/version` and `/debug/config` endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    version: serde_json::Value,
+    config: serde_json::Value,
+}
+
+impl DebugInfo {
+    /// Create a `DebugInfo` from the crate version, the git sha the binary was built from (if
+    /// known), and the YAML content of the loaded config. Any config fields which may hold
+    /// secrets are redacted.
+    pub fn new(version: &str, git_sha: Option<&str>, config_yaml: &str) -> Result<Self, String> {
+        let mut config: serde_json::Value = serde_yaml::from_str(config_yaml)
+            .map_err(|err| format!("Failed to parse config for debug endpoint: {err}"))?;
+        redact_secrets(&mut config);
+        Ok(DebugInfo {
+            version: serde_json::json!({
+                "version": version,
+                "git_sha": git_sha,
+            }),
+            config,
+        })
+    }
+}
+
+/// Replace the values of any fields whose names look like they hold secrets (or paths to them).
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                let name = name.to_lowercase();
+                if REDACTED_FIELD_PATTERNS
+                    .iter()
+                    .any(|pattern| name.contains(pattern))
+                {
+                    *field = serde_json::Value::String(REDACTED_VALUE.to_owned());
+                } else {
+                    redact_secrets(field);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// An action which may be triggered at `POST /admin/<name>`. On success, returns a message for
+/// the caller.
+pub type AdminAction = Arc<dyn Fn() -> BoxFuture<'static, Result<String, String>> + Send + Sync>;
+
+/// The actions served by the `/admin/<name>` infra endpoints. Requests must carry the shared
+/// admin secret as a bearer token in the `authorization` header. If no secret is configured, the
+/// admin endpoints are disabled.
+#[derive(Clone, Default)]
+pub struct AdminActions {
+    secret: Option<String>,
+    actions: HashMap<String, AdminAction>,
+}
+
+impl AdminActions {
+    pub fn new(secret: Option<String>) -> Self {
+        AdminActions {
+            secret,
+            actions: HashMap::new(),
+        }
+    }
+
+    pub fn with_action<F, Fut>(mut self, name: &str, action: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        self.actions
+            .insert(name.to_owned(), Arc::new(move || action().boxed()));
+        self
+    }
+
+    /// Check that `authorization` carries the admin secret.
+    fn authorize(&self, authorization: Option<&str>) -> Result<(), (StatusCode, String)> {
+        let Some(secret) = &self.secret else {
+            return Err((
+                StatusCode::NOT_FOUND,
+                "Admin endpoints are disabled".to_owned(),
+            ));
+        };
+        let authorized = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|token| constant_time_eq(token.as_bytes(), secret.as_bytes()))
+            .is_some();
+        if !authorized {
+            return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Run the named action if `authorization` carries the admin secret.
+    pub async fn run(
+        &self,
+        name: &str,
+        authorization: Option<&str>,
+    ) -> Result<String, (StatusCode, String)> {
+        self.authorize(authorization)?;
+        let action = self.actions.get(name).ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("Unknown admin action: {name}"),
+            )
+        })?;
+        log::info!("Running admin action: {name}");
+        action().await.map_err(|err| {
+            log::error!("Admin action {name} failed: {err}");
+            (StatusCode::INTERNAL_SERVER_ERROR, err)
+        })
+    }
+}
+
+/// Compare two byte strings in time which depends only on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// Setup metrics collection and scraping endpoint.
 fn setup_metrics_handler() -> Result<PrometheusHandle, String> {
     // Build the Prometheus metrics recorder and exporter.
@@ -128,12 +390,36 @@ fn setup_metrics_handler() -> Result<PrometheusHandle, String> {
     Ok(handle)
 }
 
-/// Setup infra endpoints for use by devops systems.
+/// Setup infra endpoints for use by devops systems, for a server which is ready as soon as it
+/// starts.
 ///
 /// Returns a `sync::watch` receiver that should be used by all servers as a signal for when they
 /// should be shut down by looking for RecvError when calling `.changed()`.
 pub fn setup_infra_endpoints(
     config: InfraConfig,
+    debug_info: DebugInfo,
+    admin_actions: AdminActions,
+    run_before_metrics_collection: impl Fn() + Clone + Send + Sync + 'static,
+) -> Result<watch::Receiver<()>, String> {
+    setup_infra_endpoints_with_readiness(
+        config,
+        Readiness::ready(),
+        debug_info,
+        admin_actions,
+        run_before_metrics_collection,
+    )
+}
+
+/// Setup infra endpoints for use by devops systems. The `readyz` endpoint fails until
+/// `readiness` is set.
+///
+/// Returns a `sync::watch` receiver that should be used by all servers as a signal for when they
+/// should be shut down by looking for RecvError when calling `.changed()`.
+pub fn setup_infra_endpoints_with_readiness(
+    config: InfraConfig,
+    readiness: Readiness,
+    debug_info: DebugInfo,
+    admin_actions: AdminActions,
     run_before_metrics_collection: impl Fn() + Clone + Send + Sync + 'static,
 ) -> Result<watch::Receiver<()>, String> {
     // Setup metrics collection.
@@ -181,11 +467,67 @@ pub fn setup_infra_endpoints(
             // Setup health endpoint.
             let healthz = warp::path("healthz").and(warp::get()).map(|| "OK");
 
-            // Build Warp handler to render the metrics.
-            let metrics = warp::path!("metricsz").and(warp::get()).map(move || {
-                run_before_metrics_collection();
-                metrics_handle.render()
+            // Setup readiness endpoint.
+            let readyz = warp::path("readyz").and(warp::get()).map(move || {
+                if readiness.is_ready() {
+                    warp::reply::with_status("OK", StatusCode::OK)
+                } else {
+                    warp::reply::with_status("NOT READY", StatusCode::SERVICE_UNAVAILABLE)
+                }
             });
+
+            // Setup the read-only debug endpoints. The config is only served to admins, since
+            // the redaction of its secrets is best-effort.
+            let DebugInfo { version, config } = debug_info;
+            let debug_version = warp::path!("debug" / "version")
+                .and(warp::get())
+                .map(move || warp::reply::json(&version));
+            let debug_config = {
+                let admin_actions = admin_actions.clone();
+                warp::path!("debug" / "config")
+                    .and(warp::get())
+                    .and(warp::header::optional::<String>("authorization"))
+                    .map(move |authorization: Option<String>| {
+                        match admin_actions.authorize(authorization.as_deref()) {
+                            Ok(()) => warp::reply::with_status(
+                                warp::reply::json(&config).into_response(),
+                                StatusCode::OK,
+                            ),
+                            Err((status, message)) => {
+                                warp::reply::with_status(message.into_response(), status)
+                            }
+                        }
+                    })
+            };
+
+            // Setup the admin endpoints.
+            let admin = warp::path!("admin" / String)
+                .and(warp::post())
+                .and(warp::header::optional::<String>("authorization"))
+                .then(move |name: String, authorization: Option<String>| {
+                    let admin_actions = admin_actions.clone();
+                    async move {
+                        match admin_actions.run(&name, authorization.as_deref()).await {
+                            Ok(message) => warp::reply::with_status(message, StatusCode::OK),
+                            Err((status, message)) => warp::reply::with_status(message, status),
+                        }
+                    }
+                });
+
+            // Build Warp handler to render the metrics in the Prometheus text format, at both the
+            // conventional `/metrics` path and the legacy `/metricsz` path.
+            let metrics = warp::path!("metrics")
+                .or(warp::path!("metricsz"))
+                .unify()
+                .and(warp::get())
+                .map(move || {
+                    run_before_metrics_collection();
+                    warp::reply::with_header(
+                        metrics_handle.render(),
+                        "content-type",
+                        "text/plain; version=0.0.4",
+                    )
+                });
             let metrics_fut = warp::serve(metrics).bind(metricsz_bind_addr);
 
             // Setup the sentry check endpoint.
@@ -209,7 +551,15 @@ pub fn setup_infra_endpoints(
                 });
 
             // Spawn the infra endpoints server.
-            let server_fut = warp::serve(healthz.or(sentryz)).bind(bind_addr);
+            let server_fut = warp::serve(
+                healthz
+                    .or(readyz)
+                    .or(sentryz)
+                    .or(debug_version)
+                    .or(debug_config)
+                    .or(admin),
+            )
+            .bind(bind_addr);
 
             // Join on both admin servers.
             futures::future::join(server_fut, metrics_fut).await
@@ -221,15 +571,128 @@ pub fn setup_infra_endpoints(
 
 #[cfg(test)]
 mod tests {
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::time::Instant;
+
+    use protos::build::bazel::remote::execution::v2::capabilities_client::CapabilitiesClient;
+    use protos::build::bazel::remote::execution::v2::capabilities_server::{
+        Capabilities, CapabilitiesServer,
+    };
+    use protos::build::bazel::remote::execution::v2::{GetCapabilitiesRequest, ServerCapabilities};
     use reqwest::StatusCode;
+    use tokio::sync::watch;
     use tokio::time::{sleep, Duration};
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+    use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
+    use tower_http::metrics::InFlightRequestsLayer;
+
+    use super::{
+        serve_with_drain_deadline, setup_infra_endpoints, wait_for_shutdown, AdminActions,
+        DebugInfo, InfraConfig,
+    };
+
+    struct SlowCapabilitiesService;
+
+    #[tonic::async_trait]
+    impl Capabilities for SlowCapabilitiesService {
+        async fn get_capabilities(
+            &self,
+            _request: Request<GetCapabilitiesRequest>,
+        ) -> Result<Response<ServerCapabilities>, Status> {
+            sleep(Duration::from_secs(60)).await;
+            Ok(Response::new(ServerCapabilities::default()))
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_abandons_requests_after_drain_deadline() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let (shutdown_sender, shutdown_receiver) = watch::channel(());
+        let in_flight_requests_counter = InFlightRequestsCounter::new();
+        let server = Server::builder()
+            .layer(InFlightRequestsLayer::new(
+                in_flight_requests_counter.clone(),
+            ))
+            .add_service(CapabilitiesServer::new(SlowCapabilitiesService))
+            .serve_with_shutdown(addr, wait_for_shutdown(shutdown_receiver.clone()));
+        let drain_deadline = Duration::from_millis(200);
+        let server_task = tokio::spawn(serve_with_drain_deadline(
+            server,
+            shutdown_receiver,
+            drain_deadline,
+            in_flight_requests_counter.clone(),
+        ));
+        sleep(Duration::from_millis(100)).await;
+
+        let mut client = CapabilitiesClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let _ = client
+                .get_capabilities(GetCapabilitiesRequest::default())
+                .await;
+        });
+        while in_flight_requests_counter.get() == 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let shutdown_started = Instant::now();
+        drop(shutdown_sender);
+        tokio::time::timeout(Duration::from_secs(5), server_task)
+            .await
+            .expect("server did not exit within the drain deadline")
+            .unwrap()
+            .unwrap();
+        assert!(shutdown_started.elapsed() >= drain_deadline);
+    }
 
-    use super::{setup_infra_endpoints, InfraConfig};
+    const CONFIG_YAML: &str = r#"
+listen_address: 0.0.0.0:8980
+infra:
+  sentry_dsn: https://abc@sentry.example.com/1
+redis_backends:
+  main:
+    address: redis:6379
+    password_secret: redis-password
+amberflo_backend:
+  api_key_file: /etc/amberflo/key.json
+backends:
+  - name: cas
+    auth_token: hunter2
+"#;
+
+    #[test]
+    fn debug_info_redacts_secrets() {
+        let debug_info = DebugInfo::new("1.2.3", Some("abc123"), CONFIG_YAML).unwrap();
+        assert_eq!(
+            debug_info.version,
+            serde_json::json!({"version": "1.2.3", "git_sha": "abc123"})
+        );
+        assert_eq!(
+            debug_info.config,
+            serde_json::json!({
+                "listen_address": "0.0.0.0:8980",
+                "infra": {"sentry_dsn": "<redacted>"},
+                "redis_backends": {
+                    "main": {"address": "redis:6379", "password_secret": "<redacted>"},
+                },
+                "amberflo_backend": {"api_key_file": "<redacted>"},
+                "backends": [{"name": "cas", "auth_token": "<redacted>"}],
+            })
+        );
+    }
 
     #[tokio::test]
     async fn infra_endpoints_respond() {
         let config = InfraConfig::default();
-        setup_infra_endpoints(config, || {}).unwrap();
+        let debug_info = DebugInfo::new("1.2.3", None, CONFIG_YAML).unwrap();
+        let admin_actions = AdminActions::new(Some("s3cret".to_owned()))
+            .with_action("ping", || async { Ok("pong".to_owned()) });
+        setup_infra_endpoints(config, debug_info, admin_actions, || {}).unwrap();
 
         // `warp` does not give us a way to wait until it has finished binding.
         sleep(Duration::from_millis(500)).await;
@@ -240,6 +703,57 @@ mod tests {
         let body = response.text().await.unwrap();
         assert_eq!(body, "OK");
 
+        // test /readyz
+        let response = reqwest::get("http://127.0.0.1:8000/readyz").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // test /debug/version
+        let response = reqwest::get("http://127.0.0.1:8000/debug/version")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["version"], "1.2.3");
+
+        // test /debug/config
+        let client = reqwest::Client::new();
+        let debug_config_request = |token: Option<&str>| {
+            let mut request = client.get("http://127.0.0.1:8000/debug/config");
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send()
+        };
+        let response = debug_config_request(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = debug_config_request(Some("s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["listen_address"], "0.0.0.0:8980");
+        assert_eq!(
+            body["redis_backends"]["main"]["password_secret"],
+            "<redacted>"
+        );
+        assert!(!body.to_string().contains("hunter2"));
+
+        // test /admin
+        let admin_request = |name: &str, token: Option<&str>| {
+            let mut request = client.post(format!("http://127.0.0.1:8000/admin/{name}"));
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send()
+        };
+        let response = admin_request("ping", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = admin_request("ping", Some("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = admin_request("unknown", Some("s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = admin_request("ping", Some("s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "pong");
+
         // test /metricsz
         metrics::increment_counter!("test_counter");
         let response = reqwest::get("http://127.0.0.1:8010/metricsz")
@@ -247,5 +761,16 @@ mod tests {
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
         assert!(response.text().await.unwrap().contains("test_counter"));
+
+        // test /metrics
+        metrics::counter!("toolchain_test_requests_total", 3, "service" => "test");
+        let response = reqwest::get("http://127.0.0.1:8010/metrics").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; version=0.0.4"
+        );
+        let body = response.text().await.unwrap();
+        assert!(body.contains("toolchain_test_requests_total{service=\"test\"} 3"));
     }
 }
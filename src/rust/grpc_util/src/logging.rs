@@ -1,15 +1,34 @@
 // Copyright 2021 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
-use opentelemetry::sdk::trace::Sampler;
-use opentelemetry_otlp::WithExportConfig;
+use opentelemetry::sdk::trace::{Sampler, Tracer};
+use opentelemetry_otlp::{WithExportConfig, OTEL_EXPORTER_OTLP_ENDPOINT};
+use tracing::Subscriber;
 use tracing_subscriber::filter::targets::Targets;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
 
-use crate::infra::InfraConfig;
+use crate::infra::{InfraConfig, LogFormat, TracingConfig};
+
+/// Flushes any spans buffered by the OpenTelemetry exporter when dropped. Binaries should hold
+/// onto this until they exit.
+#[must_use]
+pub struct TracingGuard {
+    opentelemetry_enabled: bool,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if self.opentelemetry_enabled {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
 
 /// Configure logging for a binary.
-pub fn setup_logging(config: Option<&InfraConfig>, service_name: &'static str) {
+pub fn setup_logging(config: Option<&InfraConfig>, service_name: &'static str) -> TracingGuard {
     // Note: This cannot use `EnvFilter` because EnvFilter filters globally even if it only
     // used in a tracing stack!
     let filter_layer = {
@@ -21,58 +40,244 @@ pub fn setup_logging(config: Option<&InfraConfig>, service_name: &'static str) {
             .expect("Failed to parse RUST_LOG")
     };
 
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .json()
-        .with_filter(filter_layer);
+    let log_format = config.map(|c| c.log_format).unwrap_or_default();
+    let fmt_layer = fmt_layer(log_format, std::io::stdout).with_filter(filter_layer);
+
+    let console_requested = std::env::var("TOKIO_CONSOLE_BIND").is_ok();
+    let console_layer_opt = console_requested.then(console_layer).flatten();
+    let console_ignored = console_requested && console_layer_opt.is_none();
+
+    let opentelemetry_layer_opt = config
+        .and_then(|c| c.tracing.as_ref())
+        .map(|tc| opentelemetry_layer(setup_tracing(tc, service_name)));
+
+    let guard = TracingGuard {
+        opentelemetry_enabled: opentelemetry_layer_opt.is_some(),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(console_layer_opt)
+        .with(opentelemetry_layer_opt)
+        .init();
+
+    if console_ignored {
+        log::warn!("Ignoring TOKIO_CONSOLE_BIND: built without the `tokio-console` feature.");
+    }
+
+    guard
+}
 
-    let console_layer_opt = std::env::var("TOKIO_CONSOLE_BIND").ok().map(|_| {
-        // Enable tokio-console debugging with configuration coming from tokio-console's
-        // documented environment variables, i.e. TOKIO_CONSOLE_BIND,
-        // TOKIO_CONSOLE_RETENTION, etc.
+/// A layer which writes log lines for events in the given format to `writer`.
+fn fmt_layer<S, W>(log_format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match log_format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// A layer which serves task states to `tokio-console`. Configuration comes from tokio-console's
+/// documented environment variables, i.e. TOKIO_CONSOLE_BIND, TOKIO_CONSOLE_RETENTION, etc.
+#[cfg(feature = "tokio-console")]
+fn console_layer<S>() -> Option<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    Some(
         console_subscriber::ConsoleLayer::builder()
             .with_default_env()
             .spawn()
+            .boxed(),
+    )
+}
+
+/// Without the `tokio-console` feature, the console layer is not compiled in, and so requesting
+/// it only logs a warning once logging is set up.
+#[cfg(not(feature = "tokio-console"))]
+fn console_layer<S>() -> Option<Box<dyn Layer<S> + Send + Sync>> {
+    None
+}
+
+/// Install an OTLP exporter for spans as the global OpenTelemetry tracer provider, and return a
+/// tracer which exports to it.
+pub fn setup_tracing(config: &TracingConfig, default_service_name: &'static str) -> Tracer {
+    if config.sampling_probability < 0.0 || config.sampling_probability > 1.0 {
+        panic!(
+            "sampling_probability must be in range [0.0, 1.0] but was {}",
+            config.sampling_probability
+        );
+    }
+
+    let endpoint = config
+        .otel_agent
+        .clone()
+        .or_else(|| std::env::var(OTEL_EXPORTER_OTLP_ENDPOINT).ok())
+        .unwrap_or_else(|| {
+            panic!("Tracing requires either otel_agent or {OTEL_EXPORTER_OTLP_ENDPOINT} to be set")
+        });
+    let service_name = config
+        .service_name
+        .clone()
+        .unwrap_or_else(|| default_service_name.to_owned());
+
+    let otlp_exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(otlp_exporter)
+        .with_trace_config(
+            opentelemetry::sdk::trace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    config.sampling_probability,
+                ))))
+                .with_resource(opentelemetry::sdk::Resource::new(vec![
+                    opentelemetry::KeyValue::new("service.name", service_name),
+                ])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)
+        .expect("Failed to set up OpenTelemetry OTLP")
+}
+
+/// A layer which sends spans to OpenTelemetry via `tracer`.
+fn opentelemetry_layer<S>(tracer: Tracer) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    // Only send opted-into spans to OpenTelemetry (Honeycomb) to reduce noise.
+    let filter_layer = tracing_subscriber::filter::FilterFn::new(|metadata| {
+        metadata.fields().field("opentelemetry").is_some()
     });
 
-    let opentelemetry_layer_opt = config.and_then(|c| c.tracing.as_ref()).map(|tc| {
-        if tc.sampling_probability < 0.0 || tc.sampling_probability > 1.0 {
-            panic!(
-                "sampling_probability must be in range [0.0, 1.0] but was {}",
-                tc.sampling_probability
-            );
+    tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(filter_layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::TracerProvider as _;
+    use parking_lot::Mutex;
+    use tracing_subscriber::prelude::*;
+
+    use super::{fmt_layer, opentelemetry_layer};
+    use crate::infra::LogFormat;
+
+    /// Collects written log lines in memory.
+    #[derive(Clone, Default)]
+    struct CollectingWriter {
+        buffer: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl std::io::Write for CollectingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buffer.lock().extend_from_slice(buf);
+            Ok(buf.len())
         }
 
-        let otlp_exporter = opentelemetry_otlp::new_exporter()
-            .tonic()
-            .with_endpoint(&tc.otel_agent);
-        let tracer = opentelemetry_otlp::new_pipeline()
-            .tracing()
-            .with_exporter(otlp_exporter)
-            .with_trace_config(
-                opentelemetry::sdk::trace::config()
-                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
-                        tc.sampling_probability,
-                    ))))
-                    .with_resource(opentelemetry::sdk::Resource::new(vec![
-                        opentelemetry::KeyValue::new("service.name", service_name),
-                    ])),
-            )
-            .install_batch(opentelemetry::runtime::Tokio)
-            .expect("Failed to set up OpenTelemetry OTLP");
-
-        // Only send opted-into spans to OpenTelemetry (Honeycomb) to reduce noise.
-        let filter_layer = tracing_subscriber::filter::FilterFn::new(|metadata| {
-            metadata.fields().field("opentelemetry").is_some()
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CollectingWriter {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn log_sample_event(log_format: LogFormat) -> String {
+        let writer = CollectingWriter::default();
+        let subscriber = tracing_subscriber::registry().with(fmt_layer(log_format, writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("request", request_id = "abc").entered();
+            tracing::warn!(digest = "1234", "blob was missing");
         });
+        let buffer = writer.buffer.lock().clone();
+        String::from_utf8(buffer).unwrap()
+    }
 
-        tracing_opentelemetry::layer()
-            .with_tracer(tracer)
-            .with_filter(filter_layer)
-    });
+    #[test]
+    fn json_log_lines_are_structured() {
+        let output = log_sample_event(LogFormat::Json);
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1, "{output}");
 
-    tracing_subscriber::registry()
-        .with(fmt_layer)
-        .with(console_layer_opt)
-        .with(opentelemetry_layer_opt)
-        .init();
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert!(line["timestamp"].is_string(), "{line}");
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["target"], module_path!());
+        assert_eq!(line["fields"]["message"], "blob was missing");
+        assert_eq!(line["fields"]["digest"], "1234");
+        assert_eq!(line["span"]["name"], "request");
+        assert_eq!(line["span"]["request_id"], "abc");
+    }
+
+    #[test]
+    fn text_log_lines_are_not_json() {
+        let output = log_sample_event(LogFormat::Text);
+        assert!(output.contains("blob was missing"), "{output}");
+        assert!(output.contains("request_id"), "{output}");
+        assert!(serde_json::from_str::<serde_json::Value>(output.trim()).is_err());
+    }
+
+    /// Collects exported spans in memory.
+    #[derive(Clone, Debug, Default)]
+    struct CollectingExporter {
+        spans: Arc<Mutex<Vec<SpanData>>>,
+    }
+
+    #[async_trait]
+    impl SpanExporter for CollectingExporter {
+        async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+            self.spans.lock().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(opentelemetry = true))]
+    fn traced_call() {
+        let _span = tracing::info_span!("not exported").entered();
+    }
+
+    #[test]
+    fn exports_opted_in_spans() {
+        let exporter = CollectingExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber =
+            tracing_subscriber::registry().with(opentelemetry_layer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, traced_call);
+        // The exporter runs on a background thread: shutting down the provider flushes it.
+        drop(provider);
+
+        let span_names = exporter
+            .spans
+            .lock()
+            .iter()
+            .map(|span| span.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(span_names, vec!["traced_call".to_owned()]);
+    }
+
+    #[cfg(feature = "tokio-console")]
+    #[test]
+    fn console_layer_is_built_with_feature() {
+        let layer = super::console_layer::<tracing_subscriber::Registry>();
+        assert!(layer.is_some());
+    }
 }
@@ -1,7 +1,19 @@
 // Copyright 2021 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use awscreds::Credentials;
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Default time for which fetched secrets are cached before being fetched again.
+pub const DEFAULT_SECRET_CACHE_TTL: Duration = Duration::from_secs(300);
 
 #[derive(Default, Deserialize, Serialize)]
 #[serde(rename_all = "UPPERCASE")]
@@ -45,7 +57,6 @@ struct RotatableSecret {
     pub removed: Option<Vec<Secret>>,
 }
 
-#[allow(dead_code)]
 pub fn parse_secret(buffer: impl AsRef<[u8]>) -> Result<String, String> {
     let rotatable_secret: RotatableSecret = serde_json::from_slice(buffer.as_ref())
         .map_err(|err| format!("Failed to parse rotatable secret: {err}"))?;
@@ -57,9 +68,332 @@ pub fn parse_secret(buffer: impl AsRef<[u8]>) -> Result<String, String> {
     }
 }
 
+/// Fetches secrets by name. What a name refers to depends on the provider: e.g. a file path, or
+/// the ARN of a secret in AWS Secrets Manager.
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    async fn get_secret(&self, name: &str) -> Result<String, String>;
+}
+
+/// Reads secrets from files, where the name of a secret is its path.
+pub struct FileSecretProvider;
+
+#[async_trait]
+impl SecretProvider for FileSecretProvider {
+    async fn get_secret(&self, name: &str) -> Result<String, String> {
+        tokio::fs::read_to_string(name)
+            .await
+            .map_err(|err| format!("Failed to read secret from {name}: {err}"))
+    }
+}
+
+/// Fetches secrets from AWS Secrets Manager, where the name of a secret is its ARN (or name).
+///
+/// Credentials are loaded via `aws-creds` (the same as for the S3 auth token mapping source):
+/// from the environment, STS via a web identity token, a profile, or instance metadata. They are
+/// cached until they expire.
+pub struct AwsSecretsManagerProvider {
+    client: reqwest::Client,
+    region: Option<String>,
+    endpoint: Option<String>,
+    credentials: tokio::sync::Mutex<Option<Credentials>>,
+}
+
+impl AwsSecretsManagerProvider {
+    /// If `region` is not set, it is taken from the ARN of each secret, or else from the
+    /// `AWS_REGION` environment variable. If `endpoint` is not set, the regional Secrets Manager
+    /// endpoint is used.
+    pub fn new(region: Option<String>, endpoint: Option<String>) -> Self {
+        AwsSecretsManagerProvider {
+            client: reqwest::Client::new(),
+            region,
+            endpoint,
+            credentials: tokio::sync::Mutex::default(),
+        }
+    }
+
+    fn region_for(&self, name: &str) -> Result<String, String> {
+        if let Some(region) = &self.region {
+            return Ok(region.clone());
+        }
+        // ARNs are of the form `arn:PARTITION:secretsmanager:REGION:ACCOUNT:secret:NAME`.
+        if let Some(region) = name
+            .strip_prefix("arn:")
+            .and_then(|arn| arn.split(':').nth(2))
+        {
+            return Ok(region.to_owned());
+        }
+        std::env::var("AWS_REGION").map_err(|_| {
+            format!("No AWS region configured for secret `{name}`, and `AWS_REGION` is not set")
+        })
+    }
+
+    /// The URL to send requests to and the host which they are signed for.
+    fn url_and_host(&self, region: &str) -> Result<(String, String), String> {
+        let Some(endpoint) = &self.endpoint else {
+            let host = format!("secretsmanager.{region}.amazonaws.com");
+            return Ok((format!("https://{host}/"), host));
+        };
+        let url = reqwest::Url::parse(endpoint)
+            .map_err(|err| format!("Invalid Secrets Manager endpoint {endpoint}: {err}"))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("Secrets Manager endpoint {endpoint} has no host"))?;
+        let host = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_owned(),
+        };
+        Ok((endpoint.clone(), host))
+    }
+
+    /// Load credentials, or refresh the cached credentials if they have expired. Loading may
+    /// make blocking HTTP requests (e.g. to STS), so it runs on the blocking thread pool.
+    async fn credentials(&self) -> Result<Credentials, String> {
+        let mut cached = self.credentials.lock().await;
+        let current = cached.clone();
+        let credentials = tokio::task::spawn_blocking(move || match current {
+            Some(mut credentials) => credentials.refresh().map(|()| credentials),
+            None => Credentials::default(),
+        })
+        .await
+        .map_err(|err| format!("Failed to load AWS credentials: {err}"))?
+        .map_err(|err| format!("Failed to load AWS credentials: {err}"))?;
+        *cached = Some(credentials.clone());
+        Ok(credentials)
+    }
+}
+
+#[async_trait]
+impl SecretProvider for AwsSecretsManagerProvider {
+    async fn get_secret(&self, name: &str) -> Result<String, String> {
+        let region = self.region_for(name)?;
+        let (url, host) = self.url_and_host(&region)?;
+        let credentials = self.credentials().await?;
+        let body = serde_json::to_vec(&serde_json::json!({ "SecretId": name }))
+            .map_err(|err| format!("Failed to encode GetSecretValue request: {err}"))?;
+
+        let headers = sign_request(
+            &credentials,
+            &region,
+            "secretsmanager",
+            &host,
+            &[
+                ("content-type", "application/x-amz-json-1.1"),
+                ("x-amz-target", "secretsmanager.GetSecretValue"),
+            ],
+            &body,
+            chrono::Utc::now(),
+        )?;
+        let mut request = self.client.post(&url).body(body);
+        for (header, value) in headers {
+            request = request.header(header, value);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|err| format!("Failed to fetch secret `{name}`: {err}"))?;
+        let status = response.status();
+        let response_body = response
+            .text()
+            .await
+            .map_err(|err| format!("Failed to fetch secret `{name}`: {err}"))?;
+        if !status.is_success() {
+            return Err(format!(
+                "Failed to fetch secret `{name}`: {status}: {response_body}"
+            ));
+        }
+
+        #[derive(Deserialize)]
+        struct GetSecretValueResponse {
+            #[serde(rename = "SecretString")]
+            secret_string: Option<String>,
+        }
+        serde_json::from_str::<GetSecretValueResponse>(&response_body)
+            .map_err(|err| format!("Failed to parse secret `{name}`: {err}"))?
+            .secret_string
+            .ok_or_else(|| format!("Secret `{name}` has no string value"))
+    }
+}
+
+/// Sign a `POST /` request with AWS Signature Version 4, returning the headers to send, which
+/// include the given `headers`. Header names must be lowercase. (The signer of `rust-s3` only
+/// signs requests for S3.)
+fn sign_request(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    host: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<(String, String)>, String> {
+    let (Some(access_key), Some(secret_key)) = (&credentials.access_key, &credentials.secret_key)
+    else {
+        return Err("AWS credentials have no access key".to_owned());
+    };
+    let session_token = credentials
+        .session_token
+        .as_ref()
+        .or(credentials.security_token.as_ref());
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let mut signed_headers: Vec<(String, String)> = headers
+        .iter()
+        .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+        .collect();
+    signed_headers.push(("host".to_owned(), host.to_owned()));
+    signed_headers.push(("x-amz-date".to_owned(), amz_date.clone()));
+    if let Some(session_token) = session_token {
+        signed_headers.push(("x-amz-security-token".to_owned(), session_token.clone()));
+    }
+    signed_headers.sort();
+
+    let canonical_headers: String = signed_headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_header_names = signed_headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{canonical_headers}\n{signed_header_names}\n{}",
+        hex::encode(Sha256::digest(body))
+    );
+
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signing_key = [region, service, "aws4_request"].iter().fold(
+        hmac_sha256(format!("AWS4{secret_key}").as_bytes(), date.as_bytes()),
+        |key, part| hmac_sha256(&key, part.as_bytes()),
+    );
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    signed_headers.push((
+        "authorization".to_owned(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_header_names}, Signature={signature}"
+        ),
+    ));
+    Ok(signed_headers)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Caches the secrets fetched from another provider. Cached secrets are fetched again once they
+/// are older than the TTL, so that rotated secrets are picked up. If fetching fails, the stale
+/// value continues to be used.
+pub struct CachingSecretProvider {
+    inner: Arc<dyn SecretProvider>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl CachingSecretProvider {
+    pub fn new(inner: Arc<dyn SecretProvider>, ttl: Duration) -> Self {
+        CachingSecretProvider {
+            inner,
+            ttl,
+            cache: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl SecretProvider for CachingSecretProvider {
+    async fn get_secret(&self, name: &str) -> Result<String, String> {
+        let cached = self.cache.lock().get(name).cloned();
+        if let Some((value, fetched_at)) = &cached {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(value.clone());
+            }
+        }
+
+        match self.inner.get_secret(name).await {
+            Ok(value) => {
+                self.cache
+                    .lock()
+                    .insert(name.to_owned(), (value.clone(), Instant::now()));
+                Ok(value)
+            }
+            Err(err) => match cached {
+                Some((value, _)) => {
+                    log::warn!("Failed to refresh secret `{name}`, using cached value: {err}");
+                    Ok(value)
+                }
+                None => Err(err),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretProviderKind {
+    /// Secrets are named by file paths.
+    #[default]
+    File,
+    /// Secrets are named by AWS Secrets Manager ARNs.
+    AwsSecretsManager,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct SecretsConfig {
+    /// Where secrets are loaded from. Defaults to files.
+    #[serde(default)]
+    pub provider: SecretProviderKind,
+
+    /// AWS region of Secrets Manager. Defaults to the region of each secret's ARN.
+    pub region: Option<String>,
+
+    /// Secrets Manager endpoint URL, e.g. for testing. Defaults to the regional endpoint.
+    pub endpoint: Option<String>,
+
+    /// Seconds for which fetched secrets are cached before being fetched again. Defaults to 300.
+    pub cache_ttl_secs: Option<u64>,
+}
+
+impl SecretsConfig {
+    pub fn provider(&self) -> Arc<dyn SecretProvider> {
+        let inner: Arc<dyn SecretProvider> = match self.provider {
+            SecretProviderKind::File => Arc::new(FileSecretProvider),
+            SecretProviderKind::AwsSecretsManager => Arc::new(AwsSecretsManagerProvider::new(
+                self.region.clone(),
+                self.endpoint.clone(),
+            )),
+        };
+        let ttl = self
+            .cache_ttl_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SECRET_CACHE_TTL);
+        Arc::new(CachingSecretProvider::new(inner, ttl))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::parse_secret;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+
+    use awscreds::Credentials;
+
+    use super::{
+        parse_secret, sign_request, AwsSecretsManagerProvider, CachingSecretProvider,
+        FileSecretProvider, SecretProvider, SecretsConfig,
+    };
 
     #[test]
     fn decodes_current_secret() {
@@ -119,4 +453,135 @@ mod tests {
         let err = parse_secret(data).unwrap_err();
         assert!(err.contains("No current secret found"));
     }
+
+    #[tokio::test]
+    async fn reads_secret_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.json");
+        std::fs::write(&path, "{\"api_key\": \"xyzzy\"}").unwrap();
+
+        let secret = FileSecretProvider
+            .get_secret(path.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(secret, "{\"api_key\": \"xyzzy\"}");
+
+        let err = FileSecretProvider
+            .get_secret(dir.path().join("missing").to_str().unwrap())
+            .await
+            .unwrap_err();
+        assert!(err.contains("Failed to read secret"));
+    }
+
+    /// Counts fetches, and fails once `fail` is set.
+    #[derive(Default)]
+    struct CountingSecretProvider {
+        fetches: AtomicUsize,
+        fail: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl SecretProvider for CountingSecretProvider {
+        async fn get_secret(&self, name: &str) -> Result<String, String> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err("unavailable".to_owned());
+            }
+            let fetch = self.fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(format!("{name}-{fetch}"))
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_trait_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        std::fs::write(&path, "value").unwrap();
+
+        // The configured provider is a caching provider wrapping the file provider.
+        let provider: Arc<dyn SecretProvider> = SecretsConfig::default().provider();
+        let path = path.to_str().unwrap();
+        assert_eq!(provider.get_secret(path).await.unwrap(), "value");
+
+        // Cached values are returned until the TTL expires, and then refreshed.
+        let inner = Arc::new(CountingSecretProvider::default());
+        let caching = CachingSecretProvider::new(inner.clone(), Duration::from_millis(100));
+        assert_eq!(caching.get_secret("a").await.unwrap(), "a-0");
+        assert_eq!(caching.get_secret("a").await.unwrap(), "a-0");
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(caching.get_secret("a").await.unwrap(), "a-1");
+
+        // Stale values are used if a refresh fails.
+        inner.fail.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(caching.get_secret("a").await.unwrap(), "a-1");
+        assert!(caching.get_secret("b").await.is_err());
+    }
+
+    #[test]
+    fn signs_requests() {
+        let credentials = Credentials::new(
+            Some("AKIDEXAMPLE"),
+            Some("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let now = chrono::DateTime::parse_from_rfc3339("2015-08-30T12:36:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let headers = sign_request(
+            &credentials,
+            "us-east-1",
+            "secretsmanager",
+            "secretsmanager.us-east-1.amazonaws.com",
+            &[("x-amz-target", "secretsmanager.GetSecretValue")],
+            b"{}",
+            now,
+        )
+        .unwrap();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        assert_eq!(header("x-amz-date"), "20150830T123600Z");
+        assert_eq!(
+            header("authorization"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/secretsmanager/aws4_request, \
+             SignedHeaders=host;x-amz-date;x-amz-target, \
+             Signature=6e7a23dfac155ad7545bfb6853ef6a6d29f9846804a78d61bae79d5f10f48ca0"
+        );
+    }
+
+    #[test]
+    fn signs_requests_for_the_endpoint_host() {
+        let provider = AwsSecretsManagerProvider::new(None, None);
+        assert_eq!(
+            provider.url_and_host("us-west-2").unwrap(),
+            (
+                "https://secretsmanager.us-west-2.amazonaws.com/".to_owned(),
+                "secretsmanager.us-west-2.amazonaws.com".to_owned()
+            )
+        );
+
+        let provider =
+            AwsSecretsManagerProvider::new(None, Some("http://localhost:4566/".to_owned()));
+        assert_eq!(
+            provider.url_and_host("us-west-2").unwrap(),
+            (
+                "http://localhost:4566/".to_owned(),
+                "localhost:4566".to_owned()
+            )
+        );
+
+        let provider =
+            AwsSecretsManagerProvider::new(None, Some("https://secrets.example.com".to_owned()));
+        assert_eq!(
+            provider.url_and_host("us-west-2").unwrap().1,
+            "secrets.example.com"
+        );
+    }
 }
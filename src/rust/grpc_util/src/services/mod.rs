@@ -6,3 +6,6 @@ pub use grpc_metrics::{convert_status_code, GrpcMetrics};
 
 mod http_metrics;
 pub use http_metrics::HttpMetrics;
+
+mod timeout;
+pub use timeout::grpc_timeout;
@@ -5,6 +5,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut config = prost_build::Config::new();
     config.bytes(["."]);
     config.disable_comments(["."]);
+    // The vendored Remote Execution API predates `CacheCapabilities.supported_compressors`, so
+    // use the wire compatible toolchain copy of `CacheCapabilities` which includes it.
+    config.extern_path(
+        ".build.bazel.remote.execution.v2.CacheCapabilities",
+        "crate::toolchain::remote::execution::v2::CacheCapabilities",
+    );
 
     tonic_build::configure()
     .build_client(true)
@@ -23,11 +29,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "protos/googleapis/google/rpc/status.proto",
         "protos/googleapis/google/longrunning/operations.proto",
         "protos/standard/google/protobuf/empty.proto",
+        "protos/toolchain/toolchain/remote/execution/v2/cache_capabilities.proto",
+        "protos/toolchain/toolchain/storage/admin/v1/admin.proto",
       ],
       &[
         "protos/bazelbuild_remote-apis",
         "protos/googleapis",
         "protos/standard",
+        "protos/toolchain",
       ],
     )?;
 
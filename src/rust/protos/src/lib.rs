@@ -40,6 +40,11 @@ pub mod build {
                         env!("OUT_DIR"),
                         "/build.bazel.remote.execution.v2.rs"
                     ));
+
+                    // Messages which are newer than the vendored Remote Execution API.
+                    pub use crate::toolchain::remote::execution::v2::{
+                        compressor, CacheCapabilities, Compressor,
+                    };
                 }
             }
         }
@@ -49,5 +54,25 @@ pub mod build {
     }
 }
 
+pub mod toolchain {
+    pub mod remote {
+        pub mod execution {
+            pub mod v2 {
+                include!(concat!(
+                    env!("OUT_DIR"),
+                    "/toolchain.remote.execution.v2.rs"
+                ));
+            }
+        }
+    }
+    pub mod storage {
+        pub mod admin {
+            pub mod v1 {
+                include!(concat!(env!("OUT_DIR"), "/toolchain.storage.admin.v1.rs"));
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -5,5 +5,7 @@
 
 mod server;
 pub use server::{
-    BackendTimeoutsConfig, InstanceConfig, InstanceName, ListenAddressConfig, ProxyServer,
+    configure_backend_error_logging, BackendErrorLoggingConfig, BackendTimeoutsConfig,
+    FindMissingBlobsCacheConfig, InstanceBackendRule, InstanceConfig, InstanceLimitsConfig,
+    InstanceName, ListenAddressConfig, ListenerTlsConfig, ProxyServer, ProxyServerConfig,
 };
@@ -4,15 +4,17 @@
 use std::sync::Arc;
 
 use ginepro::LoadBalancedChannel;
-use grpc_util::auth::{AuthScheme, Permissions};
+use grpc_util::auth::AuthScheme;
 use protos::build::bazel::remote::execution::v2::{
     action_cache_client::ActionCacheClient, action_cache_server::ActionCache, ActionResult,
     GetActionResultRequest, UpdateActionResultRequest,
 };
-use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
-use crate::server::{client_call, ProxyServerInner};
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, BackendDeadline, ClientCredentials, InstancePermit, ProxyServerInner,
+};
 
 pub(crate) struct ActionCacheService {
     inner: Arc<ProxyServerInner>,
@@ -28,21 +30,29 @@ impl ActionCacheService {
 
     fn get_client(
         &self,
-        metadata: &MetadataMap,
+        credentials: &ClientCredentials<'_>,
         requested_instance_name: &str,
-        required_permissions: Permissions,
-    ) -> Result<ActionCacheClient<LoadBalancedChannel>, Status> {
+        method_name: &str,
+    ) -> Result<(ActionCacheClient<LoadBalancedChannel>, InstancePermit), Status> {
+        let required_permissions = required_permissions(Self::SERVICE_NAME, method_name);
         self.inner.check_authorized(
             self.auth_scheme,
-            metadata,
+            credentials,
             requested_instance_name,
             required_permissions,
         )?;
-        Ok(self
+        self.inner
+            .check_writable(requested_instance_name, required_permissions)?;
+        let permit = self
             .inner
-            .backend(requested_instance_name)
-            .action_cache
-            .clone())
+            .acquire_instance_permit(requested_instance_name)?;
+        Ok((
+            self.inner
+                .backend(requested_instance_name)
+                .action_cache
+                .clone(),
+            permit,
+        ))
     }
 }
 
@@ -53,16 +63,22 @@ impl ActionCache for ActionCacheService {
         &self,
         request: Request<GetActionResultRequest>,
     ) -> Result<Response<ActionResult>, Status> {
-        let client = self.get_client(
-            request.metadata(),
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().instance_name,
-            Permissions::Read,
+            "GetActionResult",
         )?;
-        let request = request.into_inner();
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self
+            .inner
+            .backend_instance_name(&request.instance_name)
+            .to_owned();
         client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move {
                     let response_fut = client.get_action_result(request);
                     match self.inner.timeouts.get_action_result {
@@ -94,16 +110,22 @@ impl ActionCache for ActionCacheService {
         &self,
         request: Request<UpdateActionResultRequest>,
     ) -> Result<Response<ActionResult>, Status> {
-        let client = self.get_client(
-            request.metadata(),
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().instance_name,
-            Permissions::ReadWrite,
+            "UpdateActionResult",
         )?;
-        let request = request.into_inner();
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self
+            .inner
+            .backend_instance_name(&request.instance_name)
+            .to_owned();
         client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.update_action_result(request).await }
             },
             Self::SERVICE_NAME,
@@ -8,11 +8,13 @@ use protos::google::devtools::remoteworkers::v1test2::{
     bots_client::BotsClient, bots_server::Bots, BotSession, CreateBotSessionRequest,
     UpdateBotSessionRequest,
 };
-use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
-use crate::server::{client_call, ProxyServerInner};
-use grpc_util::auth::{AuthScheme, Permissions};
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, BackendDeadline, ClientCredentials, InstancePermit, ProxyServerInner,
+};
+use grpc_util::auth::AuthScheme;
 
 pub(crate) struct BotsService {
     inner: Arc<ProxyServerInner>,
@@ -40,20 +42,36 @@ impl BotsService {
             .ok_or_else(|| format!("unable to parse instance name from `{session_name}`"))
     }
 
+    /// Rewrite the session name returned by the backend so that it refers to the instance name
+    /// used by the client (which may be an alias).
+    fn to_external_bot_session(
+        &self,
+        external_instance_name: &str,
+        response: Response<BotSession>,
+    ) -> Response<BotSession> {
+        response.map(|mut bot_session| {
+            bot_session.name = self
+                .inner
+                .to_external_name(external_instance_name, bot_session.name);
+            bot_session
+        })
+    }
+
     fn get_client(
         &self,
-        metadata: &MetadataMap,
+        credentials: &ClientCredentials<'_>,
         requested_instance_name: &str,
-    ) -> Result<BotsClient<LoadBalancedChannel>, Status> {
+        method_name: &str,
+    ) -> Result<(BotsClient<LoadBalancedChannel>, InstancePermit), Status> {
         self.inner.check_authorized(
             self.auth_scheme,
-            metadata,
+            credentials,
             requested_instance_name,
-            // TODO: consider adding a Worker permission. Right now, the auth_token scheme does not
-            // have any notion of permissions/entitlements because it was seen as unnecessary. So,
-            // this value gets ignored.
-            Permissions::Execute,
+            required_permissions(Self::SERVICE_NAME, method_name),
         )?;
+        let permit = self
+            .inner
+            .acquire_instance_permit(requested_instance_name)?;
 
         self.inner
             .backend(requested_instance_name)
@@ -63,6 +81,7 @@ impl BotsService {
             .ok_or_else(|| {
                 Status::invalid_argument(format!("No such instance: {requested_instance_name}"))
             })
+            .map(|client| (client, permit))
     }
 }
 
@@ -70,63 +89,74 @@ impl BotsService {
 /// implementation of long-polling, by introspecting the deadline and ensuring that they return to
 /// the client before the deadline is reached. Because we internally retry requests in this proxy,
 /// we cannot pass the `tonic::Request` object through directly (because it is not `Clone`), and
-/// instead create a new `Request` per retry attempt. While doing so, we need to preserve the
-/// deadline, which would otherwise be lost. In future versions of `tonic` (0.8.3>=), we can use
-/// `tonic::Request::{into_parts,from_parts}` instead.
+/// instead create a new `Request` per retry attempt. `BackendDeadline` preserves the deadline on
+/// each of those requests, which would otherwise be lost.
 #[tonic::async_trait]
 impl Bots for BotsService {
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn create_bot_session(
         &self,
-        mut request: Request<CreateBotSessionRequest>,
+        request: Request<CreateBotSessionRequest>,
     ) -> Result<Response<BotSession>, Status> {
         // See the note regarding deadlines on the trait implementation.
-        let deadline = request.metadata_mut().remove("grpc-timeout");
-        let client = self.get_client(request.metadata(), &request.get_ref().parent)?;
-        let request = request.into_inner();
-        client_call(
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &request.get_ref().parent,
+            "CreateBotSession",
+        )?;
+        let mut request = request.into_inner();
+        let instance_name = std::mem::take(&mut request.parent);
+        request.parent = self.inner.backend_instance_name(&instance_name).to_owned();
+        if let Some(bot_session) = request.bot_session.as_mut() {
+            bot_session.name = self.inner.to_backend_name(&bot_session.name);
+        }
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let mut request = Request::new(request.clone());
-                if let Some(deadline) = deadline.as_ref() {
-                    request
-                        .metadata_mut()
-                        .insert("grpc-timeout", deadline.clone());
-                }
+                let request = deadline.request(request.clone());
                 async move { client.create_bot_session(request).await }
             },
             Self::SERVICE_NAME,
             "CreateBotSession",
         )
-        .await
+        .await?;
+        Ok(self.to_external_bot_session(&instance_name, response))
     }
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn update_bot_session(
         &self,
-        mut request: Request<UpdateBotSessionRequest>,
+        request: Request<UpdateBotSessionRequest>,
     ) -> Result<Response<BotSession>, Status> {
         // See the note regarding deadlines on the trait implementation.
-        let deadline = request.metadata_mut().remove("grpc-timeout");
+        let deadline = BackendDeadline::from_metadata(request.metadata());
         let requested_instance_name = Self::instance_name_from_session_name(request.get_ref())
-            .map_err(Status::invalid_argument)?;
+            .map_err(Status::invalid_argument)?
+            .to_owned();
 
-        let client = self.get_client(request.metadata(), requested_instance_name)?;
-        let request = request.into_inner();
-        client_call(
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &requested_instance_name,
+            "UpdateBotSession",
+        )?;
+        let mut request = request.into_inner();
+        request.name = self.inner.to_backend_name(&request.name);
+        if let Some(bot_session) = request.bot_session.as_mut() {
+            bot_session.name = self.inner.to_backend_name(&bot_session.name);
+        }
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let mut request = Request::new(request.clone());
-                if let Some(deadline) = deadline.as_ref() {
-                    request
-                        .metadata_mut()
-                        .insert("grpc-timeout", deadline.clone());
-                }
+                let request = deadline.request(request.clone());
                 async move { client.update_bot_session(request).await }
             },
             Self::SERVICE_NAME,
             "UpdateBotSession",
         )
-        .await
+        .await?;
+        Ok(self.to_external_bot_session(&requested_instance_name, response))
     }
 }
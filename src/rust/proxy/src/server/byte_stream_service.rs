@@ -4,18 +4,23 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use futures::stream::BoxStream;
 use futures::StreamExt;
 use ginepro::LoadBalancedChannel;
-use grpc_util::auth::{AuthScheme, Permissions};
+use grpc_util::auth::AuthScheme;
 use protos::google::bytestream::{
     byte_stream_client::ByteStreamClient, byte_stream_server::ByteStream, QueryWriteStatusRequest,
     QueryWriteStatusResponse, ReadRequest, ReadResponse, WriteRequest, WriteResponse,
 };
 use tokio::sync::Mutex;
-use tonic::metadata::MetadataMap;
-use tonic::{Request, Response, Status, Streaming};
+use tonic::{Extensions, Request, Response, Status, Streaming};
 
-use crate::server::{client_call, ProxyServerInner};
+use crate::server::find_missing_blobs_cache::parse_write_resource_digest;
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, client_call_with_failover, BackendDeadline, ClientCredentials, InstancePermit,
+    ProxyServerInner,
+};
 
 pub(crate) struct ByteStreamService {
     inner: Arc<ProxyServerInner>,
@@ -31,41 +36,76 @@ impl ByteStreamService {
 
     fn get_client(
         &self,
-        metadata: &MetadataMap,
+        credentials: &ClientCredentials<'_>,
         resource_name: &str,
-        required_permissions: Permissions,
-    ) -> Result<ByteStreamClient<LoadBalancedChannel>, Status> {
+        method_name: &str,
+    ) -> Result<(ByteStreamClient<LoadBalancedChannel>, InstancePermit), Status> {
         let parts = resource_name.split('/').collect::<Vec<_>>();
         let instance_name = match parts.first() {
             Some(&n) => n,
             None => return Err(Status::invalid_argument("unable to parse instance name")),
         };
 
+        let required_permissions = required_permissions(Self::SERVICE_NAME, method_name);
         self.inner.check_authorized(
             self.auth_scheme,
-            metadata,
+            credentials,
             instance_name,
             required_permissions,
         )?;
-        Ok(self.inner.backend(instance_name).bytestream.clone())
+        self.inner
+            .check_writable(instance_name, required_permissions)?;
+        let permit = self.inner.acquire_instance_permit(instance_name)?;
+        Ok((self.inner.backend(instance_name).bytestream.clone(), permit))
     }
 }
 
 #[tonic::async_trait]
 impl ByteStream for ByteStreamService {
-    type ReadStream = tonic::codec::Streaming<ReadResponse>;
+    type ReadStream = BoxStream<'static, Result<ReadResponse, Status>>;
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn read(
         &self,
-        request: Request<ReadRequest>,
+        mut request: Request<ReadRequest>,
     ) -> Result<Response<Self::ReadStream>, Status> {
-        let mut client = self.get_client(
-            request.metadata(),
+        let (client, permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().resource_name,
-            Permissions::Read,
+            "Read",
         )?;
-        client.read(request).await
+        let instance_name = request
+            .get_ref()
+            .resource_name
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let fallbacks = self
+            .inner
+            .backend(instance_name)
+            .fallbacks
+            .iter()
+            .map(|fallback| (fallback.name.clone(), fallback.bytestream.clone()))
+            .collect::<Vec<_>>();
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let resource_name = self.inner.to_backend_name(&request.get_ref().resource_name);
+        request.get_mut().resource_name = resource_name;
+        let (metadata, _, message) = request.into_parts();
+        let response = client_call_with_failover(
+            client,
+            fallbacks,
+            deadline,
+            move |mut client| {
+                let mut request =
+                    Request::from_parts(metadata.clone(), Extensions::default(), message.clone());
+                deadline.set_timeout(&mut request);
+                async move { client.read(request).await }
+            },
+            Self::SERVICE_NAME,
+            "Read",
+        )
+        .await?;
+        Ok(permit.hold_until_streamed(response))
     }
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
@@ -76,31 +116,41 @@ impl ByteStream for ByteStreamService {
         // Retrieve the first message from the stream to identify the requested backend instance
         // from the resource name.
         let outer_req_metadata = request.metadata().clone();
+        let credentials = ClientCredentials::new(&outer_req_metadata, request.peer_certs());
         let stream = Arc::new(Mutex::new(request.into_inner()));
-        let first_msg = stream
+        let mut first_msg = stream
             .lock()
             .await
             .next()
             .await
             .unwrap_or_else(|| Err(Status::aborted("connection closed")))?;
-        let client = self.get_client(
-            &outer_req_metadata,
-            &first_msg.resource_name,
-            Permissions::ReadWrite,
-        )?;
+        let (client, _permit) = self.get_client(&credentials, &first_msg.resource_name, "Write")?;
+        first_msg.resource_name = self.inner.to_backend_name(&first_msg.resource_name);
+        if let Some(cache) = &self.inner.find_missing_blobs_cache {
+            if let Some((instance_name, digest)) =
+                parse_write_resource_digest(&first_msg.resource_name)
+            {
+                cache.invalidate(instance_name, [&digest]);
+            }
+        }
+        let inner = self.inner.clone();
 
         // A place for the closure to store whether it had taken any elements off the stream
         // from the ultimate client. If it does, then it cannot retry as the proxy has no
         // way to replay the stream.
         let already_saw_messages = Arc::new(AtomicBool::new(false));
 
+        let deadline = BackendDeadline::from_metadata(&outer_req_metadata);
+
         // Create a future to receive the final result from the backend.
         client_call(
             client,
+            deadline,
             move |mut client| {
                 let first_msg = first_msg.clone();
                 let stream = stream.clone();
                 let already_saw_messages = already_saw_messages.clone();
+                let inner = inner.clone();
                 async move {
                     if already_saw_messages.load(Ordering::SeqCst) {
                         return Err(Status::aborted(
@@ -112,7 +162,7 @@ impl ByteStream for ByteStreamService {
                         Arc::new(Mutex::new(None));
 
                     let response = client
-                        .write(Request::new({
+                        .write(deadline.request({
                             let write_error = write_error.clone();
                             async_stream::stream! {
                               // Send the first message that was already retrieved to the backend.
@@ -123,7 +173,14 @@ impl ByteStream for ByteStreamService {
                               while let Some(write_request_res) = stream.lock().await.next().await {
                                   already_saw_messages.store(true, Ordering::SeqCst);
                                   match write_request_res {
-                                    Ok(write_request) => yield write_request,
+                                    Ok(mut write_request) => {
+                                        // Only the first message is required to carry the
+                                        // resource name, but rewrite any later ones as well.
+                                        if !write_request.resource_name.is_empty() {
+                                            write_request.resource_name = inner.to_backend_name(&write_request.resource_name);
+                                        }
+                                        yield write_request
+                                    }
                                     Err(e) => {
                                         *write_error.lock().await = Some(Err(e));
                                         break;
@@ -149,16 +206,19 @@ impl ByteStream for ByteStreamService {
         &self,
         request: Request<QueryWriteStatusRequest>,
     ) -> Result<Response<QueryWriteStatusResponse>, Status> {
-        let client = self.get_client(
-            request.metadata(),
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().resource_name,
-            Permissions::ReadWrite,
+            "QueryWriteStatus",
         )?;
-        let request = request.into_inner();
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.resource_name = self.inner.to_backend_name(&request.resource_name);
         client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.query_write_status(request).await }
             },
             Self::SERVICE_NAME,
@@ -1,15 +1,112 @@
 // Copyright 2020 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use grpc_util::auth::{AuthScheme, Permissions};
+use grpc_util::auth::AuthScheme;
+use parking_lot::Mutex;
 use protos::build::bazel::remote::execution::v2::{
-    capabilities_server::Capabilities, GetCapabilitiesRequest, ServerCapabilities,
+    capabilities_server::Capabilities, compressor, digest_function, GetCapabilitiesRequest,
+    ServerCapabilities,
 };
 use tonic::{Request, Response, Status};
 
-use crate::server::{client_call, ProxyServerInner};
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, BackendDeadline, ClientCredentials, InstanceName, ProxyServerInner,
+};
+
+/// Caches the last successful `ServerCapabilities` returned by a backend, keyed by the instance
+/// name sent to that backend.
+#[derive(Default)]
+pub(crate) struct CapabilitiesCache {
+    entries: Mutex<HashMap<InstanceName, (Instant, ServerCapabilities)>>,
+}
+
+impl CapabilitiesCache {
+    fn get(&self, instance_name: &str, ttl: Duration) -> Option<ServerCapabilities> {
+        let entries = self.entries.lock();
+        let (fetched_at, capabilities) = entries.get(instance_name)?;
+        if fetched_at.elapsed() < ttl {
+            Some(capabilities.clone())
+        } else {
+            None
+        }
+    }
+
+    fn insert(&self, instance_name: InstanceName, capabilities: ServerCapabilities) {
+        self.entries
+            .lock()
+            .insert(instance_name, (Instant::now(), capabilities));
+    }
+
+    fn invalidate(&self, instance_name: &str) {
+        self.entries.lock().remove(instance_name);
+    }
+}
+
+/// Restricts the digest functions and compressors which a backend's `ServerCapabilities` advertise
+/// to clients. A `None` allow-list passes the backend's values through unchanged.
+#[derive(Debug, Default)]
+pub(crate) struct CapabilitiesFilter {
+    digest_functions: Option<HashSet<i32>>,
+    compressors: Option<HashSet<i32>>,
+}
+
+impl CapabilitiesFilter {
+    pub(crate) fn new(
+        digest_functions: Option<&[String]>,
+        compressors: Option<&[String]>,
+    ) -> Result<Self, String> {
+        let digest_functions = digest_functions
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| {
+                        digest_function::Value::from_str_name(name)
+                            .map(|value| value as i32)
+                            .ok_or_else(|| format!("Unknown digest function: {name}"))
+                    })
+                    .collect::<Result<HashSet<_>, _>>()
+            })
+            .transpose()?;
+        let compressors = compressors
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| {
+                        compressor::Value::from_str_name(name)
+                            .map(|value| value as i32)
+                            .ok_or_else(|| format!("Unknown compressor: {name}"))
+                    })
+                    .collect::<Result<HashSet<_>, _>>()
+            })
+            .transpose()?;
+        Ok(CapabilitiesFilter {
+            digest_functions,
+            compressors,
+        })
+    }
+
+    /// Remove any digest functions and compressors which are not in the allow-lists.
+    fn apply(&self, mut capabilities: ServerCapabilities) -> ServerCapabilities {
+        if let Some(cache_capabilities) = capabilities.cache_capabilities.as_mut() {
+            if let Some(allowed) = &self.digest_functions {
+                cache_capabilities
+                    .digest_function
+                    .retain(|value| allowed.contains(value));
+            }
+            if let Some(allowed) = &self.compressors {
+                cache_capabilities
+                    .supported_compressors
+                    .retain(|value| allowed.contains(value));
+            }
+        }
+        capabilities
+    }
+}
 
 pub(crate) struct CapabilitiesService {
     inner: Arc<ProxyServerInner>,
@@ -34,27 +131,59 @@ impl Capabilities for CapabilitiesService {
         let requested_instance_name = &request.get_ref().instance_name;
         self.inner.check_authorized(
             self.auth_scheme,
-            request.metadata(),
+            &ClientCredentials::from_request(&request),
             requested_instance_name,
-            Permissions::Read,
+            required_permissions(Self::SERVICE_NAME, "GetCapabilities"),
         )?;
+        let _permit = self
+            .inner
+            .acquire_instance_permit(requested_instance_name)?;
 
         // TODO: Merge in execution capabilities call as well if configured.
-        let client = self
+        let backend = self.inner.backend(requested_instance_name);
+        let ttl = self.inner.capabilities_cache_ttl;
+        let backend_instance_name = self
             .inner
-            .backend(requested_instance_name)
-            .cas_capabilities
-            .clone();
-        let request = request.into_inner();
-        client_call(
+            .backend_instance_name(requested_instance_name)
+            .to_owned();
+        if !ttl.is_zero() {
+            if let Some(capabilities) = backend.capabilities_cache.get(&backend_instance_name, ttl)
+            {
+                metrics::counter!("toolchain_proxy_capabilities_cache_hit_total", 1);
+                return Ok(Response::new(
+                    backend.capabilities_filter.apply(capabilities),
+                ));
+            }
+            metrics::counter!("toolchain_proxy_capabilities_cache_miss_total", 1);
+        }
+
+        let client = backend.cas_capabilities.clone();
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = backend_instance_name.clone();
+        let result = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.get_capabilities(request).await }
             },
             Self::SERVICE_NAME,
             "GetCapabilities",
         )
-        .await
+        .await;
+
+        if !ttl.is_zero() {
+            match &result {
+                Ok(response) => backend
+                    .capabilities_cache
+                    .insert(backend_instance_name, response.get_ref().clone()),
+                Err(_) => backend
+                    .capabilities_cache
+                    .invalidate(&backend_instance_name),
+            }
+        }
+        result
+            .map(|response| Response::new(backend.capabilities_filter.apply(response.into_inner())))
     }
 }
@@ -3,18 +3,22 @@
 
 use std::sync::Arc;
 
+use futures::stream::BoxStream;
 use ginepro::LoadBalancedChannel;
-use grpc_util::auth::{AuthScheme, Permissions};
+use grpc_util::auth::AuthScheme;
 use protos::build::bazel::remote::execution::v2::{
     content_addressable_storage_client::ContentAddressableStorageClient,
     content_addressable_storage_server::ContentAddressableStorage, BatchReadBlobsRequest,
     BatchReadBlobsResponse, BatchUpdateBlobsRequest, BatchUpdateBlobsResponse,
     FindMissingBlobsRequest, FindMissingBlobsResponse, GetTreeRequest, GetTreeResponse,
 };
-use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
-use crate::server::{client_call, ProxyServerInner};
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, client_call_with_failover, BackendDeadline, ClientCredentials, InstancePermit,
+    ProxyServerInner,
+};
 
 pub(crate) struct CasService {
     inner: Arc<ProxyServerInner>,
@@ -31,17 +35,32 @@ impl CasService {
 
     fn get_client(
         &self,
-        metadata: &MetadataMap,
+        credentials: &ClientCredentials<'_>,
         requested_instance_name: &str,
-        required_permissions: Permissions,
-    ) -> Result<ContentAddressableStorageClient<LoadBalancedChannel>, Status> {
+        method_name: &str,
+    ) -> Result<
+        (
+            ContentAddressableStorageClient<LoadBalancedChannel>,
+            InstancePermit,
+        ),
+        Status,
+    > {
+        let required_permissions = required_permissions(Self::SERVICE_NAME, method_name);
         self.inner.check_authorized(
             self.auth_scheme,
-            metadata,
+            credentials,
             requested_instance_name,
             required_permissions,
         )?;
-        Ok(self.inner.backend(requested_instance_name).cas.clone())
+        self.inner
+            .check_writable(requested_instance_name, required_permissions)?;
+        let permit = self
+            .inner
+            .acquire_instance_permit(requested_instance_name)?;
+        Ok((
+            self.inner.backend(requested_instance_name).cas.clone(),
+            permit,
+        ))
     }
 }
 
@@ -52,22 +71,61 @@ impl ContentAddressableStorage for CasService {
         &self,
         request: Request<FindMissingBlobsRequest>,
     ) -> Result<Response<FindMissingBlobsResponse>, Status> {
-        let client = self.get_client(
-            request.metadata(),
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().instance_name,
-            Permissions::Read,
+            "FindMissingBlobs",
         )?;
-        let request = request.into_inner();
-        client_call(
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self
+            .inner
+            .backend_instance_name(&request.instance_name)
+            .to_owned();
+
+        // Only ask the backend about digests which were not recently confirmed present: those
+        // are left out of the backend's list of missing digests, and so out of the response.
+        let cache = self.inner.find_missing_blobs_cache.as_ref();
+        let cache_lookup = cache.map(|cache| {
+            let (unknown, hits) = cache.filter_present(
+                &request.instance_name,
+                std::mem::take(&mut request.blob_digests),
+            );
+            metrics::counter!(
+                "toolchain_proxy_find_missing_blobs_cache_hit_total",
+                hits as u64
+            );
+            metrics::counter!(
+                "toolchain_proxy_find_missing_blobs_cache_miss_total",
+                unknown.len() as u64
+            );
+            request.blob_digests = unknown.clone();
+            (cache, request.instance_name.clone(), unknown)
+        });
+        if cache_lookup.is_some() && request.blob_digests.is_empty() {
+            return Ok(Response::new(FindMissingBlobsResponse::default()));
+        }
+
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.find_missing_blobs(request).await }
             },
             Self::SERVICE_NAME,
             "FindMissingBlobs",
         )
-        .await
+        .await?;
+
+        if let Some((cache, instance_name, requested)) = cache_lookup {
+            cache.insert_present(
+                &instance_name,
+                &requested,
+                &response.get_ref().missing_blob_digests,
+            );
+        }
+        Ok(response)
     }
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
@@ -75,16 +133,28 @@ impl ContentAddressableStorage for CasService {
         &self,
         request: Request<BatchUpdateBlobsRequest>,
     ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
-        let client = self.get_client(
-            request.metadata(),
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().instance_name,
-            Permissions::ReadWrite,
+            "BatchUpdateBlobs",
         )?;
-        let request = request.into_inner();
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self
+            .inner
+            .backend_instance_name(&request.instance_name)
+            .to_owned();
+        if let Some(cache) = &self.inner.find_missing_blobs_cache {
+            cache.invalidate(
+                &request.instance_name,
+                request.requests.iter().filter_map(|r| r.digest.as_ref()),
+            );
+        }
         client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.batch_update_blobs(request).await }
             },
             Self::SERVICE_NAME,
@@ -98,16 +168,30 @@ impl ContentAddressableStorage for CasService {
         &self,
         request: Request<BatchReadBlobsRequest>,
     ) -> Result<Response<BatchReadBlobsResponse>, Status> {
-        let client = self.get_client(
-            request.metadata(),
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().instance_name,
-            Permissions::Read,
+            "BatchReadBlobs",
         )?;
-        let request = request.into_inner();
-        client_call(
+        let fallbacks = self
+            .inner
+            .backend(&request.get_ref().instance_name)
+            .fallbacks
+            .iter()
+            .map(|fallback| (fallback.name.clone(), fallback.cas.clone()))
+            .collect::<Vec<_>>();
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self
+            .inner
+            .backend_instance_name(&request.instance_name)
+            .to_owned();
+        client_call_with_failover(
             client,
+            fallbacks,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.batch_read_blobs(request).await }
             },
             Self::SERVICE_NAME,
@@ -116,28 +200,35 @@ impl ContentAddressableStorage for CasService {
         .await
     }
 
-    type GetTreeStream = tonic::codec::Streaming<GetTreeResponse>;
+    type GetTreeStream = BoxStream<'static, Result<GetTreeResponse, Status>>;
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn get_tree(
         &self,
         request: Request<GetTreeRequest>,
     ) -> Result<Response<Self::GetTreeStream>, Status> {
-        let client = self.get_client(
-            request.metadata(),
+        let (client, permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
             &request.get_ref().instance_name,
-            Permissions::Read,
+            "GetTree",
         )?;
-        let request = request.into_inner();
-        client_call(
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self
+            .inner
+            .backend_instance_name(&request.instance_name)
+            .to_owned();
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.get_tree(request).await }
             },
             Self::SERVICE_NAME,
             "GetTree",
         )
-        .await
+        .await?;
+        Ok(permit.hold_until_streamed(response))
     }
 }
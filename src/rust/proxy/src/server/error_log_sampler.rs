@@ -0,0 +1,274 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use grpc_util::services::convert_status_code;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tonic::{Code, Status};
+
+/// Default length of the window in which backend errors are counted for sampling.
+const DEFAULT_WINDOW_MS: u64 = 60_000;
+
+/// Default number of backend errors per method and code which are logged in each window.
+const DEFAULT_MAX_PER_WINDOW: usize = 10;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct BackendErrorLoggingConfig {
+    /// Length of the window in which transient backend errors are counted, in milliseconds.
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+
+    /// Number of transient backend errors (e.g. `Unavailable`) to log per method and code in
+    /// each window. Further errors in the window are only counted, and a summary is logged when
+    /// the next window starts. `Internal` and `Unknown` errors are always logged.
+    #[serde(default = "default_max_per_window")]
+    pub max_per_window: usize,
+}
+
+impl Default for BackendErrorLoggingConfig {
+    fn default() -> Self {
+        BackendErrorLoggingConfig {
+            window_ms: default_window_ms(),
+            max_per_window: default_max_per_window(),
+        }
+    }
+}
+
+fn default_window_ms() -> u64 {
+    DEFAULT_WINDOW_MS
+}
+
+fn default_max_per_window() -> usize {
+    DEFAULT_MAX_PER_WINDOW
+}
+
+static BACKEND_ERROR_LOG_SAMPLER: LazyLock<ArcSwap<ErrorLogSampler>> = LazyLock::new(|| {
+    ArcSwap::from_pointee(ErrorLogSampler::new(&BackendErrorLoggingConfig::default()))
+});
+
+/// Configure the sampling of backend error logs for this process.
+pub fn configure_backend_error_logging(config: &BackendErrorLoggingConfig) {
+    BACKEND_ERROR_LOG_SAMPLER.store(Arc::new(ErrorLogSampler::new(config)));
+}
+
+type SampleKey = (&'static str, &'static str, Code);
+
+struct Window {
+    started: Instant,
+    logged: usize,
+    suppressed: u64,
+}
+
+/// Whether an error should be logged.
+#[derive(Debug, PartialEq, Eq)]
+enum Sample {
+    /// Log the error. `suppressed` errors with the same key went unlogged in the previous window.
+    Log {
+        suppressed: u64,
+    },
+    Suppress,
+}
+
+/// Limits how many errors are logged per key in each window, so that a backend outage does not
+/// flood the logs with identical lines.
+pub(crate) struct ErrorLogSampler {
+    window: Duration,
+    max_per_window: usize,
+    windows: Mutex<HashMap<SampleKey, Window>>,
+}
+
+impl ErrorLogSampler {
+    fn new(config: &BackendErrorLoggingConfig) -> Self {
+        ErrorLogSampler {
+            window: Duration::from_millis(config.window_ms),
+            max_per_window: config.max_per_window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn sample(&self, key: SampleKey, now: Instant) -> Sample {
+        let mut windows = self.windows.lock();
+        let window = windows.entry(key).or_insert(Window {
+            started: now,
+            logged: 0,
+            suppressed: 0,
+        });
+
+        let mut suppressed = 0;
+        if now.saturating_duration_since(window.started) >= self.window {
+            suppressed = window.suppressed;
+            *window = Window {
+                started: now,
+                logged: 0,
+                suppressed: 0,
+            };
+        }
+
+        if window.logged < self.max_per_window {
+            window.logged += 1;
+            Sample::Log { suppressed }
+        } else {
+            window.suppressed += 1;
+            Sample::Suppress
+        }
+    }
+}
+
+/// Whether errors with this code are high-volume and transient during a backend blip, and so
+/// should be sampled rather than always logged.
+fn is_sampled(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Cancelled | Code::Unavailable | Code::ResourceExhausted | Code::Aborted
+    )
+}
+
+/// Count and (possibly) log an unexpected error from a backend call. Returns whether the error
+/// was logged.
+pub(crate) fn log_backend_error(
+    service_name: &'static str,
+    service_method: &'static str,
+    request_id: Option<&str>,
+    status: &Status,
+) -> bool {
+    let sampler = BACKEND_ERROR_LOG_SAMPLER.load();
+    log_backend_error_with_sampler(&sampler, service_name, service_method, request_id, status)
+}
+
+fn log_backend_error_with_sampler(
+    sampler: &ErrorLogSampler,
+    service_name: &'static str,
+    service_method: &'static str,
+    request_id: Option<&str>,
+    status: &Status,
+) -> bool {
+    let code = status.code();
+    metrics::counter!(
+        "toolchain_proxy_backend_errors_total",
+        1,
+        "grpc_service" => service_name,
+        "grpc_method" => service_method,
+        "grpc_code" => convert_status_code(code as u16),
+    );
+
+    if is_sampled(code) {
+        match sampler.sample((service_name, service_method, code), Instant::now()) {
+            Sample::Log { suppressed } if suppressed > 0 => log::error!(
+                "suppressed {} unexpected backend errors with code {:?} for {}.{} in the last {:?}",
+                suppressed,
+                code,
+                service_name,
+                service_method,
+                sampler.window,
+            ),
+            Sample::Log { .. } => (),
+            Sample::Suppress => return false,
+        }
+    }
+
+    log::error!(
+        "unexpected backend error for {}.{} (request ID {}): {:?}",
+        service_name,
+        service_method,
+        request_id.unwrap_or("none"),
+        status,
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use metrics_util::debugging::{DebugValue, Snapshotter};
+    use tonic::{Code, Status};
+
+    use super::{
+        log_backend_error_with_sampler, BackendErrorLoggingConfig, ErrorLogSampler, Sample,
+    };
+    use crate::server::tests::install_per_thread_metrics_recorder;
+
+    fn sampler(max_per_window: usize) -> ErrorLogSampler {
+        ErrorLogSampler::new(&BackendErrorLoggingConfig {
+            window_ms: 60_000,
+            max_per_window,
+        })
+    }
+
+    #[test]
+    fn transient_errors_are_capped_but_counted() {
+        install_per_thread_metrics_recorder();
+
+        let sampler = sampler(3);
+        let unavailable = Status::unavailable("backend down");
+        let logged = (0..100)
+            .filter(|_| {
+                log_backend_error_with_sampler(&sampler, "Service", "Method", None, &unavailable)
+            })
+            .count();
+        assert_eq!(logged, 3);
+
+        // Internal errors are always logged.
+        let internal = Status::internal("bug");
+        for _ in 0..5 {
+            assert!(log_backend_error_with_sampler(
+                &sampler, "Service", "Method", None, &internal
+            ));
+        }
+
+        let counts = Snapshotter::current_thread_snapshot()
+            .unwrap()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, _, _, _)| key.key().name() == "toolchain_proxy_backend_errors_total")
+            .map(|(key, _, _, value)| {
+                let code = key
+                    .key()
+                    .labels()
+                    .find(|label| label.key() == "grpc_code")
+                    .unwrap()
+                    .value()
+                    .to_owned();
+                let DebugValue::Counter(count) = value else {
+                    panic!("Expected a counter");
+                };
+                (code, count)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(counts.len(), 2);
+        assert!(
+            counts.contains(&("Unavailable".to_owned(), 100)),
+            "{counts:?}"
+        );
+        assert!(counts.contains(&("Internal".to_owned(), 5)), "{counts:?}");
+    }
+
+    #[test]
+    fn suppressed_count_is_reported_in_next_window() {
+        let sampler = sampler(1);
+        let key = ("Service", "Method", Code::Unavailable);
+        let start = Instant::now();
+
+        assert_eq!(sampler.sample(key, start), Sample::Log { suppressed: 0 });
+        assert_eq!(sampler.sample(key, start), Sample::Suppress);
+        assert_eq!(sampler.sample(key, start), Sample::Suppress);
+
+        // Other methods and codes are sampled independently.
+        assert_eq!(
+            sampler.sample(("Service", "Method", Code::Aborted), start),
+            Sample::Log { suppressed: 0 }
+        );
+
+        let next_window = start + Duration::from_secs(60);
+        assert_eq!(
+            sampler.sample(key, next_window),
+            Sample::Log { suppressed: 2 }
+        );
+        assert_eq!(sampler.sample(key, next_window), Sample::Suppress);
+    }
+}
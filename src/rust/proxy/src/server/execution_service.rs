@@ -1,21 +1,26 @@
 // Copyright 2020 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::pin::Pin;
 use std::sync::Arc;
 
+use futures::{Stream, StreamExt};
 use ginepro::LoadBalancedChannel;
-use grpc_util::auth::{AuthScheme, Permissions};
+use grpc_util::auth::AuthScheme;
 use protos::build::bazel::remote::execution::v2::{
     execution_client::ExecutionClient, execution_server::Execution, ExecuteRequest,
     WaitExecutionRequest,
 };
 use protos::google::longrunning::Operation;
-use tonic::metadata::MetadataMap;
+use tonic::codec::Streaming;
 use tonic::{Request, Response, Status};
 
 use execution_util::instance_name_from_session_name;
 
-use crate::server::{client_call, ProxyServerInner};
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, BackendDeadline, ClientCredentials, InstancePermit, ProxyServerInner,
+};
 
 pub(crate) struct ExecutionService {
     inner: Arc<ProxyServerInner>,
@@ -31,15 +36,19 @@ impl ExecutionService {
 
     fn get_client(
         &self,
-        metadata: &MetadataMap,
+        credentials: &ClientCredentials<'_>,
         requested_instance_name: &str,
-    ) -> Result<ExecutionClient<LoadBalancedChannel>, Status> {
+        method_name: &str,
+    ) -> Result<(ExecutionClient<LoadBalancedChannel>, InstancePermit), Status> {
         self.inner.check_authorized(
             self.auth_scheme,
-            metadata,
+            credentials,
             requested_instance_name,
-            Permissions::Execute,
+            required_permissions(Self::SERVICE_NAME, method_name),
         )?;
+        let permit = self
+            .inner
+            .acquire_instance_permit(requested_instance_name)?;
         self.inner
             .backend(requested_instance_name)
             .execution
@@ -48,34 +57,67 @@ impl ExecutionService {
             .ok_or_else(|| {
                 Status::invalid_argument(format!("No such instance: {requested_instance_name}"))
             })
+            .map(|client| (client, permit))
+    }
+}
+
+type OperationStream = Pin<Box<dyn Stream<Item = Result<Operation, Status>> + Send + 'static>>;
+
+impl ExecutionService {
+    /// Rewrite the names of operations streamed back by the backend so that they refer to the
+    /// instance name used by the client (which may be an alias).
+    fn to_external_operations(
+        &self,
+        external_instance_name: String,
+        response: Response<Streaming<Operation>>,
+    ) -> Response<OperationStream> {
+        let inner = self.inner.clone();
+        response.map(move |stream| {
+            let stream = stream.map(move |operation| {
+                operation.map(|mut operation| {
+                    operation.name =
+                        inner.to_external_name(&external_instance_name, operation.name);
+                    operation
+                })
+            });
+            Box::pin(stream) as OperationStream
+        })
     }
 }
 
 #[tonic::async_trait]
 impl Execution for ExecutionService {
-    type ExecuteStream = tonic::codec::Streaming<Operation>;
+    type ExecuteStream = OperationStream;
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn execute(
         &self,
         request: Request<ExecuteRequest>,
     ) -> Result<Response<Self::ExecuteStream>, Status> {
-        let instance_name = &request.get_ref().instance_name;
-        let client = self.get_client(request.metadata(), instance_name)?;
-        let request = request.into_inner();
-        client_call(
+        let instance_name = request.get_ref().instance_name.clone();
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &instance_name,
+            "Execute",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self.inner.backend_instance_name(&instance_name).to_owned();
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.execute(request).await }
             },
             Self::SERVICE_NAME,
             "Execute",
         )
-        .await
+        .await?;
+        Ok(self.to_external_operations(instance_name, response))
     }
 
-    type WaitExecutionStream = tonic::codec::Streaming<Operation>;
+    type WaitExecutionStream = OperationStream;
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn wait_execution(
@@ -85,17 +127,25 @@ impl Execution for ExecutionService {
         let instance_name = instance_name_from_session_name(&request.get_ref().name)
             .map_err(Status::invalid_argument)?;
 
-        let client = self.get_client(request.metadata(), &instance_name)?;
-        let request = request.into_inner();
-        client_call(
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &instance_name,
+            "WaitExecution",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.name = self.inner.to_backend_name(&request.name);
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.wait_execution(request).await }
             },
             Self::SERVICE_NAME,
             "WaitExecution",
         )
-        .await
+        .await?;
+        Ok(self.to_external_operations(instance_name, response))
     }
 }
@@ -0,0 +1,186 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+use protos::build::bazel::remote::execution::v2::Digest;
+use serde::Deserialize;
+
+use crate::server::InstanceName;
+
+/// Default maximum number of digests remembered by the `FindMissingBlobs` cache.
+const DEFAULT_FIND_MISSING_BLOBS_CACHE_MAX_ENTRIES: usize = 100_000;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct FindMissingBlobsCacheConfig {
+    /// How long a digest which a backend reported as present is assumed to remain present, in
+    /// milliseconds. Zero (the default) disables the cache.
+    #[serde(default)]
+    pub ttl_ms: u64,
+
+    /// Maximum number of digests to remember. The least recently used are evicted first.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for FindMissingBlobsCacheConfig {
+    fn default() -> Self {
+        FindMissingBlobsCacheConfig {
+            ttl_ms: 0,
+            max_entries: default_max_entries(),
+        }
+    }
+}
+
+fn default_max_entries() -> usize {
+    DEFAULT_FIND_MISSING_BLOBS_CACHE_MAX_ENTRIES
+}
+
+/// Digests are keyed by the instance name sent to the backend, their hash and their size.
+type CacheKey = (InstanceName, String, i64);
+
+/// Remembers digests which backends recently reported as present, so that repeated
+/// `FindMissingBlobs` calls for the same digests only ask the backend about the others.
+pub(crate) struct FindMissingBlobsCache {
+    ttl: Duration,
+    /// The time at which each digest was last confirmed present.
+    entries: Mutex<LruCache<CacheKey, Instant>>,
+}
+
+impl FindMissingBlobsCache {
+    /// Returns `None` if the config disables the cache.
+    pub(crate) fn new(config: &FindMissingBlobsCacheConfig) -> Option<Self> {
+        let max_entries = NonZeroUsize::new(config.max_entries)?;
+        if config.ttl_ms == 0 {
+            return None;
+        }
+        Some(FindMissingBlobsCache {
+            ttl: Duration::from_millis(config.ttl_ms),
+            entries: Mutex::new(LruCache::new(max_entries)),
+        })
+    }
+
+    /// Split `digests` into those which still need to be checked with the backend, and the
+    /// number which were recently confirmed present.
+    pub(crate) fn filter_present(
+        &self,
+        instance_name: &str,
+        digests: Vec<Digest>,
+    ) -> (Vec<Digest>, usize) {
+        let mut entries = self.entries.lock();
+        let mut hits = 0;
+        let unknown = digests
+            .into_iter()
+            .filter(|digest| {
+                let key = cache_key(instance_name, digest);
+                match entries.get(&key) {
+                    Some(confirmed_at) if confirmed_at.elapsed() < self.ttl => {
+                        hits += 1;
+                        false
+                    }
+                    Some(_) => {
+                        entries.pop(&key);
+                        true
+                    }
+                    None => true,
+                }
+            })
+            .collect();
+        (unknown, hits)
+    }
+
+    /// Remember the `requested` digests which the backend did not report as `missing`.
+    pub(crate) fn insert_present(
+        &self,
+        instance_name: &str,
+        requested: &[Digest],
+        missing: &[Digest],
+    ) {
+        let missing = missing
+            .iter()
+            .map(|digest| (digest.hash.as_str(), digest.size_bytes))
+            .collect::<HashSet<_>>();
+        let now = Instant::now();
+        let mut entries = self.entries.lock();
+        for digest in requested
+            .iter()
+            .filter(|digest| !missing.contains(&(digest.hash.as_str(), digest.size_bytes)))
+        {
+            entries.put(cache_key(instance_name, digest), now);
+        }
+    }
+
+    /// Forget the given digests, e.g. because a client is writing them.
+    pub(crate) fn invalidate<'a>(
+        &self,
+        instance_name: &str,
+        digests: impl IntoIterator<Item = &'a Digest>,
+    ) {
+        let mut entries = self.entries.lock();
+        for digest in digests {
+            entries.pop(&cache_key(instance_name, digest));
+        }
+    }
+}
+
+fn cache_key(instance_name: &str, digest: &Digest) -> CacheKey {
+    (
+        instance_name.to_owned(),
+        digest.hash.clone(),
+        digest.size_bytes,
+    )
+}
+
+/// Parse the instance name and digest from a ByteStream write resource name of the form
+/// `{instance_name}/uploads/{uuid}/blobs/{hash}/{size}` (or with
+/// `compressed-blobs/{compressor}` in place of `blobs`).
+pub(crate) fn parse_write_resource_digest(resource_name: &str) -> Option<(&str, Digest)> {
+    let (instance_name, rest) = match resource_name.split_once("uploads/") {
+        Some(("", rest)) => ("", rest),
+        Some((instance_name, rest)) => (instance_name.strip_suffix('/')?, rest),
+        None => return None,
+    };
+    let mut parts = rest.split('/').skip(1);
+    let hash = match parts.next()? {
+        "blobs" => parts.next()?,
+        "compressed-blobs" => parts.nth(1)?,
+        _ => return None,
+    };
+    let size_bytes = parts.next()?.parse().ok()?;
+    Some((
+        instance_name,
+        Digest {
+            hash: hash.to_owned(),
+            size_bytes,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use protos::build::bazel::remote::execution::v2::Digest;
+
+    use super::parse_write_resource_digest;
+
+    #[test]
+    fn parses_write_resource_digest() {
+        let digest = Digest {
+            hash: "abc123".to_owned(),
+            size_bytes: 12,
+        };
+        assert_eq!(
+            parse_write_resource_digest("main/uploads/uuid/blobs/abc123/12"),
+            Some(("main", digest.clone()))
+        );
+        assert_eq!(
+            parse_write_resource_digest("uploads/uuid/compressed-blobs/zstd/abc123/12/meta"),
+            Some(("", digest))
+        );
+        assert_eq!(parse_write_resource_digest("main/uploads/uuid/12"), None);
+        assert_eq!(parse_write_resource_digest("main/blobs/abc123/12"), None);
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Code, Response, Status};
+
+use crate::server::InstanceName;
+
+#[derive(Clone, Deserialize, Default, Debug)]
+pub struct InstanceLimitsConfig {
+    /// Maximum number of in-flight requests for any single instance. Unlimited if not set.
+    pub default_max_in_flight: Option<usize>,
+
+    /// Per-instance overrides of `default_max_in_flight`.
+    #[serde(default)]
+    pub per_instance_max_in_flight: HashMap<InstanceName, usize>,
+
+    /// Rate limit for requests to any single instance. Unlimited if not set.
+    pub default_rate_limit: Option<RateLimitConfig>,
+
+    /// Per-instance overrides of `default_rate_limit`.
+    #[serde(default)]
+    pub per_instance_rate_limit: HashMap<InstanceName, RateLimitConfig>,
+}
+
+/// A token bucket which refills at `requests_per_second`, and holds at most `burst` tokens.
+#[derive(Clone, Copy, Deserialize, Debug)]
+pub struct RateLimitConfig {
+    /// Sustained rate of requests allowed. Must be positive.
+    pub requests_per_second: f64,
+
+    /// Number of requests allowed at once after a period of inactivity. Must be non-zero.
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.requests_per_second <= 0.0 || !self.requests_per_second.is_finite() {
+            return Err(format!(
+                "Rate limit `requests_per_second` must be positive, but was {}",
+                self.requests_per_second
+            ));
+        }
+        if self.burst == 0 {
+            return Err("Rate limit `burst` must be non-zero".to_owned());
+        }
+        Ok(())
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(config: &RateLimitConfig, now: Instant) -> Self {
+        TokenBucket {
+            tokens: config.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Take a token if one is available, or else return how long until one will be.
+    fn try_take(&mut self, config: &RateLimitConfig, now: Instant) -> Result<(), Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.requests_per_second).min(config.burst as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / config.requests_per_second,
+            ))
+        }
+    }
+}
+
+/// Tracks in-flight requests and request rates per REAPI instance and enforces the configured
+/// limits so that a single instance cannot exhaust the proxy's (or its backends') resources.
+pub(crate) struct InstanceLimiter {
+    config: InstanceLimitsConfig,
+    in_flight: Mutex<HashMap<InstanceName, usize>>,
+    token_buckets: Mutex<HashMap<InstanceName, TokenBucket>>,
+}
+
+impl InstanceLimiter {
+    pub(crate) fn new(config: InstanceLimitsConfig) -> Result<Self, String> {
+        for rate_limit in config
+            .default_rate_limit
+            .iter()
+            .chain(config.per_instance_rate_limit.values())
+        {
+            rate_limit.validate()?;
+        }
+        Ok(InstanceLimiter {
+            config,
+            in_flight: Mutex::new(HashMap::new()),
+            token_buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn limit(&self, instance_name: &str) -> Option<usize> {
+        self.config
+            .per_instance_max_in_flight
+            .get(instance_name)
+            .copied()
+            .or(self.config.default_max_in_flight)
+    }
+
+    fn rate_limit(&self, instance_name: &str) -> Option<&RateLimitConfig> {
+        self.config
+            .per_instance_rate_limit
+            .get(instance_name)
+            .or(self.config.default_rate_limit.as_ref())
+    }
+
+    /// Take a token from the instance's rate limit (if any), or else fail with `ResourceExhausted`
+    /// and a `retry-after` trailer with the number of seconds until a request would be allowed.
+    fn check_rate_limit(&self, instance_name: &str) -> Result<(), Status> {
+        let Some(rate_limit) = self.rate_limit(instance_name) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut token_buckets = self.token_buckets.lock();
+        let retry_after = match token_buckets.get_mut(instance_name) {
+            Some(bucket) => bucket.try_take(rate_limit, now),
+            None => token_buckets
+                .entry(instance_name.to_owned())
+                .or_insert_with(|| TokenBucket::new(rate_limit, now))
+                .try_take(rate_limit, now),
+        };
+        let Err(retry_after) = retry_after else {
+            return Ok(());
+        };
+
+        metrics::counter!(
+            "toolchain_proxy_rate_limited_total",
+            1,
+            "instance" => instance_name.to_owned(),
+        );
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            "retry-after",
+            MetadataValue::from(retry_after.as_secs_f64().ceil() as u64),
+        );
+        Err(Status::with_metadata(
+            Code::ResourceExhausted,
+            format!("Rate limit exceeded for instance `{instance_name}`"),
+            metadata,
+        ))
+    }
+
+    /// Acquire a permit for a request to `instance_name`. The request counts as in-flight until
+    /// the permit is dropped. Returns `ResourceExhausted` if the instance is at its in-flight limit
+    /// or has exceeded its rate limit.
+    pub(crate) fn acquire(self: &Arc<Self>, instance_name: &str) -> Result<InstancePermit, Status> {
+        self.check_rate_limit(instance_name)?;
+        let mut in_flight = self.in_flight.lock();
+        let count = in_flight.entry(instance_name.to_owned()).or_default();
+        if let Some(limit) = self.limit(instance_name) {
+            if *count >= limit {
+                metrics::counter!(
+                    "toolchain_proxy_instance_limit_rejected_total",
+                    1,
+                    "instance" => instance_name.to_owned(),
+                );
+                return Err(Status::resource_exhausted(format!(
+                    "Too many in-flight requests for instance `{instance_name}`"
+                )));
+            }
+        }
+        *count += 1;
+        update_gauge(instance_name, *count);
+
+        Ok(InstancePermit {
+            limiter: self.clone(),
+            instance_name: instance_name.to_owned(),
+        })
+    }
+}
+
+fn update_gauge(instance_name: &str, count: usize) {
+    metrics::gauge!(
+        "toolchain_proxy_instance_inflight",
+        count as f64,
+        "instance" => instance_name.to_owned(),
+    );
+}
+
+/// Marks a request as in-flight for an instance until dropped.
+pub(crate) struct InstancePermit {
+    limiter: Arc<InstanceLimiter>,
+    instance_name: InstanceName,
+}
+
+impl InstancePermit {
+    /// Keep the request in flight until the streamed `response` has been consumed (or dropped),
+    /// rather than only until the handler returns.
+    pub(crate) fn hold_until_streamed<S, T>(
+        self,
+        response: Response<S>,
+    ) -> Response<BoxStream<'static, Result<T, Status>>>
+    where
+        S: Stream<Item = Result<T, Status>> + Send + 'static,
+        T: Send + 'static,
+    {
+        response.map(move |stream| {
+            stream
+                .map(move |item| {
+                    let _permit = &self;
+                    item
+                })
+                .boxed()
+        })
+    }
+}
+
+impl Drop for InstancePermit {
+    fn drop(&mut self) {
+        let mut in_flight = self.limiter.in_flight.lock();
+        if let Some(count) = in_flight.get_mut(&self.instance_name) {
+            *count = count.saturating_sub(1);
+            update_gauge(&self.instance_name, *count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use tonic::Code;
+
+    use super::{InstanceLimiter, InstanceLimitsConfig, RateLimitConfig};
+
+    #[test]
+    fn saturated_instance_does_not_affect_others() {
+        let limiter = Arc::new(
+            InstanceLimiter::new(InstanceLimitsConfig {
+                default_max_in_flight: Some(2),
+                per_instance_max_in_flight: HashMap::from([("noisy".to_owned(), 1)]),
+                ..InstanceLimitsConfig::default()
+            })
+            .unwrap(),
+        );
+
+        let noisy_permit = limiter.acquire("noisy").unwrap();
+        let err = limiter.acquire("noisy").err().unwrap();
+        assert_eq!(err.code(), Code::ResourceExhausted);
+
+        // Other instances use the default limit and are unaffected.
+        let _quiet_permit_1 = limiter.acquire("quiet").unwrap();
+        let _quiet_permit_2 = limiter.acquire("quiet").unwrap();
+        assert!(limiter.acquire("quiet").is_err());
+
+        // Releasing the permit frees capacity for the noisy instance again.
+        drop(noisy_permit);
+        limiter.acquire("noisy").unwrap();
+    }
+
+    #[test]
+    fn rate_limited_instance_does_not_affect_others() {
+        let limiter = Arc::new(
+            InstanceLimiter::new(InstanceLimitsConfig {
+                per_instance_rate_limit: HashMap::from([(
+                    "noisy".to_owned(),
+                    RateLimitConfig {
+                        requests_per_second: 0.5,
+                        burst: 2,
+                    },
+                )]),
+                ..InstanceLimitsConfig::default()
+            })
+            .unwrap(),
+        );
+
+        // The burst is allowed, but subsequent requests are throttled.
+        limiter.acquire("noisy").unwrap();
+        limiter.acquire("noisy").unwrap();
+        let err = limiter.acquire("noisy").err().unwrap();
+        assert_eq!(err.code(), Code::ResourceExhausted);
+        let retry_after = err.metadata().get("retry-after").unwrap().to_str().unwrap();
+        assert_eq!(retry_after, "2");
+        assert!(limiter.acquire("noisy").is_err());
+
+        // Other instances are unlimited by default.
+        for _ in 0..10 {
+            limiter.acquire("quiet").unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rate_limits() {
+        for (requests_per_second, burst) in [(0.0, 1), (f64::NAN, 1), (1.0, 0)] {
+            let config = InstanceLimitsConfig {
+                default_rate_limit: Some(RateLimitConfig {
+                    requests_per_second,
+                    burst,
+                }),
+                ..InstanceLimitsConfig::default()
+            };
+            assert!(InstanceLimiter::new(config).is_err());
+        }
+    }
+}
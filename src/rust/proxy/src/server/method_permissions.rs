@@ -0,0 +1,166 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use grpc_util::auth::Permissions;
+
+use crate::server::action_cache_service::ActionCacheService;
+use crate::server::bots_service::BotsService;
+use crate::server::byte_stream_service::ByteStreamService;
+use crate::server::capabilities_service::CapabilitiesService;
+use crate::server::cas_service::CasService;
+use crate::server::execution_service::ExecutionService;
+use crate::server::operations_service::OperationsService;
+use crate::server::storage_admin_service::StorageAdminService;
+
+/// The permission required to call each (service, method) served by the proxy.
+///
+/// NB: Only the JWT auth scheme carries permissions: the other schemes map credentials to an
+/// instance and otherwise ignore these values (other than refusing `Admin`).
+const METHOD_PERMISSIONS: &[(&str, &str, Permissions)] = &[
+    (
+        CasService::SERVICE_NAME,
+        "FindMissingBlobs",
+        Permissions::Read,
+    ),
+    (
+        CasService::SERVICE_NAME,
+        "BatchReadBlobs",
+        Permissions::Read,
+    ),
+    (CasService::SERVICE_NAME, "GetTree", Permissions::Read),
+    (
+        CasService::SERVICE_NAME,
+        "BatchUpdateBlobs",
+        Permissions::ReadWrite,
+    ),
+    (ByteStreamService::SERVICE_NAME, "Read", Permissions::Read),
+    (
+        ByteStreamService::SERVICE_NAME,
+        "Write",
+        Permissions::ReadWrite,
+    ),
+    (
+        ByteStreamService::SERVICE_NAME,
+        "QueryWriteStatus",
+        Permissions::ReadWrite,
+    ),
+    (
+        ActionCacheService::SERVICE_NAME,
+        "GetActionResult",
+        Permissions::Read,
+    ),
+    (
+        ActionCacheService::SERVICE_NAME,
+        "UpdateActionResult",
+        Permissions::ReadWrite,
+    ),
+    (
+        CapabilitiesService::SERVICE_NAME,
+        "GetCapabilities",
+        Permissions::Read,
+    ),
+    (
+        ExecutionService::SERVICE_NAME,
+        "Execute",
+        Permissions::Execute,
+    ),
+    (
+        ExecutionService::SERVICE_NAME,
+        "WaitExecution",
+        Permissions::Execute,
+    ),
+    (
+        OperationsService::SERVICE_NAME,
+        "ListOperations",
+        Permissions::Execute,
+    ),
+    (
+        OperationsService::SERVICE_NAME,
+        "GetOperation",
+        Permissions::Execute,
+    ),
+    (
+        OperationsService::SERVICE_NAME,
+        "DeleteOperation",
+        Permissions::Execute,
+    ),
+    (
+        OperationsService::SERVICE_NAME,
+        "CancelOperation",
+        Permissions::Execute,
+    ),
+    (
+        OperationsService::SERVICE_NAME,
+        "WaitOperation",
+        Permissions::Execute,
+    ),
+    // TODO: consider adding a Worker permission for the Bots service.
+    (
+        BotsService::SERVICE_NAME,
+        "CreateBotSession",
+        Permissions::Execute,
+    ),
+    (
+        BotsService::SERVICE_NAME,
+        "UpdateBotSession",
+        Permissions::Execute,
+    ),
+    (
+        StorageAdminService::SERVICE_NAME,
+        "DeleteBlobs",
+        Permissions::Admin,
+    ),
+];
+
+/// Look up the permission required to call `method_name` on `service_name`. Methods which are
+/// missing from the table require `Admin`, so that a newly proxied method fails closed until it
+/// has been classified.
+pub(crate) fn required_permissions(service_name: &str, method_name: &str) -> Permissions {
+    METHOD_PERMISSIONS
+        .iter()
+        .find(|(service, method, _)| *service == service_name && *method == method_name)
+        .map(|(_, _, permissions)| *permissions)
+        .unwrap_or(Permissions::Admin)
+}
+
+#[cfg(test)]
+mod tests {
+    use grpc_util::auth::Permissions;
+
+    use super::required_permissions;
+    use crate::server::byte_stream_service::ByteStreamService;
+    use crate::server::cas_service::CasService;
+    use crate::server::execution_service::ExecutionService;
+
+    #[test]
+    fn classifies_methods_by_access_level() {
+        assert_eq!(
+            required_permissions(CasService::SERVICE_NAME, "BatchReadBlobs"),
+            Permissions::Read
+        );
+        assert_eq!(
+            required_permissions(CasService::SERVICE_NAME, "BatchUpdateBlobs"),
+            Permissions::ReadWrite
+        );
+        assert_eq!(
+            required_permissions(ByteStreamService::SERVICE_NAME, "Write"),
+            Permissions::ReadWrite
+        );
+        assert_eq!(
+            required_permissions(ExecutionService::SERVICE_NAME, "Execute"),
+            Permissions::Execute
+        );
+    }
+
+    #[test]
+    fn unknown_methods_require_admin() {
+        assert_eq!(
+            required_permissions(CasService::SERVICE_NAME, "SpliceBlob"),
+            Permissions::Admin
+        );
+        assert_eq!(
+            required_permissions("some.other.Service", "Read"),
+            Permissions::Admin
+        );
+    }
+}
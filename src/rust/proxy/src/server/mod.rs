@@ -12,10 +12,14 @@ use arc_swap::ArcSwap;
 use futures::{future, Stream};
 use ginepro::LoadBalancedChannel;
 use grpc_util::auth;
-use grpc_util::auth::{AuthScheme, AuthToken, AuthTokenEntry, JWKSet, Permissions};
-use grpc_util::backend::{construct_channel, BackendConfig};
-use grpc_util::infra::GrpcConfig;
+use grpc_util::auth::{
+    ApiKeyHash, AuthScheme, AuthToken, AuthTokenEntry, ClientCertificateMapping, JWKSet,
+    JwtPermissionsClaim, Permissions,
+};
+use grpc_util::backend::{construct_channel, BackendCompression, BackendConfig};
+use grpc_util::infra::{GrpcConfig, DEFAULT_MAX_MESSAGE_SIZE};
 use grpc_util::services::convert_status_code;
+use grpc_util::services::grpc_timeout;
 use grpc_util::services::GrpcMetrics;
 use protos::build::bazel::remote::execution::v2::{
     action_cache_client::ActionCacheClient, action_cache_server::ActionCacheServer,
@@ -30,12 +34,16 @@ use protos::google::devtools::remoteworkers::v1test2::bots_client::BotsClient;
 use protos::google::devtools::remoteworkers::v1test2::bots_server::BotsServer;
 use protos::google::longrunning::operations_client::OperationsClient;
 use protos::google::longrunning::operations_server::OperationsServer;
+use protos::toolchain::storage::admin::v1::storage_admin_client::StorageAdminClient;
+use protos::toolchain::storage::admin::v1::storage_admin_server::StorageAdminServer;
+use regex::Regex;
 use serde::Deserialize;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tonic::codec::CompressionEncoding;
 use tonic::metadata::MetadataMap;
 use tonic::transport::server::Connected;
-use tonic::transport::Server;
-use tonic::{Code, Response, Status};
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Code, Request, Response, Status};
 use tower::ServiceBuilder;
 use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
 use tower_http::metrics::InFlightRequestsLayer;
@@ -48,14 +56,35 @@ mod bots_service;
 mod byte_stream_service;
 mod capabilities_service;
 mod cas_service;
+mod error_log_sampler;
 mod execution_service;
+mod find_missing_blobs_cache;
+mod instance_limits;
+mod method_permissions;
 mod operations_service;
+mod request_id;
+mod storage_admin_service;
 
 #[cfg(test)]
 mod tests;
 
+use error_log_sampler::log_backend_error;
+pub use error_log_sampler::{configure_backend_error_logging, BackendErrorLoggingConfig};
+use find_missing_blobs_cache::FindMissingBlobsCache;
+pub use find_missing_blobs_cache::FindMissingBlobsCacheConfig;
+use instance_limits::InstanceLimiter;
+pub(crate) use instance_limits::InstancePermit;
+pub use instance_limits::{InstanceLimitsConfig, RateLimitConfig};
+
+use capabilities_service::{CapabilitiesCache, CapabilitiesFilter};
+use request_id::{current_request_id, set_request_id, RequestIdLayer};
+
 pub type InstanceName = String;
 
+/// Environment variable which, when set to `1`, allows listeners to use the `dev_only_no_auth`
+/// auth scheme.
+const ALLOW_NO_AUTH_ENV_VAR: &str = "TOOLCHAIN_ALLOW_NO_AUTH";
+
 #[derive(Clone, Deserialize, Default, Debug)]
 pub struct ListenAddressConfig {
     /// IP address on which to listen for connections.
@@ -65,6 +94,86 @@ pub struct ListenAddressConfig {
     /// The services that should be supported on this address. A list of fully qualified service
     /// names, e.g. 'build.bazel.remote.execution.v2.ActionCache'.
     pub allowed_service_names: Vec<String>,
+    /// Serve TLS on this address. Required for the `mutual_tls` auth scheme.
+    pub tls: Option<ListenerTlsConfig>,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+pub struct ListenerTlsConfig {
+    /// Path to the PEM-encoded server certificate (chain).
+    pub cert_path: String,
+    /// Path to the PEM-encoded private key for the server certificate.
+    pub key_path: String,
+    /// Path to the PEM-encoded CA certificate(s) used to verify client certificates. If set,
+    /// clients must present a certificate signed by one of these CAs.
+    pub client_ca_path: Option<String>,
+}
+
+impl ListenAddressConfig {
+    /// Check that this listener may be served. The `dev_only_no_auth` auth scheme accepts every
+    /// request, so it is refused unless `dev_mode` is set or the `TOOLCHAIN_ALLOW_NO_AUTH=1`
+    /// environment variable is set. The `mutual_tls` auth scheme requires the listener to verify
+    /// client certificates against `tls.client_ca_path`.
+    pub fn validate(&self, dev_mode: bool) -> Result<(), String> {
+        let allowed_by_env = std::env::var(ALLOW_NO_AUTH_ENV_VAR).ok().as_deref() == Some("1");
+        self.validate_allowing_no_auth(dev_mode || allowed_by_env)
+    }
+
+    /// Check that this listener may be served, where `allow_no_auth` is whether the
+    /// `dev_only_no_auth` auth scheme is allowed.
+    fn validate_allowing_no_auth(&self, allow_no_auth: bool) -> Result<(), String> {
+        match self.auth_scheme {
+            None => Err(format!("Must set auth_scheme for listener {}", self.addr)),
+            Some(AuthScheme::MutualTls) => {
+                if self
+                    .tls
+                    .as_ref()
+                    .and_then(|tls| tls.client_ca_path.as_ref())
+                    .is_none()
+                {
+                    return Err(format!(
+                        "The mutual_tls auth scheme requires tls.client_ca_path to be set for \
+                        listener {}",
+                        self.addr
+                    ));
+                }
+                Ok(())
+            }
+            Some(AuthScheme::DevOnlyNoAuth) => {
+                if !allow_no_auth {
+                    return Err(format!(
+                        "Refusing to serve {} with the dev_only_no_auth auth scheme outside of \
+                        dev mode. Set `dev_mode: true` in the config or \
+                        {ALLOW_NO_AUTH_ENV_VAR}=1 to allow it.",
+                        self.addr
+                    ));
+                }
+                log::warn!(
+                    "!!! Serving {} WITHOUT AUTHENTICATION (dev_only_no_auth). This must never be \
+                    used in production. !!!",
+                    self.addr
+                );
+                Ok(())
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+impl ListenerTlsConfig {
+    pub fn to_server_tls_config(&self) -> Result<ServerTlsConfig, String> {
+        let read = |path: &str| {
+            std::fs::read(path).map_err(|err| format!("Failed to read TLS file {path}: {err}"))
+        };
+        let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(
+            read(&self.cert_path)?,
+            read(&self.key_path)?,
+        ));
+        if let Some(client_ca_path) = &self.client_ca_path {
+            tls_config = tls_config.client_ca_root(Certificate::from_pem(read(client_ca_path)?));
+        }
+        Ok(tls_config)
+    }
 }
 
 #[derive(Clone, Deserialize, Default, Debug)]
@@ -73,6 +182,36 @@ pub struct BackendTimeoutsConfig {
     pub get_action_result: Option<Duration>,
 }
 
+/// A channel to a backend, along with the compression negotiated by clients which use it.
+struct BackendChannel {
+    channel: LoadBalancedChannel,
+    compression: Option<BackendCompression>,
+}
+
+/// Construct a generated tonic client of type `$client` for a `&BackendChannel`, enabling the
+/// backend's compression setting. The generated clients share no trait for compression, hence a
+/// macro.
+///
+/// Messages of up to `DEFAULT_MAX_MESSAGE_SIZE` are accepted from backends (rather than Tonic's
+/// default of 4 MiB), so that full batches from the storage server can be relayed.
+macro_rules! backend_client {
+    ($client:ident, $backend:expr) => {{
+        let backend: &BackendChannel = $backend;
+        let client = $client::new(backend.channel.clone())
+            .max_decoding_message_size(DEFAULT_MAX_MESSAGE_SIZE)
+            .max_encoding_message_size(DEFAULT_MAX_MESSAGE_SIZE);
+        match backend.compression {
+            None => client,
+            Some(BackendCompression::AcceptGzip) => {
+                client.accept_compressed(CompressionEncoding::Gzip)
+            }
+            Some(BackendCompression::Gzip) => client
+                .accept_compressed(CompressionEncoding::Gzip)
+                .send_compressed(CompressionEncoding::Gzip),
+        }
+    }};
+}
+
 /// All of the clients for a single backend.
 pub(crate) struct Backend {
     // CAS/AC-specific clients
@@ -80,29 +219,76 @@ pub(crate) struct Backend {
     pub(crate) action_cache: ActionCacheClient<LoadBalancedChannel>,
     pub(crate) bytestream: ByteStreamClient<LoadBalancedChannel>,
     pub(crate) cas_capabilities: CapabilitiesClient<LoadBalancedChannel>,
+    pub(crate) storage_admin: StorageAdminClient<LoadBalancedChannel>,
 
     // Execution-specific clients
     pub(crate) execution: Option<ExecutionClient<LoadBalancedChannel>>,
     pub(crate) operations: Option<OperationsClient<LoadBalancedChannel>>,
     pub(crate) bots: Option<BotsClient<LoadBalancedChannel>>,
     pub(crate) _execution_capabilities: Option<CapabilitiesClient<LoadBalancedChannel>>,
+
+    /// Recent `GetCapabilities` responses from this backend.
+    pub(crate) capabilities_cache: CapabilitiesCache,
+
+    /// Restricts the digest functions and compressors advertised to clients.
+    pub(crate) capabilities_filter: CapabilitiesFilter,
+
+    /// Backends to fail over to, in order, for CAS reads when `cas` is unavailable.
+    pub(crate) fallbacks: Vec<FallbackBackend>,
+
+    /// Whether writes to the instances served by this backend are rejected.
+    pub(crate) read_only: bool,
+}
+
+/// The clients for a backend which serves CAS reads when the primary backend is unavailable.
+pub(crate) struct FallbackBackend {
+    pub(crate) name: String,
+    pub(crate) cas: ContentAddressableStorageClient<LoadBalancedChannel>,
+    pub(crate) bytestream: ByteStreamClient<LoadBalancedChannel>,
 }
 
 pub(crate) struct ProxyServerInner {
     /// Per-instance backends.
     instance_backends: HashMap<InstanceName, Backend>,
 
+    /// Backends for instances which match a pattern, in order of precedence.
+    instance_backend_rules: Vec<(Regex, Backend)>,
+
     /// Backend that will receive all requests which are not routed via per-instance backends.
     catchall_backend: Backend,
 
     /// The JSON Web Key (JWK) Set used for JWT authentication.
     jwk_set: JWKSet,
 
+    /// The JWT claim which carries the permissions granted by a token.
+    jwt_permissions_claim: JwtPermissionsClaim,
+
     /// A mapping of auth tokens to their auth metadata (for Worker authentication).
     auth_token_mapping: ArcSwap<HashMap<AuthToken, AuthTokenEntry>>,
 
+    /// A mapping of hashed API keys to their auth metadata.
+    api_key_mapping: ArcSwap<HashMap<ApiKeyHash, AuthTokenEntry>>,
+
+    /// A mapping of client certificate identities to instance names (for mutual TLS).
+    client_certificate_mapping: ClientCertificateMapping,
+
     /// Timeouts to apply to calls to backends.
     timeouts: BackendTimeoutsConfig,
+
+    /// Maps external instance names to the instance name used when forwarding to backends.
+    instance_aliases: HashMap<InstanceName, InstanceName>,
+
+    /// Limits on the number of in-flight requests per instance.
+    instance_limiter: Arc<InstanceLimiter>,
+
+    /// How long to serve cached `GetCapabilities` responses for. Zero disables the cache.
+    capabilities_cache_ttl: Duration,
+
+    /// Digests recently reported present by `FindMissingBlobs`, if enabled.
+    find_missing_blobs_cache: Option<FindMissingBlobsCache>,
+
+    /// Whether listeners may use the `dev_only_no_auth` auth scheme.
+    dev_mode: bool,
 }
 
 /// A proxy server for Remote Execution API
@@ -115,7 +301,74 @@ pub struct ProxyServer {
     inner: Arc<ProxyServerInner>,
 }
 
-#[derive(Deserialize, Debug, Default)]
+/// Configuration of a `ProxyServer`: its backends, how instances are routed to them, and how
+/// clients are authenticated.
+pub struct ProxyServerConfig {
+    /// Backends by name.
+    pub backends: HashMap<String, BackendConfig>,
+
+    /// The backends of specific instances.
+    pub per_instance_backends: HashMap<InstanceName, InstanceConfig>,
+
+    /// Routes instances matching a pattern to backends, after `per_instance_backends`.
+    pub instance_backend_rules: Vec<InstanceBackendRule>,
+
+    /// The backends of all other instances.
+    pub default_backends: InstanceConfig,
+
+    /// The JSON Web Key (JWK) Set used for JWT authentication.
+    pub jwk_set: JWKSet,
+
+    /// The JWT claim which carries the permissions granted by a token.
+    pub jwt_permissions_claim: JwtPermissionsClaim,
+
+    /// The initial mapping of auth tokens to their auth metadata.
+    pub auth_token_mapping: HashMap<AuthToken, AuthTokenEntry>,
+
+    /// Timeouts to apply to calls to backends.
+    pub timeouts: BackendTimeoutsConfig,
+
+    /// Maps external instance names to the instance name used when forwarding to backends.
+    pub instance_aliases: HashMap<InstanceName, InstanceName>,
+
+    /// Limits on the number of in-flight requests per instance.
+    pub instance_limits: InstanceLimitsConfig,
+
+    /// How long to serve cached `GetCapabilities` responses for. Zero disables the cache.
+    pub capabilities_cache_ttl: Duration,
+
+    /// Caching of digests reported present by `FindMissingBlobs`.
+    pub find_missing_blobs_cache: FindMissingBlobsCacheConfig,
+
+    /// A mapping of client certificate identities to instance names (for mutual TLS).
+    pub client_certificate_mapping: ClientCertificateMapping,
+
+    /// Whether listeners may use the `dev_only_no_auth` auth scheme.
+    pub dev_mode: bool,
+}
+
+impl Default for ProxyServerConfig {
+    fn default() -> Self {
+        ProxyServerConfig {
+            backends: HashMap::new(),
+            per_instance_backends: HashMap::new(),
+            instance_backend_rules: Vec::new(),
+            default_backends: InstanceConfig::default(),
+            jwk_set: JWKSet { keys: Vec::new() },
+            jwt_permissions_claim: JwtPermissionsClaim::default(),
+            auth_token_mapping: HashMap::new(),
+            timeouts: BackendTimeoutsConfig::default(),
+            instance_aliases: HashMap::new(),
+            instance_limits: InstanceLimitsConfig::default(),
+            capabilities_cache_ttl: Duration::ZERO,
+            find_missing_blobs_cache: FindMissingBlobsCacheConfig::default(),
+            client_certificate_mapping: ClientCertificateMapping::new(),
+            dev_mode: false,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
 pub struct InstanceConfig {
     /// Address of the remote ContentAddressableStorage service in the form HOST:PORT.
     pub cas: String,
@@ -125,6 +378,60 @@ pub struct InstanceConfig {
 
     /// Address of the remote Execution service in the form HOST:PORT (optional)
     pub execution: Option<String>,
+
+    /// Backends to try, in order, for CAS reads when the `cas` backend is unavailable.
+    #[serde(default)]
+    pub fallback_backends: Vec<String>,
+
+    /// Digest functions (e.g. `SHA256`) which may be advertised to clients by `GetCapabilities`.
+    /// If unset, the digest functions advertised by the backend are passed through.
+    #[serde(default)]
+    pub allowed_digest_functions: Option<Vec<String>>,
+
+    /// Compressors (e.g. `ZSTD`) which may be advertised to clients by `GetCapabilities`. If
+    /// unset, the compressors advertised by the backend are passed through.
+    #[serde(default)]
+    pub allowed_compressors: Option<Vec<String>>,
+
+    /// Serve reads, but reject writes (e.g. `BatchUpdateBlobs`, `ByteStream.Write`,
+    /// `UpdateActionResult` and `DeleteBlobs`) with `FAILED_PRECONDITION`. Useful during
+    /// migrations.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Routes instances whose name matches `pattern` to a set of backends. Consulted in order after
+/// `per_instance_backends` and before the catch-all backends.
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct InstanceBackendRule {
+    /// Regular expression which must match the entire instance name.
+    pub pattern: String,
+
+    /// The backends for matching instances.
+    #[serde(flatten)]
+    pub backends: InstanceConfig,
+}
+
+/// The parts of a client request which are used to authenticate it.
+pub(crate) struct ClientCredentials<'a> {
+    metadata: &'a MetadataMap,
+    peer_certs: Option<Arc<Vec<Certificate>>>,
+}
+
+impl<'a> ClientCredentials<'a> {
+    pub(crate) fn new(
+        metadata: &'a MetadataMap,
+        peer_certs: Option<Arc<Vec<Certificate>>>,
+    ) -> Self {
+        ClientCredentials {
+            metadata,
+            peer_certs,
+        }
+    }
+
+    pub(crate) fn from_request<T>(request: &'a Request<T>) -> Self {
+        Self::new(request.metadata(), request.peer_certs())
+    }
 }
 
 impl ProxyServerInner {
@@ -133,22 +440,35 @@ impl ProxyServerInner {
     pub(crate) fn check_authorized(
         &self,
         auth_scheme: AuthScheme,
-        metadata: &MetadataMap,
+        credentials: &ClientCredentials<'_>,
         requested_instance_name: &str,
         required_permissions: Permissions,
     ) -> Result<(), Status> {
+        // Only JWTs carry permissions, so admin operations are never allowed via the other auth
+        // schemes (which only map credentials to an instance name).
+        if matches!(required_permissions, Permissions::Admin)
+            && !matches!(auth_scheme, AuthScheme::Jwt | AuthScheme::DevOnlyNoAuth)
+        {
+            auth::record_auth_failure(auth_scheme, "insufficient_permissions");
+            return Err(Status::permission_denied(
+                "admin operations require a JWT with the admin permission",
+            ));
+        }
+
+        let metadata = credentials.metadata;
         match auth_scheme {
             AuthScheme::Jwt => {
-                let token = auth::get_bearer_token(metadata)?;
+                let token = auth::get_bearer_token(metadata, auth_scheme)?;
                 auth::validate_jwt(
                     token,
                     requested_instance_name,
                     required_permissions,
                     &self.jwk_set,
+                    &self.jwt_permissions_claim,
                 )
             }
             AuthScheme::AuthToken => {
-                let token = auth::get_bearer_token(metadata)?;
+                let token = auth::get_bearer_token(metadata, auth_scheme)?;
                 let token_mapping = self.auth_token_mapping.load();
                 auth::validate_auth_token(
                     AuthToken::new(token),
@@ -156,34 +476,127 @@ impl ProxyServerInner {
                     &token_mapping,
                 )
             }
+            AuthScheme::ApiKey => {
+                let key = auth::get_bearer_token(metadata, auth_scheme)?;
+                let key_mapping = self.api_key_mapping.load();
+                auth::validate_api_key(&key, requested_instance_name, &key_mapping)
+            }
+            AuthScheme::MutualTls => auth::validate_client_certificate(
+                credentials.peer_certs.as_deref().map(Vec::as_slice),
+                requested_instance_name,
+                &self.client_certificate_mapping,
+            ),
             AuthScheme::DevOnlyNoAuth => Ok(()),
         }
     }
 
-    /// Get the backend for the given `instance_name`, or return the catch-all backend if unknown.
+    /// Fail with `FailedPrecondition` if the request modifies `instance_name` (i.e. requires
+    /// `ReadWrite`, or `Admin` e.g. to delete blobs) but the instance is configured as read-only.
+    pub(crate) fn check_writable(
+        &self,
+        instance_name: &str,
+        required_permissions: Permissions,
+    ) -> Result<(), Status> {
+        let modifies = matches!(
+            required_permissions,
+            Permissions::ReadWrite | Permissions::Admin
+        );
+        if modifies && self.backend(instance_name).read_only {
+            return Err(Status::failed_precondition("instance is read-only"));
+        }
+        Ok(())
+    }
+
+    /// Get the backend for the given `instance_name`: an exact per-instance match, else the first
+    /// matching backend rule, else the catch-all backend. Instance aliases are resolved before
+    /// looking up the backend.
     pub(crate) fn backend<'a>(&'a self, instance_name: &'_ str) -> &'a Backend {
-        self.instance_backends
-            .get(instance_name)
+        let instance_name = self.backend_instance_name(instance_name);
+        if let Some(backend) = self.instance_backends.get(instance_name) {
+            return backend;
+        }
+        self.instance_backend_rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(instance_name))
+            .map(|(_, backend)| backend)
             .unwrap_or(&self.catchall_backend)
     }
+
+    /// Mark a request to `instance_name` as in-flight until the returned permit is dropped, or
+    /// fail with `ResourceExhausted` if the instance is already at its in-flight limit.
+    pub(crate) fn acquire_instance_permit(
+        &self,
+        instance_name: &str,
+    ) -> Result<InstancePermit, Status> {
+        self.instance_limiter.acquire(instance_name)
+    }
+
+    /// Get the instance name to send to backends for the given external `instance_name`.
+    pub(crate) fn backend_instance_name<'a>(&'a self, instance_name: &'a str) -> &'a str {
+        self.instance_aliases
+            .get(instance_name)
+            .map(String::as_str)
+            .unwrap_or(instance_name)
+    }
+
+    /// Rewrite a name of the form `{instance_name}/...` (e.g. an operation, bot session or
+    /// ByteStream resource name) so that it refers to the backend instance name.
+    pub(crate) fn to_backend_name(&self, name: &str) -> String {
+        match name.split_once('/') {
+            Some((instance_name, rest)) => {
+                format!("{}/{rest}", self.backend_instance_name(instance_name))
+            }
+            None => self.backend_instance_name(name).to_owned(),
+        }
+    }
+
+    /// Rewrite a name of the form `{instance_name}/...` returned by a backend so that it refers to
+    /// the `external_instance_name` used by the client.
+    pub(crate) fn to_external_name(&self, external_instance_name: &str, name: String) -> String {
+        let backend_instance_name = self.backend_instance_name(external_instance_name);
+        if backend_instance_name == external_instance_name {
+            return name;
+        }
+        match name.split_once('/') {
+            Some((instance_name, rest)) if instance_name == backend_instance_name => {
+                format!("{external_instance_name}/{rest}")
+            }
+            _ => name,
+        }
+    }
 }
 
 impl ProxyServer {
-    pub async fn new(
-        backend_configs: HashMap<String, BackendConfig>,
-        per_instance_configs: HashMap<InstanceName, InstanceConfig>,
-        catchall_instance_config: InstanceConfig,
-        jwk_set: JWKSet,
-        auth_token_mapping: HashMap<AuthToken, AuthTokenEntry>,
-        timeouts: BackendTimeoutsConfig,
-    ) -> Result<ProxyServer, String> {
-        // Verify that all InstanceConfigs refers only to known backends.
-        Self::validate_instance_config(&backend_configs, &catchall_instance_config)?;
-        for instance_config in per_instance_configs.values() {
-            Self::validate_instance_config(&backend_configs, instance_config)?;
-        }
+    pub async fn new(config: ProxyServerConfig) -> Result<ProxyServer, String> {
+        Self::check(&config)?;
+        let ProxyServerConfig {
+            backends: backend_configs,
+            per_instance_backends: per_instance_configs,
+            instance_backend_rules,
+            default_backends: catchall_instance_config,
+            jwk_set,
+            jwt_permissions_claim,
+            auth_token_mapping,
+            timeouts,
+            instance_aliases,
+            instance_limits,
+            capabilities_cache_ttl,
+            find_missing_blobs_cache,
+            client_certificate_mapping,
+            dev_mode,
+        } = config;
+        let instance_backend_patterns = Self::compile_backend_routing(
+            &backend_configs,
+            &per_instance_configs,
+            &instance_backend_rules,
+            &catchall_instance_config,
+        )?;
 
         // Convert the backends into Tonic channels.
+        let backend_compressions = backend_configs
+            .iter()
+            .map(|(name, config)| (name.clone(), config.compression))
+            .collect::<HashMap<_, _>>();
         let (backend_names, backend_configs): (Vec<_>, Vec<_>) =
             backend_configs.into_iter().unzip();
         let backends_fut = backend_configs
@@ -210,7 +623,16 @@ impl ProxyServer {
         let backends = backends
             .into_iter()
             .filter_map(|(name, endpoint_result)| match endpoint_result {
-                Ok(endpoint) => Some((name, endpoint)),
+                Ok(channel) => {
+                    let compression = backend_compressions[&name];
+                    Some((
+                        name,
+                        BackendChannel {
+                            channel,
+                            compression,
+                        },
+                    ))
+                }
                 Err(_) => None,
             })
             .collect::<HashMap<_, _>>();
@@ -226,18 +648,81 @@ impl ProxyServer {
                 ))
             })
             .collect::<Result<HashMap<_, _>, String>>()?;
+        let instance_backend_rules = instance_backend_patterns
+            .into_iter()
+            .zip(instance_backend_rules)
+            .map(|(pattern, rule)| {
+                Ok((pattern, Self::construct_backend(&backends, rule.backends)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
 
         Ok(ProxyServer {
             inner: Arc::new(ProxyServerInner {
                 instance_backends,
+                instance_backend_rules,
                 catchall_backend,
                 jwk_set,
+                jwt_permissions_claim,
                 auth_token_mapping: ArcSwap::from(Arc::new(auth_token_mapping)),
+                api_key_mapping: ArcSwap::default(),
+                client_certificate_mapping,
                 timeouts,
+                instance_aliases,
+                instance_limiter: Arc::new(InstanceLimiter::new(instance_limits)?),
+                capabilities_cache_ttl,
+                find_missing_blobs_cache: FindMissingBlobsCache::new(&find_missing_blobs_cache),
+                dev_mode,
             }),
         })
     }
 
+    /// Check `config` as `ProxyServer::new` does, without connecting to any backends: that every
+    /// backend is valid, that every instance config and rule refers only to known backends, and
+    /// that the instance limits are valid.
+    pub fn check(config: &ProxyServerConfig) -> Result<(), String> {
+        for (name, backend_config) in &config.backends {
+            backend_config
+                .validate()
+                .map_err(|err| format!("Invalid backend {name}: {err}"))?;
+        }
+        Self::compile_backend_routing(
+            &config.backends,
+            &config.per_instance_backends,
+            &config.instance_backend_rules,
+            &config.default_backends,
+        )?;
+        InstanceLimiter::new(config.instance_limits.clone())?;
+        Ok(())
+    }
+
+    /// Verify that all InstanceConfigs refer only to known backends, and compile the patterns of
+    /// the instance backend rules.
+    fn compile_backend_routing(
+        backend_configs: &HashMap<String, BackendConfig>,
+        per_instance_configs: &HashMap<InstanceName, InstanceConfig>,
+        instance_backend_rules: &[InstanceBackendRule],
+        catchall_instance_config: &InstanceConfig,
+    ) -> Result<Vec<Regex>, String> {
+        Self::validate_instance_config(backend_configs, catchall_instance_config)?;
+        for instance_config in per_instance_configs.values() {
+            Self::validate_instance_config(backend_configs, instance_config)?;
+        }
+        for rule in instance_backend_rules {
+            Self::validate_instance_config(backend_configs, &rule.backends)?;
+        }
+        instance_backend_rules
+            .iter()
+            .map(|rule| {
+                Regex::new(&format!("^(?:{})$", rule.pattern)).map_err(|err| {
+                    format!(
+                        "Invalid instance backend rule pattern `{}`: {err}",
+                        rule.pattern
+                    )
+                })
+            })
+            .collect()
+    }
+
     fn validate_instance_config(
         backend_configs: &HashMap<String, BackendConfig>,
         instance_config: &InstanceConfig,
@@ -249,6 +734,7 @@ impl ProxyServer {
         ]
         .into_iter()
         .flatten()
+        .chain(instance_config.fallback_backends.iter().cloned())
         .filter(|name| !backend_configs.contains_key(name))
         .collect::<Vec<_>>();
         if !unknown_backend_names.is_empty() {
@@ -261,53 +747,57 @@ impl ProxyServer {
     }
 
     fn construct_backend(
-        backends: &HashMap<String, LoadBalancedChannel>,
+        backends: &HashMap<String, BackendChannel>,
         instance_config: InstanceConfig,
     ) -> Result<Backend, String> {
+        let backend = |name: &String| {
+            backends
+                .get(name)
+                .ok_or_else(|| format!("Unknown backend: {name}"))
+        };
+        let cas = backend(&instance_config.cas)?;
+        let execution = instance_config
+            .execution
+            .as_ref()
+            .and_then(|name| backends.get(name));
         Ok(Backend {
             // CAS/AC-specific services
-            cas: ContentAddressableStorageClient::new(
-                backends
-                    .get(&instance_config.cas)
-                    .cloned()
-                    .ok_or_else(|| format!("Unknown backend: {}", &instance_config.cas))?,
-            ),
-            action_cache: ActionCacheClient::new(
-                backends
-                    .get(&instance_config.action_cache)
-                    .cloned()
-                    .ok_or_else(|| format!("Unknown backend: {}", &instance_config.action_cache))?,
-            ),
-            bytestream: ByteStreamClient::new(
-                backends
-                    .get(&instance_config.cas)
-                    .cloned()
-                    .ok_or_else(|| format!("Unknown backend: {}", &instance_config.cas))?,
-            ),
-            cas_capabilities: CapabilitiesClient::new(
-                backends
-                    .get(&instance_config.cas)
-                    .cloned()
-                    .ok_or_else(|| format!("Unknown backend: {}", &instance_config.cas))?,
+            cas: backend_client!(ContentAddressableStorageClient, cas),
+            action_cache: backend_client!(
+                ActionCacheClient,
+                backend(&instance_config.action_cache)?
             ),
+            bytestream: backend_client!(ByteStreamClient, cas),
+            cas_capabilities: backend_client!(CapabilitiesClient, cas),
+            storage_admin: backend_client!(StorageAdminClient, cas),
 
             // Execution services (optional)
-            execution: instance_config
-                .execution
-                .as_ref()
-                .and_then(|name| backends.get(name).cloned().map(ExecutionClient::new)),
-            operations: instance_config
-                .execution
-                .as_ref()
-                .and_then(|name| backends.get(name).cloned().map(OperationsClient::new)),
-            bots: instance_config
-                .execution
-                .as_ref()
-                .and_then(|name| backends.get(name).cloned().map(BotsClient::new)),
-            _execution_capabilities: instance_config
-                .execution
-                .as_ref()
-                .and_then(|name| backends.get(name).cloned().map(CapabilitiesClient::new)),
+            execution: execution.map(|backend| backend_client!(ExecutionClient, backend)),
+            operations: execution.map(|backend| backend_client!(OperationsClient, backend)),
+            bots: execution.map(|backend| backend_client!(BotsClient, backend)),
+            _execution_capabilities: execution
+                .map(|backend| backend_client!(CapabilitiesClient, backend)),
+
+            capabilities_cache: CapabilitiesCache::default(),
+            capabilities_filter: CapabilitiesFilter::new(
+                instance_config.allowed_digest_functions.as_deref(),
+                instance_config.allowed_compressors.as_deref(),
+            )?,
+
+            fallbacks: instance_config
+                .fallback_backends
+                .iter()
+                .map(|name| {
+                    let fallback = backend(name)?;
+                    Ok(FallbackBackend {
+                        name: name.clone(),
+                        cas: backend_client!(ContentAddressableStorageClient, fallback),
+                        bytestream: backend_client!(ByteStreamClient, fallback),
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+
+            read_only: instance_config.read_only,
         })
     }
 
@@ -315,6 +805,53 @@ impl ProxyServer {
         self.inner.auth_token_mapping.swap(Arc::new(mapping));
     }
 
+    /// The auth token mapping which is currently in use.
+    pub fn auth_token_mapping(&self) -> Arc<HashMap<AuthToken, AuthTokenEntry>> {
+        self.inner.auth_token_mapping.load_full()
+    }
+
+    pub fn swap_api_key_mapping(&self, mapping: HashMap<ApiKeyHash, AuthTokenEntry>) {
+        self.inner.api_key_mapping.swap(Arc::new(mapping));
+    }
+
+    /// Serve the listener described by `listen_config` on `incoming`, once it has been validated.
+    pub async fn serve_listener<I, IO, IE, F>(
+        self,
+        listen_config: ListenAddressConfig,
+        incoming: I,
+        shutdown_signal: F,
+        grpc_config: Option<GrpcConfig>,
+        in_flight_requests_counter: InFlightRequestsCounter,
+    ) -> Result<(), String>
+    where
+        I: Stream<Item = Result<IO, IE>>,
+        IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+        IE: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+        F: Future<Output = ()>,
+    {
+        listen_config.validate(self.inner.dev_mode)?;
+        let auth_scheme = listen_config
+            .auth_scheme
+            .expect("validated listeners have an auth_scheme");
+        let tls_config = listen_config
+            .tls
+            .as_ref()
+            .map(|tls| tls.to_server_tls_config())
+            .transpose()?;
+        self.serve_with_incoming_shutdown(
+            incoming,
+            shutdown_signal,
+            auth_scheme,
+            listen_config.allowed_service_names.into_iter().collect(),
+            grpc_config,
+            tls_config,
+            in_flight_requests_counter,
+        )
+        .await
+        .map_err(|err| format!("Failed to serve {}: {err}", listen_config.addr))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn serve_with_incoming_shutdown<I, IO, IE, F>(
         self,
         incoming: I,
@@ -322,6 +859,7 @@ impl ProxyServer {
         auth_scheme: AuthScheme,
         allowed_service_names: HashSet<String>,
         grpc_config: Option<GrpcConfig>,
+        tls_config: Option<ServerTlsConfig>,
         in_flight_requests_counter: InFlightRequestsCounter,
     ) -> Result<(), tonic::transport::Error>
     where
@@ -330,11 +868,17 @@ impl ProxyServer {
         IE: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
         F: Future<Output = ()>,
     {
+        let grpc_config = grpc_config.unwrap_or_default();
+        let max_decoding_message_size = grpc_config.max_decoding_message_size();
+        let max_encoding_message_size = grpc_config.max_encoding_message_size();
+
         let cas_server = if allowed_service_names.contains(cas_service::CasService::SERVICE_NAME) {
             let cas_service = cas_service::CasService::new(self.inner.clone(), auth_scheme);
-            Some(GrpcMetrics::new(ContentAddressableStorageServer::new(
-                cas_service,
-            )))
+            Some(GrpcMetrics::new(
+                ContentAddressableStorageServer::new(cas_service)
+                    .max_decoding_message_size(max_decoding_message_size)
+                    .max_encoding_message_size(max_encoding_message_size),
+            ))
         } else {
             None
         };
@@ -344,7 +888,11 @@ impl ProxyServer {
         {
             let bytestream_service =
                 byte_stream_service::ByteStreamService::new(self.inner.clone(), auth_scheme);
-            Some(GrpcMetrics::new(ByteStreamServer::new(bytestream_service)))
+            Some(GrpcMetrics::new(
+                ByteStreamServer::new(bytestream_service)
+                    .max_decoding_message_size(max_decoding_message_size)
+                    .max_encoding_message_size(max_encoding_message_size),
+            ))
         } else {
             None
         };
@@ -354,9 +902,11 @@ impl ProxyServer {
         {
             let action_cache_service =
                 action_cache_service::ActionCacheService::new(self.inner.clone(), auth_scheme);
-            Some(GrpcMetrics::new(ActionCacheServer::new(
-                action_cache_service,
-            )))
+            Some(GrpcMetrics::new(
+                ActionCacheServer::new(action_cache_service)
+                    .max_decoding_message_size(max_decoding_message_size)
+                    .max_encoding_message_size(max_encoding_message_size),
+            ))
         } else {
             None
         };
@@ -366,9 +916,11 @@ impl ProxyServer {
         {
             let capabilities_service =
                 capabilities_service::CapabilitiesService::new(self.inner.clone(), auth_scheme);
-            Some(GrpcMetrics::new(CapabilitiesServer::new(
-                capabilities_service,
-            )))
+            Some(GrpcMetrics::new(
+                CapabilitiesServer::new(capabilities_service)
+                    .max_decoding_message_size(max_decoding_message_size)
+                    .max_encoding_message_size(max_encoding_message_size),
+            ))
         } else {
             None
         };
@@ -377,7 +929,11 @@ impl ProxyServer {
             if allowed_service_names.contains(execution_service::ExecutionService::SERVICE_NAME) {
                 let execution_service =
                     execution_service::ExecutionService::new(self.inner.clone(), auth_scheme);
-                Some(GrpcMetrics::new(ExecutionServer::new(execution_service)))
+                Some(GrpcMetrics::new(
+                    ExecutionServer::new(execution_service)
+                        .max_decoding_message_size(max_decoding_message_size)
+                        .max_encoding_message_size(max_encoding_message_size),
+                ))
             } else {
                 None
             };
@@ -387,7 +943,11 @@ impl ProxyServer {
         {
             let operations_service =
                 operations_service::OperationsService::new(self.inner.clone(), auth_scheme);
-            Some(GrpcMetrics::new(OperationsServer::new(operations_service)))
+            Some(GrpcMetrics::new(
+                OperationsServer::new(operations_service)
+                    .max_decoding_message_size(max_decoding_message_size)
+                    .max_encoding_message_size(max_encoding_message_size),
+            ))
         } else {
             None
         };
@@ -395,14 +955,32 @@ impl ProxyServer {
         let bots_server = if allowed_service_names.contains(bots_service::BotsService::SERVICE_NAME)
         {
             let bots_service = bots_service::BotsService::new(self.inner.clone(), auth_scheme);
-            Some(GrpcMetrics::new(BotsServer::new(bots_service)))
+            Some(GrpcMetrics::new(
+                BotsServer::new(bots_service)
+                    .max_decoding_message_size(max_decoding_message_size)
+                    .max_encoding_message_size(max_encoding_message_size),
+            ))
         } else {
             None
         };
 
-        let mut server = Server::builder();
-        if let Some(c) = grpc_config.as_ref() {
-            server = c.apply_to_server(server);
+        let storage_admin_server = if allowed_service_names
+            .contains(storage_admin_service::StorageAdminService::SERVICE_NAME)
+        {
+            let storage_admin_service =
+                storage_admin_service::StorageAdminService::new(self.inner.clone(), auth_scheme);
+            Some(GrpcMetrics::new(
+                StorageAdminServer::new(storage_admin_service)
+                    .max_decoding_message_size(max_decoding_message_size)
+                    .max_encoding_message_size(max_encoding_message_size),
+            ))
+        } else {
+            None
+        };
+
+        let mut server = grpc_config.apply_to_server(Server::builder());
+        if let Some(tls_config) = tls_config {
+            server = server.tls_config(tls_config)?;
         }
 
         let in_flight_requests_layer = InFlightRequestsLayer::new(in_flight_requests_counter);
@@ -411,6 +989,7 @@ impl ProxyServer {
         let layer = ServiceBuilder::new()
             .layer(in_flight_requests_layer)
             .layer(auth_header_sensitive_layer)
+            .layer(RequestIdLayer)
             .into_inner();
 
         let router = server
@@ -421,7 +1000,8 @@ impl ProxyServer {
             .add_optional_service(capabilities_server)
             .add_optional_service(execution_server)
             .add_optional_service(operations_server)
-            .add_optional_service(bots_server);
+            .add_optional_service(bots_server)
+            .add_optional_service(storage_admin_server);
 
         router
             .serve_with_incoming_shutdown(incoming, shutdown_signal)
@@ -429,6 +1009,77 @@ impl ProxyServer {
     }
 }
 
+/// Upper bound on how much earlier than the client's deadline a backend call is abandoned. This
+/// leaves the proxy time to report `DeadlineExceeded` to the client before the client gives up.
+const MAX_DEADLINE_MARGIN: Duration = Duration::from_millis(100);
+
+/// The deadline for backend calls made on behalf of a single client request.
+///
+/// The deadline is derived from the `grpc-timeout` sent by the client, shortened slightly so that
+/// a backend call is abandoned before the client gives up on the proxy. Requests sent to the
+/// backend carry the time remaining as their own `grpc-timeout` so that the backend can stop
+/// work as well. They also carry the `x-request-id` of the client request.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct BackendDeadline(Option<Instant>);
+
+impl BackendDeadline {
+    pub(crate) fn from_metadata(metadata: &MetadataMap) -> Self {
+        let timeout = grpc_timeout(metadata);
+        BackendDeadline(
+            timeout
+                .map(|timeout| Instant::now() + timeout - (timeout / 10).min(MAX_DEADLINE_MARGIN)),
+        )
+    }
+
+    /// Time remaining until the deadline, or `None` if the client did not set a deadline.
+    fn remaining(&self) -> Option<Duration> {
+        self.0
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Wrap a message for a backend into a `Request` which carries the remaining time as its
+    /// `grpc-timeout`, and the client's request ID.
+    pub(crate) fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        self.set_timeout(&mut request);
+        request
+    }
+
+    /// Set the `grpc-timeout` of a request being forwarded to a backend to the remaining time,
+    /// and attach the client's request ID.
+    pub(crate) fn set_timeout<T>(&self, request: &mut Request<T>) {
+        if let Some(remaining) = self.remaining() {
+            request.set_timeout(remaining);
+        }
+        set_request_id(request);
+    }
+
+    /// Run a backend call, abandoning it with `DeadlineExceeded` if the deadline passes first.
+    ///
+    /// Tonic servers report an expired `grpc-timeout` as `Cancelled`, so a `Cancelled` error
+    /// received once the deadline has passed is reported as `DeadlineExceeded` as well, so that it
+    /// is not retried. Cancellations before the deadline are passed through unchanged.
+    async fn run<T>(
+        &self,
+        f: impl Future<Output = Result<Response<T>, Status>>,
+    ) -> Result<Response<T>, Status> {
+        let deadline_exceeded =
+            || Status::deadline_exceeded("backend call exceeded the request deadline");
+        match self.0 {
+            Some(deadline) => match tokio::time::timeout_at(deadline.into(), f).await {
+                Ok(Err(status))
+                    if status.code() == Code::Cancelled && Instant::now() >= deadline =>
+                {
+                    Err(deadline_exceeded())
+                }
+                Ok(result) => result,
+                Err(_) => Err(deadline_exceeded()),
+            },
+            None => f.await,
+        }
+    }
+}
+
 /// Drop guard to ensure that the "request finished" metrics is still incremented even if
 /// when the client closes its connection.
 struct ClientCancelGuard {
@@ -449,6 +1100,13 @@ impl ClientCancelGuard {
     }
 
     pub fn complete_for_code(&mut self, code: Code) {
+        self.complete(code, "backend");
+    }
+
+    /// Record the call as finished with `code`. `completed_by` distinguishes calls which the
+    /// backend completed (with any status, including `Cancelled`) from calls which were abandoned
+    /// because the client went away first.
+    fn complete(&mut self, code: Code, completed_by: &'static str) {
         self.completed = true;
 
         metrics::histogram!(
@@ -463,6 +1121,7 @@ impl ClientCancelGuard {
             "grpc_service" => self.service_name,
             "grpc_method" => self.service_method,
             "grpc_code" => convert_status_code(code as u16),
+            "completed_by" => completed_by,
         );
     }
 }
@@ -470,7 +1129,7 @@ impl ClientCancelGuard {
 impl Drop for ClientCancelGuard {
     fn drop(&mut self) {
         if !self.completed {
-            self.complete_for_code(Code::Cancelled);
+            self.complete(Code::Cancelled, "client_disconnect");
         }
     }
 }
@@ -490,11 +1149,13 @@ pub(crate) async fn do_one_client_call<T>(
     // running this function is dropped early.
     let mut cancel_guard = ClientCancelGuard::new(service_name, service_method);
 
+    let request_id = current_request_id();
     let result = f
         .instrument(tracing::info_span!(
             "gRPC client call",
             grpc_service = service_name,
             grpc_method = service_method,
+            request_id = request_id.as_deref(),
             opentelemetry = true
         ))
         .await;
@@ -502,20 +1163,18 @@ pub(crate) async fn do_one_client_call<T>(
     let code = result.as_ref().err().map(|s| s.code()).unwrap_or(Code::Ok);
     cancel_guard.complete_for_code(code);
 
-    if let Code::Internal
-    | Code::Cancelled
-    | Code::Unavailable
-    | Code::Unknown
-    | Code::ResourceExhausted
-    | Code::Aborted
-    | Code::Unimplemented = code
+    if let (
+        Code::Internal
+        | Code::Cancelled
+        | Code::Unavailable
+        | Code::Unknown
+        | Code::ResourceExhausted
+        | Code::Aborted
+        | Code::Unimplemented,
+        Err(status),
+    ) = (code, &result)
     {
-        log::error!(
-            "unexpected backend error for {}.{}: {:?}",
-            service_name,
-            service_method,
-            result.as_ref().err(),
-        );
+        log_backend_error(service_name, service_method, request_id.as_deref(), status);
     }
 
     result
@@ -536,6 +1195,7 @@ fn is_retryable(status: &Status) -> bool {
 #[inline]
 pub(crate) async fn client_call<T, C, F, Fut>(
     client: C,
+    deadline: BackendDeadline,
     f: F,
     service_name: &'static str,
     service_method: &'static str,
@@ -546,7 +1206,7 @@ where
     Fut: Future<Output = Result<Response<T>, Status>>,
 {
     let client2 = client.clone();
-    let result_fut = f(client2);
+    let result_fut = deadline.run(f(client2));
     let mut result = do_one_client_call(result_fut, service_name, service_method).await;
     if let Err(ref status) = result {
         if is_retryable(status) {
@@ -556,9 +1216,42 @@ where
                 "grpc_method" => service_method.to_owned(),
                 "grpc_code" => convert_status_code(status.code() as u16),
             );
-            let result_fut = f(client);
+            let result_fut = deadline.run(f(client));
             result = do_one_client_call(result_fut, service_name, service_method).await;
         }
     }
     result
 }
+
+/// Like `client_call`, but if the call to `client` still fails with `Unavailable` after retrying,
+/// make the call against each of the `fallbacks` in turn. Must only be used for idempotent
+/// calls.
+pub(crate) async fn client_call_with_failover<T, C, F, Fut>(
+    client: C,
+    fallbacks: Vec<(String, C)>,
+    deadline: BackendDeadline,
+    f: F,
+    service_name: &'static str,
+    service_method: &'static str,
+) -> Result<Response<T>, Status>
+where
+    C: Clone,
+    F: Fn(C) -> Fut,
+    Fut: Future<Output = Result<Response<T>, Status>>,
+{
+    let mut result = client_call(client, deadline, &f, service_name, service_method).await;
+    for (backend_name, fallback) in fallbacks {
+        match &result {
+            Err(status) if status.code() == Code::Unavailable => {}
+            _ => break,
+        }
+        metrics::increment_counter!(
+            "toolchain_proxy_failover_total",
+            "grpc_service" => service_name.to_owned(),
+            "grpc_method" => service_method.to_owned(),
+            "backend" => backend_name,
+        );
+        result = client_call(fallback, deadline, &f, service_name, service_method).await;
+    }
+    result
+}
@@ -4,18 +4,20 @@
 use std::sync::Arc;
 
 use ginepro::LoadBalancedChannel;
-use grpc_util::auth::{AuthScheme, Permissions};
+use grpc_util::auth::AuthScheme;
 use protos::google::longrunning::{
     operations_client::OperationsClient, operations_server::Operations, CancelOperationRequest,
     DeleteOperationRequest, GetOperationRequest, ListOperationsRequest, ListOperationsResponse,
     Operation, WaitOperationRequest,
 };
-use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
 use execution_util::instance_name_from_operation_name;
 
-use crate::server::{client_call, ProxyServerInner};
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, BackendDeadline, ClientCredentials, InstancePermit, ProxyServerInner,
+};
 
 pub(crate) struct OperationsService {
     inner: Arc<ProxyServerInner>,
@@ -31,18 +33,22 @@ impl OperationsService {
 
     fn get_client(
         &self,
-        metadata: &MetadataMap,
+        credentials: &ClientCredentials<'_>,
         operation_name: &str,
-    ) -> Result<OperationsClient<LoadBalancedChannel>, Status> {
+        method_name: &str,
+    ) -> Result<(OperationsClient<LoadBalancedChannel>, InstancePermit), Status> {
         let requested_instance_name = instance_name_from_operation_name(&operation_name.to_owned())
             .map_err(Status::invalid_argument)?;
 
         self.inner.check_authorized(
             self.auth_scheme,
-            metadata,
+            credentials,
             &requested_instance_name,
-            Permissions::Execute,
+            required_permissions(Self::SERVICE_NAME, method_name),
         )?;
+        let permit = self
+            .inner
+            .acquire_instance_permit(&requested_instance_name)?;
 
         self.inner
             .backend(&requested_instance_name)
@@ -52,6 +58,7 @@ impl OperationsService {
             .ok_or_else(|| {
                 Status::invalid_argument(format!("No such instance: {requested_instance_name}"))
             })
+            .map(|client| (client, permit))
     }
 }
 
@@ -62,18 +69,35 @@ impl Operations for OperationsService {
         &self,
         request: Request<ListOperationsRequest>,
     ) -> Result<Response<ListOperationsResponse>, Status> {
-        let client = self.get_client(request.metadata(), &request.get_ref().name)?;
-        let request = request.into_inner();
-        client_call(
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &request.get_ref().name,
+            "ListOperations",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        let instance_name =
+            instance_name_from_operation_name(&request.name).map_err(Status::invalid_argument)?;
+        request.name = self.inner.to_backend_name(&request.name);
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.list_operations(request).await }
             },
             Self::SERVICE_NAME,
             "ListOperations",
         )
-        .await
+        .await?;
+        Ok(response.map(|mut response| {
+            for operation in &mut response.operations {
+                operation.name = self
+                    .inner
+                    .to_external_name(&instance_name, std::mem::take(&mut operation.name));
+            }
+            response
+        }))
     }
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
@@ -81,18 +105,31 @@ impl Operations for OperationsService {
         &self,
         request: Request<GetOperationRequest>,
     ) -> Result<Response<Operation>, Status> {
-        let client = self.get_client(request.metadata(), &request.get_ref().name)?;
-        let request = request.into_inner();
-        client_call(
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &request.get_ref().name,
+            "GetOperation",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        let instance_name =
+            instance_name_from_operation_name(&request.name).map_err(Status::invalid_argument)?;
+        request.name = self.inner.to_backend_name(&request.name);
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.get_operation(request).await }
             },
             Self::SERVICE_NAME,
             "GetOperation",
         )
-        .await
+        .await?;
+        Ok(response.map(|mut operation| {
+            operation.name = self.inner.to_external_name(&instance_name, operation.name);
+            operation
+        }))
     }
 
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
@@ -100,12 +137,19 @@ impl Operations for OperationsService {
         &self,
         request: Request<DeleteOperationRequest>,
     ) -> Result<Response<()>, Status> {
-        let client = self.get_client(request.metadata(), &request.get_ref().name)?;
-        let request = request.into_inner();
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &request.get_ref().name,
+            "DeleteOperation",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.name = self.inner.to_backend_name(&request.name);
         client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.delete_operation(request).await }
             },
             Self::SERVICE_NAME,
@@ -119,12 +163,19 @@ impl Operations for OperationsService {
         &self,
         request: Request<CancelOperationRequest>,
     ) -> Result<Response<()>, Status> {
-        let client = self.get_client(request.metadata(), &request.get_ref().name)?;
-        let request = request.into_inner();
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &request.get_ref().name,
+            "CancelOperation",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.name = self.inner.to_backend_name(&request.name);
         client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.cancel_operation(request).await }
             },
             Self::SERVICE_NAME,
@@ -138,17 +189,30 @@ impl Operations for OperationsService {
         &self,
         request: Request<WaitOperationRequest>,
     ) -> Result<Response<Operation>, Status> {
-        let client = self.get_client(request.metadata(), &request.get_ref().name)?;
-        let request = request.into_inner();
-        client_call(
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &request.get_ref().name,
+            "WaitOperation",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        let instance_name =
+            instance_name_from_operation_name(&request.name).map_err(Status::invalid_argument)?;
+        request.name = self.inner.to_backend_name(&request.name);
+        let response = client_call(
             client,
+            deadline,
             move |mut client| {
-                let request = request.clone();
+                let request = deadline.request(request.clone());
                 async move { client.wait_operation(request).await }
             },
             Self::SERVICE_NAME,
             "WaitOperation",
         )
-        .await
+        .await?;
+        Ok(response.map(|mut operation| {
+            operation.name = self.inner.to_external_name(&instance_name, operation.name);
+            operation
+        }))
     }
 }
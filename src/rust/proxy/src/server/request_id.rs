@@ -0,0 +1,94 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::HeaderValue;
+use tonic::metadata::MetadataValue;
+use tonic::Request;
+use tower::{Layer, Service};
+
+/// Metadata key which carries the ID of a client request through the proxy and its backends.
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    /// The request ID of the client request being handled by the current task.
+    static REQUEST_ID: HeaderValue;
+}
+
+/// The request ID of the client request being handled by the current task, if any.
+pub(crate) fn current_request_id() -> Option<String> {
+    REQUEST_ID
+        .try_with(|request_id| request_id.to_str().ok().map(str::to_owned))
+        .ok()
+        .flatten()
+}
+
+/// Attach the request ID of the current client request to a request being sent to a backend.
+pub(crate) fn set_request_id<T>(request: &mut Request<T>) {
+    let request_id = REQUEST_ID
+        .try_with(|request_id| MetadataValue::try_from(request_id.as_bytes()))
+        .ok()
+        .and_then(Result::ok);
+    if let Some(request_id) = request_id {
+        request.metadata_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+}
+
+/// Layer which assigns each client request an ID: either the `x-request-id` sent by the client,
+/// or a newly generated UUID. The ID is available to handlers via `current_request_id` and is
+/// returned to the client in the response metadata.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RequestIdService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .filter(|value| !value.is_empty() && value.to_str().is_ok())
+            .cloned()
+            .unwrap_or_else(|| {
+                HeaderValue::from_str(&uuid::Uuid::new_v4().to_string())
+                    .expect("UUID is a valid header value")
+            });
+        request
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, request_id.clone());
+
+        let response_fut = REQUEST_ID.scope(request_id.clone(), self.inner.call(request));
+        async move {
+            let mut response = response_fut.await?;
+            response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+            Ok(response)
+        }
+        .boxed()
+    }
+}
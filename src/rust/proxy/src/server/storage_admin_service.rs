@@ -0,0 +1,92 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::sync::Arc;
+
+use ginepro::LoadBalancedChannel;
+use grpc_util::auth::AuthScheme;
+use protos::toolchain::storage::admin::v1::{
+    storage_admin_client::StorageAdminClient, storage_admin_server::StorageAdmin,
+    DeleteBlobsRequest, DeleteBlobsResponse,
+};
+use tonic::{Request, Response, Status};
+
+use crate::server::method_permissions::required_permissions;
+use crate::server::{
+    client_call, BackendDeadline, ClientCredentials, InstancePermit, ProxyServerInner,
+};
+
+pub(crate) struct StorageAdminService {
+    inner: Arc<ProxyServerInner>,
+    auth_scheme: AuthScheme,
+}
+
+impl StorageAdminService {
+    pub const SERVICE_NAME: &'static str = "toolchain.storage.admin.v1.StorageAdmin";
+
+    pub(crate) fn new(inner: Arc<ProxyServerInner>, auth_scheme: AuthScheme) -> Self {
+        StorageAdminService { inner, auth_scheme }
+    }
+
+    fn get_client(
+        &self,
+        credentials: &ClientCredentials<'_>,
+        requested_instance_name: &str,
+        method_name: &str,
+    ) -> Result<(StorageAdminClient<LoadBalancedChannel>, InstancePermit), Status> {
+        let required_permissions = required_permissions(Self::SERVICE_NAME, method_name);
+        self.inner.check_authorized(
+            self.auth_scheme,
+            credentials,
+            requested_instance_name,
+            required_permissions,
+        )?;
+        self.inner
+            .check_writable(requested_instance_name, required_permissions)?;
+        let permit = self
+            .inner
+            .acquire_instance_permit(requested_instance_name)?;
+        Ok((
+            self.inner
+                .backend(requested_instance_name)
+                .storage_admin
+                .clone(),
+            permit,
+        ))
+    }
+}
+
+#[tonic::async_trait]
+impl StorageAdmin for StorageAdminService {
+    #[tracing::instrument(skip_all, fields(opentelemetry = true))]
+    async fn delete_blobs(
+        &self,
+        request: Request<DeleteBlobsRequest>,
+    ) -> Result<Response<DeleteBlobsResponse>, Status> {
+        let (client, _permit) = self.get_client(
+            &ClientCredentials::from_request(&request),
+            &request.get_ref().instance_name,
+            "DeleteBlobs",
+        )?;
+        let deadline = BackendDeadline::from_metadata(request.metadata());
+        let mut request = request.into_inner();
+        request.instance_name = self
+            .inner
+            .backend_instance_name(&request.instance_name)
+            .to_owned();
+        if let Some(cache) = &self.inner.find_missing_blobs_cache {
+            cache.invalidate(&request.instance_name, request.blob_digests.iter());
+        }
+        client_call(
+            client,
+            deadline,
+            move |mut client| {
+                let request = deadline.request(request.clone());
+                async move { client.delete_blobs(request).await }
+            },
+            Self::SERVICE_NAME,
+            "DeleteBlobs",
+        )
+        .await
+    }
+}
@@ -6,31 +6,34 @@ use std::convert::TryInto;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Once};
 use std::time::Duration;
 
 use bytes::Bytes;
-use futures::{FutureExt, StreamExt};
+use futures::{future, FutureExt, StreamExt};
 use grpc_util::auth::{
     generate_jwt, make_jwk_set, make_jwk_set_multiple, AuthScheme, AuthToken, AuthTokenEntry,
     Permissions, TEST_INSTANCE_NAME, TEST_KEY_ID_1, TEST_KEY_ID_2, TEST_SECRET_1, TEST_SECRET_2,
 };
-use grpc_util::backend::BackendConfig;
+use grpc_util::backend::{BackendCompression, BackendConfig};
 use grpc_util::hyper::AddrIncomingWithStream;
+use grpc_util::infra::DEFAULT_MAX_MESSAGE_SIZE;
 use hyper::server::conn::AddrIncoming;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
 use protos::build::bazel::remote::execution::v2 as remoting_protos;
 use protos::build::bazel::remote::execution::v2::{
     action_cache_client::ActionCacheClient, action_cache_server::ActionCache,
     action_cache_server::ActionCacheServer, capabilities_client::CapabilitiesClient,
-    capabilities_server::Capabilities, capabilities_server::CapabilitiesServer,
+    capabilities_server::Capabilities, capabilities_server::CapabilitiesServer, compressor,
     content_addressable_storage_client::ContentAddressableStorageClient,
     content_addressable_storage_server::ContentAddressableStorage,
-    content_addressable_storage_server::ContentAddressableStorageServer,
+    content_addressable_storage_server::ContentAddressableStorageServer, digest_function,
     execution_client::ExecutionClient, execution_server::Execution,
     execution_server::ExecutionServer, ActionResult, BatchReadBlobsRequest, BatchReadBlobsResponse,
-    BatchUpdateBlobsRequest, BatchUpdateBlobsResponse, ExecuteRequest, FindMissingBlobsRequest,
-    FindMissingBlobsResponse, GetActionResultRequest, GetCapabilitiesRequest, GetTreeRequest,
-    GetTreeResponse, ServerCapabilities, UpdateActionResultRequest, WaitExecutionRequest,
+    BatchUpdateBlobsRequest, BatchUpdateBlobsResponse, CacheCapabilities, ExecuteRequest,
+    FindMissingBlobsRequest, FindMissingBlobsResponse, GetActionResultRequest,
+    GetCapabilitiesRequest, GetTreeRequest, GetTreeResponse, ServerCapabilities,
+    UpdateActionResultRequest, WaitExecutionRequest,
 };
 use protos::google::bytestream::{
     byte_stream_client::ByteStreamClient, byte_stream_server::ByteStream,
@@ -43,17 +46,30 @@ use protos::google::longrunning::{
     GetOperationRequest, ListOperationsRequest, ListOperationsResponse, Operation,
     WaitOperationRequest,
 };
+use protos::toolchain::storage::admin::v1::{
+    storage_admin_client::StorageAdminClient,
+    storage_admin_server::{StorageAdmin, StorageAdminServer},
+    DeleteBlobsRequest, DeleteBlobsResponse,
+};
 use tokio::task::JoinHandle;
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::MetadataMap;
 use tonic::transport::{Endpoint, Server};
 use tonic::{Code, Request, Response, Status, Streaming};
 use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
 
-use super::ProxyServer;
+use super::request_id::REQUEST_ID_HEADER;
+use super::{
+    do_one_client_call, BackendDeadline, ClientCredentials, ProxyServer, ProxyServerConfig,
+};
 use crate::server::{
     action_cache_service, byte_stream_service, capabilities_service, cas_service,
-    execution_service, operations_service,
+    execution_service, operations_service, storage_admin_service,
+};
+use crate::{
+    BackendTimeoutsConfig, FindMissingBlobsCacheConfig, InstanceBackendRule, InstanceConfig,
+    InstanceLimitsConfig, ListenAddressConfig, ListenerTlsConfig,
 };
-use crate::{BackendTimeoutsConfig, InstanceConfig};
 
 fn all_service_names() -> HashSet<String> {
     HashSet::from([
@@ -63,6 +79,7 @@ fn all_service_names() -> HashSet<String> {
         capabilities_service::CapabilitiesService::SERVICE_NAME.to_owned(),
         execution_service::ExecutionService::SERVICE_NAME.to_owned(),
         operations_service::OperationsService::SERVICE_NAME.to_owned(),
+        storage_admin_service::StorageAdminService::SERVICE_NAME.to_owned(),
     ])
 }
 
@@ -287,7 +304,17 @@ impl Capabilities for MockCapabilitiesService {
             return Err(Status::unavailable("unavailable"));
         }
 
-        Ok(Response::new(ServerCapabilities::default()))
+        Ok(Response::new(ServerCapabilities {
+            cache_capabilities: Some(CacheCapabilities {
+                digest_function: vec![
+                    digest_function::Value::Sha256 as i32,
+                    digest_function::Value::Sha1 as i32,
+                ],
+                supported_compressors: vec![compressor::Value::Zstd as i32],
+                ..CacheCapabilities::default()
+            }),
+            ..ServerCapabilities::default()
+        }))
     }
 }
 
@@ -421,6 +448,7 @@ async fn test_basic_proxy_functionality() {
         BackendConfig {
             address: format!("{mock_server_addr}"),
             connections: 1,
+            ..BackendConfig::default()
         },
     );
 
@@ -428,16 +456,15 @@ async fn test_basic_proxy_functionality() {
         execution: Some("backend".to_owned()),
         cas: "backend".to_owned(),
         action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
     };
 
-    let proxy_server = ProxyServer::new(
-        backend_addresses,
-        HashMap::new(),
-        instance_config,
-        make_jwk_set(),
-        HashMap::new(),
-        BackendTimeoutsConfig::default(),
-    )
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
     .await
     .unwrap();
     let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
@@ -447,6 +474,7 @@ async fn test_basic_proxy_functionality() {
         AuthScheme::Jwt,
         all_service_names(),
         None,
+        None,
         InFlightRequestsCounter::new(),
     );
     let _proxy_server_handle = tokio::spawn(async move {
@@ -618,7 +646,17 @@ async fn test_basic_proxy_functionality() {
 }
 
 #[tokio::test]
-async fn auth_token_scheme() {
+async fn enforces_per_method_permissions() {
+    fn add_token<T>(request: &mut Request<T>, permissions: Permissions) {
+        let token = generate_jwt(
+            &permissions.to_string(),
+            TEST_INSTANCE_NAME,
+            TEST_KEY_ID_1,
+            TEST_SECRET_1,
+        );
+        add_auth_token_to_request(request, &token);
+    }
+
     let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
         setup_mock_server(false, false);
 
@@ -632,6 +670,7 @@ async fn auth_token_scheme() {
         BackendConfig {
             address: format!("{mock_server_addr}"),
             connections: 1,
+            ..BackendConfig::default()
         },
     );
 
@@ -639,44 +678,25 @@ async fn auth_token_scheme() {
         execution: Some("backend".to_owned()),
         cas: "backend".to_owned(),
         action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
     };
 
-    let proxy_server = ProxyServer::new(
-        backend_addresses,
-        HashMap::new(),
-        instance_config,
-        make_jwk_set(),
-        HashMap::from([
-            (
-                AuthToken::new("active-token".to_owned()),
-                AuthTokenEntry {
-                    id: "abc".to_owned(),
-                    is_active: true,
-                    instance_name: TEST_INSTANCE_NAME.to_owned(),
-                    customer_slug: "customer-slug".to_owned(),
-                },
-            ),
-            (
-                AuthToken::new("inactive-token".to_owned()),
-                AuthTokenEntry {
-                    id: "xyz".to_owned(),
-                    is_active: false,
-                    instance_name: TEST_INSTANCE_NAME.to_owned(),
-                    customer_slug: "customer-slug".to_owned(),
-                },
-            ),
-        ]),
-        BackendTimeoutsConfig::default(),
-    )
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
     .await
     .unwrap();
     let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
     let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
         proxy_server_incoming,
         shutdown_receiver.map(drop),
-        AuthScheme::AuthToken,
+        AuthScheme::Jwt,
         all_service_names(),
         None,
+        None,
         InFlightRequestsCounter::new(),
     );
     let _proxy_server_handle = tokio::spawn(async move {
@@ -684,37 +704,59 @@ async fn auth_token_scheme() {
     });
 
     tokio::time::sleep(Duration::from_secs(2)).await;
-    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint)
+
+    let mut cas_client = ContentAddressableStorageClient::connect(proxy_server_endpoint.clone())
         .await
         .unwrap();
-    let get_capabilities_request = GetCapabilitiesRequest {
+    let mut execution_client = ExecutionClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let find_missing_blobs_request = FindMissingBlobsRequest {
         instance_name: TEST_INSTANCE_NAME.into(),
+        ..FindMissingBlobsRequest::default()
+    };
+    let batch_read_blobs_request = BatchReadBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..BatchReadBlobsRequest::default()
+    };
+    let batch_update_blobs_request = BatchUpdateBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..BatchUpdateBlobsRequest::default()
+    };
+    let execute_request = ExecuteRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..ExecuteRequest::default()
     };
 
-    // Send a request with a valid token.
-    let mut request1 = Request::new(get_capabilities_request.clone());
-    add_auth_token_to_request(&mut request1, "active-token");
-    capabilities_client
-        .get_capabilities(request1)
-        .await
-        .expect("get_capabilities returns capabilities");
-    assert_eq!(1, calls_count.load(Ordering::SeqCst));
+    // A `cache_ro` token may read from the CAS, but not write to it.
+    let mut request = Request::new(find_missing_blobs_request);
+    add_token(&mut request, Permissions::Read);
+    let err = cas_client.find_missing_blobs(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unimplemented);
+    let mut request = Request::new(batch_read_blobs_request);
+    add_token(&mut request, Permissions::Read);
+    let err = cas_client.batch_read_blobs(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unimplemented);
+    let mut request = Request::new(batch_update_blobs_request.clone());
+    add_token(&mut request, Permissions::Read);
+    let err = cas_client.batch_update_blobs(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert_eq!(2, calls_count.load(Ordering::SeqCst));
 
-    // Send a request with an invalid token.
-    let mut request2 = Request::new(get_capabilities_request);
-    add_auth_token_to_request(&mut request2, "inactive-token");
-    let error = capabilities_client
-        .get_capabilities(request2)
-        .await
-        .expect_err("error return");
-    assert_eq!(1, calls_count.load(Ordering::SeqCst));
-    assert_eq!(Code::Unauthenticated, error.code())
+    // A `cache_rw` token may write to the CAS, but not execute.
+    let mut request = Request::new(batch_update_blobs_request);
+    add_token(&mut request, Permissions::ReadWrite);
+    let err = cas_client.batch_update_blobs(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unimplemented);
+    let mut request = Request::new(execute_request);
+    add_token(&mut request, Permissions::ReadWrite);
+    let err = execution_client.execute(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert_eq!(3, calls_count.load(Ordering::SeqCst));
 }
 
-/// Tests whether the proxy will accept requests with each configured key in cases where there
-/// are multiple keys configured.
 #[tokio::test]
-async fn handles_auth_with_multiple_key_configured() {
+async fn rejects_writes_to_read_only_instances() {
     let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
         setup_mock_server(false, false);
 
@@ -728,23 +770,24 @@ async fn handles_auth_with_multiple_key_configured() {
         BackendConfig {
             address: format!("{mock_server_addr}"),
             connections: 1,
+            ..BackendConfig::default()
         },
     );
 
     let instance_config = InstanceConfig {
-        execution: Some("backend".to_owned()),
+        execution: None,
         cas: "backend".to_owned(),
         action_cache: "backend".to_owned(),
+        read_only: true,
+        ..InstanceConfig::default()
     };
 
-    let proxy_server = ProxyServer::new(
-        backend_addresses,
-        HashMap::new(),
-        instance_config,
-        make_jwk_set_multiple(),
-        HashMap::new(),
-        BackendTimeoutsConfig::default(),
-    )
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
     .await
     .unwrap();
     let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
@@ -754,6 +797,7 @@ async fn handles_auth_with_multiple_key_configured() {
         AuthScheme::Jwt,
         all_service_names(),
         None,
+        None,
         InFlightRequestsCounter::new(),
     );
     let _proxy_server_handle = tokio::spawn(async move {
@@ -761,46 +805,81 @@ async fn handles_auth_with_multiple_key_configured() {
     });
 
     tokio::time::sleep(Duration::from_secs(2)).await;
-    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint)
+
+    let mut cas_client = ContentAddressableStorageClient::connect(proxy_server_endpoint.clone())
         .await
         .unwrap();
-    let get_capabilities_request = GetCapabilitiesRequest {
+    let mut byte_stream_client = ByteStreamClient::connect(proxy_server_endpoint.clone())
+        .await
+        .unwrap();
+    let mut action_cache_client = ActionCacheClient::connect(proxy_server_endpoint.clone())
+        .await
+        .unwrap();
+    let mut storage_admin_client = StorageAdminClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+
+    let mut request = Request::new(BatchUpdateBlobsRequest {
         instance_name: TEST_INSTANCE_NAME.into(),
-    };
+        ..BatchUpdateBlobsRequest::default()
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let err = cas_client.batch_update_blobs(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+
+    let mut request = Request::new(futures::stream::iter(vec![WriteRequest {
+        resource_name: format!("{TEST_INSTANCE_NAME}/uploads/foo/bar"),
+        data: Bytes::from_static(&[0; 16]),
+        finish_write: true,
+        ..WriteRequest::default()
+    }]));
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let err = byte_stream_client.write(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
 
-    // Send a request with the first key.
-    let mut request1 = Request::new(get_capabilities_request.clone());
-    add_jwt_to_request(&mut request1, TEST_KEY_ID_1, TEST_SECRET_1);
-    capabilities_client
-        .get_capabilities(request1)
+    let mut request = Request::new(UpdateActionResultRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..UpdateActionResultRequest::default()
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let err = action_cache_client
+        .update_action_result(request)
         .await
-        .expect("get_capabilities returns capabilities");
-    assert_eq!(1, calls_count.load(Ordering::SeqCst));
+        .unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
 
-    // Send a request with the second key.
-    let mut request2 = Request::new(get_capabilities_request.clone());
-    add_jwt_to_request(&mut request2, TEST_KEY_ID_2, TEST_SECRET_2);
-    capabilities_client
-        .get_capabilities(request2)
+    let mut request = Request::new(DeleteBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        blob_digests: vec![],
+    });
+    let token = generate_jwt(
+        &Permissions::Admin.to_string(),
+        TEST_INSTANCE_NAME,
+        TEST_KEY_ID_1,
+        TEST_SECRET_1,
+    );
+    add_auth_token_to_request(&mut request, &token);
+    let err = storage_admin_client
+        .delete_blobs(request)
         .await
-        .expect("get_capabilities returns capabilities");
-    assert_eq!(2, calls_count.load(Ordering::SeqCst));
+        .unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+    assert_eq!(0, calls_count.load(Ordering::SeqCst));
 
-    // Send a request using an existing key but without a matching key ID. This should fail.
-    let mut request3 = Request::new(get_capabilities_request);
-    add_jwt_to_request(&mut request3, "xyzzy", TEST_SECRET_1);
-    let error = capabilities_client
-        .get_capabilities(request3)
-        .await
-        .expect_err("error return");
-    assert_eq!(2, calls_count.load(Ordering::SeqCst));
-    assert_eq!(Code::Unauthenticated, error.code())
+    // Reads are still passed through to the backend.
+    let mut request = Request::new(FindMissingBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..FindMissingBlobsRequest::default()
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let err = cas_client.find_missing_blobs(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unimplemented);
+    assert_eq!(1, calls_count.load(Ordering::SeqCst));
 }
 
-/// Tests whether the proxy will retry certain requests to the backend.
 #[tokio::test]
-async fn retries_backend_requests_which_are_retryable() {
-    let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, is_unavailable) =
+async fn auth_token_scheme() {
+    let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
         setup_mock_server(false, false);
 
     let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
@@ -813,6 +892,7 @@ async fn retries_backend_requests_which_are_retryable() {
         BackendConfig {
             address: format!("{mock_server_addr}"),
             connections: 1,
+            ..BackendConfig::default()
         },
     );
 
@@ -820,25 +900,45 @@ async fn retries_backend_requests_which_are_retryable() {
         execution: Some("backend".to_owned()),
         cas: "backend".to_owned(),
         action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
     };
 
-    let proxy_server = ProxyServer::new(
-        backend_addresses,
-        HashMap::new(),
-        instance_config,
-        make_jwk_set_multiple(),
-        HashMap::new(),
-        BackendTimeoutsConfig::default(),
-    )
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        auth_token_mapping: HashMap::from([
+            (
+                AuthToken::new("active-token".to_owned()),
+                AuthTokenEntry {
+                    id: "abc".to_owned(),
+                    is_active: true,
+                    instance_name: TEST_INSTANCE_NAME.to_owned(),
+                    customer_slug: "customer-slug".to_owned(),
+                },
+            ),
+            (
+                AuthToken::new("inactive-token".to_owned()),
+                AuthTokenEntry {
+                    id: "xyz".to_owned(),
+                    is_active: false,
+                    instance_name: TEST_INSTANCE_NAME.to_owned(),
+                    customer_slug: "customer-slug".to_owned(),
+                },
+            ),
+        ]),
+        ..ProxyServerConfig::default()
+    })
     .await
     .unwrap();
     let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
     let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
         proxy_server_incoming,
         shutdown_receiver.map(drop),
-        AuthScheme::Jwt,
+        AuthScheme::AuthToken,
         all_service_names(),
         None,
+        None,
         InFlightRequestsCounter::new(),
     );
     let _proxy_server_handle = tokio::spawn(async move {
@@ -846,32 +946,53 @@ async fn retries_backend_requests_which_are_retryable() {
     });
 
     tokio::time::sleep(Duration::from_secs(2)).await;
-    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint)
+    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint.clone())
         .await
         .unwrap();
     let get_capabilities_request = GetCapabilitiesRequest {
         instance_name: TEST_INSTANCE_NAME.into(),
     };
 
-    // Send a request with the first key.
-    let mut request = Request::new(get_capabilities_request.clone());
-    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
-    is_unavailable.store(true, Ordering::SeqCst);
-    let err = capabilities_client
-        .get_capabilities(request)
+    // Send a request with a valid token.
+    let mut request1 = Request::new(get_capabilities_request.clone());
+    add_auth_token_to_request(&mut request1, "active-token");
+    capabilities_client
+        .get_capabilities(request1)
         .await
-        .unwrap_err();
-    assert_eq!(err.code(), Code::Unavailable);
+        .expect("get_capabilities returns capabilities");
+    assert_eq!(1, calls_count.load(Ordering::SeqCst));
 
-    // Expect two calls (not one) to the backend since it will be retried once.
-    assert_eq!(2, calls_count.load(Ordering::SeqCst));
+    // Send a request with an invalid token.
+    let mut request2 = Request::new(get_capabilities_request);
+    add_auth_token_to_request(&mut request2, "inactive-token");
+    let error = capabilities_client
+        .get_capabilities(request2)
+        .await
+        .expect_err("error return");
+    assert_eq!(1, calls_count.load(Ordering::SeqCst));
+    assert_eq!(Code::Unauthenticated, error.code());
+
+    // Auth tokens do not carry permissions, so they never grant admin access.
+    let mut storage_admin_client = StorageAdminClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let mut request3 = Request::new(DeleteBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        blob_digests: vec![],
+    });
+    add_auth_token_to_request(&mut request3, "active-token");
+    let error = storage_admin_client
+        .delete_blobs(request3)
+        .await
+        .expect_err("error return");
+    assert_eq!(1, calls_count.load(Ordering::SeqCst));
+    assert_eq!(Code::PermissionDenied, error.code())
 }
 
-/// Tests whether the proxy will respect backend timeouts.
 #[tokio::test]
-async fn times_out_backend_requests() {
+async fn restricts_advertised_digest_functions() {
     let (_calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
-        setup_mock_server(true, false);
+        setup_mock_server(false, false);
 
     let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
         .try_into()
@@ -883,25 +1004,25 @@ async fn times_out_backend_requests() {
         BackendConfig {
             address: format!("{mock_server_addr}"),
             connections: 1,
+            ..BackendConfig::default()
         },
     );
 
     let instance_config = InstanceConfig {
-        execution: Some("backend".to_owned()),
+        execution: None,
         cas: "backend".to_owned(),
         action_cache: "backend".to_owned(),
+        allowed_digest_functions: Some(vec!["SHA256".to_owned()]),
+        ..InstanceConfig::default()
     };
 
-    let proxy_server = ProxyServer::new(
-        backend_addresses,
-        HashMap::new(),
-        instance_config,
-        make_jwk_set(),
-        HashMap::new(),
-        BackendTimeoutsConfig {
-            get_action_result: Some(Duration::from_micros(100)),
-        },
-    )
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        capabilities_cache_ttl: Duration::from_secs(60),
+        ..ProxyServerConfig::default()
+    })
     .await
     .unwrap();
     let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
@@ -911,89 +1032,1773 @@ async fn times_out_backend_requests() {
         AuthScheme::Jwt,
         all_service_names(),
         None,
+        None,
         InFlightRequestsCounter::new(),
     );
     let _proxy_server_handle = tokio::spawn(async move {
         let _ = proxy_server_fut.await;
     });
 
-    let mut action_cache_client = ActionCacheClient::connect(proxy_server_endpoint.clone())
+    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint)
         .await
         .unwrap();
-    let get_action_result_request = GetActionResultRequest {
-        instance_name: TEST_INSTANCE_NAME.into(),
-        ..Default::default()
-    };
-    let mut request = Request::new(get_action_result_request);
-    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
-    let err = action_cache_client
-        .get_action_result(request)
-        .await
-        .expect_err("");
-    assert_eq!(err.code(), Code::Unavailable);
-    assert!(err.message().contains("storage backend timeout"));
+    // The second request is served from the capabilities cache, which must be filtered too.
+    for _ in 0..2 {
+        let mut request = Request::new(GetCapabilitiesRequest {
+            instance_name: TEST_INSTANCE_NAME.into(),
+        });
+        add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+        let cache_capabilities = capabilities_client
+            .get_capabilities(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .cache_capabilities
+            .unwrap();
+        assert_eq!(
+            cache_capabilities.digest_function,
+            vec![digest_function::Value::Sha256 as i32]
+        );
+        // Compressors are not restricted, so are passed through.
+        assert_eq!(
+            cache_capabilities.supported_compressors,
+            vec![compressor::Value::Zstd as i32]
+        );
+    }
 }
 
-/// Tests that the "early exit for existing blob" case is successful.
 #[tokio::test]
-async fn early_exit_for_existing_blob() {
-    let (
-        calls_count,
-        mock_server_addr,
-        _mock_server_handle,
-        proxy_server_incoming,
-        _is_unavailable,
-    ) = setup_mock_server(false, true);
-
-    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
-        .try_into()
-        .unwrap();
-
+async fn rejects_unknown_allowed_digest_function() {
     let mut backend_addresses = HashMap::new();
     backend_addresses.insert(
         "backend".to_owned(),
         BackendConfig {
-            address: format!("{mock_server_addr}"),
+            address: "127.0.0.1:1".to_owned(),
             connections: 1,
+            ..BackendConfig::default()
         },
     );
 
     let instance_config = InstanceConfig {
-        execution: Some("backend".to_owned()),
+        execution: None,
         cas: "backend".to_owned(),
         action_cache: "backend".to_owned(),
+        allowed_digest_functions: Some(vec!["SHA257".to_owned()]),
+        ..InstanceConfig::default()
     };
 
-    let proxy_server = ProxyServer::new(
-        backend_addresses,
-        HashMap::new(),
-        instance_config,
-        make_jwk_set_multiple(),
-        HashMap::new(),
-        BackendTimeoutsConfig::default(),
-    )
-    .await
-    .unwrap();
-    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
-    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
-        proxy_server_incoming,
-        shutdown_receiver.map(drop),
-        AuthScheme::Jwt,
-        all_service_names(),
-        None,
-        InFlightRequestsCounter::new(),
+    let result = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
+    .await;
+    assert_eq!(
+        result.err().unwrap(),
+        "Unknown digest function: SHA257".to_owned()
     );
-    let _proxy_server_handle = tokio::spawn(async move {
-        let _ = proxy_server_fut.await;
-    });
-    tokio::time::sleep(Duration::from_secs(2)).await;
+}
 
-    let mut byte_stream_client = ByteStreamClient::connect(proxy_server_endpoint.clone())
-        .await
-        .unwrap();
-    // NB: We declare the blob to be of a certain size, even though there are many more WriteRequests
-    // in the stream.
-    let declared_size = 32;
+#[test]
+fn refuses_no_auth_listeners_outside_dev_mode() {
+    let listen_config = |auth_scheme| ListenAddressConfig {
+        addr: "127.0.0.1:8980".to_owned(),
+        auth_scheme,
+        ..ListenAddressConfig::default()
+    };
+
+    let no_auth = listen_config(Some(AuthScheme::DevOnlyNoAuth));
+    let err = no_auth.validate_allowing_no_auth(false).unwrap_err();
+    assert!(err.contains("dev_only_no_auth"), "{err}");
+    assert!(no_auth.validate_allowing_no_auth(true).is_ok());
+
+    assert!(listen_config(Some(AuthScheme::Jwt))
+        .validate_allowing_no_auth(false)
+        .is_ok());
+    assert!(listen_config(None).validate_allowing_no_auth(true).is_err());
+}
+
+#[test]
+fn requires_client_ca_for_mutual_tls_listeners() {
+    let listen_config = |tls| ListenAddressConfig {
+        addr: "127.0.0.1:8980".to_owned(),
+        auth_scheme: Some(AuthScheme::MutualTls),
+        tls,
+        ..ListenAddressConfig::default()
+    };
+    let tls_config = |client_ca_path| ListenerTlsConfig {
+        cert_path: "server.pem".to_owned(),
+        key_path: "server.key".to_owned(),
+        client_ca_path,
+    };
+
+    let err = listen_config(None)
+        .validate_allowing_no_auth(false)
+        .unwrap_err();
+    assert!(err.contains("client_ca_path"), "{err}");
+    let err = listen_config(Some(tls_config(None)))
+        .validate_allowing_no_auth(false)
+        .unwrap_err();
+    assert!(err.contains("client_ca_path"), "{err}");
+    assert!(listen_config(Some(tls_config(Some("ca.pem".to_owned()))))
+        .validate_allowing_no_auth(false)
+        .is_ok());
+}
+
+#[tokio::test]
+async fn serve_listener_validates_the_listener() {
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let listen_config = ListenAddressConfig {
+        addr: "127.0.0.1:0".to_owned(),
+        auth_scheme: Some(AuthScheme::MutualTls),
+        ..ListenAddressConfig::default()
+    };
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let incoming = AddrIncoming::bind(&addr).unwrap();
+
+    let err = proxy_server
+        .serve_listener(
+            listen_config,
+            AddrIncomingWithStream(incoming),
+            future::ready(()),
+            None,
+            InFlightRequestsCounter::new(),
+        )
+        .await
+        .unwrap_err();
+    assert!(err.contains("client_ca_path"), "{err}");
+}
+
+#[tokio::test]
+async fn reports_backend_cancellations_as_deadline_exceeded_only_after_the_deadline() {
+    let cancelled = || future::ready(Err::<Response<()>, _>(Status::cancelled("cancelled")));
+
+    // A backend which cancels a call well before the deadline was cancelled for another reason.
+    let deadline = BackendDeadline(Some(std::time::Instant::now() + Duration::from_secs(60)));
+    let status = deadline.run(cancelled()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Cancelled);
+
+    // Whereas a call cancelled once the deadline has passed exceeded it.
+    let deadline = BackendDeadline(Some(std::time::Instant::now()));
+    let status = deadline.run(cancelled()).await.unwrap_err();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+
+    // Without a deadline, cancellations are passed through.
+    let status = BackendDeadline(None).run(cancelled()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Cancelled);
+}
+
+/// Install a recorder which records metrics for each thread separately, so that tests which
+/// inspect metrics do not interfere with each other. The recorder is global, so it is only
+/// installed once per test process.
+pub(crate) fn install_per_thread_metrics_recorder() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| DebuggingRecorder::per_thread().install().unwrap());
+}
+
+#[tokio::test]
+async fn distinguishes_client_disconnects_from_backend_cancellations() {
+    install_per_thread_metrics_recorder();
+
+    // A call which the client abandons before the backend responds.
+    let abandoned = do_one_client_call(
+        future::pending::<Result<Response<()>, Status>>(),
+        "Service",
+        "Abandoned",
+    );
+    assert!(abandoned.now_or_never().is_none());
+
+    // A call which the backend completes with `Cancelled`.
+    do_one_client_call(
+        future::ready(Err::<Response<()>, _>(Status::cancelled("cancelled"))),
+        "Service",
+        "Cancelled",
+    )
+    .await
+    .unwrap_err();
+
+    let handled = Snapshotter::current_thread_snapshot()
+        .unwrap()
+        .into_vec()
+        .into_iter()
+        .filter(|(key, _, _, _)| key.key().name() == "grpc_client_handled_total")
+        .map(|(key, _, _, value)| {
+            let label = |name: &str| {
+                key.key()
+                    .labels()
+                    .find(|label| label.key() == name)
+                    .unwrap()
+                    .value()
+                    .to_owned()
+            };
+            let DebugValue::Counter(count) = value else {
+                panic!("Expected a counter");
+            };
+            (
+                (
+                    label("grpc_method"),
+                    label("grpc_code"),
+                    label("completed_by"),
+                ),
+                count,
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    assert_eq!(
+        handled,
+        HashMap::from([
+            (
+                (
+                    "Abandoned".to_owned(),
+                    "Canceled".to_owned(),
+                    "client_disconnect".to_owned()
+                ),
+                1
+            ),
+            (
+                (
+                    "Cancelled".to_owned(),
+                    "Canceled".to_owned(),
+                    "backend".to_owned()
+                ),
+                1
+            ),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn records_auth_failure_metrics() {
+    install_per_thread_metrics_recorder();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: "127.0.0.1:1".to_owned(),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+    let instance_config = InstanceConfig {
+        execution: None,
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        auth_token_mapping: HashMap::from([(
+            AuthToken::new("inactive-token".to_owned()),
+            AuthTokenEntry {
+                id: "xyz".to_owned(),
+                is_active: false,
+                instance_name: TEST_INSTANCE_NAME.to_owned(),
+                customer_slug: "customer-slug".to_owned(),
+            },
+        )]),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+
+    let check_authorized = |token: Option<&str>| {
+        let mut metadata = MetadataMap::new();
+        if let Some(token) = token {
+            metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        }
+        proxy_server.inner.check_authorized(
+            AuthScheme::AuthToken,
+            &ClientCredentials::new(&metadata, None),
+            TEST_INSTANCE_NAME,
+            Permissions::Read,
+        )
+    };
+    check_authorized(None).unwrap_err();
+    check_authorized(Some("unknown-token")).unwrap_err();
+    check_authorized(Some("unknown-token")).unwrap_err();
+    check_authorized(Some("inactive-token")).unwrap_err();
+
+    let failures = Snapshotter::current_thread_snapshot()
+        .unwrap()
+        .into_vec()
+        .into_iter()
+        .filter(|(key, _, _, _)| key.key().name() == "toolchain_auth_failures_total")
+        .map(|(key, _, _, value)| {
+            let label = |name: &str| {
+                key.key()
+                    .labels()
+                    .find(|label| label.key() == name)
+                    .unwrap()
+                    .value()
+                    .to_owned()
+            };
+            let DebugValue::Counter(count) = value else {
+                panic!("Expected a counter");
+            };
+            ((label("scheme"), label("reason")), count)
+        })
+        .collect::<HashMap<_, _>>();
+    assert_eq!(
+        failures,
+        HashMap::from([
+            (("auth_token".to_owned(), "missing_header".to_owned()), 1),
+            (("auth_token".to_owned(), "unknown_token".to_owned()), 2),
+            (("auth_token".to_owned(), "inactive".to_owned()), 1),
+        ])
+    );
+}
+
+/// Tests whether the proxy will accept requests with each configured key in cases where there
+/// are multiple keys configured.
+#[tokio::test]
+async fn handles_auth_with_multiple_key_configured() {
+    let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
+        setup_mock_server(false, false);
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: Some("backend".to_owned()),
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set_multiple(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let get_capabilities_request = GetCapabilitiesRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+    };
+
+    // Send a request with the first key.
+    let mut request1 = Request::new(get_capabilities_request.clone());
+    add_jwt_to_request(&mut request1, TEST_KEY_ID_1, TEST_SECRET_1);
+    capabilities_client
+        .get_capabilities(request1)
+        .await
+        .expect("get_capabilities returns capabilities");
+    assert_eq!(1, calls_count.load(Ordering::SeqCst));
+
+    // Send a request with the second key.
+    let mut request2 = Request::new(get_capabilities_request.clone());
+    add_jwt_to_request(&mut request2, TEST_KEY_ID_2, TEST_SECRET_2);
+    capabilities_client
+        .get_capabilities(request2)
+        .await
+        .expect("get_capabilities returns capabilities");
+    assert_eq!(2, calls_count.load(Ordering::SeqCst));
+
+    // Send a request using an existing key but without a matching key ID. This should fail.
+    let mut request3 = Request::new(get_capabilities_request);
+    add_jwt_to_request(&mut request3, "xyzzy", TEST_SECRET_1);
+    let error = capabilities_client
+        .get_capabilities(request3)
+        .await
+        .expect_err("error return");
+    assert_eq!(2, calls_count.load(Ordering::SeqCst));
+    assert_eq!(Code::Unauthenticated, error.code())
+}
+
+/// Tests whether the proxy will retry certain requests to the backend.
+#[tokio::test]
+async fn retries_backend_requests_which_are_retryable() {
+    let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, is_unavailable) =
+        setup_mock_server(false, false);
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: Some("backend".to_owned()),
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set_multiple(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let get_capabilities_request = GetCapabilitiesRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+    };
+
+    // Send a request with the first key.
+    let mut request = Request::new(get_capabilities_request.clone());
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    is_unavailable.store(true, Ordering::SeqCst);
+    let err = capabilities_client
+        .get_capabilities(request)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unavailable);
+
+    // Expect two calls (not one) to the backend since it will be retried once.
+    assert_eq!(2, calls_count.load(Ordering::SeqCst));
+}
+
+/// Tests whether the proxy serves repeated `GetCapabilities` calls from its cache.
+#[tokio::test]
+async fn caches_capabilities_responses() {
+    let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
+        setup_mock_server(false, false);
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: Some("backend".to_owned()),
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set_multiple(),
+        capabilities_cache_ttl: Duration::from_secs(60),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let mut capabilities_client = CapabilitiesClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let get_capabilities_request = GetCapabilitiesRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+    };
+
+    for _ in 0..2 {
+        let mut request = Request::new(get_capabilities_request.clone());
+        add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+        capabilities_client
+            .get_capabilities(request)
+            .await
+            .expect("get_capabilities returns capabilities");
+    }
+
+    // Only the first call should have reached the backend.
+    assert_eq!(1, calls_count.load(Ordering::SeqCst));
+}
+
+/// Tests whether the proxy will respect backend timeouts.
+#[tokio::test]
+async fn times_out_backend_requests() {
+    let (_calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
+        setup_mock_server(true, false);
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: Some("backend".to_owned()),
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        timeouts: BackendTimeoutsConfig {
+            get_action_result: Some(Duration::from_micros(100)),
+        },
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let mut action_cache_client = ActionCacheClient::connect(proxy_server_endpoint.clone())
+        .await
+        .unwrap();
+    let get_action_result_request = GetActionResultRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..Default::default()
+    };
+    let mut request = Request::new(get_action_result_request);
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let err = action_cache_client
+        .get_action_result(request)
+        .await
+        .expect_err("");
+    assert_eq!(err.code(), Code::Unavailable);
+    assert!(err.message().contains("storage backend timeout"));
+}
+
+/// Tests that the proxy abandons a backend call once the client's gRPC deadline is near.
+#[tokio::test]
+async fn propagates_client_deadline_to_backend_calls() {
+    let (calls_count, mock_server_addr, _mock_server_handle, proxy_server_incoming, _) =
+        setup_mock_server(true, false);
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: Some("backend".to_owned()),
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    // No static timeout is configured, so only the client's deadline bounds the backend call.
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let mut action_cache_client = ActionCacheClient::connect(proxy_server_endpoint.clone())
+        .await
+        .unwrap();
+    let get_action_result_request = GetActionResultRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..Default::default()
+    };
+    let mut request = Request::new(get_action_result_request);
+    request.set_timeout(Duration::from_millis(500));
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let err = action_cache_client
+        .get_action_result(request)
+        .await
+        .expect_err("");
+    assert_eq!(err.code(), Code::DeadlineExceeded);
+
+    // A deadline is not retryable, so the backend only sees a single call.
+    assert_eq!(1, calls_count.load(Ordering::SeqCst));
+}
+
+/// Action Cache backend which records the instance names and request IDs of the requests it
+/// receives.
+#[derive(Clone, Default)]
+struct RecordingActionCacheService {
+    instance_names: Arc<parking_lot::Mutex<Vec<String>>>,
+    request_ids: Arc<parking_lot::Mutex<Vec<Option<String>>>>,
+}
+
+#[tonic::async_trait]
+impl ActionCache for RecordingActionCacheService {
+    async fn get_action_result(
+        &self,
+        request: Request<GetActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        self.request_ids.lock().push(
+            request
+                .metadata()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+        );
+        self.instance_names
+            .lock()
+            .push(request.into_inner().instance_name);
+        Ok(Response::new(ActionResult::default()))
+    }
+
+    async fn update_action_result(
+        &self,
+        _request: Request<UpdateActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+}
+
+/// Tests that aliased instance names are rewritten before forwarding to the backend.
+#[tokio::test]
+async fn rewrites_aliased_instance_names() {
+    let (mock_server_incoming, mock_server_addr) = make_incoming();
+    let (proxy_server_incoming, _) = make_incoming();
+
+    let backend = RecordingActionCacheService::default();
+    let mock_server_fut = Server::builder()
+        .add_service(ActionCacheServer::new(backend.clone()))
+        .serve_with_incoming(mock_server_incoming);
+    let _mock_server_handle = tokio::spawn(async move {
+        let _ = mock_server_fut.await;
+    });
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: None,
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    // The JWT used by the client is only valid for `TEST_INSTANCE_NAME`, so authorization must
+    // be performed against the external name rather than the rewritten one.
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        instance_aliases: HashMap::from([(TEST_INSTANCE_NAME.to_owned(), "new".to_owned())]),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let mut action_cache_client = ActionCacheClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let mut request = Request::new(GetActionResultRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        ..Default::default()
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    action_cache_client
+        .get_action_result(request)
+        .await
+        .unwrap();
+
+    assert_eq!(*backend.instance_names.lock(), vec!["new".to_owned()]);
+}
+
+/// Tests that instances matching a backend rule are routed to its backend, while other instances
+/// fall through to the catch-all backend.
+#[tokio::test]
+async fn routes_instances_by_backend_rule() {
+    let spawn_backend = || {
+        let (mock_server_incoming, mock_server_addr) = make_incoming();
+        let backend = RecordingActionCacheService::default();
+        let mock_server_fut = Server::builder()
+            .add_service(ActionCacheServer::new(backend.clone()))
+            .serve_with_incoming(mock_server_incoming);
+        tokio::spawn(async move {
+            let _ = mock_server_fut.await;
+        });
+        (backend, mock_server_addr)
+    };
+    let (acme_backend, acme_addr) = spawn_backend();
+    let (catchall_backend, catchall_addr) = spawn_backend();
+    let (proxy_server_incoming, _) = make_incoming();
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let backend_addresses = || {
+        HashMap::from([
+            (
+                "acme".to_owned(),
+                BackendConfig {
+                    address: format!("{acme_addr}"),
+                    connections: 1,
+                    ..BackendConfig::default()
+                },
+            ),
+            (
+                "catchall".to_owned(),
+                BackendConfig {
+                    address: format!("{catchall_addr}"),
+                    connections: 1,
+                    ..BackendConfig::default()
+                },
+            ),
+        ])
+    };
+    let instance_config = |backend: &str| InstanceConfig {
+        execution: None,
+        cas: backend.to_owned(),
+        action_cache: backend.to_owned(),
+        ..InstanceConfig::default()
+    };
+    let make_proxy_server = |pattern: &str| {
+        ProxyServer::new(ProxyServerConfig {
+            backends: backend_addresses(),
+            instance_backend_rules: vec![InstanceBackendRule {
+                pattern: pattern.to_owned(),
+                backends: instance_config("acme"),
+            }],
+            default_backends: instance_config("catchall"),
+            jwk_set: make_jwk_set(),
+            ..ProxyServerConfig::default()
+        })
+    };
+
+    // Invalid patterns are rejected at construction.
+    let err = make_proxy_server("acme-(").await.err().unwrap();
+    assert!(err.contains("acme-("), "{err}");
+
+    let proxy_server = make_proxy_server("acme-.*").await.unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::DevOnlyNoAuth,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let mut action_cache_client = ActionCacheClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    // The pattern must match the entire instance name.
+    for instance_name in ["acme-ci-123", "other", "not-acme-ci"] {
+        action_cache_client
+            .get_action_result(GetActionResultRequest {
+                instance_name: instance_name.to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        *acme_backend.instance_names.lock(),
+        vec!["acme-ci-123".to_owned()]
+    );
+    assert_eq!(
+        *catchall_backend.instance_names.lock(),
+        vec!["other".to_owned(), "not-acme-ci".to_owned()]
+    );
+}
+
+#[test]
+fn validates_backend_routing() {
+    let backends = HashMap::from([(
+        "cas".to_owned(),
+        BackendConfig {
+            address: "cas.example.com:8980".to_owned(),
+            ..BackendConfig::default()
+        },
+    )]);
+    let instance_config = |backend: &str| InstanceConfig {
+        cas: backend.to_owned(),
+        action_cache: backend.to_owned(),
+        ..InstanceConfig::default()
+    };
+    let rules = |backend: &str, pattern: &str| {
+        vec![InstanceBackendRule {
+            pattern: pattern.to_owned(),
+            backends: instance_config(backend),
+        }]
+    };
+
+    ProxyServer::check(&ProxyServerConfig {
+        backends: backends.clone(),
+        instance_backend_rules: rules("cas", "acme-.*"),
+        default_backends: instance_config("cas"),
+        ..ProxyServerConfig::default()
+    })
+    .unwrap();
+
+    let err = ProxyServer::check(&ProxyServerConfig {
+        backends: backends.clone(),
+        instance_backend_rules: rules("missing", "acme-.*"),
+        default_backends: instance_config("cas"),
+        ..ProxyServerConfig::default()
+    })
+    .unwrap_err();
+    assert!(err.contains("missing"), "{err}");
+
+    let err = ProxyServer::check(&ProxyServerConfig {
+        backends,
+        instance_backend_rules: rules("cas", "acme-("),
+        default_backends: instance_config("cas"),
+        ..ProxyServerConfig::default()
+    })
+    .unwrap_err();
+    assert!(err.contains("acme-("), "{err}");
+
+    let invalid_backends = HashMap::from([(
+        "cas".to_owned(),
+        BackendConfig {
+            address: "cas.example.com".to_owned(),
+            ..BackendConfig::default()
+        },
+    )]);
+    let err = ProxyServer::check(&ProxyServerConfig {
+        backends: invalid_backends,
+        default_backends: instance_config("cas"),
+        ..ProxyServerConfig::default()
+    })
+    .unwrap_err();
+    assert_eq!(err, "Invalid backend cas: Expected NAME:PORT");
+}
+
+/// Tests that the client's request ID is propagated to the backend and returned to the client,
+/// and that one is generated if the client did not send one.
+#[tokio::test]
+async fn propagates_request_id_to_backend() {
+    let (mock_server_incoming, mock_server_addr) = make_incoming();
+    let (proxy_server_incoming, _) = make_incoming();
+
+    let backend = RecordingActionCacheService::default();
+    let mock_server_fut = Server::builder()
+        .add_service(ActionCacheServer::new(backend.clone()))
+        .serve_with_incoming(mock_server_incoming);
+    let _mock_server_handle = tokio::spawn(async move {
+        let _ = mock_server_fut.await;
+    });
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: None,
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let mut action_cache_client = ActionCacheClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let make_request = || {
+        let mut request = Request::new(GetActionResultRequest {
+            instance_name: TEST_INSTANCE_NAME.into(),
+            ..Default::default()
+        });
+        add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+        request
+    };
+    let response_request_id = |response: &Response<ActionResult>| {
+        response
+            .metadata()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+    };
+
+    let mut request = make_request();
+    request
+        .metadata_mut()
+        .insert(REQUEST_ID_HEADER, "my-request-id".parse().unwrap());
+    let response = action_cache_client
+        .get_action_result(request)
+        .await
+        .unwrap();
+    assert_eq!(
+        response_request_id(&response),
+        Some("my-request-id".to_owned())
+    );
+
+    let response = action_cache_client
+        .get_action_result(make_request())
+        .await
+        .unwrap();
+    let generated_request_id = response_request_id(&response).unwrap();
+    assert!(uuid::Uuid::parse_str(&generated_request_id).is_ok());
+
+    assert_eq!(
+        *backend.request_ids.lock(),
+        vec![Some("my-request-id".to_owned()), Some(generated_request_id)]
+    );
+}
+
+/// CAS backend which records the digests of the `FindMissingBlobs` requests it receives, and
+/// reports the digests in `missing` as missing. `BatchReadBlobs` returns zeroes of the requested
+/// size for each digest.
+#[derive(Clone, Default)]
+struct RecordingCasService {
+    missing: Vec<remoting_protos::Digest>,
+    find_missing_requests: Arc<parking_lot::Mutex<Vec<Vec<remoting_protos::Digest>>>>,
+}
+
+#[tonic::async_trait]
+impl ContentAddressableStorage for RecordingCasService {
+    async fn find_missing_blobs(
+        &self,
+        request: Request<FindMissingBlobsRequest>,
+    ) -> Result<Response<FindMissingBlobsResponse>, Status> {
+        let digests = request.into_inner().blob_digests;
+        let missing_blob_digests = digests
+            .iter()
+            .filter(|digest| self.missing.contains(digest))
+            .cloned()
+            .collect();
+        self.find_missing_requests.lock().push(digests);
+        Ok(Response::new(FindMissingBlobsResponse {
+            missing_blob_digests,
+        }))
+    }
+
+    async fn batch_update_blobs(
+        &self,
+        _request: Request<BatchUpdateBlobsRequest>,
+    ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
+        Ok(Response::new(BatchUpdateBlobsResponse::default()))
+    }
+
+    async fn batch_read_blobs(
+        &self,
+        request: Request<BatchReadBlobsRequest>,
+    ) -> Result<Response<BatchReadBlobsResponse>, Status> {
+        let responses = request
+            .into_inner()
+            .digests
+            .into_iter()
+            .map(
+                |digest| remoting_protos::batch_read_blobs_response::Response {
+                    data: vec![0; digest.size_bytes as usize].into(),
+                    digest: Some(digest),
+                    ..Default::default()
+                },
+            )
+            .collect();
+        Ok(Response::new(BatchReadBlobsResponse { responses }))
+    }
+
+    type GetTreeStream = tonic::codec::Streaming<GetTreeResponse>;
+
+    async fn get_tree(
+        &self,
+        _request: Request<GetTreeRequest>,
+    ) -> Result<Response<Self::GetTreeStream>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+}
+
+/// Reports every requested digest as deleted.
+#[tonic::async_trait]
+impl StorageAdmin for RecordingCasService {
+    async fn delete_blobs(
+        &self,
+        request: Request<DeleteBlobsRequest>,
+    ) -> Result<Response<DeleteBlobsResponse>, Status> {
+        Ok(Response::new(DeleteBlobsResponse {
+            deleted_blob_digests: request.into_inner().blob_digests,
+        }))
+    }
+}
+
+/// Tests that digests recently reported present are not sent to the backend again, and that a
+/// write or delete through the proxy invalidates them.
+#[tokio::test]
+async fn caches_present_digests_for_find_missing_blobs() {
+    let (mock_server_incoming, mock_server_addr) = make_incoming();
+    let (proxy_server_incoming, _) = make_incoming();
+
+    let digest = |hash: &str| remoting_protos::Digest {
+        hash: hash.to_owned(),
+        size_bytes: 1,
+    };
+    let backend = RecordingCasService {
+        missing: vec![digest("c")],
+        ..RecordingCasService::default()
+    };
+    let mock_server_fut = Server::builder()
+        .add_service(ContentAddressableStorageServer::new(backend.clone()))
+        .add_service(StorageAdminServer::new(backend.clone()))
+        .serve_with_incoming(mock_server_incoming);
+    let _mock_server_handle = tokio::spawn(async move {
+        let _ = mock_server_fut.await;
+    });
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: None,
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        find_missing_blobs_cache: FindMissingBlobsCacheConfig {
+            ttl_ms: 60_000,
+            ..FindMissingBlobsCacheConfig::default()
+        },
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let cas_client = ContentAddressableStorageClient::connect(proxy_server_endpoint.clone())
+        .await
+        .unwrap();
+    let find_missing_blobs = |digests: Vec<remoting_protos::Digest>| {
+        let mut request = Request::new(FindMissingBlobsRequest {
+            instance_name: TEST_INSTANCE_NAME.into(),
+            blob_digests: digests,
+        });
+        add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+        let mut cas_client = cas_client.clone();
+        async move {
+            cas_client
+                .find_missing_blobs(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .missing_blob_digests
+        }
+    };
+
+    let all_digests = vec![digest("a"), digest("b"), digest("c")];
+    for _ in 0..2 {
+        assert_eq!(
+            find_missing_blobs(all_digests.clone()).await,
+            vec![digest("c")]
+        );
+    }
+    // The second request only asked the backend about the digest which was missing.
+    assert_eq!(
+        *backend.find_missing_requests.lock(),
+        vec![all_digests.clone(), vec![digest("c")]]
+    );
+
+    // Writing a digest through the proxy invalidates it.
+    let mut request = Request::new(BatchUpdateBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        requests: vec![remoting_protos::batch_update_blobs_request::Request {
+            digest: Some(digest("a")),
+            data: Bytes::from_static(b"a"),
+        }],
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    cas_client
+        .clone()
+        .batch_update_blobs(request)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        find_missing_blobs(all_digests.clone()).await,
+        vec![digest("c")]
+    );
+    assert_eq!(
+        backend.find_missing_requests.lock().last().unwrap(),
+        &vec![digest("a"), digest("c")]
+    );
+
+    // Deleting a digest through the proxy invalidates it.
+    let mut storage_admin_client = StorageAdminClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let mut request = Request::new(DeleteBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        blob_digests: vec![digest("b")],
+    });
+    let token = generate_jwt(
+        &Permissions::Admin.to_string(),
+        TEST_INSTANCE_NAME,
+        TEST_KEY_ID_1,
+        TEST_SECRET_1,
+    );
+    add_auth_token_to_request(&mut request, &token);
+    storage_admin_client.delete_blobs(request).await.unwrap();
+
+    assert_eq!(find_missing_blobs(all_digests).await, vec![digest("c")]);
+    assert_eq!(
+        backend.find_missing_requests.lock().last().unwrap(),
+        &vec![digest("b"), digest("c")]
+    );
+}
+
+/// Tests that `FindMissingBlobs` round-trips through the proxy with each backend compression
+/// setting: `accept_gzip` against a backend which does not support compression, and `gzip`
+/// against one which does.
+#[tokio::test]
+async fn negotiates_backend_compression() {
+    for (compression, backend_supports_gzip) in [
+        (BackendCompression::AcceptGzip, false),
+        (BackendCompression::Gzip, true),
+    ] {
+        let (mock_server_incoming, mock_server_addr) = make_incoming();
+        let (proxy_server_incoming, _) = make_incoming();
+
+        let digest = |hash: &str| remoting_protos::Digest {
+            hash: hash.to_owned(),
+            size_bytes: 1,
+        };
+        let backend = RecordingCasService {
+            missing: vec![digest("b")],
+            ..RecordingCasService::default()
+        };
+        let mut cas_server = ContentAddressableStorageServer::new(backend.clone());
+        if backend_supports_gzip {
+            cas_server = cas_server
+                .accept_compressed(CompressionEncoding::Gzip)
+                .send_compressed(CompressionEncoding::Gzip);
+        }
+        let mock_server_fut = Server::builder()
+            .add_service(cas_server)
+            .serve_with_incoming(mock_server_incoming);
+        let _mock_server_handle = tokio::spawn(async move {
+            let _ = mock_server_fut.await;
+        });
+
+        let proxy_server_endpoint: Endpoint =
+            format!("http://{}", proxy_server_incoming.local_addr())
+                .try_into()
+                .unwrap();
+
+        let mut backend_addresses = HashMap::new();
+        backend_addresses.insert(
+            "backend".to_owned(),
+            BackendConfig {
+                address: format!("{mock_server_addr}"),
+                connections: 1,
+                compression: Some(compression),
+                ..BackendConfig::default()
+            },
+        );
+
+        let instance_config = InstanceConfig {
+            execution: None,
+            cas: "backend".to_owned(),
+            action_cache: "backend".to_owned(),
+            ..InstanceConfig::default()
+        };
+
+        let proxy_server = ProxyServer::new(ProxyServerConfig {
+            backends: backend_addresses,
+            default_backends: instance_config,
+            jwk_set: make_jwk_set(),
+            ..ProxyServerConfig::default()
+        })
+        .await
+        .unwrap();
+        let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+        let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+            proxy_server_incoming,
+            shutdown_receiver.map(drop),
+            AuthScheme::Jwt,
+            all_service_names(),
+            None,
+            None,
+            InFlightRequestsCounter::new(),
+        );
+        let _proxy_server_handle = tokio::spawn(async move {
+            let _ = proxy_server_fut.await;
+        });
+
+        let mut cas_client = ContentAddressableStorageClient::connect(proxy_server_endpoint)
+            .await
+            .unwrap();
+        let mut request = Request::new(FindMissingBlobsRequest {
+            instance_name: TEST_INSTANCE_NAME.into(),
+            blob_digests: vec![digest("a"), digest("b")],
+        });
+        add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+        let missing = cas_client
+            .find_missing_blobs(request)
+            .await
+            .unwrap_or_else(|err| panic!("{compression:?}: {err:?}"))
+            .into_inner()
+            .missing_blob_digests;
+        assert_eq!(missing, vec![digest("b")], "{compression:?}");
+        assert_eq!(
+            *backend.find_missing_requests.lock(),
+            vec![vec![digest("a"), digest("b")]],
+            "{compression:?}"
+        );
+    }
+}
+
+/// Tests that responses larger than Tonic's default limit of 4 MiB, but within the storage
+/// server's limit, are relayed from backends.
+#[tokio::test]
+async fn relays_responses_up_to_the_storage_message_size_limit() {
+    let (mock_server_incoming, mock_server_addr) = make_incoming();
+    let (proxy_server_incoming, _) = make_incoming();
+
+    let mock_server_fut = Server::builder()
+        .add_service(ContentAddressableStorageServer::new(
+            RecordingCasService::default(),
+        ))
+        .serve_with_incoming(mock_server_incoming);
+    let _mock_server_handle = tokio::spawn(async move {
+        let _ = mock_server_fut.await;
+    });
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: None,
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let mut cas_client = ContentAddressableStorageClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap()
+        .max_decoding_message_size(DEFAULT_MAX_MESSAGE_SIZE);
+    let digest = remoting_protos::Digest {
+        hash: "a".to_owned(),
+        size_bytes: 4 * 1024 * 1024 + 1024,
+    };
+    let mut request = Request::new(BatchReadBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        digests: vec![digest.clone()],
+        ..BatchReadBlobsRequest::default()
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let responses = cas_client
+        .batch_read_blobs(request)
+        .await
+        .unwrap()
+        .into_inner()
+        .responses;
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].data.len(), digest.size_bytes as usize);
+}
+
+/// CAS backend which serves a single blob, or reports itself unavailable. Counts the writes it
+/// receives.
+#[derive(Clone, Default)]
+struct FailoverCasService {
+    unavailable: bool,
+    blob: Bytes,
+    writes: Arc<AtomicUsize>,
+}
+
+impl FailoverCasService {
+    fn check_available(&self) -> Result<(), Status> {
+        if self.unavailable {
+            Err(Status::unavailable("backend is down"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ContentAddressableStorage for FailoverCasService {
+    async fn find_missing_blobs(
+        &self,
+        _request: Request<FindMissingBlobsRequest>,
+    ) -> Result<Response<FindMissingBlobsResponse>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+
+    async fn batch_update_blobs(
+        &self,
+        _request: Request<BatchUpdateBlobsRequest>,
+    ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        self.check_available()?;
+        Ok(Response::new(BatchUpdateBlobsResponse::default()))
+    }
+
+    async fn batch_read_blobs(
+        &self,
+        request: Request<BatchReadBlobsRequest>,
+    ) -> Result<Response<BatchReadBlobsResponse>, Status> {
+        self.check_available()?;
+        let responses = request
+            .into_inner()
+            .digests
+            .into_iter()
+            .map(
+                |digest| remoting_protos::batch_read_blobs_response::Response {
+                    digest: Some(digest),
+                    data: self.blob.clone(),
+                    ..Default::default()
+                },
+            )
+            .collect();
+        Ok(Response::new(BatchReadBlobsResponse { responses }))
+    }
+
+    type GetTreeStream = tonic::codec::Streaming<GetTreeResponse>;
+
+    async fn get_tree(
+        &self,
+        _request: Request<GetTreeRequest>,
+    ) -> Result<Response<Self::GetTreeStream>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+}
+
+#[tonic::async_trait]
+impl ByteStream for FailoverCasService {
+    type ReadStream = futures::stream::BoxStream<'static, Result<ReadResponse, Status>>;
+
+    async fn read(
+        &self,
+        _request: Request<ReadRequest>,
+    ) -> Result<Response<Self::ReadStream>, Status> {
+        self.check_available()?;
+        let response = ReadResponse {
+            data: self.blob.clone(),
+        };
+        Ok(Response::new(futures::stream::iter([Ok(response)]).boxed()))
+    }
+
+    async fn write(
+        &self,
+        _request: Request<Streaming<WriteRequest>>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+
+    async fn query_write_status(
+        &self,
+        _request: Request<QueryWriteStatusRequest>,
+    ) -> Result<Response<QueryWriteStatusResponse>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+}
+
+/// Tests that CAS reads fail over to a fallback backend when the primary is unavailable, while
+/// writes do not.
+#[tokio::test]
+async fn fails_over_cas_reads_to_fallback_backends() {
+    let spawn_backend = |backend: FailoverCasService| {
+        let (mock_server_incoming, mock_server_addr) = make_incoming();
+        let mock_server_fut = Server::builder()
+            .add_service(ContentAddressableStorageServer::new(backend.clone()))
+            .add_service(ByteStreamServer::new(backend))
+            .serve_with_incoming(mock_server_incoming);
+        tokio::spawn(async move {
+            let _ = mock_server_fut.await;
+        });
+        mock_server_addr
+    };
+    let blob = Bytes::from_static(b"hello");
+    let primary = FailoverCasService {
+        unavailable: true,
+        ..FailoverCasService::default()
+    };
+    let fallback = FailoverCasService {
+        blob: blob.clone(),
+        ..FailoverCasService::default()
+    };
+    let primary_addr = spawn_backend(primary.clone());
+    let fallback_addr = spawn_backend(fallback.clone());
+    let (proxy_server_incoming, _) = make_incoming();
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let backend_addresses = HashMap::from([
+        (
+            "primary".to_owned(),
+            BackendConfig {
+                address: format!("{primary_addr}"),
+                connections: 1,
+                ..BackendConfig::default()
+            },
+        ),
+        (
+            "fallback".to_owned(),
+            BackendConfig {
+                address: format!("{fallback_addr}"),
+                connections: 1,
+                ..BackendConfig::default()
+            },
+        ),
+    ]);
+
+    let instance_config = InstanceConfig {
+        execution: None,
+        cas: "primary".to_owned(),
+        action_cache: "primary".to_owned(),
+        fallback_backends: vec!["fallback".to_owned()],
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+
+    let digest = remoting_protos::Digest {
+        hash: "abc123".to_owned(),
+        size_bytes: blob.len() as i64,
+    };
+
+    let mut cas_client = ContentAddressableStorageClient::connect(proxy_server_endpoint.clone())
+        .await
+        .unwrap();
+    let mut request = Request::new(BatchReadBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        digests: vec![digest.clone()],
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let response = cas_client.batch_read_blobs(request).await.unwrap();
+    assert_eq!(response.into_inner().responses[0].data, blob);
+
+    let mut byte_stream_client = ByteStreamClient::connect(proxy_server_endpoint)
+        .await
+        .unwrap();
+    let mut request = Request::new(ReadRequest {
+        resource_name: format!("{TEST_INSTANCE_NAME}/blobs/abc123/{}", blob.len()),
+        ..Default::default()
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let mut stream = byte_stream_client.read(request).await.unwrap().into_inner();
+    assert_eq!(stream.next().await.unwrap().unwrap().data, blob);
+
+    // Writes are not retried against the fallback.
+    let mut request = Request::new(BatchUpdateBlobsRequest {
+        instance_name: TEST_INSTANCE_NAME.into(),
+        requests: vec![remoting_protos::batch_update_blobs_request::Request {
+            digest: Some(digest),
+            data: blob,
+        }],
+    });
+    add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+    let status = cas_client.batch_update_blobs(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unavailable);
+    assert!(primary.writes.load(Ordering::SeqCst) > 0);
+    assert_eq!(fallback.writes.load(Ordering::SeqCst), 0);
+}
+
+/// Tests that the "early exit for existing blob" case is successful.
+#[tokio::test]
+async fn early_exit_for_existing_blob() {
+    let (
+        calls_count,
+        mock_server_addr,
+        _mock_server_handle,
+        proxy_server_incoming,
+        _is_unavailable,
+    ) = setup_mock_server(false, true);
+
+    let proxy_server_endpoint: Endpoint = format!("http://{}", proxy_server_incoming.local_addr())
+        .try_into()
+        .unwrap();
+
+    let mut backend_addresses = HashMap::new();
+    backend_addresses.insert(
+        "backend".to_owned(),
+        BackendConfig {
+            address: format!("{mock_server_addr}"),
+            connections: 1,
+            ..BackendConfig::default()
+        },
+    );
+
+    let instance_config = InstanceConfig {
+        execution: Some("backend".to_owned()),
+        cas: "backend".to_owned(),
+        action_cache: "backend".to_owned(),
+        ..InstanceConfig::default()
+    };
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: backend_addresses,
+        default_backends: instance_config,
+        jwk_set: make_jwk_set_multiple(),
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let proxy_server_fut = proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    );
+    let _proxy_server_handle = tokio::spawn(async move {
+        let _ = proxy_server_fut.await;
+    });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let mut byte_stream_client = ByteStreamClient::connect(proxy_server_endpoint.clone())
+        .await
+        .unwrap();
+    // NB: We declare the blob to be of a certain size, even though there are many more WriteRequests
+    // in the stream.
+    let declared_size = 32;
     let requests_to_stream = (0..1024).map(move |i| WriteRequest {
         resource_name: format!("{TEST_INSTANCE_NAME}/uploads/foo/{declared_size}"),
         write_offset: i * 8,
@@ -1008,3 +2813,120 @@ async fn early_exit_for_existing_blob() {
     // resource name.
     assert_eq!(1, calls_count.load(Ordering::SeqCst));
 }
+
+/// ByteStream backend whose reads send a single message and then stall until the client goes
+/// away.
+#[derive(Clone)]
+struct StallingByteStreamService;
+
+#[tonic::async_trait]
+impl ByteStream for StallingByteStreamService {
+    type ReadStream = futures::stream::BoxStream<'static, Result<ReadResponse, Status>>;
+
+    async fn read(
+        &self,
+        _request: Request<ReadRequest>,
+    ) -> Result<Response<Self::ReadStream>, Status> {
+        let response = ReadResponse {
+            data: Bytes::from_static(b"foo"),
+        };
+        Ok(Response::new(
+            futures::stream::iter([Ok(response)])
+                .chain(futures::stream::pending())
+                .boxed(),
+        ))
+    }
+
+    async fn write(
+        &self,
+        _request: Request<Streaming<WriteRequest>>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+
+    async fn query_write_status(
+        &self,
+        _request: Request<QueryWriteStatusRequest>,
+    ) -> Result<Response<QueryWriteStatusResponse>, Status> {
+        Err(Status::unimplemented("nothing to see here"))
+    }
+}
+
+/// Tests that a streamed response counts towards the in-flight limit of its instance until the
+/// stream has ended, rather than only until the backend call returns.
+#[tokio::test]
+async fn holds_instance_permits_until_read_streams_end() {
+    let (mock_server_incoming, mock_server_addr) = make_incoming();
+    let _mock_server_handle = tokio::spawn(
+        Server::builder()
+            .add_service(ByteStreamServer::new(StallingByteStreamService))
+            .serve_with_incoming(mock_server_incoming),
+    );
+    let (proxy_server_incoming, proxy_server_addr) = make_incoming();
+
+    let proxy_server = ProxyServer::new(ProxyServerConfig {
+        backends: HashMap::from([(
+            "backend".to_owned(),
+            BackendConfig {
+                address: format!("{mock_server_addr}"),
+                connections: 1,
+                ..BackendConfig::default()
+            },
+        )]),
+        default_backends: InstanceConfig {
+            cas: "backend".to_owned(),
+            action_cache: "backend".to_owned(),
+            ..InstanceConfig::default()
+        },
+        jwk_set: make_jwk_set(),
+        instance_limits: InstanceLimitsConfig {
+            default_max_in_flight: Some(1),
+            ..InstanceLimitsConfig::default()
+        },
+        ..ProxyServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let (_shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+    let _proxy_server_handle = tokio::spawn(proxy_server.serve_with_incoming_shutdown(
+        proxy_server_incoming,
+        shutdown_receiver.map(drop),
+        AuthScheme::Jwt,
+        all_service_names(),
+        None,
+        None,
+        InFlightRequestsCounter::new(),
+    ));
+
+    let mut client = ByteStreamClient::connect(format!("http://{proxy_server_addr}"))
+        .await
+        .unwrap();
+    let read_request = || {
+        let mut request = Request::new(ReadRequest {
+            resource_name: format!("{TEST_INSTANCE_NAME}/blobs/abc123/3"),
+            ..Default::default()
+        });
+        add_jwt_to_request(&mut request, TEST_KEY_ID_1, TEST_SECRET_1);
+        request
+    };
+
+    let mut stream = client.read(read_request()).await.unwrap().into_inner();
+    assert_eq!(
+        stream.next().await.unwrap().unwrap().data,
+        Bytes::from_static(b"foo")
+    );
+
+    // The stream is still open, so the instance is at its limit.
+    let status = client.read(read_request()).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    // Once the client abandons the stream, the permit is released.
+    drop(stream);
+    let mut attempts = 0;
+    while let Err(status) = client.read(read_request()).await {
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        attempts += 1;
+        assert!(attempts < 50, "permit was not released");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
@@ -1,14 +1,28 @@
 // Copyright 2023 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use crate::config::AuthTokenMappingConfig;
-use grpc_util::auth::{deserialize_jwk_set, AuthToken, AuthTokenEntry, JWKSet};
+use async_trait::async_trait;
+
+use crate::config::{ApiKeyMappingConfig, AuthTokenMappingConfig, AuthTokenMappingSourceConfig};
+use grpc_util::auth::{
+    deserialize_api_key_mapping, deserialize_jwk_set, ApiKeyHash, AuthToken, AuthTokenEntry, JWKSet,
+};
+use grpc_util::infra::AdminActions;
+use grpc_util::secrets::SecretProvider;
 use proxy::ProxyServer;
 
-pub async fn read_jwk_set(jwk_set_path: &str) -> Result<JWKSet, String> {
-    let secret_json = tokio::fs::read_to_string(jwk_set_path)
+/// Name of the admin action which immediately reloads the auth token mapping.
+pub const RELOAD_AUTH_TOKEN_MAPPING_ACTION: &str = "reload_auth_token_mapping";
+
+pub async fn read_jwk_set(
+    secret_provider: &dyn SecretProvider,
+    jwk_set_path: &str,
+) -> Result<JWKSet, String> {
+    let secret_json = secret_provider
+        .get_secret(jwk_set_path)
         .await
         .map_err(|err| format!("Failed to read JWT keys from {jwk_set_path}: {err}"))?;
     let jwk_json = grpc_util::secrets::parse_secret(secret_json.as_bytes())?;
@@ -49,8 +63,21 @@ pub async fn read_auth_token_mapping(
         .get_object(s3_auth_token_mapping_path)
         .await
         .map_err(|e| format!("{e}"))?;
+    parse_auth_token_mapping(s3_obj.bytes())
+}
+
+pub async fn read_auth_token_mapping_file(
+    path: &str,
+) -> Result<HashMap<AuthToken, AuthTokenEntry>, String> {
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("Failed to read auth token file {path}: {e}"))?;
+    parse_auth_token_mapping(&content)
+}
+
+fn parse_auth_token_mapping(content: &[u8]) -> Result<HashMap<AuthToken, AuthTokenEntry>, String> {
     let mapping: HashMap<AuthToken, AuthTokenEntry> =
-        serde_json::from_slice(s3_obj.bytes()).map_err(|e| format!("{e}"))?;
+        serde_json::from_slice(content).map_err(|e| format!("{e}"))?;
     log::info!(
         "Loaded auth token file with token IDs: {}",
         mapping
@@ -62,36 +89,588 @@ pub async fn read_auth_token_mapping(
     Ok(mapping)
 }
 
+/// Where the auth token mapping is read from, along with a version which changes whenever the
+/// mapping does.
+#[async_trait]
+pub trait AuthTokenMappingSource: Send + Sync {
+    async fn version(&self) -> Result<String, String>;
+
+    async fn read(&self) -> Result<HashMap<AuthToken, AuthTokenEntry>, String>;
+}
+
+/// Reads the auth token mapping from a versioned S3 object.
+pub struct S3AuthTokenMappingSource {
+    bucket: s3::Bucket,
+    path: String,
+}
+
+impl S3AuthTokenMappingSource {
+    pub fn new(bucket: s3::Bucket, path: String) -> Self {
+        S3AuthTokenMappingSource { bucket, path }
+    }
+}
+
+#[async_trait]
+impl AuthTokenMappingSource for S3AuthTokenMappingSource {
+    async fn version(&self) -> Result<String, String> {
+        get_auth_token_mapping_version(&self.bucket, &self.path).await
+    }
+
+    async fn read(&self) -> Result<HashMap<AuthToken, AuthTokenEntry>, String> {
+        read_auth_token_mapping(&self.bucket, &self.path).await
+    }
+}
+
+/// Reads the auth token mapping from a local file, versioned by its modification time.
+pub struct FileAuthTokenMappingSource {
+    path: String,
+}
+
+impl FileAuthTokenMappingSource {
+    pub fn new(path: String) -> Self {
+        FileAuthTokenMappingSource { path }
+    }
+}
+
+#[async_trait]
+impl AuthTokenMappingSource for FileAuthTokenMappingSource {
+    async fn version(&self) -> Result<String, String> {
+        let modified = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("Failed to stat auth token file {}: {e}", self.path))?;
+        let since_epoch = modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| format!("Invalid modification time for {}: {e}", self.path))?;
+        Ok(since_epoch.as_nanos().to_string())
+    }
+
+    async fn read(&self) -> Result<HashMap<AuthToken, AuthTokenEntry>, String> {
+        read_auth_token_mapping_file(&self.path).await
+    }
+}
+
+/// Reads the auth token mapping from an HTTP(S) endpoint, versioned by its `ETag`.
+pub struct HttpAuthTokenMappingSource {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpAuthTokenMappingSource {
+    pub fn new(url: String) -> Self {
+        HttpAuthTokenMappingSource {
+            client: reqwest::Client::new(),
+            url,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthTokenMappingSource for HttpAuthTokenMappingSource {
+    async fn version(&self) -> Result<String, String> {
+        let response = self
+            .client
+            .head(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("HEAD request for {} failed: {e}", self.url))?;
+        response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_owned)
+            .ok_or_else(|| format!("No ETag in the response for {}", self.url))
+    }
+
+    async fn read(&self) -> Result<HashMap<AuthToken, AuthTokenEntry>, String> {
+        let content = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("GET request for {} failed: {e}", self.url))?
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read the response for {}: {e}", self.url))?;
+        parse_auth_token_mapping(&content)
+    }
+}
+
+/// Create the configured `AuthTokenMappingSource`.
+pub fn auth_token_mapping_source(
+    config: &AuthTokenMappingSourceConfig,
+) -> Result<Arc<dyn AuthTokenMappingSource>, String> {
+    let source: Arc<dyn AuthTokenMappingSource> = match config {
+        AuthTokenMappingSourceConfig::S3 {
+            s3_bucket,
+            s3_region,
+            s3_path,
+        } => {
+            let region = s3_region
+                .parse()
+                .map_err(|e| format!("Invalid auth token mapping s3_region: {e}"))?;
+            let credentials = s3::creds::Credentials::from_sts_env("aws-creds")
+                .map_err(|e| format!("Failed to load AWS credentials: {e}"))?;
+            let bucket = s3::Bucket::new(s3_bucket, region, credentials)
+                .map_err(|e| format!("Failed to create S3 bucket {s3_bucket}: {e}"))?;
+            Arc::new(S3AuthTokenMappingSource::new(bucket, s3_path.clone()))
+        }
+        AuthTokenMappingSourceConfig::File { file_path } => {
+            Arc::new(FileAuthTokenMappingSource::new(file_path.clone()))
+        }
+        AuthTokenMappingSourceConfig::Http { url } => {
+            reqwest::Url::parse(url)
+                .map_err(|err| format!("Invalid auth token mapping url {url}: {err}"))?;
+            Arc::new(HttpAuthTokenMappingSource::new(url.clone()))
+        }
+    };
+    Ok(source)
+}
+
+/// Add the admin action which reloads the auth token mapping on demand to `admin_actions`. The
+/// reload shares `refresher` with the periodic refresh, so that they do not race.
+pub fn add_reload_auth_token_mapping_action(
+    admin_actions: AdminActions,
+    refresher: Arc<tokio::sync::Mutex<AuthTokenMappingRefresher>>,
+    proxy_server: ProxyServer,
+) -> AdminActions {
+    admin_actions.with_action(RELOAD_AUTH_TOKEN_MAPPING_ACTION, move || {
+        let refresher = refresher.clone();
+        let proxy_server = proxy_server.clone();
+        async move {
+            let result = refresher.lock().await.reload(&proxy_server).await;
+            if let Err(e) = &result {
+                log_auth_token_failure(e.clone());
+            }
+            result
+        }
+    })
+}
+
+/// Delay before retrying the first failed refresh of the auth token mapping. The delay doubles
+/// with each consecutive failure, up to `AUTH_TOKEN_REFRESH_MAX_BACKOFF`.
+const AUTH_TOKEN_REFRESH_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const AUTH_TOKEN_REFRESH_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Polls an `AuthTokenMappingSource`, and swaps its mapping into the proxy whenever its version
+/// changes. Failed refreshes keep the previous mapping and are retried with exponential backoff.
+pub struct AuthTokenMappingRefresher {
+    source: Arc<dyn AuthTokenMappingSource>,
+    version: String,
+    refresh_frequency: Duration,
+    consecutive_failures: u32,
+}
+
+impl AuthTokenMappingRefresher {
+    pub fn new(
+        source: Arc<dyn AuthTokenMappingSource>,
+        initial_version: String,
+        refresh_frequency: Duration,
+    ) -> Self {
+        AuthTokenMappingRefresher {
+            source,
+            version: initial_version,
+            refresh_frequency,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Refresh the mapping if its version has changed. Returns how long to wait before the next
+    /// refresh.
+    pub async fn refresh(&mut self, proxy_server: &ProxyServer) -> Duration {
+        match self.try_refresh(proxy_server).await {
+            Ok(updated) => {
+                let result = if updated { "updated" } else { "unchanged" };
+                metrics::counter!("toolchain_proxy_auth_map_refresh_total", 1, "result" => result);
+                self.consecutive_failures = 0;
+                self.refresh_frequency
+            }
+            Err(e) => {
+                metrics::counter!("toolchain_proxy_auth_map_refresh_total", 1, "result" => "error");
+                log_auth_token_failure(e);
+                self.consecutive_failures += 1;
+                self.backoff()
+            }
+        }
+    }
+
+    async fn try_refresh(&mut self, proxy_server: &ProxyServer) -> Result<bool, String> {
+        let new_version = self.source.version().await?;
+        if new_version == self.version {
+            return Ok(false);
+        }
+        let mapping = self.source.read().await?;
+        proxy_server.swap_auth_token_mapping(mapping);
+        // Only record the version once its mapping is in use, so that a failed read is retried.
+        self.version = new_version;
+        Ok(true)
+    }
+
+    /// Immediately re-read the mapping and swap it into the proxy, whether or not its version
+    /// has changed. Returns the version which was read.
+    pub async fn reload(&mut self, proxy_server: &ProxyServer) -> Result<String, String> {
+        // Read the version first: if the mapping changes in between, the next refresh will
+        // notice the newer version and read it again.
+        let version = self.source.version().await?;
+        let mapping = self.source.read().await?;
+        proxy_server.swap_auth_token_mapping(mapping);
+        log::info!("Reloaded auth token mapping at version {version}");
+        self.version = version.clone();
+        Ok(version)
+    }
+
+    fn backoff(&self) -> Duration {
+        let multiplier = 2_u32.saturating_pow(self.consecutive_failures.saturating_sub(1));
+        AUTH_TOKEN_REFRESH_INITIAL_BACKOFF
+            .saturating_mul(multiplier)
+            .min(AUTH_TOKEN_REFRESH_MAX_BACKOFF)
+    }
+}
+
+/// Create the refresher for the auth token mapping, which is shared by the periodic refresh and
+/// the reload admin action.
+pub fn auth_token_mapping_refresher(
+    source: Arc<dyn AuthTokenMappingSource>,
+    config: &AuthTokenMappingConfig,
+    initial_version: String,
+) -> Arc<tokio::sync::Mutex<AuthTokenMappingRefresher>> {
+    let refresh_frequency = Duration::from_secs(config.refresh_frequency_s.unwrap_or(20));
+    Arc::new(tokio::sync::Mutex::new(AuthTokenMappingRefresher::new(
+        source,
+        initial_version,
+        refresh_frequency,
+    )))
+}
+
 pub async fn refresh_auth_token_mapping(
-    s3_bucket: s3::Bucket,
-    config: AuthTokenMappingConfig,
-    auth_token_mapping_initial_version: String,
+    refresher: Arc<tokio::sync::Mutex<AuthTokenMappingRefresher>>,
+    proxy_server: ProxyServer,
+) {
+    loop {
+        let delay = refresher.lock().await.refresh(&proxy_server).await;
+        tokio::time::sleep(delay).await;
+    }
+}
+
+pub async fn get_api_key_mapping_version(path: &str) -> Result<SystemTime, String> {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| format!("Failed to stat API key file {path}: {e}"))
+}
+
+pub async fn read_api_key_mapping(
+    path: &str,
+) -> Result<HashMap<ApiKeyHash, AuthTokenEntry>, String> {
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("Failed to read API key file {path}: {e}"))?;
+    let mapping = deserialize_api_key_mapping(&content)?;
+    log::info!(
+        "Loaded API key file with key IDs: {}",
+        mapping
+            .values()
+            .map(|e| e.id.clone())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(mapping)
+}
+
+/// Reload the API key file whenever its modification time changes.
+pub async fn refresh_api_key_mapping(
+    config: ApiKeyMappingConfig,
+    api_key_mapping_initial_version: Option<SystemTime>,
     proxy_server: ProxyServer,
 ) {
     let duration = Duration::from_secs(config.refresh_frequency_s.unwrap_or(20));
     let mut interval = tokio::time::interval(duration);
-    let mut version = auth_token_mapping_initial_version;
+    let mut version = api_key_mapping_initial_version;
     loop {
         interval.tick().await;
-        match get_auth_token_mapping_version(&s3_bucket, &config.s3_path).await {
+        match get_api_key_mapping_version(&config.path).await {
             Ok(new_version) => {
-                if new_version == version {
+                if version == Some(new_version) {
                     continue;
                 }
-                version = new_version;
-                match read_auth_token_mapping(&s3_bucket, &config.s3_path).await {
+                match read_api_key_mapping(&config.path).await {
                     Ok(mapping) => {
-                        proxy_server.swap_auth_token_mapping(mapping);
+                        version = Some(new_version);
+                        proxy_server.swap_api_key_mapping(mapping);
                     }
-                    Err(e) => log_auth_token_failure(e),
+                    Err(e) => log_api_key_failure(e),
                 }
             }
-            Err(e) => log_auth_token_failure(e),
+            Err(e) => log_api_key_failure(e),
         }
     }
 }
 
+pub fn log_api_key_failure(e: String) {
+    metrics::increment_counter!("api_key_mapping_refresh_failure");
+    log::error!("auth_failure: Could not read API key mapping. Error: {e:?}");
+}
+
 pub fn log_auth_token_failure(e: String) {
     metrics::increment_counter!("auth_token_mapping_refresh_failure");
-    log::error!("auth_failure: Could not read auth token mapping from S3. Error: {e:?}");
+    log::error!("auth_failure: Could not read auth token mapping. Error: {e:?}");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use async_trait::async_trait;
+    use grpc_util::auth::{make_jwk_set, AuthToken, AuthTokenEntry};
+    use grpc_util::backend::BackendConfig;
+    use grpc_util::infra::AdminActions;
+    use hyper::StatusCode;
+    use parking_lot::Mutex;
+    use proxy::{InstanceConfig, ProxyServer, ProxyServerConfig};
+
+    use super::{
+        add_reload_auth_token_mapping_action, AuthTokenMappingRefresher, AuthTokenMappingSource,
+        FileAuthTokenMappingSource, RELOAD_AUTH_TOKEN_MAPPING_ACTION,
+    };
+
+    /// An in-memory auth token mapping, held as its token IDs, along with its version. Fails the
+    /// next `failing_reads` reads.
+    #[derive(Default)]
+    struct TestAuthTokenMappingSource {
+        mapping: Mutex<(String, Vec<String>)>,
+        failing_reads: AtomicUsize,
+    }
+
+    impl TestAuthTokenMappingSource {
+        fn set(&self, version: &str, token_ids: &[&str]) {
+            *self.mapping.lock() = (
+                version.to_owned(),
+                token_ids.iter().map(|id| (*id).to_owned()).collect(),
+            );
+        }
+    }
+
+    #[async_trait]
+    impl AuthTokenMappingSource for TestAuthTokenMappingSource {
+        async fn version(&self) -> Result<String, String> {
+            Ok(self.mapping.lock().0.clone())
+        }
+
+        async fn read(&self) -> Result<HashMap<AuthToken, AuthTokenEntry>, String> {
+            if self
+                .failing_reads
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err("S3 is unavailable".to_owned());
+            }
+            let mapping = self
+                .mapping
+                .lock()
+                .1
+                .iter()
+                .map(|id| {
+                    (
+                        AuthToken::new(format!("token-{id}")),
+                        AuthTokenEntry {
+                            id: id.clone(),
+                            is_active: true,
+                            instance_name: "main".to_owned(),
+                            customer_slug: "customer".to_owned(),
+                        },
+                    )
+                })
+                .collect();
+            Ok(mapping)
+        }
+    }
+
+    async fn create_proxy_server() -> ProxyServer {
+        let backends = HashMap::from([(
+            "backend".to_owned(),
+            BackendConfig {
+                address: "127.0.0.1:1".to_owned(),
+                connections: 1,
+                ..BackendConfig::default()
+            },
+        )]);
+        let instance_config = InstanceConfig {
+            cas: "backend".to_owned(),
+            action_cache: "backend".to_owned(),
+            ..InstanceConfig::default()
+        };
+        ProxyServer::new(ProxyServerConfig {
+            backends,
+            default_backends: instance_config,
+            jwk_set: make_jwk_set(),
+            ..ProxyServerConfig::default()
+        })
+        .await
+        .unwrap()
+    }
+
+    fn token_ids(proxy_server: &ProxyServer) -> Vec<String> {
+        let mut ids = proxy_server
+            .auth_token_mapping()
+            .values()
+            .map(|entry| entry.id.clone())
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn admin_action_reloads_auth_token_mapping() {
+        let proxy_server = create_proxy_server().await;
+        let source = Arc::new(TestAuthTokenMappingSource::default());
+        let refresh_frequency = Duration::from_secs(20);
+        let refresher = Arc::new(tokio::sync::Mutex::new(AuthTokenMappingRefresher::new(
+            source.clone(),
+            "".to_owned(),
+            refresh_frequency,
+        )));
+        let admin_actions = add_reload_auth_token_mapping_action(
+            AdminActions::new(Some("s3cret".to_owned())),
+            refresher.clone(),
+            proxy_server.clone(),
+        );
+        let token_ids = || token_ids(&proxy_server);
+
+        source.set("v1", &["a", "b"]);
+        let version = admin_actions
+            .run(RELOAD_AUTH_TOKEN_MAPPING_ACTION, Some("Bearer s3cret"))
+            .await
+            .unwrap();
+        assert_eq!(version, "v1");
+        assert_eq!(token_ids(), vec!["a", "b"]);
+
+        // Simulate an out-of-band revocation.
+        source.set("v2", &["a"]);
+        let error = admin_actions
+            .run(RELOAD_AUTH_TOKEN_MAPPING_ACTION, None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(token_ids(), vec!["a", "b"]);
+
+        let version = admin_actions
+            .run(RELOAD_AUTH_TOKEN_MAPPING_ACTION, Some("Bearer s3cret"))
+            .await
+            .unwrap();
+        assert_eq!(version, "v2");
+        assert_eq!(token_ids(), vec!["a"]);
+
+        // The periodic refresh knows that the reload already read the latest version, so does
+        // not read it again.
+        source.failing_reads.store(1, Ordering::SeqCst);
+        assert_eq!(
+            refresher.lock().await.refresh(&proxy_server).await,
+            refresh_frequency
+        );
+        assert_eq!(token_ids(), vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn refresher_keeps_mapping_and_backs_off_on_errors() {
+        let proxy_server = create_proxy_server().await;
+        let source = Arc::new(TestAuthTokenMappingSource::default());
+        let refresh_frequency = Duration::from_secs(20);
+        let mut refresher =
+            AuthTokenMappingRefresher::new(source.clone(), "".to_owned(), refresh_frequency);
+
+        source.set("v1", &["a", "b"]);
+        assert_eq!(refresher.refresh(&proxy_server).await, refresh_frequency);
+        assert_eq!(token_ids(&proxy_server), vec!["a", "b"]);
+
+        // The version is unchanged, so the mapping is not read again.
+        source.failing_reads.store(1, Ordering::SeqCst);
+        assert_eq!(refresher.refresh(&proxy_server).await, refresh_frequency);
+        source.failing_reads.store(0, Ordering::SeqCst);
+
+        // Transient errors while reading the new version keep the previous mapping, and are
+        // retried with exponential backoff.
+        source.set("v2", &["a"]);
+        source.failing_reads.store(3, Ordering::SeqCst);
+        assert_eq!(
+            refresher.refresh(&proxy_server).await,
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            refresher.refresh(&proxy_server).await,
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            refresher.refresh(&proxy_server).await,
+            Duration::from_secs(4)
+        );
+        assert_eq!(token_ids(&proxy_server), vec!["a", "b"]);
+
+        // Once the source recovers, the new version is read and the backoff is reset.
+        assert_eq!(refresher.refresh(&proxy_server).await, refresh_frequency);
+        assert_eq!(token_ids(&proxy_server), vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn file_source_detects_changes_via_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth-token-mapping.json");
+        let write = |token_ids: &[&str], modified: SystemTime| {
+            let mapping = token_ids
+                .iter()
+                .map(|id| {
+                    (
+                        format!("token-{id}"),
+                        serde_json::json!({
+                            "id": id,
+                            "is_active": true,
+                            "instance_name": "main",
+                            "customer_slug": "customer",
+                        }),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>();
+            std::fs::write(&path, serde_json::to_vec(&mapping).unwrap()).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        let source = FileAuthTokenMappingSource::new(path.to_str().unwrap().to_owned());
+
+        // The initial load.
+        let first_modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        write(&["a", "b"], first_modified);
+        let initial_version = source.version().await.unwrap();
+        let mut ids = source
+            .read()
+            .await
+            .unwrap()
+            .into_values()
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        // The version only changes when the file is modified.
+        assert_eq!(source.version().await.unwrap(), initial_version);
+        write(&["a"], first_modified + Duration::from_secs(1));
+        let new_version = source.version().await.unwrap();
+        assert_ne!(new_version, initial_version);
+
+        // The refresher picks up the modified file.
+        let proxy_server = create_proxy_server().await;
+        let refresh_frequency = Duration::from_secs(20);
+        let mut refresher =
+            AuthTokenMappingRefresher::new(Arc::new(source), initial_version, refresh_frequency);
+        assert_eq!(refresher.refresh(&proxy_server).await, refresh_frequency);
+        assert_eq!(token_ids(&proxy_server), vec!["a"]);
+    }
 }
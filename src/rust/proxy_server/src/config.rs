@@ -4,12 +4,17 @@
 use std::collections::HashMap;
 use std::time::Duration;
 
+use grpc_util::auth::JwtPermissionsClaim;
 use grpc_util::backend::BackendConfig;
 use grpc_util::infra::{GrpcConfig, InfraConfig};
-use proxy::{BackendTimeoutsConfig, InstanceConfig, InstanceName, ListenAddressConfig};
+use grpc_util::secrets::SecretsConfig;
+use proxy::{
+    BackendErrorLoggingConfig, BackendTimeoutsConfig, FindMissingBlobsCacheConfig,
+    InstanceBackendRule, InstanceConfig, InstanceLimitsConfig, InstanceName, ListenAddressConfig,
+};
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Clone, Deserialize, Debug, Default)]
 pub struct ProxyTimeoutsConfig {
     /// Timeout for GetActionResult API in milliseconds.
     pub get_action_result: Option<u64>,
@@ -23,11 +28,37 @@ impl ProxyTimeoutsConfig {
     }
 }
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct AuthTokenMappingConfig {
-    pub s3_bucket: String,
-    pub s3_region: String,
-    pub s3_path: String,
+    /// Where the JSON file mapping tokens to their metadata is read from.
+    #[serde(flatten)]
+    pub source: AuthTokenMappingSourceConfig,
+    pub refresh_frequency_s: Option<u64>,
+}
+
+/// The source of the auth token mapping, selected by which keys are set.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum AuthTokenMappingSourceConfig {
+    /// A versioned S3 object. Changes are detected via its version ID.
+    S3 {
+        s3_bucket: String,
+        s3_region: String,
+        s3_path: String,
+    },
+    /// A local file. Changes are detected via its modification time.
+    File { file_path: String },
+    /// An HTTP(S) endpoint. Changes are detected via the `ETag` response header.
+    Http { url: String },
+}
+
+/// Default TTL for cached `GetCapabilities` responses.
+pub const DEFAULT_CAPABILITIES_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ApiKeyMappingConfig {
+    /// Path to a JSON file mapping hex-encoded SHA-256 hashes of API keys to their metadata.
+    pub path: String,
     pub refresh_frequency_s: Option<u64>,
 }
 
@@ -37,14 +68,36 @@ pub struct Config {
     pub listen_addresses: Vec<ListenAddressConfig>,
 
     /// The path to the rotable secret(s) storing the JWK set to be used when validating JWT tokens.
+    /// When `secrets` configures another provider, this is the name of the secret instead.
     pub jwk_set_path: String,
 
+    /// The JWT claim which carries the permissions granted by a token. Defaults to the `aud`
+    /// (audience) claim.
+    pub jwt_permissions_claim: Option<JwtPermissionsClaim>,
+
+    /// Where secrets (the JWK set) are loaded from. Defaults to files.
+    pub secrets: Option<SecretsConfig>,
+
+    /// Config for a JSON file mapping hashed API keys to their metadata.
+    ///
+    /// If not set, no API keys will be loaded and the `api_key` auth scheme rejects all requests.
+    pub api_key_mapping: Option<ApiKeyMappingConfig>,
+
+    /// Map of client certificate identities (subject CN, or a DNS/URI subject alternative name) to
+    /// the instance name they are authorized for. Used by the `mutual_tls` auth scheme.
+    pub client_certificate_mapping: Option<HashMap<String, InstanceName>>,
+
     /// Config for a JSON file mapping token strings to their metadata.
     ///
     /// If not set, no auth tokens will be loaded. Clients can still send requests with auth tokens,
     /// but no tokens will be recognized.
     pub auth_token_mapping: Option<AuthTokenMappingConfig>,
 
+    /// Path (as understood by the configured secrets provider) to the shared secret which
+    /// authenticates requests to the admin infra endpoints. If unset, the admin endpoints are
+    /// disabled.
+    pub admin_secret_path: Option<String>,
+
     /// Map of backend names to the ADDRESS:PORT of the backend. The backend names are
     /// referenced later as a service name. This allows defining addresses once and reusing
     /// throughout the config.
@@ -53,6 +106,10 @@ pub struct Config {
     /// Defines per-instance backends to use for each service.
     pub per_instance_backends: Option<HashMap<InstanceName, InstanceConfig>>,
 
+    /// Ordered rules routing instances whose name matches a regular expression to backends.
+    /// Consulted after `per_instance_backends` and before `default_backends`.
+    pub instance_backend_rules: Option<Vec<InstanceBackendRule>>,
+
     /// Defines the default backends to use for each service (if no per-instance backend is
     /// configured).
     pub default_backends: InstanceConfig,
@@ -65,6 +122,28 @@ pub struct Config {
 
     /// Backend timeouts configuration.
     pub backend_timeouts: Option<ProxyTimeoutsConfig>,
+
+    /// Maps external instance names to the instance name used when forwarding to backends.
+    /// Requests are still authorized against the external instance name.
+    pub instance_aliases: Option<HashMap<InstanceName, InstanceName>>,
+
+    /// Limits on the number of in-flight requests per instance.
+    pub instance_limits: Option<InstanceLimitsConfig>,
+
+    /// How long to cache `GetCapabilities` responses from backends, in milliseconds. Set to 0 to
+    /// disable caching.
+    pub capabilities_cache_ttl_ms: Option<u64>,
+
+    /// Remember digests which backends recently reported present, so that repeated
+    /// `FindMissingBlobs` calls only ask backends about the others. Disabled if not set.
+    pub find_missing_blobs_cache: Option<FindMissingBlobsCacheConfig>,
+
+    /// Sampling of the logs for transient errors from backends (e.g. `Unavailable`).
+    pub backend_error_logging: Option<BackendErrorLoggingConfig>,
+
+    /// Allow listeners to use the `dev_only_no_auth` auth scheme. Never set this in production.
+    #[serde(default)]
+    pub dev_mode: bool,
 }
 
 impl Config {
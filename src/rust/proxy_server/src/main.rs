@@ -5,18 +5,24 @@
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use futures::future;
 use hyper::server::conn::AddrIncoming;
 use tokio::sync::watch;
 use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
 
+use grpc_util::auth::{AuthToken, AuthTokenEntry, JWKSet};
 use grpc_util::hyper::AddrIncomingWithStream;
-use grpc_util::infra::{setup_infra_endpoints, GrpcConfig};
+use grpc_util::infra::{
+    serve_with_drain_deadline, setup_infra_endpoints, wait_for_shutdown, AdminActions, DebugInfo,
+    GrpcConfig,
+};
 use grpc_util::logging::setup_logging;
 use grpc_util::sentry::setup_sentry;
-use proxy::{ListenAddressConfig, ProxyServer};
+use proxy::{ListenAddressConfig, ProxyServer, ProxyServerConfig};
 
 mod auth_setup;
 mod config;
@@ -30,77 +36,116 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .required(true)
                 .value_name("FILE"),
         )
+        .arg(
+            Arg::new("config-check")
+                .long("config-check")
+                .action(ArgAction::SetTrue)
+                .help("Validate the config file and exit without serving"),
+        )
         .get_matches();
 
-    let config = {
+    let (config, debug_info) = {
         let filename = matches.get_one::<String>("config").unwrap();
         let config_content = tokio::fs::read_to_string(&filename)
             .await
             .map_err(|err| format!("Failed to read config from {}: {}", &filename, err))?;
-        config::Config::from_str(&config_content)?
+        let debug_info = DebugInfo::new(
+            env!("CARGO_PKG_VERSION"),
+            option_env!("GIT_SHA"),
+            &config_content,
+        )?;
+        let config = config::Config::from_str(&config_content)
+            .map_err(|err| format!("Invalid config file {filename}: {err}"))?;
+        (config, debug_info)
     };
 
-    setup_logging(config.infra.as_ref(), "proxy_server");
+    if matches.get_flag("config-check") {
+        check_config(&config).await?;
+        println!("Config is valid.");
+        return Ok(());
+    }
+
+    let _tracing_guard = setup_logging(config.infra.as_ref(), "proxy_server");
     log::info!("proxy server config: {config:?}");
     let _sentry_guard = setup_sentry(config.infra.as_ref(), "proxy_server");
 
-    let jwk_set = auth_setup::read_jwk_set(&config.jwk_set_path).await?;
-
-    let (maybe_s3_bucket, auth_token_mapping, auth_token_mapping_initial_version) =
-        if let Some(ref auth_token_config) = config.auth_token_mapping {
-            let s3_bucket = s3::Bucket::new(
-                &auth_token_config.s3_bucket,
-                auth_token_config.s3_region.parse()?,
-                s3::creds::Credentials::from_sts_env("aws-creds")?,
-            )?;
-            let (auth_token_mapping, auth_token_mapping_initial_version) = futures::try_join!(
-                auth_setup::read_auth_token_mapping(&s3_bucket, &auth_token_config.s3_path),
-                auth_setup::get_auth_token_mapping_version(&s3_bucket, &auth_token_config.s3_path,),
-            )
-            .unwrap_or_else(|e: String| {
+    let Startup {
+        jwk_set,
+        admin_secret,
+        auth_token_mapping_source,
+    } = prepare_startup(&config).await?;
+
+    let (auth_token_mapping, auth_token_mapping_initial_version) =
+        if let Some(source) = &auth_token_mapping_source {
+            futures::try_join!(source.read(), source.version()).unwrap_or_else(|e: String| {
                 auth_setup::log_auth_token_failure(e);
                 (HashMap::new(), "".to_owned())
-            });
-            (
-                Some(s3_bucket),
-                auth_token_mapping,
-                auth_token_mapping_initial_version,
-            )
+            })
         } else {
             log::warn!("Auth token map disabled because it was not configured");
-            (None, HashMap::new(), "".to_owned())
+            (HashMap::new(), "".to_owned())
         };
 
-    let backend_timeouts = config
-        .backend_timeouts
-        .map(|t| t.into_backend_timeouts())
-        .unwrap_or_default();
+    proxy::configure_backend_error_logging(
+        &config.backend_error_logging.clone().unwrap_or_default(),
+    );
+
+    let proxy_server =
+        ProxyServer::new(proxy_server_config(&config, jwk_set, auth_token_mapping)).await?;
+
+    let mut admin_actions = AdminActions::new(admin_secret);
+    if let (Some(source), Some(auth_token_config)) =
+        (auth_token_mapping_source, &config.auth_token_mapping)
+    {
+        let refresher = auth_setup::auth_token_mapping_refresher(
+            source,
+            auth_token_config,
+            auth_token_mapping_initial_version,
+        );
+        admin_actions = auth_setup::add_reload_auth_token_mapping_action(
+            admin_actions,
+            refresher.clone(),
+            proxy_server.clone(),
+        );
+        tokio::spawn(auth_setup::refresh_auth_token_mapping(
+            refresher,
+            proxy_server.clone(),
+        ));
+    }
 
     // Setup infra endpoints.
     let in_flight_requests_counter = InFlightRequestsCounter::new();
     let in_flight_requests_counter_2 = in_flight_requests_counter.clone();
-    let shutdown_receiver = setup_infra_endpoints(config.infra.unwrap_or_default(), move || {
-        let count = in_flight_requests_counter_2.get();
-        metrics::gauge!("toolchain_grpc_inflight_requests", count as f64, "service" => "proxy_server");
-    })
-    .expect("setup infra endpoints");
-
-    let proxy_server = ProxyServer::new(
-        config.backends,
-        config.per_instance_backends.unwrap_or_default(),
-        config.default_backends,
-        jwk_set,
-        auth_token_mapping,
-        backend_timeouts,
+    let shutdown_receiver = setup_infra_endpoints(
+        config.infra.unwrap_or_default(),
+        debug_info,
+        admin_actions,
+        move || {
+            let count = in_flight_requests_counter_2.get();
+            metrics::gauge!(
+                "toolchain_grpc_inflight_requests",
+                count as f64,
+                "service" => "proxy_server",
+            );
+        },
     )
-    .await
-    .unwrap();
+    .expect("setup infra endpoints");
 
-    if let Some(s3_bucket) = maybe_s3_bucket {
-        tokio::spawn(auth_setup::refresh_auth_token_mapping(
-            s3_bucket,
-            config.auth_token_mapping.clone().unwrap(),
-            auth_token_mapping_initial_version,
+    if let Some(api_key_config) = config.api_key_mapping.clone() {
+        // Load the initial mapping before serving so that valid keys are not rejected at startup.
+        let api_key_mapping_initial_version = futures::try_join!(
+            auth_setup::read_api_key_mapping(&api_key_config.path),
+            auth_setup::get_api_key_mapping_version(&api_key_config.path),
+        )
+        .map(|(mapping, version)| {
+            proxy_server.swap_api_key_mapping(mapping);
+            version
+        })
+        .map_err(auth_setup::log_api_key_failure)
+        .ok();
+        tokio::spawn(auth_setup::refresh_api_key_mapping(
+            api_key_config,
+            api_key_mapping_initial_version,
             proxy_server.clone(),
         ));
     }
@@ -122,29 +167,135 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// What the proxy is set up from besides the config file.
+struct Startup {
+    jwk_set: JWKSet,
+    admin_secret: Option<String>,
+    auth_token_mapping_source: Option<Arc<dyn auth_setup::AuthTokenMappingSource>>,
+}
+
+/// Load the secrets which the config refers to, and check the listeners and the config of the
+/// `ProxyServer`, without binding any ports or connecting to any backends.
+async fn prepare_startup(config: &config::Config) -> Result<Startup, String> {
+    for listen_config in &config.listen_addresses {
+        listen_config.validate(config.dev_mode)?;
+        listen_config
+            .addr
+            .parse::<SocketAddr>()
+            .map_err(|err| format!("Invalid listen address {}: {err}", listen_config.addr))?;
+        if let Some(tls) = &listen_config.tls {
+            tls.to_server_tls_config()?;
+        }
+    }
+
+    let secret_provider = config.secrets.clone().unwrap_or_default().provider();
+    let jwk_set = auth_setup::read_jwk_set(&*secret_provider, &config.jwk_set_path).await?;
+    let admin_secret = match &config.admin_secret_path {
+        Some(path) => Some(grpc_util::secrets::parse_secret(
+            secret_provider.get_secret(path).await?,
+        )?),
+        None => None,
+    };
+    let auth_token_mapping_source = config
+        .auth_token_mapping
+        .as_ref()
+        .map(|auth_token_config| auth_setup::auth_token_mapping_source(&auth_token_config.source))
+        .transpose()?;
+    Ok(Startup {
+        jwk_set,
+        admin_secret,
+        auth_token_mapping_source,
+    })
+}
+
+/// The config of the `ProxyServer`.
+fn proxy_server_config(
+    config: &config::Config,
+    jwk_set: JWKSet,
+    auth_token_mapping: HashMap<AuthToken, AuthTokenEntry>,
+) -> ProxyServerConfig {
+    ProxyServerConfig {
+        backends: config.backends.clone(),
+        per_instance_backends: config.per_instance_backends.clone().unwrap_or_default(),
+        instance_backend_rules: config.instance_backend_rules.clone().unwrap_or_default(),
+        default_backends: config.default_backends.clone(),
+        jwk_set,
+        jwt_permissions_claim: config.jwt_permissions_claim.clone().unwrap_or_default(),
+        auth_token_mapping,
+        timeouts: config
+            .backend_timeouts
+            .clone()
+            .map(|t| t.into_backend_timeouts())
+            .unwrap_or_default(),
+        instance_aliases: config.instance_aliases.clone().unwrap_or_default(),
+        instance_limits: config.instance_limits.clone().unwrap_or_default(),
+        capabilities_cache_ttl: config
+            .capabilities_cache_ttl_ms
+            .map(Duration::from_millis)
+            .unwrap_or(config::DEFAULT_CAPABILITIES_CACHE_TTL),
+        find_missing_blobs_cache: config.find_missing_blobs_cache.clone().unwrap_or_default(),
+        client_certificate_mapping: config
+            .client_certificate_mapping
+            .clone()
+            .unwrap_or_default(),
+        dev_mode: config.dev_mode,
+    }
+}
+
+/// Validate the config as fully as possible without binding any ports or connecting to any
+/// backends: everything which is checked at startup, and also that the auth token and API key
+/// mapping files can be read, which startup tolerates.
+async fn check_config(config: &config::Config) -> Result<(), String> {
+    let Startup {
+        jwk_set,
+        auth_token_mapping_source,
+        ..
+    } = prepare_startup(config).await?;
+    ProxyServer::check(&proxy_server_config(config, jwk_set, HashMap::new()))?;
+    if let Some(auth_token_config) = &config.auth_token_mapping {
+        if let config::AuthTokenMappingSourceConfig::File { .. } = &auth_token_config.source {
+            if let Some(source) = auth_token_mapping_source {
+                source.read().await?;
+            }
+        }
+    }
+    if let Some(api_key_config) = &config.api_key_mapping {
+        auth_setup::read_api_key_mapping(&api_key_config.path).await?;
+    }
+    Ok(())
+}
+
 async fn serve(
     listen_config: ListenAddressConfig,
     proxy_server: ProxyServer,
     in_flight_requests_counter: InFlightRequestsCounter,
-    mut shutdown_receiver: watch::Receiver<()>,
+    shutdown_receiver: watch::Receiver<()>,
     grpc_config: Option<GrpcConfig>,
-) -> Result<(), tonic::transport::Error> {
-    let address: SocketAddr = listen_config.addr.parse().unwrap();
+) -> Result<(), String> {
+    let address: SocketAddr = listen_config
+        .addr
+        .parse()
+        .map_err(|err| format!("Invalid listen address {}: {err}", listen_config.addr))?;
     let incoming = AddrIncoming::bind(&address).expect("failed to bind port");
     log::info!(
         "Serving proxy on {address} with auth scheme {:?}",
         listen_config.auth_scheme
     );
-    proxy_server
-        .serve_with_incoming_shutdown(
+    let drain_deadline = grpc_config
+        .clone()
+        .unwrap_or_default()
+        .shutdown_drain_deadline();
+    serve_with_drain_deadline(
+        proxy_server.serve_listener(
+            listen_config,
             AddrIncomingWithStream(incoming),
-            async move { while shutdown_receiver.changed().await.is_ok() {} },
-            listen_config
-                .auth_scheme
-                .expect("Must set auth_scheme in config"),
-            listen_config.allowed_service_names.into_iter().collect(),
+            wait_for_shutdown(shutdown_receiver.clone()),
             grpc_config,
-            in_flight_requests_counter,
-        )
-        .await
+            in_flight_requests_counter.clone(),
+        ),
+        shutdown_receiver,
+        drain_deadline,
+        in_flight_requests_counter,
+    )
+    .await
 }
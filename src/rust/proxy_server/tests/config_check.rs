@@ -0,0 +1,91 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn config_check(dir: &Path, config: &str) -> Output {
+    let config_path = dir.join("config.yaml");
+    std::fs::write(&config_path, config).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_proxy_server"))
+        .arg("-c")
+        .arg(&config_path)
+        .arg("--config-check")
+        .env_remove("TOOLCHAIN_ALLOW_NO_AUTH")
+        .output()
+        .unwrap()
+}
+
+fn config(jwk_set: &Path, auth_scheme: &str, cas_backend: &str) -> String {
+    format!(
+        r#"
+listen_addresses:
+  - addr: "127.0.0.1:0"
+    auth_scheme: "{auth_scheme}"
+    allowed_service_names: []
+jwk_set_path: "{}"
+backends:
+  main:
+    address: "cas.invalid:8980"
+default_backends:
+  cas: "{cas_backend}"
+  action_cache: "main"
+"#,
+        jwk_set.display()
+    )
+}
+
+fn write_jwk_set(dir: &Path) -> std::path::PathBuf {
+    let jwk_set = dir.join("jwk-set");
+    std::fs::write(&jwk_set, r#"{"keys": []}"#).unwrap();
+    jwk_set
+}
+
+#[test]
+fn config_check_accepts_valid_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let jwk_set = write_jwk_set(dir.path());
+
+    let output = config_check(dir.path(), &config(&jwk_set, "jwt", "main"));
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn config_check_rejects_unknown_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    let jwk_set = write_jwk_set(dir.path());
+
+    let output = config_check(dir.path(), &config(&jwk_set, "jwt", "other"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Unknown backend names present in catch-all backend configuration: other"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn config_check_rejects_missing_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    let jwk_set = dir.path().join("jwk-set");
+
+    let output = config_check(dir.path(), &config(&jwk_set, "jwt", "main"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to read JWT keys"), "{stderr}");
+}
+
+#[test]
+fn config_check_rejects_no_auth_outside_dev_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let jwk_set = write_jwk_set(dir.path());
+
+    let output = config_check(dir.path(), &config(&jwk_set, "dev_only_no_auth", "main"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("dev_only_no_auth"), "{stderr}");
+}
@@ -0,0 +1,110 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use digest::Digest;
+use futures::StreamExt;
+
+use crate::api::InnerServer;
+use crate::driver::{BoxReadStream, Instance};
+
+/// `tracing` target used for access log events so they can be filtered independently of the
+/// rest of the storage server's logs.
+pub(super) const ACCESS_LOG_TARGET: &str = "storage::access_log";
+
+/// A single access log entry for a CAS operation. The entry is emitted when it is dropped so
+/// that operations abandoned by the client (e.g., a partially-consumed read stream) are still
+/// logged with a `cancelled` outcome.
+///
+/// Only the fields below are logged. Request metadata (and in particular the `authorization`
+/// header) is never part of an access log entry.
+pub(super) struct AccessLogEntry {
+    method: &'static str,
+    instance: Instance,
+    digest: Option<Digest>,
+    num_bytes: usize,
+    outcome: tonic::Code,
+}
+
+impl AccessLogEntry {
+    pub fn add_bytes(&mut self, num_bytes: usize) {
+        self.num_bytes += num_bytes;
+    }
+
+    pub fn set_outcome(&mut self, outcome: tonic::Code) {
+        self.outcome = outcome;
+    }
+}
+
+impl Drop for AccessLogEntry {
+    fn drop(&mut self) {
+        let (digest_hash, digest_size) = match self.digest {
+            Some(d) => (d.hex(), d.size_bytes),
+            None => (String::new(), 0),
+        };
+        tracing::info!(
+            target: ACCESS_LOG_TARGET,
+            instance = %self.instance.name,
+            method = self.method,
+            digest_hash = %digest_hash,
+            digest_size = digest_size,
+            num_bytes = self.num_bytes,
+            outcome = ?self.outcome,
+            "access"
+        );
+    }
+}
+
+impl InnerServer {
+    /// Start an access log entry for an operation. Returns `None` if access logging is disabled.
+    pub(super) fn access_log_entry(
+        &self,
+        method: &'static str,
+        instance: &Instance,
+        digest: Option<Digest>,
+    ) -> Option<AccessLogEntry> {
+        if !self.access_log {
+            return None;
+        }
+        Some(AccessLogEntry {
+            method,
+            instance: instance.clone(),
+            digest,
+            num_bytes: 0,
+            outcome: tonic::Code::Cancelled,
+        })
+    }
+
+    /// Log a completed operation to the access log (if enabled).
+    pub(super) fn log_access(
+        &self,
+        method: &'static str,
+        instance: &Instance,
+        digest: Option<Digest>,
+        num_bytes: usize,
+        outcome: tonic::Code,
+    ) {
+        if let Some(mut entry) = self.access_log_entry(method, instance, digest) {
+            entry.add_bytes(num_bytes);
+            entry.set_outcome(outcome);
+        }
+    }
+}
+
+/// Wrap a read stream so that the given access log entry records the number of bytes actually
+/// streamed to the client and is emitted once the stream completes, fails, or is dropped.
+pub(super) fn log_read_stream(
+    mut stream: BoxReadStream,
+    mut entry: AccessLogEntry,
+) -> BoxReadStream {
+    Box::pin(async_stream::stream! {
+        let mut code = tonic::Code::Ok;
+        while let Some(chunk) = stream.next().await {
+            match &chunk {
+                Ok(c) => entry.add_bytes(c.len()),
+                Err(err) => code = tonic::Status::from(err.clone()).code(),
+            }
+            yield chunk;
+        }
+        entry.set_outcome(code);
+    })
+}
@@ -57,7 +57,7 @@ impl ActionCacheService {
                 16 * 1024 * 1024,
                 None,
                 None,
-                DriverState,
+                DriverState::default(),
             )
             .await?
         {
@@ -130,7 +130,7 @@ impl ActionCacheService {
         let missing_digests = self
             .inner
             .cas
-            .find_missing_blobs(instance, digests, DriverState)
+            .find_missing_blobs(instance, digests, DriverState::default())
             .await?;
         Ok(missing_digests.is_empty())
     }
@@ -151,6 +151,7 @@ impl ActionCache for ActionCacheService {
         let instance = Instance {
             name: request.instance_name,
         };
+        self.inner.ensure_instance(&instance);
 
         let action_digest = required_digest("action_digest", request.action_digest)
             .map_err(Status::invalid_argument)?;
@@ -164,7 +165,7 @@ impl ActionCache for ActionCacheService {
                 16 * 1024 * 1024,
                 None,
                 None,
-                DriverState,
+                DriverState::default(),
             )
             .await
             .map_err(Status::internal)?;
@@ -209,6 +210,7 @@ impl ActionCache for ActionCacheService {
                     "complete" => if action_result_complete { "true".to_owned() } else { "false".to_owned() },
                 );
                 if !action_result_complete {
+                    metrics::counter!("toolchain_storage_ac_incomplete_total", 1);
                     return Err(Status::not_found("Not found"));
                 }
             }
@@ -237,7 +239,14 @@ impl ActionCache for ActionCacheService {
 
             async move {
                 let stream_opt = cas
-                    .read_blob(instance, digest, 16 * 1024 * 1024, None, None, DriverState)
+                    .read_blob(
+                        instance,
+                        digest,
+                        16 * 1024 * 1024,
+                        None,
+                        None,
+                        DriverState::default(),
+                    )
                     .await
                     .ok()?;
 
@@ -301,6 +310,8 @@ impl ActionCache for ActionCacheService {
         let instance = Instance {
             name: request.instance_name,
         };
+        self.inner.ensure_instance(&instance);
+        self.inner.check_writable(&instance)?;
 
         let action_digest = required_digest("action_digest", request.action_digest)
             .map_err(Status::invalid_argument)?;
@@ -320,7 +331,7 @@ impl ActionCache for ActionCacheService {
         ) -> Result<(), Status> {
             let write = async move {
                 let mut attempt = storage
-                    .begin_write_blob(instance, digest, DriverState)
+                    .begin_write_blob(instance, digest, DriverState::default())
                     .await?;
                 attempt.write(data).await?;
                 attempt.commit().await
@@ -0,0 +1,52 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::sync::Arc;
+
+use protos::toolchain::storage::admin::v1::{
+    storage_admin_server::StorageAdmin, DeleteBlobsRequest, DeleteBlobsResponse,
+};
+use tonic::{Request, Response, Status};
+
+use crate::api::{convert_digests, InnerServer};
+use crate::driver::{DriverState, Instance};
+
+/// Administrative operations on the CAS. This service is only served when enabled since it
+/// performs destructive operations; access control is enforced by the proxy.
+pub(super) struct AdminService {
+    pub(super) inner: Arc<InnerServer>,
+}
+
+#[tonic::async_trait]
+impl StorageAdmin for AdminService {
+    #[tracing::instrument(skip_all, fields(opentelemetry = true))]
+    async fn delete_blobs(
+        &self,
+        request: Request<DeleteBlobsRequest>,
+    ) -> Result<Response<DeleteBlobsResponse>, Status> {
+        let request = request.into_inner();
+        let instance = Instance {
+            name: request.instance_name,
+        };
+        self.inner.check_writable(&instance)?;
+        self.inner.ensure_instance(&instance);
+        let digests = convert_digests(request.blob_digests)?;
+        let mut log_entry = self.inner.access_log_entry("DeleteBlobs", &instance, None);
+        let deleted_digests = self
+            .inner
+            .cas
+            .delete_blobs(instance, digests, DriverState::default())
+            .await
+            .map_err(Status::from);
+        if let Some(entry) = log_entry.as_mut() {
+            entry.set_outcome(match &deleted_digests {
+                Ok(_) => tonic::Code::Ok,
+                Err(status) => status.code(),
+            });
+        }
+        let response = DeleteBlobsResponse {
+            deleted_blob_digests: deleted_digests?.into_iter().map(|d| d.into()).collect(),
+        };
+        Ok(Response::new(response))
+    }
+}
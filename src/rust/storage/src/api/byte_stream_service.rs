@@ -6,32 +6,121 @@ use std::sync::Arc;
 
 use digest::Digest;
 use futures::{Stream, StreamExt};
+use protos::build::bazel::remote::execution::v2::compressor::Value as Compressor;
 use protos::google::bytestream::byte_stream_server::ByteStream;
 use protos::google::bytestream::{
     QueryWriteStatusRequest, QueryWriteStatusResponse, ReadRequest, ReadResponse, WriteRequest,
     WriteResponse,
 };
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::UnboundedReceiverStream;
 use tonic::{Request, Response, Status, Streaming};
 
+use crate::api::access_log::log_read_stream;
+use crate::api::compression::{compress_stream, parse_compressor, Decompressor};
 use crate::api::sync_wrapper::SyncWrapper;
 use crate::api::InnerServer;
-use crate::driver::{DriverState, Instance, StorageError, StreamingWriteError};
+use crate::driver::{BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError};
 
 pub(super) struct ByteStreamService {
     pub(super) inner: Arc<InnerServer>,
 }
 
+/// Aborts the task reading ahead from a write stream once the write has completed.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Read ahead from a client's write stream, buffering at most `max_buffered_bytes` of data which
+/// has not yet been consumed by the returned stream (plus the message currently being read). Each
+/// message counts as at least one byte, so that empty messages are bounded as well.
+fn buffer_write_stream(
+    mut stream: Streaming<WriteRequest>,
+    max_buffered_bytes: usize,
+) -> (
+    impl Stream<Item = Result<WriteRequest, Status>> + Unpin,
+    AbortOnDrop,
+) {
+    let max_permits = max_buffered_bytes.clamp(1, u32::MAX as usize);
+    let buffered_bytes = Arc::new(Semaphore::new(max_permits));
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let reader = tokio::spawn(async move {
+        while let Some(msg) = stream.next().await {
+            let size = msg.as_ref().map(|msg| msg.data.len()).unwrap_or_default();
+            let permits = size.clamp(1, max_permits) as u32;
+            let Ok(permit) = buffered_bytes.clone().acquire_many_owned(permits).await else {
+                break;
+            };
+            if sender.send((msg, permit)).is_err() {
+                break;
+            }
+        }
+    });
+    // The permits of a message are released once it has been consumed.
+    let stream = UnboundedReceiverStream::new(receiver).map(|(msg, _permit)| msg);
+    (stream, AbortOnDrop(reader))
+}
+
 #[derive(Debug, Eq, PartialEq)]
 struct ParsedWriteResourceName<'a> {
     instance_name: &'a str,
-    _uuid: &'a str,
+    uuid: &'a str,
+    compressor: Compressor,
     hash: &'a str,
     size: usize,
 }
 
-/// Parses a resource name of the form `{instance_name}/uploads/{uuid}/blobs/{hash}/{size}` into
-/// a struct with references to the individual components of the resource name. The
-/// `{instance_name}` may be blank (with no leading slash).
+impl ParsedWriteResourceName<'_> {
+    /// The name which identifies this upload for resumption, ignoring any trailing components.
+    fn upload_name(&self) -> String {
+        format!(
+            "{}/uploads/{}/{}/{}/{}",
+            self.instance_name,
+            self.uuid,
+            blobs_component(self.compressor),
+            self.hash,
+            self.size
+        )
+    }
+}
+
+/// The path component(s) which introduce the digest in a resource name using `compressor`.
+fn blobs_component(compressor: Compressor) -> String {
+    match compressor {
+        Compressor::Identity => "blobs".to_owned(),
+        compressor => format!(
+            "compressed-blobs/{}",
+            compressor.as_str_name().to_ascii_lowercase()
+        ),
+    }
+}
+
+/// Parses the `blobs` or `compressed-blobs/{compressor}` path component(s) at `index` of a
+/// resource name, returning the compressor and the index of the `{hash}` component.
+fn parse_blobs_component(parts: &[&str], index: usize) -> Result<(Compressor, usize), String> {
+    match parts[index] {
+        "blobs" => Ok((Compressor::Identity, index + 1)),
+        "compressed-blobs" => {
+            let compressor = parts
+                .get(index + 1)
+                .ok_or_else(|| "Malformed resource name: missing compressor".to_owned())?;
+            let compressor = parse_compressor(compressor)
+                .map_err(|err| format!("Malformed resource name: {err}"))?;
+            Ok((compressor, index + 2))
+        }
+        _ => Err("Malformed resource name: expected `blobs` component".to_owned()),
+    }
+}
+
+/// Parses a resource name of the form `{instance_name}/uploads/{uuid}/blobs/{hash}/{size}` or
+/// `{instance_name}/uploads/{uuid}/compressed-blobs/{compressor}/{hash}/{size}` into a struct
+/// with references to the individual components of the resource name. The `{instance_name}`
+/// may be blank (with no leading slash).
 fn parse_write_resource_name(resource: &str) -> Result<ParsedWriteResourceName<'_>, String> {
     if resource.is_empty() {
         return Err("Missing resource name".to_owned());
@@ -53,11 +142,14 @@ fn parse_write_resource_name(resource: &str) -> Result<ParsedWriteResourceName<'
         );
     }
 
-    if parts[uploads_index + 2] != "blobs" {
-        return Err("Malformed resource name: expected `blobs` component".to_owned());
+    let (compressor, hash_index) = parse_blobs_component(&parts, uploads_index + 2)?;
+    if parts.len() < hash_index + 2 {
+        return Err(
+            "Malformed resource name: not enough path components after `uploads`".to_owned(),
+        );
     }
 
-    let size = parts[uploads_index + 4]
+    let size = parts[hash_index + 1]
         .parse::<usize>()
         .map_err(|_| "Malformed resource name: cannot parse size".to_owned())?;
 
@@ -71,8 +163,9 @@ fn parse_write_resource_name(resource: &str) -> Result<ParsedWriteResourceName<'
 
     Ok(ParsedWriteResourceName {
         instance_name,
-        _uuid: parts[uploads_index + 1],
-        hash: parts[uploads_index + 3],
+        uuid: parts[uploads_index + 1],
+        compressor,
+        hash: parts[hash_index],
         size,
     })
 }
@@ -80,13 +173,15 @@ fn parse_write_resource_name(resource: &str) -> Result<ParsedWriteResourceName<'
 #[derive(Debug, Eq, PartialEq)]
 struct ParsedReadResourceName<'a> {
     instance_name: &'a str,
+    compressor: Compressor,
     hash: &'a str,
     size: usize,
 }
 
-/// Parses a resource name of the form `"{instance_name}/blobs/{hash}/{size}"` into a struct
-/// with references to the individual components of the resource name. The `{instance_name}`
-/// may be blank (with no leading slash).
+/// Parses a resource name of the form `"{instance_name}/blobs/{hash}/{size}"` or
+/// `"{instance_name}/compressed-blobs/{compressor}/{hash}/{size}"` into a struct with
+/// references to the individual components of the resource name. The `{instance_name}` may be
+/// blank (with no leading slash).
 fn parse_read_resource_name(resource: &str) -> Result<ParsedReadResourceName<'_>, String> {
     if resource.is_empty() {
         return Err("Missing resource name".to_owned());
@@ -95,18 +190,22 @@ fn parse_read_resource_name(resource: &str) -> Result<ParsedReadResourceName<'_>
     // Parse the resource name into parts separated by slashes (/).
     let parts: Vec<_> = resource.split('/').collect();
 
-    // Search for the `blobs` path component.
-    let blobs_index = match parts.iter().position(|p| *p == "blobs") {
+    // Search for the `blobs` (or `compressed-blobs`) path component.
+    let blobs_index = match parts
+        .iter()
+        .position(|p| *p == "blobs" || *p == "compressed-blobs")
+    {
         Some(index) => index,
         None => return Err("Malformed resource name: missing `blobs` component".to_owned()),
     };
     let instance_parts = &parts[0..blobs_index];
 
-    if (parts.len() - blobs_index) < 3 {
+    let (compressor, hash_index) = parse_blobs_component(&parts, blobs_index)?;
+    if parts.len() < hash_index + 2 {
         return Err("Malformed resource name: not enough path components after `blobs`".to_owned());
     }
 
-    let size = parts[blobs_index + 2]
+    let size = parts[hash_index + 1]
         .parse::<usize>()
         .map_err(|_| "Malformed resource name: cannot parse size".to_owned())?;
 
@@ -120,7 +219,8 @@ fn parse_read_resource_name(resource: &str) -> Result<ParsedReadResourceName<'_>
 
     Ok(ParsedReadResourceName {
         instance_name,
-        hash: parts[blobs_index + 1],
+        compressor,
+        hash: parts[hash_index],
         size,
     })
 }
@@ -146,6 +246,8 @@ impl ByteStream for ByteStreamService {
         let instance = Instance {
             name: parsed_resource_name.instance_name.to_owned(),
         };
+        self.inner.ensure_instance(&instance);
+        let instance_name = instance.name.clone();
 
         let read_offset = match request.read_offset {
             x if x < 0 => return Err(Status::out_of_range("negative read_offset")),
@@ -161,9 +263,20 @@ impl ByteStream for ByteStreamService {
         let read_limit = match request.read_limit {
             x if x < 0 => return Err(Status::out_of_range("negative read_limit")),
             0 => None,
+            // The limit would apply to the compressed data, which has no fixed relation to the
+            // uncompressed offsets of the blob.
+            _ if parsed_resource_name.compressor != Compressor::Identity => {
+                return Err(Status::invalid_argument(
+                    "read_limit is not supported for compressed-blobs",
+                ))
+            }
             x => Some(x as usize),
         };
 
+        let mut log_entry = self
+            .inner
+            .access_log_entry("ByteStream.Read", &instance, Some(digest));
+
         let chunk_stream = match self
             .inner
             .cas
@@ -173,20 +286,47 @@ impl ByteStream for ByteStreamService {
                 4 * 1024,
                 read_offset,
                 read_limit,
-                DriverState,
+                DriverState::default(),
             )
             .await
         {
             Ok(Some(stream)) => {
+                let stream = compress_stream(stream, parsed_resource_name.compressor);
+                let stream = match log_entry.take() {
+                    Some(entry) => log_read_stream(stream, entry),
+                    None => stream,
+                };
+                let stream = record_read_blob_size(stream, digest);
                 let stream = stream.map(move |chunk| {
                     chunk
-                        .map(|c| ReadResponse { data: c })
+                        .map(|c| {
+                            metrics::counter!(
+                                "toolchain_bytestream_read_bytes_total",
+                                c.len() as u64,
+                                "instance" => instance_name.clone(),
+                            );
+                            ReadResponse { data: c }
+                        })
                         .map_err(Status::from)
                 });
                 Box::pin(stream)
             }
-            Ok(None) => return Err(Status::not_found("")),
-            Err(err) => return Err(Status::internal(err)),
+            Ok(None) => {
+                if let Some(entry) = log_entry.as_mut() {
+                    entry.set_outcome(tonic::Code::NotFound);
+                }
+                return Err(Status::not_found(""));
+            }
+            Err(err) => {
+                let status = match err {
+                    StorageError::NotFound(_) => Status::from(err),
+                    err => Status::internal(err),
+                };
+                if let Some(entry) = log_entry.as_mut() {
+                    entry.set_outcome(status.code());
+                }
+                return Err(status);
+            }
         };
 
         Ok(Response::new(SyncWrapper::new(chunk_stream)))
@@ -213,18 +353,85 @@ impl ByteStream for ByteStreamService {
         let digest = Digest::new(parsed_resource_name.hash, parsed_resource_name.size)
             .map_err(Status::invalid_argument)?;
 
+        // The size of the blob is known from its digest, so a blob which is too large is rejected
+        // before any of its content is read.
+        let write_limits = self.inner.write_limits;
+        let exceeds_max_blob_size = move |size: usize| match write_limits.max_blob_size_bytes {
+            Some(max_blob_size_bytes) if size > max_blob_size_bytes => Some(format!(
+                "write exceeds the maximum blob size of {max_blob_size_bytes} bytes"
+            )),
+            _ => None,
+        };
+        if let Some(msg) = exceeds_max_blob_size(digest.size_bytes) {
+            return Err(Status::resource_exhausted(msg));
+        }
+
         let instance = Instance {
             name: parsed_resource_name.instance_name.to_owned(),
         };
+        self.inner.ensure_instance(&instance);
+        self.inner.check_writable(&instance)?;
+        let instance_name = instance.name.clone();
+        let upload_name = parsed_resource_name.upload_name();
+        let compressor = parsed_resource_name.compressor;
+
+        // Read ahead while earlier chunks are being written, but only by a bounded amount: the
+        // client must otherwise wait for the storage driver to absorb the data.
+        let (mut stream, _reader) = buffer_write_stream(stream, write_limits.max_buffered_bytes);
+
+        let mut log_entry =
+            self.inner
+                .access_log_entry("ByteStream.Write", &instance, Some(digest));
 
         let write = async move {
-            let mut attempt = self
-                .inner
-                .cas
-                .begin_write_blob(instance, digest, DriverState)
-                .await?;
+            // Compressed uploads are decompressed before being written, so that the blob is
+            // stored (and its digest verified) in its uncompressed form. They cannot be resumed,
+            // because the state of the decompressor is not kept.
+            let mut decompressor = Decompressor::new(compressor, digest.size_bytes)?;
+
+            // A write starting at offset 0 always starts over. A non-zero offset must continue
+            // exactly where an interrupted upload left off.
+            let partial_upload = self.inner.partial_uploads.take(&upload_name);
+            let resumed_upload = match partial_upload {
+                _ if msg.write_offset == 0 => None,
+                Some(upload) if upload.committed_size == msg.write_offset => Some(upload),
+                partial_upload => {
+                    let committed_size = partial_upload
+                        .as_ref()
+                        .map(|upload| upload.committed_size)
+                        .unwrap_or(0);
+                    if let Some(upload) = partial_upload {
+                        // Keep the upload so that the client can retry at the right offset.
+                        self.inner.partial_uploads.insert(
+                            upload_name,
+                            upload.attempt,
+                            upload.committed_size,
+                        );
+                    }
+                    return Err(StreamingWriteError::StorageError(
+                        StorageError::InvalidArgument(format!(
+                            "write_offset {} does not match committed size {committed_size}",
+                            msg.write_offset
+                        )),
+                    ));
+                }
+            };
+            let (mut attempt, mut committed_size) = match resumed_upload {
+                Some(upload) => (upload.attempt, upload.committed_size),
+                None => (
+                    self.inner
+                        .cas
+                        .begin_write_blob(instance, digest, DriverState::default())
+                        .await?,
+                    0,
+                ),
+            };
+
+            // The size of the (uncompressed) data written so far, which is limited rather than
+            // the size of the messages, since compressed data may be larger than the blob.
+            // Resumed uploads are never compressed.
+            let mut written_size = committed_size as usize;
 
-            let mut committed_size: i64 = 0;
             let mut next_msg = Some(msg);
             while let Some(msg) = next_msg {
                 let chunk_size = msg.data.len() as i64;
@@ -237,8 +444,25 @@ impl ByteStream for ByteStreamService {
                 }
 
                 // Write the current data into the write attempt.
-                if !msg.data.is_empty() {
-                    attempt.write(msg.data).await?;
+                let data = match decompressor.as_mut() {
+                    Some(decompressor) => decompressor.decompress(&msg.data)?,
+                    None => msg.data,
+                };
+                written_size += data.len();
+                if let Some(msg) = exceeds_max_blob_size(written_size) {
+                    return Err(StreamingWriteError::StorageError(
+                        StorageError::ResourceExhausted(msg),
+                    ));
+                }
+                if !data.is_empty() {
+                    attempt.write(data).await?;
+                }
+                if chunk_size > 0 {
+                    metrics::counter!(
+                        "toolchain_bytestream_write_bytes_total",
+                        chunk_size as u64,
+                        "instance" => instance_name.clone(),
+                    );
                 }
 
                 committed_size += chunk_size;
@@ -250,19 +474,40 @@ impl ByteStream for ByteStreamService {
                 next_msg = match stream.next().await {
                     Some(Ok(m)) => Some(m),
                     Some(Err(status)) => {
+                        // Keep the upload so that the client can resume it.
+                        if decompressor.is_none() {
+                            self.inner
+                                .partial_uploads
+                                .insert(upload_name, attempt, committed_size);
+                        }
                         return Err(StreamingWriteError::StorageError(StorageError::Cancelled(
                             format!("client stream error: {status}"),
-                        )))
+                        )));
                     }
                     None => {
+                        if decompressor.is_none() {
+                            self.inner
+                                .partial_uploads
+                                .insert(upload_name, attempt, committed_size);
+                        }
                         return Err(StreamingWriteError::StorageError(StorageError::Cancelled(
                             "write stream closed without specifying finish_write".to_owned(),
-                        )))
+                        )));
                     }
                 };
             }
 
-            if committed_size != digest.size_bytes as i64 {
+            let uncompressed_size = match decompressor.as_mut() {
+                Some(decompressor) => {
+                    let data = decompressor.finish()?;
+                    if !data.is_empty() {
+                        attempt.write(data).await?;
+                    }
+                    decompressor.decompressed_size() as i64
+                }
+                None => committed_size,
+            };
+            if uncompressed_size != digest.size_bytes as i64 {
                 return Err(StreamingWriteError::StorageError(
                     StorageError::InvalidArgument(
                         "committed size does not match digest size".to_owned(),
@@ -277,32 +522,84 @@ impl ByteStream for ByteStreamService {
         };
 
         // If a blob already exists, we should early return with the full size of the digest as
-        // the committed_size. See:
+        // the committed_size, or -1 for a compressed upload. See:
         // https://github.com/pantsbuild/pants/blob/89d686fd5fbbec1290cdf32c961af56bc06e1e2e/src/rust/engine/protos/protos/bazelbuild_remote-apis/build/bazel/remote/execution/v2/remote_execution.proto#L250-L254
         let committed_size = write
             .await
             .or_else(|e| match e {
+                StreamingWriteError::AlreadyExists if compressor != Compressor::Identity => Ok(-1),
                 StreamingWriteError::AlreadyExists => Ok(digest.size_bytes as i64),
                 StreamingWriteError::StorageError(e) => Err(e),
             })
-            .map_err(Status::from)?;
+            .map_err(Status::from);
+
+        if committed_size.is_ok() {
+            record_blob_size("write", digest);
+        }
+        if let Some(entry) = log_entry.as_mut() {
+            match &committed_size {
+                Ok(size) => {
+                    // A compressed upload of an existing blob reports a size of -1.
+                    entry.add_bytes((*size).max(0) as usize);
+                    entry.set_outcome(tonic::Code::Ok);
+                }
+                Err(status) => entry.set_outcome(status.code()),
+            }
+        }
+        let committed_size = committed_size?;
 
         Ok(Response::new(WriteResponse { committed_size }))
     }
 
     /// Query status of a resumable write.
-    /// TODO: No need to support this yet because Pants does not support it.
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
     async fn query_write_status(
         &self,
-        _request: Request<QueryWriteStatusRequest>,
+        request: Request<QueryWriteStatusRequest>,
     ) -> Result<Response<QueryWriteStatusResponse>, Status> {
-        Err(Status::unimplemented("Not supported"))
+        let request = request.into_inner();
+
+        let parsed_resource_name =
+            parse_write_resource_name(&request.resource_name).map_err(Status::invalid_argument)?;
+
+        let digest = Digest::new(parsed_resource_name.hash, parsed_resource_name.size)
+            .map_err(Status::invalid_argument)?;
+
+        if let Some(committed_size) = self
+            .inner
+            .partial_uploads
+            .committed_size(&parsed_resource_name.upload_name())
+        {
+            return Ok(Response::new(QueryWriteStatusResponse {
+                committed_size,
+                complete: false,
+            }));
+        }
+
+        let instance = Instance {
+            name: parsed_resource_name.instance_name.to_owned(),
+        };
+        self.inner.ensure_instance(&instance);
+        let missing = self
+            .inner
+            .cas
+            .find_missing_blobs(instance, vec![digest], DriverState::default())
+            .await?;
+        if !missing.is_empty() {
+            return Err(Status::not_found("no upload in progress for resource"));
+        }
+
+        Ok(Response::new(QueryWriteStatusResponse {
+            committed_size: digest.size_bytes as i64,
+            complete: true,
+        }))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use protos::build::bazel::remote::execution::v2::compressor::Value as Compressor;
+
     use super::{
         parse_read_resource_name, parse_write_resource_name, ParsedReadResourceName,
         ParsedWriteResourceName,
@@ -315,7 +612,8 @@ mod tests {
             result,
             ParsedWriteResourceName {
                 instance_name: "main",
-                _uuid: "uuid-12345",
+                uuid: "uuid-12345",
+                compressor: Compressor::Identity,
                 hash: "abc123",
                 size: 12,
             }
@@ -326,7 +624,8 @@ mod tests {
             result,
             ParsedWriteResourceName {
                 instance_name: "",
-                _uuid: "uuid-12345",
+                uuid: "uuid-12345",
+                compressor: Compressor::Identity,
                 hash: "abc123",
                 size: 12,
             }
@@ -337,11 +636,30 @@ mod tests {
             result,
             ParsedWriteResourceName {
                 instance_name: "a/b/c",
-                _uuid: "uuid-12345",
+                uuid: "uuid-12345",
+                compressor: Compressor::Identity,
+                hash: "abc123",
+                size: 12,
+            }
+        );
+
+        let result =
+            parse_write_resource_name("main/uploads/uuid-12345/compressed-blobs/zstd/abc123/12")
+                .unwrap();
+        assert_eq!(
+            result,
+            ParsedWriteResourceName {
+                instance_name: "main",
+                uuid: "uuid-12345",
+                compressor: Compressor::Zstd,
                 hash: "abc123",
                 size: 12,
             }
         );
+        assert_eq!(
+            result.upload_name(),
+            "main/uploads/uuid-12345/compressed-blobs/zstd/abc123/12"
+        );
 
         // extra components after the size are accepted
         let result =
@@ -351,7 +669,8 @@ mod tests {
             result,
             ParsedWriteResourceName {
                 instance_name: "a/b/c",
-                _uuid: "uuid-12345",
+                uuid: "uuid-12345",
+                compressor: Compressor::Identity,
                 hash: "abc123",
                 size: 12,
             }
@@ -379,6 +698,21 @@ mod tests {
         let err =
             parse_write_resource_name("main/uploads/uuid-12345/blobs/abc123/-12").unwrap_err();
         assert_eq!(err, "Malformed resource name: cannot parse size");
+
+        let err = parse_write_resource_name("main/uploads/uuid-12345/compressed-blobs/zstd/abc123")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Malformed resource name: not enough path components after `uploads`"
+        );
+
+        let err =
+            parse_write_resource_name("main/uploads/uuid-12345/compressed-blobs/lz4/abc123/12")
+                .unwrap_err();
+        assert_eq!(
+            err,
+            "Malformed resource name: Unsupported compressor: `lz4`"
+        );
     }
 
     #[test]
@@ -388,6 +722,7 @@ mod tests {
             result,
             ParsedReadResourceName {
                 instance_name: "main",
+                compressor: Compressor::Identity,
                 hash: "abc123",
                 size: 12,
             }
@@ -398,6 +733,7 @@ mod tests {
             result,
             ParsedReadResourceName {
                 instance_name: "",
+                compressor: Compressor::Identity,
                 hash: "abc123",
                 size: 12,
             }
@@ -408,6 +744,18 @@ mod tests {
             result,
             ParsedReadResourceName {
                 instance_name: "a/b/c",
+                compressor: Compressor::Identity,
+                hash: "abc123",
+                size: 12,
+            }
+        );
+
+        let result = parse_read_resource_name("main/compressed-blobs/zstd/abc123/12").unwrap();
+        assert_eq!(
+            result,
+            ParsedReadResourceName {
+                instance_name: "main",
+                compressor: Compressor::Zstd,
                 hash: "abc123",
                 size: 12,
             }
@@ -431,5 +779,17 @@ mod tests {
         // negative size should be rejected
         let err = parse_read_resource_name("main/blobs/abc123/-12").unwrap_err();
         assert_eq!(err, "Malformed resource name: cannot parse size");
+
+        let err = parse_read_resource_name("main/compressed-blobs/zstd/12").unwrap_err();
+        assert_eq!(
+            err,
+            "Malformed resource name: not enough path components after `blobs`"
+        );
+
+        let err = parse_read_resource_name("main/compressed-blobs/lz4/abc123/12").unwrap_err();
+        assert_eq!(
+            err,
+            "Malformed resource name: Unsupported compressor: `lz4`"
+        );
     }
 }
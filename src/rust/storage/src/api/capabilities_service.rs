@@ -4,8 +4,9 @@
 use std::sync::Arc;
 
 use protos::build::bazel::remote::execution::v2::{
-    capabilities_server::Capabilities, digest_function::Value as DigestFunction_Value,
-    ActionCacheUpdateCapabilities, CacheCapabilities, GetCapabilitiesRequest, ServerCapabilities,
+    capabilities_server::Capabilities, compressor::Value as Compressor,
+    digest_function::Value as DigestFunction_Value, ActionCacheUpdateCapabilities,
+    CacheCapabilities, GetCapabilitiesRequest, ServerCapabilities,
 };
 use tonic::{Request, Response, Status};
 
@@ -31,6 +32,7 @@ impl Capabilities for CapabilitiesService {
                     // authorized to write to the Action Cache.
                     update_enabled: true,
                 }),
+                supported_compressors: vec![Compressor::Zstd as i32],
                 ..CacheCapabilities::default()
             }),
             ..ServerCapabilities::default()
@@ -4,9 +4,9 @@
 use std::convert::TryInto;
 use std::sync::Arc;
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::Bytes;
 use digest::Digest;
-use futures::{future, StreamExt};
+use futures::future;
 use tonic::{Request, Response, Status};
 
 use protos::build::bazel::remote::execution::v2::{
@@ -17,19 +17,19 @@ use protos::build::bazel::remote::execution::v2::{
 };
 
 use crate::api::{convert_digests, InnerServer};
-use crate::driver::{DriverState, Instance, StreamingWriteError};
+use crate::driver::{DriverState, Instance, StorageError, StreamingWriteError};
 
 pub(super) struct CasService {
     pub(super) inner: Arc<InnerServer>,
 }
 
 impl CasService {
-    /// Reads a single blob out of a `BlobStorage` and consolidates all chunks into a single
-    /// `Bytes`. Returns the response struct used by the `batch_read_blobs` RPC implementation.
-    async fn read_blob(
-        &self,
-        instance: &Instance,
+    /// Converts the result of reading a blob with `BlobStorage::read_blobs` (or `None` for an
+    /// invalid digest) into the response struct used by the `batch_read_blobs` RPC
+    /// implementation.
+    fn make_read_response(
         api_digest: ApiDigest,
+        read_result: Option<(Digest, Result<Option<Bytes>, StorageError>)>,
     ) -> batch_read_blobs_response::Response {
         fn make_response(
             digest: ApiDigest,
@@ -47,10 +47,18 @@ impl CasService {
             }
         }
 
-        // Convert the Digest proto into the internal Digest struct.
-        let digest: Digest = match api_digest.clone().try_into() {
-            Ok(digest) => digest,
-            Err(_) => {
+        let (digest, data) = match read_result {
+            Some((digest, Ok(Some(data)))) => (digest, data),
+            Some((_, Ok(None))) => {
+                return make_response(api_digest, protos::google::rpc::Code::NotFound, "");
+            }
+            Some((_, Err(err @ StorageError::NotFound(_)))) => {
+                return make_response(api_digest, protos::google::rpc::Code::NotFound, err);
+            }
+            Some((_, Err(err))) => {
+                return make_response(api_digest, protos::google::rpc::Code::Internal, err);
+            }
+            None => {
                 return make_response(
                     api_digest,
                     protos::google::rpc::Code::InvalidArgument,
@@ -59,50 +67,22 @@ impl CasService {
             }
         };
 
-        // Obtain a stream of data chunks for this blob.
-        let mut buffer = BytesMut::with_capacity(digest.size_bytes);
-        let mut stream = match self
-            .inner
-            .cas
-            .read_blob(instance.clone(), digest, 2048, None, None, DriverState)
-            .await
-        {
-            Ok(Some(stream)) => stream,
-            Ok(None) => {
-                return make_response(api_digest, protos::google::rpc::Code::NotFound, "");
-            }
-            Err(err) => {
-                return make_response(api_digest, protos::google::rpc::Code::Internal, err);
-            }
-        };
-
-        // Obtain each data chunk and append it to the consolidated buffer.
-        while let Some(chunk) = stream.next().await {
-            let chunk = match chunk {
-                Ok(c) => c,
-                Err(err) => {
-                    return make_response(api_digest, protos::google::rpc::Code::Internal, err)
-                }
-            };
-            buffer.put_slice(&chunk[..]);
-        }
-
-        // Ensure that the buffer length matches the expected length.
-        if buffer.len() != digest.size_bytes {
+        // Ensure that the content length matches the expected length.
+        if data.len() != digest.size_bytes {
             return make_response(
                 api_digest,
                 protos::google::rpc::Code::DataLoss,
                 format!(
                     "digest has wrong size (expected={}, actual={})",
                     digest.size_bytes,
-                    buffer.len()
+                    data.len()
                 ),
             );
         }
 
         batch_read_blobs_response::Response {
             digest: Some(api_digest),
-            data: buffer.freeze(),
+            data,
             status: Some(protos::google::rpc::Status {
                 code: protos::google::rpc::Code::Ok as i32,
                 ..protos::google::rpc::Status::default()
@@ -161,7 +141,7 @@ impl CasService {
             let mut attempt = self
                 .inner
                 .cas
-                .begin_write_blob(instance.clone(), digest, DriverState)
+                .begin_write_blob(instance.clone(), digest, DriverState::default())
                 .await?;
 
             attempt.write(data).await?;
@@ -179,6 +159,24 @@ impl CasService {
     }
 }
 
+/// Fail a batch API call whose blobs total more than the advertised `max_batch_total_size_bytes`.
+fn check_batch_size(
+    total_size_bytes: usize,
+    max_batch_total_size_bytes: usize,
+) -> Result<(), Status> {
+    if total_size_bytes > max_batch_total_size_bytes {
+        return Err(Status::invalid_argument(format!(
+            "total size of blobs in batch ({total_size_bytes} bytes) exceeds max_batch_total_size_bytes ({max_batch_total_size_bytes} bytes)"
+        )));
+    }
+    Ok(())
+}
+
+/// Render the status of a single batch API response as an access log outcome.
+fn rpc_outcome(status: Option<&protos::google::rpc::Status>) -> tonic::Code {
+    tonic::Code::from_i32(status.map(|s| s.code).unwrap_or_default())
+}
+
 #[tonic::async_trait]
 impl ContentAddressableStorage for CasService {
     #[tracing::instrument(skip_all, fields(opentelemetry = true))]
@@ -190,13 +188,24 @@ impl ContentAddressableStorage for CasService {
         let instance = Instance {
             name: request.instance_name,
         };
+        self.inner.ensure_instance(&instance);
         let digests = convert_digests(request.blob_digests)?;
+        let mut log_entry = self
+            .inner
+            .access_log_entry("FindMissingBlobs", &instance, None);
         let missing_digests = self
             .inner
             .cas
-            .find_missing_blobs(instance, digests, DriverState)
+            .find_missing_blobs(instance, digests, DriverState::default())
             .await
-            .map_err(Status::internal)?;
+            .map_err(Status::internal);
+        if let Some(entry) = log_entry.as_mut() {
+            entry.set_outcome(match &missing_digests {
+                Ok(_) => tonic::Code::Ok,
+                Err(status) => status.code(),
+            });
+        }
+        let missing_digests = missing_digests?;
         let response = FindMissingBlobsResponse {
             missing_blob_digests: missing_digests.into_iter().map(|d| d.into()).collect(),
         };
@@ -212,11 +221,30 @@ impl ContentAddressableStorage for CasService {
         let instance = Instance {
             name: request.instance_name,
         };
+        self.inner.ensure_instance(&instance);
+        self.inner.check_writable(&instance)?;
+
+        check_batch_size(
+            request.requests.iter().map(|req| req.data.len()).sum(),
+            self.inner.max_batch_total_size_bytes,
+        )?;
 
         let write_requests_futures: Vec<_> = request
             .requests
             .into_iter()
-            .map(|req| self.write_blob(&instance, req.digest, req.data))
+            .map(|req| async {
+                let num_bytes = req.data.len();
+                let digest = req.digest.clone().and_then(|d| d.try_into().ok());
+                let response = self.write_blob(&instance, req.digest, req.data).await;
+                self.inner.log_access(
+                    "BatchUpdateBlobs",
+                    &instance,
+                    digest,
+                    num_bytes,
+                    rpc_outcome(response.status.as_ref()),
+                );
+                response
+            })
             .collect();
 
         let responses = future::join_all(write_requests_futures).await;
@@ -233,14 +261,62 @@ impl ContentAddressableStorage for CasService {
         let instance = Instance {
             name: request.instance_name,
         };
+        self.inner.ensure_instance(&instance);
 
-        let read_futures: Vec<_> = request
+        // Convert the Digest protos into internal Digest structs. Invalid digests are reported
+        // in their responses rather than failing the whole request.
+        let digests: Vec<Option<Digest>> = request
             .digests
-            .into_iter()
-            .map(|digest| self.read_blob(&instance, digest))
+            .iter()
+            .map(|api_digest| api_digest.clone().try_into().ok())
             .collect();
+        check_batch_size(
+            digests
+                .iter()
+                .flatten()
+                .map(|digest| digest.size_bytes)
+                .sum(),
+            self.inner.max_batch_total_size_bytes,
+        )?;
 
-        let responses = future::join_all(read_futures).await;
+        // Read all of the valid digests at once. An error for the request as a whole is
+        // reported as the result of each read.
+        let valid_digests: Vec<Digest> = digests.iter().flatten().copied().collect();
+        let mut read_results = match self
+            .inner
+            .cas
+            .read_blobs(
+                instance.clone(),
+                valid_digests.clone(),
+                DriverState::default(),
+            )
+            .await
+        {
+            Ok(read_results) => read_results,
+            Err(err) => valid_digests
+                .into_iter()
+                .map(|digest| (digest, Err(err.clone())))
+                .collect(),
+        }
+        .into_iter();
+
+        let responses = request
+            .digests
+            .into_iter()
+            .zip(digests)
+            .map(|(api_digest, digest)| {
+                let read_result = digest.and_then(|_| read_results.next());
+                let response = Self::make_read_response(api_digest, read_result);
+                self.inner.log_access(
+                    "BatchReadBlobs",
+                    &instance,
+                    digest,
+                    response.data.len(),
+                    rpc_outcome(response.status.as_ref()),
+                );
+                response
+            })
+            .collect();
 
         Ok(Response::new(BatchReadBlobsResponse { responses }))
     }
@@ -0,0 +1,114 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::io::Write;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use protos::build::bazel::remote::execution::v2::compressor::Value as Compressor;
+
+use crate::driver::{BoxReadStream, StorageError};
+
+/// Parses the `{compressor}` component of a `compressed-blobs` resource name. Only the
+/// compressors advertised in `CacheCapabilities.supported_compressors` are accepted.
+pub(super) fn parse_compressor(name: &str) -> Result<Compressor, String> {
+    match name {
+        "identity" => Ok(Compressor::Identity),
+        "zstd" => Ok(Compressor::Zstd),
+        _ => Err(format!("Unsupported compressor: `{name}`")),
+    }
+}
+
+/// Compress a blob read stream on the fly.
+pub(super) fn compress_stream(stream: BoxReadStream, compressor: Compressor) -> BoxReadStream {
+    match compressor {
+        Compressor::Zstd => zstd_compress_stream(stream),
+        _ => stream,
+    }
+}
+
+fn zstd_compress_stream(mut stream: BoxReadStream) -> BoxReadStream {
+    Box::pin(async_stream::try_stream! {
+        let mut encoder =
+            zstd::stream::write::Encoder::new(Vec::new(), zstd::DEFAULT_COMPRESSION_LEVEL)
+                .map_err(compression_error)?;
+        while let Some(chunk) = stream.next().await {
+            encoder.write_all(&chunk?).map_err(compression_error)?;
+            let compressed = std::mem::take(encoder.get_mut());
+            if !compressed.is_empty() {
+                yield Bytes::from(compressed);
+            }
+        }
+        let compressed = encoder.finish().map_err(compression_error)?;
+        if !compressed.is_empty() {
+            yield Bytes::from(compressed);
+        }
+    })
+}
+
+fn compression_error(err: std::io::Error) -> StorageError {
+    StorageError::Internal(format!("Failed to compress blob: {err}"))
+}
+
+/// Incrementally decompresses the chunks of a compressed upload. Refuses to produce more than
+/// the expected uncompressed size so that a small upload cannot expand without bound.
+pub(super) struct Decompressor {
+    decoder: zstd::stream::write::Decoder<'static, Vec<u8>>,
+    expected_size: usize,
+    decompressed_size: usize,
+}
+
+impl Decompressor {
+    /// Returns `None` for `Compressor::Identity`, whose data needs no decompression.
+    pub(super) fn new(
+        compressor: Compressor,
+        expected_size: usize,
+    ) -> Result<Option<Decompressor>, StorageError> {
+        match compressor {
+            Compressor::Zstd => {
+                let decoder =
+                    zstd::stream::write::Decoder::new(Vec::new()).map_err(decompression_error)?;
+                Ok(Some(Decompressor {
+                    decoder,
+                    expected_size,
+                    decompressed_size: 0,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Decompress the next chunk of the upload, returning whatever uncompressed data is
+    /// available so far.
+    pub(super) fn decompress(&mut self, data: &[u8]) -> Result<Bytes, StorageError> {
+        self.decoder.write_all(data).map_err(decompression_error)?;
+        self.take_output()
+    }
+
+    /// Flush any remaining uncompressed data once the upload is complete.
+    pub(super) fn finish(&mut self) -> Result<Bytes, StorageError> {
+        self.decoder.flush().map_err(decompression_error)?;
+        self.take_output()
+    }
+
+    /// The total size of the uncompressed data produced so far.
+    pub(super) fn decompressed_size(&self) -> usize {
+        self.decompressed_size
+    }
+
+    fn take_output(&mut self) -> Result<Bytes, StorageError> {
+        let output = std::mem::take(self.decoder.get_mut());
+        self.decompressed_size += output.len();
+        if self.decompressed_size > self.expected_size {
+            return Err(StorageError::InvalidArgument(format!(
+                "decompressed data exceeds the expected size of {} bytes",
+                self.expected_size
+            )));
+        }
+        Ok(Bytes::from(output))
+    }
+}
+
+fn decompression_error(err: std::io::Error) -> StorageError {
+    StorageError::InvalidArgument(format!("Failed to decompress blob: {err}"))
+}
@@ -3,7 +3,9 @@
 
 #![allow(clippy::result_large_err)]
 
+use std::collections::HashSet;
 use std::convert::TryInto;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use cas_service::CasService;
@@ -12,10 +14,13 @@ use futures::{Future, Stream};
 use grpc_util::infra::GrpcConfig;
 use grpc_util::services::GrpcMetrics;
 use itertools::{Either, Itertools};
+use lru::LruCache;
+use parking_lot::RwLock;
 use protos::build::bazel::remote::execution::v2 as remoting_protos;
 use protos::build::bazel::remote::execution::v2::action_cache_server::ActionCacheServer;
 use protos::build::bazel::remote::execution::v2::capabilities_server::CapabilitiesServer;
 use protos::google::bytestream::byte_stream_server::ByteStreamServer;
+use protos::toolchain::storage::admin::v1::storage_admin_server::StorageAdminServer;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tonic::transport::server::Connected;
 use tonic::Status;
@@ -25,30 +30,134 @@ use tower_http::metrics::InFlightRequestsLayer;
 use tower_http::sensitive_headers::SetSensitiveHeadersLayer;
 
 use crate::api::action_cache_service::ActionCacheService;
+use crate::api::admin_service::AdminService;
 use crate::api::byte_stream_service::ByteStreamService;
 use crate::api::capabilities_service::CapabilitiesService;
-use crate::driver::BlobStorage;
+use crate::api::partial_uploads::PartialUploads;
+use crate::driver::{BlobStorage, DriverState, Instance};
 
+mod access_log;
 mod action_cache_service;
+mod admin_service;
 mod byte_stream_service;
 mod capabilities_service;
 mod cas_service;
+mod compression;
+mod partial_uploads;
 pub mod sync_wrapper;
 
 #[cfg(test)]
 mod tests;
 
+/// Maximum number of instance names remembered as having been set up on the storage drivers.
+const MAX_KNOWN_INSTANCES: usize = 10_000;
+
 struct InnerServer {
     cas: Arc<dyn BlobStorage + Send + Sync + 'static>,
     action_cache: Arc<dyn BlobStorage + Send + Sync + 'static>,
     max_batch_total_size_bytes: usize,
     check_action_cache_completeness: bool,
     completeness_check_probability: u32,
+    access_log: bool,
+    partial_uploads: PartialUploads,
+    write_limits: ByteStreamWriteLimits,
+    read_only_instances: HashSet<String>,
+    /// Names of the instances which have been set up on `cas` and `action_cache`. Bounded, since
+    /// clients choose instance names: an instance which has been evicted is set up again when it
+    /// is next seen.
+    known_instances: RwLock<LruCache<String, ()>>,
+}
+
+impl InnerServer {
+    /// Set up `instance` on the storage drivers the first time that it is seen by any RPC.
+    fn ensure_instance(&self, instance: &Instance) {
+        if self.known_instances.read().contains(&instance.name) {
+            return;
+        }
+        // Hold the write lock while setting up the instance, so that concurrent requests for the
+        // same instance do not reach the drivers before it is set up.
+        let mut known_instances = self.known_instances.write();
+        if known_instances.contains(&instance.name) {
+            return;
+        }
+        self.cas.ensure_instance(instance, DriverState::default());
+        self.action_cache
+            .ensure_instance(instance, DriverState::default());
+        known_instances.put(instance.name.clone(), ());
+    }
+
+    /// Fail with `FailedPrecondition` if `instance` is read-only (e.g. during a migration). Reads
+    /// from a read-only instance are still served.
+    fn check_writable(&self, instance: &Instance) -> Result<(), Status> {
+        if self.read_only_instances.contains(&instance.name) {
+            return Err(Status::failed_precondition("instance is read-only"));
+        }
+        Ok(())
+    }
+}
+
+/// Limits applied to each `ByteStream.Write` stream.
+#[derive(Clone, Copy, Debug)]
+pub struct ByteStreamWriteLimits {
+    /// Maximum number of bytes read ahead from the client while the storage driver is still
+    /// writing earlier data. Once that much is buffered, the client is held back by HTTP/2 flow
+    /// control until the driver catches up.
+    pub max_buffered_bytes: usize,
+    /// Maximum size of a single blob, after decompression. A write whose resource name declares a
+    /// larger blob is rejected up front, and a stream which sends more data is terminated, with
+    /// `ResourceExhausted`.
+    pub max_blob_size_bytes: Option<usize>,
+}
+
+impl ByteStreamWriteLimits {
+    pub const DEFAULT_MAX_BUFFERED_BYTES: usize = 8 * 1024 * 1024;
+}
+
+impl Default for ByteStreamWriteLimits {
+    fn default() -> Self {
+        ByteStreamWriteLimits {
+            max_buffered_bytes: Self::DEFAULT_MAX_BUFFERED_BYTES,
+            max_blob_size_bytes: None,
+        }
+    }
+}
+
+/// Options for a `Server`, other than its storage.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Maximum total size of blobs to be processed by a single call to the batch CAS APIs.
+    pub max_batch_total_size_bytes: usize,
+    /// Check that the outputs of action results exist in the CAS before returning them.
+    pub check_action_cache_completeness: bool,
+    /// Probability of checking action cache completeness, in the range 0-1000.
+    pub completeness_check_probability: u32,
+    /// Log each CAS and byte stream operation.
+    pub access_log: bool,
+    /// Serve the storage admin API.
+    pub admin_api: bool,
+    pub write_limits: ByteStreamWriteLimits,
+    /// Instances which are served read-only (e.g. during a migration).
+    pub read_only_instances: HashSet<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_batch_total_size_bytes: Server::DEFAULT_MAX_BATCH_TOTAL_SIZE_BYTES,
+            check_action_cache_completeness: false,
+            completeness_check_probability: 1000,
+            access_log: false,
+            admin_api: false,
+            write_limits: ByteStreamWriteLimits::default(),
+            read_only_instances: HashSet::new(),
+        }
+    }
 }
 
 /// The `Server` implements the CAS APIs and adapts them to call into a `BlobStorage` implementation.
 pub struct Server {
     inner: Arc<InnerServer>,
+    admin_api: bool,
 }
 
 /// Convert a list of REAPI digests into the internal Digest type.
@@ -72,24 +181,31 @@ pub fn convert_digests(digests: Vec<remoting_protos::Digest>) -> Result<Vec<Dige
 }
 
 impl Server {
-    /// Maximum size of blobs to be processed through the batch CAS APIs. This is hard-coded
-    /// for now until there is a need to configure it. Default to 4 MB.
+    /// Default maximum total size of blobs to be processed by a single call to the batch CAS
+    /// APIs. Default to 4 MB.
     pub const DEFAULT_MAX_BATCH_TOTAL_SIZE_BYTES: usize = 4 * 1024 * 1024;
 
     pub fn new(
         cas: Box<dyn BlobStorage + Send + Sync + 'static>,
         action_cache: Box<dyn BlobStorage + Send + Sync + 'static>,
-        check_action_cache_completeness: bool,
-        completeness_check_probability: u32,
+        config: ServerConfig,
     ) -> Self {
         Server {
             inner: Arc::new(InnerServer {
                 cas: Arc::from(cas),
                 action_cache: Arc::from(action_cache),
-                max_batch_total_size_bytes: Self::DEFAULT_MAX_BATCH_TOTAL_SIZE_BYTES,
-                check_action_cache_completeness,
-                completeness_check_probability,
+                max_batch_total_size_bytes: config.max_batch_total_size_bytes,
+                check_action_cache_completeness: config.check_action_cache_completeness,
+                completeness_check_probability: config.completeness_check_probability,
+                access_log: config.access_log,
+                partial_uploads: PartialUploads::new(),
+                write_limits: config.write_limits,
+                read_only_instances: config.read_only_instances,
+                known_instances: RwLock::new(LruCache::new(
+                    NonZeroUsize::new(MAX_KNOWN_INSTANCES).unwrap(),
+                )),
             }),
+            admin_api: config.admin_api,
         }
     }
 
@@ -106,30 +222,47 @@ impl Server {
         IE: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
         F: Future<Output = ()>,
     {
+        let grpc_config = grpc_config.unwrap_or_default();
+        let max_decoding_message_size = grpc_config.max_decoding_message_size();
+        let max_encoding_message_size = grpc_config.max_encoding_message_size();
+
         let cas_service = CasService {
             inner: self.inner.clone(),
         };
-        let cas_server = remoting_protos::content_addressable_storage_server::ContentAddressableStorageServer::new(cas_service);
+        let cas_server = remoting_protos::content_addressable_storage_server::ContentAddressableStorageServer::new(cas_service)
+            .max_decoding_message_size(max_decoding_message_size)
+            .max_encoding_message_size(max_encoding_message_size);
 
         let byte_stream_service = ByteStreamService {
             inner: self.inner.clone(),
         };
-        let byte_stream_server = ByteStreamServer::new(byte_stream_service);
+        let byte_stream_server = ByteStreamServer::new(byte_stream_service)
+            .max_decoding_message_size(max_decoding_message_size)
+            .max_encoding_message_size(max_encoding_message_size);
 
         let action_cache_service = ActionCacheService {
             inner: self.inner.clone(),
         };
-        let action_cache_server = ActionCacheServer::new(action_cache_service);
+        let action_cache_server = ActionCacheServer::new(action_cache_service)
+            .max_decoding_message_size(max_decoding_message_size)
+            .max_encoding_message_size(max_encoding_message_size);
 
         let capabilities_service = CapabilitiesService {
             inner: self.inner.clone(),
         };
-        let capabilities_server = CapabilitiesServer::new(capabilities_service);
+        let capabilities_server = CapabilitiesServer::new(capabilities_service)
+            .max_decoding_message_size(max_decoding_message_size)
+            .max_encoding_message_size(max_encoding_message_size);
 
-        let mut server = tonic::transport::Server::builder();
-        if let Some(c) = grpc_config.as_ref() {
-            server = c.apply_to_server(server);
-        }
+        let admin_server = self.admin_api.then(|| {
+            StorageAdminServer::new(AdminService {
+                inner: self.inner.clone(),
+            })
+            .max_decoding_message_size(max_decoding_message_size)
+            .max_encoding_message_size(max_encoding_message_size)
+        });
+
+        let server = grpc_config.apply_to_server(tonic::transport::Server::builder());
 
         let in_flight_requests_layer = InFlightRequestsLayer::new(in_flight_requests_counter);
         let auth_header_sensitive_layer =
@@ -145,7 +278,8 @@ impl Server {
             .add_service(GrpcMetrics::new(cas_server))
             .add_service(GrpcMetrics::new(byte_stream_server))
             .add_service(GrpcMetrics::new(action_cache_server))
-            .add_service(GrpcMetrics::new(capabilities_server));
+            .add_service(GrpcMetrics::new(capabilities_server))
+            .add_optional_service(admin_server.map(GrpcMetrics::new));
 
         router
             .serve_with_incoming_shutdown(incoming, shutdown_signal)
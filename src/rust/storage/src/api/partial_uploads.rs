@@ -0,0 +1,194 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::driver::WriteAttemptOps;
+
+/// How long an interrupted upload is kept around for the client to resume it.
+const PARTIAL_UPLOAD_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+/// How often expired uploads are discarded, so that their write attempts (and any resources which
+/// they hold, e.g. concurrency limit permits) are released even if no other upload is interrupted.
+const PARTIAL_UPLOAD_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum number of interrupted uploads kept at once.
+const MAX_PARTIAL_UPLOADS: usize = 1024;
+
+/// Maximum number of bytes written by the interrupted uploads kept at once, since some write
+/// attempts buffer their content until they are committed.
+const MAX_PARTIAL_UPLOAD_BYTES: i64 = 1024 * 1024 * 1024;
+
+/// An upload which was interrupted before `finish_write` and can be resumed by a later `Write`
+/// with the same upload resource name.
+pub(super) struct PartialUpload {
+    pub(super) attempt: Box<dyn WriteAttemptOps + Send + Sync + 'static>,
+    pub(super) committed_size: i64,
+    last_updated: Instant,
+}
+
+/// Tracks interrupted uploads keyed by their upload resource name (which includes the client's
+/// upload UUID). Once there are more than a maximum number of uploads (or bytes written by them),
+/// the least recently interrupted uploads are discarded.
+pub(super) struct PartialUploads {
+    uploads: Arc<Mutex<HashMap<String, PartialUpload>>>,
+    max_uploads: usize,
+    max_bytes: i64,
+}
+
+impl PartialUploads {
+    pub(super) fn new() -> Self {
+        Self::with_limits(MAX_PARTIAL_UPLOADS, MAX_PARTIAL_UPLOAD_BYTES)
+    }
+
+    /// Keep at most `max_uploads` uploads, which have written at most `max_bytes` in total. If
+    /// called within a Tokio runtime, expired uploads are also discarded periodically.
+    fn with_limits(max_uploads: usize, max_bytes: i64) -> Self {
+        let uploads = Arc::default();
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(Self::sweep_periodically(Arc::downgrade(&uploads)));
+        }
+        Self {
+            uploads,
+            max_uploads,
+            max_bytes,
+        }
+    }
+
+    /// Discard expired uploads every `PARTIAL_UPLOAD_SWEEP_INTERVAL`, until the `PartialUploads`
+    /// is dropped.
+    async fn sweep_periodically(uploads: Weak<Mutex<HashMap<String, PartialUpload>>>) {
+        let mut interval = tokio::time::interval(PARTIAL_UPLOAD_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(uploads) = uploads.upgrade() else {
+                break;
+            };
+            Self::discard_expired(&mut uploads.lock());
+        }
+    }
+
+    fn discard_expired(uploads: &mut HashMap<String, PartialUpload>) {
+        uploads.retain(|_, upload| upload.last_updated.elapsed() < PARTIAL_UPLOAD_EXPIRY);
+    }
+
+    /// Store an interrupted upload so that it can be resumed. Expired uploads, and the oldest
+    /// uploads beyond the limits, are discarded, which drops (and so aborts) their write attempts.
+    pub(super) fn insert(
+        &self,
+        upload_name: String,
+        attempt: Box<dyn WriteAttemptOps + Send + Sync + 'static>,
+        committed_size: i64,
+    ) {
+        let mut uploads = self.uploads.lock();
+        Self::discard_expired(&mut uploads);
+        uploads.insert(
+            upload_name,
+            PartialUpload {
+                attempt,
+                committed_size,
+                last_updated: Instant::now(),
+            },
+        );
+
+        let mut total_bytes = uploads
+            .values()
+            .map(|upload| upload.committed_size)
+            .sum::<i64>();
+        while uploads.len() > self.max_uploads || total_bytes > self.max_bytes {
+            let Some(oldest_name) = uploads
+                .iter()
+                .min_by_key(|(_, upload)| upload.last_updated)
+                .map(|(name, _)| name.clone())
+            else {
+                break;
+            };
+            if let Some(upload) = uploads.remove(&oldest_name) {
+                total_bytes -= upload.committed_size;
+            }
+            metrics::counter!("toolchain_bytestream_partial_uploads_discarded_total", 1);
+        }
+    }
+
+    /// Remove an interrupted upload in order to resume it.
+    pub(super) fn take(&self, upload_name: &str) -> Option<PartialUpload> {
+        self.uploads
+            .lock()
+            .remove(upload_name)
+            .filter(|upload| upload.last_updated.elapsed() < PARTIAL_UPLOAD_EXPIRY)
+    }
+
+    /// The number of bytes written so far by an interrupted upload.
+    pub(super) fn committed_size(&self, upload_name: &str) -> Option<i64> {
+        self.uploads
+            .lock()
+            .get(upload_name)
+            .filter(|upload| upload.last_updated.elapsed() < PARTIAL_UPLOAD_EXPIRY)
+            .map(|upload| upload.committed_size)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.uploads.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{PartialUploads, PARTIAL_UPLOAD_EXPIRY, PARTIAL_UPLOAD_SWEEP_INTERVAL};
+    use crate::driver::{BlobStorage, DriverState, Instance, MemoryStorage, WriteAttemptOps};
+    use crate::testutil::TestData;
+
+    async fn write_attempt(
+        storage: &MemoryStorage,
+        instance: &Instance,
+    ) -> Box<dyn WriteAttemptOps + Send + Sync + 'static> {
+        let content = TestData::from_static(b"foobar");
+        storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn discards_the_oldest_uploads_beyond_the_limits() {
+        let storage = MemoryStorage::new();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+        let partial_uploads = PartialUploads::with_limits(2, 100);
+
+        partial_uploads.insert("a".to_owned(), write_attempt(&storage, &instance).await, 10);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        partial_uploads.insert("b".to_owned(), write_attempt(&storage, &instance).await, 10);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        partial_uploads.insert("c".to_owned(), write_attempt(&storage, &instance).await, 10);
+        assert_eq!(partial_uploads.committed_size("a"), None);
+        assert_eq!(partial_uploads.committed_size("b"), Some(10));
+        assert_eq!(partial_uploads.committed_size("c"), Some(10));
+
+        // An upload which exceeds the byte limit together with the others displaces them.
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        partial_uploads.insert("d".to_owned(), write_attempt(&storage, &instance).await, 95);
+        assert_eq!(partial_uploads.len(), 1);
+        assert_eq!(partial_uploads.committed_size("d"), Some(95));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn discards_expired_uploads_periodically() {
+        let storage = MemoryStorage::new();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+        let partial_uploads = PartialUploads::new();
+
+        partial_uploads.insert("a".to_owned(), write_attempt(&storage, &instance).await, 10);
+        tokio::time::sleep(PARTIAL_UPLOAD_EXPIRY + PARTIAL_UPLOAD_SWEEP_INTERVAL).await;
+        assert_eq!(partial_uploads.len(), 0);
+    }
+}
@@ -2,9 +2,16 @@
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
+use bytes::Bytes;
+use digest::Digest;
 use futures::{Future, Stream};
+use tokio::runtime::Handle;
+
+use crate::bytes::consolidate_stream;
+use crate::driver::{BlobStorage, DriverState, Instance, StorageError, StreamingWriteError};
 
 /// SyncWrapper implements a "statically-checked mutex" prototyped in [this Rust blog
 /// entry](https://internals.rust-lang.org/t/what-shall-sync-mean-across-an-await/12020/2).
@@ -76,3 +83,131 @@ impl<S: Stream> Stream for SyncWrapper<S> {
         self.get().size_hint()
     }
 }
+
+/// Exposes blocking versions of the core `BlobStorage` operations, for embedding the storage
+/// stack in synchronous code (e.g. a CLI which uploads to a cache).
+///
+/// Each call blocks the current thread on `handle` until the operation completes, so:
+/// - methods must not be called from within an async context (including a thread which is
+///   driving a runtime), as `Handle::block_on` panics there.
+/// - `handle` should belong to a multi-threaded runtime, whose worker threads drive the IO and
+///   timers used by drivers. A current-thread runtime would need to be driven by another thread
+///   for the duration of each call.
+pub struct SyncBlobStorage {
+    storage: Arc<dyn BlobStorage + Send + Sync + 'static>,
+    handle: Handle,
+}
+
+impl SyncBlobStorage {
+    pub fn new(storage: Arc<dyn BlobStorage + Send + Sync + 'static>, handle: Handle) -> Self {
+        SyncBlobStorage { storage, handle }
+    }
+
+    /// Return the digests which are not stored, as for `BlobStorage::find_missing_blobs`.
+    pub fn find_missing_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.handle.block_on(self.storage.find_missing_blobs(
+            instance,
+            digests,
+            DriverState::default(),
+        ))
+    }
+
+    /// Read the entire content of `digest` into memory, or return `None` if it is not stored.
+    pub fn read_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+    ) -> Result<Option<Bytes>, StorageError> {
+        self.handle.block_on(async {
+            let stream = self
+                .storage
+                .read_blob(
+                    instance,
+                    digest,
+                    digest.size_bytes.max(1),
+                    None,
+                    None,
+                    DriverState::default(),
+                )
+                .await?;
+            match stream {
+                Some(stream) => consolidate_stream(stream).await.map(Some),
+                None => Ok(None),
+            }
+        })
+    }
+
+    /// Store `content` as the blob for `digest`. Writing a blob which is already stored succeeds.
+    pub fn write_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        content: Bytes,
+    ) -> Result<(), StorageError> {
+        self.handle
+            .block_on(async {
+                let mut attempt = self
+                    .storage
+                    .begin_write_blob(instance, digest, DriverState::default())
+                    .await?;
+                attempt.write(content).await?;
+                attempt.commit().await
+            })
+            .or_else(StreamingWriteError::ok_if_already_exists)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::SyncBlobStorage;
+    use crate::driver::{BlobStorage, DriverState, Instance, MemoryStorage};
+    use crate::testutil::TestData;
+
+    #[test]
+    fn reads_and_writes_blobs_from_sync_code() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let instance = Instance::from("main");
+        let storage = MemoryStorage::new();
+        storage.ensure_instance(&instance, DriverState::default());
+        let storage = SyncBlobStorage::new(Arc::new(storage), runtime.handle().clone());
+
+        let content = TestData::from_static(b"foobar");
+        assert_eq!(
+            storage
+                .find_missing_blobs(instance.clone(), vec![content.digest])
+                .unwrap(),
+            vec![content.digest]
+        );
+        assert_eq!(
+            storage.read_blob(instance.clone(), content.digest).unwrap(),
+            None
+        );
+
+        storage
+            .write_blob(instance.clone(), content.digest, content.bytes.clone())
+            .unwrap();
+        // Writing the same blob again succeeds.
+        storage
+            .write_blob(instance.clone(), content.digest, content.bytes.clone())
+            .unwrap();
+
+        assert!(storage
+            .find_missing_blobs(instance.clone(), vec![content.digest])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            storage.read_blob(instance, content.digest).unwrap(),
+            Some(content.bytes)
+        );
+    }
+}
@@ -1,36 +1,51 @@
 // Copyright 2021 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::io;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use digest::Digest;
 use futures::{FutureExt, StreamExt};
 use grpc_util::hyper::AddrIncomingWithStream;
+use grpc_util::infra::GrpcConfig;
 use hyper::server::conn::AddrIncoming;
 use prost::Message;
 use protos::build::bazel::remote::execution::v2::{
     action_cache_client::ActionCacheClient, batch_read_blobs_response, batch_update_blobs_request,
     batch_update_blobs_response, capabilities_client::CapabilitiesClient,
-    command::EnvironmentVariable,
+    command::EnvironmentVariable, compressor::Value as Compressor,
     content_addressable_storage_client::ContentAddressableStorageClient,
     digest_function::Value as DigestFunction_Value, Action, ActionCacheUpdateCapabilities,
     ActionResult, BatchReadBlobsRequest, BatchReadBlobsResponse, BatchUpdateBlobsRequest,
-    BatchUpdateBlobsResponse, CacheCapabilities, Command, FindMissingBlobsRequest,
-    GetActionResultRequest, GetCapabilitiesRequest, OutputFile, ServerCapabilities,
-    UpdateActionResultRequest,
+    BatchUpdateBlobsResponse, CacheCapabilities, Command, Directory, FileNode,
+    FindMissingBlobsRequest, GetActionResultRequest, GetCapabilitiesRequest, OutputDirectory,
+    OutputFile, ServerCapabilities, Tree, UpdateActionResultRequest,
 };
 use protos::google::bytestream::{
-    byte_stream_client::ByteStreamClient, ReadRequest, ReadResponse, WriteRequest, WriteResponse,
+    byte_stream_client::ByteStreamClient, QueryWriteStatusRequest, QueryWriteStatusResponse,
+    ReadRequest, ReadResponse, WriteRequest, WriteResponse,
+};
+use protos::toolchain::storage::admin::v1::{
+    storage_admin_client::StorageAdminClient, DeleteBlobsRequest,
 };
 use tonic::transport::{Channel, Endpoint};
 use tonic::Code;
 use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
+use tracing_subscriber::fmt::MakeWriter;
 
-use crate::api::Server;
-use crate::driver::{BlobStorage, DriverState, Instance, MemoryStorage};
-use crate::testutil::TestData;
+use crate::api::{ByteStreamWriteLimits, Server, ServerConfig};
+use crate::driver::{
+    BlobStorage, BoxReadStream, ChunkingStorage, DriverState, Instance, MemoryStorage,
+    StorageError, StreamingWriteError, WriteAttemptOps,
+};
+use crate::testutil::{
+    capture_metrics, counter_total, histogram_samples, CountMethodCallsStorage, TestData,
+};
 
 /// Create a Tonic `Endpoint` from a string containing a schema and IP address/name.
 fn create_endpoint(addr: &str) -> Result<Endpoint, String> {
@@ -45,10 +60,10 @@ fn create_storage() -> (MemoryStorage, MemoryStorage, Instance) {
         name: "main".to_owned(),
     };
 
-    let mut cas = MemoryStorage::new();
+    let cas = MemoryStorage::new();
     cas.ensure_instance(&instance, DriverState::default());
 
-    let mut action_cache = MemoryStorage::new();
+    let action_cache = MemoryStorage::new();
     action_cache.ensure_instance(&instance, DriverState::default());
 
     (cas, action_cache, instance)
@@ -67,7 +82,42 @@ impl Drop for TestServer {
     }
 }
 
-fn spawn_server<BS1, BS2>(cas: BS1, action_cache: BS2, check_completeness: bool) -> TestServer
+fn spawn_server<BS1, BS2>(
+    cas: BS1,
+    action_cache: BS2,
+    check_completeness: bool,
+    access_log: bool,
+) -> TestServer
+where
+    BS1: BlobStorage + Send + Sync + 'static,
+    BS2: BlobStorage + Send + Sync + 'static,
+{
+    spawn_configured_server(
+        cas,
+        action_cache,
+        ServerConfig {
+            check_action_cache_completeness: check_completeness,
+            access_log,
+            ..test_server_config()
+        },
+        None,
+    )
+}
+
+/// The default server config, with the admin API enabled.
+fn test_server_config() -> ServerConfig {
+    ServerConfig {
+        admin_api: true,
+        ..ServerConfig::default()
+    }
+}
+
+fn spawn_configured_server<BS1, BS2>(
+    cas: BS1,
+    action_cache: BS2,
+    config: ServerConfig,
+    grpc_config: Option<GrpcConfig>,
+) -> TestServer
 where
     BS1: BlobStorage + Send + Sync + 'static,
     BS2: BlobStorage + Send + Sync + 'static,
@@ -80,18 +130,13 @@ where
     let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel();
 
     tokio::spawn(async move {
-        let server = Server::new(
-            Box::new(cas),
-            Box::new(action_cache),
-            check_completeness,
-            1000,
-        );
+        let server = Server::new(Box::new(cas), Box::new(action_cache), config);
 
         server
             .serve_with_incoming_shutdown(
                 incoming,
                 shutdown_receiver.map(drop),
-                None,
+                grpc_config,
                 InFlightRequestsCounter::new(),
             )
             .await
@@ -110,7 +155,7 @@ async fn check_cas_apis() {
 
     let content = TestData::from_static(b"foobar");
 
-    let server = spawn_server(storage, action_cache, false);
+    let server = spawn_server(storage, action_cache, false, false);
 
     let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
     let channel = Channel::balance_list(vec![endpoint].into_iter());
@@ -193,81 +238,814 @@ async fn check_cas_apis() {
         .into_inner();
     assert_eq!(
         response,
-        BatchUpdateBlobsResponse {
-            responses: vec![batch_update_blobs_response::Response {
-                digest: Some(content.digest.into()),
-                status: Some(protos::google::rpc::Status {
-                    code: protos::google::rpc::Code::Ok as i32,
-                    ..protos::google::rpc::Status::default()
-                })
-            }]
+        BatchUpdateBlobsResponse {
+            responses: vec![batch_update_blobs_response::Response {
+                digest: Some(content.digest.into()),
+                status: Some(protos::google::rpc::Status {
+                    code: protos::google::rpc::Code::Ok as i32,
+                    ..protos::google::rpc::Status::default()
+                })
+            }]
+        }
+    );
+}
+
+/// Tests that an instance which was not set up in advance is set up on the underlying storage
+/// (once) the first time that it is seen.
+#[tokio::test]
+async fn sets_up_unseen_instances() {
+    let memory = MemoryStorage::new();
+    let cas = CountMethodCallsStorage::new(ChunkingStorage::new(memory.clone(), 1024));
+    let ensure_instance_count = cas.ensure_instance_count.clone();
+    let instance = Instance::from("brand_new");
+    let content = TestData::from_static(b"foobar");
+
+    let server = spawn_server(cas, MemoryStorage::new(), false, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut cas_client = ContentAddressableStorageClient::new(channel);
+
+    for _ in 0..2 {
+        let request = FindMissingBlobsRequest {
+            instance_name: instance.name.clone(),
+            blob_digests: vec![content.digest.into()],
+        };
+        let response = cas_client.find_missing_blobs(request).await.unwrap();
+        assert_eq!(
+            response.into_inner().missing_blob_digests,
+            vec![content.digest.into()]
+        );
+    }
+    assert_eq!(ensure_instance_count.load(Ordering::SeqCst), 1);
+
+    // The instance was set up on the `MemoryStorage` beneath the wrappers, which fails for
+    // instances that it does not know about.
+    let missing = memory
+        .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+        .await
+        .unwrap();
+    assert_eq!(missing, vec![content.digest]);
+}
+
+/// Tests that a read-only instance rejects writes, but continues to serve reads.
+#[tokio::test]
+async fn rejects_writes_to_read_only_instances() {
+    let (storage, action_cache, instance) = create_storage();
+
+    let content = TestData::from_static(b"foobar");
+    let mut attempt = storage
+        .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+        .await
+        .unwrap();
+    attempt.write(content.bytes.clone()).await.unwrap();
+    attempt.commit().await.unwrap();
+
+    let server = spawn_configured_server(
+        storage,
+        action_cache,
+        ServerConfig {
+            read_only_instances: HashSet::from([instance.name.clone()]),
+            ..test_server_config()
+        },
+        None,
+    );
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let mut cas_client = ContentAddressableStorageClient::new(endpoint.connect_lazy());
+    let mut bs_client = ByteStreamClient::new(endpoint.connect_lazy());
+    let mut ac_client = ActionCacheClient::new(endpoint.connect_lazy());
+
+    let other_content = TestData::from_static(b"xyzzy");
+    let status = cas_client
+        .batch_update_blobs(BatchUpdateBlobsRequest {
+            instance_name: instance.name.clone(),
+            requests: vec![batch_update_blobs_request::Request {
+                digest: Some(other_content.digest.into()),
+                data: other_content.bytes.clone(),
+            }],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    let status = bs_client
+        .write(futures::stream::iter(vec![WriteRequest {
+            resource_name: format!(
+                "{}/uploads/12345/blobs/{}/{}",
+                &instance.name,
+                hex::encode(other_content.digest.hash),
+                other_content.digest.size_bytes
+            ),
+            write_offset: 0,
+            finish_write: true,
+            data: other_content.bytes.clone(),
+        }]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    let status = ac_client
+        .update_action_result(UpdateActionResultRequest {
+            instance_name: instance.name.clone(),
+            action_digest: Some(other_content.digest.into()),
+            action_result: Some(ActionResult::default()),
+            ..UpdateActionResultRequest::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    let status = StorageAdminClient::new(endpoint.connect_lazy())
+        .delete_blobs(DeleteBlobsRequest {
+            instance_name: instance.name.clone(),
+            blob_digests: vec![content.digest.into()],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    // Reads are unaffected.
+    let response = cas_client
+        .find_missing_blobs(FindMissingBlobsRequest {
+            instance_name: instance.name.clone(),
+            blob_digests: vec![content.digest.into(), other_content.digest.into()],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response.missing_blob_digests,
+        vec![other_content.digest.into()]
+    );
+    let response = cas_client
+        .batch_read_blobs(BatchReadBlobsRequest {
+            instance_name: instance.name.clone(),
+            digests: vec![content.digest.into()],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.responses[0].data, content.bytes);
+}
+
+/// Tests that batches of up to the maximum batch size fit within the gRPC message size limit,
+/// even though the message itself is larger than Tonic's default limit of 4 MiB.
+#[tokio::test]
+async fn accepts_batches_larger_than_default_message_size() {
+    let bytes = Bytes::from(vec![7; Server::DEFAULT_MAX_BATCH_TOTAL_SIZE_BYTES]);
+    let content = TestData {
+        digest: Digest::of_bytes(&bytes).unwrap(),
+        bytes,
+    };
+    let write_request = |instance: &Instance| BatchUpdateBlobsRequest {
+        instance_name: instance.name.clone(),
+        requests: vec![batch_update_blobs_request::Request {
+            digest: Some(content.digest.into()),
+            data: content.bytes.clone(),
+        }],
+    };
+
+    // With the old limit, the batch is rejected before reaching the service.
+    let (storage, action_cache, instance) = create_storage();
+    let server = spawn_configured_server(
+        storage,
+        action_cache,
+        test_server_config(),
+        Some(GrpcConfig {
+            max_decoding_message_size: Some(4 * 1024 * 1024),
+            ..GrpcConfig::default()
+        }),
+    );
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let mut cas_client = ContentAddressableStorageClient::new(endpoint.connect_lazy());
+    let err = cas_client
+        .batch_update_blobs(write_request(&instance))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+
+    // With the default limit, it succeeds.
+    let (storage, action_cache, instance) = create_storage();
+    let server = spawn_server(storage, action_cache, false, false);
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let mut cas_client = ContentAddressableStorageClient::new(endpoint.connect_lazy());
+    let response = cas_client
+        .batch_update_blobs(write_request(&instance))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response.responses[0].status.as_ref().unwrap().code,
+        protos::google::rpc::Code::Ok as i32
+    );
+}
+
+/// Tests that a configured `max_batch_total_size_bytes` is advertised and enforced.
+#[tokio::test]
+async fn enforces_max_batch_total_size_bytes() {
+    let (storage, action_cache, instance) = create_storage();
+    let content1 = TestData::from_static(b"foobar");
+    let content2 = TestData::from_static(b"helloworld");
+
+    let server = spawn_configured_server(
+        storage,
+        action_cache,
+        ServerConfig {
+            max_batch_total_size_bytes: 10,
+            ..test_server_config()
+        },
+        None,
+    );
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = endpoint.connect_lazy();
+
+    let mut capabilities_client = CapabilitiesClient::new(channel.clone());
+    let capabilities = capabilities_client
+        .get_capabilities(GetCapabilitiesRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        capabilities
+            .cache_capabilities
+            .unwrap()
+            .max_batch_total_size_bytes,
+        10
+    );
+
+    let mut cas_client = ContentAddressableStorageClient::new(channel);
+    let write_request = |contents: &[&TestData]| BatchUpdateBlobsRequest {
+        instance_name: instance.name.clone(),
+        requests: contents
+            .iter()
+            .map(|content| batch_update_blobs_request::Request {
+                digest: Some(content.digest.into()),
+                data: content.bytes.clone(),
+            })
+            .collect(),
+    };
+    let read_request = |contents: &[&TestData]| BatchReadBlobsRequest {
+        instance_name: instance.name.clone(),
+        digests: contents
+            .iter()
+            .map(|content| content.digest.into())
+            .collect(),
+    };
+
+    // Batches of up to the limit succeed.
+    cas_client
+        .batch_update_blobs(write_request(&[&content2]))
+        .await
+        .unwrap();
+    cas_client
+        .batch_read_blobs(read_request(&[&content2]))
+        .await
+        .unwrap();
+
+    // Larger batches are rejected as a whole.
+    let err = cas_client
+        .batch_update_blobs(write_request(&[&content1, &content2]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = cas_client
+        .batch_read_blobs(read_request(&[&content1, &content2]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn deleted_blobs_are_reported_missing() {
+    let (storage, action_cache, instance) = create_storage();
+
+    let content = TestData::from_static(b"foobar");
+    let other_content = TestData::from_static(b"xyzzy");
+
+    let server = spawn_server(storage, action_cache, false, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut cas_client = ContentAddressableStorageClient::new(channel.clone());
+    let mut admin_client = StorageAdminClient::new(channel);
+
+    let write_request = BatchUpdateBlobsRequest {
+        instance_name: instance.name.clone(),
+        requests: vec![batch_update_blobs_request::Request {
+            digest: Some(content.digest.into()),
+            data: content.bytes.clone(),
+        }],
+    };
+    cas_client.batch_update_blobs(write_request).await.unwrap();
+
+    // Only the stored blob is reported as deleted.
+    let response = admin_client
+        .delete_blobs(DeleteBlobsRequest {
+            instance_name: instance.name.clone(),
+            blob_digests: vec![content.digest.into(), other_content.digest.into()],
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        response.into_inner().deleted_blob_digests,
+        vec![content.digest.into()]
+    );
+
+    let request = FindMissingBlobsRequest {
+        instance_name: instance.name.clone(),
+        blob_digests: vec![content.digest.into()],
+    };
+    let response = cas_client.find_missing_blobs(request).await.unwrap();
+    assert_eq!(
+        response.into_inner().missing_blob_digests,
+        vec![content.digest.into()]
+    );
+}
+
+/// A CAS which reports every blob as having been evicted while it was being read.
+struct EvictedStorage;
+
+#[async_trait::async_trait]
+impl BlobStorage for EvictedStorage {
+    async fn find_missing_blobs(
+        &self,
+        _instance: Instance,
+        _digests: Vec<Digest>,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    async fn read_blob(
+        &self,
+        _instance: Instance,
+        _digest: Digest,
+        _max_batch_size: usize,
+        _read_offset: Option<usize>,
+        _read_limit: Option<usize>,
+        _state: DriverState,
+    ) -> Result<Option<BoxReadStream>, StorageError> {
+        Err(StorageError::NotFound("evicted".to_owned()))
+    }
+
+    async fn begin_write_blob(
+        &self,
+        _instance: Instance,
+        _digest: Digest,
+        _state: DriverState,
+    ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        Err(StorageError::Unimplemented("read-only".to_owned()).into())
+    }
+}
+
+#[tokio::test]
+async fn maps_not_found_storage_errors() {
+    let (_, action_cache, instance) = create_storage();
+
+    let content = TestData::from_static(b"foobar");
+
+    let server = spawn_server(EvictedStorage, action_cache, false, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut cas_client = ContentAddressableStorageClient::new(channel.clone());
+    let mut bs_client = ByteStreamClient::new(channel);
+
+    let request = BatchReadBlobsRequest {
+        instance_name: instance.name.clone(),
+        digests: vec![content.digest.into()],
+    };
+    let response = cas_client
+        .batch_read_blobs(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response.responses[0].status.as_ref().unwrap().code,
+        protos::google::rpc::Code::NotFound as i32
+    );
+
+    let request = ReadRequest {
+        resource_name: format!(
+            "{}/blobs/{}/{}",
+            &instance.name,
+            hex::encode(content.digest.hash),
+            content.digest.size_bytes
+        ),
+        read_offset: 0,
+        read_limit: 0,
+    };
+    let status = bs_client.read(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn check_bytestream_apis() {
+    let (storage, action_cache, instance) = create_storage();
+
+    let content = TestData::from_static(b"foobar");
+
+    let server = spawn_server(storage, action_cache, false, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut cas_client = ContentAddressableStorageClient::new(channel.clone());
+    let mut bs_client = ByteStreamClient::new(channel);
+
+    // Verify that the digest is missing.
+    let request = FindMissingBlobsRequest {
+        instance_name: instance.name.clone(),
+        blob_digests: vec![content.digest.into()],
+    };
+    let response = cas_client.find_missing_blobs(request).await.unwrap();
+    assert_eq!(
+        response.into_inner().missing_blob_digests,
+        vec![content.digest.into()]
+    );
+
+    // Write the blob into storage.
+    let resource_name = format!(
+        "{}/uploads/12345/blobs/{}/{}",
+        &instance.name,
+        hex::encode(content.digest.hash),
+        content.digest.size_bytes
+    );
+    let write_stream = {
+        let resource_name = resource_name.clone();
+        let content = content.bytes.clone();
+        async_stream::stream! {
+        let req1 = WriteRequest {
+            resource_name,
+            write_offset: 0,
+            finish_write: false,
+            data: content.slice(0..3),
+        };
+        yield req1;
+        let req2 = WriteRequest {
+            resource_name: "".into(),
+            write_offset: 3,
+            finish_write: true,
+            data: content.slice(3..),
+        };
+        yield req2;
+        }
+    };
+    let response = bs_client.write(write_stream).await.unwrap().into_inner();
+    assert_eq!(response, WriteResponse { committed_size: 6 });
+
+    // Verify that the digest is now present.
+    let request = FindMissingBlobsRequest {
+        instance_name: instance.name.clone(),
+        blob_digests: vec![content.digest.into()],
+    };
+    let response = cas_client.find_missing_blobs(request).await.unwrap();
+    assert!(response.into_inner().missing_blob_digests.is_empty());
+
+    // Read the blob back from the storage.
+    let request = ReadRequest {
+        resource_name: format!(
+            "{}/blobs/{}/{}",
+            &instance.name,
+            hex::encode(content.digest.hash),
+            content.digest.size_bytes
+        ),
+        read_offset: 0,
+        read_limit: 0,
+    };
+    let response = bs_client.read(request).await.unwrap();
+    let mut stream = response.into_inner();
+    let chunk = stream.next().await.unwrap().unwrap();
+    assert_eq!(
+        chunk,
+        ReadResponse {
+            data: content.bytes.clone()
+        }
+    );
+    assert!(stream.next().await.is_none());
+
+    // Confirm that re-writing it succeeds early.
+    let write_stream = {
+        let resource_name = resource_name.clone();
+        let content = content.bytes.clone();
+        async_stream::stream! {
+        let req1 = WriteRequest {
+            resource_name,
+            write_offset: 0,
+            finish_write: false,
+            data: content.slice(0..3),
+        };
+        yield req1;
+        }
+    };
+    let response = bs_client.write(write_stream).await.unwrap().into_inner();
+    assert_eq!(response, WriteResponse { committed_size: 6 });
+}
+
+#[tokio::test]
+async fn records_bytestream_transfer_metrics() {
+    capture_metrics();
+    // The captured metrics are shared between tests, so use an instance name and blob size
+    // which are unique to this test.
+    let instance = Instance {
+        name: "bytestream-metrics".to_owned(),
+    };
+    let storage = MemoryStorage::new();
+    storage.ensure_instance(&instance, DriverState::default());
+    let action_cache = MemoryStorage::new();
+    action_cache.ensure_instance(&instance, DriverState::default());
+    let content = TestData::from_static(b"bytestream-metrics-blob");
+    let half = content.bytes.len() / 2;
+    let server = spawn_server(storage, action_cache, false, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut bs_client = ByteStreamClient::new(channel);
+
+    let instance_label = [("instance", instance.name.as_str())];
+    let blob_sizes = |method: &str| {
+        histogram_samples(
+            "toolchain_bytestream_blob_size_bytes",
+            &[("method", method)],
+        )
+        .into_iter()
+        .filter(|size| *size == content.bytes.len() as f64)
+        .count()
+    };
+    let write_sizes_before = blob_sizes("write");
+    let read_sizes_before = blob_sizes("read");
+
+    let resource_name = format!(
+        "{}/uploads/12345/blobs/{}/{}",
+        &instance.name,
+        hex::encode(content.digest.hash),
+        content.digest.size_bytes
+    );
+    let write_request = |offset: usize, end: usize, finish_write: bool| WriteRequest {
+        resource_name: if offset == 0 {
+            resource_name.clone()
+        } else {
+            String::new()
+        },
+        write_offset: offset as i64,
+        finish_write,
+        data: content.bytes.slice(offset..end),
+    };
+
+    // Write the blob in two chunks.
+    let requests = vec![
+        write_request(0, half, false),
+        write_request(half, content.bytes.len(), true),
+    ];
+    let response = bs_client
+        .write(futures::stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response,
+        WriteResponse {
+            committed_size: content.bytes.len() as i64
+        }
+    );
+
+    // Re-writing it exits early without transferring any bytes to storage.
+    let requests = vec![write_request(0, half, false)];
+    let response = bs_client
+        .write(futures::stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response,
+        WriteResponse {
+            committed_size: content.bytes.len() as i64
+        }
+    );
+
+    let request = ReadRequest {
+        resource_name: format!(
+            "{}/blobs/{}/{}",
+            &instance.name,
+            hex::encode(content.digest.hash),
+            content.digest.size_bytes
+        ),
+        read_offset: 0,
+        read_limit: 0,
+    };
+    let chunks = bs_client
+        .read(request)
+        .await
+        .unwrap()
+        .into_inner()
+        .map(|chunk| chunk.unwrap().data)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(chunks.concat(), content.bytes.to_vec());
+
+    assert_eq!(
+        counter_total("toolchain_bytestream_write_bytes_total", &instance_label),
+        content.bytes.len() as u64
+    );
+    assert_eq!(
+        counter_total("toolchain_bytestream_read_bytes_total", &instance_label),
+        content.bytes.len() as u64
+    );
+    assert_eq!(blob_sizes("write") - write_sizes_before, 2);
+    assert_eq!(blob_sizes("read") - read_sizes_before, 1);
+}
+
+#[tokio::test]
+async fn reads_and_writes_zstd_compressed_blobs() {
+    let (storage, action_cache, instance) = create_storage();
+
+    let content = TestData::from_static(&[b'a'; 64 * 1024]);
+    let compressed = Bytes::from(zstd::encode_all(&content.bytes[..], 0).unwrap());
+    assert!(compressed.len() < content.bytes.len());
+
+    let server = spawn_server(storage, action_cache, false, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut bs_client = ByteStreamClient::new(channel);
+
+    // Write the blob compressed, in two chunks.
+    let write_request = |write_offset: usize, data: Bytes, finish_write: bool| WriteRequest {
+        resource_name: format!(
+            "{}/uploads/12345/compressed-blobs/zstd/{}/{}",
+            &instance.name,
+            hex::encode(content.digest.hash),
+            content.digest.size_bytes
+        ),
+        write_offset: write_offset as i64,
+        finish_write,
+        data,
+    };
+    let split = compressed.len() / 2;
+    let requests = vec![
+        write_request(0, compressed.slice(..split), false),
+        write_request(split, compressed.slice(split..), true),
+    ];
+    let response = bs_client
+        .write(futures::stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response,
+        WriteResponse {
+            committed_size: compressed.len() as i64
+        }
+    );
+
+    let read = |compressor: Option<&str>| {
+        let mut bs_client = bs_client.clone();
+        let blobs = match compressor {
+            Some(compressor) => format!("compressed-blobs/{compressor}"),
+            None => "blobs".to_owned(),
+        };
+        let request = ReadRequest {
+            resource_name: format!(
+                "{}/{blobs}/{}/{}",
+                &instance.name,
+                hex::encode(content.digest.hash),
+                content.digest.size_bytes
+            ),
+            read_offset: 0,
+            read_limit: 0,
+        };
+        async move {
+            let mut stream = bs_client.read(request).await.unwrap().into_inner();
+            let mut data = BytesMut::new();
+            while let Some(response) = stream.next().await {
+                data.extend_from_slice(&response.unwrap().data);
+            }
+            data.freeze()
         }
+    };
+
+    // The blob was stored uncompressed, and can be read back either way.
+    assert_eq!(read(None).await, content.bytes);
+    let compressed_read = read(Some("zstd")).await;
+    assert_eq!(
+        zstd::decode_all(&compressed_read[..]).unwrap(),
+        content.bytes.to_vec()
     );
+
+    // Re-writing an existing blob compressed reports a committed size of -1.
+    let response = bs_client
+        .write(futures::stream::iter(vec![write_request(
+            0,
+            compressed.slice(..split),
+            false,
+        )]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response, WriteResponse { committed_size: -1 });
+
+    // A read_limit cannot be applied to compressed data.
+    let request = ReadRequest {
+        resource_name: format!(
+            "{}/compressed-blobs/zstd/{}/{}",
+            &instance.name,
+            hex::encode(content.digest.hash),
+            content.digest.size_bytes
+        ),
+        read_offset: 0,
+        read_limit: 10,
+    };
+    let status = bs_client.read(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
 }
 
 #[tokio::test]
-async fn check_bytestream_apis() {
+async fn resumes_interrupted_bytestream_write() {
     let (storage, action_cache, instance) = create_storage();
 
     let content = TestData::from_static(b"foobar");
 
-    let server = spawn_server(storage, action_cache, false);
+    let server = spawn_server(storage, action_cache, false, false);
 
     let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
     let channel = Channel::balance_list(vec![endpoint].into_iter());
-    let mut cas_client = ContentAddressableStorageClient::new(channel.clone());
     let mut bs_client = ByteStreamClient::new(channel);
 
-    // Verify that the digest is missing.
-    let request = FindMissingBlobsRequest {
-        instance_name: instance.name.clone(),
-        blob_digests: vec![content.digest.into()],
-    };
-    let response = cas_client.find_missing_blobs(request).await.unwrap();
-    assert_eq!(
-        response.into_inner().missing_blob_digests,
-        vec![content.digest.into()]
-    );
-
-    // Write the blob into storage.
     let resource_name = format!(
         "{}/uploads/12345/blobs/{}/{}",
         &instance.name,
         hex::encode(content.digest.hash),
         content.digest.size_bytes
     );
+
+    // Nothing is known about the upload before it starts.
+    let status = bs_client
+        .query_write_status(QueryWriteStatusRequest {
+            resource_name: resource_name.clone(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    // Write the first half of the blob, then disconnect without finishing the write.
     let write_stream = {
         let resource_name = resource_name.clone();
         let content = content.bytes.clone();
         async_stream::stream! {
-        let req1 = WriteRequest {
+        yield WriteRequest {
             resource_name,
             write_offset: 0,
             finish_write: false,
             data: content.slice(0..3),
         };
-        yield req1;
-        let req2 = WriteRequest {
-            resource_name: "".into(),
+        }
+    };
+    bs_client.write(write_stream).await.unwrap_err();
+
+    let response = bs_client
+        .query_write_status(QueryWriteStatusRequest {
+            resource_name: resource_name.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response,
+        QueryWriteStatusResponse {
+            committed_size: 3,
+            complete: false,
+        }
+    );
+
+    // Resume the write at the reported offset and complete it.
+    let write_stream = {
+        let resource_name = resource_name.clone();
+        let content = content.bytes.clone();
+        async_stream::stream! {
+        yield WriteRequest {
+            resource_name,
             write_offset: 3,
             finish_write: true,
             data: content.slice(3..),
         };
-        yield req2;
         }
     };
     let response = bs_client.write(write_stream).await.unwrap().into_inner();
     assert_eq!(response, WriteResponse { committed_size: 6 });
 
-    // Verify that the digest is now present.
-    let request = FindMissingBlobsRequest {
-        instance_name: instance.name.clone(),
-        blob_digests: vec![content.digest.into()],
-    };
-    let response = cas_client.find_missing_blobs(request).await.unwrap();
-    assert!(response.into_inner().missing_blob_digests.is_empty());
+    let response = bs_client
+        .query_write_status(QueryWriteStatusRequest { resource_name })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response,
+        QueryWriteStatusResponse {
+            committed_size: 6,
+            complete: true,
+        }
+    );
 
-    // Read the blob back from the storage.
+    // The resumed upload stored the complete blob.
     let request = ReadRequest {
         resource_name: format!(
             "{}/blobs/{}/{}",
@@ -279,39 +1057,177 @@ async fn check_bytestream_apis() {
         read_limit: 0,
     };
     let response = bs_client.read(request).await.unwrap();
-    let mut stream = response.into_inner();
-    let chunk = stream.next().await.unwrap().unwrap();
-    assert_eq!(
-        chunk,
-        ReadResponse {
-            data: content.bytes.clone()
-        }
+    let chunk = response.into_inner().next().await.unwrap().unwrap();
+    assert_eq!(chunk.data, content.bytes);
+}
+
+#[tokio::test]
+async fn validates_write_offset_when_resuming_bytestream_write() {
+    let (storage, action_cache, instance) = create_storage();
+
+    let content = TestData::from_static(b"foobar");
+
+    let server = spawn_server(storage, action_cache, false, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut bs_client = ByteStreamClient::new(channel);
+
+    let resource_name = format!(
+        "{}/uploads/12345/blobs/{}/{}",
+        &instance.name,
+        hex::encode(content.digest.hash),
+        content.digest.size_bytes
     );
-    assert!(stream.next().await.is_none());
+    let write_request = |write_offset: usize, end: usize| WriteRequest {
+        resource_name: resource_name.clone(),
+        write_offset: write_offset as i64,
+        finish_write: end == content.bytes.len(),
+        data: content.bytes.slice(write_offset..end),
+    };
 
-    // Confirm that re-writing it succeeds early.
-    let write_stream = {
-        let resource_name = resource_name.clone();
-        let content = content.bytes.clone();
-        async_stream::stream! {
-        let req1 = WriteRequest {
-            resource_name,
-            write_offset: 0,
-            finish_write: false,
-            data: content.slice(0..3),
+    // A non-zero offset for an upload which was never started leaves a gap.
+    let status = bs_client
+        .write(futures::stream::iter(vec![write_request(3, 6)]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    // Interrupt an upload after the first half.
+    bs_client
+        .write(futures::stream::iter(vec![write_request(0, 3)]))
+        .await
+        .unwrap_err();
+
+    // Resuming past the committed size is rejected, but the upload can still be resumed.
+    let status = bs_client
+        .write(futures::stream::iter(vec![write_request(4, 6)]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    let response = bs_client
+        .query_write_status(QueryWriteStatusRequest {
+            resource_name: resource_name.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.committed_size, 3);
+
+    // Resuming exactly at the committed size completes the upload.
+    let response = bs_client
+        .write(futures::stream::iter(vec![write_request(3, 6)]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response, WriteResponse { committed_size: 6 });
+}
+
+/// Tests that writes of blobs larger than the configured maximum blob size are rejected with
+/// `ResourceExhausted`, whether declared by their resource name or only by the data sent, and
+/// that the limit applies to the size of the blob rather than of its compressed data.
+#[tokio::test]
+async fn rejects_bytestream_writes_exceeding_max_blob_size() {
+    let (storage, action_cache, instance) = create_storage();
+    let server = spawn_configured_server(
+        storage,
+        action_cache,
+        ServerConfig {
+            write_limits: ByteStreamWriteLimits {
+                max_buffered_bytes: 4,
+                max_blob_size_bytes: Some(8),
+            },
+            ..test_server_config()
+        },
+        None,
+    );
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let mut bs_client = ByteStreamClient::new(endpoint.connect_lazy());
+
+    let resource_name = |upload: &str, compressor: Option<&str>, digest: Digest| {
+        let blobs = match compressor {
+            Some(compressor) => format!("compressed-blobs/{compressor}"),
+            None => "blobs".to_owned(),
         };
-        yield req1;
-        }
+        format!(
+            "{}/uploads/{upload}/{blobs}/{}/{}",
+            &instance.name,
+            hex::encode(digest.hash),
+            digest.size_bytes
+        )
     };
-    let response = bs_client.write(write_stream).await.unwrap().into_inner();
-    assert_eq!(response, WriteResponse { committed_size: 6 });
+    let write_requests = |resource_name: &str, data: Bytes| {
+        (0..data.len())
+            .step_by(4)
+            .map(|offset| {
+                let end = (offset + 4).min(data.len());
+                WriteRequest {
+                    resource_name: resource_name.to_owned(),
+                    write_offset: offset as i64,
+                    finish_write: end == data.len(),
+                    data: data.slice(offset..end),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // A blob which is declared to be too large is rejected.
+    let content = TestData::from_static(b"0123456789abcdef");
+    let too_large = resource_name("1", None, content.digest);
+    let status = bs_client
+        .write(futures::stream::iter(write_requests(
+            &too_large,
+            content.bytes.clone(),
+        )))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    // As is a stream which sends more data than the limit for a blob declared to fit.
+    let content = TestData::from_static(b"01234567");
+    let overlong = resource_name("2", None, content.digest);
+    let status = bs_client
+        .write(futures::stream::iter(write_requests(
+            &overlong,
+            Bytes::from_static(b"0123456789ab"),
+        )))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    // Nothing was stored.
+    for resource_name in [too_large, overlong] {
+        let status = bs_client
+            .query_write_status(QueryWriteStatusRequest { resource_name })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    // A blob within the limit is accepted even though its compressed data exceeds it.
+    let compressed = Bytes::from(zstd::encode_all(&content.bytes[..], 0).unwrap());
+    assert!(compressed.len() > 8);
+    let response = bs_client
+        .write(futures::stream::iter(write_requests(
+            &resource_name("3", Some("zstd"), content.digest),
+            compressed.clone(),
+        )))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response,
+        WriteResponse {
+            committed_size: compressed.len() as i64
+        }
+    );
 }
 
 #[tokio::test]
 async fn check_action_cache_apis() {
     let (storage, action_cache, instance) = create_storage();
 
-    let server = spawn_server(storage, action_cache, false);
+    let server = spawn_server(storage, action_cache, false, false);
 
     let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
     let channel = Channel::balance_list(vec![endpoint].into_iter());
@@ -401,7 +1317,7 @@ async fn check_action_cache_apis() {
 async fn check_handling_of_inlining_data_with_action_cache() {
     let (storage, action_cache, instance) = create_storage();
 
-    let server = spawn_server(storage, action_cache, false);
+    let server = spawn_server(storage, action_cache, false, false);
 
     let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
     let channel = Channel::balance_list(vec![endpoint].into_iter());
@@ -551,7 +1467,7 @@ async fn check_handling_of_inlining_data_with_action_cache() {
 async fn check_capabilities_apis() {
     let (storage, action_cache, instance) = create_storage();
 
-    let server = spawn_server(storage, action_cache, false);
+    let server = spawn_server(storage, action_cache, false, false);
 
     let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
     let channel = Channel::balance_list(vec![endpoint].into_iter());
@@ -569,6 +1485,7 @@ async fn check_capabilities_apis() {
             action_cache_update_capabilities: Some(ActionCacheUpdateCapabilities {
                 update_enabled: true,
             }),
+            supported_compressors: vec![Compressor::Zstd as i32],
             ..CacheCapabilities::default()
         }),
         ..ServerCapabilities::default()
@@ -580,7 +1497,7 @@ async fn check_capabilities_apis() {
 async fn verify_check_action_completeness_checking() {
     let (storage, action_cache, instance) = create_storage();
 
-    let server = spawn_server(storage, action_cache, true);
+    let server = spawn_server(storage, action_cache, true, false);
 
     let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
     let channel = Channel::balance_list(vec![endpoint].into_iter());
@@ -680,3 +1597,169 @@ async fn verify_check_action_completeness_checking() {
         .into_inner();
     assert_eq!(response, action_result);
 }
+
+#[tokio::test]
+async fn completeness_check_walks_output_directory_trees() {
+    let (storage, action_cache, instance) = create_storage();
+
+    let server = spawn_server(storage, action_cache, true, false);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut action_cache_client = ActionCacheClient::new(channel.clone());
+    let mut cas_client = ContentAddressableStorageClient::new(channel);
+
+    // The tree references a file whose content is not stored in the CAS.
+    let missing_file = TestData::from_static(b"missing output");
+    let tree = Tree {
+        root: Some(Directory {
+            files: vec![FileNode {
+                name: "out.txt".to_owned(),
+                digest: Some(missing_file.digest.into()),
+                ..FileNode::default()
+            }],
+            ..Directory::default()
+        }),
+        children: vec![],
+    };
+    let tree_bytes = {
+        let mut buffer = BytesMut::with_capacity(tree.encoded_len());
+        tree.encode(&mut buffer).unwrap();
+        buffer.freeze()
+    };
+    let tree_digest = Digest::of_bytes(&tree_bytes).unwrap();
+
+    let request = BatchUpdateBlobsRequest {
+        requests: vec![batch_update_blobs_request::Request {
+            digest: Some(tree_digest.into()),
+            data: tree_bytes,
+        }],
+        instance_name: instance.name.clone(),
+    };
+    cas_client.batch_update_blobs(request).await.unwrap();
+
+    let action_digest = TestData::from_static(b"action").digest;
+    let action_result = ActionResult {
+        output_directories: vec![OutputDirectory {
+            path: "out".to_owned(),
+            tree_digest: Some(tree_digest.into()),
+        }],
+        ..ActionResult::default()
+    };
+    let request = UpdateActionResultRequest {
+        instance_name: instance.name.clone(),
+        action_digest: Some(action_digest.into()),
+        action_result: Some(action_result.clone()),
+        ..UpdateActionResultRequest::default()
+    };
+    action_cache_client
+        .update_action_result(request)
+        .await
+        .unwrap();
+
+    let request = GetActionResultRequest {
+        action_digest: Some(action_digest.into()),
+        instance_name: instance.name.clone(),
+        ..GetActionResultRequest::default()
+    };
+    let err = action_cache_client
+        .get_action_result(request.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    // Storing the file referenced by the tree makes the cache entry complete.
+    let request2 = BatchUpdateBlobsRequest {
+        requests: vec![batch_update_blobs_request::Request {
+            digest: Some(missing_file.digest.into()),
+            data: missing_file.bytes,
+        }],
+        instance_name: instance.name.clone(),
+    };
+    cas_client.batch_update_blobs(request2).await.unwrap();
+
+    let response = action_cache_client
+        .get_action_result(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response, action_result);
+}
+
+/// Collects formatted `tracing` output so tests can assert on emitted log lines.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock()).into_owned()
+    }
+}
+
+impl io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLogs {
+    type Writer = CapturedLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[tokio::test]
+async fn access_log_records_batch_read_blobs() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .finish();
+    // The test runtime is single-threaded, so the server tasks run on this thread as well.
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (storage, action_cache, instance) = create_storage();
+    let content = TestData::from_static(b"foobar");
+    let mut attempt = storage
+        .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+        .await
+        .unwrap();
+    attempt.write(content.bytes.clone()).await.unwrap();
+    attempt.commit().await.unwrap();
+    storage.ensure_instance(&instance, DriverState::default());
+
+    let server = spawn_server(storage, action_cache, false, true);
+
+    let endpoint = create_endpoint(&format!("http://{}", server.local_addr)).unwrap();
+    let channel = Channel::balance_list(vec![endpoint].into_iter());
+    let mut cas_client = ContentAddressableStorageClient::new(channel);
+
+    let request = BatchReadBlobsRequest {
+        instance_name: instance.name.clone(),
+        digests: vec![content.digest.into()],
+    };
+    cas_client.batch_read_blobs(request).await.unwrap();
+
+    let logs = logs.contents();
+    let line = logs
+        .lines()
+        .find(|line| line.contains("storage::access_log"))
+        .unwrap_or_else(|| panic!("no access log line in: {logs}"));
+    assert!(line.contains("instance=main"), "{line}");
+    assert!(line.contains("method=\"BatchReadBlobs\""), "{line}");
+    assert!(
+        line.contains(&format!("digest_hash={}", content.digest.hex())),
+        "{line}"
+    );
+    assert!(line.contains("digest_size=6"), "{line}");
+    assert!(line.contains("num_bytes=6"), "{line}");
+    assert!(line.contains("outcome=Ok"), "{line}");
+    assert!(!line.to_lowercase().contains("authorization"), "{line}");
+}
@@ -6,25 +6,122 @@ use bytes::{Buf, Bytes, BytesMut};
 use futures::StreamExt;
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, StorageError,
+    StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
+/// How `ChunkingStorage` splits written content into the chunks passed to the underlying driver.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChunkingStrategy {
+    /// Chunks of a fixed size (except for the final chunk).
+    Fixed(usize),
+
+    /// Chunks whose boundaries are derived from the content (using FastCDC), so that identical
+    /// regions of similar blobs are split into identical chunks. Chunks are between `min` and
+    /// `max` bytes (except for the final chunk), and average roughly `avg` bytes.
+    ///
+    /// Note: This does not by itself deduplicate storage. `RedisStorage` stores the chunks of
+    /// each write under keys unique to that write, so identical chunks are still stored once per
+    /// blob. Deduplicating them requires keying chunks by their content (and reference counting
+    /// them for deletion), which this strategy is groundwork for.
+    ContentDefined { min: usize, avg: usize, max: usize },
+}
+
+impl ChunkingStrategy {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            ChunkingStrategy::Fixed(0) => Err("Chunk size must be greater than zero".to_owned()),
+            ChunkingStrategy::ContentDefined { min, avg, max }
+                if min == 0 || min > avg || avg > max =>
+            {
+                Err(format!(
+                    "Content-defined chunk sizes must satisfy 0 < min <= avg <= max, \
+                     got min={min} avg={avg} max={max}"
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Initial capacity for buffers of chunks.
+    fn buffer_capacity(&self) -> usize {
+        match *self {
+            ChunkingStrategy::Fixed(size) => size,
+            ChunkingStrategy::ContentDefined { max, .. } => max,
+        }
+    }
+}
+
+/// Table of random values used by the gear rolling hash, generated deterministically so that
+/// chunk boundaries are stable across builds.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Mask selecting the `bits` most significant bits of the gear hash, which depend on the
+/// preceding 64 bytes of content.
+fn gear_mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        bits if bits >= 64 => u64::MAX,
+        bits => !(u64::MAX >> bits),
+    }
+}
+
+/// Find the first content-defined chunk boundary in `data` using FastCDC with normalized
+/// chunking: boundaries before `avg` bytes use a stricter mask than those after it.
+///
+/// Returns `None` if `data` is shorter than `max` and contains no boundary, in which case more
+/// data is needed to find one.
+fn find_content_defined_boundary(data: &[u8], min: usize, avg: usize, max: usize) -> Option<usize> {
+    let limit = data.len().min(max);
+    let bits = usize::BITS - avg.max(1).leading_zeros() - 1;
+    let strict_mask = gear_mask(bits + 1);
+    let loose_mask = gear_mask(bits.saturating_sub(1));
+
+    let mut hash = 0u64;
+    for (i, byte) in data.iter().enumerate().take(limit).skip(min) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        let mask = if i < avg { strict_mask } else { loose_mask };
+        if hash & mask == 0 {
+            return Some(i + 1);
+        }
+    }
+
+    (data.len() >= max).then_some(max)
+}
+
 /// Adds chunking semantics to another storage driver.
 ///
 /// Wraps an underlying storage driver to add batching of data in preferred chunk sizes to
 /// reads and writes. This relieves the underlying storage driver from having to implement
 /// that functionality.
+///
+/// Each chunk is passed to the underlying driver as a separate write, and so drivers which
+/// store chunks individually (e.g. `RedisStorage`, which records the number of chunks in its
+/// metadata chunk) reassemble content chunked with either strategy.
 pub struct ChunkingStorage<BS> {
     underlying: BS,
-    write_chunk_size: usize,
+    strategy: ChunkingStrategy,
 }
 
 struct WriteAttempt {
     underlying: Box<dyn WriteAttemptOps + Send + Sync>,
     buffer: BytesMut,
-    chunk_size: usize,
+    strategy: ChunkingStrategy,
 }
 
 #[async_trait]
@@ -32,6 +129,7 @@ impl<BS> BlobStorage for ChunkingStorage<BS>
 where
     BS: BlobStorage + Send + Sync + 'static,
 {
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
     async fn find_missing_blobs(
         &self,
         instance: Instance,
@@ -44,6 +142,7 @@ where
             .await
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
     async fn read_blob(
         &self,
         instance: Instance,
@@ -101,6 +200,7 @@ where
         Ok(Some(stream))
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -114,35 +214,96 @@ where
             .await?;
         let wrapped_attempt = WriteAttempt {
             underlying: attempt,
-            buffer: BytesMut::with_capacity(self.write_chunk_size),
-            chunk_size: self.write_chunk_size,
+            buffer: BytesMut::with_capacity(self.strategy.buffer_capacity()),
+            strategy: self.strategy,
         };
         Ok(Box::new(wrapped_attempt))
     }
+
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        self.underlying.read_blobs(instance, digests, state).await
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.underlying.stat(instance, digest, state).await
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying.delete_blobs(instance, digests, state).await
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying
+            .list_recent_blobs(instance, limit, state)
+            .await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.underlying
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.underlying.ensure_instance(instance, state);
+    }
 }
 
 impl WriteAttempt {
     /// Stores the current buffer into the underlying storage driver and sets up a fresh
     /// buffer for further writes.
     async fn store_buffer(&mut self, last: bool) -> Result<(), StreamingWriteError> {
-        let chunk_size = if last { 0 } else { self.chunk_size };
-        let buffer = std::mem::replace(&mut self.buffer, BytesMut::with_capacity(chunk_size));
+        let capacity = if last {
+            0
+        } else {
+            self.strategy.buffer_capacity()
+        };
+        let buffer = std::mem::replace(&mut self.buffer, BytesMut::with_capacity(capacity));
         let buffer = buffer.freeze();
         self.underlying.write(buffer).await?;
         Ok(())
     }
-}
-
-#[async_trait]
-impl WriteAttemptOps for WriteAttempt {
-    async fn write(&mut self, mut data: Bytes) -> Result<(), StreamingWriteError> {
-        metrics::counter!("toolchain_storage_bytes_written_total", data.len() as u64, "driver" => "chunking");
 
+    async fn write_fixed(
+        &mut self,
+        mut data: Bytes,
+        chunk_size: usize,
+    ) -> Result<(), StreamingWriteError> {
         // Write the data into the temporary buffer used to create the chunk.
         while data.has_remaining() {
             // Attempt to fill the current chunk from the buffer to the preferred size.
-            debug_assert!(self.buffer.len() < self.chunk_size);
-            let bytes_remaining_to_fill_chunk = self.chunk_size - self.buffer.len();
+            debug_assert!(self.buffer.len() < chunk_size);
+            let bytes_remaining_to_fill_chunk = chunk_size - self.buffer.len();
             debug_assert!(bytes_remaining_to_fill_chunk > 0);
 
             // Copy the next set of bytes into the chunk.
@@ -151,7 +312,7 @@ impl WriteAttemptOps for WriteAttempt {
             data.advance(bytes_to_read);
 
             // If the buffer is at the preferred size, send it the underlying driver.
-            if self.buffer.len() >= self.chunk_size {
+            if self.buffer.len() >= chunk_size {
                 self.store_buffer(false).await?;
             }
         }
@@ -159,6 +320,38 @@ impl WriteAttemptOps for WriteAttempt {
         Ok(())
     }
 
+    async fn write_content_defined(
+        &mut self,
+        data: Bytes,
+        min: usize,
+        avg: usize,
+        max: usize,
+    ) -> Result<(), StreamingWriteError> {
+        // Boundaries only depend on the content since the previous boundary, and so are the same
+        // regardless of how the content is split across calls to `write`.
+        self.buffer.extend_from_slice(&data);
+        while let Some(boundary) = find_content_defined_boundary(&self.buffer, min, avg, max) {
+            let chunk = self.buffer.split_to(boundary).freeze();
+            self.underlying.write(chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WriteAttemptOps for WriteAttempt {
+    async fn write(&mut self, data: Bytes) -> Result<(), StreamingWriteError> {
+        metrics::counter!("toolchain_storage_bytes_written_total", data.len() as u64, "driver" => "chunking");
+
+        match self.strategy {
+            ChunkingStrategy::Fixed(chunk_size) => self.write_fixed(data, chunk_size).await,
+            ChunkingStrategy::ContentDefined { min, avg, max } => {
+                self.write_content_defined(data, min, avg, max).await
+            }
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "chunking"))]
     async fn commit(mut self: Box<Self>) -> Result<(), StreamingWriteError> {
         if !self.buffer.is_empty() {
             self.store_buffer(true).await?;
@@ -174,9 +367,13 @@ where
 {
     #[allow(dead_code)]
     pub fn new(underlying: BS, write_chunk_size: usize) -> Self {
+        Self::with_strategy(underlying, ChunkingStrategy::Fixed(write_chunk_size))
+    }
+
+    pub fn with_strategy(underlying: BS, strategy: ChunkingStrategy) -> Self {
         ChunkingStorage {
             underlying,
-            write_chunk_size,
+            strategy,
         }
     }
 
@@ -202,10 +399,11 @@ mod tests {
     use futures::{future, FutureExt, Stream, TryStreamExt};
     use parking_lot::Mutex;
 
-    use crate::driver::chunking::ChunkingStorage;
+    use crate::bytes::consolidate_stream;
+    use crate::driver::chunking::{ChunkingStorage, ChunkingStrategy};
     use crate::driver::{
-        BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-        WriteAttemptOps,
+        BlobStorage, BoxReadStream, DriverState, Instance, MemoryStorage, StorageError,
+        StreamingWriteError, WriteAttemptOps,
     };
     use crate::Digest;
 
@@ -321,7 +519,7 @@ mod tests {
             writes: Arc::new(Mutex::new(VecDeque::new())),
             reads: Arc::new(Mutex::new(VecDeque::new())),
         };
-        let mut storage = ChunkingStorage::new(test_storage, 5);
+        let storage = ChunkingStorage::new(test_storage, 5);
 
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
@@ -388,7 +586,7 @@ mod tests {
             writes: Arc::new(Mutex::new(VecDeque::new())),
             reads: Arc::new(Mutex::new(VecDeque::new())),
         };
-        let mut storage = ChunkingStorage::new(test_storage, 5);
+        let storage = ChunkingStorage::new(test_storage, 5);
 
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
@@ -440,4 +638,104 @@ mod tests {
         let lengths = collect_lengths(stream).await.unwrap();
         assert_eq!(lengths, vec![5, 5, 2]);
     }
+
+    /// Deterministic pseudo-random content (xorshift64).
+    fn make_random_content(n: usize) -> Bytes {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut buffer = BytesMut::with_capacity(n);
+        while buffer.len() < n {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let remaining = n - buffer.len();
+            buffer.extend_from_slice(&state.to_le_bytes()[0..remaining.min(8)]);
+        }
+        buffer.freeze()
+    }
+
+    const CONTENT_DEFINED: ChunkingStrategy = ChunkingStrategy::ContentDefined {
+        min: 16 * 1024,
+        avg: 64 * 1024,
+        max: 256 * 1024,
+    };
+
+    async fn write_in_batches<BS: BlobStorage>(
+        storage: &BS,
+        instance: &Instance,
+        content: &Bytes,
+        batch_size: usize,
+    ) {
+        let digest = Digest::of_bytes(content).unwrap();
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), digest, DriverState::default())
+            .await
+            .unwrap();
+        for start in (0..content.len()).step_by(batch_size) {
+            let end = (start + batch_size).min(content.len());
+            attempt.write(content.slice(start..end)).await.unwrap();
+        }
+        attempt.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn content_defined_chunking_round_trip() {
+        let instance = Instance::from("main");
+        let content = make_random_content(4 * 1024 * 1024 + 123);
+        assert!(CONTENT_DEFINED.validate().is_ok());
+
+        // Chunk boundaries are derived from the content, and so do not depend on how it is
+        // batched when written.
+        let test_storage = TestStorage {
+            writes: Arc::new(Mutex::new(VecDeque::new())),
+            reads: Arc::new(Mutex::new(VecDeque::new())),
+        };
+        let storage = ChunkingStorage::with_strategy(test_storage, CONTENT_DEFINED);
+        write_in_batches(&storage, &instance, &content, 100_000).await;
+        write_in_batches(&storage, &instance, &content, 4096).await;
+        let chunk_sizes1 = storage.get_inner().writes.lock().pop_front().unwrap();
+        let chunk_sizes2 = storage.get_inner().writes.lock().pop_front().unwrap();
+        assert_eq!(chunk_sizes1, chunk_sizes2);
+
+        assert_eq!(chunk_sizes1.iter().sum::<usize>(), content.len());
+        let (last, rest) = chunk_sizes1.split_last().unwrap();
+        assert!(*last <= 256 * 1024);
+        assert!(rest
+            .iter()
+            .all(|size| (16 * 1024..=256 * 1024).contains(size)));
+        assert!(
+            rest.iter().any(|size| *size != rest[0]),
+            "chunk sizes should vary with content: {chunk_sizes1:?}"
+        );
+
+        // Content is reassembled correctly.
+        let memory = MemoryStorage::new();
+        memory.ensure_instance(&instance, DriverState::default());
+        let storage = ChunkingStorage::with_strategy(memory, CONTENT_DEFINED);
+        write_in_batches(&storage, &instance, &content, 100_000).await;
+        let stream = storage
+            .read_blob(
+                instance,
+                Digest::of_bytes(&content).unwrap(),
+                64 * 1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consolidate_stream(stream).await.unwrap(), content);
+    }
+
+    #[test]
+    fn validates_chunking_strategy() {
+        assert!(ChunkingStrategy::Fixed(0).validate().is_err());
+        assert!(ChunkingStrategy::ContentDefined {
+            min: 1024,
+            avg: 512,
+            max: 4096
+        }
+        .validate()
+        .is_err());
+    }
 }
@@ -0,0 +1,324 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::driver::{
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, StorageError,
+    StreamingWriteError, WriteAttemptOps,
+};
+use crate::Digest;
+
+/// A `BlobStorage` that bounds the number of in-flight operations against an underlying
+/// storage driver. This protects backends like Redis from being saturated by bursts of traffic.
+///
+/// A permit is held for the duration of each `find_missing_blobs` call, for the lifetime of the
+/// stream returned by `read_blob`, and for the lifetime of the write attempt returned by
+/// `begin_write_blob`. If no permit becomes available within the acquire timeout, the operation
+/// fails with `StorageError::Unavailable`.
+pub struct ConcurrencyLimitStorage<S> {
+    semaphore: Arc<Semaphore>,
+    acquire_timeout: Duration,
+    purpose: &'static str,
+    inner: S,
+}
+
+impl<S> ConcurrencyLimitStorage<S> {
+    pub fn new(
+        inner: S,
+        max_in_flight: usize,
+        acquire_timeout: Duration,
+        purpose: &'static str,
+    ) -> Self {
+        ConcurrencyLimitStorage {
+            semaphore: Arc::new(Semaphore::new(max_in_flight)),
+            acquire_timeout,
+            purpose,
+            inner,
+        }
+    }
+
+    async fn acquire_permit(
+        &self,
+        operation: &'static str,
+    ) -> Result<OwnedSemaphorePermit, StorageError> {
+        match tokio::time::timeout(self.acquire_timeout, self.semaphore.clone().acquire_owned())
+            .await
+        {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(StorageError::Internal(
+                "Concurrency limit semaphore was closed".to_owned(),
+            )),
+            Err(_) => {
+                metrics::counter!(
+                    "toolchain_storage_concurrency_limit_timeouts_total",
+                    1,
+                    "operation" => operation,
+                    "purpose" => self.purpose,
+                );
+                Err(StorageError::Unavailable(format!(
+                    "Timed out after {:?} waiting for a concurrency limit permit",
+                    self.acquire_timeout
+                )))
+            }
+        }
+    }
+}
+
+/// Read stream that holds a concurrency limit permit until the stream is dropped.
+struct PermitReadStream {
+    stream: BoxReadStream,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Stream for PermitReadStream {
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+/// Write attempt that holds a concurrency limit permit until the attempt is committed or dropped.
+struct PermitWriteAttempt {
+    inner: Box<dyn WriteAttemptOps + Send + Sync + 'static>,
+    _permit: OwnedSemaphorePermit,
+}
+
+#[async_trait]
+impl WriteAttemptOps for PermitWriteAttempt {
+    async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
+        self.inner.write(batch).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError> {
+        self.inner.commit().await
+    }
+}
+
+#[async_trait]
+impl<S> BlobStorage for ConcurrencyLimitStorage<S>
+where
+    S: BlobStorage + Send + Sync + 'static,
+{
+    async fn find_missing_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let _permit = self.acquire_permit("find_missing_blobs").await?;
+        self.inner
+            .find_missing_blobs(instance, digests, state)
+            .await
+    }
+
+    async fn read_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        max_batch_size: usize,
+        read_offset: Option<usize>,
+        read_limit: Option<usize>,
+        state: DriverState,
+    ) -> Result<Option<BoxReadStream>, StorageError> {
+        let permit = self.acquire_permit("read").await?;
+        let stream_opt = self
+            .inner
+            .read_blob(
+                instance,
+                digest,
+                max_batch_size,
+                read_offset,
+                read_limit,
+                state,
+            )
+            .await?;
+        Ok(stream_opt.map(|stream| {
+            Box::pin(PermitReadStream {
+                stream,
+                _permit: permit,
+            }) as BoxReadStream
+        }))
+    }
+
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        let _permit = self.acquire_permit("read").await?;
+        self.inner.read_blobs(instance, digests, state).await
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        let _permit = self.acquire_permit("stat").await?;
+        self.inner.stat(instance, digest, state).await
+    }
+
+    async fn begin_write_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        let permit = self.acquire_permit("write").await?;
+        let attempt = self.inner.begin_write_blob(instance, digest, state).await?;
+        Ok(Box::new(PermitWriteAttempt {
+            inner: attempt,
+            _permit: permit,
+        }))
+    }
+
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let _permit = self.acquire_permit("delete").await?;
+        self.inner.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let _permit = self.acquire_permit("list").await?;
+        self.inner.list_recent_blobs(instance, limit, state).await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        let _permit = self.acquire_permit("list").await?;
+        self.inner
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.inner.ensure_instance(instance, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use futures::StreamExt;
+
+    use super::ConcurrencyLimitStorage;
+    use crate::driver::{BlobStorage, DriverState, Instance, MemoryStorage, StorageError};
+    use crate::testutil::{SlowStorage, TestData};
+
+    #[tokio::test]
+    async fn times_out_when_permits_are_saturated() {
+        let memory = MemoryStorage::new();
+        let instance = Instance::from("main");
+        memory.ensure_instance(&instance, DriverState::default());
+        let storage = ConcurrencyLimitStorage::new(memory, 1, Duration::from_millis(50), "test");
+
+        let content = TestData::from_static(b"foobar");
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        // Hold the only permit by keeping the read stream alive.
+        let mut stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let err = storage
+            .find_missing_blobs(
+                instance.clone(),
+                vec![content.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::Unavailable(_)));
+
+        // Consuming and dropping the stream releases the permit.
+        assert_eq!(stream.next().await.unwrap(), Ok(content.bytes.clone()));
+        drop(stream);
+
+        let missing_blobs = storage
+            .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+            .await
+            .unwrap();
+        assert!(missing_blobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn enforces_acquire_timeout_with_slow_storage() {
+        let slow = SlowStorage::new(MemoryStorage::new(), Duration::from_secs(2));
+        let instance = Instance::from("main");
+        slow.ensure_instance(&instance, DriverState::default());
+        let storage = Arc::new(ConcurrencyLimitStorage::new(
+            slow,
+            1,
+            Duration::from_millis(50),
+            "test",
+        ));
+        let content = TestData::from_static(b"foobar");
+
+        // The first call holds the only permit while the slow storage delays it.
+        let slow_call = {
+            let storage = storage.clone();
+            let instance = instance.clone();
+            tokio::spawn(async move {
+                storage
+                    .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+                    .await
+            })
+        };
+        while storage.inner.call_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let start = Instant::now();
+        let err = storage
+            .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::Unavailable(_)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(storage.inner.call_count(), 1);
+
+        slow_call.abort();
+    }
+}
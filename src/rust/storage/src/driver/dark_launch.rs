@@ -5,22 +5,28 @@ use std::collections::HashSet;
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::StreamExt;
+use sha2::{Digest as Sha256Digest, Sha256};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, StorageError,
+    StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
 /// A `BlobStorage` that allows "dark launching" a new feature by redirecting traffic for
 /// some customers to a new backend.
+///
+/// Failures of the secondary storage (when writing to it, or when comparing reads against it)
+/// never affect the result of an operation, and are only counted.
 pub struct DarkLaunchStorage<S1, S2> {
     storage1: S1,
     storage2: S2,
     storage2_instance_names: HashSet<String>,
     write_to_secondary: bool,
+    compare_probability: f64,
     purpose: &'static str,
 }
 
@@ -41,19 +47,50 @@ struct WriteAttempt {
     _secondary_processor_fut: JoinHandle<()>,
 }
 
+fn count_secondary_error(op: &'static str, purpose: &'static str) {
+    metrics::counter!(
+        "toolchain_storage_dark_launch_secondary_error_total",
+        1,
+        "op" => op,
+        "driver" => "dark_launch",
+        "purpose" => purpose,
+    );
+}
+
+fn count_mismatch(kind: &'static str, purpose: &'static str) {
+    metrics::counter!(
+        "toolchain_storage_dark_launch_mismatch_total",
+        1,
+        "kind" => kind,
+        "driver" => "dark_launch",
+        "purpose" => purpose,
+    );
+}
+
+/// The SHA-256 hash of the content of a stream.
+async fn hash_stream(
+    mut stream: BoxReadStream,
+) -> Result<sha2::digest::Output<Sha256>, StorageError> {
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = stream.next().await {
+        hasher.update(chunk?);
+    }
+    Ok(hasher.finalize())
+}
+
 #[async_trait]
 impl WriteAttemptOps for WriteAttempt {
     async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
-        self.secondary_sender
-            .send(SecondaryWriteChannelOp::Write(batch.clone()))
-            .unwrap();
+        // The secondary processor only goes away if it panicked, which must not affect the
+        // primary write.
+        let _ = self
+            .secondary_sender
+            .send(SecondaryWriteChannelOp::Write(batch.clone()));
         self.primary_attempt.write(batch).await
     }
 
     async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError> {
-        self.secondary_sender
-            .send(SecondaryWriteChannelOp::Commit)
-            .unwrap();
+        let _ = self.secondary_sender.send(SecondaryWriteChannelOp::Commit);
         self.primary_attempt.commit().await
     }
 }
@@ -80,12 +117,7 @@ impl WriteAttempt {
 
                             let result = secondary_attempt.write(batch).await;
                             if result.is_err() {
-                                metrics::counter!(
-                                    "toolchain_storage_secondary_write_errors_total",
-                                    1,
-                                    "driver" => "dark_launch",
-                                    "purpose" => purpose,
-                                );
+                                count_secondary_error("write", purpose);
                                 saw_error = true;
                             }
                         }
@@ -99,12 +131,7 @@ impl WriteAttempt {
                 if do_commit && !saw_error {
                     let result = secondary_attempt.commit().await;
                     if result.is_err() {
-                        metrics::counter!(
-                            "toolchain_storage_secondary_write_errors_total",
-                            1,
-                            "driver" => "dark_launch",
-                            "purpose" => purpose,
-                        );
+                        count_secondary_error("commit", purpose);
                     }
                 }
             })
@@ -168,6 +195,53 @@ where
                     )
                     .await
             }
+            StorageChoice::Storage2 if rand::random::<f64>() < self.compare_probability => {
+                let (primary_result, secondary_result) = futures::join!(
+                    self.storage2.read_blob(
+                        instance.clone(),
+                        digest,
+                        max_batch_size,
+                        read_offset,
+                        read_limit,
+                        state.clone(),
+                    ),
+                    self.storage1.read_blob(
+                        instance,
+                        digest,
+                        max_batch_size,
+                        read_offset,
+                        read_limit,
+                        state,
+                    ),
+                );
+                let secondary_stream_opt = match secondary_result {
+                    Ok(secondary_stream_opt) => secondary_stream_opt,
+                    Err(_) => {
+                        count_secondary_error("read", self.purpose);
+                        return primary_result;
+                    }
+                };
+                match primary_result {
+                    Ok(Some(primary_stream)) => match secondary_stream_opt {
+                        Some(secondary_stream) => Ok(Some(Self::compare_streams(
+                            primary_stream,
+                            secondary_stream,
+                            self.purpose,
+                        ))),
+                        None => {
+                            count_mismatch("presence", self.purpose);
+                            Ok(Some(primary_stream))
+                        }
+                    },
+                    Ok(None) => {
+                        if secondary_stream_opt.is_some() {
+                            count_mismatch("presence", self.purpose);
+                        }
+                        Ok(None)
+                    }
+                    Err(err) => Err(err),
+                }
+            }
             StorageChoice::Storage2 => {
                 self.storage2
                     .read_blob(
@@ -183,6 +257,45 @@ where
         }
     }
 
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        match self.choose_storage(&instance) {
+            StorageChoice::Storage1 => self.storage1.read_blobs(instance, digests, state).await,
+            StorageChoice::Storage2 if rand::random::<f64>() < self.compare_probability => {
+                let (primary_result, secondary_result) = futures::join!(
+                    self.storage2
+                        .read_blobs(instance.clone(), digests.clone(), state.clone()),
+                    self.storage1.read_blobs(instance, digests, state),
+                );
+                match (&primary_result, secondary_result) {
+                    (Ok(primary_blobs), Ok(secondary_blobs)) => {
+                        Self::compare_blobs(primary_blobs, &secondary_blobs, self.purpose)
+                    }
+                    (_, Err(_)) => count_secondary_error("read", self.purpose),
+                    (Err(_), Ok(_)) => (),
+                }
+                primary_result
+            }
+            StorageChoice::Storage2 => self.storage2.read_blobs(instance, digests, state).await,
+        }
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        match self.choose_storage(&instance) {
+            StorageChoice::Storage1 => self.storage1.stat(instance, digest, state).await,
+            StorageChoice::Storage2 => self.storage2.stat(instance, digest, state).await,
+        }
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -197,11 +310,8 @@ where
                     .await?;
 
                 let attempt2_opt = if self.write_to_secondary {
-                    Some(
-                        self.storage2
-                            .begin_write_blob(instance, digest, state)
-                            .await?,
-                    )
+                    self.begin_secondary_write(&self.storage2, instance, digest, state)
+                        .await
                 } else {
                     None
                 };
@@ -215,11 +325,8 @@ where
                     .await?;
 
                 let attempt1_opt = if self.write_to_secondary {
-                    Some(
-                        self.storage1
-                            .begin_write_blob(instance, digest, state)
-                            .await?,
-                    )
+                    self.begin_secondary_write(&self.storage1, instance, digest, state)
+                        .await
                 } else {
                     None
                 };
@@ -233,7 +340,67 @@ where
         Ok(Box::new(merged_attempt))
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    /// Deletes from both storages since content may have been written to either of them. The
+    /// deleted digests are those reported by the primary storage for the instance.
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let (deleted1, deleted2) = futures::try_join!(
+            self.storage1
+                .delete_blobs(instance.clone(), digests.clone(), state.clone()),
+            self.storage2.delete_blobs(instance.clone(), digests, state),
+        )?;
+        match self.choose_storage(&instance) {
+            StorageChoice::Storage1 => Ok(deleted1),
+            StorageChoice::Storage2 => Ok(deleted2),
+        }
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        match self.choose_storage(&instance) {
+            StorageChoice::Storage1 => {
+                self.storage1
+                    .list_recent_blobs(instance, limit, state)
+                    .await
+            }
+            StorageChoice::Storage2 => {
+                self.storage2
+                    .list_recent_blobs(instance, limit, state)
+                    .await
+            }
+        }
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        match self.choose_storage(&instance) {
+            StorageChoice::Storage1 => {
+                self.storage1
+                    .list_blobs(instance, cursor, page_size, state)
+                    .await
+            }
+            StorageChoice::Storage2 => {
+                self.storage2
+                    .list_blobs(instance, cursor, page_size, state)
+                    .await
+            }
+        }
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
         self.storage1.ensure_instance(instance, state.clone());
         self.storage2.ensure_instance(instance, state);
     }
@@ -264,11 +431,88 @@ where
         }
     }
 
+    /// Begin a write to the secondary storage, counting (and otherwise ignoring) any failure.
+    async fn begin_secondary_write<S>(
+        &self,
+        secondary: &S,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Option<Box<dyn WriteAttemptOps + Send + Sync + 'static>>
+    where
+        S: BlobStorage + Send + Sync + 'static,
+    {
+        match secondary.begin_write_blob(instance, digest, state).await {
+            Ok(attempt) => Some(attempt),
+            Err(_) => {
+                count_secondary_error("begin_write", self.purpose);
+                None
+            }
+        }
+    }
+
+    /// Pass through the primary stream, and once it has been read completely, compare its
+    /// content with the secondary stream in the background. The streams are compared by their
+    /// hashes, so that neither is buffered in memory.
+    fn compare_streams(
+        mut primary_stream: BoxReadStream,
+        secondary_stream: BoxReadStream,
+        purpose: &'static str,
+    ) -> BoxReadStream {
+        Box::pin(async_stream::stream! {
+            let mut primary_hasher = Sha256::new();
+            let mut complete = true;
+            while let Some(chunk) = primary_stream.next().await {
+                match &chunk {
+                    Ok(bytes) => primary_hasher.update(bytes),
+                    Err(_) => complete = false,
+                }
+                yield chunk;
+            }
+
+            if complete {
+                let primary_hash = primary_hasher.finalize();
+                tokio::spawn(async move {
+                    match hash_stream(secondary_stream).await {
+                        Ok(secondary_hash) if secondary_hash == primary_hash => (),
+                        Ok(_) => count_mismatch("content", purpose),
+                        Err(_) => count_secondary_error("read", purpose),
+                    }
+                });
+            }
+        })
+    }
+
+    /// Compare the results of a batch read from each storage, which are in the same order.
+    fn compare_blobs(
+        primary_blobs: &[(Digest, Result<Option<Bytes>, StorageError>)],
+        secondary_blobs: &[(Digest, Result<Option<Bytes>, StorageError>)],
+        purpose: &'static str,
+    ) {
+        for ((_, primary_result), (_, secondary_result)) in
+            primary_blobs.iter().zip(secondary_blobs)
+        {
+            match (primary_result, secondary_result) {
+                (_, Err(_)) => count_secondary_error("read", purpose),
+                (Ok(Some(primary)), Ok(Some(secondary))) if primary != secondary => {
+                    count_mismatch("content", purpose)
+                }
+                (Ok(Some(_)), Ok(None)) | (Ok(None), Ok(Some(_))) => {
+                    count_mismatch("presence", purpose)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Create a new `DarkLaunchStorage`. Reads for instances in `storage2_instance_names` are
+    /// also compared against `storage1` with probability `compare_probability` (between 0 and 1).
     pub fn new(
         storage1: S1,
         storage2: S2,
         storage2_instance_names: impl IntoIterator<Item = String>,
         write_to_secondary: bool,
+        compare_probability: f64,
         purpose: &'static str,
     ) -> Self {
         DarkLaunchStorage {
@@ -276,6 +520,7 @@ where
             storage2,
             storage2_instance_names: storage2_instance_names.into_iter().collect::<HashSet<_>>(),
             write_to_secondary,
+            compare_probability,
             purpose,
         }
     }
@@ -296,8 +541,13 @@ mod tests {
 
     use super::DarkLaunchStorage;
     use crate::bytes::consolidate_stream;
-    use crate::driver::{BlobStorage, DriverState, Instance, MemoryStorage};
-    use crate::testutil::{CountMethodCallsStorage, TestData, WriteSemaphoreStorage};
+    use crate::driver::{
+        AlwaysErrorsStorage, BlobStorage, DriverState, Instance, MemoryStorage,
+        SmallBlobStorageAdapter,
+    };
+    use crate::testutil::{
+        capture_metrics, counter_total, CountMethodCallsStorage, TestData, WriteSemaphoreStorage,
+    };
     use crate::Digest;
 
     async fn acquire_and_forget(semaphore: &Semaphore, n: u32, timeout: Duration) {
@@ -334,11 +584,12 @@ mod tests {
 
         let semaphore = Arc::new(Semaphore::new(0));
 
-        let mut storage = DarkLaunchStorage::new(
+        let storage = DarkLaunchStorage::new(
             WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone()),
             WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone()),
             vec![new_instance.name.clone()],
             true,
+            0.0,
             "test",
         );
 
@@ -429,11 +680,12 @@ mod tests {
 
         let semaphore = Arc::new(Semaphore::new(0));
 
-        let mut storage = DarkLaunchStorage::new(
+        let storage = DarkLaunchStorage::new(
             WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone()),
             WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone()),
             vec![new_instance.name.clone()],
             false, // note: do not write to secondary storage
+            0.0,
             "test",
         );
 
@@ -522,11 +774,12 @@ mod tests {
         let instance1 = Instance::from("left");
         let instance2 = Instance::from("right");
 
-        let mut storage = DarkLaunchStorage::new(
+        let storage = DarkLaunchStorage::new(
             CountMethodCallsStorage::new(MemoryStorage::new()),
             CountMethodCallsStorage::new(MemoryStorage::new()),
             vec![instance2.name.clone()],
             true,
+            0.0,
             "test",
         );
 
@@ -585,4 +838,117 @@ mod tests {
         assert_eq!(storage2.read_count.load(Ordering::SeqCst), 1);
         assert_eq!(storage2.write_count.load(Ordering::SeqCst), 2);
     }
+
+    #[tokio::test]
+    async fn dark_launch_storage_isolates_secondary_failures() {
+        let content = TestData::from_static(b"foobar");
+        let old_instance = Instance::from("old");
+        let new_instance = Instance::from("new");
+
+        // Storage #1 is secondary for `new_instance`, and storage #2 is secondary for
+        // `old_instance`: both reads and writes against the broken secondary must succeed.
+        let storage = DarkLaunchStorage::new(
+            SmallBlobStorageAdapter::new(AlwaysErrorsStorage),
+            MemoryStorage::new(),
+            vec![new_instance.name.clone()],
+            true,
+            1.0,
+            "test",
+        );
+        storage.ensure_instance(&old_instance, DriverState::default());
+        storage.ensure_instance(&new_instance, DriverState::default());
+
+        let mut attempt = storage
+            .begin_write_blob(new_instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stream = storage
+            .read_blob(
+                new_instance,
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+
+        // The primary storage for `old_instance` is the broken one, so its errors do surface.
+        let mut attempt = storage
+            .begin_write_blob(old_instance, content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes).await.unwrap();
+        assert!(attempt.commit().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dark_launch_storage_counts_content_mismatches() {
+        capture_metrics();
+        let good_content = TestData::from_static(b"foobar");
+        let bad_content = TestData::from_static(b"barfoo");
+        let instance = Instance::from("main");
+
+        // Storage #1 holds corrupt content under the digest of the good content.
+        let storage1 = MemoryStorage::new();
+        let storage2 = MemoryStorage::new();
+        for (storage, content) in [(&storage1, &bad_content), (&storage2, &good_content)] {
+            storage.ensure_instance(&instance, DriverState::default());
+            let mut attempt = storage
+                .begin_write_blob(
+                    instance.clone(),
+                    good_content.digest,
+                    DriverState::default(),
+                )
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+        let storage = DarkLaunchStorage::new(
+            storage1,
+            storage2,
+            vec![instance.name.clone()],
+            false,
+            1.0,
+            "content_mismatch_test",
+        );
+
+        let labels = [("kind", "content"), ("purpose", "content_mismatch_test")];
+        let mismatches_before =
+            counter_total("toolchain_storage_dark_launch_mismatch_total", &labels);
+        let stream = storage
+            .read_blob(
+                instance,
+                good_content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            consolidate_stream(stream).await.unwrap(),
+            good_content.bytes
+        );
+
+        // The comparison happens in the background once the primary stream has been read.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while counter_total("toolchain_storage_dark_launch_mismatch_total", &labels)
+                == mismatches_before
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("content mismatch was counted");
+    }
 }
@@ -2,6 +2,7 @@
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -10,13 +11,14 @@ use futures::StreamExt;
 use sha2::{Digest as Sha256Digest, Sha256};
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, StorageError,
+    StreamingWriteError, WriteAttemptOps,
 };
 
 /// A `BlobStorage` that wraps an underlying `BlobStorage` implementation and computes the
 /// digest of content as it is written. This is used to enforce that the digest of content matches
-/// the digest provided by the client on writes.
+/// the digest provided by the client on writes. Writes of more bytes than the digest's size are
+/// rejected as soon as they exceed it, and writes of fewer bytes are rejected on commit.
 ///
 /// Use `ReadDigestVerifier` instead to verify digests as they are read (to detect corruption
 /// in a storage backend).
@@ -44,6 +46,14 @@ pub struct WriteAttempt {
 impl WriteAttemptOps for WriteAttempt {
     async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
         self.written += batch.len();
+        if self.written > self.expected_digest.size_bytes {
+            // Fail without writing the excess bytes to the underlying storage.
+            return Err(StorageError::InvalidSize {
+                expected_size: self.expected_digest.size_bytes,
+                is_data_loss: false,
+            }
+            .into());
+        }
         self.hasher.update(batch.as_ref());
         self.underlying.write(batch).await
     }
@@ -111,6 +121,24 @@ where
             .await
     }
 
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        self.underlying.read_blobs(instance, digests, state).await
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.underlying.stat(instance, digest, state).await
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -130,7 +158,39 @@ where
         Ok(Box::new(wrapped_attempt))
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying
+            .list_recent_blobs(instance, limit, state)
+            .await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.underlying
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
         self.underlying.ensure_instance(instance, state)
     }
 }
@@ -146,10 +206,15 @@ impl<BS> WriteDigestVerifier<BS> {
 /// the original digest of that data when it is read again, which can help detect storage
 /// corruption.
 ///
+/// If `repair_on_mismatch` is set, a blob whose content does not match its digest on a full
+/// read is deleted from the underlying storage so that it may be rewritten by clients. The read
+/// which detected the corruption still fails.
+///
 /// Use `WriteDigestVerifier` instead to verify digests as they are written.
 #[derive(Debug)]
 pub struct ReadDigestVerifier<BS> {
-    underlying: BS,
+    underlying: Arc<BS>,
+    repair_on_mismatch: bool,
 }
 
 #[async_trait]
@@ -177,6 +242,12 @@ where
         read_limit: Option<usize>,
         state: DriverState,
     ) -> Result<Option<BoxReadStream>, StorageError> {
+        // Only a read of the entire blob can tell whether the stored blob itself is corrupt.
+        let is_full_read = read_offset.unwrap_or(0) == 0
+            && read_limit.map_or(true, |limit| limit >= digest.size_bytes);
+        let repair = (self.repair_on_mismatch && is_full_read)
+            .then(|| (self.underlying.clone(), instance.clone(), state.clone()));
+
         let stream_opt = self
             .underlying
             .read_blob(
@@ -215,6 +286,9 @@ where
             }
 
             if actual_size != digest.size_bytes {
+                if let Some((underlying, instance, state)) = repair {
+                    repair_corrupt_blob(underlying.as_ref(), instance, digest, state).await;
+                }
                 yield Err(StorageError::InvalidSize {
                     expected_size: digest.size_bytes,
                     is_data_loss: true,
@@ -225,6 +299,9 @@ where
             let hash = hasher.finalize();
             let actual_digest = Digest::from_slice(&hash, digest.size_bytes)?;
             if actual_digest != digest {
+                if let Some((underlying, instance, state)) = repair {
+                    repair_corrupt_blob(underlying.as_ref(), instance, digest, state).await;
+                }
                 yield Err(StorageError::InvalidHash {
                     expected_digest: digest,
                     actual_digest,
@@ -236,6 +313,51 @@ where
         Ok(Some(stream))
     }
 
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        let blobs = self
+            .underlying
+            .read_blobs(instance.clone(), digests, state.clone())
+            .await?;
+
+        let mut verified_blobs = Vec::with_capacity(blobs.len());
+        for (digest, result) in blobs {
+            let result = match result {
+                Ok(Some(content)) => match verify_content(digest, &content) {
+                    Ok(()) => Ok(Some(content)),
+                    Err(err) => {
+                        if self.repair_on_mismatch {
+                            repair_corrupt_blob(
+                                self.underlying.as_ref(),
+                                instance.clone(),
+                                digest,
+                                state.clone(),
+                            )
+                            .await;
+                        }
+                        Err(err)
+                    }
+                },
+                result => result,
+            };
+            verified_blobs.push((digest, result));
+        }
+        Ok(verified_blobs)
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.underlying.stat(instance, digest, state).await
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -247,14 +369,86 @@ where
             .await
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying
+            .list_recent_blobs(instance, limit, state)
+            .await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.underlying
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
         self.underlying.ensure_instance(instance, state);
     }
 }
 
 impl<BS> ReadDigestVerifier<BS> {
-    pub fn new(underlying: BS) -> Self {
-        ReadDigestVerifier { underlying }
+    pub fn new(underlying: BS, repair_on_mismatch: bool) -> Self {
+        ReadDigestVerifier {
+            underlying: Arc::new(underlying),
+            repair_on_mismatch,
+        }
+    }
+}
+
+/// Check that the entire `content` of a blob matches its `digest`.
+fn verify_content(digest: Digest, content: &[u8]) -> Result<(), StorageError> {
+    if content.len() != digest.size_bytes {
+        return Err(StorageError::InvalidSize {
+            expected_size: digest.size_bytes,
+            is_data_loss: true,
+        });
+    }
+
+    let actual_digest = Digest::from_slice(&Sha256::digest(content), digest.size_bytes)?;
+    if actual_digest != digest {
+        return Err(StorageError::InvalidHash {
+            expected_digest: digest,
+            actual_digest,
+            is_data_loss: true,
+        });
+    }
+    Ok(())
+}
+
+/// Delete a blob which failed verification on read from the underlying storage.
+async fn repair_corrupt_blob<BS: BlobStorage + Send + Sync>(
+    underlying: &BS,
+    instance: Instance,
+    digest: Digest,
+    state: DriverState,
+) {
+    log::error!(
+        "Deleting corrupt blob {digest:?} from instance {}",
+        instance.name
+    );
+    match underlying.delete_blobs(instance, vec![digest], state).await {
+        Ok(_) => metrics::counter!("toolchain_storage_read_verify_repair_total", 1),
+        Err(err) => log::error!("Failed to delete corrupt blob {digest:?}: {err}"),
     }
 }
 
@@ -270,11 +464,11 @@ mod tests {
         BlobStorage, BoxReadStream, DriverState, Instance, MemoryStorage, StorageError,
         StreamingWriteError, WriteAttemptOps,
     };
-    use crate::testutil::TestData;
+    use crate::testutil::{capture_metrics, counter_total, TestData};
 
     #[tokio::test]
     async fn write_good_data() {
-        let mut storage = WriteDigestVerifier::new(MemoryStorage::new());
+        let storage = WriteDigestVerifier::new(MemoryStorage::new());
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -307,7 +501,7 @@ mod tests {
 
     #[tokio::test]
     async fn write_bad_data() {
-        let mut storage = WriteDigestVerifier::new(MemoryStorage::new());
+        let storage = WriteDigestVerifier::new(MemoryStorage::new());
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -342,7 +536,7 @@ mod tests {
 
     #[tokio::test]
     async fn write_bad_size_small() {
-        let mut storage = WriteDigestVerifier::new(MemoryStorage::new());
+        let storage = WriteDigestVerifier::new(MemoryStorage::new());
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -373,7 +567,7 @@ mod tests {
 
     #[tokio::test]
     async fn bad_size_large() {
-        let mut storage = WriteDigestVerifier::new(MemoryStorage::new());
+        let storage = WriteDigestVerifier::new(MemoryStorage::new());
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -383,11 +577,17 @@ mod tests {
             .begin_write_blob(instance.clone(), content.digest, DriverState::default())
             .await
             .unwrap();
-        // Note: We are writing the first half three times, which should fail validation
+        // Note: We are writing the first half three times, which should fail validation as soon
+        // as the write exceeds the expected size.
         let half_of_content_bytes = content.bytes.slice(0..content.bytes.len() / 2);
         attempt.write(half_of_content_bytes.clone()).await.unwrap();
         attempt.write(half_of_content_bytes.clone()).await.unwrap();
-        attempt.write(half_of_content_bytes).await.unwrap();
+        let err = attempt
+            .write(half_of_content_bytes)
+            .await
+            .unwrap_err()
+            .unwrap_storage_error();
+        assert!(matches!(err, StorageError::InvalidSize { .. }));
         let err = attempt.commit().await.unwrap_err().unwrap_storage_error();
         assert!(matches!(err, StorageError::InvalidSize { .. }));
 
@@ -406,7 +606,7 @@ mod tests {
     async fn read_good_data() {
         let content = TestData::from_static(b"foobar");
 
-        let mut memory_storage = MemoryStorage::new();
+        let memory_storage = MemoryStorage::new();
         let instance = Instance::from("main");
         memory_storage.ensure_instance(&instance, DriverState::default());
 
@@ -417,7 +617,7 @@ mod tests {
         attempt.write(content.bytes.clone()).await.unwrap();
         attempt.commit().await.unwrap();
 
-        let storage = ReadDigestVerifier::new(memory_storage);
+        let storage = ReadDigestVerifier::new(memory_storage, false);
 
         let stream = storage
             .read_blob(
@@ -485,7 +685,7 @@ mod tests {
         let storage = ReadConstantBlobStorage {
             constant_content: bad_content.bytes.clone(),
         };
-        let storage = ReadDigestVerifier::new(storage);
+        let storage = ReadDigestVerifier::new(storage, false);
 
         let stream = storage
             .read_blob(
@@ -513,7 +713,7 @@ mod tests {
         let storage = ReadConstantBlobStorage {
             constant_content: bad_content.bytes.clone(),
         };
-        let storage = ReadDigestVerifier::new(storage);
+        let storage = ReadDigestVerifier::new(storage, false);
 
         let stream = storage
             .read_blob(
@@ -536,4 +736,58 @@ mod tests {
             }
         );
     }
+
+    #[tokio::test]
+    async fn read_bad_data_repairs_when_enabled() {
+        capture_metrics();
+        let good_content = TestData::from_static(b"foobar");
+        let bad_content = TestData::from_static(b"barfoo");
+
+        // Store corrupt content under the digest of the good content.
+        let memory_storage = MemoryStorage::new();
+        let instance = Instance::from("main");
+        memory_storage.ensure_instance(&instance, DriverState::default());
+        let mut attempt = memory_storage
+            .begin_write_blob(
+                instance.clone(),
+                good_content.digest,
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        attempt.write(bad_content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let storage = ReadDigestVerifier::new(memory_storage, true);
+        let repairs_before = counter_total("toolchain_storage_read_verify_repair_total", &[]);
+
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                good_content.digest,
+                3,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let err = consolidate_stream(stream).await.unwrap_err();
+        assert!(matches!(err, StorageError::InvalidHash { .. }));
+
+        let missing_blobs = storage
+            .find_missing_blobs(
+                instance.clone(),
+                vec![good_content.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing_blobs, vec![good_content.digest]);
+        assert_eq!(
+            counter_total("toolchain_storage_read_verify_repair_total", &[]) - repairs_before,
+            1
+        );
+    }
 }
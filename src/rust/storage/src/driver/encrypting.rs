@@ -0,0 +1,746 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::StreamExt;
+
+use crate::bytes::consolidate_stream;
+use crate::driver::{
+    BlobEncoding, BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance,
+    StorageError, StreamingWriteError, WriteAttemptOps,
+};
+use crate::Digest;
+
+/// Version of the format of encrypted blobs, stored as the first byte of each blob.
+const FORMAT_VERSION: u8 = 1;
+
+/// Size of the per-blob nonce stored after the format version.
+const NONCE_SIZE: usize = 12;
+
+/// Size of the header preceding the encrypted segments of a blob.
+const HEADER_SIZE: usize = 1 + NONCE_SIZE;
+
+/// Size of the authentication tag appended to each encrypted segment.
+const TAG_SIZE: usize = 16;
+
+/// Size of the plaintext of each encrypted segment (except for the final segment).
+const SEGMENT_SIZE: usize = 64 * 1024;
+
+/// Size of each stored segment (except for the final segment).
+const STORED_SEGMENT_SIZE: usize = SEGMENT_SIZE + TAG_SIZE;
+
+/// Encrypts blobs at rest in the underlying storage driver using AES-256-GCM.
+///
+/// Blobs remain addressed by the digest of their plaintext, so `find_missing_blobs` and the
+/// other digest-based operations pass through unchanged. Each stored blob consists of a header
+/// holding a random per-blob nonce, followed by the plaintext encrypted in fixed-size segments
+/// so that neither reads nor writes need to buffer whole blobs. Each segment is authenticated
+/// along with its position, whether it is the final segment, and the blob's digest, so that
+/// truncated, reordered or swapped content fails decryption with `StorageError::Internal`.
+/// Writes also set the `Encrypted` encoding in the `DriverState`, for underlying drivers which
+/// record it.
+///
+/// The nonce is stored in-band rather than in blob metadata, since most of the underlying
+/// drivers (e.g. the memory, local and direct Redis drivers) have nowhere to store per-blob
+/// metadata. Since segments have a fixed size, ranged reads fetch the header and then only the
+/// segments covering the requested range.
+///
+/// Since stored blobs neither hash to nor have the size of their digests, drivers which check
+/// stored content against digests (e.g. `ReadDigestVerifier`, or local storage with
+/// `verify_reads`) must wrap this driver rather than be wrapped by it.
+pub struct EncryptingStorage<BS> {
+    underlying: BS,
+    cipher: Arc<Aes256Gcm>,
+}
+
+impl<BS> EncryptingStorage<BS> {
+    /// Create an `EncryptingStorage` using `hex_key`, a hex-encoded 256-bit key.
+    pub fn new(underlying: BS, hex_key: &str) -> Result<Self, String> {
+        let key =
+            hex::decode(hex_key.trim()).map_err(|err| format!("Invalid encryption key: {err}"))?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| format!("Encryption key must be 32 bytes, got {}", key.len()))?;
+        Ok(EncryptingStorage {
+            underlying,
+            cipher: Arc::new(cipher),
+        })
+    }
+
+    /// Decrypt the whole of a `stored` blob.
+    fn decrypt_blob(&self, digest: Digest, stored: Bytes) -> Result<Bytes, StorageError> {
+        // The empty blob is stored as-is, since it has no content to protect.
+        if digest == Digest::EMPTY {
+            return Ok(stored);
+        }
+        let mut decryptor = BlobDecryptor::new(
+            self.cipher.clone(),
+            digest,
+            None,
+            0..segment_count(digest.size_bytes),
+        )?;
+        let plaintext = decryptor.push(&stored)?;
+        decryptor.finish()?;
+        Ok(plaintext)
+    }
+}
+
+/// The nonce of a segment: the blob's nonce with the segment's index mixed into its last bytes.
+fn segment_nonce(blob_nonce: &[u8; NONCE_SIZE], index: u32) -> [u8; NONCE_SIZE] {
+    let mut nonce = *blob_nonce;
+    for (byte, index_byte) in nonce[NONCE_SIZE - 4..].iter_mut().zip(index.to_be_bytes()) {
+        *byte ^= index_byte;
+    }
+    nonce
+}
+
+/// The associated data of a segment, which binds it to the blob's digest and marks whether it
+/// is the final segment of the blob.
+fn segment_aad(digest: Digest, is_final: bool) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(digest.hash.len() + 9);
+    aad.extend_from_slice(&digest.hash);
+    aad.extend_from_slice(&(digest.size_bytes as u64).to_be_bytes());
+    aad.push(is_final as u8);
+    aad
+}
+
+/// Encrypts and decrypts the segments of a single blob.
+struct SegmentCipher {
+    cipher: Arc<Aes256Gcm>,
+    nonce: [u8; NONCE_SIZE],
+    digest: Digest,
+    index: u32,
+}
+
+impl SegmentCipher {
+    fn encrypt(&mut self, plaintext: &[u8], is_final: bool) -> Result<Bytes, StorageError> {
+        let nonce = segment_nonce(&self.nonce, self.next_index()?);
+        let aad = segment_aad(self.digest, is_final);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| StorageError::Internal("Failed to encrypt blob segment".to_owned()))?;
+        Ok(Bytes::from(ciphertext))
+    }
+
+    fn decrypt(&mut self, ciphertext: &[u8], is_final: bool) -> Result<Bytes, StorageError> {
+        let nonce = segment_nonce(&self.nonce, self.next_index()?);
+        let aad = segment_aad(self.digest, is_final);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| {
+                StorageError::Internal(format!("Failed to decrypt blob {:?}", self.digest))
+            })?;
+        Ok(Bytes::from(plaintext))
+    }
+
+    fn next_index(&mut self) -> Result<u32, StorageError> {
+        let index = self.index;
+        self.index = index
+            .checked_add(1)
+            .ok_or_else(|| StorageError::Internal("Too many blob segments".to_owned()))?;
+        Ok(index)
+    }
+}
+
+struct EncryptingWriteAttempt {
+    underlying: Box<dyn WriteAttemptOps + Send + Sync>,
+    cipher: SegmentCipher,
+    /// The header of the blob, until it is written along with the first segment.
+    header: Option<Bytes>,
+    /// Plaintext which has not yet been encrypted.
+    buffer: BytesMut,
+}
+
+impl EncryptingWriteAttempt {
+    async fn write_segment(&mut self, is_final: bool) -> Result<(), StreamingWriteError> {
+        let size = self.buffer.len().min(SEGMENT_SIZE);
+        let plaintext = self.buffer.split_to(size);
+        let ciphertext = self.cipher.encrypt(&plaintext, is_final)?;
+        let output = match self.header.take() {
+            Some(header) => {
+                let mut output = BytesMut::with_capacity(header.len() + ciphertext.len());
+                output.extend_from_slice(&header);
+                output.extend_from_slice(&ciphertext);
+                output.freeze()
+            }
+            None => ciphertext,
+        };
+        self.underlying.write(output).await
+    }
+}
+
+#[async_trait]
+impl WriteAttemptOps for EncryptingWriteAttempt {
+    async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
+        self.buffer.extend_from_slice(&batch);
+        // A full segment is only known not to be the final segment once more data follows it.
+        while self.buffer.len() > SEGMENT_SIZE {
+            self.write_segment(false).await?;
+        }
+        Ok(())
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<(), StreamingWriteError> {
+        self.write_segment(true).await?;
+        self.underlying.commit().await
+    }
+}
+
+/// The number of segments in the stored form of a blob of `size` bytes. Every blob has a final
+/// segment, even if it is empty.
+fn segment_count(size: usize) -> usize {
+    size.div_ceil(SEGMENT_SIZE).max(1)
+}
+
+/// The size of segment `index` in the stored form of a blob of `size` bytes.
+fn stored_segment_size(size: usize, index: usize) -> usize {
+    SEGMENT_SIZE.min(size - index * SEGMENT_SIZE) + TAG_SIZE
+}
+
+/// The offset of segment `index` in the stored form of a blob of `size` bytes, which is the
+/// size of the stored blob when `index` is the segment count.
+fn stored_segment_offset(size: usize, index: usize) -> usize {
+    HEADER_SIZE + (index * SEGMENT_SIZE).min(size) + index * TAG_SIZE
+}
+
+/// Parse the header of a stored blob, returning its nonce.
+fn parse_header(header: &[u8], digest: Digest) -> Result<[u8; NONCE_SIZE], StorageError> {
+    if header.len() != HEADER_SIZE {
+        return Err(StorageError::Internal(format!(
+            "Encrypted blob {digest:?} is truncated"
+        )));
+    }
+    if header[0] != FORMAT_VERSION {
+        return Err(StorageError::Internal(format!(
+            "Unsupported encrypted blob format version {} for {digest:?}",
+            header[0]
+        )));
+    }
+    Ok(header[1..].try_into().expect("nonce size"))
+}
+
+/// Decrypts a range of segments of a stored blob as its content is read.
+struct BlobDecryptor {
+    cipher: Arc<Aes256Gcm>,
+    digest: Digest,
+    /// The cipher for the blob's segments, once its header has been read.
+    segment_cipher: Option<SegmentCipher>,
+    /// The index of the first segment to be read.
+    start_segment: u32,
+    /// The index after the last segment to be read.
+    end_segment: u32,
+    /// Content which has not yet been decrypted.
+    buffer: BytesMut,
+}
+
+impl BlobDecryptor {
+    fn new(
+        cipher: Arc<Aes256Gcm>,
+        digest: Digest,
+        nonce: Option<[u8; NONCE_SIZE]>,
+        segments: Range<usize>,
+    ) -> Result<Self, StorageError> {
+        let segment_index = |index: usize| {
+            u32::try_from(index)
+                .map_err(|_| StorageError::Internal("Too many blob segments".to_owned()))
+        };
+        let start_segment = segment_index(segments.start)?;
+        let mut decryptor = BlobDecryptor {
+            cipher,
+            digest,
+            segment_cipher: None,
+            start_segment,
+            end_segment: segment_index(segments.end)?,
+            buffer: BytesMut::new(),
+        };
+        if let Some(nonce) = nonce {
+            decryptor.init_segment_cipher(nonce);
+        }
+        Ok(decryptor)
+    }
+
+    fn init_segment_cipher(&mut self, nonce: [u8; NONCE_SIZE]) {
+        self.segment_cipher = Some(SegmentCipher {
+            cipher: self.cipher.clone(),
+            nonce,
+            digest: self.digest,
+            index: self.start_segment,
+        });
+    }
+
+    /// Add a chunk of the stored blob, returning the plaintext of any segments it completes.
+    fn push(&mut self, chunk: &[u8]) -> Result<Bytes, StorageError> {
+        self.buffer.extend_from_slice(chunk);
+        if self.segment_cipher.is_none() && self.buffer.len() >= HEADER_SIZE {
+            let header = self.buffer.split_to(HEADER_SIZE);
+            let nonce = parse_header(&header, self.digest)?;
+            self.init_segment_cipher(nonce);
+        }
+
+        let mut plaintext = BytesMut::new();
+        if let Some(segment_cipher) = self.segment_cipher.as_mut() {
+            let size = self.digest.size_bytes;
+            let final_segment = segment_count(size) - 1;
+            while segment_cipher.index < self.end_segment {
+                let index = segment_cipher.index as usize;
+                let stored_size = stored_segment_size(size, index);
+                if self.buffer.len() < stored_size {
+                    break;
+                }
+                let ciphertext = self.buffer.split_to(stored_size);
+                plaintext.extend_from_slice(
+                    &segment_cipher.decrypt(&ciphertext, index == final_segment)?,
+                );
+            }
+        }
+        Ok(plaintext.freeze())
+    }
+
+    /// Check that all of the segments were read once all of the stored content has been read.
+    fn finish(&self) -> Result<(), StorageError> {
+        match &self.segment_cipher {
+            Some(segment_cipher)
+                if segment_cipher.index == self.end_segment && self.buffer.is_empty() =>
+            {
+                Ok(())
+            }
+            _ => Err(StorageError::Internal(format!(
+                "Encrypted blob {:?} has an unexpected size",
+                self.digest
+            ))),
+        }
+    }
+}
+
+/// Decrypt the segments of the stored blob read from `stream`, skipping the first `skip` bytes
+/// of their plaintext and returning up to `remaining` bytes in chunks of at most `max_batch_size`
+/// bytes.
+fn decrypting_read_stream(
+    stream: BoxReadStream,
+    mut decryptor: BlobDecryptor,
+    max_batch_size: usize,
+    skip: usize,
+    remaining: usize,
+) -> BoxReadStream {
+    let stream = async_stream::stream! {
+      let mut stream = stream;
+      let mut skip = skip;
+      let mut remaining = remaining;
+      let mut is_final = false;
+
+      while !is_final && remaining > 0 {
+        let result = match stream.next().await {
+          Some(Ok(chunk)) => decryptor.push(&chunk),
+          Some(Err(err)) => Err(err),
+          None => {
+            is_final = true;
+            decryptor.finish().map(|()| Bytes::new())
+          }
+        };
+        let mut plaintext = match result {
+          Ok(plaintext) => plaintext,
+          Err(err) => {
+            yield Err(err);
+            return;
+          }
+        };
+
+        let skipped = skip.min(plaintext.len());
+        plaintext.advance(skipped);
+        skip -= skipped;
+        plaintext.truncate(remaining);
+        remaining -= plaintext.len();
+
+        while !plaintext.is_empty() {
+          yield Ok(plaintext.split_to(max_batch_size.clamp(1, plaintext.len())));
+        }
+      }
+    };
+    Box::pin(stream)
+}
+
+#[async_trait]
+impl<BS> BlobStorage for EncryptingStorage<BS>
+where
+    BS: BlobStorage + Send + Sync + 'static,
+{
+    async fn find_missing_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying
+            .find_missing_blobs(instance, digests, state)
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "encrypting"))]
+    async fn read_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        max_batch_size: usize,
+        read_offset: Option<usize>,
+        read_limit: Option<usize>,
+        state: DriverState,
+    ) -> Result<Option<BoxReadStream>, StorageError> {
+        // The empty blob is stored as-is, since it has no content to protect.
+        if digest == Digest::EMPTY {
+            return self
+                .underlying
+                .read_blob(
+                    instance,
+                    digest,
+                    max_batch_size,
+                    read_offset,
+                    read_limit,
+                    state,
+                )
+                .await;
+        }
+
+        // Only read the segments which cover the requested range of the plaintext.
+        let size = digest.size_bytes;
+        let start = read_offset.unwrap_or_default().min(size);
+        let end = read_limit.map_or(size, |limit| start.saturating_add(limit).min(size));
+        let start_segment = (start / SEGMENT_SIZE).min(segment_count(size) - 1);
+        let end_segment = segment_count(end).max(start_segment + 1);
+        let stored_end = stored_segment_offset(size, end_segment);
+        let stored_limit = stored_end < stored_segment_offset(size, segment_count(size));
+
+        let (stream_opt, nonce) = if start_segment == 0 {
+            // The header is read along with the first segment.
+            let stream_opt = self
+                .underlying
+                .read_blob(
+                    instance,
+                    digest,
+                    max_batch_size,
+                    None,
+                    stored_limit.then_some(stored_end),
+                    state,
+                )
+                .await?;
+            (stream_opt, None)
+        } else {
+            let header_stream = self
+                .underlying
+                .read_blob(
+                    instance.clone(),
+                    digest,
+                    HEADER_SIZE,
+                    None,
+                    Some(HEADER_SIZE),
+                    state.clone(),
+                )
+                .await?;
+            let Some(header_stream) = header_stream else {
+                return Ok(None);
+            };
+            let nonce = parse_header(&consolidate_stream(header_stream).await?, digest)?;
+            let stored_start = stored_segment_offset(size, start_segment);
+            let stream_opt = self
+                .underlying
+                .read_blob(
+                    instance,
+                    digest,
+                    max_batch_size,
+                    Some(stored_start),
+                    stored_limit.then_some(stored_end - stored_start),
+                    state,
+                )
+                .await?;
+            (stream_opt, Some(nonce))
+        };
+
+        let Some(stream) = stream_opt else {
+            return Ok(None);
+        };
+        let decryptor = BlobDecryptor::new(
+            self.cipher.clone(),
+            digest,
+            nonce,
+            start_segment..end_segment,
+        )?;
+        Ok(Some(decrypting_read_stream(
+            stream,
+            decryptor,
+            max_batch_size,
+            start - start_segment * SEGMENT_SIZE,
+            end - start,
+        )))
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "encrypting"))]
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        let blobs = self.underlying.read_blobs(instance, digests, state).await?;
+        Ok(blobs
+            .into_iter()
+            .map(|(digest, result)| {
+                let result = match result {
+                    Ok(Some(stored)) => self.decrypt_blob(digest, stored).map(Some),
+                    result => result,
+                };
+                (digest, result)
+            })
+            .collect())
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        // Report the size of the plaintext rather than of the stored blob.
+        let stat = self.underlying.stat(instance, digest, state).await?;
+        Ok(stat.map(|stat| BlobStat {
+            size_bytes: digest.size_bytes,
+            ..stat
+        }))
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "encrypting"))]
+    async fn begin_write_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        if digest == Digest::EMPTY {
+            return self
+                .underlying
+                .begin_write_blob(instance, digest, state)
+                .await;
+        }
+
+        let mut state = state;
+        state.encoding = BlobEncoding::Encrypted;
+        let underlying = self
+            .underlying
+            .begin_write_blob(instance, digest, state)
+            .await?;
+
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let mut header = BytesMut::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&[FORMAT_VERSION]);
+        header.extend_from_slice(&nonce);
+
+        Ok(Box::new(EncryptingWriteAttempt {
+            underlying,
+            cipher: SegmentCipher {
+                cipher: self.cipher.clone(),
+                nonce,
+                digest,
+                index: 0,
+            },
+            header: Some(header.freeze()),
+            buffer: BytesMut::with_capacity(SEGMENT_SIZE.min(digest.size_bytes) + 1),
+        }))
+    }
+
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying
+            .list_recent_blobs(instance, limit, state)
+            .await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.underlying
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.underlying.ensure_instance(instance, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use super::{EncryptingStorage, SEGMENT_SIZE};
+    use crate::bytes::consolidate_stream;
+    use crate::driver::{BlobStorage, DriverState, Instance, MemoryStorage, StorageError};
+    use crate::Digest;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn content() -> (Bytes, Digest) {
+        // Spans several segments, with a partial final segment.
+        let content = (0..2 * SEGMENT_SIZE + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let content = Bytes::from(content);
+        let digest = Digest::of_bytes(&content).unwrap();
+        (content, digest)
+    }
+
+    async fn write(storage: &impl BlobStorage, instance: &Instance, content: Bytes) {
+        let digest = Digest::of_bytes(&content).unwrap();
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), digest, DriverState::default())
+            .await
+            .unwrap();
+        for chunk in content.chunks(10_000) {
+            attempt.write(content.slice_ref(chunk)).await.unwrap();
+        }
+        attempt.commit().await.unwrap();
+    }
+
+    async fn read(
+        storage: &impl BlobStorage,
+        instance: &Instance,
+        digest: Digest,
+        read_offset: Option<usize>,
+        read_limit: Option<usize>,
+    ) -> Result<Bytes, StorageError> {
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                digest,
+                4096,
+                read_offset,
+                read_limit,
+                DriverState::default(),
+            )
+            .await?
+            .unwrap();
+        consolidate_stream(stream).await
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let instance = Instance::from("main");
+        let memory_storage = MemoryStorage::new();
+        memory_storage.ensure_instance(&instance, DriverState::default());
+        let storage = EncryptingStorage::new(memory_storage.clone(), KEY).unwrap();
+        let (content, digest) = content();
+
+        write(&storage, &instance, content.clone()).await;
+
+        let missing_blobs = storage
+            .find_missing_blobs(instance.clone(), vec![digest], DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(missing_blobs, vec![]);
+
+        let stored = read(&memory_storage, &instance, digest, None, None)
+            .await
+            .unwrap();
+        assert_ne!(stored, content);
+        assert!(!stored
+            .windows(64)
+            .any(|window| window == &content[SEGMENT_SIZE..SEGMENT_SIZE + 64]));
+
+        assert_eq!(
+            read(&storage, &instance, digest, None, None).await.unwrap(),
+            content
+        );
+        let (offset, limit) = (SEGMENT_SIZE - 10, SEGMENT_SIZE + 20);
+        assert_eq!(
+            read(&storage, &instance, digest, Some(offset), Some(limit))
+                .await
+                .unwrap(),
+            content.slice(offset..offset + limit)
+        );
+        // Reads starting after the first segment only read the segments they cover.
+        let (offset, limit) = (SEGMENT_SIZE + 5, 100);
+        assert_eq!(
+            read(&storage, &instance, digest, Some(offset), Some(limit))
+                .await
+                .unwrap(),
+            content.slice(offset..offset + limit)
+        );
+        let offset = 2 * SEGMENT_SIZE + 10;
+        assert_eq!(
+            read(&storage, &instance, digest, Some(offset), None)
+                .await
+                .unwrap(),
+            content.slice(offset..)
+        );
+        assert_eq!(
+            read(&storage, &instance, digest, Some(content.len()), None)
+                .await
+                .unwrap(),
+            Bytes::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn tampered_content_fails_decryption() {
+        let instance = Instance::from("main");
+        let memory_storage = MemoryStorage::new();
+        memory_storage.ensure_instance(&instance, DriverState::default());
+        let storage = EncryptingStorage::new(memory_storage.clone(), KEY).unwrap();
+        let (content, digest) = content();
+        write(&storage, &instance, content).await;
+
+        // Flip a bit of the ciphertext and store it under the same digest.
+        let stored = read(&memory_storage, &instance, digest, None, None)
+            .await
+            .unwrap();
+        let mut tampered = BytesMut::from(&stored[..]);
+        tampered[SEGMENT_SIZE + 100] ^= 1;
+        let tampered_storage = MemoryStorage::new();
+        tampered_storage.ensure_instance(&instance, DriverState::default());
+        let mut attempt = tampered_storage
+            .begin_write_blob(instance.clone(), digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(tampered.freeze()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let storage = EncryptingStorage::new(tampered_storage, KEY).unwrap();
+        let err = read(&storage, &instance, digest, None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::Internal(_)), "{err:?}");
+    }
+}
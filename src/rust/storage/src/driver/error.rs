@@ -24,6 +24,12 @@ pub enum StorageError {
     Internal(String),
     Unavailable(String),
     OutOfRange(String, usize),
+    Unimplemented(String),
+    /// Content was positively determined to be absent (e.g. it was evicted while being read).
+    /// Drivers which merely did not find content return `Ok(None)` instead.
+    NotFound(String),
+    /// A request exceeded a configured limit (e.g. the maximum size of a streamed blob).
+    ResourceExhausted(String),
 }
 
 impl std::error::Error for StorageError {}
@@ -75,6 +81,9 @@ impl fmt::Display for StorageError {
             StorageError::OutOfRange(param_name, value) => {
                 write!(f, "Out-of-range value {param_name} for parameter {value}")
             }
+            StorageError::Unimplemented(msg) => write!(f, "Unimplemented: {msg}"),
+            StorageError::NotFound(msg) => write!(f, "Not found: {msg}"),
+            StorageError::ResourceExhausted(msg) => write!(f, "Resource exhausted: {msg}"),
         }
     }
 }
@@ -127,6 +136,9 @@ impl From<StorageError> for Status {
                 let msg = format!("{err}");
                 Status::out_of_range(msg)
             }
+            StorageError::Unimplemented(msg) => Status::unimplemented(msg),
+            StorageError::NotFound(msg) => Status::not_found(msg),
+            StorageError::ResourceExhausted(msg) => Status::resource_exhausted(msg),
         }
     }
 }
@@ -1,26 +1,76 @@
 // Copyright 2021 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::collections::HashSet;
 use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use lasso::{Spur, ThreadedRodeo};
 use lru::LruCache;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, StorageError,
+    StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
+type ExistenceCache = Arc<RwLock<LruCache<(Spur, Digest), ()>>>;
+
+/// Digests known to be missing, along with the time at which each entry expires.
+type MissingCache = Arc<RwLock<LruCache<(Spur, Digest), Instant>>>;
+
 /// A `BlobStorage` that speeds up `find_missing_blobs` RPCs by caching the existence of blobs
 /// found in an underlying storage backend.
+///
+/// If `warmup_entries` is non-zero, then `ensure_instance` prewarms the cache in the background
+/// with the instance's most recently written digests so that a restart does not send every
+/// `find_missing_blobs` call to the underlying storage. This is a no-op for underlying storage
+/// which cannot list its blobs.
+///
+/// Only present blobs are cached by default. Caching missing blobs is opt-in via `missing_ttl`
+/// since a cached absence hides concurrent writes through other drivers until it expires. Writes
+/// which pass through this driver invalidate the entry for their digest immediately.
 pub struct ExistenceCacheStorage<S> {
     instance_interns: ThreadedRodeo,
-    cache: Arc<RwLock<LruCache<(Spur, Digest), ()>>>,
-    underlying: S,
+    cache: ExistenceCache,
+    missing_cache: MissingCache,
+    missing_ttl: Option<Duration>,
+    /// Incremented as writes are committed, so that `find_missing_blobs` does not cache an
+    /// absence which was made stale by a write committed while it was in progress.
+    writes_committed: Arc<AtomicU64>,
+    underlying: Arc<S>,
+    warmup_entries: usize,
+    /// Instances for which warmup has been started, so that it only runs once per instance.
+    warmed_up_instances: Mutex<HashSet<Spur>>,
+    lookups: AtomicU64,
+    hits: AtomicU64,
+}
+
+/// Write attempt which invalidates the cached absence of its digest once committed.
+struct WriteAttempt {
+    inner: Box<dyn WriteAttemptOps + Send + Sync>,
+    cache_key: (Spur, Digest),
+    missing_cache: MissingCache,
+    writes_committed: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl WriteAttemptOps for WriteAttempt {
+    async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
+        self.inner.write(batch).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError> {
+        let result = self.inner.commit().await;
+        self.writes_committed.fetch_add(1, Ordering::SeqCst);
+        self.missing_cache.write().pop(&self.cache_key);
+        result
+    }
 }
 
 impl<S> ExistenceCacheStorage<S> {
@@ -28,6 +78,52 @@ impl<S> ExistenceCacheStorage<S> {
     fn get_key_for_instance(&self, instance: &Instance) -> Spur {
         self.instance_interns.get_or_intern(&instance.name)
     }
+
+    /// Record the outcome of cache lookups and update the hit ratio gauge.
+    fn record_lookups(&self, lookups: usize, hits: usize) {
+        let lookups = self.lookups.fetch_add(lookups as u64, Ordering::Relaxed) + lookups as u64;
+        let hits = self.hits.fetch_add(hits as u64, Ordering::Relaxed) + hits as u64;
+        if lookups > 0 {
+            metrics::gauge!(
+                "toolchain_storage_existence_cache_hit_ratio",
+                hits as f64 / lookups as f64,
+            );
+        }
+    }
+}
+
+/// Load up to `warmup_entries` recently written digests for an instance into the cache.
+async fn warm_up<S>(
+    underlying: Arc<S>,
+    cache: ExistenceCache,
+    instance: Instance,
+    instance_key: Spur,
+    warmup_entries: usize,
+    state: DriverState,
+) where
+    S: BlobStorage + Send + Sync + 'static,
+{
+    let digests = match underlying
+        .list_recent_blobs(instance.clone(), warmup_entries, state)
+        .await
+    {
+        Ok(digests) => digests,
+        Err(StorageError::Unimplemented(_)) => return,
+        Err(err) => {
+            log::warn!(
+                "Failed to warm up existence cache for instance `{}`: {err}",
+                instance.name
+            );
+            return;
+        }
+    };
+
+    let mut cache = cache.write();
+    // Insert the oldest digests first so that the most recent ones are least likely to be
+    // evicted.
+    for digest in digests.into_iter().rev() {
+        cache.put((instance_key, digest), ());
+    }
 }
 
 #[async_trait]
@@ -42,27 +138,33 @@ where
         state: DriverState,
     ) -> Result<Vec<Digest>, StorageError> {
         let instance_key = self.get_key_for_instance(&instance);
+        let num_lookups = digests.len();
+        let now = Instant::now();
 
-        let unknown_digests = {
+        let mut cached_missing_digests = Vec::new();
+        let mut unknown_digests = Vec::new();
+        {
             let cache = self.cache.read();
+            let missing_cache = self.missing_cache.read();
 
-            digests
-                .into_iter()
-                .map(|digest| (instance_key, digest))
-                .filter_map(|cache_key| {
-                    if cache.contains(&cache_key) {
-                        None
-                    } else {
-                        Some(cache_key.1)
-                    }
-                })
-                .collect::<Vec<_>>()
-        };
+            for digest in digests {
+                let cache_key = (instance_key, digest);
+                if cache.contains(&cache_key) {
+                    continue;
+                }
+                match missing_cache.peek(&cache_key) {
+                    Some(expires_at) if *expires_at > now => cached_missing_digests.push(digest),
+                    _ => unknown_digests.push(digest),
+                }
+            }
+        }
+        self.record_lookups(num_lookups, num_lookups - unknown_digests.len());
 
         if unknown_digests.is_empty() {
-            return Ok(Vec::new());
+            return Ok(cached_missing_digests);
         }
 
+        let writes_committed = self.writes_committed.load(Ordering::SeqCst);
         let missing_digests = self
             .underlying
             .find_missing_blobs(instance, unknown_digests.clone(), state)
@@ -80,7 +182,19 @@ where
             }
         }
 
-        Ok(missing_digests)
+        if let Some(missing_ttl) = self.missing_ttl {
+            if !missing_digests.is_empty()
+                && self.writes_committed.load(Ordering::SeqCst) == writes_committed
+            {
+                let mut missing_cache = self.missing_cache.write();
+                for missing_digest in &missing_digests {
+                    missing_cache.put((instance_key, *missing_digest), now + missing_ttl);
+                }
+            }
+        }
+
+        cached_missing_digests.extend(missing_digests);
+        Ok(cached_missing_digests)
     }
 
     async fn read_blob(
@@ -104,15 +218,131 @@ where
             .await
     }
 
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        self.underlying.read_blobs(instance, digests, state).await
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.underlying.stat(instance, digest, state).await
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
         digest: Digest,
         state: DriverState,
     ) -> Result<Box<dyn WriteAttemptOps + Send + Sync>, StreamingWriteError> {
-        self.underlying
+        if self.missing_ttl.is_none() {
+            return self
+                .underlying
+                .begin_write_blob(instance, digest, state)
+                .await;
+        }
+
+        let cache_key = (self.get_key_for_instance(&instance), digest);
+        match self
+            .underlying
             .begin_write_blob(instance, digest, state)
             .await
+        {
+            Ok(attempt) => Ok(Box::new(WriteAttempt {
+                inner: attempt,
+                cache_key,
+                missing_cache: self.missing_cache.clone(),
+                writes_committed: self.writes_committed.clone(),
+            })),
+            Err(StreamingWriteError::AlreadyExists) => {
+                self.missing_cache.write().pop(&cache_key);
+                Err(StreamingWriteError::AlreadyExists)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let instance_key = self.get_key_for_instance(&instance);
+        let result = self
+            .underlying
+            .delete_blobs(instance, digests.clone(), state)
+            .await;
+
+        // Evict the digests even if the delete failed since some of them may have been deleted.
+        let mut cache = self.cache.write();
+        for digest in digests {
+            cache.pop(&(instance_key, digest));
+        }
+
+        result
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.underlying
+            .list_recent_blobs(instance, limit, state)
+            .await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.underlying
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    /// Sets up `instance` on the underlying storage, and then starts warming up the cache for it
+    /// in the background, the first time this is called for the instance. Warmup is skipped if
+    /// this is not called from within a Tokio runtime.
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.underlying.ensure_instance(instance, state.clone());
+        if self.warmup_entries == 0 {
+            return;
+        }
+        let instance_key = self.get_key_for_instance(instance);
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                log::warn!(
+                    "Not warming up existence cache for instance `{}` outside of a Tokio runtime",
+                    instance.name
+                );
+                return;
+            }
+        };
+        if !self.warmed_up_instances.lock().insert(instance_key) {
+            return;
+        }
+        handle.spawn(warm_up(
+            self.underlying.clone(),
+            self.cache.clone(),
+            instance.clone(),
+            instance_key,
+            self.warmup_entries,
+            state,
+        ));
     }
 }
 
@@ -120,11 +350,25 @@ impl<S> ExistenceCacheStorage<S>
 where
     S: BlobStorage + Send + Sync + 'static,
 {
-    pub fn new(max_lru_entries: NonZeroUsize, underlying: S) -> Self {
+    /// Create an `ExistenceCacheStorage`. Missing digests are only cached (for `missing_ttl`)
+    /// if `missing_ttl` is set.
+    pub fn new(
+        max_lru_entries: NonZeroUsize,
+        warmup_entries: usize,
+        missing_ttl: Option<Duration>,
+        underlying: S,
+    ) -> Self {
         ExistenceCacheStorage {
             instance_interns: ThreadedRodeo::new(),
             cache: Arc::new(RwLock::new(LruCache::new(max_lru_entries))),
-            underlying,
+            missing_cache: Arc::new(RwLock::new(LruCache::new(max_lru_entries))),
+            missing_ttl,
+            writes_committed: Arc::new(AtomicU64::new(0)),
+            underlying: Arc::new(underlying),
+            warmup_entries,
+            warmed_up_instances: Mutex::new(HashSet::new()),
+            lookups: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
         }
     }
 }
@@ -134,15 +378,16 @@ mod tests {
     use std::num::NonZeroUsize;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
+    use std::time::Duration;
 
     use async_trait::async_trait;
 
     use super::ExistenceCacheStorage;
     use crate::driver::{
-        BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-        WriteAttemptOps,
+        BlobStorage, BoxReadStream, DriverState, Instance, MemoryStorage, StorageError,
+        StreamingWriteError, WriteAttemptOps,
     };
-    use crate::testutil::TestData;
+    use crate::testutil::{CountMethodCallsStorage, TestData};
     use crate::Digest;
 
     struct CountFindMissingBlobsStorage {
@@ -189,6 +434,8 @@ mod tests {
 
         let storage = ExistenceCacheStorage::new(
             NonZeroUsize::new(256).unwrap(),
+            0,
+            None,
             CountFindMissingBlobsStorage {
                 count: calls_count.clone(),
             },
@@ -249,4 +496,123 @@ mod tests {
         assert!(missing_digests.is_empty());
         assert_eq!(2, calls_count.load(Ordering::SeqCst));
     }
+
+    #[tokio::test]
+    async fn warms_up_cache_from_underlying_storage() {
+        let instance = Instance::from("main");
+        let content1 = TestData::from_static(b"foobar");
+        let content2 = TestData::from_static(b"xyzzy");
+
+        let memory_storage = MemoryStorage::new();
+        memory_storage.ensure_instance(&instance, DriverState::default());
+        for content in [&content1, &content2] {
+            let mut attempt = memory_storage
+                .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+
+        let underlying = CountMethodCallsStorage::new(memory_storage);
+        let calls_count = underlying.find_missing_blobs_count.clone();
+        let list_recent_count = underlying.list_recent_blobs_count.clone();
+        let storage =
+            ExistenceCacheStorage::new(NonZeroUsize::new(256).unwrap(), 10, None, underlying);
+        storage.ensure_instance(&instance, DriverState::default());
+        storage.ensure_instance(&instance, DriverState::default());
+
+        // Warmup happens in the background, so wait for it to populate the cache.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while storage.cache.read().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("cache was warmed up");
+        assert_eq!(1, list_recent_count.load(Ordering::SeqCst));
+
+        let missing_digests = storage
+            .find_missing_blobs(
+                instance,
+                vec![content1.digest, content2.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert!(missing_digests.is_empty());
+        assert_eq!(0, calls_count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn skips_warmup_outside_of_runtime() {
+        let instance = Instance::from("main");
+        let underlying = CountMethodCallsStorage::new(MemoryStorage::new());
+        let list_recent_count = underlying.list_recent_blobs_count.clone();
+        let storage =
+            ExistenceCacheStorage::new(NonZeroUsize::new(256).unwrap(), 10, None, underlying);
+        storage.ensure_instance(&instance, DriverState::default());
+        assert_eq!(0, list_recent_count.load(Ordering::SeqCst));
+
+        // The instance is warmed up once it is ensured from within a runtime.
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                storage.ensure_instance(&instance, DriverState::default());
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while list_recent_count.load(Ordering::SeqCst) == 0 {
+                        tokio::task::yield_now().await;
+                    }
+                })
+                .await
+                .expect("cache was warmed up");
+            });
+    }
+
+    #[tokio::test]
+    async fn write_invalidates_cached_missing_digest() {
+        let instance = Instance::from("main");
+        let content = TestData::from_static(b"foobar");
+
+        let memory_storage = MemoryStorage::new();
+        memory_storage.ensure_instance(&instance, DriverState::default());
+        let underlying = CountMethodCallsStorage::new(memory_storage);
+        let calls_count = underlying.find_missing_blobs_count.clone();
+        let storage = ExistenceCacheStorage::new(
+            NonZeroUsize::new(256).unwrap(),
+            0,
+            Some(Duration::from_secs(60)),
+            underlying,
+        );
+
+        // The missing digest is cached, so the second call does not reach the underlying storage.
+        for _ in 0..2 {
+            let missing_digests = storage
+                .find_missing_blobs(
+                    instance.clone(),
+                    vec![content.digest],
+                    DriverState::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(missing_digests, vec![content.digest]);
+        }
+        assert_eq!(1, calls_count.load(Ordering::SeqCst));
+
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        // The write invalidated the cached absence, so the blob is now reported as present.
+        let missing_digests = storage
+            .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+            .await
+            .unwrap();
+        assert!(missing_digests.is_empty());
+        assert_eq!(2, calls_count.load(Ordering::SeqCst));
+    }
 }
@@ -6,7 +6,8 @@ use bytes::{Bytes, BytesMut};
 use futures::{StreamExt, TryFutureExt};
 
 use crate::driver::{
-    BlobStorage, DriverState, Instance, SmallBlobStorage, StorageError, StreamingWriteError,
+    BlobPage, BlobStorage, DriverState, Instance, SmallBlobStorage, StorageError,
+    StreamingWriteError,
 };
 use crate::Digest;
 
@@ -124,6 +125,28 @@ where
             .map(|((), ())| ())
             .or_else(StreamingWriteError::ok_if_already_exists)
     }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        // Every blob is written to the slow storage, while the fast storage only caches some.
+        self.slow_storage
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn max_blob_size(&self) -> Option<usize> {
+        self.fast_storage.max_blob_size()
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.fast_storage.ensure_instance(instance, state.clone());
+        self.slow_storage.ensure_instance(instance, state);
+    }
 }
 
 impl<Fast, Slow> FastSlowReplicationStorage<Fast, Slow>
@@ -154,6 +177,7 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::Ordering;
     use std::sync::Arc;
     use std::time::Duration;
 
@@ -161,7 +185,8 @@ mod tests {
 
     use super::FastSlowReplicationStorage;
     use crate::driver::{
-        BlobStorage, DriverState, Instance, MemoryStorage, SmallBlobStorage, StreamingWriteError,
+        BlobStorage, DriverState, Instance, MemoryStorage, SmallBlobStorage,
+        SmallBlobStorageAdapter, StreamingWriteError,
     };
     use crate::testutil::{
         AlwaysExistsStorage, CountMethodCallsStorage, SmallMemoryStorage, TestData,
@@ -185,6 +210,20 @@ mod tests {
         attempt.commit().await
     }
 
+    #[test]
+    fn ensure_instance_reaches_both_storages() {
+        let fast_storage = CountMethodCallsStorage::new(SmallMemoryStorage::new());
+        let slow_storage = CountMethodCallsStorage::new(MemoryStorage::new());
+        let storage = SmallBlobStorageAdapter::new(FastSlowReplicationStorage::new(
+            fast_storage.clone(),
+            slow_storage.clone(),
+        ));
+
+        storage.ensure_instance(&Instance::from("main"), DriverState::default());
+        assert_eq!(fast_storage.ensure_instance_count.load(Ordering::SeqCst), 1);
+        assert_eq!(slow_storage.ensure_instance_count.load(Ordering::SeqCst), 1);
+    }
+
     #[tokio::test]
     async fn fast_slow_basic_operations() {
         let instance = Instance::from("main");
@@ -206,7 +245,7 @@ mod tests {
             .await
             .unwrap();
 
-        let mut slow_storage = CountMethodCallsStorage::new(MemoryStorage::new());
+        let slow_storage = CountMethodCallsStorage::new(MemoryStorage::new());
         slow_storage.ensure_instance(&instance, DriverState::default());
         write_blob(
             &slow_storage,
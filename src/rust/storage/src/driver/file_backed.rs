@@ -5,18 +5,38 @@ use std::io::{ErrorKind, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use digest::Digest;
+use serde::Deserialize;
+use sha2::{Digest as Sha256Digest, Sha256};
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use super::Instance;
 use crate::driver::{
-    BoxReadStream, DriverState, StorageError, StreamingWriteError, WriteAttemptOps,
+    BlobEncoding, BlobPage, BlobStat, BoxReadStream, DriverState, StorageError,
+    StreamingWriteError, WriteAttemptOps,
 };
 
+/// How much effort `FileBackedStorage` makes to ensure that committed writes survive a crash.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Rely on the operating system to eventually flush writes. Suitable for caches.
+    #[default]
+    None,
+
+    /// Fsync the content of each blob before it is made visible.
+    Fsync,
+
+    /// Fsync the content of each blob before it is made visible, and fsync its directory after
+    /// the rename which makes it visible.
+    FsyncDir,
+}
+
 /// Represents an attempt to write content to the BlobStorage. Content is written to a temporary
 /// file which is then renamed to the final name upon `commit`.
 #[derive(Debug)]
@@ -25,6 +45,7 @@ pub struct WriteAttempt {
     digest: Digest,
     tmp_path: PathBuf,
     final_path: PathBuf,
+    durability: Durability,
 }
 
 #[async_trait]
@@ -39,6 +60,7 @@ impl WriteAttemptOps for WriteAttempt {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
     async fn commit(mut self: Box<Self>) -> Result<(), StreamingWriteError> {
         // Close the temp file.
         self.file
@@ -46,6 +68,13 @@ impl WriteAttemptOps for WriteAttempt {
             .await
             .map_err(|err| format!("error while writing digest {:?}: {}", self.digest, err))?;
 
+        if self.durability != Durability::None {
+            self.file
+                .sync_all()
+                .await
+                .map_err(|err| format!("error while syncing digest {:?}: {}", self.digest, err))?;
+        }
+
         // Rename the temporary file to the final path. This will make the digest visible to
         // readers. It is okay to overwrite the final path. For CAS, all such content should have
         // the same content. For AC, it means that a different ActionResult will take
@@ -70,10 +99,26 @@ impl WriteAttemptOps for WriteAttempt {
             },
         }
 
+        if self.durability == Durability::FsyncDir {
+            if let Some(parent) = self.final_path.parent() {
+                sync_directory(parent).await.map_err(|err| {
+                    format!(
+                        "error while syncing directory for digest {:?}: {}",
+                        self.digest, err
+                    )
+                })?;
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Fsync a directory so that renames of files into it are durable.
+async fn sync_directory(path: &Path) -> std::io::Result<()> {
+    File::open(path).await?.sync_all().await
+}
+
 impl Drop for WriteAttempt {
     fn drop(&mut self) {
         let path = self.tmp_path.clone();
@@ -100,9 +145,7 @@ impl Inner {
     /// with large numbers of files in a directory).
     fn path_for_digest(&self, digest: Digest, instance: &Instance) -> PathBuf {
         let hex_hash = digest.hex();
-        let mut blobs_path = self.instances_path.clone();
-        blobs_path.push(&instance.name);
-        blobs_path.push("blobs");
+        let mut blobs_path = self.blobs_path(instance);
         blobs_path.push(&hex_hash[0..2]);
         blobs_path.push(&hex_hash[2..4]);
         blobs_path.push(&hex_hash[4..6]);
@@ -110,6 +153,11 @@ impl Inner {
         blobs_path
     }
 
+    /// The directory under which all blobs for `instance` are stored.
+    fn blobs_path(&self, instance: &Instance) -> PathBuf {
+        self.instances_path.join(&instance.name).join("blobs")
+    }
+
     /// Checks whether a blob is missing. If so, returns the Digest (which helps to make
     /// `find_missing_blobs` easier to implement). If not, returns None.
     async fn blob_exists(&self, digest: Digest, instance: &Instance) -> bool {
@@ -129,15 +177,128 @@ impl Inner {
     }
 }
 
+/// Walk the blob directory structure under `blobs_path`, returning the modification time and
+/// digest of each blob file.
+fn list_blob_files(blobs_path: &Path) -> std::io::Result<Vec<(SystemTime, Digest)>> {
+    let mut blobs = Vec::new();
+    let mut dirs = vec![blobs_path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+                continue;
+            }
+            if let Some(digest) = entry.file_name().to_str().and_then(digest_for_file_name) {
+                blobs.push((entry.metadata()?.modified()?, digest));
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// Walk the blob directory structure under `blobs_path` in digest order, returning up to `limit`
+/// digests which sort after `after`. Directories which only hold digests before `after` are
+/// skipped rather than read.
+fn list_blob_files_after(
+    blobs_path: &Path,
+    after: Option<Digest>,
+    limit: usize,
+) -> std::io::Result<Vec<Digest>> {
+    fn visit(
+        dir: &Path,
+        depth: usize,
+        after: Option<Digest>,
+        after_hex: Option<&str>,
+        limit: usize,
+        digests: &mut Vec<Digest>,
+    ) -> std::io::Result<()> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        // Blob files are stored three directory levels deep, under two hex digits per level.
+        if depth < 3 {
+            let mut subdirs = Vec::new();
+            for entry in entries {
+                let entry = entry?;
+                if let (true, Ok(name)) =
+                    (entry.file_type()?.is_dir(), entry.file_name().into_string())
+                {
+                    subdirs.push(name);
+                }
+            }
+            subdirs.sort_unstable();
+            for name in subdirs {
+                if after_hex.map_or(false, |after_hex| {
+                    name.as_str() < &after_hex[depth * 2..depth * 2 + 2]
+                }) {
+                    continue;
+                }
+                visit(&dir.join(name), depth + 1, after, after_hex, limit, digests)?;
+                if digests.len() >= limit {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        let mut blob_digests = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if let Some(digest) = entry.file_name().to_str().and_then(digest_for_file_name) {
+                if after.map_or(true, |after| digest > after) {
+                    blob_digests.push(digest);
+                }
+            }
+        }
+        blob_digests.sort_unstable();
+        digests.extend(blob_digests.into_iter().take(limit - digests.len()));
+        Ok(())
+    }
+
+    let after_hex = after.map(|digest| digest.hex());
+    let mut digests = Vec::new();
+    visit(
+        blobs_path,
+        0,
+        after,
+        after_hex.as_deref(),
+        limit,
+        &mut digests,
+    )?;
+    Ok(digests)
+}
+
+/// Parse the digest from a blob file name of the form `{hash}-{size}.bin`.
+fn digest_for_file_name(file_name: &str) -> Option<Digest> {
+    let (hex_hash, size_bytes) = file_name.strip_suffix(".bin")?.split_once('-')?;
+    Digest::new(hex_hash, size_bytes.parse().ok()?).ok()
+}
+
 /// A `BlobStorage` implementation that stores blob content in files in the filesystem. The files
 /// are stored in a three-level directory structure under the base path. Assuming a digest is of
 /// the form XXYYZZ....., the path is: {base_path}/blobs/XX/YY/ZZ/{XXYYZZdigest}-{size}.bin
+///
+/// If read verification is enabled, reads of an entire blob hash the content as it is streamed.
+/// A blob whose content does not match its digest (e.g. due to bit-rot) fails the read with
+/// `StorageError::Internal` and is deleted, so that a subsequent write can repair it.
 pub struct FileBackedStorage {
     inner: Arc<Inner>,
+    durability: Durability,
+    verify_reads: bool,
 }
 
 #[async_trait]
 impl super::BlobStorage for FileBackedStorage {
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
     async fn find_missing_blobs(
         &self,
         instance: Instance,
@@ -161,6 +322,7 @@ impl super::BlobStorage for FileBackedStorage {
         Ok(missing_digests)
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
     async fn read_blob(
         &self,
         instance: Instance,
@@ -172,7 +334,7 @@ impl super::BlobStorage for FileBackedStorage {
     ) -> Result<Option<BoxReadStream>, StorageError> {
         let blob_path = self.inner.path_for_digest(digest, &instance);
 
-        let mut blob_file = match tokio::fs::File::open(blob_path).await {
+        let mut blob_file = match tokio::fs::File::open(&blob_path).await {
             Ok(f) => f,
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
             Err(err) => {
@@ -200,6 +362,12 @@ impl super::BlobStorage for FileBackedStorage {
             None => max_read_limit,
         };
 
+        // Only reads of the entire blob can be verified against its digest.
+        let mut hasher = (self.verify_reads
+            && read_offset.unwrap_or(0) == 0
+            && amount_to_read == blob_metadata.len() as usize)
+            .then(Sha256::default);
+
         let stream = async_stream::stream! {
           while amount_to_read > 0 {
             let chunk_amount_read = max_batch_size.min(amount_to_read);
@@ -207,14 +375,54 @@ impl super::BlobStorage for FileBackedStorage {
             blob_file.read_exact(&mut buffer).await.map_err(|e| {
               StorageError::Unavailable(e.to_string())
             })?;
-            yield Ok(buffer.freeze());
             amount_to_read -= chunk_amount_read;
+            if let Some(hasher) = hasher.as_mut() {
+              hasher.update(&buffer);
+              // Verify before yielding the final chunk, so that a corrupt blob is never read in
+              // its entirety.
+              if amount_to_read == 0 {
+                verify_blob(hasher.clone(), digest, &blob_path).await?;
+              }
+            }
+            yield Ok(buffer.freeze());
           }
         };
 
         Ok(Some(Box::pin(stream)))
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        _state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        if digest == Digest::EMPTY {
+            return Ok(Some(BlobStat {
+                size_bytes: 0,
+                last_accessed: None,
+                created: None,
+                encoding: BlobEncoding::Raw,
+            }));
+        }
+        let blob_path = self.inner.path_for_digest(digest, &instance);
+        let metadata = match tokio::fs::metadata(&blob_path).await {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("failed to stat blob {blob_path:?}: {err}").into()),
+        };
+        Ok(Some(BlobStat {
+            size_bytes: metadata.len() as usize,
+            last_accessed: metadata.accessed().ok(),
+            // Blob files are never modified once committed, so their modification time stands in
+            // for their creation time on filesystems which do not record one.
+            created: metadata.created().or_else(|_| metadata.modified()).ok(),
+            encoding: BlobEncoding::Raw,
+        }))
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -254,14 +462,101 @@ impl super::BlobStorage for FileBackedStorage {
             digest,
             tmp_path: blob_tmp_path,
             final_path: blob_path,
+            durability: self.durability,
         }))
     }
+
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let mut deleted_digests = Vec::new();
+        for digest in digests {
+            let blob_path = self.inner.path_for_digest(digest, &instance);
+            match tokio::fs::remove_file(&blob_path).await {
+                Ok(()) => deleted_digests.push(digest),
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => {
+                    return Err(format!("failed to delete blob {blob_path:?}: {err}").into())
+                }
+            }
+        }
+        Ok(deleted_digests)
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let blobs_path = self.inner.blobs_path(&instance);
+        let mut blobs = tokio::task::spawn_blocking(move || list_blob_files(&blobs_path))
+            .await
+            .map_err(|err| format!("failed to list blobs: {err}"))?
+            .map_err(|err| format!("failed to list blobs: {err}"))?;
+
+        // Most recently written first.
+        blobs.sort_unstable_by(|(modified1, _), (modified2, _)| modified2.cmp(modified1));
+        Ok(blobs
+            .into_iter()
+            .take(limit)
+            .map(|(_, digest)| digest)
+            .collect())
+    }
+
+    /// Lists blobs in digest order.
+    #[tracing::instrument(skip_all, fields(driver = "file_backed"))]
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        _state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        let after = BlobPage::parse_digest_cursor(cursor.as_deref())?;
+        let page_size = page_size.max(1);
+        let blobs_path = self.inner.blobs_path(&instance);
+        let digests = tokio::task::spawn_blocking(move || {
+            list_blob_files_after(&blobs_path, after, page_size)
+        })
+        .await
+        .map_err(|err| format!("failed to list blobs: {err}"))?
+        .map_err(|err| format!("failed to list blobs: {err}"))?;
+        Ok(BlobPage::in_digest_order(digests, page_size))
+    }
+}
+
+/// Check that the hashed content of the blob at `blob_path` matches `digest`, deleting the blob if
+/// it does not.
+async fn verify_blob(hasher: Sha256, digest: Digest, blob_path: &Path) -> Result<(), StorageError> {
+    let hash = hasher.finalize();
+    let actual_digest = Digest::from_slice(&hash, digest.size_bytes)?;
+    if actual_digest == digest {
+        return Ok(());
+    }
+
+    metrics::counter!("toolchain_storage_file_corruption_total", 1);
+    log::error!("Deleting corrupt blob {blob_path:?}: expected digest {digest:?}, but content has digest {actual_digest:?}");
+    if let Err(err) = tokio::fs::remove_file(blob_path).await {
+        if err.kind() != ErrorKind::NotFound {
+            log::error!("Failed to delete corrupt blob {blob_path:?}: {err}");
+        }
+    }
+    Err(StorageError::Internal(format!(
+        "corrupt content for digest {digest:?}: content has digest {actual_digest:?}"
+    )))
 }
 
 impl FileBackedStorage {
     pub async fn new(
         base_path: impl AsRef<Path>,
         container_id: &str,
+        durability: Durability,
     ) -> Result<Self, StorageError> {
         let base_path = base_path.as_ref().join("v1").to_owned();
 
@@ -281,27 +576,35 @@ impl FileBackedStorage {
                 tmp_blobs_path,
                 blob_sequence: AtomicUsize::new(0),
             }),
+            durability,
+            verify_reads: false,
         })
     }
+
+    /// Verify the content of blobs against their digests as they are read.
+    pub fn with_read_verification(mut self, verify_reads: bool) -> Self {
+        self.verify_reads = verify_reads;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime};
 
     use bytes::Bytes;
     use tokio::time;
 
-    use super::FileBackedStorage;
+    use super::{Durability, FileBackedStorage};
     use crate::bytes::consolidate_stream;
-    use crate::driver::{BlobStorage, DriverState, Instance};
+    use crate::driver::{BlobStorage, DriverState, Instance, StorageError};
     use crate::testutil::TestData;
 
     #[tokio::test]
     async fn test_basic_read_write() {
         let base_path = tempfile::tempdir().unwrap();
 
-        let mut storage = FileBackedStorage::new(base_path.path(), "test")
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
             .await
             .unwrap();
         let instance = Instance::from("main");
@@ -363,7 +666,7 @@ mod tests {
     async fn test_offset_and_limit() {
         let base_path = tempfile::tempdir().unwrap();
 
-        let mut storage = FileBackedStorage::new(base_path.path(), "test")
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
             .await
             .unwrap();
         let instance = Instance::from("main");
@@ -394,11 +697,214 @@ mod tests {
         assert_eq!(actual_content, Bytes::from_static(b"bar"));
     }
 
+    #[tokio::test]
+    async fn test_sharded_layout() {
+        let base_path = tempfile::tempdir().unwrap();
+
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
+            .await
+            .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let contents = [
+            TestData::from_static(b"foobar"),
+            TestData::from_static(b"xyzzy"),
+            TestData::from_static(b"hello world"),
+        ];
+        for content in &contents {
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+
+        for content in &contents {
+            let hex_hash = content.digest.hex();
+            let expected_path = base_path
+                .path()
+                .join("v1/instances/main/blobs")
+                .join(&hex_hash[0..2])
+                .join(&hex_hash[2..4])
+                .join(&hex_hash[4..6])
+                .join(format!("{}-{}.bin", hex_hash, content.digest.size_bytes));
+            assert!(expected_path.is_file(), "missing {expected_path:?}");
+
+            let stream = storage
+                .read_blob(
+                    instance.clone(),
+                    content.digest,
+                    1024,
+                    None,
+                    None,
+                    DriverState::default(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_recent_blobs() {
+        let base_path = tempfile::tempdir().unwrap();
+
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
+            .await
+            .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let content1 = TestData::from_static(b"foobar");
+        let content2 = TestData::from_static(b"xyzzy");
+
+        for content in [&content1, &content2] {
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+            // Ensure that the blobs have distinct modification times.
+            time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let digests = storage
+            .list_recent_blobs(instance.clone(), 10, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(digests, vec![content2.digest, content1.digest]);
+
+        let digests = storage
+            .list_recent_blobs(instance, 1, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(digests, vec![content2.digest]);
+    }
+
+    #[tokio::test]
+    async fn test_list_blobs() {
+        let base_path = tempfile::tempdir().unwrap();
+
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
+            .await
+            .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let mut expected_digests = Vec::new();
+        for content in [&b"foo"[..], b"bar", b"baz", b"xyzzy", b"grok"] {
+            let content = TestData::from_static(content);
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+            expected_digests.push(content.digest);
+        }
+        expected_digests.sort();
+
+        // Listing in pages returns every blob once, in digest order.
+        let mut digests = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = storage
+                .list_blobs(instance.clone(), cursor, 2, DriverState::default())
+                .await
+                .unwrap();
+            assert!(page.digests.len() <= 2);
+            digests.extend(page.digests);
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(digests, expected_digests);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_write_is_not_visible() {
+        let base_path = tempfile::tempdir().unwrap();
+
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::FsyncDir)
+            .await
+            .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let content = TestData::from_static(b"foobar");
+
+        // Drop a partially written attempt without committing it.
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.slice(0..3)).await.unwrap();
+        drop(attempt);
+
+        let missing_blobs = storage
+            .find_missing_blobs(
+                instance.clone(),
+                vec![content.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing_blobs, vec![content.digest]);
+        let read_result = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert!(read_result.is_none());
+
+        // Wait for the tmp file delete to process. The partial content must not remain anywhere.
+        time::sleep(Duration::from_secs(1)).await;
+        let entries = walkdir::WalkDir::new(&base_path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .count();
+        assert_eq!(entries, 0);
+
+        // A complete write is durably committed and visible.
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stream = storage
+            .read_blob(
+                instance,
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+    }
+
     #[tokio::test]
     async fn test_multiple_writers() {
         let base_path = tempfile::tempdir().unwrap();
 
-        let mut storage = FileBackedStorage::new(base_path.path(), "test")
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
             .await
             .unwrap();
         let instance = Instance::from("main");
@@ -434,4 +940,101 @@ mod tests {
         println!("{entries:?}");
         assert_eq!(entries.len(), 1, "There must only be one file.");
     }
+
+    #[tokio::test]
+    async fn test_read_verification_removes_corrupt_blob() {
+        let base_path = tempfile::tempdir().unwrap();
+
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
+            .await
+            .unwrap()
+            .with_read_verification(true);
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let content = TestData::from_static(b"foobar");
+
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        // Intact content is read successfully.
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                4,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+
+        // Corrupt the on-disk content without changing its size.
+        let blob_path = storage.inner.path_for_digest(content.digest, &instance);
+        std::fs::write(&blob_path, b"fooBAR").unwrap();
+
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                4,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let err = consolidate_stream(stream).await.unwrap_err();
+        assert!(matches!(err, StorageError::Internal(_)), "{err:?}");
+        assert!(!blob_path.exists());
+
+        let missing_blobs = storage
+            .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(missing_blobs, vec![content.digest]);
+    }
+
+    #[tokio::test]
+    async fn test_stat() {
+        let base_path = tempfile::tempdir().unwrap();
+
+        let storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
+            .await
+            .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let content = TestData::from_static(b"foobar");
+        let stat = storage
+            .stat(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(stat, None);
+
+        let before_write = SystemTime::now() - Duration::from_secs(1);
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stat = storage
+            .stat(instance, content.digest, DriverState::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.size_bytes, content.bytes.len());
+        assert!(stat.created.unwrap() >= before_write);
+        assert!(stat.last_accessed.is_some());
+    }
 }
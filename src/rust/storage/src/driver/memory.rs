@@ -3,6 +3,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
@@ -11,7 +12,8 @@ use parking_lot::Mutex;
 
 use super::Instance;
 use crate::driver::{
-    BoxReadStream, DriverState, StorageError, StreamingWriteError, WriteAttemptOps,
+    BlobEncoding, BlobPage, BlobStat, BoxReadStream, DriverState, StorageError,
+    StreamingWriteError, WriteAttemptOps,
 };
 
 pub struct MemoryWriteAttempt {
@@ -21,9 +23,16 @@ pub struct MemoryWriteAttempt {
     storage: Arc<Mutex<Inner>>,
 }
 
+/// The content of a stored blob, and when it was stored and last read.
+struct StoredBlob {
+    content: Bytes,
+    created: SystemTime,
+    last_accessed: SystemTime,
+}
+
 struct Inner {
     /// Stores the content associated with a digest.
-    blobs: HashMap<Digest, Bytes>,
+    blobs: HashMap<Digest, StoredBlob>,
 
     /// Stores whether a particular blob is visible in particular instance.
     ///
@@ -65,7 +74,12 @@ impl super::WriteAttemptOps for MemoryWriteAttempt {
             });
 
         let content = self.content.freeze();
-        inner.blobs.entry(digest).or_insert(content);
+        let now = SystemTime::now();
+        inner.blobs.entry(digest).or_insert(StoredBlob {
+            content,
+            created: now,
+            last_accessed: now,
+        });
 
         Ok(())
     }
@@ -105,21 +119,24 @@ impl super::BlobStorage for MemoryStorage {
         read_limit: Option<usize>,
         _state: DriverState,
     ) -> Result<Option<BoxReadStream>, StorageError> {
-        let inner = self.inner.lock();
-        let instance_blobs = inner.get_blobs_for_instance(&instance)?;
+        let mut inner = self.inner.lock();
+        let is_visible = inner.get_blobs_for_instance(&instance)?.contains(&digest);
 
-        let blob = match (instance_blobs.contains(&digest), inner.blobs.get(&digest)) {
-            (true, Some(b)) => b.clone(),
+        let blob = match (is_visible, inner.blobs.get_mut(&digest)) {
+            (true, Some(b)) => {
+                b.last_accessed = SystemTime::now();
+                b.content.clone()
+            }
             _ => return Ok(None),
         };
 
         let stream = async_stream::stream! {
           let mut offset = read_offset.unwrap_or_default();
-          let final_offset = offset + read_limit.unwrap_or(blob.len()).min(blob.len());
+          let final_offset = (offset + read_limit.unwrap_or(blob.len())).min(blob.len());
 
           while offset < final_offset {
             let start: usize = offset;
-            let end: usize = (start + max_batch_size).min(blob.len());
+            let end: usize = (start + max_batch_size).min(final_offset);
             let chunk = blob.slice(start..end);
             yield Ok(chunk);
             offset = end;
@@ -129,6 +146,32 @@ impl super::BlobStorage for MemoryStorage {
         Ok(Some(Box::pin(stream)))
     }
 
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        _state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        if digest == Digest::EMPTY {
+            return Ok(Some(BlobStat {
+                size_bytes: 0,
+                last_accessed: None,
+                created: None,
+                encoding: BlobEncoding::Raw,
+            }));
+        }
+        let inner = self.inner.lock();
+        if !inner.get_blobs_for_instance(&instance)?.contains(&digest) {
+            return Ok(None);
+        }
+        Ok(inner.blobs.get(&digest).map(|blob| BlobStat {
+            size_bytes: blob.content.len(),
+            last_accessed: Some(blob.last_accessed),
+            created: Some(blob.created),
+            encoding: BlobEncoding::Raw,
+        }))
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -156,7 +199,75 @@ impl super::BlobStorage for MemoryStorage {
         Ok(Box::new(attempt))
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, _state: DriverState) {
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let mut inner = self.inner.lock();
+        inner.get_blobs_for_instance(&instance)?;
+
+        let mut deleted_digests = Vec::new();
+        for digest in digests {
+            let removed = inner
+                .blobs_by_instance
+                .get_mut(&instance)
+                .map(|blobs| blobs.remove(&digest))
+                .unwrap_or(false);
+            if !removed {
+                continue;
+            }
+            deleted_digests.push(digest);
+
+            // Only drop the content once no other instance refers to it.
+            if !inner
+                .blobs_by_instance
+                .values()
+                .any(|blobs| blobs.contains(&digest))
+            {
+                inner.blobs.remove(&digest);
+            }
+        }
+
+        Ok(deleted_digests)
+    }
+
+    /// The memory driver does not track write order, so this returns an arbitrary subset of the
+    /// instance's blobs.
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let inner = self.inner.lock();
+        let instance_blobs = inner.get_blobs_for_instance(&instance)?;
+        Ok(instance_blobs.iter().take(limit).copied().collect())
+    }
+
+    /// Lists blobs in digest order.
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        _state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        let after = BlobPage::parse_digest_cursor(cursor.as_deref())?;
+        let inner = self.inner.lock();
+        let instance_blobs = inner.get_blobs_for_instance(&instance)?;
+        let mut digests = instance_blobs
+            .iter()
+            .filter(|digest| after.map_or(true, |after| **digest > after))
+            .copied()
+            .collect::<Vec<_>>();
+        digests.sort_unstable();
+        digests.truncate(page_size.max(1));
+        Ok(BlobPage::in_digest_order(digests, page_size.max(1)))
+    }
+
+    fn ensure_instance(&self, instance: &Instance, _state: DriverState) {
         let mut inner = self.inner.lock();
         inner.setup_instance(instance);
     }
@@ -201,7 +312,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_basic_read_write() {
-        let mut storage = MemoryStorage::new();
+        let storage = MemoryStorage::new();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -262,7 +373,7 @@ mod tests {
 
     #[tokio::test]
     async fn dropped_write_attempt() {
-        let mut storage = MemoryStorage::new();
+        let storage = MemoryStorage::new();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -303,4 +414,64 @@ mod tests {
             .unwrap();
         assert_eq!(missing_blobs, vec![content.digest]);
     }
+
+    #[tokio::test]
+    async fn stat_reports_size_and_times() {
+        let storage = MemoryStorage::new();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let content = TestData::from_static(b"foobar");
+        let stat = storage
+            .stat(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(stat, None);
+
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stat = storage
+            .stat(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.size_bytes, content.bytes.len());
+        assert_eq!(stat.last_accessed, stat.created);
+        let created = stat.created.unwrap();
+
+        // Reading the blob updates its access time, but not its creation time.
+        let _stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let stat = storage
+            .stat(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.created, Some(created));
+        assert!(stat.last_accessed.unwrap() >= created);
+
+        // The blob is not visible to other instances.
+        let other_instance = Instance::from("other");
+        storage.ensure_instance(&other_instance, DriverState::default());
+        let stat = storage
+            .stat(other_instance, content.digest, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(stat, None);
+    }
 }
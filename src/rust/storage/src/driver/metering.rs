@@ -3,26 +3,51 @@
 
 use std::collections::HashMap;
 use std::ops::{Add, AddAssign};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::mpsc::{channel, Sender};
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, StorageError,
+    StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
+/// Default number of usage reports which may be queued for the `AmberfloEmitter`. Reports are
+/// dropped when the queue is full.
+pub const DEFAULT_AMBERFLO_QUEUE_CAPACITY: usize = 10_000;
+
+/// Number of attempts made to send the meter events of an aggregation window to Amberflo before
+/// they are carried over to the next window.
+const EMIT_MAX_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry of sending meter events to Amberflo, which doubles on each retry.
+const EMIT_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Timeout for a single request to the Amberflo ingest endpoint.
+const EMIT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// A `BlobStorage` that emits metrics for calls into an underlying `BlobStorage` implementation
 /// to enable metering of usage.
+///
+/// Reads (egress) and writes (ingress) are metered separately, by the bytes actually streamed:
+/// a read counts the bytes delivered to the client, even if it stops part way through, and a
+/// write counts the bytes of the blob only once it is committed.
+///
+/// Usage is accumulated for the whole of each read and write, and sent to the `AmberfloEmitter`
+/// as a single report when the operation ends (rather than per chunk). The emitter then sums the
+/// reports of each customer and sends them to Amberflo once per aggregation window, so totals
+/// remain exact.
 #[derive(Clone, Debug)]
 pub struct MeteredStorage<BS> {
-    sender: UnboundedSender<UsageReport>,
+    sender: Sender<UsageReport>,
     inner: BS,
 }
 
@@ -45,8 +70,59 @@ impl AddAssign<&UsageReport> for UsageReport {
     }
 }
 
+impl UsageReport {
+    fn is_empty(&self) -> bool {
+        self.cache_read_bytes == 0
+            && self.num_read_blobs == 0
+            && self.cache_write_bytes == 0
+            && self.num_write_blobs == 0
+    }
+}
+
+/// Usage accumulated by a single read, which is sent to the emitter when it is dropped so that
+/// reads which are abandoned part way through are still metered.
+struct PendingUsage {
+    sender: Sender<UsageReport>,
+    report: UsageReport,
+}
+
+impl PendingUsage {
+    fn new(sender: Sender<UsageReport>, customer_id: String) -> Self {
+        PendingUsage {
+            sender,
+            report: UsageReport {
+                customer_id,
+                ..UsageReport::default()
+            },
+        }
+    }
+}
+
+impl Drop for PendingUsage {
+    fn drop(&mut self) {
+        if !self.report.is_empty() {
+            send_usage_report(&self.sender, std::mem::take(&mut self.report));
+        }
+    }
+}
+
+/// Queue a usage report for the emitter without waiting, so that metering never delays storage
+/// operations. The report is dropped (and counted) if the queue is full.
+fn send_usage_report(sender: &Sender<UsageReport>, report: UsageReport) {
+    if sender.try_send(report).is_err() {
+        metrics::counter!("toolchain_amberflo_dropped_events_total", 1);
+    }
+}
+
+fn merge_usage_report(reports: &mut HashMap<String, UsageReport>, report: UsageReport) {
+    reports
+        .entry(report.customer_id.clone())
+        .and_modify(|agg| *agg += &report)
+        .or_insert(report);
+}
+
 impl<BS> MeteredStorage<BS> {
-    pub fn new(inner: BS, sender: UnboundedSender<UsageReport>) -> Self {
+    pub fn new(inner: BS, sender: Sender<UsageReport>) -> Self {
         MeteredStorage { sender, inner }
     }
 }
@@ -97,20 +173,13 @@ where
 
         let sender = self.sender.clone();
         let stream = async_stream::try_stream! {
+            let mut usage = PendingUsage::new(sender, customer_id);
             while let Some(chunk_result) = stream.next().await {
                 let chunk = chunk_result?;
-                let _ = sender.send(UsageReport {
-                    customer_id: customer_id.clone(),
-                    cache_read_bytes: chunk.len(),
-                    ..UsageReport::default()
-                });
+                usage.report.cache_read_bytes += chunk.len();
                 yield chunk;
             }
-            let _ = sender.send(UsageReport {
-                customer_id: customer_id.clone(),
-                num_read_blobs: 1,
-                ..UsageReport::default()
-            });
+            usage.report.num_read_blobs = 1;
         };
 
         let stream = Box::pin(stream) as BoxReadStream;
@@ -118,6 +187,42 @@ where
         Ok(Some(stream))
     }
 
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        let customer_id = instance.name.clone();
+        let blobs = self.inner.read_blobs(instance, digests, state).await?;
+
+        // Meter the whole batch as a single report.
+        let mut report = UsageReport {
+            customer_id,
+            ..UsageReport::default()
+        };
+        for (_, result) in &blobs {
+            if let Ok(Some(content)) = result {
+                report.cache_read_bytes += content.len();
+                report.num_read_blobs += 1;
+            }
+        }
+        if !report.is_empty() {
+            send_usage_report(&self.sender, report);
+        }
+
+        Ok(blobs)
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.inner.stat(instance, digest, state).await
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -127,51 +232,87 @@ where
         let customer_id = instance.name.clone();
         let attempt = self.inner.begin_write_blob(instance, digest, state).await?;
         let attempt = WriteAttempt {
-            customer_id,
-            sender: self.sender.clone(),
             attempt,
+            sender: self.sender.clone(),
+            report: UsageReport {
+                customer_id,
+                ..UsageReport::default()
+            },
         };
         Ok(Box::new(attempt))
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.inner.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.inner.list_recent_blobs(instance, limit, state).await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.inner
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
         self.inner.ensure_instance(instance, state);
     }
 }
 
 struct WriteAttempt {
-    customer_id: String,
     attempt: Box<dyn WriteAttemptOps + Send + Sync + 'static>,
-    sender: UnboundedSender<UsageReport>,
+    sender: Sender<UsageReport>,
+    report: UsageReport,
 }
 
 #[async_trait]
 impl WriteAttemptOps for WriteAttempt {
     async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
-        let _ = self.sender.send(UsageReport {
-            customer_id: self.customer_id.clone(),
-            cache_write_bytes: batch.len(),
-            ..UsageReport::default()
-        });
+        self.report.cache_write_bytes += batch.len();
         self.attempt.write(batch).await
     }
 
     async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError> {
-        let customer_id = self.customer_id.clone();
-        let sender = self.sender.clone();
-        let result = self.attempt.commit().await;
-        let _ = sender.send(UsageReport {
-            customer_id,
-            num_write_blobs: 1,
-            ..UsageReport::default()
-        });
+        let WriteAttempt {
+            attempt,
+            sender,
+            mut report,
+        } = *self;
+        let result = attempt.commit().await;
+        // Only blobs which were stored are metered, so abandoned and failed writes are not.
+        if result.is_ok() {
+            report.num_write_blobs = 1;
+            send_usage_report(&sender, report);
+        }
         result
     }
 }
 
 /// Amberflo monitoring sink that aggregates and transmits meter events to Amberflo.
+///
+/// Sending to Amberflo happens in the background and is retried with backoff, while usage reports
+/// continue to be aggregated. Reports which still cannot be sent are carried over to the next
+/// aggregation window.
 pub struct AmberfloEmitter {
-    sender: UnboundedSender<UsageReport>,
+    sender: Sender<UsageReport>,
     _actor_fut: JoinHandle<()>,
 }
 
@@ -191,6 +332,15 @@ struct AmberfloIngestionEvent {
     dimensions: AmberfloIngestionEventDimensions,
 }
 
+/// Where and how meter events are sent to Amberflo.
+struct AmberfloIngestSettings {
+    client: reqwest::Client,
+    id_prefix: String,
+    api_key: String,
+    api_ingest_url: String,
+    env_dimension: String,
+}
+
 impl AmberfloEmitter {
     pub fn new(
         aggregation_duration: Duration,
@@ -198,14 +348,23 @@ impl AmberfloEmitter {
         env_dimension: String,
         api_key: String,
         api_ingest_url: Option<String>,
+        queue_capacity: usize,
     ) -> Self {
-        let (sender, mut receiver) = unbounded_channel::<UsageReport>();
-        let api_ingest_url =
-            api_ingest_url.unwrap_or_else(|| "https://app.amberflo.io/ingest".to_string());
+        let (sender, mut receiver) = channel::<UsageReport>(queue_capacity);
+        let settings = Arc::new(AmberfloIngestSettings {
+            client: reqwest::Client::new(),
+            id_prefix,
+            api_key,
+            api_ingest_url: api_ingest_url
+                .unwrap_or_else(|| "https://app.amberflo.io/ingest".to_string()),
+            env_dimension,
+        });
 
         let actor_fut = tokio::spawn(async move {
-            let client = reqwest::Client::new();
             let mut aggregated_usage_reports = HashMap::new();
+            // The background task sending the previous window's usage reports, which returns
+            // any reports which could not be sent.
+            let mut in_flight: Option<JoinHandle<Option<HashMap<String, UsageReport>>>> = None;
 
             let mut continue_running = true;
             'OUTER: while continue_running {
@@ -227,37 +386,40 @@ impl AmberfloEmitter {
                                     break 'INNER;
                                 }
                             };
-                            aggregated_usage_reports
-                                .entry(usage_report.customer_id.clone())
-                                .and_modify(|agg| *agg += &usage_report)
-                                .or_insert(usage_report);
+                            merge_usage_report(&mut aggregated_usage_reports, usage_report);
                         }
-                        _ = tokio::time::sleep_until(aggregation_window_ends.into()) => {
+                        _ = tokio::time::sleep_until(aggregation_window_ends) => {
                             break 'INNER;
                         }
                     }
                 }
 
+                // Keep aggregating while the previous window's reports are still being sent.
+                if continue_running && matches!(&in_flight, Some(h) if !h.is_finished()) {
+                    continue 'OUTER;
+                }
+                if let Some(handle) = in_flight.take() {
+                    if let Ok(Some(unsent_usage_reports)) = handle.await {
+                        for usage_report in unsent_usage_reports.into_values() {
+                            merge_usage_report(&mut aggregated_usage_reports, usage_report);
+                        }
+                    }
+                }
+
                 // Go back into the aggregation loop if there are no records to send.
                 if aggregated_usage_reports.is_empty() {
                     continue 'OUTER;
                 }
 
                 // Then transmit the usage reports to Amberflo for ingestion.
-                let result = Self::emit_meter_events(
-                    &client,
-                    &id_prefix,
-                    &api_key,
-                    &api_ingest_url,
-                    &aggregated_usage_reports,
-                    &env_dimension,
-                )
-                .await;
-
-                // On success, clear the aggregation buckets. Thus, on failure, keep them so
-                // they are sent on a subsequent aggregation window.
-                if result.is_ok() {
-                    aggregated_usage_reports.clear();
+                let handle = tokio::spawn(Self::emit_with_retries(
+                    settings.clone(),
+                    std::mem::take(&mut aggregated_usage_reports),
+                ));
+                if continue_running {
+                    in_flight = Some(handle);
+                } else {
+                    let _ = handle.await;
                 }
             }
         });
@@ -268,10 +430,38 @@ impl AmberfloEmitter {
         }
     }
 
-    pub fn sender(&self) -> UnboundedSender<UsageReport> {
+    pub fn sender(&self) -> Sender<UsageReport> {
         self.sender.clone()
     }
 
+    /// Send usage reports to Amberflo, retrying with exponential backoff. Returns the reports if
+    /// they could not be sent, so that they can be sent with the next aggregation window.
+    async fn emit_with_retries(
+        settings: Arc<AmberfloIngestSettings>,
+        aggregated_usage_reports: HashMap<String, UsageReport>,
+    ) -> Option<HashMap<String, UsageReport>> {
+        let mut delay = EMIT_RETRY_BASE_DELAY;
+        for attempt in 1..=EMIT_MAX_ATTEMPTS {
+            let result = Self::emit_meter_events(
+                &settings.client,
+                &settings.id_prefix,
+                &settings.api_key,
+                &settings.api_ingest_url,
+                &aggregated_usage_reports,
+                &settings.env_dimension,
+            )
+            .await;
+            if result.is_ok() {
+                return None;
+            }
+            if attempt < EMIT_MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        Some(aggregated_usage_reports)
+    }
+
     async fn emit_meter_events(
         client: &reqwest::Client,
         id_prefix: &str,
@@ -367,6 +557,7 @@ impl AmberfloEmitter {
             .post(api_ingest_url)
             .header("Accept", "application/json")
             .header("x-api-key", api_key)
+            .timeout(EMIT_REQUEST_TIMEOUT)
             .json(&records)
             .build()
             .map_err(|err| format!("Failed to build Amberflo request: {err}"))?;
@@ -404,18 +595,26 @@ mod tests {
     use std::cmp::Ordering;
     use std::convert::Infallible;
     use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
     use std::sync::Arc;
     use std::time::Duration;
 
     use axum::body::{boxed, Body};
     use axum::http::{Request, Response};
     use axum::routing::Router;
+    use bytes::Bytes;
+    use futures::StreamExt;
     use hyper::server::conn::AddrIncoming;
     use parking_lot::Mutex;
+    use tokio::sync::mpsc::UnboundedReceiver;
 
     use super::{
-        AmberfloEmitter, AmberfloIngestionEvent, AmberfloIngestionEventDimensions, UsageReport,
+        AmberfloEmitter, AmberfloIngestionEvent, AmberfloIngestionEventDimensions, MeteredStorage,
+        UsageReport, DEFAULT_AMBERFLO_QUEUE_CAPACITY,
     };
+    use crate::driver::{BlobStorage, DriverState, Instance, MemoryStorage};
+    use crate::testutil::{capture_metrics, counter_total};
+    use crate::Digest;
 
     fn make_incoming() -> (AddrIncoming, SocketAddr) {
         let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
@@ -424,13 +623,19 @@ mod tests {
         (incoming, local_addr)
     }
 
-    #[tokio::test]
-    async fn ensure_amberflo_emitter_send_events() {
-        // Spawn a Web server to capture Amerbflo emitted meters.
+    type CapturedRequests = Arc<Mutex<Vec<Request<Body>>>>;
+
+    /// Spawn a Web server to capture Amberflo emitted meters, which fails the first `failures`
+    /// requests. Returns its address, the requests it has received, and a channel which is
+    /// signalled for each request.
+    fn spawn_capture_server(
+        failures: usize,
+    ) -> (SocketAddr, CapturedRequests, UnboundedReceiver<()>) {
+        let remaining_failures = Arc::new(AtomicUsize::new(failures));
         let actual_amberflo_events = Arc::new(Mutex::new(Vec::new()));
         let actual_amberflo_events_2 = actual_amberflo_events.clone();
         let (capture_server_incoming, capture_server_local_addr) = make_incoming();
-        let (received_request_sender, mut received_request_receiver) =
+        let (received_request_sender, received_request_receiver) =
             tokio::sync::mpsc::unbounded_channel::<()>();
         tokio::spawn(async move {
             let app = Router::new().route_service(
@@ -438,11 +643,16 @@ mod tests {
                 tower::service_fn(move |request: Request<Body>| {
                     let actual_amberflo_events_2 = actual_amberflo_events_2.clone();
                     let received_request_sender = received_request_sender.clone();
+                    let fail = remaining_failures
+                        .fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, |n| {
+                            n.checked_sub(1)
+                        })
+                        .is_ok();
                     async move {
                         actual_amberflo_events_2.lock().push(request);
                         received_request_sender.send(()).unwrap();
                         let response = Response::builder()
-                            .status(200)
+                            .status(if fail { 500 } else { 200 })
                             .body(boxed(Body::empty()))
                             .unwrap();
                         Ok::<_, Infallible>(response)
@@ -455,6 +665,33 @@ mod tests {
                 .await
                 .unwrap();
         });
+        (
+            capture_server_local_addr,
+            actual_amberflo_events,
+            received_request_receiver,
+        )
+    }
+
+    /// Parse the meter events sent in a captured request, sorted by customer and meter name and
+    /// with their timestamps cleared.
+    async fn parse_records(request: Request<Body>) -> Vec<AmberfloIngestionEvent> {
+        let (_, body) = request.into_parts();
+        let body_bytes = hyper::body::to_bytes(body).await.unwrap();
+        let mut records: Vec<AmberfloIngestionEvent> = serde_json::from_slice(&body_bytes).unwrap();
+        records.sort_by(|a, b| match a.customer_id.cmp(&b.customer_id) {
+            Ordering::Equal => a.meter_api_name.cmp(&b.meter_api_name),
+            ord => ord,
+        });
+        for record in &mut records {
+            record.meter_time_in_millis = 0;
+        }
+        records
+    }
+
+    #[tokio::test]
+    async fn ensure_amberflo_emitter_send_events() {
+        let (capture_server_local_addr, actual_amberflo_events, mut received_request_receiver) =
+            spawn_capture_server(0);
 
         let emitter = AmberfloEmitter::new(
             Duration::from_secs(2),
@@ -462,11 +699,12 @@ mod tests {
             "test".into(),
             "test-api-key".into(),
             Some(format!("http://{capture_server_local_addr}/")),
+            DEFAULT_AMBERFLO_QUEUE_CAPACITY,
         );
 
         let sender = emitter.sender();
         sender
-            .send(UsageReport {
+            .try_send(UsageReport {
                 customer_id: "abc123".into(),
                 cache_read_bytes: 1024,
                 num_read_blobs: 1,
@@ -474,7 +712,7 @@ mod tests {
             })
             .unwrap();
         sender
-            .send(UsageReport {
+            .try_send(UsageReport {
                 customer_id: "def456".into(),
                 cache_read_bytes: 1024,
                 num_read_blobs: 1,
@@ -482,7 +720,7 @@ mod tests {
             })
             .unwrap();
         sender
-            .send(UsageReport {
+            .try_send(UsageReport {
                 customer_id: "abc123".into(),
                 cache_read_bytes: 512,
                 num_read_blobs: 1,
@@ -502,16 +740,7 @@ mod tests {
         let headers = request.headers();
         assert_eq!(headers.get("x-api-key").unwrap(), "test-api-key");
 
-        let (_, body) = request.into_parts();
-        let body_bytes = hyper::body::to_bytes(body).await.unwrap();
-        let mut records: Vec<AmberfloIngestionEvent> = serde_json::from_slice(&body_bytes).unwrap();
-        records.sort_by(|a, b| match a.customer_id.cmp(&b.customer_id) {
-            Ordering::Equal => a.meter_api_name.cmp(&b.meter_api_name),
-            ord => ord,
-        });
-        for record in &mut records {
-            record.meter_time_in_millis = 0;
-        }
+        let records = parse_records(request).await;
         assert_eq!(
             records,
             vec![
@@ -554,4 +783,245 @@ mod tests {
             ]
         );
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn metered_storage_aggregates_operations_within_a_window() {
+        let (capture_server_local_addr, actual_amberflo_events, mut received_request_receiver) =
+            spawn_capture_server(0);
+        let emitter = AmberfloEmitter::new(
+            Duration::from_secs(1),
+            "prefix".into(),
+            "test".into(),
+            "test-api-key".into(),
+            Some(format!("http://{capture_server_local_addr}/")),
+            DEFAULT_AMBERFLO_QUEUE_CAPACITY,
+        );
+
+        let instance = Instance::from("abc123");
+        let memory = MemoryStorage::new();
+        memory.ensure_instance(&instance, DriverState::default());
+        let storage = MeteredStorage::new(memory, emitter.sender());
+
+        // Write and then read many blobs, each in several chunks.
+        let blobs = (0..50)
+            .map(|i| {
+                let bytes = Bytes::from(format!("blob number {i}"));
+                let digest = Digest::of_bytes(&bytes).unwrap();
+                (bytes, digest)
+            })
+            .collect::<Vec<_>>();
+        let total_bytes = blobs.iter().map(|(bytes, _)| bytes.len()).sum::<usize>();
+        for (bytes, digest) in &blobs {
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), *digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(bytes.slice(..4)).await.unwrap();
+            attempt.write(bytes.slice(4..)).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+        for (bytes, digest) in &blobs {
+            let chunks = storage
+                .read_blob(
+                    instance.clone(),
+                    *digest,
+                    4,
+                    None,
+                    None,
+                    DriverState::default(),
+                )
+                .await
+                .unwrap()
+                .unwrap()
+                .map(|chunk| chunk.unwrap())
+                .collect::<Vec<_>>()
+                .await;
+            assert_eq!(chunks.concat(), *bytes);
+        }
+
+        received_request_receiver.recv().await.unwrap();
+        // Nothing else is sent in later windows.
+        assert!(tokio::time::timeout(
+            Duration::from_millis(1500),
+            received_request_receiver.recv()
+        )
+        .await
+        .is_err());
+
+        let actual_events = actual_amberflo_events.lock().drain(..).collect::<Vec<_>>();
+        assert_eq!(actual_events.len(), 1);
+        let records = parse_records(actual_events.into_iter().next().unwrap()).await;
+        let meter = |meter_api_name: &str, meter_value: usize| AmberfloIngestionEvent {
+            customer_id: "prefix_abc123".to_string(),
+            meter_api_name: meter_api_name.to_string(),
+            meter_value,
+            meter_time_in_millis: 0,
+            dimensions: AmberfloIngestionEventDimensions {
+                env: "test".to_string(),
+            },
+        };
+        assert_eq!(
+            records,
+            vec![
+                meter("cache-num-read-blobs", 50),
+                meter("cache-num-write-blobs", 50),
+                meter("cache-read-bytes", total_bytes),
+                meter("cache-write-bytes", total_bytes),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn metered_storage_sends_one_report_per_operation() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let instance = Instance::from("abc123");
+        let memory = MemoryStorage::new();
+        memory.ensure_instance(&instance, DriverState::default());
+        let storage = MeteredStorage::new(memory, sender);
+
+        let bytes = Bytes::from_static(b"0123456789");
+        let digest = Digest::of_bytes(&bytes).unwrap();
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), digest, DriverState::default())
+            .await
+            .unwrap();
+        for i in 0..bytes.len() {
+            attempt.write(bytes.slice(i..i + 1)).await.unwrap();
+        }
+        attempt.commit().await.unwrap();
+        let report = receiver.try_recv().unwrap();
+        assert_eq!(report.cache_write_bytes, 10);
+        assert_eq!(report.num_write_blobs, 1);
+        assert!(receiver.try_recv().is_err());
+
+        let chunks = storage
+            .read_blob(instance, digest, 1, None, None, DriverState::default())
+            .await
+            .unwrap()
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(chunks.len(), 10);
+        let report = receiver.try_recv().unwrap();
+        assert_eq!(report.cache_read_bytes, 10);
+        assert_eq!(report.num_read_blobs, 1);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn metered_storage_meters_partial_reads_and_committed_writes() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let instance = Instance::from("abc123");
+        let memory = MemoryStorage::new();
+        memory.ensure_instance(&instance, DriverState::default());
+        let storage = MeteredStorage::new(memory, sender);
+
+        // An abandoned write is not metered.
+        let bytes = Bytes::from_static(b"0123456789");
+        let digest = Digest::of_bytes(&bytes).unwrap();
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(bytes.slice(..5)).await.unwrap();
+        drop(attempt);
+        assert!(receiver.try_recv().is_err());
+
+        // A committed write meters the size of the blob as ingress.
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+        let report = receiver.try_recv().unwrap();
+        assert_eq!(report.cache_write_bytes, 10);
+        assert_eq!(report.num_write_blobs, 1);
+        assert_eq!(report.cache_read_bytes, 0);
+
+        // A read which is abandoned part way through meters only the bytes delivered as egress.
+        let mut stream = storage
+            .read_blob(instance, digest, 3, None, None, DriverState::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap().len(), 3);
+        assert_eq!(stream.next().await.unwrap().unwrap().len(), 3);
+        drop(stream);
+        let report = receiver.try_recv().unwrap();
+        assert_eq!(report.cache_read_bytes, 6);
+        assert_eq!(report.num_read_blobs, 0);
+        assert_eq!(report.cache_write_bytes, 0);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn metered_storage_drops_usage_reports_when_queue_is_full() {
+        capture_metrics();
+
+        // The receiver is never drained, as if the emitter were stalled by an Amberflo outage.
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let instance = Instance::from("abc123");
+        let memory = MemoryStorage::new();
+        memory.ensure_instance(&instance, DriverState::default());
+        let storage = MeteredStorage::new(memory, sender);
+        let dropped_before = counter_total("toolchain_amberflo_dropped_events_total", &[]);
+
+        let write_blobs = async {
+            for i in 0..10 {
+                let bytes = Bytes::from(format!("blob number {i}"));
+                let digest = Digest::of_bytes(&bytes).unwrap();
+                let mut attempt = storage
+                    .begin_write_blob(instance.clone(), digest, DriverState::default())
+                    .await
+                    .unwrap();
+                attempt.write(bytes).await.unwrap();
+                attempt.commit().await.unwrap();
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), write_blobs)
+            .await
+            .unwrap();
+
+        let dropped =
+            counter_total("toolchain_amberflo_dropped_events_total", &[]) - dropped_before;
+        assert_eq!(dropped, 9);
+    }
+
+    #[tokio::test]
+    async fn amberflo_emitter_retries_failed_requests() {
+        let (capture_server_local_addr, actual_amberflo_events, mut received_request_receiver) =
+            spawn_capture_server(1);
+        let emitter = AmberfloEmitter::new(
+            Duration::from_millis(100),
+            "prefix".into(),
+            "test".into(),
+            "test-api-key".into(),
+            Some(format!("http://{capture_server_local_addr}/")),
+            DEFAULT_AMBERFLO_QUEUE_CAPACITY,
+        );
+        emitter
+            .sender()
+            .try_send(UsageReport {
+                customer_id: "abc123".into(),
+                cache_read_bytes: 1024,
+                ..UsageReport::default()
+            })
+            .unwrap();
+
+        // The first request fails, and the same events are sent again after a backoff.
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(5), received_request_receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let actual_events = actual_amberflo_events.lock().drain(..).collect::<Vec<_>>();
+        assert_eq!(actual_events.len(), 2);
+        for request in actual_events {
+            let records = parse_records(request).await;
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].meter_value, 1024);
+        }
+    }
 }
@@ -11,8 +11,8 @@ use futures::Stream;
 use metrics::{counter, histogram};
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, SmallBlobStorage, StorageError,
-    StreamingWriteError, WriteAttemptOps,
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, SmallBlobStorage,
+    StorageError, StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
@@ -319,7 +319,82 @@ where
         Ok(Box::new(wrapped_attempt))
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        counter!(
+            "toolchain_storage_requests_started_total",
+            1,
+            "operation" => "read_blobs",
+            "driver" => self.driver_label,
+            "purpose" => self.purpose_label,
+            "leaf" => self.leaf_label,
+            "reapi_instance" => instance.name.clone(),
+        );
+        self.inner.read_blobs(instance, digests, state).await
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        counter!(
+            "toolchain_storage_requests_started_total",
+            1,
+            "operation" => "stat",
+            "driver" => self.driver_label,
+            "purpose" => self.purpose_label,
+            "leaf" => self.leaf_label,
+            "reapi_instance" => instance.name.clone(),
+        );
+        self.inner.stat(instance, digest, state).await
+    }
+
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        counter!(
+            "toolchain_storage_requests_started_total",
+            1,
+            "operation" => "delete_blobs",
+            "driver" => self.driver_label,
+            "purpose" => self.purpose_label,
+            "leaf" => self.leaf_label,
+            "reapi_instance" => instance.name.clone(),
+        );
+        self.inner.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.inner.list_recent_blobs(instance, limit, state).await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.inner
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
         self.inner.ensure_instance(instance, state)
     }
 }
@@ -537,6 +612,24 @@ where
         result
     }
 
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        counter!(
+            "toolchain_storage_requests_started_total",
+            1,
+            "operation" => "stat",
+            "driver" => self.driver_label,
+            "purpose" => self.purpose_label,
+            "leaf" => self.leaf_label,
+            "reapi_instance" => instance.name.clone(),
+        );
+        self.inner.stat(instance, digest, state).await
+    }
+
     async fn write_blob(
         &self,
         instance: Instance,
@@ -601,4 +694,24 @@ where
         );
         result
     }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.inner
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn max_blob_size(&self) -> Option<usize> {
+        self.inner.max_blob_size()
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.inner.ensure_instance(instance, state)
+    }
 }
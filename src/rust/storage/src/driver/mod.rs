@@ -2,16 +2,22 @@
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use digest::Digest;
-use futures::Stream;
+use futures::{future, Stream};
+
+use crate::bytes::consolidate_stream;
 
 mod always_errors;
 mod chunking;
+mod concurrency_limit;
 mod dark_launch;
 mod digest_verifier;
+mod encrypting;
 mod error;
 mod existence_cache;
 mod fast_slow;
@@ -21,23 +27,27 @@ mod metering;
 mod metrics;
 mod null;
 pub mod redis;
+mod retry;
 mod sharding;
 mod size_split;
 mod small;
 
-pub use self::metering::{AmberfloEmitter, MeteredStorage};
+pub use self::metering::{AmberfloEmitter, MeteredStorage, DEFAULT_AMBERFLO_QUEUE_CAPACITY};
 pub use self::metrics::MetricsMonitoredStorage;
 pub use self::redis::{RedisBackend, RedisDirectStorage, RedisStorage};
 pub use always_errors::AlwaysErrorsStorage;
-pub use chunking::ChunkingStorage;
+pub use chunking::{ChunkingStorage, ChunkingStrategy};
+pub use concurrency_limit::ConcurrencyLimitStorage;
 pub use dark_launch::DarkLaunchStorage;
 pub use digest_verifier::{ReadDigestVerifier, WriteDigestVerifier};
+pub use encrypting::EncryptingStorage;
 pub use error::{StorageError, StreamingWriteError};
 pub use existence_cache::ExistenceCacheStorage;
 pub use fast_slow::FastSlowReplicationStorage;
-pub use file_backed::FileBackedStorage;
+pub use file_backed::{Durability, FileBackedStorage};
 pub use memory::{MemoryStorage, MemoryWriteAttempt};
 pub use null::NullStorage;
+pub use retry::RetryingStorage;
 pub use sharding::ShardingStorage;
 pub use size_split::SizeSplitStorage;
 pub use small::{BlobStorageAdapter, SmallBlobStorage, SmallBlobStorageAdapter};
@@ -54,7 +64,23 @@ pub use small::{BlobStorageAdapter, SmallBlobStorage, SmallBlobStorageAdapter};
 ///
 /// Driver authors can add new fields and methods when they would like to use some new state.
 #[derive(Clone, Debug, Default)]
-pub struct DriverState;
+pub struct DriverState {
+    /// How the content passed to `begin_write_blob` is encoded. Drivers which store per-blob
+    /// metadata (e.g. `RedisStorage`) record it, report it with `stat`, and decode `Zstd` content
+    /// when it is read.
+    pub encoding: BlobEncoding,
+}
+
+/// How the content of a stored blob is encoded, as recorded by drivers which store per-blob
+/// metadata. Blobs stored without an encoding are `Raw`. `Encrypted` content is read back as
+/// stored, and decrypted by the `EncryptingStorage` which wrote it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BlobEncoding {
+    #[default]
+    Raw,
+    Zstd,
+    Encrypted,
+}
 
 /// Represents metadata about an REAPI instance. Only stores a `name` for now.
 #[derive(Clone, Hash, Eq, PartialEq)]
@@ -91,6 +117,100 @@ pub trait WriteAttemptOps {
     async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError>;
 }
 
+/// Metadata about a stored blob, as returned by `BlobStorage::stat`. Timestamps are `None` where
+/// the driver does not track them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BlobStat {
+    pub size_bytes: usize,
+    pub last_accessed: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+    pub encoding: BlobEncoding,
+}
+
+/// A page of the digests stored for an instance, as returned by `BlobStorage::list_blobs`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlobPage {
+    pub digests: Vec<Digest>,
+    /// The cursor from which to list the next page, or `None` if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl BlobPage {
+    /// A page of `digests` listed in digest order, given the `page_size` which was requested.
+    /// Only a full page is followed by another page, from the last digest listed.
+    pub(crate) fn in_digest_order(digests: Vec<Digest>, page_size: usize) -> Self {
+        let next_cursor = match digests.last() {
+            Some(last) if digests.len() >= page_size => {
+                Some(format!("{}-{}", last.hex(), last.size_bytes))
+            }
+            _ => None,
+        };
+        BlobPage {
+            digests,
+            next_cursor,
+        }
+    }
+
+    /// Parse a cursor returned by `in_digest_order`: the digest after which to list.
+    pub(crate) fn parse_digest_cursor(
+        cursor: Option<&str>,
+    ) -> Result<Option<Digest>, StorageError> {
+        let Some(cursor) = cursor else {
+            return Ok(None);
+        };
+        let invalid = || StorageError::InvalidArgument(format!("Invalid cursor: {cursor}"));
+        let (hex_hash, size_bytes) = cursor.split_once('-').ok_or_else(invalid)?;
+        let size_bytes = size_bytes.parse().map_err(|_| invalid())?;
+        Digest::new(hex_hash, size_bytes)
+            .map(Some)
+            .map_err(|_| invalid())
+    }
+}
+
+/// List a page of the blobs of each of `storages` in turn, for drivers which divide blobs between
+/// several storages. The cursor holds the index of the storage being listed and its own cursor.
+pub(crate) async fn list_blobs_in_turn(
+    storages: &[&(dyn BlobStorage + Send + Sync)],
+    instance: Instance,
+    cursor: Option<String>,
+    page_size: usize,
+    state: DriverState,
+) -> Result<BlobPage, StorageError> {
+    let invalid = |cursor: &str| StorageError::InvalidArgument(format!("Invalid cursor: {cursor}"));
+    let (index, storage_cursor) = match cursor.as_deref() {
+        None => (0, None),
+        Some(cursor) => {
+            let (index, storage_cursor) = cursor.split_once(':').ok_or_else(|| invalid(cursor))?;
+            let index = index.parse::<usize>().map_err(|_| invalid(cursor))?;
+            (index, Some(storage_cursor).filter(|c| !c.is_empty()))
+        }
+    };
+    let Some(storage) = storages.get(index) else {
+        return match cursor {
+            Some(cursor) => Err(invalid(&cursor)),
+            None => Ok(BlobPage::default()),
+        };
+    };
+
+    let page = storage
+        .list_blobs(
+            instance,
+            storage_cursor.map(str::to_owned),
+            page_size,
+            state,
+        )
+        .await?;
+    let next_cursor = match page.next_cursor {
+        Some(storage_cursor) => Some(format!("{index}:{storage_cursor}")),
+        None if index + 1 < storages.len() => Some(format!("{}:", index + 1)),
+        None => None,
+    };
+    Ok(BlobPage {
+        digests: page.digests,
+        next_cursor,
+    })
+}
+
 /// Alias for the type of a read stream.
 pub type BoxReadStream = Pin<Box<dyn Stream<Item = Result<Bytes, StorageError>> + Send + 'static>>;
 
@@ -126,6 +246,70 @@ pub trait BlobStorage {
         state: DriverState,
     ) -> Result<Option<BoxReadStream>, StorageError>;
 
+    /// Read the entire content of each of the given digests, returning the content (or `None` if
+    /// the blob is not stored) for each digest in the order requested. An error for the
+    /// request as a whole fails all reads.
+    ///
+    /// This is used to implement the BatchReadBlobs RPC from the CAS API, and so is intended for
+    /// small blobs. The default implementation reads each blob with `read_blob` concurrently;
+    /// drivers which can fetch several blobs in one round-trip should override it.
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        let read_futures = digests.into_iter().map(|digest| {
+            let instance = instance.clone();
+            let state = state.clone();
+            async move {
+                let result = match self
+                    .read_blob(
+                        instance,
+                        digest,
+                        digest.size_bytes.max(1),
+                        None,
+                        None,
+                        state,
+                    )
+                    .await
+                {
+                    Ok(Some(stream)) => consolidate_stream(stream).await.map(Some),
+                    Ok(None) => Ok(None),
+                    Err(err) => Err(err),
+                };
+                (digest, result)
+            }
+        });
+        Ok(future::join_all(read_futures).await)
+    }
+
+    /// Return metadata about the blob for `digest` without reading it, or `None` if the blob is
+    /// not stored.
+    ///
+    /// This is intended for admin tooling. The default implementation only determines whether the
+    /// blob is present (using `find_missing_blobs`), and so reports the size from the digest, no
+    /// timestamps, and the `Raw` encoding.
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        let missing = self
+            .find_missing_blobs(instance, vec![digest], state)
+            .await?;
+        if !missing.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(BlobStat {
+            size_bytes: digest.size_bytes,
+            last_accessed: None,
+            created: None,
+            encoding: BlobEncoding::Raw,
+        }))
+    }
+
     /// Begin storing an upload into temporary upload space. The content for the blob will be
     /// streamed on a (potentially) piecemeal basis via `content_stream`.
     ///
@@ -138,8 +322,65 @@ pub trait BlobStorage {
         state: DriverState,
     ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError>;
 
+    /// Remove the given digests from storage, returning the digests which were actually deleted.
+    /// Digests which were not stored are ignored.
+    ///
+    /// This is an administrative operation (e.g. for data removal requests) and is not part of
+    /// the REAPI. Drivers which cannot delete blobs return `StorageError::Unimplemented`.
+    async fn delete_blobs(
+        &self,
+        _instance: Instance,
+        _digests: Vec<Digest>,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        Err(StorageError::Unimplemented(
+            "This storage driver does not support deleting blobs.".to_owned(),
+        ))
+    }
+
+    /// Return up to `limit` digests stored for `instance`, most recently written first where the
+    /// driver tracks write order.
+    ///
+    /// This is used to prewarm caches (e.g. `ExistenceCacheStorage`) and so is best-effort.
+    /// Drivers which cannot enumerate their content return `StorageError::Unimplemented`.
+    async fn list_recent_blobs(
+        &self,
+        _instance: Instance,
+        _limit: usize,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        Err(StorageError::Unimplemented(
+            "This storage driver does not support listing blobs.".to_owned(),
+        ))
+    }
+
+    /// List the digests of the blobs stored for `instance`, a page of about `page_size` digests at
+    /// a time (drivers whose backends page by themselves may return more or fewer, including
+    /// none). Pass the `next_cursor` of the previous page (or `None` for the first page) to list
+    /// the next page. Every blob which is stored for the whole of a listing is listed at least
+    /// once, while blobs written or deleted during the listing may or may not be.
+    ///
+    /// Unlike `list_recent_blobs`, this enumerates all of an instance's blobs, and is intended
+    /// for admin tooling (e.g. `ShardingStorage::rebalance`). Drivers which cannot enumerate
+    /// their content return `StorageError::Unimplemented`.
+    async fn list_blobs(
+        &self,
+        _instance: Instance,
+        _cursor: Option<String>,
+        _page_size: usize,
+        _state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        Err(StorageError::Unimplemented(
+            "This storage driver does not support listing blobs.".to_owned(),
+        ))
+    }
+
     /// Ensure the driver is setup to receive instances with the name `instance`.
-    fn ensure_instance(&mut self, _instance: &Instance, _state: DriverState) {}
+    ///
+    /// The API server calls this the first time that it sees each instance name, and so it may be
+    /// called concurrently with other operations, and more than once for the same instance if the
+    /// server has since forgotten it. Drivers which wrap other drivers must forward it.
+    fn ensure_instance(&self, _instance: &Instance, _state: DriverState) {}
 }
 
 #[async_trait]
@@ -177,6 +418,24 @@ where
             .await
     }
 
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        (**self).read_blobs(instance, digests, state).await
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        (**self).stat(instance, digest, state).await
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
@@ -186,7 +445,134 @@ where
         (**self).begin_write_blob(instance, digest, state).await
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        (**self).delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        (**self).list_recent_blobs(instance, limit, state).await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        (**self)
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        (**self).ensure_instance(instance, state)
+    }
+}
+
+#[async_trait]
+impl<BS> BlobStorage for Arc<BS>
+where
+    BS: BlobStorage + Send + Sync + 'static + ?Sized,
+{
+    async fn find_missing_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        (**self).find_missing_blobs(instance, digests, state).await
+    }
+
+    async fn read_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        max_batch_size: usize,
+        read_offset: Option<usize>,
+        read_limit: Option<usize>,
+        state: DriverState,
+    ) -> Result<Option<BoxReadStream>, StorageError> {
+        (**self)
+            .read_blob(
+                instance,
+                digest,
+                max_batch_size,
+                read_offset,
+                read_limit,
+                state,
+            )
+            .await
+    }
+
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        (**self).read_blobs(instance, digests, state).await
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        (**self).stat(instance, digest, state).await
+    }
+
+    async fn begin_write_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        (**self).begin_write_blob(instance, digest, state).await
+    }
+
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        (**self).delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        (**self).list_recent_blobs(instance, limit, state).await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        (**self)
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
         (**self).ensure_instance(instance, state)
     }
 }
@@ -1,7 +1,7 @@
 // Copyright 2022 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
-use crate::driver::{DriverState, Instance, SmallBlobStorage, StorageError};
+use crate::driver::{BlobPage, DriverState, Instance, SmallBlobStorage, StorageError};
 use crate::Digest;
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -39,4 +39,14 @@ impl SmallBlobStorage for NullStorage {
     ) -> Result<(), StorageError> {
         Ok(())
     }
+
+    async fn list_blobs(
+        &self,
+        _instance: Instance,
+        _cursor: Option<String>,
+        _page_size: usize,
+        _state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        Ok(BlobPage::default())
+    }
 }
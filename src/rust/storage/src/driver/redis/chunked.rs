@@ -1,23 +1,34 @@
 // Copyright 2021 Toolchain Labs, Inc. All rights reserved.
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::io::Write;
+use std::num::NonZeroUsize;
+
 use async_trait::async_trait;
-use bytes::Bytes;
-use futures::{future, FutureExt};
+use bytes::{Bytes, BytesMut};
+use futures::{future, FutureExt, StreamExt, TryStreamExt};
 use prost::Message;
+use redis::FromRedisValue;
 
-use super::common::{redis_pipeline, redis_query, ConnectionGetter};
+use super::common::{redis_pipeline, redis_query, scan_keys, ConnectionGetter};
+use super::traits::{AsRedisConnectionMut, IdentifyRedisConnection};
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    BlobEncoding, BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance,
+    StorageError, StreamingWriteError, WriteAttemptOps,
+};
+use crate::protos::toolchain::storage::redis::{
+    BlobEncoding as RedisBlobEncoding, RedisMetadataChunk,
 };
-use crate::protos::toolchain::storage::redis::RedisMetadataChunk;
 use crate::uuid_gen::{DefaultUuidGenerator, UuidGenerator};
 use crate::Digest;
 
 /// Label used for metrics.
 const DRIVER_LABEL: &str = "redis";
 
+/// Version of the blob format recorded in the metadata chunk of newly written blobs. Blobs
+/// written before the version was recorded have version 0, and are otherwise identical.
+const METADATA_FORMAT_VERSION: u32 = 1;
+
 /// Stores blobs in Redis using a "chunked" encoding where blobs are split into one or more
 /// chunks. Supports multiple instances.
 ///
@@ -26,8 +37,9 @@ const DRIVER_LABEL: &str = "redis";
 ///   - Stores the actual content of a blob. Maps (UUID on upload, block number) to data for
 ///     the block.
 ///   - Name format: INSTANCE:data-UUID-BLOCK
-///   - There is also a "metadata chunk" which stores the total number of chunks for the blob.
-///     The BLOCK in the name is `meta` for this metadata chunk.
+///   - There is also a "metadata chunk" which stores the total number of chunks for the blob,
+///     the version of its format, and the encoding of its content. The BLOCK in the name is
+///     `meta` for this metadata chunk.
 /// * CAS Index Maps:
 ///   - Maps a content hash to the UUID in the Data Map for the blob. There is one Index Map
 ///     per digest function per REAPI instance.
@@ -35,6 +47,10 @@ const DRIVER_LABEL: &str = "redis";
 ///
 /// Note: This driver must be used in conjunction with `ChunkingStorage` in order to manage
 /// the size of the chunks of blobs stored in Redis.
+///
+/// Note: Chunks are keyed by the UUID of the write which stored them rather than by their
+/// content, so identical chunks of different blobs (e.g. from content-defined chunking) are not
+/// deduplicated.
 pub struct RedisStorage<C, UG = DefaultUuidGenerator>
 where
     C: ConnectionGetter + Clone + Send + Sync + 'static,
@@ -43,6 +59,7 @@ where
     conn: C,
     prefix: String,
     uuid_generator: UG,
+    find_missing_concurrency: NonZeroUsize,
 }
 
 struct RedisWriteAttempt<C>
@@ -55,6 +72,7 @@ where
     prefix: String,
     digest: Digest,
     chunk_num: u64, // match type used in the protobuf definition of the metadata chunk
+    encoding: BlobEncoding,
     conn: C,
 }
 
@@ -64,34 +82,42 @@ where
     C: ConnectionGetter + Clone + Send + Sync + 'static,
     UG: UuidGenerator + Send + Sync,
 {
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
     async fn find_missing_blobs(
         &self,
         instance: Instance,
         digests: Vec<Digest>,
         _state: DriverState,
     ) -> Result<Vec<Digest>, StorageError> {
-        let exists_futures = digests
-            .into_iter()
-            .map(|digest| {
+        // Check at most `find_missing_concurrency` digests at a time so that a large request does
+        // not exhaust the connection pool. The first error fails the whole request (dropping any
+        // in-flight checks).
+        let mut exists_responses = futures::stream::iter(digests.into_iter().enumerate())
+            .map(|(index, digest)| {
                 Self::check_digest_does_not_exist(&instance, &self.conn, digest, &self.prefix)
+                    .map(move |result| result.map(|missing| (index, missing)))
             })
-            .collect::<Vec<_>>();
-        let exists_responses = future::try_join_all(exists_futures).await?;
+            .buffer_unordered(self.find_missing_concurrency.get())
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // Return the missing digests in the order in which they were requested.
+        exists_responses.sort_unstable_by_key(|(index, _)| *index);
         let missing_digests = exists_responses
-            .iter()
-            .flatten()
-            .cloned()
+            .into_iter()
+            .filter_map(|(_, missing)| missing)
             .collect::<Vec<_>>();
         Ok(missing_digests)
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
     async fn read_blob(
         &self,
         instance: Instance,
         digest: Digest,
         _max_batch_size: usize,
-        _read_offset: Option<usize>,
-        _read_limit: Option<usize>,
+        read_offset: Option<usize>,
+        read_limit: Option<usize>,
         _state: DriverState,
     ) -> Result<Option<BoxReadStream>, StorageError> {
         async fn fetch_chunk<C>(conn: C, key: String) -> Result<Bytes, StorageError>
@@ -110,10 +136,12 @@ where
 
             let data_vec = match data_vec_opt {
                 Some(data_vec) => data_vec,
-                None => return Err(StorageError::Internal(format!("Missing data block: {key}"))),
+                // The blob was evicted after its existence was checked.
+                None => return Err(StorageError::NotFound(format!("Missing data block: {key}"))),
             };
 
-            Ok(Bytes::copy_from_slice(&data_vec[..]))
+            // NB: Takes ownership of the fetched buffer rather than copying it.
+            Ok(Bytes::from(data_vec))
         }
 
         // Check if all keys for the blob exist first.
@@ -164,14 +192,14 @@ where
         .await?;
 
         let metadata = match metadata_opt {
-            Some(data) => RedisMetadataChunk::decode(&data[..])
-                .map_err(|_| StorageError::Internal("Corrupt metadata chunk".to_owned()))?,
+            Some(data) => decode_metadata(&data)?,
             None => {
                 // TODO: If metadata chunk is missing, then delete this entry from the Index Map.
                 return Ok(None);
             }
         };
 
+        let encoding = metadata_encoding(&metadata)?;
         let num_chunks = metadata.num_chunks;
         let conn_clone = self.conn.clone();
         let stream = futures::stream::unfold(Some(0u64), move |state| {
@@ -196,16 +224,187 @@ where
             .boxed()
         });
 
-        let stream = Box::pin(stream) as BoxReadStream;
+        let stream = decode_stream(Box::pin(stream), encoding, digest.size_bytes);
 
-        Ok(Some(stream))
+        Ok(Some(apply_read_range(stream, read_offset, read_limit)))
     }
 
-    async fn begin_write_blob(
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        _state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        // The metadata and data keys of a blob are only known after looking up the previous
+        // key, so each of the three lookups is done for all of the blobs in a single pipeline.
+        let mut conn = self.conn.get_redis_connection(false).await?;
+
+        // Query the Index Map for the UUID of each blob.
+        let index_map_keys = digests
+            .iter()
+            .map(|digest| {
+                format!(
+                    "{}{}:index-sha256-{}-{}",
+                    &self.prefix,
+                    &instance.name,
+                    digest.hex(),
+                    digest.size_bytes
+                )
+            })
+            .collect::<Vec<_>>();
+        let uuids: Vec<Option<String>> = get_values(&mut conn, &index_map_keys).await?;
+        let data_map_key_bases = uuids
+            .into_iter()
+            .map(|uuid_opt| {
+                uuid_opt.map(|uuid| format!("{}{}:data-{}", &self.prefix, &instance.name, uuid))
+            })
+            .collect::<Vec<_>>();
+
+        // Query the Data Map for the metadata chunk of each blob present in the Index Map.
+        let metadata_keys = data_map_key_bases
+            .iter()
+            .flatten()
+            .map(|data_map_key_base| format!("{data_map_key_base}-meta"))
+            .collect::<Vec<_>>();
+        let mut metadata_values = get_values::<_, Option<Vec<u8>>>(&mut conn, &metadata_keys)
+            .await?
+            .into_iter();
+
+        // Determine the number of chunks of each blob and the keys of all of those chunks.
+        let mut chunk_keys = Vec::new();
+        let num_chunks = data_map_key_bases
+            .into_iter()
+            .map(|data_map_key_base_opt| {
+                let data_map_key_base = match data_map_key_base_opt {
+                    Some(data_map_key_base) => data_map_key_base,
+                    None => return Ok(None),
+                };
+                let metadata = match metadata_values.next().flatten() {
+                    Some(data) => decode_metadata(&data)?,
+                    // TODO: If metadata chunk is missing, then delete this entry from the Index Map.
+                    None => return Ok(None),
+                };
+                let encoding = metadata_encoding(&metadata)?;
+                chunk_keys.extend(
+                    (0..metadata.num_chunks)
+                        .map(|chunk_num| format!("{}-{}", &data_map_key_base, chunk_num)),
+                );
+                Ok(Some((metadata.num_chunks, encoding)))
+            })
+            .collect::<Vec<Result<Option<(u64, BlobEncoding)>, StorageError>>>();
+
+        // Query the Data Map for the data chunks of all of the blobs.
+        let chunk_values: Vec<Option<Vec<u8>>> = get_values(&mut conn, &chunk_keys).await?;
+        let mut chunks = chunk_values.into_iter().zip(chunk_keys);
+
+        let results = digests
+            .into_iter()
+            .zip(num_chunks)
+            .map(|(digest, num_chunks)| {
+                let result = num_chunks.and_then(|num_chunks_opt| {
+                    let (num_chunks, encoding) = match num_chunks_opt {
+                        Some(num_chunks) => num_chunks,
+                        None => return Ok(None),
+                    };
+                    // Consume all of this blob's chunks before checking them so that the next
+                    // blob starts at its own first chunk.
+                    let mut blob_chunks = chunks
+                        .by_ref()
+                        .take(num_chunks as usize)
+                        .collect::<Vec<_>>();
+                    // A blob stored in a single chunk is returned without copying it.
+                    let content = if blob_chunks.len() == 1 {
+                        let (chunk_opt, key) = blob_chunks.pop().unwrap();
+                        match chunk_opt {
+                            Some(chunk) => Bytes::from(chunk),
+                            None => {
+                                return Err(StorageError::NotFound(format!(
+                                    "Missing data block: {key}"
+                                )))
+                            }
+                        }
+                    } else {
+                        let mut buffer = BytesMut::with_capacity(digest.size_bytes);
+                        for (chunk_opt, key) in blob_chunks {
+                            match chunk_opt {
+                                Some(chunk) => buffer.extend_from_slice(&chunk),
+                                None => {
+                                    return Err(StorageError::NotFound(format!(
+                                        "Missing data block: {key}"
+                                    )))
+                                }
+                            }
+                        }
+                        buffer.freeze()
+                    };
+                    decode_blob(content, encoding, digest.size_bytes).map(Some)
+                });
+                (digest, result)
+            })
+            .collect();
+        Ok(results)
+    }
+
+    /// Reports the encoding recorded in the blob's metadata chunk. Unlike `find_missing_blobs`,
+    /// this does not check that each of the blob's data chunks is present.
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
+    async fn stat(
         &self,
         instance: Instance,
         digest: Digest,
         _state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        let mut conn = self.conn.get_redis_connection(false).await?;
+
+        let index_map_key = format!(
+            "{}{}:index-sha256-{}-{}",
+            &self.prefix,
+            &instance.name,
+            digest.hex(),
+            digest.size_bytes
+        );
+        let uuid_opt: Option<String> = redis_query(
+            &mut conn,
+            "GET",
+            DRIVER_LABEL,
+            redis::cmd("GET").arg(index_map_key),
+        )
+        .await?;
+
+        let uuid = match uuid_opt {
+            Some(uuid) => uuid,
+            None => return Ok(None),
+        };
+
+        let metadata_key = format!("{}{}:data-{}-meta", &self.prefix, &instance.name, &uuid);
+        let metadata_opt: Option<Vec<u8>> = redis_query(
+            &mut conn,
+            "GET",
+            DRIVER_LABEL,
+            redis::cmd("GET").arg(&metadata_key),
+        )
+        .await?;
+
+        let metadata = match metadata_opt {
+            Some(data) => decode_metadata(&data)?,
+            None => return Ok(None),
+        };
+
+        Ok(Some(BlobStat {
+            size_bytes: digest.size_bytes,
+            last_accessed: None,
+            created: None,
+            encoding: metadata_encoding(&metadata)?,
+        }))
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
+    async fn begin_write_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
     ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
         let uuid = self.uuid_generator.generate_uuid();
         let base_key = format!("{}{}:data-{}", &self.prefix, &instance.name, uuid);
@@ -216,9 +415,59 @@ where
             prefix: self.prefix.clone(),
             digest,
             chunk_num: 0,
+            encoding: state.encoding,
             conn: self.conn.clone(),
         }))
     }
+
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        _state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let delete_futures = digests
+            .into_iter()
+            .map(|digest| Self::delete_digest(&instance, &self.conn, digest, &self.prefix))
+            .collect::<Vec<_>>();
+        let delete_responses = future::try_join_all(delete_futures).await?;
+        Ok(delete_responses.into_iter().flatten().collect())
+    }
+
+    /// Lists blobs by scanning the keys of the instance's Index Map. The cursor is the `SCAN`
+    /// cursor, and so pages may hold more or fewer than `page_size` digests.
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        _state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        let key_prefix = format!("{}{}:index-sha256-", &self.prefix, &instance.name);
+        // Use the primary, since a `SCAN` cursor is only valid on the server which returned it.
+        let mut conn = self.conn.get_redis_connection(true).await?;
+        let (keys, next_cursor) = scan_keys(
+            &mut conn,
+            DRIVER_LABEL,
+            &key_prefix,
+            cursor.as_deref(),
+            page_size,
+        )
+        .await?;
+        let digests = keys
+            .iter()
+            .filter_map(|key| {
+                let (hex_hash, size_bytes) = key.strip_prefix(&key_prefix)?.split_once('-')?;
+                Digest::new(hex_hash, size_bytes.parse().ok()?).ok()
+            })
+            .collect();
+        Ok(BlobPage {
+            digests,
+            next_cursor,
+        })
+    }
 }
 
 #[async_trait]
@@ -245,13 +494,21 @@ where
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "redis"))]
     async fn commit(mut self: Box<Self>) -> Result<(), StreamingWriteError> {
         let mut conn = self.conn.get_redis_connection(true).await?;
 
         // Write the metadata chunk with the total number of chunks written.
         let metadata_key = format!("{}-meta", &self.base_key);
+        let encoding = match self.encoding {
+            BlobEncoding::Raw => RedisBlobEncoding::Raw,
+            BlobEncoding::Zstd => RedisBlobEncoding::Zstd,
+            BlobEncoding::Encrypted => RedisBlobEncoding::Encrypted,
+        };
         let metadata = RedisMetadataChunk {
             num_chunks: self.chunk_num,
+            format_version: METADATA_FORMAT_VERSION,
+            encoding: encoding.into(),
         };
         let mut metadata_value = Vec::with_capacity(metadata.encoded_len());
         metadata
@@ -292,15 +549,19 @@ where
     C: ConnectionGetter + Clone + Send + Sync + 'static,
     UG: UuidGenerator + Send + Sync,
 {
+    /// Create a new `RedisStorage`. `find_missing_concurrency` bounds the number of digests
+    /// checked concurrently by a single `find_missing_blobs` call.
     pub async fn new(
         client: C,
         prefix: Option<String>,
         uuid_generator: UG,
+        find_missing_concurrency: NonZeroUsize,
     ) -> Result<Self, StorageError> {
         Ok(RedisStorage {
             conn: client,
             prefix: prefix.unwrap_or_else(|| "".to_owned()),
             uuid_generator,
+            find_missing_concurrency,
         })
     }
 
@@ -353,8 +614,7 @@ where
         )
         .await?;
         let metadata = match metadata_opt {
-            Some(data) => RedisMetadataChunk::decode(&data[..])
-                .map_err(|_| StorageError::Internal("Corrupt metadata chunk".to_owned()))?,
+            Some(data) => decode_metadata(&data)?,
             None => {
                 // TODO: If metadata chunk is missing, then delete this entry from the Index Map.
                 return Ok(Some(digest));
@@ -378,20 +638,256 @@ where
         // If execution reaches this point, then all blocks exist.
         Ok(None)
     }
+
+    /// Deletes the Index Map entry, metadata chunk, and data chunks for a digest. Returns the
+    /// digest if it was deleted, or None if there was no Index Map entry for it.
+    async fn delete_digest(
+        instance: &Instance,
+        connection_manager: &C,
+        digest: Digest,
+        prefix: &str,
+    ) -> Result<Option<Digest>, StorageError>
+    where
+        C: ConnectionGetter + Clone + Send + Sync,
+    {
+        let mut conn = connection_manager.get_redis_connection(false).await?;
+
+        let index_map_key = format!(
+            "{}{}:index-sha256-{}-{}",
+            prefix,
+            &instance.name,
+            digest.hex(),
+            digest.size_bytes
+        );
+
+        let uuid_opt: Option<String> = redis_query(
+            &mut conn,
+            "GET",
+            DRIVER_LABEL,
+            redis::cmd("GET").arg(&index_map_key),
+        )
+        .await?;
+
+        let uuid = match uuid_opt {
+            Some(uuid) => uuid,
+            None => return Ok(None),
+        };
+
+        let data_map_key_base = format!("{}{}:data-{}", prefix, &instance.name, uuid);
+        let metadata_key = format!("{}-meta", &data_map_key_base);
+        let metadata_opt: Option<Vec<u8>> = redis_query(
+            &mut conn,
+            "GET",
+            DRIVER_LABEL,
+            redis::cmd("GET").arg(&metadata_key),
+        )
+        .await?;
+        // If the metadata chunk is missing or corrupt, the data chunks cannot be located, but the
+        // Index Map entry and metadata chunk are still removed so the blob is no longer visible.
+        let num_chunks = metadata_opt
+            .and_then(|data| RedisMetadataChunk::decode(&data[..]).ok())
+            .map(|metadata| metadata.num_chunks)
+            .unwrap_or_default();
+
+        // Remove the Index Map entry first so that the blob stops being visible before its data
+        // is removed. Each key is deleted individually since the keys may live in different
+        // cluster slots.
+        let mut pipeline = redis::pipe();
+        pipeline.cmd("DEL").arg(&index_map_key);
+        pipeline.cmd("DEL").arg(&metadata_key);
+        for chunk_num in 0..num_chunks {
+            let key = format!("{}-{}", &data_map_key_base, chunk_num);
+            pipeline.cmd("DEL").arg(key);
+        }
+
+        let _: Vec<i64> = redis_pipeline(&mut conn, "DEL", DRIVER_LABEL, &pipeline).await?;
+
+        Ok(Some(digest))
+    }
+}
+
+/// Decode a metadata chunk, rejecting chunks written in a newer format than this driver reads.
+fn decode_metadata(data: &[u8]) -> Result<RedisMetadataChunk, StorageError> {
+    let metadata = RedisMetadataChunk::decode(data)
+        .map_err(|_| StorageError::Internal("Corrupt metadata chunk".to_owned()))?;
+    if metadata.format_version > METADATA_FORMAT_VERSION {
+        return Err(StorageError::Internal(format!(
+            "Unsupported metadata chunk format version {}",
+            metadata.format_version
+        )));
+    }
+    Ok(metadata)
+}
+
+/// The encoding of a blob's content recorded in its metadata chunk.
+fn metadata_encoding(metadata: &RedisMetadataChunk) -> Result<BlobEncoding, StorageError> {
+    match RedisBlobEncoding::from_i32(metadata.encoding) {
+        Some(RedisBlobEncoding::Raw) => Ok(BlobEncoding::Raw),
+        Some(RedisBlobEncoding::Zstd) => Ok(BlobEncoding::Zstd),
+        Some(RedisBlobEncoding::Encrypted) => Ok(BlobEncoding::Encrypted),
+        None => Err(StorageError::Internal(format!(
+            "Unsupported blob encoding {}",
+            metadata.encoding
+        ))),
+    }
+}
+
+/// Decode the stored content of a blob of `size_bytes` according to its recorded encoding.
+/// `Encrypted` content is returned as stored, since it is decrypted by the `EncryptingStorage`
+/// which wrote it.
+fn decode_stream(
+    stream: BoxReadStream,
+    encoding: BlobEncoding,
+    size_bytes: usize,
+) -> BoxReadStream {
+    match encoding {
+        BlobEncoding::Raw | BlobEncoding::Encrypted => stream,
+        BlobEncoding::Zstd => zstd_decode_stream(stream, size_bytes),
+    }
+}
+
+/// Like `decode_stream`, for a blob whose content has been read in full.
+fn decode_blob(
+    content: Bytes,
+    encoding: BlobEncoding,
+    size_bytes: usize,
+) -> Result<Bytes, StorageError> {
+    match encoding {
+        BlobEncoding::Raw | BlobEncoding::Encrypted => Ok(content),
+        BlobEncoding::Zstd => zstd::bulk::decompress(&content, size_bytes)
+            .map(Bytes::from)
+            .map_err(zstd_decode_error),
+    }
+}
+
+/// Decompress zstd-encoded content on the fly, refusing to produce more than `size_bytes` so
+/// that corrupt content cannot expand without bound.
+fn zstd_decode_stream(mut stream: BoxReadStream, size_bytes: usize) -> BoxReadStream {
+    Box::pin(async_stream::try_stream! {
+        let mut decoder = zstd::stream::write::Decoder::new(Vec::new()).map_err(zstd_decode_error)?;
+        let mut decoded_size = 0;
+        let mut finished = false;
+        while !finished {
+            match stream.next().await {
+                Some(chunk) => decoder.write_all(&chunk?).map_err(zstd_decode_error)?,
+                None => {
+                    decoder.flush().map_err(zstd_decode_error)?;
+                    finished = true;
+                }
+            }
+            let decoded = std::mem::take(decoder.get_mut());
+            decoded_size += decoded.len();
+            check_decoded_size(decoded_size, size_bytes)?;
+            if !decoded.is_empty() {
+                yield Bytes::from(decoded);
+            }
+        }
+    })
+}
+
+fn check_decoded_size(decoded_size: usize, size_bytes: usize) -> Result<(), StorageError> {
+    if decoded_size > size_bytes {
+        return Err(StorageError::Internal(format!(
+            "Decoded blob exceeds its size of {size_bytes} bytes"
+        )));
+    }
+    Ok(())
+}
+
+fn zstd_decode_error(err: std::io::Error) -> StorageError {
+    StorageError::Internal(format!("Corrupt zstd-encoded blob: {err}"))
+}
+
+/// Fetch the values of the given keys with a single pipeline of GET commands. MGET is not used
+/// since the keys may live in different cluster slots.
+async fn get_values<Conn, T>(conn: &mut Conn, keys: &[String]) -> Result<Vec<T>, StorageError>
+where
+    Conn: AsRedisConnectionMut + IdentifyRedisConnection + Send + Sync,
+    T: FromRedisValue,
+{
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pipeline = redis::pipe();
+    for key in keys {
+        pipeline.cmd("GET").arg(key);
+    }
+    redis_pipeline(conn, "GET", DRIVER_LABEL, &pipeline).await
+}
+
+/// Skip the first `read_offset` bytes of a blob's chunk stream, and end it after `read_limit`
+/// bytes. Chunks may have any size, so the chunks which are skipped are still fetched.
+fn apply_read_range(
+    stream: BoxReadStream,
+    read_offset: Option<usize>,
+    read_limit: Option<usize>,
+) -> BoxReadStream {
+    if read_offset.unwrap_or_default() == 0 && read_limit.is_none() {
+        return stream;
+    }
+    let stream = async_stream::stream! {
+      let mut stream = stream;
+      let mut to_skip = read_offset.unwrap_or_default();
+      let mut remaining = read_limit.unwrap_or(usize::MAX);
+      while remaining > 0 {
+        let chunk = match stream.next().await {
+          Some(Ok(chunk)) => chunk,
+          Some(Err(err)) => {
+            yield Err(err);
+            break;
+          }
+          None => break,
+        };
+        if to_skip >= chunk.len() {
+          to_skip -= chunk.len();
+          continue;
+        }
+        let end = chunk.len().min(to_skip.saturating_add(remaining));
+        let chunk = chunk.slice(to_skip..end);
+        to_skip = 0;
+        remaining -= chunk.len();
+        yield Ok(chunk);
+      }
+    };
+    Box::pin(stream)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use futures::{FutureExt, StreamExt};
     use prost::Message;
-    use redis::{Cmd, ToRedisArgs};
+    use redis::aio::ConnectionLike;
+    use redis::{
+        Cmd, ErrorKind as RedisErrorKind, Pipeline, RedisError, RedisFuture, ToRedisArgs, Value,
+    };
 
+    use super::super::common::ConnectionGetter;
     use super::super::testutil::{MockCommand, MockRedisConnection};
-    use super::RedisStorage;
+    use super::super::traits::{
+        AsRedisConnectionMut, IdentifyRedisConnection, RedisConnectionName,
+    };
+    use super::{RedisStorage, METADATA_FORMAT_VERSION};
     use crate::bytes::consolidate_stream;
-    use crate::driver::{BlobStorage, ChunkingStorage, DriverState, Instance, WriteDigestVerifier};
-    use crate::protos::toolchain::storage::redis::RedisMetadataChunk;
+    use crate::driver::{
+        BlobEncoding, BlobStorage, ChunkingStorage, DriverState, ExistenceCacheStorage, Instance,
+        MemoryStorage, MetricsMonitoredStorage, ReadDigestVerifier, ShardingStorage, StorageError,
+        WriteDigestVerifier,
+    };
+    use crate::protos::toolchain::storage::redis::{
+        BlobEncoding as RedisBlobEncoding, RedisMetadataChunk,
+    };
     use crate::testutil::TestData;
     use crate::uuid_gen::{DefaultUuidGenerator, UuidGenerator};
+    use crate::Digest;
 
     struct TestUuidGenerator;
 
@@ -402,7 +898,14 @@ mod tests {
     }
 
     fn metadata_value(num_chunks: u64) -> Vec<u8> {
-        let metadata = RedisMetadataChunk { num_chunks };
+        encode_metadata(RedisMetadataChunk {
+            num_chunks,
+            format_version: METADATA_FORMAT_VERSION,
+            encoding: RedisBlobEncoding::Raw.into(),
+        })
+    }
+
+    fn encode_metadata(metadata: RedisMetadataChunk) -> Vec<u8> {
         let mut buffer = Vec::with_capacity(metadata.encoded_len());
         metadata.encode(&mut buffer).unwrap();
         buffer
@@ -454,9 +957,14 @@ mod tests {
             ),
         ]);
 
-        let mut storage = RedisStorage::new(conn, None, DefaultUuidGenerator)
-            .await
-            .unwrap();
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -471,6 +979,128 @@ mod tests {
         assert_eq!(missing_digests, vec![content2.digest])
     }
 
+    /// Redis connection which answers every command with `nil` (i.e., every digest is missing)
+    /// after a short delay, and records the maximum number of commands in flight at once.
+    #[derive(Clone, Default)]
+    struct InFlightCountingConnection {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+        calls: Arc<AtomicUsize>,
+        fail_on_call: Option<usize>,
+    }
+
+    impl ConnectionLike for InFlightCountingConnection {
+        fn req_packed_command<'a>(&'a mut self, _cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+            async move {
+                let call = self.calls.fetch_add(1, Ordering::SeqCst);
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                if self.fail_on_call == Some(call) {
+                    return Err(RedisError::from((RedisErrorKind::ResponseError, "broken")));
+                }
+                Ok(Value::Nil)
+            }
+            .boxed()
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            _cmd: &'a Pipeline,
+            _offset: usize,
+            _count: usize,
+        ) -> RedisFuture<'a, Vec<Value>> {
+            unimplemented!("pipelines are not used by `find_missing_blobs` for missing digests")
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    #[async_trait]
+    impl ConnectionGetter for InFlightCountingConnection {
+        type Connection = InFlightCountingConnection;
+
+        async fn get_redis_connection(
+            &self,
+            _read_write: bool,
+        ) -> Result<Self::Connection, RedisError> {
+            Ok(self.clone())
+        }
+
+        async fn verify_connection(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    impl AsRedisConnectionMut for InFlightCountingConnection {
+        type Target = Self;
+
+        fn as_redis_conn_mut(&mut self) -> &mut Self::Target {
+            self
+        }
+    }
+
+    impl IdentifyRedisConnection for InFlightCountingConnection {
+        fn identify_redis_connection(&self) -> RedisConnectionName {
+            RedisConnectionName {
+                backend: "test".into(),
+                endpoint: "test",
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn find_missing_blobs_bounds_concurrency() {
+        let digests = (0..500)
+            .map(|i| Digest::of_bytes(&Bytes::from(format!("content-{i}"))).unwrap())
+            .collect::<Vec<_>>();
+
+        let conn = InFlightCountingConnection::default();
+        let storage = RedisStorage::new(
+            conn.clone(),
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(8).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let missing_digests = storage
+            .find_missing_blobs(
+                Instance::from("main"),
+                digests.clone(),
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing_digests, digests);
+        assert_eq!(conn.calls.load(Ordering::SeqCst), digests.len());
+        assert!(conn.max_in_flight.load(Ordering::SeqCst) <= 8);
+
+        // The first error fails the request without checking the remaining digests.
+        let conn = InFlightCountingConnection {
+            fail_on_call: Some(10),
+            ..InFlightCountingConnection::default()
+        };
+        let storage = RedisStorage::new(
+            conn.clone(),
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(8).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let result = storage
+            .find_missing_blobs(Instance::from("main"), digests, DriverState::default())
+            .await;
+        assert!(matches!(result, Err(StorageError::Internal(_))));
+        assert!(conn.calls.load(Ordering::SeqCst) < 50);
+    }
+
     #[tokio::test]
     async fn read_blob_success() {
         let content = TestData::from_static(b"xyzzy-grok");
@@ -501,9 +1131,14 @@ mod tests {
             MockCommand::new(get_cmd("main:data-abc123-0"), Ok(content.bytes.clone())),
         ]);
 
-        let mut storage = RedisStorage::new(conn, None, DefaultUuidGenerator)
-            .await
-            .unwrap();
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -524,22 +1159,443 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn write_blob() {
-        let content = TestData::from_static(b"xyzzy-grok");
+    async fn read_blob_multiple_chunks() {
+        let content = TestData::from_static(b"xyzzy-grok-foobar");
+        let chunks = [
+            content.bytes.slice(0..6),
+            content.bytes.slice(6..12),
+            content.bytes.slice(12..),
+        ];
+        let index_cmd = || {
+            get_cmd(format!(
+                "main:index-sha256-{}-{}",
+                content.digest.hex(),
+                content.digest.size_bytes
+            ))
+        };
 
-        let conn = MockRedisConnection::new(vec![
-            MockCommand::new(
-                set_cmd("main:data-abc123-0", content.bytes.slice(0..6).as_ref()),
-                Ok(""),
-            ),
-            MockCommand::new(
-                set_cmd("main:data-abc123-1", content.bytes.slice(6..).as_ref()),
-                Ok(""),
+        let mut commands = vec![
+            MockCommand::new(index_cmd(), Ok("abc123".to_owned())),
+            MockCommand::new(get_cmd("main:data-abc123-meta"), Ok(metadata_value(3))),
+            MockCommand::with_values(
+                redis::pipe()
+                    .cmd("EXISTS")
+                    .arg("main:data-abc123-0")
+                    .cmd("EXISTS")
+                    .arg("main:data-abc123-1")
+                    .cmd("EXISTS")
+                    .arg("main:data-abc123-2"),
+                Ok(vec!["1", "1", "1"]),
             ),
-            MockCommand::new(set_cmd("main:data-abc123-meta", metadata_value(2)), Ok("")),
-            MockCommand::new(
-                set_cmd(
-                    format!(
+            MockCommand::new(index_cmd(), Ok("abc123".to_owned())),
+            MockCommand::new(get_cmd("main:data-abc123-meta"), Ok(metadata_value(3))),
+        ];
+        commands.extend(chunks.iter().enumerate().map(|(i, chunk)| {
+            MockCommand::new(get_cmd(format!("main:data-abc123-{i}")), Ok(chunk.clone()))
+        }));
+        let conn = MockRedisConnection::new(commands);
+
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let stream = storage
+            .read_blob(
+                instance,
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let read_chunks = stream.collect::<Vec<_>>().await;
+        let read_chunks = read_chunks
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read_chunks, chunks);
+    }
+
+    /// The commands which read a blob stored as the given chunks, where `None` is a chunk which
+    /// was evicted after the blob's existence was checked.
+    fn read_blob_commands(content: &TestData, chunks: &[Option<Bytes>]) -> Vec<MockCommand> {
+        read_blob_commands_with_metadata(content, metadata_value(chunks.len() as u64), chunks)
+    }
+
+    fn read_blob_commands_with_metadata(
+        content: &TestData,
+        metadata: Vec<u8>,
+        chunks: &[Option<Bytes>],
+    ) -> Vec<MockCommand> {
+        let index_cmd = || {
+            get_cmd(format!(
+                "main:index-sha256-{}-{}",
+                content.digest.hex(),
+                content.digest.size_bytes
+            ))
+        };
+        let mut exists_pipeline = redis::pipe();
+        for i in 0..chunks.len() {
+            exists_pipeline
+                .cmd("EXISTS")
+                .arg(format!("main:data-abc123-{i}"));
+        }
+
+        let mut commands = vec![
+            MockCommand::new(index_cmd(), Ok("abc123".to_owned())),
+            MockCommand::new(get_cmd("main:data-abc123-meta"), Ok(metadata.clone())),
+            MockCommand::with_values(exists_pipeline, Ok(vec!["1"; chunks.len()])),
+            MockCommand::new(index_cmd(), Ok("abc123".to_owned())),
+            MockCommand::new(get_cmd("main:data-abc123-meta"), Ok(metadata)),
+        ];
+        commands.extend(chunks.iter().enumerate().map(|(i, chunk)| {
+            let value = match chunk {
+                Some(chunk) => Value::Data(chunk.to_vec()),
+                None => Value::Nil,
+            };
+            MockCommand::new(get_cmd(format!("main:data-abc123-{i}")), Ok(value))
+        }));
+        commands
+    }
+
+    #[tokio::test]
+    async fn read_blob_applies_offset_and_limit() {
+        let content = TestData::from_static(b"xyzzy-grok-foobar");
+        let chunks = [
+            Some(content.bytes.slice(0..6)),
+            Some(content.bytes.slice(6..12)),
+            Some(content.bytes.slice(12..)),
+        ];
+        let conn = MockRedisConnection::new(read_blob_commands(&content, &chunks));
+
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let stream = storage
+            .read_blob(
+                instance,
+                content.digest,
+                1024,
+                Some(4),
+                Some(10),
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let buffer = consolidate_stream(stream).await.unwrap();
+        assert_eq!(buffer, content.bytes.slice(4..14));
+    }
+
+    #[tokio::test]
+    async fn reads_decode_zstd_encoded_blobs() {
+        let content = TestData::from_static(b"xyzzy-grok-foobar");
+        let compressed = Bytes::from(zstd::encode_all(&content.bytes[..], 0).unwrap());
+        let split = compressed.len() / 2;
+        let chunks = [
+            Some(compressed.slice(..split)),
+            Some(compressed.slice(split..)),
+        ];
+        let metadata = encode_metadata(RedisMetadataChunk {
+            num_chunks: 2,
+            format_version: METADATA_FORMAT_VERSION,
+            encoding: RedisBlobEncoding::Zstd.into(),
+        });
+        let mut commands = read_blob_commands_with_metadata(&content, metadata.clone(), &chunks);
+        commands.extend([
+            MockCommand::with_values(
+                redis::pipe().cmd("GET").arg(format!(
+                    "main:index-sha256-{}-{}",
+                    content.digest.hex(),
+                    content.digest.size_bytes
+                )),
+                Ok(vec![Value::Data(b"abc123".to_vec())]),
+            ),
+            MockCommand::with_values(
+                redis::pipe().cmd("GET").arg("main:data-abc123-meta"),
+                Ok(vec![metadata]),
+            ),
+            MockCommand::with_values(
+                redis::pipe()
+                    .cmd("GET")
+                    .arg("main:data-abc123-0")
+                    .cmd("GET")
+                    .arg("main:data-abc123-1"),
+                Ok(vec![compressed.slice(..split), compressed.slice(split..)]),
+            ),
+        ]);
+        let conn = MockRedisConnection::new(commands);
+
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        // Ranges apply to the decoded content.
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                1024,
+                Some(4),
+                Some(10),
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let buffer = consolidate_stream(stream).await.unwrap();
+        assert_eq!(buffer, content.bytes.slice(4..14));
+
+        let results = storage
+            .read_blobs(instance, vec![content.digest], DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(results, vec![(content.digest, Ok(Some(content.bytes)))]);
+    }
+
+    #[tokio::test]
+    async fn sharded_reads_resume_on_replicas_when_chunks_are_evicted() {
+        let content = TestData::from_static(b"xyzzy-grok-foobar");
+        // The second chunk is evicted after the blob's existence has been checked, so the read
+        // fails with `NotFound` partway through the stream.
+        let chunks = [Some(content.bytes.slice(0..6)), None];
+        let conn = MockRedisConnection::new(read_blob_commands(&content, &chunks));
+        let redis_storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let replica_storage = Arc::new(MemoryStorage::new());
+
+        let instance = Instance::from("main");
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            vec![
+                (0, Box::new(redis_storage)),
+                (1, Box::new(replica_storage.clone())),
+            ],
+            2.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+        storage.ensure_instance(&instance, DriverState::default());
+
+        // The replica is missing the blob when the read starts, so that it is served by Redis...
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        // ...and only has it by the time that the evicted chunk is reached.
+        let mut attempt = replica_storage
+            .begin_write_blob(instance, content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let buffer = consolidate_stream(stream).await.unwrap();
+        assert_eq!(buffer, content.bytes);
+    }
+
+    /// The pipelined commands which read `content1` (stored as one chunk) and `content2` (stored
+    /// as two chunks) in a single batch, along with `missing`.
+    fn read_blobs_commands(
+        content1: &TestData,
+        content2: &TestData,
+        missing: &TestData,
+    ) -> Vec<MockCommand> {
+        let index_key = |content: &TestData| {
+            format!(
+                "main:index-sha256-{}-{}",
+                content.digest.hex(),
+                content.digest.size_bytes
+            )
+        };
+        vec![
+            MockCommand::with_values(
+                redis::pipe()
+                    .cmd("GET")
+                    .arg(index_key(content1))
+                    .cmd("GET")
+                    .arg(index_key(content2))
+                    .cmd("GET")
+                    .arg(index_key(missing)),
+                Ok(vec![
+                    Value::Data(b"abc123".to_vec()),
+                    Value::Data(b"def456".to_vec()),
+                    Value::Nil,
+                ]),
+            ),
+            MockCommand::with_values(
+                redis::pipe()
+                    .cmd("GET")
+                    .arg("main:data-abc123-meta")
+                    .cmd("GET")
+                    .arg("main:data-def456-meta"),
+                Ok(vec![metadata_value(1), metadata_value(2)]),
+            ),
+            MockCommand::with_values(
+                redis::pipe()
+                    .cmd("GET")
+                    .arg("main:data-abc123-0")
+                    .cmd("GET")
+                    .arg("main:data-def456-0")
+                    .cmd("GET")
+                    .arg("main:data-def456-1"),
+                Ok(vec![
+                    content1.bytes.clone(),
+                    content2.bytes.slice(0..5),
+                    content2.bytes.slice(5..),
+                ]),
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn read_blobs_uses_one_pipeline_per_lookup() {
+        let content1 = TestData::from_static(b"foobar");
+        let content2 = TestData::from_static(b"xyzzy-grok");
+        let missing = TestData::from_static(b"missing");
+
+        let conn = MockRedisConnection::new(read_blobs_commands(&content1, &content2, &missing));
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let results = storage
+            .read_blobs(
+                Instance::from("main"),
+                vec![content1.digest, content2.digest, missing.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                (content1.digest, Ok(Some(content1.bytes))),
+                (content2.digest, Ok(Some(content2.bytes))),
+                (missing.digest, Ok(None)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sharded_read_blobs_use_one_pipeline_per_lookup() {
+        let content1 = TestData::from_static(b"foobar");
+        let content2 = TestData::from_static(b"xyzzy-grok");
+        let missing = TestData::from_static(b"missing");
+
+        // The batch must pass through each of the wrapping drivers as a batch, since reading the
+        // blobs one at a time would not match the pipelined commands.
+        let conn = MockRedisConnection::new(read_blobs_commands(&content1, &content2, &missing));
+        let redis_storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let shard_storage = MetricsMonitoredStorage::new(
+            ExistenceCacheStorage::new(
+                NonZeroUsize::new(16).unwrap(),
+                0,
+                None,
+                ReadDigestVerifier::new(redis_storage, false),
+            ),
+            "redis",
+            "test",
+            true,
+        );
+        let storage = MetricsMonitoredStorage::new(
+            ShardingStorage::<usize>::new(
+                vec![(0, Box::new(shard_storage))],
+                1.try_into().unwrap(),
+                "test",
+                HashMap::default(),
+            ),
+            "sharding",
+            "test",
+            false,
+        );
+
+        let results = storage
+            .read_blobs(
+                Instance::from("main"),
+                vec![content1.digest, content2.digest, missing.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                (content1.digest, Ok(Some(content1.bytes))),
+                (content2.digest, Ok(Some(content2.bytes))),
+                (missing.digest, Ok(None)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn write_blob() {
+        let content = TestData::from_static(b"xyzzy-grok");
+
+        let conn = MockRedisConnection::new(vec![
+            MockCommand::new(
+                set_cmd("main:data-abc123-0", content.bytes.slice(0..6).as_ref()),
+                Ok(""),
+            ),
+            MockCommand::new(
+                set_cmd("main:data-abc123-1", content.bytes.slice(6..).as_ref()),
+                Ok(""),
+            ),
+            MockCommand::new(set_cmd("main:data-abc123-meta", metadata_value(2)), Ok("")),
+            MockCommand::new(
+                set_cmd(
+                    format!(
                         "main:index-sha256-{}-{}",
                         content.digest.hex(),
                         content.digest.size_bytes
@@ -550,9 +1606,14 @@ mod tests {
             ),
         ]);
 
-        let mut storage = RedisStorage::new(conn, None, TestUuidGenerator)
-            .await
-            .unwrap();
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            TestUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -565,6 +1626,88 @@ mod tests {
         attempt.commit().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn stat_reports_recorded_encoding() {
+        let legacy_content = TestData::from_static(b"foobar");
+        let content = TestData::from_static(b"xyzzy-grok");
+        let index_key = |content: &TestData| {
+            format!(
+                "main:index-sha256-{}-{}",
+                content.digest.hex(),
+                content.digest.size_bytes
+            )
+        };
+        let zstd_metadata = encode_metadata(RedisMetadataChunk {
+            num_chunks: 1,
+            format_version: METADATA_FORMAT_VERSION,
+            encoding: RedisBlobEncoding::Zstd.into(),
+        });
+
+        let conn = MockRedisConnection::new(vec![
+            // A blob written before the format version and encoding were recorded.
+            MockCommand::new(get_cmd(index_key(&legacy_content)), Ok("legacy".to_owned())),
+            MockCommand::new(
+                get_cmd("main:data-legacy-meta"),
+                Ok(encode_metadata(RedisMetadataChunk {
+                    num_chunks: 1,
+                    ..RedisMetadataChunk::default()
+                })),
+            ),
+            // A blob written with an encoding.
+            MockCommand::new(
+                set_cmd("main:data-abc123-0", content.bytes.as_ref()),
+                Ok(""),
+            ),
+            MockCommand::new(
+                set_cmd("main:data-abc123-meta", zstd_metadata.clone()),
+                Ok(""),
+            ),
+            MockCommand::new(set_cmd(index_key(&content), "abc123"), Ok("")),
+            MockCommand::new(get_cmd(index_key(&content)), Ok("abc123".to_owned())),
+            MockCommand::new(get_cmd("main:data-abc123-meta"), Ok(zstd_metadata)),
+        ]);
+
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            TestUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let stat = storage
+            .stat(
+                instance.clone(),
+                legacy_content.digest,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.encoding, BlobEncoding::Raw);
+
+        let state = DriverState {
+            encoding: BlobEncoding::Zstd,
+        };
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, state)
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stat = storage
+            .stat(instance, content.digest, DriverState::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.encoding, BlobEncoding::Zstd);
+        assert_eq!(stat.size_bytes, content.bytes.len());
+    }
+
     #[tokio::test]
     async fn prefixed_keys() {
         let content = TestData::from_static(b"xyzzy-grok");
@@ -630,9 +1773,14 @@ mod tests {
             MockCommand::new(get_cmd("foo-main:data-abc123-0"), Ok(content.bytes.clone())),
         ]);
 
-        let mut storage = RedisStorage::new(conn, Some("foo-".into()), TestUuidGenerator)
-            .await
-            .unwrap();
+        let storage = RedisStorage::new(
+            conn,
+            Some("foo-".into()),
+            TestUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -729,9 +1877,14 @@ mod tests {
             ),
         ]);
 
-        let mut storage = RedisStorage::new(conn, None, DefaultUuidGenerator)
-            .await
-            .unwrap();
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
@@ -772,6 +1925,132 @@ mod tests {
         assert!(read_result.is_none());
     }
 
+    #[tokio::test]
+    async fn delete_blobs() {
+        let content1 = TestData::from_static(b"foobar");
+        let content2 = TestData::from_static(b"xyzzy-grok");
+
+        let conn = MockRedisConnection::new(vec![
+            MockCommand::new(
+                get_cmd(format!(
+                    "main:index-sha256-{}-{}",
+                    content1.digest.hex(),
+                    content1.digest.size_bytes
+                )),
+                Ok("abc123".to_owned()),
+            ),
+            MockCommand::new(get_cmd("main:data-abc123-meta"), Ok(metadata_value(2))),
+            MockCommand::with_values(
+                redis::pipe()
+                    .cmd("DEL")
+                    .arg(format!(
+                        "main:index-sha256-{}-{}",
+                        content1.digest.hex(),
+                        content1.digest.size_bytes
+                    ))
+                    .cmd("DEL")
+                    .arg("main:data-abc123-meta")
+                    .cmd("DEL")
+                    .arg("main:data-abc123-0")
+                    .cmd("DEL")
+                    .arg("main:data-abc123-1"),
+                Ok(vec![Value::Int(1); 4]),
+            ),
+            MockCommand::new(
+                get_cmd(format!(
+                    "main:index-sha256-{}-{}",
+                    content2.digest.hex(),
+                    content2.digest.size_bytes
+                )),
+                Ok(Value::Nil),
+            ),
+        ]);
+
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let deleted_digests = storage
+            .delete_blobs(
+                instance,
+                vec![content1.digest, content2.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(deleted_digests, vec![content1.digest]);
+    }
+
+    #[tokio::test]
+    async fn list_blobs() {
+        let content1 = TestData::from_static(b"foobar");
+        let content2 = TestData::from_static(b"xyzzy-grok");
+        let index_key = |content: &TestData| {
+            format!(
+                "main:index-sha256-{}-{}",
+                content.digest.hex(),
+                content.digest.size_bytes
+            )
+        };
+        let scan_cmd = |cursor: &str| {
+            let mut cmd = redis::cmd("SCAN");
+            cmd.arg(cursor)
+                .arg("MATCH")
+                .arg("main:index-sha256-*")
+                .arg("COUNT")
+                .arg(100);
+            cmd
+        };
+
+        let conn = MockRedisConnection::new(vec![
+            MockCommand::new(
+                scan_cmd("0"),
+                Ok(Value::Bulk(vec![
+                    Value::Data(b"17".to_vec()),
+                    Value::Bulk(vec![Value::Data(index_key(&content1).into_bytes())]),
+                ])),
+            ),
+            MockCommand::new(
+                scan_cmd("17"),
+                Ok(Value::Bulk(vec![
+                    Value::Data(b"0".to_vec()),
+                    Value::Bulk(vec![Value::Data(index_key(&content2).into_bytes())]),
+                ])),
+            ),
+        ]);
+
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            DefaultUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
+        let instance = Instance::from("main");
+
+        let page = storage
+            .list_blobs(instance.clone(), None, 100, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(page.digests, vec![content1.digest]);
+        assert_eq!(page.next_cursor, Some("17".to_owned()));
+
+        let page = storage
+            .list_blobs(instance, page.next_cursor, 100, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(page.digests, vec![content2.digest]);
+        assert_eq!(page.next_cursor, None);
+    }
+
     #[tokio::test]
     async fn redis_chunking_functional_test() {
         let content = TestData::from_static(b"foobar");
@@ -795,9 +2074,14 @@ mod tests {
             ),
         ]);
 
-        let mut storage = RedisStorage::new(conn, None, TestUuidGenerator)
-            .await
-            .unwrap();
+        let storage = RedisStorage::new(
+            conn,
+            None,
+            TestUuidGenerator,
+            NonZeroUsize::new(16).unwrap(),
+        )
+        .await
+        .unwrap();
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
 
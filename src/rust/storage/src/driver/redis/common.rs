@@ -25,6 +25,12 @@ pub trait ConnectionGetter {
     /// Verify that this Redis connection is operating properly.
     async fn verify_connection(&self) -> Result<(), String>;
 
+    /// Eagerly establish and verify any connections managed by this type. Connection getters
+    /// which do not manage a pool of connections do not need to do anything.
+    async fn warmup(&self) -> Result<(), String> {
+        Ok(())
+    }
+
     /// Update any metrics gauges. This will be called whenever the metrics scraping
     /// admin endpoint is invoked by Prometheus.
     fn update_gauges(&self) {}
@@ -131,6 +137,26 @@ where
         Ok(())
     }
 
+    async fn warmup(&self) -> Result<(), String> {
+        self.primary_pool.warmup().await.map_err(|err| {
+            format!(
+                "Unable to warm up primary pool of backend `{}`: {}",
+                &self.name, err
+            )
+        })?;
+
+        if let Some(pool) = &self.read_only_pool {
+            pool.warmup().await.map_err(|err| {
+                format!(
+                    "Unable to warm up read-only pool of backend `{}`: {}",
+                    &self.name, err
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
     /// Update any gauge metrics generated by this `RedisBackend`.
     fn update_gauges(&self) {
         self.primary_pool.update_gauges();
@@ -161,6 +187,26 @@ where
     }
 }
 
+/// Emit the per-command latency and error metrics for a Redis command (or pipeline of commands)
+/// sent to `backend`. Unlike the per-request metrics, these are not broken down by driver or
+/// endpoint, and so give a view of which commands are slow or failing on each backend.
+fn emit_command_metrics(backend: &str, cmd_label: &'static str, elapsed: Duration, failed: bool) {
+    metrics::histogram!(
+        "toolchain_redis_command_duration_seconds",
+        elapsed,
+        "redis_backend" => backend.to_owned(),
+        "redis_cmd" => cmd_label,
+    );
+    if failed {
+        metrics::counter!(
+            "toolchain_redis_command_errors_total",
+            1,
+            "redis_backend" => backend.to_owned(),
+            "redis_cmd" => cmd_label,
+        );
+    }
+}
+
 /// Make a call to the Redis backend and emit metrics.
 fn send_one_query<'a, C, T>(
     conn: &'a mut C,
@@ -187,6 +233,7 @@ where
         let start_time = Instant::now();
         let result = cmd.query_async(conn.as_redis_conn_mut()).await;
         let elapsed = start_time.elapsed();
+        emit_command_metrics(&conn_name.backend, cmd_label, elapsed, result.is_err());
 
         metrics::histogram!(
             "toolchain_storage_redis_requests_duration_seconds",
@@ -250,6 +297,7 @@ where
         let start_time = Instant::now();
         let result = pipeline.query_async(conn.as_redis_conn_mut()).await;
         let elapsed = start_time.elapsed();
+        emit_command_metrics(&conn_name.backend, cmd_label, elapsed, result.is_err());
 
         metrics::histogram!(
             "toolchain_storage_redis_requests_duration_seconds",
@@ -297,6 +345,43 @@ where
     result.map_err(StorageError::from)
 }
 
+/// Scan a page of the keys which start with `key_prefix`, from `cursor` (or from the start if
+/// `None`). `count` is passed to `SCAN` as a hint of the number of keys to return. Returns the keys
+/// and the cursor from which to continue, or `None` once the scan is complete.
+///
+/// Note: `SCAN` only covers the server which `conn` is connected to.
+pub async fn scan_keys<C>(
+    conn: &mut C,
+    driver_label: &'static str,
+    key_prefix: &str,
+    cursor: Option<&str>,
+    count: usize,
+) -> Result<(Vec<String>, Option<String>), StorageError>
+where
+    C: AsRedisConnectionMut + IdentifyRedisConnection + Send + Sync,
+{
+    // Escape the characters which are special in `MATCH` patterns.
+    let mut pattern = String::with_capacity(key_prefix.len() + 1);
+    for c in key_prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('*');
+
+    let mut cmd = redis::cmd("SCAN");
+    cmd.arg(cursor.unwrap_or("0"))
+        .arg("MATCH")
+        .arg(pattern)
+        .arg("COUNT")
+        .arg(count.max(1));
+    let (next_cursor, keys): (String, Vec<String>) =
+        redis_query(conn, "SCAN", driver_label, &cmd).await?;
+    let next_cursor = (next_cursor != "0").then_some(next_cursor);
+    Ok((keys, next_cursor))
+}
+
 /// Wrap `redis::Client` to implement `ConnectionGetter` and `IdentifyRedisConnection`.
 #[derive(Clone)]
 pub struct ClientWrapper {
@@ -376,6 +461,7 @@ mod tests {
 
     use super::super::testutil::{MockCommand, MockRedisConnection};
     use super::redis_query;
+    use crate::testutil::{capture_metrics, histogram_samples};
 
     #[tokio::test]
     async fn retries_once_on_connection_drop() {
@@ -392,4 +478,25 @@ mod tests {
             .unwrap();
         assert_eq!(value, "bar");
     }
+
+    #[tokio::test]
+    async fn records_command_metrics() {
+        capture_metrics();
+
+        let mut conn = MockRedisConnection::new(vec![MockCommand::new(
+            redis::cmd("GET").arg("foo"),
+            Ok("bar"),
+        )])
+        .with_backend("command-metrics");
+        let value: String = redis_query(&mut conn, "GET", "test", redis::cmd("GET").arg("foo"))
+            .await
+            .unwrap();
+        assert_eq!(value, "bar");
+
+        let samples = histogram_samples(
+            "toolchain_redis_command_duration_seconds",
+            &[("redis_backend", "command-metrics"), ("redis_cmd", "GET")],
+        );
+        assert_eq!(samples.len(), 1);
+    }
 }
@@ -7,10 +7,10 @@ use futures::future;
 use itertools::Itertools;
 use redis::FromRedisValue;
 
-use super::common::{redis_query, ConnectionGetter};
+use super::common::{redis_query, scan_keys, ConnectionGetter};
 use crate::driver::redis::common::redis_pipeline;
-use crate::driver::small::SmallBlobStorage;
-use crate::driver::{DriverState, Instance, StorageError};
+use crate::driver::small::{check_blob_size, SmallBlobStorage};
+use crate::driver::{BlobPage, DriverState, Instance, StorageError};
 use crate::Digest;
 
 /// Label used for metrics.
@@ -22,6 +22,10 @@ const FIND_MISSING_BLOBS_BATCH_SIZE: usize = 1000;
 /// Stores blobs in Redis using a "direct" encoding where blobs are stored in a single key. This
 /// is intended for blobs of small size. Supports multiple instances.
 ///
+/// Blobs are read and written whole, and so this driver is meant to sit in a smaller tier of a
+/// `SizeSplitStorage`. Setting a `max_blob_size` which is at least that tier's size causes blobs
+/// which are misrouted to it (e.g. by a misconfigured size) to be rejected rather than buffered.
+///
 /// Keys have the format: INSTANCE-DIGEST_HASH-DIGEST_SIZE
 #[derive(Clone)]
 pub struct RedisDirectStorage<C>
@@ -30,6 +34,7 @@ where
 {
     conn: C,
     prefix: String,
+    max_blob_size: Option<usize>,
 }
 
 #[async_trait]
@@ -117,6 +122,10 @@ where
         content: Bytes,
         _state: DriverState,
     ) -> Result<(), StorageError> {
+        if let Some(max_blob_size) = self.max_blob_size {
+            check_blob_size(content.len(), max_blob_size)?;
+        }
+
         let mut conn = self.conn.get_redis_connection(true).await?;
         let key = Self::key_for_digest(&self.prefix, &instance, digest);
         redis_query::<_, ()>(
@@ -129,6 +138,45 @@ where
 
         Ok(())
     }
+
+    /// Lists blobs by scanning the instance's keys. The cursor is the `SCAN` cursor, and so pages
+    /// may hold more or fewer than `page_size` digests.
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        _state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        let key_prefix = format!("{}{}-", &self.prefix, &instance.name);
+        // Use the primary, since a `SCAN` cursor is only valid on the server which returned it.
+        let mut conn = self.conn.get_redis_connection(true).await?;
+        let (keys, next_cursor) = scan_keys(
+            &mut conn,
+            DRIVER_LABEL,
+            &key_prefix,
+            cursor.as_deref(),
+            page_size,
+        )
+        .await?;
+        // Keys of instances whose names extend this instance's name (e.g. `main-2` for `main`)
+        // also match the prefix, but fail to parse as digests.
+        let digests = keys
+            .iter()
+            .filter_map(|key| {
+                let (hex_hash, size_bytes) = key.strip_prefix(&key_prefix)?.split_once('-')?;
+                Digest::new(hex_hash, size_bytes.parse().ok()?).ok()
+            })
+            .collect();
+        Ok(BlobPage {
+            digests,
+            next_cursor,
+        })
+    }
+
+    fn max_blob_size(&self) -> Option<usize> {
+        self.max_blob_size
+    }
 }
 
 impl<C> RedisDirectStorage<C>
@@ -139,9 +187,16 @@ where
         Ok(RedisDirectStorage {
             conn,
             prefix: prefix.unwrap_or_else(|| "".to_owned()),
+            max_blob_size: None,
         })
     }
 
+    /// Reject writes of blobs larger than `max_blob_size` bytes with `ResourceExhausted`.
+    pub fn with_max_blob_size(mut self, max_blob_size: usize) -> Self {
+        self.max_blob_size = Some(max_blob_size);
+        self
+    }
+
     fn key_for_digest(prefix: &str, instance: &Instance, digest: Digest) -> String {
         format!(
             "{}{}-{}-{}",
@@ -159,7 +214,10 @@ mod tests {
     use rand::{Rng, RngCore};
     use redis::{Cmd, Value as RedisValue};
 
-    use crate::driver::{DriverState, Instance, RedisDirectStorage, SmallBlobStorage};
+    use crate::driver::{
+        BlobStorage, DriverState, Instance, RedisDirectStorage, SmallBlobStorage,
+        SmallBlobStorageAdapter, StorageError, StreamingWriteError,
+    };
     use crate::testutil::TestData;
     use crate::Digest;
 
@@ -359,4 +417,43 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn rejects_blobs_over_max_blob_size() {
+        let content = TestData::from_static(b"foobar");
+
+        // No Redis commands are expected: the blob is rejected before reaching Redis.
+        let conn = MockRedisConnection::new(vec![]);
+        let storage = RedisDirectStorage::new(conn, Some("foo-".to_owned()))
+            .await
+            .unwrap()
+            .with_max_blob_size(5);
+        let instance = Instance::from("main");
+
+        let err = storage
+            .write_blob(
+                instance.clone(),
+                content.digest,
+                content.bytes,
+                DriverState::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::ResourceExhausted(_)), "{err}");
+
+        // When adapted into a `BlobStorage`, the write is rejected when it begins.
+        let storage = SmallBlobStorageAdapter::new(storage);
+        let err = storage
+            .begin_write_blob(instance, content.digest, DriverState::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(
+                err,
+                StreamingWriteError::StorageError(StorageError::ResourceExhausted(_))
+            ),
+            "{err:?}"
+        );
+    }
 }
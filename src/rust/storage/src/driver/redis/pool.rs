@@ -9,7 +9,9 @@
 //! of a traditional connection pool under a lock. Moreover, it avoids the PING commands sent
 //! by `deadpool-redis` on every recycle of a connection between requests.
 
-use std::time::Instant;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use async_channel::{Receiver, Sender};
 use async_trait::async_trait;
@@ -17,12 +19,24 @@ use grpc_util::retry::retry_call;
 use redis::aio::ConnectionLike;
 use redis::{Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult, Value as RedisValue};
 use tokio::sync::oneshot::Sender as OneshotSender;
+use tokio::sync::watch;
+use tokio::time::Instant;
 
 use crate::driver::redis::common::{send_info_cmd, ConnectionGetter};
 use crate::driver::redis::traits::{
     AsRedisConnectionMut, IdentifyRedisConnection, RedisConnectionName,
 };
 
+/// Default interval after which an idle pooled connection is sent a PING to verify that it is
+/// still alive.
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum time to wait for pooled connections to be established by `warmup`.
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum time to wait for a response to a health-check PING.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Async Redis connection pool
 ///
 /// This is an async-first connection pool that will drive N connections to Redis. It exposes
@@ -33,6 +47,34 @@ pub struct AsyncRedisConnectionPool {
     requests_sender: Sender<RedisRequest>,
     conn_name: String,
     conn_endpoint: &'static str,
+    num_connections: usize,
+    min_healthy_connections: usize,
+    state: Arc<PoolState>,
+}
+
+/// State shared between the pool and the tasks driving its connections.
+struct PoolState {
+    /// Number of pooled connections which are currently connected and responded to a PING.
+    healthy_connections: watch::Sender<usize>,
+
+    /// Number of pooled connections which are currently executing a request.
+    in_use_connections: AtomicUsize,
+}
+
+/// Marks a pooled connection as in-use until dropped.
+struct InUseGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InUseGuard<'a> {
+    fn new(in_use_connections: &'a AtomicUsize) -> Self {
+        in_use_connections.fetch_add(1, Ordering::SeqCst);
+        InUseGuard(in_use_connections)
+    }
+}
+
+impl<'a> Drop for InUseGuard<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 enum RedisRequestCmd {
@@ -74,6 +116,23 @@ enum EventLoopStepResult {
     Ok,
     ChannelRecvErr,
     Disconnected,
+    Idle,
+}
+
+/// Send a PING to Redis to verify that the connection is alive.
+async fn ping_connection<C>(conn: &mut C) -> Result<(), RedisError>
+where
+    C: ConnectionLike,
+{
+    let ping_cmd = redis::cmd("PING");
+    match tokio::time::timeout(PING_TIMEOUT, ping_cmd.query_async::<_, ()>(conn)).await {
+        Ok(result) => result,
+        Err(_) => Err(RedisError::from((
+            ErrorKind::IoError,
+            "timed-out",
+            "Timed out while sending PING command to server.".to_string(),
+        ))),
+    }
 }
 
 /// Evaluate a single step of the event loop.
@@ -82,17 +141,24 @@ async fn redis_event_loop_step<C>(
     requests_receiver: &Receiver<RedisRequest>,
     conn_name: String,
     conn_endpoint: &'static str,
+    health_check_interval: Duration,
+    state: &PoolState,
 ) -> EventLoopStepResult
 where
     C: ConnectionLike,
 {
-    let request = match requests_receiver.recv().await {
-        Ok(r) => r,
-        Err(_) => return EventLoopStepResult::ChannelRecvErr,
+    let request = match tokio::time::timeout(health_check_interval, requests_receiver.recv()).await
+    {
+        Ok(Ok(r)) => r,
+        Ok(Err(_)) => return EventLoopStepResult::ChannelRecvErr,
+        Err(_) => return EventLoopStepResult::Idle,
     };
 
+    let _in_use_guard = InUseGuard::new(&state.in_use_connections);
     let start_time = Instant::now();
 
+    // Note: This only measures the time the request spent waiting for a connection to become
+    // available and not the time spent executing the request.
     let queued_duration = start_time.duration_since(request.creation_time);
 
     metrics::histogram!(
@@ -172,21 +238,31 @@ where
 }
 
 /// Drives a single connection to Redis.
+///
+/// Each new connection is verified with a PING before it serves requests. Connections which sit
+/// idle for `health_check_interval` are PING'ed again and are evicted (and reconnected) if the
+/// PING fails.
 async fn redis_connection_task<CG>(
     conn_getter: CG,
     requests_receiver: Receiver<RedisRequest>,
     conn_name: String,
     conn_endpoint: &'static str,
+    health_check_interval: Duration,
+    state: Arc<PoolState>,
 ) -> Result<(), RedisError>
 where
     CG: ConnectionGetter + Clone + Send + Sync + 'static,
 {
     'CONN: loop {
-        // Connect to Redis.
+        // Connect to Redis and verify the connection with a PING.
         let mut conn = match retry_call(
             conn_getter.clone(),
-            |conn_getter| async move { conn_getter.get_redis_connection(true).await },
-            |err| {
+            |conn_getter| async move {
+                let mut conn = conn_getter.get_redis_connection(true).await?;
+                ping_connection(conn.as_redis_conn_mut()).await?;
+                Ok(conn)
+            },
+            |err: &RedisError| {
                 metrics::counter!(
                     "toolchain_storage_redis_connect_failed_total",
                     1,
@@ -208,12 +284,37 @@ where
             }
         };
         let conn = conn.as_redis_conn_mut();
+        state.healthy_connections.send_modify(|count| *count += 1);
 
         loop {
-            match redis_event_loop_step(conn, &requests_receiver, conn_name.clone(), conn_endpoint)
-                .await
+            match redis_event_loop_step(
+                conn,
+                &requests_receiver,
+                conn_name.clone(),
+                conn_endpoint,
+                health_check_interval,
+                &state,
+            )
+            .await
             {
                 EventLoopStepResult::Ok => (),
+                EventLoopStepResult::Idle => {
+                    if let Err(err) = ping_connection(conn).await {
+                        log::warn!(
+                            "Redis health check failed: conn_name={}, conn_endpoint={}: {err}",
+                            &conn_name,
+                            conn_endpoint
+                        );
+                        metrics::counter!(
+                            "toolchain_storage_redis_health_check_failed_total",
+                            1,
+                            "redis_backend" => conn_name.clone(),
+                            "redis_endpoint" => conn_endpoint,
+                        );
+                        state.healthy_connections.send_modify(|count| *count -= 1);
+                        continue 'CONN;
+                    }
+                }
                 EventLoopStepResult::Disconnected => {
                     log::debug!(
                         "Redis disconnected: conn_name={}, conn_endpoint={}",
@@ -227,9 +328,13 @@ where
                         "redis_backend" => conn_name.clone(),
                         "redis_endpoint" => conn_endpoint,
                     );
+                    state.healthy_connections.send_modify(|count| *count -= 1);
                     continue 'CONN;
                 }
-                EventLoopStepResult::ChannelRecvErr => return Ok(()),
+                EventLoopStepResult::ChannelRecvErr => {
+                    state.healthy_connections.send_modify(|count| *count -= 1);
+                    return Ok(());
+                }
             }
         }
     }
@@ -242,21 +347,51 @@ impl AsyncRedisConnectionPool {
         conn_name: String,
         conn_endpoint: &'static str,
     ) -> Self
+    where
+        CG: ConnectionGetter + Clone + Send + Sync + 'static,
+    {
+        Self::with_health_checks(
+            conn_getter,
+            num_connections,
+            1,
+            DEFAULT_HEALTH_CHECK_INTERVAL,
+            conn_name,
+            conn_endpoint,
+        )
+    }
+
+    /// Create a pool which requires at least `min_healthy_connections` connections to be
+    /// established by `warmup` and which PINGs connections idle for `health_check_interval`.
+    pub fn with_health_checks<CG>(
+        conn_getter: CG,
+        num_connections: usize,
+        min_healthy_connections: usize,
+        health_check_interval: Duration,
+        conn_name: String,
+        conn_endpoint: &'static str,
+    ) -> Self
     where
         CG: ConnectionGetter + Clone + Send + Sync + 'static,
     {
         let (requests_sender, requests_receiver) = async_channel::bounded(3 * num_connections);
+        let state = Arc::new(PoolState {
+            healthy_connections: watch::channel(0).0,
+            in_use_connections: AtomicUsize::new(0),
+        });
 
         for i in 0..num_connections {
             let conn_getter2 = conn_getter.clone();
             let requests_receiver2 = requests_receiver.clone();
             let conn_name2 = conn_name.clone();
+            let state2 = state.clone();
             tokio::spawn(async move {
                 let result = redis_connection_task(
                     conn_getter2,
                     requests_receiver2,
                     conn_name2,
                     conn_endpoint,
+                    health_check_interval,
+                    state2,
                 )
                 .await;
                 if let Err(err) = &result {
@@ -269,7 +404,57 @@ impl AsyncRedisConnectionPool {
             requests_sender,
             conn_name,
             conn_endpoint,
+            num_connections,
+            min_healthy_connections,
+            state,
+        }
+    }
+
+    /// Number of pooled connections which are currently connected and healthy.
+    pub fn healthy_connections(&self) -> usize {
+        *self.state.healthy_connections.borrow()
+    }
+
+    /// Number of pooled connections which are currently executing a request.
+    pub fn in_use_connections(&self) -> usize {
+        self.state.in_use_connections.load(Ordering::SeqCst)
+    }
+
+    /// Number of healthy pooled connections which are waiting for a request.
+    pub fn idle_connections(&self) -> usize {
+        self.healthy_connections()
+            .saturating_sub(self.in_use_connections())
+    }
+
+    /// Wait for the pooled connections to be established and verified with a PING. Fails if
+    /// fewer than the configured minimum number of connections are healthy after a timeout.
+    pub async fn warmup(&self) -> Result<(), String> {
+        let mut receiver = self.state.healthy_connections.subscribe();
+        let wait_for_all_connections = async {
+            while *receiver.borrow_and_update() < self.num_connections {
+                if receiver.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        let _ = tokio::time::timeout(WARMUP_TIMEOUT, wait_for_all_connections).await;
+
+        let healthy_connections = self.healthy_connections();
+        if healthy_connections < self.min_healthy_connections {
+            return Err(format!(
+                "Only {healthy_connections} of {} connections are healthy (minimum is {})",
+                self.num_connections, self.min_healthy_connections
+            ));
         }
+        if healthy_connections < self.num_connections {
+            log::warn!(
+                "Redis pool warmup incomplete: conn_name={}, conn_endpoint={}, healthy={healthy_connections}, total={}",
+                &self.conn_name,
+                self.conn_endpoint,
+                self.num_connections
+            );
+        }
+        Ok(())
     }
 }
 
@@ -293,6 +478,31 @@ impl ConnectionGetter for AsyncRedisConnectionPool {
             .await
             .map_err(|err| format!("Redis error: {err}"))
     }
+
+    async fn warmup(&self) -> Result<(), String> {
+        AsyncRedisConnectionPool::warmup(self).await
+    }
+
+    fn update_gauges(&self) {
+        metrics::gauge!(
+            "toolchain_redis_pool_healthy_connections",
+            self.healthy_connections() as f64,
+            "redis_backend" => self.conn_name.clone(),
+            "redis_endpoint" => self.conn_endpoint,
+        );
+        metrics::gauge!(
+            "toolchain_redis_pool_in_use",
+            self.in_use_connections() as f64,
+            "redis_backend" => self.conn_name.clone(),
+            "redis_endpoint" => self.conn_endpoint,
+        );
+        metrics::gauge!(
+            "toolchain_redis_pool_idle",
+            self.idle_connections() as f64,
+            "redis_backend" => self.conn_name.clone(),
+            "redis_endpoint" => self.conn_endpoint,
+        );
+    }
 }
 
 impl ConnectionLike for AsyncRedisConnectionPool {
@@ -385,10 +595,22 @@ impl IdentifyRedisConnection for AsyncRedisConnectionPool {
 
 #[cfg(test)]
 mod tests {
-    use redis::Cmd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use redis::aio::ConnectionLike;
+    use redis::{Cmd, Pipeline, RedisError, RedisFuture, Value as RedisValue};
+    use tokio::sync::Semaphore;
 
+    use crate::driver::redis::common::ConnectionGetter;
     use crate::driver::redis::pool::AsyncRedisConnectionPool;
     use crate::driver::redis::testutil::{MockCommand, MockRedisConnection};
+    use crate::driver::redis::traits::{
+        AsRedisConnectionMut, IdentifyRedisConnection, RedisConnectionName,
+    };
+    use crate::testutil::{capture_metrics, histogram_samples};
 
     fn exists_cmd(key: impl AsRef<str>) -> Cmd {
         let mut cmd = redis::cmd("EXISTS");
@@ -396,13 +618,178 @@ mod tests {
         cmd
     }
 
+    fn ping_cmd() -> MockCommand {
+        MockCommand::new(redis::cmd("PING"), Ok("PONG"))
+    }
+
+    /// Counts the number of connections opened via the wrapped `MockRedisConnection`.
+    #[derive(Clone)]
+    struct CountingConnectionGetter {
+        conn: MockRedisConnection,
+        count: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ConnectionGetter for CountingConnectionGetter {
+        type Connection = MockRedisConnection;
+
+        async fn get_redis_connection(
+            &self,
+            read_write: bool,
+        ) -> Result<Self::Connection, RedisError> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.conn.get_redis_connection(read_write).await
+        }
+
+        async fn verify_connection(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    /// Fake Redis connection which answers PING immediately but blocks all other commands until
+    /// a permit is added to the `release` semaphore.
+    #[derive(Clone)]
+    struct BlockingConnection {
+        release: Arc<Semaphore>,
+    }
+
+    impl ConnectionLike for BlockingConnection {
+        fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, RedisValue> {
+            let is_ping = cmd.get_packed_command() == redis::cmd("PING").get_packed_command();
+            let release = self.release.clone();
+            Box::pin(async move {
+                if !is_ping {
+                    release.acquire().await.unwrap().forget();
+                }
+                Ok(RedisValue::Int(1))
+            })
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            _cmd: &'a Pipeline,
+            _offset: usize,
+            _count: usize,
+        ) -> RedisFuture<'a, Vec<RedisValue>> {
+            unimplemented!()
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    #[async_trait]
+    impl ConnectionGetter for BlockingConnection {
+        type Connection = Self;
+
+        async fn get_redis_connection(
+            &self,
+            _read_write: bool,
+        ) -> Result<Self::Connection, RedisError> {
+            Ok(self.clone())
+        }
+
+        async fn verify_connection(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    impl AsRedisConnectionMut for BlockingConnection {
+        type Target = Self;
+
+        fn as_redis_conn_mut(&mut self) -> &mut Self::Target {
+            self
+        }
+    }
+
+    impl IdentifyRedisConnection for BlockingConnection {
+        fn identify_redis_connection(&self) -> RedisConnectionName {
+            RedisConnectionName {
+                backend: "test".into(),
+                endpoint: "test",
+            }
+        }
+    }
+
     #[tokio::test]
     async fn basic_async_pool_end_to_end() {
-        let conn = MockRedisConnection::new(vec![MockCommand::new(exists_cmd("xyzzy"), Ok("1"))]);
+        let conn = MockRedisConnection::new(vec![
+            ping_cmd(),
+            MockCommand::new(exists_cmd("xyzzy"), Ok("1")),
+        ]);
 
         let mut pool = AsyncRedisConnectionPool::new(conn, 1, "test".to_string(), "test");
+        pool.warmup().await.unwrap();
 
         let result: bool = exists_cmd("xyzzy").query_async(&mut pool).await.unwrap();
         assert!(result);
     }
+
+    #[tokio::test]
+    async fn warmup_opens_all_connections() {
+        let conn_getter = CountingConnectionGetter {
+            conn: MockRedisConnection::new(vec![ping_cmd(), ping_cmd(), ping_cmd()]),
+            count: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let pool = AsyncRedisConnectionPool::with_health_checks(
+            conn_getter.clone(),
+            3,
+            3,
+            Duration::from_secs(30),
+            "test".to_string(),
+            "test",
+        );
+        pool.warmup().await.unwrap();
+
+        assert_eq!(pool.healthy_connections(), 3);
+        assert_eq!(conn_getter.count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn records_acquire_wait_when_saturated() {
+        capture_metrics();
+
+        let release = Arc::new(Semaphore::new(0));
+        let pool = AsyncRedisConnectionPool::new(
+            BlockingConnection {
+                release: release.clone(),
+            },
+            1,
+            "saturated".to_string(),
+            "test",
+        );
+        pool.warmup().await.unwrap();
+
+        // Hold the only connection in the pool.
+        let mut pool1 = pool.clone();
+        let first =
+            tokio::spawn(
+                async move { exists_cmd("first").query_async::<_, bool>(&mut pool1).await },
+            );
+        while pool.in_use_connections() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(pool.idle_connections(), 0);
+
+        // The next request must wait for the connection to be released.
+        let mut pool2 = pool.clone();
+        let second = tokio::spawn(async move {
+            exists_cmd("second")
+                .query_async::<_, bool>(&mut pool2)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        release.add_permits(2);
+        assert!(first.await.unwrap().unwrap());
+        assert!(second.await.unwrap().unwrap());
+
+        let samples = histogram_samples(
+            "toolchain_storage_redis_request_queued_duration_seconds",
+            &[("redis_backend", "saturated")],
+        );
+        assert_eq!(samples.len(), 2);
+        assert!(samples.iter().any(|wait| *wait >= 0.05));
+    }
 }
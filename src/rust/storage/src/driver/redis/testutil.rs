@@ -128,14 +128,23 @@ impl MockCommand {
 #[derive(Clone)]
 pub struct MockRedisConnection {
     commands: Arc<Mutex<VecDeque<MockCommand>>>,
+    backend: String,
 }
 
 impl MockRedisConnection {
     pub fn new(commands: Vec<MockCommand>) -> Self {
         MockRedisConnection {
             commands: Arc::new(Mutex::new(VecDeque::from(commands))),
+            backend: "test".into(),
         }
     }
+
+    /// Identify the connection as belonging to `backend` (instead of `test`), e.g. so that a
+    /// test can find its own metrics.
+    pub fn with_backend(mut self, backend: &str) -> Self {
+        self.backend = backend.into();
+        self
+    }
 }
 
 impl ConnectionLike for MockRedisConnection {
@@ -229,7 +238,7 @@ impl AsRedisConnectionMut for MockRedisConnection {
 impl IdentifyRedisConnection for MockRedisConnection {
     fn identify_redis_connection(&self) -> RedisConnectionName {
         RedisConnectionName {
-            backend: "test".into(),
+            backend: self.backend.clone(),
             endpoint: "test",
         }
     }
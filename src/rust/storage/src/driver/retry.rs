@@ -0,0 +1,369 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use rand::{thread_rng, Rng};
+
+use crate::driver::{
+    BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance, StorageError,
+    StreamingWriteError, WriteAttemptOps,
+};
+use crate::Digest;
+
+/// Upper bound on the delay between two attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A `BlobStorage` that retries operations against an underlying storage driver which fail with
+/// `StorageError::Unavailable`, using exponential back-off with jitter between attempts.
+///
+/// Only the initial `read_blob` call is retried; errors on the returned stream are passed through
+/// since the stream cannot be replayed. Other errors (e.g., `Internal` or `AlreadyExists`) are
+/// never retried.
+pub struct RetryingStorage<S> {
+    inner: S,
+    max_attempts: NonZeroU32,
+    base_delay: Duration,
+    purpose: &'static str,
+}
+
+impl<S> RetryingStorage<S> {
+    pub fn new(
+        inner: S,
+        max_attempts: NonZeroU32,
+        base_delay: Duration,
+        purpose: &'static str,
+    ) -> Self {
+        RetryingStorage {
+            inner,
+            max_attempts,
+            base_delay,
+            purpose,
+        }
+    }
+
+    /// The delay before the given retry (starting at 1): a random multiple of `base_delay` of up
+    /// to 2^retry, capped at `MAX_RETRY_DELAY`.
+    fn retry_delay(&self, retry: u32) -> Duration {
+        let multiplier = thread_rng().gen_range(0..=2_u32.saturating_pow(retry));
+        self.base_delay
+            .saturating_mul(multiplier)
+            .min(MAX_RETRY_DELAY)
+    }
+
+    async fn retry<T, E, F, Fut>(&self, operation: &'static str, f: F) -> Result<T, E>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: RetryableError,
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(err) if err.is_retryable() && attempt < self.max_attempts.get() => {
+                    metrics::counter!(
+                        "toolchain_storage_retries_total",
+                        1,
+                        "operation" => operation,
+                        "purpose" => self.purpose,
+                    );
+                    tokio::time::sleep(self.retry_delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+trait RetryableError {
+    fn is_retryable(&self) -> bool;
+}
+
+impl RetryableError for StorageError {
+    fn is_retryable(&self) -> bool {
+        matches!(self, StorageError::Unavailable(_))
+    }
+}
+
+impl RetryableError for StreamingWriteError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            StreamingWriteError::StorageError(err) => err.is_retryable(),
+            StreamingWriteError::AlreadyExists => false,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> BlobStorage for RetryingStorage<S>
+where
+    S: BlobStorage + Send + Sync + 'static,
+{
+    async fn find_missing_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.retry("find_missing_blobs", || {
+            self.inner
+                .find_missing_blobs(instance.clone(), digests.clone(), state.clone())
+        })
+        .await
+    }
+
+    async fn read_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        max_batch_size: usize,
+        read_offset: Option<usize>,
+        read_limit: Option<usize>,
+        state: DriverState,
+    ) -> Result<Option<BoxReadStream>, StorageError> {
+        self.retry("read", || {
+            self.inner.read_blob(
+                instance.clone(),
+                digest,
+                max_batch_size,
+                read_offset,
+                read_limit,
+                state.clone(),
+            )
+        })
+        .await
+    }
+
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        self.retry("read_blobs", || {
+            self.inner
+                .read_blobs(instance.clone(), digests.clone(), state.clone())
+        })
+        .await
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.retry("stat", || {
+            self.inner.stat(instance.clone(), digest, state.clone())
+        })
+        .await
+    }
+
+    async fn begin_write_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        self.retry("write", || {
+            self.inner
+                .begin_write_blob(instance.clone(), digest, state.clone())
+        })
+        .await
+    }
+
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.inner.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.inner.list_recent_blobs(instance, limit, state).await
+    }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.inner
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.inner.ensure_instance(instance, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+
+    use super::RetryingStorage;
+    use crate::driver::{
+        AlwaysErrorsStorage, BlobStorage, DriverState, Instance, SmallBlobStorage,
+        SmallBlobStorageAdapter, StorageError,
+    };
+    use crate::testutil::TestData;
+    use crate::Digest;
+
+    /// Fails the first `failures` calls with `error`, and then switches to `AlwaysErrorsStorage`
+    /// (for reads and writes) or reports all digests as present (for `find_missing_blobs`).
+    struct FlakyStorage {
+        calls: Arc<AtomicUsize>,
+        failures: usize,
+        error: StorageError,
+    }
+
+    impl FlakyStorage {
+        fn check_call(&self) -> Result<(), StorageError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                Err(self.error.clone())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SmallBlobStorage for FlakyStorage {
+        async fn find_missing_blobs(
+            &self,
+            _instance: Instance,
+            _digests: Vec<Digest>,
+            _state: DriverState,
+        ) -> Result<Vec<Digest>, StorageError> {
+            self.check_call()?;
+            Ok(Vec::new())
+        }
+
+        async fn read_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            state: DriverState,
+        ) -> Result<Option<Bytes>, StorageError> {
+            self.check_call()?;
+            AlwaysErrorsStorage.read_blob(instance, digest, state).await
+        }
+
+        async fn write_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            content: Bytes,
+            state: DriverState,
+        ) -> Result<(), StorageError> {
+            self.check_call()?;
+            AlwaysErrorsStorage
+                .write_blob(instance, digest, content, state)
+                .await
+        }
+    }
+
+    fn make_storage(
+        failures: usize,
+        error: StorageError,
+    ) -> (
+        Arc<AtomicUsize>,
+        RetryingStorage<SmallBlobStorageAdapter<FlakyStorage>>,
+    ) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let storage = RetryingStorage::new(
+            SmallBlobStorageAdapter::new(FlakyStorage {
+                calls: calls.clone(),
+                failures,
+                error,
+            }),
+            NonZeroU32::new(3).unwrap(),
+            Duration::from_millis(1),
+            "test",
+        );
+        (calls, storage)
+    }
+
+    #[tokio::test]
+    async fn retries_unavailable_errors() {
+        let instance = Instance::from("main");
+        let content = TestData::from_static(b"foobar");
+
+        // Succeeds on the third attempt.
+        let (calls, storage) = make_storage(2, StorageError::Unavailable("down".to_owned()));
+        let missing_digests = storage
+            .find_missing_blobs(
+                instance.clone(),
+                vec![content.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert!(missing_digests.is_empty());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        // Gives up after the maximum number of attempts.
+        let (calls, storage) = make_storage(3, StorageError::Unavailable("down".to_owned()));
+        let err = storage
+            .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::Unavailable(_)));
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        let instance = Instance::from("main");
+        let content = TestData::from_static(b"foobar");
+
+        let (calls, storage) = make_storage(2, StorageError::Internal("broken".to_owned()));
+        let err = storage
+            .find_missing_blobs(
+                instance.clone(),
+                vec![content.digest],
+                DriverState::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::Internal(_)));
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        // Once the storage switches over to `AlwaysErrorsStorage`, its `Internal` errors are
+        // not retried either.
+        let (calls, storage) = make_storage(1, StorageError::Unavailable("down".to_owned()));
+        let result = storage
+            .read_blob(
+                instance,
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(StorageError::Internal(_))));
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+}
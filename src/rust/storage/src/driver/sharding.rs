@@ -5,31 +5,116 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
 
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use bytes::Bytes;
 use consistent_hash_ring::{Ring, RingBuilder};
 use futures::stream::FuturesUnordered;
 use futures::{future, FutureExt, StreamExt};
+use parking_lot::Mutex;
+use tokio::task::JoinSet;
 
+use crate::bytes::consolidate_stream;
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    list_blobs_in_turn, BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance,
+    StorageError, StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
 type BoxBlobStorage = Box<dyn BlobStorage + Send + Sync + 'static>;
+type SharedBlobStorage = Arc<dyn BlobStorage + Send + Sync + 'static>;
 
 /// Number of virtual nodes in the hash ring used for sharding.
 const RING_SIZE: usize = 10240;
 
+/// Maximum size of each chunk read from a shard while copying a blob during `rebalance`.
+const REBALANCE_READ_BATCH_SIZE: usize = 1024 * 1024;
+
 /// Shards digests over N storage shards.
 pub struct ShardingStorage<T> {
-    ring: Ring<T>,
-    shard_key_to_storage: HashMap<T, BoxBlobStorage>,
+    shards: ArcSwap<ShardMap<T>>,
     key_replicas: NonZeroUsize,
     purpose: &'static str,
-    _shard_descriptions: HashMap<T, String>,
+    write_timeout: Option<Duration>,
+    /// The instances set up by `ensure_instance`, to set up on shards added by `reconfigure`.
+    instances: Mutex<Vec<(Instance, DriverState)>>,
+    /// How far an interrupted `rebalance` of each instance got, by instance name. Held for the
+    /// whole of a rebalance, so that rebalances of the same storage run one at a time.
+    rebalance_progress: tokio::sync::Mutex<HashMap<String, RebalanceProgress>>,
+}
+
+/// The hash ring and the shards placed on it. Replaced as a unit by `reconfigure` so that each
+/// operation sees a consistent ring and shard map.
+struct ShardMap<T> {
+    ring: Ring<T>,
+    shard_key_to_storage: HashMap<T, SharedBlobStorage>,
+    shard_descriptions: HashMap<T, String>,
+    /// The shard keys in a stable order, in which shards are listed.
+    shard_order: Vec<T>,
+    /// Incremented by each `reconfigure`, to tell whether a ring has changed.
+    generation: u64,
+}
+
+/// The position from which to resume a `rebalance` which failed partway through.
+struct RebalanceProgress {
+    /// The generation of the ring which was being rebalanced. Progress made on an earlier ring is
+    /// discarded, since the blobs which it copied may belong elsewhere on the current ring.
+    generation: u64,
+    /// The index in `ShardMap::shard_order` of the shard being listed.
+    shard_index: usize,
+    /// The cursor of the next page of that shard's blobs.
+    cursor: Option<String>,
+}
+
+impl<T> ShardMap<T>
+where
+    T: Hash + Eq + Copy,
+{
+    fn new(
+        shards: impl IntoIterator<Item = (T, SharedBlobStorage)>,
+        shard_descriptions: HashMap<T, String>,
+        generation: u64,
+    ) -> Self {
+        let mut ring_builder = RingBuilder::default().vnodes(RING_SIZE);
+
+        let mut shard_key_to_storage = HashMap::new();
+        let mut shard_order = Vec::new();
+        for (key, storage) in shards {
+            if shard_key_to_storage.insert(key, storage).is_none() {
+                shard_order.push(key);
+                ring_builder = ring_builder.node(key);
+            }
+        }
+
+        Self {
+            ring: ring_builder.build(),
+            shard_key_to_storage,
+            shard_descriptions,
+            shard_order,
+            generation,
+        }
+    }
+
+    fn ordered_storages(&self) -> Vec<&(dyn BlobStorage + Send + Sync)> {
+        self.shard_order
+            .iter()
+            .map(|key| &*self.shard_key_to_storage[key])
+            .collect()
+    }
+
+    fn storages_for_digest(
+        &self,
+        digest: Digest,
+        key_replicas: NonZeroUsize,
+    ) -> impl Iterator<Item = &SharedBlobStorage> {
+        self.ring
+            .replicas(digest)
+            .take(key_replicas.into())
+            .flat_map(|k| self.shard_key_to_storage.get(k))
+    }
 }
 
 /// Distribute queries over a set of shards using a consistent-hash algorithm.
@@ -43,11 +128,18 @@ pub struct ShardingStorage<T> {
 ///
 /// - Reads are sent to all shards for a digest with the first shard returning content for that
 ///   digest winning. Unavailable shards are skipped; other errors are surfaced. A digest will
-///   only be reported as missing if there was at least one available shard.
+///   only be reported as missing if there was at least one available shard. If the winning
+///   shard fails partway through its stream, the read is resumed from the next replica at the
+///   offset already delivered. Batch reads are instead sent as one batch to each primary shard,
+///   and only the digests which a primary shard did not return are read from the replicas.
 ///
 /// - Writes are distributed to all shards for a digest. Each chunk on the write stream is
-///   written in lockstep. If any shard errors, it is removed from the write attempt in order
-///   to avoid passing that error back to the API client.
+///   written in lockstep. If any shard errors (or, if a write timeout is set, takes longer than
+///   the timeout to write a chunk), it is removed from the write attempt in order to avoid
+///   passing that error back to the API client. A write succeeds if it commits to at least one
+///   shard.
+///
+/// The set of shards may be changed without a restart via `reconfigure`.
 ///
 /// Note: This driver intentionally favors high availability over strong consistency and so
 /// the driver will not attempt to retry failed writes etc.
@@ -66,28 +158,300 @@ where
         purpose: &'static str,
         shard_descriptions: HashMap<T, String>,
     ) -> Self {
-        let mut ring_builder = RingBuilder::default().vnodes(RING_SIZE);
-
-        let mut shard_key_to_storage = HashMap::new();
-        for (key, storage) in shards {
-            shard_key_to_storage.insert(key, storage);
-            ring_builder = ring_builder.node(key);
-        }
+        let shards = shards
+            .into_iter()
+            .map(|(key, storage)| (key, Arc::from(storage)));
 
         Self {
-            ring: ring_builder.build(),
-            shard_key_to_storage,
+            shards: ArcSwap::from_pointee(ShardMap::new(shards, shard_descriptions, 0)),
             key_replicas,
             purpose,
-            _shard_descriptions: shard_descriptions,
+            write_timeout: None,
+            instances: Mutex::default(),
+            rebalance_progress: tokio::sync::Mutex::default(),
         }
     }
 
-    fn storages_for_digest(&self, digest: Digest) -> impl Iterator<Item = &BoxBlobStorage> {
-        self.ring
-            .replicas(digest)
-            .take(self.key_replicas.into())
-            .flat_map(|k| self.shard_key_to_storage.get(k))
+    /// Drop shards from a write attempt if they take longer than `write_timeout` to write a chunk,
+    /// rather than stalling the write until they respond.
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = Some(write_timeout);
+        self
+    }
+
+    /// Atomically add and remove shards, rebuilding the hash ring over the resulting set of
+    /// shards. Shards which are neither added nor removed are kept as-is. A shard added under
+    /// an existing key replaces that shard.
+    ///
+    /// Operations which are already in flight complete against the previous ring and shards.
+    /// Instances previously set up via `ensure_instance` are set up on each added shard.
+    ///
+    /// Note: Changing the ring moves keys between shards. Until data has been rewritten onto its
+    /// new shards, reads will miss on those shards and rely on falling back to the remaining
+    /// replicas, and digests whose replicas have all moved will be reported as missing.
+    pub fn reconfigure(
+        &self,
+        added_shards: Vec<(T, BoxBlobStorage)>,
+        removed_shards: &[T],
+        shard_descriptions: HashMap<T, String>,
+    ) {
+        let instances = self.instances.lock();
+        let added_shards = added_shards
+            .into_iter()
+            .map(|(key, storage)| {
+                for (instance, state) in instances.iter() {
+                    storage.ensure_instance(instance, state.clone());
+                }
+                (key, Arc::from(storage))
+            })
+            .collect::<Vec<(T, SharedBlobStorage)>>();
+
+        self.shards.rcu(|current| {
+            // Keep the order of the remaining shards, and list added shards after them.
+            let mut shards = current
+                .shard_order
+                .iter()
+                .filter(|key| !removed_shards.contains(*key))
+                .map(|key| (*key, current.shard_key_to_storage[key].clone()))
+                .collect::<Vec<_>>();
+            for (key, storage) in &added_shards {
+                match shards.iter_mut().find(|(k, _)| k == key) {
+                    Some(shard) => shard.1 = storage.clone(),
+                    None => shards.push((*key, storage.clone())),
+                }
+            }
+
+            let mut descriptions = current.shard_descriptions.clone();
+            descriptions.extend(shard_descriptions.clone());
+            descriptions.retain(|key, _| shards.iter().any(|(k, _)| k == key));
+
+            ShardMap::new(shards, descriptions, current.generation + 1)
+        });
+
+        log::info!(
+            "Reconfigured {} shards: added {}, removed {}.",
+            self.purpose,
+            added_shards.len(),
+            removed_shards.len()
+        );
+    }
+
+    /// Copy blobs of `instance` which are held by shards that do not own them on the current ring
+    /// (e.g. after `reconfigure` added shards) onto the shards which do own them. Returns the
+    /// number of blobs copied.
+    ///
+    /// Every blob of each shard is enumerated via `list_blobs`, `page_size` digests at a time, and
+    /// a blob is only copied to owning shards which are missing it. Copies are thus idempotent,
+    /// and it is safe to rebalance while serving. A rebalance which fails partway through records
+    /// how far it got, and running it again resumes from there unless the ring has since changed.
+    /// Blobs are not deleted from the shards which no longer own them, and are instead left to be
+    /// evicted.
+    ///
+    /// Note: Blobs on shards which have already been removed cannot be copied, and so shards should
+    /// be removed only after rebalancing onto the shards which replace them. Shards whose driver
+    /// cannot list blobs fail the rebalance with `Unimplemented`.
+    pub async fn rebalance(
+        &self,
+        instance: Instance,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<usize, StorageError> {
+        let mut rebalance_progress = self.rebalance_progress.lock().await;
+        let shards = self.shards.load_full();
+        let mut progress = match rebalance_progress.remove(&instance.name) {
+            Some(progress) if progress.generation == shards.generation => progress,
+            _ => RebalanceProgress {
+                generation: shards.generation,
+                shard_index: 0,
+                cursor: None,
+            },
+        };
+
+        let mut copied = 0;
+        while let Some(source_key) = shards.shard_order.get(progress.shard_index) {
+            let source = &shards.shard_key_to_storage[source_key];
+            let result = async {
+                let page = source
+                    .list_blobs(
+                        instance.clone(),
+                        progress.cursor.clone(),
+                        page_size,
+                        state.clone(),
+                    )
+                    .await?;
+                let page_copied = self
+                    .copy_misplaced_blobs(&shards, source_key, page.digests, &instance, &state)
+                    .await?;
+                Ok::<_, StorageError>((page_copied, page.next_cursor))
+            }
+            .await;
+            let (page_copied, next_cursor) = match result {
+                Ok(page) => page,
+                Err(err) => {
+                    log::warn!(
+                        "Rebalancing {} shards for instance `{}` failed after copying {copied} \
+                         blobs: {err}",
+                        self.purpose,
+                        instance.name
+                    );
+                    rebalance_progress.insert(instance.name.clone(), progress);
+                    return Err(err);
+                }
+            };
+
+            copied += page_copied;
+            match next_cursor {
+                Some(cursor) => progress.cursor = Some(cursor),
+                None => {
+                    progress.shard_index += 1;
+                    progress.cursor = None;
+                }
+            }
+        }
+
+        log::info!(
+            "Rebalanced {} shards for instance `{}`: copied {copied} blobs.",
+            self.purpose,
+            instance.name
+        );
+        Ok(copied)
+    }
+
+    /// Copy those of `digests` (which are held by the shard `source_key`) that the shard does not
+    /// own onto the shards which do own them, if they are missing there.
+    async fn copy_misplaced_blobs(
+        &self,
+        shards: &ShardMap<T>,
+        source_key: &T,
+        digests: Vec<Digest>,
+        instance: &Instance,
+        state: &DriverState,
+    ) -> Result<usize, StorageError> {
+        let source = &shards.shard_key_to_storage[source_key];
+
+        // Group the digests which this shard does not own by the shards which do.
+        let mut digests_by_owner: HashMap<T, Vec<Digest>> = HashMap::new();
+        for digest in digests {
+            let owner_keys = shards
+                .ring
+                .replicas(digest)
+                .take(self.key_replicas.into())
+                .copied()
+                .collect::<Vec<_>>();
+            if owner_keys.contains(source_key) {
+                continue;
+            }
+            for owner_key in owner_keys {
+                digests_by_owner.entry(owner_key).or_default().push(digest);
+            }
+        }
+
+        let mut copied = 0;
+        for (owner_key, digests) in digests_by_owner {
+            let Some(owner) = shards.shard_key_to_storage.get(&owner_key) else {
+                continue;
+            };
+            let missing_digests = owner
+                .find_missing_blobs(instance.clone(), digests, state.clone())
+                .await?;
+            for digest in missing_digests {
+                if copy_blob(source, owner, instance, digest, state).await? {
+                    copied += 1;
+                    metrics::counter!(
+                        "toolchain_storage_rebalance_copied_total",
+                        1,
+                        "driver" => "sharding",
+                        "purpose" => self.purpose,
+                    );
+                }
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Read a blob from whichever of `storages` first returns its content, resuming the read on
+    /// the others if that stream fails partway through. `available` is whether a shard which is
+    /// not among `storages` has already reported the blob missing, in which case the blob is
+    /// reported missing rather than unavailable if none of `storages` are available.
+    async fn read_blob_from_replicas(
+        &self,
+        storages: Vec<SharedBlobStorage>,
+        available: bool,
+        params: ReadParams,
+    ) -> Result<Option<BoxReadStream>, StorageError> {
+        let mut results_stream = storages
+            .iter()
+            .enumerate()
+            .map(|(index, storage)| {
+                storage
+                    .read_blob(
+                        params.instance.clone(),
+                        params.digest,
+                        params.max_batch_size,
+                        params.read_offset,
+                        params.read_limit,
+                        params.state.clone(),
+                    )
+                    .map(move |result| (index, result))
+                    .boxed()
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut at_least_one_available = available;
+        while let Some((index, result)) = results_stream.next().await {
+            match result {
+                Ok(Some(stream)) => {
+                    let replicas = storages
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| *i != index)
+                        .map(|(_, storage)| storage.clone())
+                        .collect();
+                    return Ok(Some(failover_read_stream(
+                        stream,
+                        replicas,
+                        params,
+                        self.purpose,
+                    )));
+                }
+                Ok(None) | Err(StorageError::NotFound(_)) => {
+                    // Skip missing results in hope it will be found in another shard.
+                    at_least_one_available = true;
+                    continue;
+                }
+                Err(err @ StorageError::Unavailable(_)) => {
+                    self.record_unavailable_read(&err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // If the digest was not found in any available shard, then it is missing.
+        // If there were no available shards, then error.
+        if at_least_one_available {
+            Ok(None)
+        } else {
+            Err(StorageError::Unavailable(
+                "No shards were available to answer read query.".to_string(),
+            ))
+        }
+    }
+
+    fn record_unavailable_read(&self, err: &StorageError) {
+        log::error!("Skipping unavailable sharding during read: {:?}", err);
+        metrics::counter!(
+            "toolchain_storage_shard_unavailable_total",
+            1,
+            "driver" => "sharding",
+            "purpose" => self.purpose,
+        );
+    }
+
+    /// The instances which have been set up via `ensure_instance`, e.g. to `rebalance` each of
+    /// them.
+    pub fn instances(&self) -> Vec<(Instance, DriverState)> {
+        self.instances.lock().clone()
     }
 
     /// Return a vector with each shard ID and its applicable storage driver.
@@ -96,8 +460,13 @@ where
     /// due to hash randomization in `HashMap`. If you need a consistent order, then sort
     /// the vector as necessary.
     #[cfg(test)]
-    pub fn into_inner(self) -> Vec<(T, BoxBlobStorage)> {
-        self.shard_key_to_storage.into_iter().collect()
+    pub fn into_inner(self) -> Vec<(T, SharedBlobStorage)> {
+        self.shards
+            .load()
+            .shard_key_to_storage
+            .iter()
+            .map(|(key, storage)| (*key, storage.clone()))
+            .collect()
     }
 }
 
@@ -106,16 +475,19 @@ impl<T> BlobStorage for ShardingStorage<T>
 where
     T: Hash + Eq + Copy + Debug + Send + Sync + 'static,
 {
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
     async fn find_missing_blobs(
         &self,
         instance: Instance,
         digests: Vec<Digest>,
         state: DriverState,
     ) -> Result<Vec<Digest>, StorageError> {
+        let shards = self.shards.load_full();
+
         // Partition the digests by the storage driver to use.
         let mut storage_to_digest: HashMap<T, HashSet<Digest>> = HashMap::new();
         for digest in &digests {
-            let replica_keys = self.ring.replicas(digest).take(self.key_replicas.into());
+            let replica_keys = shards.ring.replicas(digest).take(self.key_replicas.into());
             for replica_key in replica_keys {
                 storage_to_digest
                     .entry(*replica_key)
@@ -130,7 +502,7 @@ where
                 xs.sort();
                 xs
             };
-            let storage = self
+            let storage = shards
                 .shard_key_to_storage
                 .get(key)
                 .expect("lookup shard in shard map");
@@ -199,6 +571,7 @@ where
         Ok(missing_digests)
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
     async fn read_blob(
         &self,
         instance: Instance,
@@ -208,65 +581,173 @@ where
         read_limit: Option<usize>,
         state: DriverState,
     ) -> Result<Option<BoxReadStream>, StorageError> {
-        let mut results_stream = self
-            .storages_for_digest(digest)
-            .map(|storage| {
-                storage
-                    .read_blob(
-                        instance.clone(),
-                        digest,
-                        max_batch_size,
-                        read_offset,
-                        read_limit,
-                        state.clone(),
-                    )
-                    .boxed()
-            })
+        let shards = self.shards.load_full();
+        let storages = shards
+            .storages_for_digest(digest, self.key_replicas)
+            .cloned()
+            .collect();
+        self.read_blob_from_replicas(
+            storages,
+            false,
+            ReadParams {
+                instance,
+                digest,
+                max_batch_size,
+                read_offset,
+                read_limit,
+                state,
+            },
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        let shards = self.shards.load_full();
+
+        // Batch the digests by their primary shard, so that each shard is sent a single batch.
+        // Digests which are not read from their primary shard are read from their other replicas
+        // instead, along with whether the primary shard was available.
+        let mut primary_to_positions: HashMap<T, Vec<usize>> = HashMap::new();
+        let mut fallbacks = Vec::new();
+        for (position, digest) in digests.iter().enumerate() {
+            match shards.ring.replicas(digest).next() {
+                Some(primary_key) => primary_to_positions
+                    .entry(*primary_key)
+                    .or_default()
+                    .push(position),
+                None => fallbacks.push((position, false)),
+            }
+        }
+
+        let batches = primary_to_positions.into_iter().map(|(key, positions)| {
+            let storage = shards
+                .shard_key_to_storage
+                .get(&key)
+                .expect("lookup shard in shard map");
+            let batch = positions
+                .iter()
+                .map(|position| digests[*position])
+                .collect();
+            storage
+                .read_blobs(instance.clone(), batch, state.clone())
+                .map(move |result| (positions, result))
+        });
+        let batch_results = future::join_all(batches).await;
+
+        let mut results: Vec<Option<Result<Option<Bytes>, StorageError>>> =
+            digests.iter().map(|_| None).collect();
+        for (positions, batch_result) in batch_results {
+            let blobs = match batch_result {
+                Ok(blobs) => blobs,
+                Err(err @ StorageError::Unavailable(_)) => {
+                    self.record_unavailable_read(&err);
+                    fallbacks.extend(positions.into_iter().map(|position| (position, false)));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            for (position, (_, result)) in positions.into_iter().zip(blobs) {
+                match result {
+                    Ok(Some(content)) => results[position] = Some(Ok(Some(content))),
+                    Ok(None) | Err(StorageError::NotFound(_)) => fallbacks.push((position, true)),
+                    Err(err @ StorageError::Unavailable(_)) => {
+                        self.record_unavailable_read(&err);
+                        fallbacks.push((position, false));
+                    }
+                    Err(err) => results[position] = Some(Err(err)),
+                }
+            }
+        }
+
+        let fallback_reads = fallbacks.into_iter().map(|(position, primary_available)| {
+            let digest = digests[position];
+            let replicas = shards
+                .storages_for_digest(digest, self.key_replicas)
+                .skip(1)
+                .cloned()
+                .collect();
+            let params = ReadParams {
+                instance: instance.clone(),
+                digest,
+                max_batch_size: digest.size_bytes.max(1),
+                read_offset: None,
+                read_limit: None,
+                state: state.clone(),
+            };
+            async move {
+                let result = match self
+                    .read_blob_from_replicas(replicas, primary_available, params)
+                    .await
+                {
+                    Ok(Some(stream)) => consolidate_stream(stream).await.map(Some),
+                    Ok(None) => Ok(None),
+                    Err(err) => Err(err),
+                };
+                (position, result)
+            }
+        });
+        for (position, result) in future::join_all(fallback_reads).await {
+            results[position] = Some(result);
+        }
+
+        Ok(digests
+            .into_iter()
+            .zip(results)
+            .map(|(digest, result)| (digest, result.expect("result for each digest")))
+            .collect())
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        let shards = self.shards.load_full();
+        let mut results_stream = shards
+            .storages_for_digest(digest, self.key_replicas)
+            .map(|storage| storage.stat(instance.clone(), digest, state.clone()))
             .collect::<FuturesUnordered<_>>();
 
+        // As for reads, the first shard to report the blob wins, and the blob is only missing if
+        // at least one shard was available.
         let mut at_least_one_available = false;
         while let Some(result) = results_stream.next().await {
             match result {
-                Ok(Some(stream)) => return Ok(Some(stream)),
-                Ok(None) => {
-                    // Skip missing results in hope it will be found in another shard.
-                    at_least_one_available = true;
-                    continue;
-                }
-                Err(err @ StorageError::Unavailable(_)) => {
-                    log::error!("Skipping unavailable sharding during read: {:?}", err);
-
-                    metrics::counter!(
-                        "toolchain_storage_shard_unavailable_total",
-                        1,
-                        "driver" => "sharding",
-                        "purpose" => self.purpose,
-                    );
-                    continue;
-                }
+                Ok(Some(stat)) => return Ok(Some(stat)),
+                Ok(None) | Err(StorageError::NotFound(_)) => at_least_one_available = true,
+                Err(err @ StorageError::Unavailable(_)) => self.record_unavailable_read(&err),
                 Err(err) => return Err(err),
             }
         }
 
-        // If the digest was not found in any available shard, then it is missing.
-        // If there were no available shards, then error.
         if at_least_one_available {
             Ok(None)
         } else {
             Err(StorageError::Unavailable(
-                "No shards were available to answer read query.".to_string(),
+                "No shards were available to answer stat query.".to_string(),
             ))
         }
     }
 
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
     async fn begin_write_blob(
         &self,
         instance: Instance,
         digest: Digest,
         state: DriverState,
     ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        let shards = self.shards.load_full();
         let attempt_results = futures::future::join_all(
-            self.storages_for_digest(digest)
+            shards
+                .storages_for_digest(digest, self.key_replicas)
                 .map(|storage| {
                     storage
                         .begin_write_blob(instance.clone(), digest, state.clone())
@@ -309,70 +790,311 @@ where
         Ok(Box::new(WriteAttempt {
             attempts,
             purpose: self.purpose,
+            write_timeout: self.write_timeout,
         }))
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
-        for shard in self.shard_key_to_storage.values_mut() {
-            shard.ensure_instance(instance, state.clone());
-        }
-    }
-}
-
-struct WriteAttempt {
-    attempts: Vec<Box<dyn WriteAttemptOps + Send + Sync>>,
-    purpose: &'static str,
-}
-
-#[async_trait]
-impl WriteAttemptOps for WriteAttempt {
-    async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
-        let mut write_futures = Vec::new();
-        for attempt in &mut self.attempts {
-            let write_fut = attempt.write(batch.clone()).boxed();
-            write_futures.push(write_fut);
-        }
-
-        let results = futures::future::join_all(write_futures).await;
-
-        let mut indices_to_remove = Vec::new();
-        for (i, result) in results.into_iter().enumerate() {
-            if result.is_err() {
-                // Ignore errors from failed shards by removing this shard's write attempt
-                // so subsequent writes (and commit) do not operate on this shard.
-                log::error!(
-                    "Failed to write chunk to shard (shard will be dropped from this write): {:?}",
-                    result
-                );
-
-                metrics::counter!(
-                    "toolchain_storage_sharding_write_error_total",
-                    1,
-                    "driver" => "sharding",
-                    "purpose" => self.purpose,
-                );
-                indices_to_remove.push(i);
+    /// Deletes each digest from all of the shards to which it is assigned. A digest is reported
+    /// as deleted if any shard deleted it.
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let shards = self.shards.load_full();
+        let mut digests_by_shard: HashMap<T, Vec<Digest>> = HashMap::new();
+        for digest in digests {
+            for shard_key in shards.ring.replicas(digest).take(self.key_replicas.into()) {
+                digests_by_shard.entry(*shard_key).or_default().push(digest);
             }
         }
 
-        for i in indices_to_remove.into_iter().rev() {
-            self.attempts.remove(i);
-        }
-
-        Ok(())
+        let deleted_results = future::try_join_all(digests_by_shard.into_iter().flat_map(
+            |(shard_key, digests)| {
+                shards
+                    .shard_key_to_storage
+                    .get(&shard_key)
+                    .map(|storage| storage.delete_blobs(instance.clone(), digests, state.clone()))
+            },
+        ))
+        .await?;
+
+        let deleted_digests = deleted_results
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<_>>();
+        Ok(deleted_digests.into_iter().collect())
     }
 
-    async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError> {
-        let purpose = self.purpose;
+    /// Lists blobs from every shard. Shards do not share a write ordering, so the result is only
+    /// ordered within each shard's portion.
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let shards = self.shards.load_full();
+        let listed_results = future::try_join_all(
+            shards
+                .shard_key_to_storage
+                .values()
+                .map(|storage| storage.list_recent_blobs(instance.clone(), limit, state.clone())),
+        )
+        .await?;
 
-        let commit_futures = self
-            .attempts
+        // Replicated digests are listed by more than one shard.
+        let mut seen_digests = HashSet::new();
+        let mut digests = listed_results
             .into_iter()
-            .map(|attempt| attempt.commit().boxed());
+            .flatten()
+            .filter(|digest| seen_digests.insert(*digest))
+            .collect::<Vec<_>>();
+        digests.truncate(limit);
+        Ok(digests)
+    }
 
-        let results = futures::future::join_all(commit_futures).await;
-        let mut at_least_one_success = false;
-        let mut last_error: Option<StreamingWriteError> = None;
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        // Note: Replicated blobs are listed once per shard holding them. A `reconfigure` during the
+        // listing may shift which shard a cursor refers to, so a listing should be restarted then.
+        let shards = self.shards.load_full();
+        list_blobs_in_turn(
+            &shards.ordered_storages(),
+            instance,
+            cursor,
+            page_size,
+            state,
+        )
+        .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        // Hold the lock while setting up the shards, so that a concurrent `reconfigure` either sees
+        // this instance or installs its shards before they are set up here.
+        let mut instances = self.instances.lock();
+        for shard in self.shards.load().shard_key_to_storage.values() {
+            shard.ensure_instance(instance, state.clone());
+        }
+        match instances
+            .iter_mut()
+            .find(|(known, _)| known.name == instance.name)
+        {
+            Some(known) => known.1 = state,
+            None => instances.push((instance.clone(), state)),
+        }
+    }
+}
+
+/// Copy `digest` from `source` to `destination`. Returns false if there was nothing to copy: either
+/// the blob is no longer present in `source`, or it is already present in `destination`.
+async fn copy_blob(
+    source: &SharedBlobStorage,
+    destination: &SharedBlobStorage,
+    instance: &Instance,
+    digest: Digest,
+    state: &DriverState,
+) -> Result<bool, StorageError> {
+    let read_result = source
+        .read_blob(
+            instance.clone(),
+            digest,
+            REBALANCE_READ_BATCH_SIZE,
+            None,
+            None,
+            state.clone(),
+        )
+        .await;
+    let mut stream = match read_result {
+        Ok(Some(stream)) => stream,
+        Ok(None) | Err(StorageError::NotFound(_)) => return Ok(false),
+        Err(err) => return Err(err),
+    };
+
+    let write_result = async {
+        let mut attempt = destination
+            .begin_write_blob(instance.clone(), digest, state.clone())
+            .await?;
+        while let Some(chunk) = stream.next().await {
+            attempt.write(chunk?).await?;
+        }
+        attempt.commit().await
+    }
+    .await;
+    match write_result {
+        Ok(()) => Ok(true),
+        Err(StreamingWriteError::AlreadyExists) => Ok(false),
+        Err(StreamingWriteError::StorageError(err)) => Err(err),
+    }
+}
+
+/// The arguments of a `read_blob` call, kept in order to resume the read on another replica.
+struct ReadParams {
+    instance: Instance,
+    digest: Digest,
+    max_batch_size: usize,
+    read_offset: Option<usize>,
+    read_limit: Option<usize>,
+    state: DriverState,
+}
+
+/// Wrap the read `stream` of one replica so that if it fails partway through, the read is
+/// transparently resumed from each of the other `replicas` in turn, starting at the offset which
+/// has already been delivered. The number of failovers is thus capped by the replica count.
+fn failover_read_stream(
+    stream: BoxReadStream,
+    replicas: Vec<SharedBlobStorage>,
+    params: ReadParams,
+    purpose: &'static str,
+) -> BoxReadStream {
+    let stream = async_stream::stream! {
+      let mut stream = stream;
+      let mut replicas = replicas.into_iter();
+      let mut delivered = 0;
+      loop {
+        let err = match stream.next().await {
+          Some(Ok(chunk)) => {
+            delivered += chunk.len();
+            yield Ok(chunk);
+            continue;
+          }
+          Some(Err(err)) => err,
+          None => break,
+        };
+
+        let mut resumed = None;
+        for replica in replicas.by_ref() {
+          let result = replica
+            .read_blob(
+              params.instance.clone(),
+              params.digest,
+              params.max_batch_size,
+              Some(params.read_offset.unwrap_or_default() + delivered),
+              params.read_limit.map(|limit| limit.saturating_sub(delivered)),
+              params.state.clone(),
+            )
+            .await;
+          match result {
+            Ok(Some(stream)) => {
+              resumed = Some(stream);
+              break;
+            }
+            // The blob may also have been evicted from the replica.
+            Ok(None) | Err(StorageError::NotFound(_)) => (),
+            Err(err) => log::error!("Failed to resume read of {:?} on replica: {err}", params.digest),
+          }
+        }
+
+        match resumed {
+          Some(replica_stream) => {
+            log::warn!(
+              "Resuming read of {:?} on another replica at offset {delivered}: {err}",
+              params.digest
+            );
+            metrics::counter!(
+              "toolchain_storage_sharding_read_failover_total",
+              1,
+              "driver" => "sharding",
+              "purpose" => purpose,
+            );
+            stream = replica_stream;
+          }
+          None => {
+            yield Err(err);
+            break;
+          }
+        }
+      }
+    };
+    Box::pin(stream)
+}
+
+struct WriteAttempt {
+    attempts: Vec<Box<dyn WriteAttemptOps + Send + Sync>>,
+    purpose: &'static str,
+    write_timeout: Option<Duration>,
+}
+
+#[async_trait]
+impl WriteAttemptOps for WriteAttempt {
+    async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
+        // Each shard's attempt is moved into its own task, and only returned to this write
+        // attempt if it succeeds. NB: If this future is dropped, the JoinSet aborts the tasks.
+        let write_timeout = self.write_timeout;
+        let mut writes = JoinSet::new();
+        for (i, mut attempt) in std::mem::take(&mut self.attempts).into_iter().enumerate() {
+            let batch = batch.clone();
+            writes.spawn(async move {
+                let result = match write_timeout {
+                    Some(write_timeout) => {
+                        tokio::time::timeout(write_timeout, attempt.write(batch))
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(StorageError::Unavailable(format!(
+                                    "Timed out after {write_timeout:?} writing chunk to shard"
+                                ))
+                                .into())
+                            })
+                    }
+                    None => attempt.write(batch).await,
+                };
+                (i, attempt, result)
+            });
+        }
+
+        let mut attempts = Vec::new();
+        while let Some(joined) = writes.join_next().await {
+            let result = match joined {
+                Ok((i, attempt, Ok(()))) => {
+                    attempts.push((i, attempt));
+                    continue;
+                }
+                Ok((_, _, Err(err))) => format!("{err:?}"),
+                Err(join_err) => join_err.to_string(),
+            };
+
+            // Ignore errors from failed shards by dropping this shard's write attempt so
+            // subsequent writes (and commit) do not operate on this shard.
+            log::error!(
+                "Failed to write chunk to shard (shard will be dropped from this write): {}",
+                result
+            );
+
+            metrics::counter!(
+                "toolchain_storage_sharding_write_error_total",
+                1,
+                "driver" => "sharding",
+                "purpose" => self.purpose,
+            );
+        }
+
+        // Preserve the order of the surviving shards.
+        attempts.sort_by_key(|(i, _)| *i);
+        self.attempts = attempts.into_iter().map(|(_, attempt)| attempt).collect();
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(driver = "sharding"))]
+    async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError> {
+        let purpose = self.purpose;
+
+        let commit_futures = self
+            .attempts
+            .into_iter()
+            .map(|attempt| attempt.commit().boxed());
+
+        let results = futures::future::join_all(commit_futures).await;
+        let mut at_least_one_success = false;
+        let mut last_error: Option<StreamingWriteError> = None;
 
         for result in results {
             match result {
@@ -409,22 +1131,32 @@ impl WriteAttemptOps for WriteAttempt {
 mod tests {
     use std::collections::HashMap;
     use std::fmt::Write;
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::time::Duration;
 
     use async_trait::async_trait;
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
+    use futures::StreamExt;
+    use parking_lot::Mutex;
     use tokio::sync::Semaphore;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
 
     use crate::bytes::consolidate_stream;
     use crate::driver::{
-        BlobStorage, BoxReadStream, DriverState, Instance, MemoryStorage, ShardingStorage,
-        StorageError, StreamingWriteError, WriteAttemptOps,
+        BlobPage, BlobStorage, BoxReadStream, DriverState, Durability, FileBackedStorage, Instance,
+        MemoryStorage, ShardingStorage, StorageError, StreamingWriteError, WriteAttemptOps,
     };
-    use crate::testutil::{AlwaysExistsStorage, WriteSemaphoreStorage};
+    use crate::testutil::{AlwaysExistsStorage, TestData, WriteSemaphoreStorage};
     use crate::Digest;
 
+    use super::{BoxBlobStorage, SharedBlobStorage};
+
     async fn acquire_and_forget(semaphore: &Semaphore, n: u32, timeout: Duration) {
         let permits = tokio::time::timeout(timeout, semaphore.acquire_many(n))
             .await
@@ -435,8 +1167,8 @@ mod tests {
 
     #[tokio::test]
     async fn basic_sharding_works() {
-        let mut storage1 = MemoryStorage::new();
-        let mut storage2 = MemoryStorage::new();
+        let storage1 = MemoryStorage::new();
+        let storage2 = MemoryStorage::new();
         let instance = Instance::from("main");
         storage1.ensure_instance(&instance, DriverState::default());
         storage2.ensure_instance(&instance, DriverState::default());
@@ -509,9 +1241,9 @@ mod tests {
     #[tokio::test]
     async fn basic_replicated_writes_are_successful() {
         let semaphore = Arc::new(Semaphore::new(0));
-        let mut storage1 = WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone());
-        let mut storage2 = WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone());
-        let mut storage3 = WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone());
+        let storage1 = WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone());
+        let storage2 = WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone());
+        let storage3 = WriteSemaphoreStorage::new(MemoryStorage::new(), semaphore.clone());
         let instance = Instance::from("main");
         storage1.ensure_instance(&instance, DriverState::default());
         storage2.ensure_instance(&instance, DriverState::default());
@@ -576,53 +1308,680 @@ mod tests {
         // Shard 2: digest2 only
         // Shard 3: digest1 only
 
-        let missing_blobs = child_storages[0]
-            .find_missing_blobs(
+        let missing_blobs = child_storages[0]
+            .find_missing_blobs(
+                instance.clone(),
+                vec![digest1, digest2],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing_blobs, vec![]);
+
+        let missing_blobs = child_storages[1]
+            .find_missing_blobs(
+                instance.clone(),
+                vec![digest1, digest2],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing_blobs, vec![digest1]);
+
+        let missing_blobs = child_storages[2]
+            .find_missing_blobs(
+                instance.clone(),
+                vec![digest1, digest2],
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing_blobs, vec![digest2]);
+    }
+
+    struct FailGatedStorage<S> {
+        inner: S,
+        respond_unavailable: Arc<AtomicBool>,
+    }
+
+    impl<S> FailGatedStorage<S> {
+        pub fn new(inner: S, respond_unavailable: &Arc<AtomicBool>) -> Self {
+            Self {
+                inner,
+                respond_unavailable: respond_unavailable.clone(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl<S> BlobStorage for FailGatedStorage<S>
+    where
+        S: BlobStorage + Send + Sync + 'static,
+    {
+        async fn find_missing_blobs(
+            &self,
+            instance: Instance,
+            digests: Vec<Digest>,
+            state: DriverState,
+        ) -> Result<Vec<Digest>, StorageError> {
+            if self.respond_unavailable.load(Ordering::SeqCst) {
+                Err(StorageError::Unavailable("UNAVAILABLE".to_string()))
+            } else {
+                self.inner
+                    .find_missing_blobs(instance, digests, state)
+                    .await
+            }
+        }
+
+        async fn read_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            max_batch_size: usize,
+            read_offset: Option<usize>,
+            read_limit: Option<usize>,
+            state: DriverState,
+        ) -> Result<Option<BoxReadStream>, StorageError> {
+            if self.respond_unavailable.load(Ordering::SeqCst) {
+                Err(StorageError::Unavailable("UNAVAILABLE".to_string()))
+            } else {
+                self.inner
+                    .read_blob(
+                        instance,
+                        digest,
+                        max_batch_size,
+                        read_offset,
+                        read_limit,
+                        state,
+                    )
+                    .await
+            }
+        }
+
+        async fn begin_write_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            state: DriverState,
+        ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+            if self.respond_unavailable.load(Ordering::SeqCst) {
+                Err(StorageError::Unavailable("UNAVAILABLE".to_string()).into())
+            } else {
+                self.inner.begin_write_blob(instance, digest, state).await
+            }
+        }
+
+        fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+            self.inner.ensure_instance(instance, state);
+        }
+    }
+
+    /// Reports every blob as having been evicted when it is read.
+    struct EvictingStorage<S> {
+        inner: S,
+    }
+
+    #[async_trait]
+    impl<S> BlobStorage for EvictingStorage<S>
+    where
+        S: BlobStorage + Send + Sync + 'static,
+    {
+        async fn find_missing_blobs(
+            &self,
+            instance: Instance,
+            digests: Vec<Digest>,
+            state: DriverState,
+        ) -> Result<Vec<Digest>, StorageError> {
+            self.inner
+                .find_missing_blobs(instance, digests, state)
+                .await
+        }
+
+        async fn read_blob(
+            &self,
+            _instance: Instance,
+            digest: Digest,
+            _max_batch_size: usize,
+            _read_offset: Option<usize>,
+            _read_limit: Option<usize>,
+            _state: DriverState,
+        ) -> Result<Option<BoxReadStream>, StorageError> {
+            Err(StorageError::NotFound(format!("{digest:?} was evicted")))
+        }
+
+        async fn begin_write_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            state: DriverState,
+        ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+            self.inner.begin_write_blob(instance, digest, state).await
+        }
+
+        fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+            self.inner.ensure_instance(instance, state);
+        }
+    }
+
+    /// Fails reads with the given error after the first chunk of content, optionally after a
+    /// delay.
+    struct FlakyReadStorage<S> {
+        inner: S,
+        failure_after_first_chunk: Option<StorageError>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl<S> BlobStorage for FlakyReadStorage<S>
+    where
+        S: BlobStorage + Send + Sync + 'static,
+    {
+        async fn find_missing_blobs(
+            &self,
+            instance: Instance,
+            digests: Vec<Digest>,
+            state: DriverState,
+        ) -> Result<Vec<Digest>, StorageError> {
+            self.inner
+                .find_missing_blobs(instance, digests, state)
+                .await
+        }
+
+        async fn read_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            max_batch_size: usize,
+            read_offset: Option<usize>,
+            read_limit: Option<usize>,
+            state: DriverState,
+        ) -> Result<Option<BoxReadStream>, StorageError> {
+            tokio::time::sleep(self.delay).await;
+            let stream = self
+                .inner
+                .read_blob(
+                    instance,
+                    digest,
+                    max_batch_size,
+                    read_offset,
+                    read_limit,
+                    state,
+                )
+                .await?;
+            let Some(failure) = self.failure_after_first_chunk.clone() else {
+                return Ok(stream);
+            };
+            Ok(stream.map(|stream| {
+                let failure = futures::stream::once(async { Err(failure) });
+                Box::pin(stream.take(1).chain(failure)) as BoxReadStream
+            }))
+        }
+
+        async fn begin_write_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            state: DriverState,
+        ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+            self.inner.begin_write_blob(instance, digest, state).await
+        }
+
+        fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+            self.inner.ensure_instance(instance, state);
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_interrupted_reads_on_replicas() {
+        let failing_storage = FlakyReadStorage {
+            inner: MemoryStorage::new(),
+            failure_after_first_chunk: Some(StorageError::Unavailable("shard died".to_owned())),
+            delay: Duration::ZERO,
+        };
+        // The healthy replica responds more slowly, so that the failing shard wins the read.
+        let healthy_storage = FlakyReadStorage {
+            inner: MemoryStorage::new(),
+            failure_after_first_chunk: None,
+            delay: Duration::from_millis(100),
+        };
+        let instance = Instance::from("main");
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            vec![
+                (0, Box::new(failing_storage)),
+                (1, Box::new(healthy_storage)),
+            ],
+            2.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let content = TestData::from_static(b"foobarbaz");
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                3,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+
+        // Offsets and limits are preserved when resuming.
+        let stream = storage
+            .read_blob(
+                instance,
+                content.digest,
+                3,
+                Some(1),
+                Some(7),
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            consolidate_stream(stream).await.unwrap(),
+            content.bytes.slice(1..8)
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_reads_evicted_midstream_on_replicas() {
+        // The chunked Redis driver checks that all of a blob's chunks exist before streaming them,
+        // so a chunk which is evicted in the meantime fails the stream with `NotFound` partway.
+        let evicted = || StorageError::NotFound("Missing data block: data-0-1".to_owned());
+        let sharded_storage = |healthy_replica: bool| {
+            let evicting_storage = FlakyReadStorage {
+                inner: MemoryStorage::new(),
+                failure_after_first_chunk: Some(evicted()),
+                delay: Duration::ZERO,
+            };
+            // The other replica responds more slowly, so that the evicting shard wins the read.
+            let other_storage = FlakyReadStorage {
+                inner: MemoryStorage::new(),
+                failure_after_first_chunk: (!healthy_replica).then(evicted),
+                delay: Duration::from_millis(100),
+            };
+            ShardingStorage::<usize>::new(
+                vec![
+                    (0, Box::new(evicting_storage)),
+                    (1, Box::new(other_storage)),
+                ],
+                2.try_into().unwrap(),
+                "test",
+                HashMap::default(),
+            )
+        };
+        let instance = Instance::from("main");
+        let content = TestData::from_static(b"foobarbaz");
+
+        for healthy_replica in [true, false] {
+            let storage = sharded_storage(healthy_replica);
+            storage.ensure_instance(&instance, DriverState::default());
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+
+            let stream = storage
+                .read_blob(
+                    instance.clone(),
+                    content.digest,
+                    3,
+                    None,
+                    None,
+                    DriverState::default(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            let result = consolidate_stream(stream).await;
+            if healthy_replica {
+                assert_eq!(result.unwrap(), content.bytes);
+            } else {
+                // Once every replica has lost the blob, it is reported as missing.
+                assert_eq!(result.unwrap_err(), evicted());
+            }
+        }
+    }
+
+    /// Delays each chunk written to the inner storage.
+    struct SlowStorage<S> {
+        inner: S,
+        write_delay: Duration,
+    }
+
+    #[async_trait]
+    impl<S> BlobStorage for SlowStorage<S>
+    where
+        S: BlobStorage + Send + Sync + 'static,
+    {
+        async fn find_missing_blobs(
+            &self,
+            instance: Instance,
+            digests: Vec<Digest>,
+            state: DriverState,
+        ) -> Result<Vec<Digest>, StorageError> {
+            self.inner
+                .find_missing_blobs(instance, digests, state)
+                .await
+        }
+
+        async fn read_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            max_batch_size: usize,
+            read_offset: Option<usize>,
+            read_limit: Option<usize>,
+            state: DriverState,
+        ) -> Result<Option<BoxReadStream>, StorageError> {
+            self.inner
+                .read_blob(
+                    instance,
+                    digest,
+                    max_batch_size,
+                    read_offset,
+                    read_limit,
+                    state,
+                )
+                .await
+        }
+
+        async fn begin_write_blob(
+            &self,
+            instance: Instance,
+            digest: Digest,
+            state: DriverState,
+        ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+            let attempt = self.inner.begin_write_blob(instance, digest, state).await?;
+            Ok(Box::new(SlowWriteAttempt {
+                attempt,
+                write_delay: self.write_delay,
+            }))
+        }
+
+        fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+            self.inner.ensure_instance(instance, state);
+        }
+    }
+
+    struct SlowWriteAttempt {
+        attempt: Box<dyn WriteAttemptOps + Send + Sync + 'static>,
+        write_delay: Duration,
+    }
+
+    #[async_trait]
+    impl WriteAttemptOps for SlowWriteAttempt {
+        async fn write(&mut self, batch: Bytes) -> Result<(), StreamingWriteError> {
+            tokio::time::sleep(self.write_delay).await;
+            self.attempt.write(batch).await
+        }
+
+        async fn commit(self: Box<Self>) -> Result<(), StreamingWriteError> {
+            self.attempt.commit().await
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_shards_which_exceed_write_timeout() {
+        let instance = Instance::from("main");
+        let content = TestData::from_static(b"foobar");
+        let write_timeout = Duration::from_millis(100);
+        let make_storage = |slow_shards: usize| {
+            let shards = (0..2)
+                .map(|i| {
+                    let storage: BoxBlobStorage = if i < slow_shards {
+                        Box::new(SlowStorage {
+                            inner: MemoryStorage::new(),
+                            write_delay: Duration::from_secs(60),
+                        })
+                    } else {
+                        Box::new(MemoryStorage::new())
+                    };
+                    storage.ensure_instance(&instance, DriverState::default());
+                    (i, storage)
+                })
+                .collect();
+            let storage: ShardingStorage<usize> =
+                ShardingStorage::new(shards, 2.try_into().unwrap(), "test", HashMap::default())
+                    .with_write_timeout(write_timeout);
+            storage
+        };
+
+        // The write completes via the fast shard once the slow shard times out.
+        let storage = make_storage(1);
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        })
+        .await
+        .expect("write was stalled by the slow shard");
+
+        let mut storages = storage.into_inner();
+        storages.sort_by_key(|(id, _)| *id);
+        let missing = |storage: SharedBlobStorage| {
+            let instance = instance.clone();
+            async move {
+                storage
+                    .find_missing_blobs(instance, vec![content.digest], DriverState::default())
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(missing(storages[0].1.clone()).await, vec![content.digest]);
+        assert_eq!(missing(storages[1].1.clone()).await, vec![]);
+
+        // But if every shard times out, then the write fails on commit.
+        let storage = make_storage(2);
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        assert!(matches!(
+            attempt.commit().await,
+            Err(StreamingWriteError::StorageError(
+                StorageError::Unavailable(_)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reads_succeed_across_reconfiguration() {
+        let instance = Instance::from("main");
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            (0..3)
+                .map(|i| (i, Box::new(MemoryStorage::new()) as BoxBlobStorage))
+                .collect(),
+            2.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let contents = (0..32)
+            .map(|i| Bytes::from(format!("content-{i}")))
+            .collect::<Vec<_>>();
+        let digests = contents
+            .iter()
+            .map(|content| Digest::of_bytes(content).unwrap())
+            .collect::<Vec<_>>();
+        for (content, digest) in contents.iter().zip(&digests) {
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), *digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+
+        // A read which is in flight during reconfiguration completes against the old shards.
+        let in_flight_stream = storage
+            .read_blob(
                 instance.clone(),
-                vec![digest1, digest2],
+                digests[0],
+                1,
+                None,
+                None,
                 DriverState::default(),
             )
             .await
+            .unwrap()
+            .unwrap();
+
+        storage.reconfigure(
+            vec![(3, Box::new(MemoryStorage::new()))],
+            &[],
+            HashMap::default(),
+        );
+
+        assert_eq!(
+            consolidate_stream(in_flight_stream).await.unwrap(),
+            contents[0]
+        );
+
+        // Every key keeps at least one of its previous replicas, so reads fall back to it.
+        for (content, digest) in contents.iter().zip(&digests) {
+            let stream = storage
+                .read_blob(
+                    instance.clone(),
+                    *digest,
+                    1024,
+                    None,
+                    None,
+                    DriverState::default(),
+                )
+                .await
+                .unwrap()
+                .expect("digest should be readable after reconfiguration");
+            assert_eq!(consolidate_stream(stream).await.unwrap(), *content);
+        }
+        let missing_blobs = storage
+            .find_missing_blobs(instance.clone(), digests.clone(), DriverState::default())
+            .await
             .unwrap();
         assert_eq!(missing_blobs, vec![]);
 
-        let missing_blobs = child_storages[1]
-            .find_missing_blobs(
-                instance.clone(),
-                vec![digest1, digest2],
-                DriverState::default(),
-            )
+        // The added shard has the instance set up but holds none of the existing data yet.
+        let mut storages = storage.into_inner();
+        storages.sort_by_key(|(id, _)| *id);
+        assert_eq!(storages.len(), 4);
+        let missing_blobs = storages[3]
+            .1
+            .find_missing_blobs(instance.clone(), digests.clone(), DriverState::default())
             .await
             .unwrap();
-        assert_eq!(missing_blobs, vec![digest1]);
+        assert_eq!(missing_blobs.len(), digests.len());
+    }
 
-        let missing_blobs = child_storages[2]
-            .find_missing_blobs(
-                instance.clone(),
-                vec![digest1, digest2],
-                DriverState::default(),
-            )
+    #[tokio::test]
+    async fn rebalance_copies_blobs_to_their_owning_shards() {
+        let instance = Instance::from("main");
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            (0..3)
+                .map(|i| (i, Box::new(MemoryStorage::new()) as BoxBlobStorage))
+                .collect(),
+            2.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let contents = (0..32)
+            .map(|i| Bytes::from(format!("content-{i}")))
+            .collect::<Vec<_>>();
+        let digests = contents
+            .iter()
+            .map(|content| Digest::of_bytes(content).unwrap())
+            .collect::<Vec<_>>();
+        for (content, digest) in contents.iter().zip(&digests) {
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), *digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+
+        storage.reconfigure(
+            vec![(3, Box::new(MemoryStorage::new()))],
+            &[],
+            HashMap::default(),
+        );
+
+        // A small page size, so that each shard is listed over several pages.
+        let copied = storage
+            .rebalance(instance.clone(), 5, DriverState::default())
             .await
             .unwrap();
-        assert_eq!(missing_blobs, vec![digest2]);
+        assert!(copied > 0);
+
+        // Every blob is now present on each of the shards which own it on the new ring.
+        let shards = storage.shards.load();
+        for (content, digest) in contents.iter().zip(&digests) {
+            for owner_key in shards.ring.replicas(digest).take(2) {
+                let owner = &shards.shard_key_to_storage[owner_key];
+                let stream = owner
+                    .read_blob(
+                        instance.clone(),
+                        *digest,
+                        1024,
+                        None,
+                        None,
+                        DriverState::default(),
+                    )
+                    .await
+                    .unwrap()
+                    .expect("digest should have been copied to its owning shard");
+                assert_eq!(consolidate_stream(stream).await.unwrap(), *content);
+            }
+        }
+
+        // Rebalancing again has nothing left to copy.
+        let copied = storage
+            .rebalance(instance.clone(), 5, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(copied, 0);
     }
 
-    struct FailGatedStorage<S> {
+    struct FailingListStorage<S> {
         inner: S,
-        respond_unavailable: Arc<AtomicBool>,
+        fail_lists: Arc<AtomicBool>,
+        list_calls: Arc<AtomicUsize>,
     }
 
-    impl<S> FailGatedStorage<S> {
-        pub fn new(inner: S, respond_unavailable: &Arc<AtomicBool>) -> Self {
+    impl<S> FailingListStorage<S> {
+        fn new(inner: S) -> Self {
             Self {
                 inner,
-                respond_unavailable: respond_unavailable.clone(),
+                fail_lists: Arc::default(),
+                list_calls: Arc::default(),
             }
         }
     }
 
     #[async_trait]
-    impl<S> BlobStorage for FailGatedStorage<S>
+    impl<S> BlobStorage for FailingListStorage<S>
     where
         S: BlobStorage + Send + Sync + 'static,
     {
@@ -632,13 +1991,9 @@ mod tests {
             digests: Vec<Digest>,
             state: DriverState,
         ) -> Result<Vec<Digest>, StorageError> {
-            if self.respond_unavailable.load(Ordering::SeqCst) {
-                Err(StorageError::Unavailable("UNAVAILABLE".to_string()))
-            } else {
-                self.inner
-                    .find_missing_blobs(instance, digests, state)
-                    .await
-            }
+            self.inner
+                .find_missing_blobs(instance, digests, state)
+                .await
         }
 
         async fn read_blob(
@@ -650,20 +2005,16 @@ mod tests {
             read_limit: Option<usize>,
             state: DriverState,
         ) -> Result<Option<BoxReadStream>, StorageError> {
-            if self.respond_unavailable.load(Ordering::SeqCst) {
-                Err(StorageError::Unavailable("UNAVAILABLE".to_string()))
-            } else {
-                self.inner
-                    .read_blob(
-                        instance,
-                        digest,
-                        max_batch_size,
-                        read_offset,
-                        read_limit,
-                        state,
-                    )
-                    .await
-            }
+            self.inner
+                .read_blob(
+                    instance,
+                    digest,
+                    max_batch_size,
+                    read_offset,
+                    read_limit,
+                    state,
+                )
+                .await
         }
 
         async fn begin_write_blob(
@@ -671,26 +2022,238 @@ mod tests {
             instance: Instance,
             digest: Digest,
             state: DriverState,
-        ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
-            if self.respond_unavailable.load(Ordering::SeqCst) {
-                Err(StorageError::Unavailable("UNAVAILABLE".to_string()).into())
-            } else {
-                self.inner.begin_write_blob(instance, digest, state).await
+        ) -> Result<Box<dyn WriteAttemptOps + Send + Sync>, StreamingWriteError> {
+            self.inner.begin_write_blob(instance, digest, state).await
+        }
+
+        async fn list_blobs(
+            &self,
+            instance: Instance,
+            cursor: Option<String>,
+            page_size: usize,
+            state: DriverState,
+        ) -> Result<BlobPage, StorageError> {
+            if self.fail_lists.load(Ordering::SeqCst) {
+                return Err(StorageError::Unavailable("UNAVAILABLE".to_owned()));
             }
+            self.list_calls.fetch_add(1, Ordering::SeqCst);
+            self.inner
+                .list_blobs(instance, cursor, page_size, state)
+                .await
         }
 
-        fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+        fn ensure_instance(&self, instance: &Instance, state: DriverState) {
             self.inner.ensure_instance(instance, state);
         }
     }
 
+    #[tokio::test]
+    async fn rebalance_resumes_after_a_failure() {
+        let instance = Instance::from("main");
+        let first_shard = FailingListStorage::new(MemoryStorage::new());
+        let first_shard_list_calls = first_shard.list_calls.clone();
+        let second_shard = FailingListStorage::new(MemoryStorage::new());
+        let second_shard_fail_lists = second_shard.fail_lists.clone();
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            vec![
+                (0, Box::new(first_shard) as BoxBlobStorage),
+                (1, Box::new(second_shard) as BoxBlobStorage),
+            ],
+            1.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+        storage.ensure_instance(&instance, DriverState::default());
+        for i in 0..16 {
+            let content = Bytes::from(format!("content-{i}"));
+            let digest = Digest::of_bytes(&content).unwrap();
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+        storage.reconfigure(
+            vec![(2, Box::new(MemoryStorage::new()))],
+            &[],
+            HashMap::default(),
+        );
+
+        // The rebalance lists the first shard fully, and then fails on the second.
+        second_shard_fail_lists.store(true, Ordering::SeqCst);
+        storage
+            .rebalance(instance.clone(), 1000, DriverState::default())
+            .await
+            .unwrap_err();
+        assert_eq!(first_shard_list_calls.load(Ordering::SeqCst), 1);
+
+        // Running it again resumes from the second shard.
+        second_shard_fail_lists.store(false, Ordering::SeqCst);
+        storage
+            .rebalance(instance.clone(), 1000, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(first_shard_list_calls.load(Ordering::SeqCst), 1);
+
+        // Once complete, a rebalance starts over from the first shard.
+        let copied = storage
+            .rebalance(instance.clone(), 1000, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(copied, 0);
+        assert_eq!(first_shard_list_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn lists_blobs_across_shards() {
+        let instance = Instance::from("main");
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            (0..3)
+                .map(|i| (i, Box::new(MemoryStorage::new()) as BoxBlobStorage))
+                .collect(),
+            1.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+        storage.ensure_instance(&instance, DriverState::default());
+        let mut digests = Vec::new();
+        for i in 0..16 {
+            let content = Bytes::from(format!("content-{i}"));
+            let digest = Digest::of_bytes(&content).unwrap();
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content).await.unwrap();
+            attempt.commit().await.unwrap();
+            digests.push(digest);
+        }
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = storage
+                .list_blobs(instance.clone(), cursor, 3, DriverState::default())
+                .await
+                .unwrap();
+            listed.extend(page.digests);
+            match page.next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
+            }
+        }
+        listed.sort();
+        digests.sort();
+        assert_eq!(listed, digests);
+    }
+
+    #[tokio::test]
+    async fn not_found_does_not_mask_replicas() {
+        let storage1 = EvictingStorage {
+            inner: MemoryStorage::new(),
+        };
+        let storage2 = MemoryStorage::new();
+        let instance = Instance::from("main");
+        storage1.ensure_instance(&instance, DriverState::default());
+        storage2.ensure_instance(&instance, DriverState::default());
+
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            vec![(0, Box::new(storage1)), (1, Box::new(storage2))],
+            2.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+
+        let content = TestData::from_static(b"foobar");
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stream = storage
+            .read_blob(
+                instance.clone(),
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+
+        // A blob which is evicted from one shard and missing from the other is missing.
+        let other_content = TestData::from_static(b"xyzzy");
+        let result = storage
+            .read_blob(
+                instance,
+                other_content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn stat_finds_blobs_on_any_replica() {
+        let storage1 = MemoryStorage::new();
+        let storage2 = MemoryStorage::new();
+        let instance = Instance::from("main");
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            vec![
+                (0, Box::new(storage1.clone())),
+                (1, Box::new(storage2.clone())),
+            ],
+            2.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+        storage.ensure_instance(&instance, DriverState::default());
+
+        // The blob is only stored on one of its replicas.
+        let content = TestData::from_static(b"foobar");
+        let mut attempt = storage2
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let stat = storage
+            .stat(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        let expected_stat = storage2
+            .stat(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        assert!(stat.as_ref().unwrap().created.is_some());
+        assert_eq!(stat, expected_stat);
+
+        let other_content = TestData::from_static(b"xyzzy");
+        let stat = storage
+            .stat(instance, other_content.digest, DriverState::default())
+            .await
+            .unwrap();
+        assert_eq!(stat, None);
+    }
+
     #[tokio::test]
     async fn sharding_falls_back_to_replicas_without_errors() {
         let shard1_unavailable = Arc::new(AtomicBool::new(false));
         let shard2_unavailable = Arc::new(AtomicBool::new(false));
 
-        let mut storage1 = FailGatedStorage::new(MemoryStorage::new(), &shard1_unavailable);
-        let mut storage2 = FailGatedStorage::new(MemoryStorage::new(), &shard2_unavailable);
+        let storage1 = FailGatedStorage::new(MemoryStorage::new(), &shard1_unavailable);
+        let storage2 = FailGatedStorage::new(MemoryStorage::new(), &shard2_unavailable);
         let instance = Instance::from("main");
         storage1.ensure_instance(&instance, DriverState::default());
         storage2.ensure_instance(&instance, DriverState::default());
@@ -852,7 +2415,7 @@ mod tests {
 
     #[tokio::test]
     async fn handles_existing_blobs_partial() {
-        let mut storage1 = MemoryStorage::new();
+        let storage1 = MemoryStorage::new();
         let storage2 = AlwaysExistsStorage;
         let instance = Instance::from("main");
         storage1.ensure_instance(&instance, DriverState::default());
@@ -880,4 +2443,102 @@ mod tests {
         attempt.write(content1.clone()).await.unwrap();
         attempt.commit().await.unwrap();
     }
+
+    /// Label of a driver span, in the form `driver.op`.
+    struct SpanLabel(String);
+
+    #[derive(Default)]
+    struct DriverFieldVisitor(Option<String>);
+
+    impl Visit for DriverFieldVisitor {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "driver" {
+                self.0 = Some(value.to_owned());
+            }
+        }
+
+        fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+    }
+
+    /// Labels of driver spans, along with the labels of their parents.
+    type RecordedSpans = Vec<(String, Option<String>)>;
+
+    /// Records the label of each driver span which is created, along with the label of its parent.
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<RecordedSpans>>);
+
+    impl<S> Layer<S> for SpanRecorder
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut visitor = DriverFieldVisitor::default();
+            attrs.record(&mut visitor);
+            let Some(driver) = visitor.0 else {
+                return;
+            };
+            let span = ctx.span(id).unwrap();
+            let label = format!("{driver}.{}", attrs.metadata().name());
+            let parent_label = span
+                .parent()
+                .and_then(|parent| parent.extensions().get::<SpanLabel>().map(|l| l.0.clone()));
+            span.extensions_mut().insert(SpanLabel(label.clone()));
+            self.0.lock().push((label, parent_label));
+        }
+    }
+
+    #[tokio::test]
+    async fn driver_spans_nest_through_storage_stack() {
+        let base_path = tempfile::tempdir().unwrap();
+        let file_storage = FileBackedStorage::new(base_path.path(), "test", Durability::None)
+            .await
+            .unwrap();
+        let instance = Instance::from("main");
+        file_storage.ensure_instance(&instance, DriverState::default());
+
+        let storage: ShardingStorage<usize> = ShardingStorage::new(
+            vec![(0, Box::new(file_storage))],
+            1.try_into().unwrap(),
+            "test",
+            HashMap::default(),
+        );
+
+        let content = TestData::from_static(b"foobar");
+        let mut attempt = storage
+            .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+            .await
+            .unwrap();
+        attempt.write(content.bytes.clone()).await.unwrap();
+        attempt.commit().await.unwrap();
+
+        let recorder = SpanRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        // The test runtime is single-threaded, so any spawned tasks run on this thread as well.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let stream = storage
+            .read_blob(
+                instance,
+                content.digest,
+                1024,
+                None,
+                None,
+                DriverState::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+
+        assert_eq!(
+            *recorder.0.lock(),
+            vec![
+                ("sharding.read_blob".to_owned(), None),
+                (
+                    "file_backed.read_blob".to_owned(),
+                    Some("sharding.read_blob".to_owned())
+                ),
+            ]
+        );
+    }
 }
@@ -2,52 +2,43 @@
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::future;
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    list_blobs_in_turn, BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance,
+    StorageError, StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
-/// A `BlobStorage` that sends blobs smaller than the `split_size` to one `BlobStorage`
-/// implementation and blobs equal to or larger than the `split_size` to another
-/// `BlobStorage` implementation.
+type BoxBlobStorage = Box<dyn BlobStorage + Send + Sync + 'static>;
+
+/// A `BlobStorage` that sends each blob to one of several `BlobStorage` implementations
+/// depending on its size. Each tier has a threshold, and a blob is sent to the first tier
+/// (in increasing order of threshold) whose threshold is larger than the blob's size. Blobs
+/// equal to or larger than every threshold are sent to the fallback storage.
 ///
 /// This is useful for putting smaller (and/or larger blobs) into storage backends that are
 /// more efficient for those blobs given their sizes.
-pub struct SizeSplitStorage<LT, GE> {
-    split_size: usize,
-    storage1: LT,
-    storage2: GE,
+pub struct SizeSplitStorage {
+    tiers: Vec<(usize, BoxBlobStorage)>,
+    fallback: BoxBlobStorage,
 }
 
 #[async_trait]
-impl<LT, GE> BlobStorage for SizeSplitStorage<LT, GE>
-where
-    LT: BlobStorage + Send + Sync + 'static,
-    GE: BlobStorage + Send + Sync + 'static,
-{
+impl BlobStorage for SizeSplitStorage {
     async fn find_missing_blobs(
         &self,
         instance: Instance,
         digests: Vec<Digest>,
         state: DriverState,
     ) -> Result<Vec<Digest>, StorageError> {
-        let (digests1, digests2) = digests
-            .into_iter()
-            .partition(|d| d.size_bytes < self.split_size);
-        let (missing_digests1, missing_digest2) = future::try_join(
-            self.storage1
-                .find_missing_blobs(instance.clone(), digests1, state.clone()),
-            self.storage2.find_missing_blobs(instance, digests2, state),
-        )
-        .await?;
-        let missing_digests = missing_digests1
-            .into_iter()
-            .chain(missing_digest2)
-            .collect();
-        Ok(missing_digests)
+        let missing_digests =
+            future::try_join_all(self.partition(digests).map(|(storage, digests)| {
+                storage.find_missing_blobs(instance.clone(), digests, state.clone())
+            }))
+            .await?;
+        Ok(missing_digests.into_iter().flatten().collect())
     }
 
     async fn read_blob(
@@ -59,29 +50,61 @@ where
         read_limit: Option<usize>,
         state: DriverState,
     ) -> Result<Option<BoxReadStream>, StorageError> {
-        if digest.size_bytes < self.split_size {
-            self.storage1
-                .read_blob(
-                    instance,
-                    digest,
-                    max_batch_size,
-                    read_offset,
-                    read_limit,
-                    state,
-                )
-                .await
-        } else {
-            self.storage2
-                .read_blob(
-                    instance,
-                    digest,
-                    max_batch_size,
-                    read_offset,
-                    read_limit,
-                    state,
-                )
-                .await
+        self.storage_for(&digest)
+            .read_blob(
+                instance,
+                digest,
+                max_batch_size,
+                read_offset,
+                read_limit,
+                state,
+            )
+            .await
+    }
+
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        let mut partitions = vec![Vec::new(); self.tiers.len() + 1];
+        for digest in &digests {
+            partitions[self.tier_index(digest)].push(*digest);
         }
+        let batches =
+            future::try_join_all(self.storages().zip(partitions).map(|(storage, digests)| {
+                let (instance, state) = (instance.clone(), state.clone());
+                async move {
+                    if digests.is_empty() {
+                        return Ok(Vec::new());
+                    }
+                    storage.read_blobs(instance, digests, state).await
+                }
+            }))
+            .await?;
+
+        // Each tier returns its results in order, so take them in the order they were requested.
+        let mut batches = batches.into_iter().map(Vec::into_iter).collect::<Vec<_>>();
+        Ok(digests
+            .iter()
+            .map(|digest| {
+                batches[self.tier_index(digest)]
+                    .next()
+                    .expect("result for each digest")
+            })
+            .collect())
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.storage_for(&digest)
+            .stat(instance, digest, state)
+            .await
     }
 
     async fn begin_write_blob(
@@ -90,41 +113,119 @@ where
         digest: Digest,
         state: DriverState,
     ) -> Result<Box<dyn WriteAttemptOps + Send + Sync>, StreamingWriteError> {
-        if digest.size_bytes < self.split_size {
-            self.storage1
-                .begin_write_blob(instance, digest, state)
-                .await
-        } else {
-            self.storage2
-                .begin_write_blob(instance, digest, state)
-                .await
-        }
+        self.storage_for(&digest)
+            .begin_write_blob(instance, digest, state)
+            .await
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
-        self.storage1.ensure_instance(instance, state.clone());
-        self.storage2.ensure_instance(instance, state);
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let deleted_digests =
+            future::try_join_all(self.partition(digests).map(|(storage, digests)| {
+                storage.delete_blobs(instance.clone(), digests, state.clone())
+            }))
+            .await?;
+        Ok(deleted_digests.into_iter().flatten().collect())
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        let digests = future::try_join_all(
+            self.storages()
+                .map(|storage| storage.list_recent_blobs(instance.clone(), limit, state.clone())),
+        )
+        .await?;
+        // The storages do not share an ordering, so just fill up to `limit` from each in turn.
+        let mut digests = digests.into_iter().flatten().collect::<Vec<_>>();
+        digests.truncate(limit);
+        Ok(digests)
     }
-}
 
-impl<LT, GE> SizeSplitStorage<LT, GE>
-where
-    LT: BlobStorage + Send + Sync + 'static,
-    GE: BlobStorage + Send + Sync + 'static,
-{
-    /// Create a new `SizeSwitchStorage` with the specified `split_size` and child
-    /// storage implementations.
-    pub fn new(split_size: usize, storage1: LT, storage2: GE) -> Self {
-        SizeSplitStorage {
-            split_size,
-            storage1,
-            storage2,
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        let storages = self
+            .storages()
+            .map(|storage| &**storage)
+            .collect::<Vec<_>>();
+        list_blobs_in_turn(&storages, instance, cursor, page_size, state).await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        for (_, storage) in &self.tiers {
+            storage.ensure_instance(instance, state.clone());
         }
+        self.fallback.ensure_instance(instance, state);
+    }
+}
+
+impl SizeSplitStorage {
+    /// Create a new `SizeSplitStorage` which sends blobs smaller than `split_size` to `storage1`
+    /// and all other blobs to `storage2`.
+    pub fn new<LT, GE>(split_size: usize, storage1: LT, storage2: GE) -> Self
+    where
+        LT: BlobStorage + Send + Sync + 'static,
+        GE: BlobStorage + Send + Sync + 'static,
+    {
+        Self::with_thresholds(vec![(split_size, Box::new(storage1))], Box::new(storage2))
     }
 
-    /// Consume this `BlobStorage` and return the child storage implementations.
-    pub fn into_inner(self) -> (LT, GE) {
-        (self.storage1, self.storage2)
+    /// Create a new `SizeSplitStorage` with the given tiers of (threshold, storage) and the
+    /// `fallback` storage for blobs which are too large for every tier.
+    pub fn with_thresholds(
+        mut tiers: Vec<(usize, BoxBlobStorage)>,
+        fallback: BoxBlobStorage,
+    ) -> Self {
+        tiers.sort_by_key(|(threshold, _)| *threshold);
+        SizeSplitStorage { tiers, fallback }
+    }
+
+    /// The index of the tier for `digest`, where `self.tiers.len()` is the fallback storage.
+    fn tier_index(&self, digest: &Digest) -> usize {
+        self.tiers
+            .iter()
+            .position(|(threshold, _)| digest.size_bytes < *threshold)
+            .unwrap_or(self.tiers.len())
+    }
+
+    fn storage_for(&self, digest: &Digest) -> &BoxBlobStorage {
+        self.storages()
+            .nth(self.tier_index(digest))
+            .expect("tier index is in range")
+    }
+
+    /// All of the storages, in tier order followed by the fallback storage.
+    fn storages(&self) -> impl Iterator<Item = &BoxBlobStorage> {
+        self.tiers
+            .iter()
+            .map(|(_, storage)| storage)
+            .chain(std::iter::once(&self.fallback))
+    }
+
+    /// Group `digests` by the storage responsible for them, skipping storages without any.
+    fn partition(
+        &self,
+        digests: Vec<Digest>,
+    ) -> impl Iterator<Item = (&BoxBlobStorage, Vec<Digest>)> {
+        let mut partitions = vec![Vec::new(); self.tiers.len() + 1];
+        for digest in digests {
+            partitions[self.tier_index(&digest)].push(digest);
+        }
+        self.storages()
+            .zip(partitions)
+            .filter(|(_, digests)| !digests.is_empty())
     }
 }
 
@@ -139,10 +240,10 @@ mod tests {
         let content1 = TestData::from_static(b"foobar");
         let content2 = TestData::from_static(b"foobarxyzzy");
 
-        let child_storage1 = MemoryStorage::new();
-        let child_storage2 = MemoryStorage::new();
-        let mut storage =
-            SizeSplitStorage::new(1 + content1.bytes.len(), child_storage1, child_storage2);
+        let storage1 = MemoryStorage::new();
+        let storage2 = MemoryStorage::new();
+        let storage =
+            SizeSplitStorage::new(1 + content1.bytes.len(), storage1.clone(), storage2.clone());
 
         let instance = Instance::from("main");
         storage.ensure_instance(&instance, DriverState::default());
@@ -206,9 +307,8 @@ mod tests {
         let actual_content2 = consolidate_stream(stream).await.unwrap();
         assert_eq!(content2.bytes, actual_content2);
 
-        // Finally, recover the child storage implementations and confirm that each only contains
-        // the expected blobs.
-        let (storage1, storage2) = storage.into_inner();
+        // Finally, confirm that each of the child storage implementations only contains the
+        // expected blobs.
         let missing_blobs = storage1
             .find_missing_blobs(
                 instance.clone(),
@@ -228,4 +328,117 @@ mod tests {
             .unwrap();
         assert_eq!(missing_blobs, vec![content1.digest]);
     }
+
+    #[tokio::test]
+    async fn routes_blobs_by_threshold() {
+        let small = TestData::from_static(b"foo");
+        let medium = TestData::from_static(b"foobarxyzzy");
+        let huge = TestData::from_static(b"foobarxyzzygrokquux");
+
+        let small_storage = MemoryStorage::new();
+        let medium_storage = MemoryStorage::new();
+        let huge_storage = MemoryStorage::new();
+        // Tiers are given out of order to check that they are sorted by threshold.
+        let storage = SizeSplitStorage::with_thresholds(
+            vec![
+                (16, Box::new(medium_storage.clone())),
+                (4, Box::new(small_storage.clone())),
+            ],
+            Box::new(huge_storage.clone()),
+        );
+
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        let all_digests = vec![small.digest, medium.digest, huge.digest];
+        for content in [&small, &medium, &huge] {
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+
+        let missing_blobs = storage
+            .find_missing_blobs(
+                instance.clone(),
+                all_digests.clone(),
+                DriverState::default(),
+            )
+            .await
+            .unwrap();
+        assert!(missing_blobs.is_empty());
+
+        for content in [&small, &medium, &huge] {
+            let stream = storage
+                .read_blob(
+                    instance.clone(),
+                    content.digest,
+                    1024,
+                    None,
+                    None,
+                    DriverState::default(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(consolidate_stream(stream).await.unwrap(), content.bytes);
+        }
+
+        // Each child storage only contains the blob for its tier.
+        for (child_storage, content) in [
+            (small_storage, &small),
+            (medium_storage, &medium),
+            (huge_storage, &huge),
+        ] {
+            let mut expected_missing = all_digests.clone();
+            expected_missing.retain(|digest| *digest != content.digest);
+            let missing_blobs = child_storage
+                .find_missing_blobs(
+                    instance.clone(),
+                    all_digests.clone(),
+                    DriverState::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(missing_blobs, expected_missing);
+        }
+    }
+
+    #[tokio::test]
+    async fn stat_routes_to_tier() {
+        let small = TestData::from_static(b"foo");
+        let large = TestData::from_static(b"foobarxyzzy");
+
+        let small_storage = MemoryStorage::new();
+        let large_storage = MemoryStorage::new();
+        let storage = SizeSplitStorage::new(4, small_storage.clone(), large_storage.clone());
+
+        let instance = Instance::from("main");
+        storage.ensure_instance(&instance, DriverState::default());
+
+        for content in [&small, &large] {
+            let mut attempt = storage
+                .begin_write_blob(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            attempt.write(content.bytes.clone()).await.unwrap();
+            attempt.commit().await.unwrap();
+        }
+
+        // The stat of each blob comes from the storage for its tier, including its timestamps.
+        for (child_storage, content) in [(small_storage, &small), (large_storage, &large)] {
+            let stat = storage
+                .stat(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            let expected_stat = child_storage
+                .stat(instance.clone(), content.digest, DriverState::default())
+                .await
+                .unwrap();
+            assert!(stat.as_ref().unwrap().created.is_some());
+            assert_eq!(stat, expected_stat);
+        }
+    }
 }
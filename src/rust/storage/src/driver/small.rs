@@ -5,10 +5,11 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
+use futures::{future, FutureExt};
 
 use crate::driver::{
-    BlobStorage, BoxReadStream, DriverState, Instance, StorageError, StreamingWriteError,
-    WriteAttemptOps,
+    BlobEncoding, BlobPage, BlobStat, BlobStorage, BoxReadStream, DriverState, Instance,
+    StorageError, StreamingWriteError, WriteAttemptOps,
 };
 use crate::Digest;
 
@@ -35,6 +36,28 @@ pub trait SmallBlobStorage {
         state: DriverState,
     ) -> Result<Option<Bytes>, StorageError>;
 
+    /// Return metadata about the blob for `digest`, as for `BlobStorage::stat`. The default
+    /// implementation only determines whether the blob is present.
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        let missing = self
+            .find_missing_blobs(instance, vec![digest], state)
+            .await?;
+        if !missing.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(BlobStat {
+            size_bytes: digest.size_bytes,
+            last_accessed: None,
+            created: None,
+            encoding: BlobEncoding::Raw,
+        }))
+    }
+
     /// Store the blob provided in the given `Bytes`.
     async fn write_blob(
         &self,
@@ -43,6 +66,30 @@ pub trait SmallBlobStorage {
         content: Bytes,
         state: DriverState,
     ) -> Result<(), StorageError>;
+
+    /// List the digests of the blobs stored for `instance` a page at a time, as for
+    /// `BlobStorage::list_blobs`.
+    async fn list_blobs(
+        &self,
+        _instance: Instance,
+        _cursor: Option<String>,
+        _page_size: usize,
+        _state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        Err(StorageError::Unimplemented(
+            "This storage driver does not support listing blobs.".to_owned(),
+        ))
+    }
+
+    /// The maximum size of blob which this driver will store, if any. `SmallBlobStorageAdapter`
+    /// rejects writes of larger blobs before buffering them.
+    fn max_blob_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Ensure the driver is setup to receive instances with the name `instance`, as for
+    /// `BlobStorage::ensure_instance`.
+    fn ensure_instance(&self, _instance: &Instance, _state: DriverState) {}
 }
 
 #[async_trait]
@@ -68,6 +115,15 @@ where
         (**self).read_blob(instance, digest, state).await
     }
 
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        (**self).stat(instance, digest, state).await
+    }
+
     async fn write_blob(
         &self,
         instance: Instance,
@@ -77,6 +133,26 @@ where
     ) -> Result<(), StorageError> {
         (**self).write_blob(instance, digest, content, state).await
     }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        (**self)
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn max_blob_size(&self) -> Option<usize> {
+        (**self).max_blob_size()
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        (**self).ensure_instance(instance, state)
+    }
 }
 
 /// Adapts a `SmallBlobStorage` into a `BlobStorage`
@@ -160,12 +236,42 @@ where
         Ok(Some(stream))
     }
 
+    async fn read_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<(Digest, Result<Option<Bytes>, StorageError>)>, StorageError> {
+        // The inner storage returns whole blobs, so there are no streams to consolidate.
+        let reads = digests.into_iter().map(|digest| {
+            self.inner
+                .read_blob(instance.clone(), digest, state.clone())
+                .map(move |result| (digest, result))
+        });
+        Ok(future::join_all(reads).await)
+    }
+
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.inner.stat(instance, digest, state).await
+    }
+
     async fn begin_write_blob(
         &self,
         instance: Instance,
         digest: Digest,
         state: DriverState,
     ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        // Writes are buffered in full before being stored, so reject blobs which the driver would
+        // not store before buffering them.
+        if let Some(max_blob_size) = self.inner.max_blob_size() {
+            check_blob_size(digest.size_bytes, max_blob_size)?;
+        }
+
         let attempt = WriteAttempt {
             instance,
             digest,
@@ -175,6 +281,22 @@ where
         };
         Ok(Box::new(attempt) as Box<dyn WriteAttemptOps + Send + Sync + 'static>)
     }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.inner
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.inner.ensure_instance(instance, state)
+    }
 }
 
 #[async_trait]
@@ -227,6 +349,16 @@ where
     }
 }
 
+/// Fail with `ResourceExhausted` if a blob of `size` bytes exceeds `max_blob_size`.
+pub(crate) fn check_blob_size(size: usize, max_blob_size: usize) -> Result<(), StorageError> {
+    if size > max_blob_size {
+        return Err(StorageError::ResourceExhausted(format!(
+            "Blob of {size} bytes exceeds the maximum of {max_blob_size} bytes for this storage"
+        )));
+    }
+    Ok(())
+}
+
 /// Adapts a `BlobStorage` into a `SmallBlobStorage`.
 ///
 /// NB: This type is a convenience, but it will generally be more efficient to directly implement
@@ -290,6 +422,15 @@ where
         Ok(Some(crate::bytes::consolidate_stream(stream).await?))
     }
 
+    async fn stat(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Option<BlobStat>, StorageError> {
+        self.inner.stat(instance, digest, state).await
+    }
+
     async fn write_blob(
         &self,
         instance: Instance,
@@ -309,6 +450,22 @@ where
             .await
             .or_else(StreamingWriteError::ok_if_already_exists)
     }
+
+    async fn list_blobs(
+        &self,
+        instance: Instance,
+        cursor: Option<String>,
+        page_size: usize,
+        state: DriverState,
+    ) -> Result<BlobPage, StorageError> {
+        self.inner
+            .list_blobs(instance, cursor, page_size, state)
+            .await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.inner.ensure_instance(instance, state)
+    }
 }
 
 #[cfg(test)]
@@ -326,7 +483,7 @@ mod tests {
         let content = TestData::from_static(b"foobarxyzzy");
 
         let storage = SmallMemoryStorage::new();
-        let mut storage = SmallBlobStorageAdapter::new(storage);
+        let storage = SmallBlobStorageAdapter::new(storage);
         storage.ensure_instance(&instance, DriverState::default());
 
         let missing_blobs = storage
@@ -405,7 +562,7 @@ mod tests {
         let instance = Instance::from("main");
         let content = TestData::from_static(b"foobarxyzzy");
 
-        let mut storage = MemoryStorage::new();
+        let storage = MemoryStorage::new();
         // TODO: `ensure_instance` is unused except for in `MemoryStorage`: `BlobStorageAdapter`
         // will not call it.
         storage.ensure_instance(&instance, DriverState::default());
@@ -3,11 +3,15 @@
 
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Once};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use parking_lot::Mutex;
+use metrics::{
+    Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Recorder, SharedString, Unit,
+};
+use parking_lot::{const_mutex, Mutex};
 use tokio::sync::Semaphore;
 
 use crate::driver::{
@@ -40,6 +44,8 @@ pub struct CountMethodCallsStorage<S> {
     pub find_missing_blobs_count: Arc<AtomicUsize>,
     pub read_count: Arc<AtomicUsize>,
     pub write_count: Arc<AtomicUsize>,
+    pub list_recent_blobs_count: Arc<AtomicUsize>,
+    pub ensure_instance_count: Arc<AtomicUsize>,
 }
 
 #[async_trait]
@@ -91,7 +97,18 @@ where
         self.inner.begin_write_blob(instance, digest, state).await
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.list_recent_blobs_count.fetch_add(1, Ordering::SeqCst);
+        self.inner.list_recent_blobs(instance, limit, state).await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.ensure_instance_count.fetch_add(1, Ordering::SeqCst);
         self.inner.ensure_instance(instance, state);
     }
 }
@@ -135,6 +152,11 @@ where
             .write_blob(instance, digest, content, state)
             .await
     }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.ensure_instance_count.fetch_add(1, Ordering::SeqCst);
+        self.inner.ensure_instance(instance, state);
+    }
 }
 
 impl<S> CountMethodCallsStorage<S> {
@@ -144,6 +166,8 @@ impl<S> CountMethodCallsStorage<S> {
             find_missing_blobs_count: Arc::new(AtomicUsize::new(0)),
             read_count: Arc::new(AtomicUsize::new(0)),
             write_count: Arc::new(AtomicUsize::new(0)),
+            list_recent_blobs_count: Arc::new(AtomicUsize::new(0)),
+            ensure_instance_count: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -197,7 +221,7 @@ impl BlobStorage for AlwaysExistsStorage {
         Err(StreamingWriteError::AlreadyExists)
     }
 
-    fn ensure_instance(&mut self, _: &Instance, _: DriverState) {}
+    fn ensure_instance(&self, _: &Instance, _: DriverState) {}
 }
 
 #[derive(Clone)]
@@ -253,6 +277,124 @@ impl SmallBlobStorage for SmallMemoryStorage {
     }
 }
 
+/// Delays each `BlobStorage` call by a fixed latency before delegating to the inner storage.
+///
+/// Once `fail_after` calls have been made, further calls fail with `StorageError::Unavailable`
+/// (after the delay), which allows tests of timeouts and error handling to be deterministic.
+#[derive(Clone, Debug)]
+pub struct SlowStorage<S> {
+    inner: S,
+    latency: Duration,
+    fail_after: Option<usize>,
+    call_count: Arc<AtomicUsize>,
+}
+
+impl<S> SlowStorage<S> {
+    pub fn new(inner: S, latency: Duration) -> Self {
+        Self {
+            inner,
+            latency,
+            fail_after: None,
+            call_count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Fail every call after the first `fail_after` calls with `StorageError::Unavailable`.
+    pub fn failing_after(mut self, fail_after: usize) -> Self {
+        self.fail_after = Some(fail_after);
+        self
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.call_count.load(Ordering::SeqCst)
+    }
+
+    async fn delay(&self) -> Result<(), StorageError> {
+        let call_count = self.call_count.fetch_add(1, Ordering::SeqCst) + 1;
+        tokio::time::sleep(self.latency).await;
+        match self.fail_after {
+            Some(fail_after) if call_count > fail_after => Err(StorageError::Unavailable(format!(
+                "SlowStorage failing after {fail_after} calls"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> BlobStorage for SlowStorage<S>
+where
+    S: BlobStorage + Send + Sync + 'static,
+{
+    async fn find_missing_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.delay().await?;
+        self.inner
+            .find_missing_blobs(instance, digests, state)
+            .await
+    }
+
+    async fn read_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        max_batch_size: usize,
+        read_offset: Option<usize>,
+        read_limit: Option<usize>,
+        state: DriverState,
+    ) -> Result<Option<BoxReadStream>, StorageError> {
+        self.delay().await?;
+        self.inner
+            .read_blob(
+                instance,
+                digest,
+                max_batch_size,
+                read_offset,
+                read_limit,
+                state,
+            )
+            .await
+    }
+
+    async fn begin_write_blob(
+        &self,
+        instance: Instance,
+        digest: Digest,
+        state: DriverState,
+    ) -> Result<Box<dyn WriteAttemptOps + Send + Sync + 'static>, StreamingWriteError> {
+        self.delay().await?;
+        self.inner.begin_write_blob(instance, digest, state).await
+    }
+
+    async fn delete_blobs(
+        &self,
+        instance: Instance,
+        digests: Vec<Digest>,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.delay().await?;
+        self.inner.delete_blobs(instance, digests, state).await
+    }
+
+    async fn list_recent_blobs(
+        &self,
+        instance: Instance,
+        limit: usize,
+        state: DriverState,
+    ) -> Result<Vec<Digest>, StorageError> {
+        self.delay().await?;
+        self.inner.list_recent_blobs(instance, limit, state).await
+    }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.inner.ensure_instance(instance, state);
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum WriteSemaphoreOperation {
     Increment,
@@ -334,7 +476,7 @@ where
         }))
     }
 
-    fn ensure_instance(&mut self, instance: &Instance, state: DriverState) {
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
         self.inner.ensure_instance(instance, state);
     }
 }
@@ -396,6 +538,10 @@ where
         self.operation.execute(self.semaphore.clone()).await?;
         Ok(())
     }
+
+    fn ensure_instance(&self, instance: &Instance, state: DriverState) {
+        self.inner.ensure_instance(instance, state);
+    }
 }
 
 impl<S> WriteSemaphoreStorage<S> {
@@ -423,3 +569,88 @@ impl<S> WriteSemaphoreStorage<S> {
         &self.inner
     }
 }
+
+/// Samples recorded for every histogram since `capture_metrics` was first called.
+static HISTOGRAM_SAMPLES: Mutex<Vec<(Key, f64)>> = const_mutex(Vec::new());
+
+/// Increments of every counter since `capture_metrics` was first called.
+static COUNTER_INCREMENTS: Mutex<Vec<(Key, u64)>> = const_mutex(Vec::new());
+
+static INSTALL_METRICS_CAPTURE: Once = Once::new();
+
+/// Metrics recorder which captures the samples of every histogram into `HISTOGRAM_SAMPLES` and
+/// the increments of every counter into `COUNTER_INCREMENTS`.
+struct MetricsCapture;
+
+struct CapturedHistogram(Key);
+
+impl HistogramFn for CapturedHistogram {
+    fn record(&self, value: f64) {
+        HISTOGRAM_SAMPLES.lock().push((self.0.clone(), value));
+    }
+}
+
+struct CapturedCounter(Key);
+
+impl CounterFn for CapturedCounter {
+    fn increment(&self, value: u64) {
+        COUNTER_INCREMENTS.lock().push((self.0.clone(), value));
+    }
+
+    fn absolute(&self, _: u64) {}
+}
+
+impl Recorder for MetricsCapture {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        Counter::from_arc(Arc::new(CapturedCounter(key.clone())))
+    }
+
+    fn register_gauge(&self, _: &Key) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        Histogram::from_arc(Arc::new(CapturedHistogram(key.clone())))
+    }
+}
+
+/// Install a global metrics recorder which captures histogram samples and counter increments.
+/// Since only one recorder can be installed per process, tests share it and should use labels
+/// (e.g., the backend name) which are unique to the test when reading captured metrics.
+pub fn capture_metrics() {
+    INSTALL_METRICS_CAPTURE.call_once(|| {
+        metrics::set_boxed_recorder(Box::new(MetricsCapture))
+            .expect("metrics recorder already installed");
+    });
+}
+
+fn has_labels(key: &Key, labels: &[(&str, &str)]) -> bool {
+    labels.iter().all(|(label_key, label_value)| {
+        key.labels()
+            .any(|l| l.key() == *label_key && l.value() == *label_value)
+    })
+}
+
+/// Return the captured samples of the histogram `name` which has all of the given labels.
+pub fn histogram_samples(name: &str, labels: &[(&str, &str)]) -> Vec<f64> {
+    HISTOGRAM_SAMPLES
+        .lock()
+        .iter()
+        .filter(|(key, _)| key.name() == name && has_labels(key, labels))
+        .map(|(_, value)| *value)
+        .collect()
+}
+
+/// Return the total captured increments of the counter `name` which has all of the given labels.
+pub fn counter_total(name: &str, labels: &[(&str, &str)]) -> u64 {
+    COUNTER_INCREMENTS
+        .lock()
+        .iter()
+        .filter(|(key, _)| key.name() == name && has_labels(key, labels))
+        .map(|(_, value)| *value)
+        .sum()
+}
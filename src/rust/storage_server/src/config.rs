@@ -2,40 +2,165 @@
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
 use std::collections::HashMap;
-use std::num::NonZeroUsize;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::str::FromStr;
 
 use grpc_util::infra::{GrpcConfig, InfraConfig};
+use grpc_util::secrets::SecretsConfig;
 use serde::Deserialize;
+use storage::driver::{ChunkingStrategy, Durability};
 
 /// Preferred size of chunks written to storage.
 pub const DEFAULT_CHUNK_SIZE: usize = 512 * 1024;
 
+/// Maximum number of digests checked concurrently by a single `FindMissingBlobs` call to the
+/// chunked Redis driver.
+pub const DEFAULT_REDIS_FIND_MISSING_CONCURRENCY: usize = 64;
+
+/// How long the existence cache caches missing digests for, if enabled.
+pub const DEFAULT_EXISTENCE_CACHE_MISSING_TTL_MS: u64 = 1000;
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct LocalBlobStorageConfig {
     /// Base path under which to store blobs.
     pub base_path: String,
+
+    /// Whether to verify the content of blobs against their digests as they are read, deleting
+    /// any corrupt blobs.
+    #[serde(default)]
+    pub verify_reads: bool,
+
+    /// Whether to fsync blobs (`fsync`), and also their directories (`fsync_dir`), when they
+    /// are written. Defaults to `none`.
+    #[serde(default)]
+    pub durability: Durability,
 }
 
 #[derive(Clone, Deserialize, Debug)]
-pub struct SizeSplitStorageConfig {
-    /// Blobs less than this size will be stored in the `smaller` storage.
+pub struct SizeSplitTierConfig {
+    /// Blobs less than this size (and not in a tier with a smaller size) will be stored in
+    /// this tier's storage.
     pub size: usize,
 
-    /// Storage for "smaller" blobs.
+    /// Storage for blobs in this tier.
     #[serde(with = "serde_yaml::with::singleton_map")]
-    pub smaller: Box<BlobStorageConfig>,
+    pub storage: Box<BlobStorageConfig>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct SizeSplitStorageConfig {
+    /// Blobs less than this size will be stored in the `smaller` storage. Must be set together
+    /// with `smaller`, unless `tiers` is used instead.
+    pub size: Option<usize>,
 
-    /// Storage for "larger" blobs.
+    /// Storage for "smaller" blobs.
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub smaller: Option<Box<BlobStorageConfig>>,
+
+    /// Additional size tiers, for splitting blobs between more than two storages.
+    #[serde(default)]
+    pub tiers: Vec<SizeSplitTierConfig>,
+
+    /// Storage for "larger" blobs, i.e. those not less than any of the sizes.
     #[serde(with = "serde_yaml::with::singleton_map")]
     pub larger: Box<BlobStorageConfig>,
 }
 
+impl SizeSplitStorageConfig {
+    /// The size threshold and storage config of each tier, smallest first, not including the
+    /// `larger` storage.
+    pub fn tier_configs(&self) -> Result<Vec<(usize, Box<BlobStorageConfig>)>, String> {
+        let mut tier_configs = Vec::with_capacity(self.tiers.len() + 1);
+        match (self.size, &self.smaller) {
+            (Some(size), Some(smaller)) => tier_configs.push((size, smaller.clone())),
+            (None, None) => (),
+            _ => return Err("size_split: `size` and `smaller` must be set together".to_owned()),
+        }
+        tier_configs.extend(
+            self.tiers
+                .iter()
+                .map(|tier| (tier.size, tier.storage.clone())),
+        );
+        if tier_configs.is_empty() {
+            return Err("size_split: one of `size` or `tiers` must be set".to_owned());
+        }
+        Ok(tier_configs)
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct ExistenceCacheStorageConfig {
     /// Maximum of number of digests to cache.
     pub max_entries: NonZeroUsize,
 
+    /// Number of recently written digests to load into the cache on startup, for underlying
+    /// storage which can list its blobs. Defaults to 0 (no warmup).
+    #[serde(default)]
+    pub warmup_entries: usize,
+
+    /// Instances whose cache entries are warmed up on startup.
+    #[serde(default)]
+    pub warmup_instances: Vec<String>,
+
+    /// Also cache digests which are missing from the underlying storage. Off by default since a
+    /// cached absence hides writes made through other storage servers until it expires.
+    #[serde(default)]
+    pub cache_missing: bool,
+
+    /// How long to cache missing digests for in milliseconds, if `cache_missing` is set.
+    pub missing_ttl_ms: Option<u64>,
+
+    /// The underlying storage driver.
+    #[serde(with = "serde_yaml::with::singleton_map")]
+    pub underlying: Box<BlobStorageConfig>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ConcurrencyLimitStorageConfig {
+    /// Maximum number of operations allowed in-flight to the underlying storage driver.
+    pub max_in_flight: usize,
+
+    /// Time to wait for an in-flight slot in milliseconds before failing the operation
+    /// as unavailable.
+    pub acquire_timeout_ms: u64,
+
+    /// The underlying storage driver.
+    #[serde(with = "serde_yaml::with::singleton_map")]
+    pub underlying: Box<BlobStorageConfig>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct EncryptedStorageConfig {
+    /// Name of the secret (see `secrets`) holding the hex-encoded 256-bit AES key with which
+    /// blobs are encrypted.
+    pub key_ref: String,
+
+    /// The underlying storage driver, which stores the encrypted blobs.
+    #[serde(with = "serde_yaml::with::singleton_map")]
+    pub underlying: Box<BlobStorageConfig>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ReadDigestVerifierConfig {
+    /// Delete blobs whose content does not match their digest when fully read, so that clients
+    /// may rewrite them. Off by default.
+    #[serde(default)]
+    pub repair_on_mismatch: bool,
+
+    /// The underlying storage driver.
+    #[serde(with = "serde_yaml::with::singleton_map")]
+    pub underlying: Box<BlobStorageConfig>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct RetryStorageConfig {
+    /// Maximum number of attempts (including the first) for operations which fail as unavailable.
+    pub max_attempts: NonZeroU32,
+
+    /// Base delay between attempts in milliseconds. The delay grows exponentially (with jitter)
+    /// with each retry.
+    pub base_delay_ms: u64,
+
     /// The underlying storage driver.
     #[serde(with = "serde_yaml::with::singleton_map")]
     pub underlying: Box<BlobStorageConfig>,
@@ -56,8 +181,19 @@ pub struct RedisBackendConfig {
     /// Number of connections to use for new client.
     pub num_connections: Option<usize>,
 
+    /// Minimum number of connections which must be established at startup for each endpoint.
+    /// Defaults to 1.
+    pub min_healthy_connections: Option<usize>,
+
+    /// Interval in seconds after which idle connections are sent a PING to verify they are
+    /// still alive. Defaults to 30 seconds.
+    pub health_check_interval_secs: Option<u64>,
+
     /// Probability of using primary for read-only traffic out of denominator of 1000.
     pub use_primary_for_read_only_probability: Option<usize>,
+
+    /// Name of the secret (see `secrets`) holding the password for the Redis cluster.
+    pub password_secret: Option<String>,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -68,8 +204,41 @@ pub struct RedisChunkedStorageConfig {
     /// Preferred size of written data chunks.
     pub write_chunk_size: Option<usize>,
 
+    /// Split written data into content-defined chunks instead of chunks of `write_chunk_size`.
+    pub content_defined_chunking: Option<ContentDefinedChunkingConfig>,
+
     /// Prefix to prepend to all Redis keys.
     pub prefix: Option<String>,
+
+    /// Maximum number of digests checked concurrently by a single `FindMissingBlobs` call.
+    pub find_missing_concurrency: Option<NonZeroUsize>,
+}
+
+impl RedisChunkedStorageConfig {
+    pub fn chunking_strategy(&self) -> Result<ChunkingStrategy, String> {
+        let chunking_strategy = match &self.content_defined_chunking {
+            Some(cdc) => ChunkingStrategy::ContentDefined {
+                min: cdc.min_chunk_size,
+                avg: cdc.avg_chunk_size,
+                max: cdc.max_chunk_size,
+            },
+            None => ChunkingStrategy::Fixed(self.write_chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE)),
+        };
+        chunking_strategy.validate()?;
+        Ok(chunking_strategy)
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ContentDefinedChunkingConfig {
+    /// Minimum size of a chunk (except for the final chunk of a blob).
+    pub min_chunk_size: usize,
+
+    /// Target average size of a chunk.
+    pub avg_chunk_size: usize,
+
+    /// Maximum size of a chunk.
+    pub max_chunk_size: usize,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -79,6 +248,11 @@ pub struct RedisDirectStorageConfig {
 
     /// Prefix to prepend to all Redis keys.
     pub prefix: Option<String>,
+
+    /// Maximum size in bytes of a blob to store. Larger blobs are rejected rather than buffered,
+    /// so that a misconfigured `size_split` cannot send large blobs to this storage. When used as
+    /// a smaller tier of `size_split`, this should be at least that tier's `size`.
+    pub max_blob_size: Option<usize>,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -89,6 +263,11 @@ pub struct DarkLaunchConfig {
     /// Write to secondary backend when enabled.
     pub write_to_secondary: Option<bool>,
 
+    /// Probability (between 0 and 1) of comparing a read served by storage2 against storage1.
+    /// Defaults to 0 (no comparison).
+    #[serde(default)]
+    pub compare_probability: f64,
+
     /// Storage #1
     #[serde(with = "serde_yaml::with::singleton_map")]
     pub storage1: Box<BlobStorageConfig>,
@@ -98,6 +277,18 @@ pub struct DarkLaunchConfig {
     pub storage2: Box<BlobStorageConfig>,
 }
 
+impl DarkLaunchConfig {
+    pub fn validate_compare_probability(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.compare_probability) {
+            return Err(format!(
+                "dark_launch compare_probability must be between 0 and 1, got {}",
+                self.compare_probability
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct ShardConfig {
     /// Shard key to use for this shard. This will be hashed to determine the subset of
@@ -118,6 +309,18 @@ pub struct ShardedStorageConfig {
     /// keys. With `num_replicas` == 2, a key would be distributed to the k'th and k+1'th
     /// shards.
     pub num_replicas: usize,
+
+    /// How long a shard may take to write a chunk of a blob before it is dropped from that write,
+    /// in milliseconds. If unset, writes wait for every shard.
+    pub write_timeout_ms: Option<u64>,
+}
+
+impl ShardedStorageConfig {
+    pub fn key_replicas(&self) -> Result<NonZeroUsize, String> {
+        self.num_replicas
+            .try_into()
+            .map_err(|_| "num_replicas must be non-zero".to_string())
+    }
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -142,7 +345,8 @@ pub struct AmberfloBackendConfig {
     /// Prefix to add to all customer IDs.
     pub customer_id_prefix: String,
 
-    /// File that stores the API key (JSON object with `api_key` key.)
+    /// Name of the secret (see `secrets`) that stores the API key (JSON object with `api_key`
+    /// key.) By default this is a file path.
     pub api_key_file: String,
 
     /// Duration of the aggregation window in seconds.
@@ -153,6 +357,11 @@ pub struct AmberfloBackendConfig {
 
     /// URL to the Amberflo API endpoint for ingesting metrics.
     pub api_ingest_url: Option<String>,
+
+    /// Maximum number of usage reports queued for aggregation. Reports are dropped (and counted
+    /// by `toolchain_amberflo_dropped_events_total`) rather than delaying storage operations when
+    /// the queue is full. Defaults to 10000.
+    pub queue_capacity: Option<usize>,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -165,14 +374,52 @@ pub enum BlobStorageConfig {
     RedisDirect(RedisDirectStorageConfig),
     ExistenceCache(ExistenceCacheStorageConfig),
     DarkLaunch(DarkLaunchConfig),
-    ReadDigestVerifier(Box<BlobStorageConfig>),
+    ReadDigestVerifier(ReadDigestVerifierConfig),
     Metered(Box<BlobStorageConfig>),
     Sharded(ShardedStorageConfig),
     ReadCache(ReadCacheStorageConfig),
+    ConcurrencyLimit(ConcurrencyLimitStorageConfig),
+    Retry(RetryStorageConfig),
+    Encrypted(EncryptedStorageConfig),
     Null,
     AlwaysErrors,
 }
 
+impl BlobStorageConfig {
+    /// Whether this storage checks the content it stores against digests as it is read.
+    pub fn verifies_stored_content(&self) -> bool {
+        match self {
+            BlobStorageConfig::Local(c) => c.verify_reads,
+            BlobStorageConfig::ReadDigestVerifier(_) => true,
+            BlobStorageConfig::Memory
+            | BlobStorageConfig::RedisChunked(_)
+            | BlobStorageConfig::RedisDirect(_)
+            | BlobStorageConfig::Null
+            | BlobStorageConfig::AlwaysErrors => false,
+            BlobStorageConfig::SizeSplit(c) => c
+                .smaller
+                .iter()
+                .chain(c.tiers.iter().map(|t| &t.storage))
+                .chain(std::iter::once(&c.larger))
+                .any(|storage| storage.verifies_stored_content()),
+            BlobStorageConfig::ExistenceCache(c) => c.underlying.verifies_stored_content(),
+            BlobStorageConfig::DarkLaunch(c) => {
+                c.storage1.verifies_stored_content() || c.storage2.verifies_stored_content()
+            }
+            BlobStorageConfig::Metered(c) => c.verifies_stored_content(),
+            BlobStorageConfig::Sharded(c) => c
+                .shards
+                .iter()
+                .any(|shard| shard.storage.verifies_stored_content()),
+            BlobStorageConfig::ReadCache(c) => c.slow.verifies_stored_content(),
+            BlobStorageConfig::ConcurrencyLimit(c) => c.underlying.verifies_stored_content(),
+            BlobStorageConfig::Retry(c) => c.underlying.verifies_stored_content(),
+            // Nested encrypted storage is validated separately.
+            BlobStorageConfig::Encrypted(_) => false,
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum SmallBlobStorageConfig {
@@ -198,6 +445,11 @@ pub struct Config {
     /// Admin endpoints configuration.
     pub infra: Option<InfraConfig>,
 
+    /// Path (as understood by the configured secrets provider) to the shared secret which
+    /// authenticates requests to the admin infra endpoints. If unset, the admin endpoints are
+    /// disabled.
+    pub admin_secret_path: Option<String>,
+
     /// gRPC configuration.
     pub grpc: Option<GrpcConfig>,
 
@@ -210,8 +462,39 @@ pub struct Config {
     /// Probability of checking action cache completeness. Stored as integer in range 0-1000.
     pub completeness_check_probability: Option<u32>,
 
+    /// Maximum total size of blobs in a single `BatchUpdateBlobs` or `BatchReadBlobs` call,
+    /// which is advertised to clients via `GetCapabilities`. Defaults to 4 MiB.
+    pub max_batch_total_size_bytes: Option<usize>,
+
+    /// Maximum number of bytes read ahead from a `ByteStream.Write` stream while earlier data is
+    /// still being written to storage. Defaults to 8 MiB.
+    pub max_write_buffered_bytes: Option<usize>,
+
+    /// Maximum size (after decompression) of a blob written by `ByteStream.Write`. Larger writes
+    /// are rejected with `ResourceExhausted`. Unlimited by default.
+    pub max_write_blob_size_bytes: Option<usize>,
+
     /// Amberflo backend config
     pub amberflo_backend: Option<AmberfloBackendConfig>,
+
+    /// Where secrets (the Amberflo API key, Redis passwords and encryption keys) are loaded from.
+    /// Defaults to files.
+    pub secrets: Option<SecretsConfig>,
+
+    /// Log each CAS and byte stream operation (instance, digest, bytes, outcome) at `info`.
+    #[serde(default)]
+    pub access_log: bool,
+
+    /// Instances which serve reads but reject writes (`BatchUpdateBlobs`, `ByteStream.Write`,
+    /// `UpdateActionResult` and `DeleteBlobs`) with `FAILED_PRECONDITION`, e.g. during a
+    /// migration.
+    #[serde(default)]
+    pub read_only_instances: Vec<String>,
+
+    /// Serve the `StorageAdmin` service (e.g. `DeleteBlobs`). Access to it must be restricted
+    /// by the proxy.
+    #[serde(default)]
+    pub admin_api: bool,
 }
 
 impl FromStr for Config {
@@ -3,43 +3,49 @@
 
 #![deny(warnings)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::net::SocketAddr;
 use std::num::NonZeroUsize;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use bytes::Bytes;
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use futures::future::BoxFuture;
 use futures::FutureExt;
 use grpc_util::hyper::AddrIncomingWithStream;
-use grpc_util::infra::setup_infra_endpoints;
+use grpc_util::infra::{
+    serve_with_drain_deadline, setup_infra_endpoints, wait_for_shutdown, AdminActions, DebugInfo,
+};
 use grpc_util::logging::setup_logging;
+use grpc_util::secrets::SecretProvider;
 use grpc_util::sentry::setup_sentry;
 use hyper::server::conn::AddrIncoming;
 use itertools::Itertools;
+use parking_lot::Mutex;
 use redis::{ConnectionAddr, ConnectionInfo, RedisConnectionInfo};
-use storage::api::Server;
+use storage::api::{ByteStreamWriteLimits, Server, ServerConfig};
 use storage::driver::redis::common::{ClientWrapper, ConnectionGetter};
-use storage::driver::redis::pool::AsyncRedisConnectionPool;
+use storage::driver::redis::pool::{AsyncRedisConnectionPool, DEFAULT_HEALTH_CHECK_INTERVAL};
 use storage::driver::redis::RedisConnectionName;
 use storage::driver::{
     AlwaysErrorsStorage, AmberfloEmitter, BlobStorage, BlobStorageAdapter, ChunkingStorage,
-    DarkLaunchStorage, ExistenceCacheStorage, FastSlowReplicationStorage, FileBackedStorage,
-    MemoryStorage, MeteredStorage, MetricsMonitoredStorage, NullStorage, ReadDigestVerifier,
-    RedisBackend, RedisDirectStorage, RedisStorage, ShardingStorage, SizeSplitStorage,
+    ConcurrencyLimitStorage, DarkLaunchStorage, DriverState, EncryptingStorage,
+    ExistenceCacheStorage, FastSlowReplicationStorage, FileBackedStorage, Instance, MemoryStorage,
+    MeteredStorage, MetricsMonitoredStorage, NullStorage, ReadDigestVerifier, RedisBackend,
+    RedisDirectStorage, RedisStorage, RetryingStorage, ShardingStorage, SizeSplitStorage,
     SmallBlobStorage, SmallBlobStorageAdapter, WriteDigestVerifier,
+    DEFAULT_AMBERFLO_QUEUE_CAPACITY,
 };
 use storage::uuid_gen::DefaultUuidGenerator;
 use storage::Digest;
-use tokio::io::AsyncReadExt;
 use tower_http::metrics::in_flight_requests::InFlightRequestsCounter;
 
 use crate::config::{
-    AmberfloApiKeyFile, BlobStorageConfig, RedisBackendConfig, ShardedStorageConfig,
-    SmallBlobStorageConfig,
+    AmberfloApiKeyFile, AmberfloBackendConfig, BlobStorageConfig, RedisBackendConfig,
+    ShardedStorageConfig, SmallBlobStorageConfig,
 };
 
 pub mod config;
@@ -49,7 +55,66 @@ const DEFAULT_REDIS_PORT: u16 = 6379;
 type BoxBlobStorage = Box<dyn BlobStorage + Send + Sync + 'static>;
 type BoxSmallBlobStorage = Box<dyn SmallBlobStorage + Send + Sync + 'static>;
 
-fn parse_redis_addr(address: &str, backend_name: &str) -> Result<ConnectionInfo, String> {
+/// A sharded storage in the CAS or Action Cache storage stack, with the config it was last
+/// configured from.
+struct RegisteredShardedStorage {
+    purpose: &'static str,
+    storage: Arc<ShardingStorage<Digest>>,
+    config: ShardedStorageConfig,
+}
+
+/// The sharded storages in the CAS and Action Cache storage stacks, in the order in which they
+/// were constructed, for the admin actions which operate on them.
+type ShardedStorages = Mutex<Vec<RegisteredShardedStorage>>;
+
+/// What storage drivers are constructed with besides their config.
+struct StorageContext<'a, P> {
+    redis_backends: RedisBackends<'a, P>,
+    amberflo_emitter: Option<&'a AmberfloEmitter>,
+    secret_provider: &'a dyn SecretProvider,
+    sharded_storages: &'a ShardedStorages,
+}
+
+/// The Redis backends which storage drivers may use.
+enum RedisBackends<'a, P> {
+    Connected(&'a HashMap<String, P>),
+    /// For a dry run, which checks the storage config without connecting to any backends or
+    /// touching the filesystem: the names of the backends which would be connected. Drivers which
+    /// would connect to a backend or touch the filesystem are replaced by `NullStorage` once their
+    /// config has been checked.
+    DryRun(HashSet<String>),
+}
+
+impl<P: Clone> StorageContext<'_, P> {
+    fn is_dry_run(&self) -> bool {
+        matches!(self.redis_backends, RedisBackends::DryRun(_))
+    }
+
+    /// The named Redis backend, or `None` for a dry run.
+    fn redis_backend(&self, name: &str) -> Result<Option<P>, String> {
+        let known = match &self.redis_backends {
+            RedisBackends::Connected(backends) => match backends.get(name) {
+                Some(backend) => return Ok(Some(backend.clone())),
+                None => false,
+            },
+            RedisBackends::DryRun(names) => names.contains(name),
+        };
+        if known {
+            Ok(None)
+        } else {
+            Err(format!("unknown Redis backend: {name}"))
+        }
+    }
+}
+
+/// Number of digests listed per request to a shard while rebalancing.
+const REBALANCE_PAGE_SIZE: usize = 1000;
+
+fn parse_redis_addr(
+    address: &str,
+    backend_name: &str,
+    password: Option<String>,
+) -> Result<ConnectionInfo, String> {
     let (address, port) = match address.split(':').collect::<Vec<_>>().as_slice() {
         [addr, port] => {
             let port: u16 = (*port).parse().map_err(|err| {
@@ -73,7 +138,7 @@ fn parse_redis_addr(address: &str, backend_name: &str) -> Result<ConnectionInfo,
         redis: RedisConnectionInfo {
             db: 0,
             username: None,
-            password: None,
+            password,
         },
     })
 }
@@ -82,6 +147,10 @@ async fn verify_redis_backends(
     redis_backends: &HashMap<String, RedisBackend<AsyncRedisConnectionPool>>,
 ) -> Result<(), String> {
     for (name, backend) in redis_backends {
+        backend
+            .warmup()
+            .await
+            .map_err(|err| format!("Unable to warm up connections to backend `{name}`: {err}"))?;
         backend
             .verify_connection()
             .await
@@ -91,16 +160,56 @@ async fn verify_redis_backends(
     Ok(())
 }
 
+/// Load the passwords of any Redis backends which are configured with one.
+async fn load_redis_passwords(
+    config: Option<&HashMap<String, RedisBackendConfig>>,
+    secret_provider: &dyn SecretProvider,
+) -> Result<HashMap<String, String>, String> {
+    let mut passwords = HashMap::new();
+    for (name, backend_config) in config.into_iter().flatten() {
+        if let Some(password_secret) = &backend_config.password_secret {
+            let password = secret_provider.get_secret(password_secret).await?;
+            passwords.insert(name.clone(), password.trim().to_owned());
+        }
+    }
+    Ok(passwords)
+}
+
+/// Load the Amberflo API key from the given secret, which holds an `AmberfloApiKeyFile`.
+async fn load_amberflo_api_key(
+    api_key_file: &str,
+    secret_provider: &dyn SecretProvider,
+) -> Result<String, String> {
+    let api_key_json = secret_provider
+        .get_secret(api_key_file)
+        .await
+        .map_err(|err| format!("Failed to read Amberflo api key secret: {err}"))?;
+    let api_key_wrapper: AmberfloApiKeyFile = serde_json::from_str(&api_key_json)
+        .map_err(|err| format!("Failed to parse Amberflo api key file: {err}"))?;
+    Ok(api_key_wrapper.api_key)
+}
+
 fn setup_redis_backends(
     config: Option<HashMap<String, RedisBackendConfig>>,
+    passwords: &HashMap<String, String>,
 ) -> Result<HashMap<String, RedisBackend<AsyncRedisConnectionPool>>, String> {
     let backend_configs = config.unwrap_or_default();
     let (redis_backends_by_name, errors): (Vec<_>, Vec<String>) = backend_configs
         .into_iter()
         .map(|(name, backend_config)| {
+            let num_connections = backend_config.num_connections.unwrap_or(20);
+            let min_healthy_connections = backend_config.min_healthy_connections.unwrap_or(1);
+            let health_check_interval = backend_config
+                .health_check_interval_secs
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL);
             let annotated_pool = {
                 let primary_pool = {
-                    let conn_info = parse_redis_addr(&backend_config.address, &name)?;
+                    let conn_info = parse_redis_addr(
+                        &backend_config.address,
+                        &name,
+                        passwords.get(&name).cloned(),
+                    )?;
                     log::info!("primary pool addr = {conn_info:?}");
                     let client = redis::Client::open(conn_info)
                         .map_err(|err| format!("Redis setup error: {err}"))?;
@@ -111,9 +220,11 @@ fn setup_redis_backends(
                             endpoint: "primary",
                         },
                     );
-                    AsyncRedisConnectionPool::new(
+                    AsyncRedisConnectionPool::with_health_checks(
                         client_wrapper,
-                        backend_config.num_connections.unwrap_or(20),
+                        num_connections,
+                        min_healthy_connections,
+                        health_check_interval,
                         name.clone(),
                         "primary",
                     )
@@ -123,7 +234,8 @@ fn setup_redis_backends(
                     .read_only_address
                     .as_ref()
                     .map(|addr| -> Result<_, String> {
-                        let conn_info = parse_redis_addr(addr, &name)?;
+                        let conn_info =
+                            parse_redis_addr(addr, &name, passwords.get(&name).cloned())?;
                         let client = redis::Client::open(conn_info)
                             .map_err(|err| format!("Redis setup error: {err}"))?;
                         let client_wrapper = ClientWrapper::new(
@@ -134,9 +246,11 @@ fn setup_redis_backends(
                             },
                         );
 
-                        let async_pool = AsyncRedisConnectionPool::new(
+                        let async_pool = AsyncRedisConnectionPool::with_health_checks(
                             client_wrapper,
-                            backend_config.num_connections.unwrap_or(20),
+                            num_connections,
+                            min_healthy_connections,
+                            health_check_interval,
                             name.clone(),
                             "read-only",
                         );
@@ -164,11 +278,18 @@ fn setup_redis_backends(
     }
 }
 
+fn shard_key_digest(shard_key: &str) -> Result<Digest, String> {
+    // Note: The shard key only needs to be stable and deterministic. It
+    // does not necessarily need to be, but `Digest` is stable and
+    // deterministic so it used here.
+    let bytes = Bytes::copy_from_slice(shard_key.as_bytes());
+    Digest::of_bytes(&bytes)
+}
+
 async fn make_sharding_storage<'a, P>(
     c: &ShardedStorageConfig,
     purpose: &'static str,
-    redis_backends: &'a HashMap<String, P>,
-    amberflo_emitter: Option<&'a AmberfloEmitter>,
+    ctx: &'a StorageContext<'a, P>,
 ) -> Result<impl BlobStorage, String>
 where
     P: ConnectionGetter + Clone + Send + Sync + 'static,
@@ -176,35 +297,28 @@ where
     let mut shards: Vec<(Digest, BoxBlobStorage)> = Vec::new();
     let mut shard_descriptions = HashMap::new();
     for shard_config in &c.shards {
-        let storage = make_storage(
-            shard_config.storage.clone(),
-            false,
-            purpose,
-            redis_backends,
-            amberflo_emitter,
-        )
-        .await
-        .map_err(|err| {
-            format!(
-                "Shard {} failed to construct: {err}",
-                &shard_config.shard_key
-            )
-        })?;
-        let shard_key = {
-            // Note: The shard key only needs to be stable and deterministic. It
-            // does not necessarily need to be, but `Digest` is stable and
-            // deterministic so it used here.
-            let bytes = Bytes::copy_from_slice(shard_config.shard_key.as_bytes());
-            Digest::of_bytes(&bytes)?
-        };
+        let storage = make_storage(shard_config.storage.clone(), false, purpose, ctx)
+            .await
+            .map_err(|err| {
+                format!(
+                    "Shard {} failed to construct: {err}",
+                    &shard_config.shard_key
+                )
+            })?;
+        let shard_key = shard_key_digest(&shard_config.shard_key)?;
         shards.push((shard_key, storage));
         shard_descriptions.insert(shard_key, shard_config.shard_key.to_string());
     }
-    let key_replicas: NonZeroUsize = c
-        .num_replicas
-        .try_into()
-        .map_err(|_| "num_replicas must be non-zero".to_string())?;
-    let storage = ShardingStorage::new(shards, key_replicas, purpose, shard_descriptions);
+    let mut storage = ShardingStorage::new(shards, c.key_replicas()?, purpose, shard_descriptions);
+    if let Some(write_timeout_ms) = c.write_timeout_ms {
+        storage = storage.with_write_timeout(Duration::from_millis(write_timeout_ms));
+    }
+    let storage = Arc::new(storage);
+    ctx.sharded_storages.lock().push(RegisteredShardedStorage {
+        purpose,
+        storage: storage.clone(),
+        config: c.clone(),
+    });
     Ok(MetricsMonitoredStorage::new(
         storage, "sharded", purpose, false,
     ))
@@ -214,22 +328,24 @@ fn make_storage<'a, P>(
     config: Box<BlobStorageConfig>,
     verify_digests: bool,
     purpose: &'static str,
-    redis_backends: &'a HashMap<String, P>,
-    amberflo_emitter: Option<&'a AmberfloEmitter>,
+    ctx: &'a StorageContext<'a, P>,
 ) -> BoxFuture<'a, Result<BoxBlobStorage, String>>
 where
     P: ConnectionGetter + Clone + Send + Sync + 'static,
 {
     (async move {
         let storage = match config.as_ref() {
+            BlobStorageConfig::Local(_) if ctx.is_dry_run() => Box::new(NullStorage),
             BlobStorageConfig::Local(c) => {
                 let pod_namespace =
                     env::var("K8S_POD_NAMESPACE").expect("Expected K8S_POD_NAMESPACE to be set.");
                 let pod_name = env::var("K8S_POD_NAME").expect("Expected K8S_POD_NAME to be set.");
                 let container_id = format!("{pod_namespace}-{pod_name}");
-                let storage = FileBackedStorage::new(c.base_path.clone(), &container_id)
-                    .await
-                    .map_err(String::from)?;
+                let storage =
+                    FileBackedStorage::new(c.base_path.clone(), &container_id, c.durability)
+                        .await
+                        .map_err(String::from)?
+                        .with_read_verification(c.verify_reads);
                 let storage = MetricsMonitoredStorage::new(storage, "file", purpose, true);
                 Box::new(storage) as BoxBlobStorage
             }
@@ -239,36 +355,34 @@ where
                 Box::new(storage) as BoxBlobStorage
             }
             BlobStorageConfig::SizeSplit(c) => {
-                let storage1 = make_storage(
-                    c.smaller.clone(),
-                    false,
-                    purpose,
-                    redis_backends,
-                    amberflo_emitter,
-                )
-                .await?;
-                let storage2 = make_storage(
-                    c.larger.clone(),
-                    false,
-                    purpose,
-                    redis_backends,
-                    amberflo_emitter,
-                )
-                .await?;
-                let storage = SizeSplitStorage::new(c.size, storage1, storage2);
+                let tier_configs = c.tier_configs()?;
+                let mut tiers = Vec::with_capacity(tier_configs.len());
+                for (size, tier_config) in tier_configs {
+                    let storage = make_storage(tier_config, false, purpose, ctx).await?;
+                    tiers.push((size, storage));
+                }
+                let fallback = make_storage(c.larger.clone(), false, purpose, ctx).await?;
+                let storage = SizeSplitStorage::with_thresholds(tiers, fallback);
                 let storage = MetricsMonitoredStorage::new(storage, "size_split", purpose, false);
                 Box::new(storage) as BoxBlobStorage
             }
             BlobStorageConfig::RedisChunked(c) => {
-                let pool = redis_backends
-                    .get(&c.backend)
-                    .ok_or_else(|| format!("Redis setup error: unknown backend: {}", &c.backend))?
-                    .clone();
-                let write_chunk_size = c.write_chunk_size.unwrap_or(config::DEFAULT_CHUNK_SIZE);
-                let storage = RedisStorage::new(pool, c.prefix.clone(), DefaultUuidGenerator)
-                    .await
-                    .map_err(|err| format!("Redis setup error: {err}"))?;
-                let storage = ChunkingStorage::new(storage, write_chunk_size);
+                let chunking_strategy = c.chunking_strategy()?;
+                let Some(pool) = ctx.redis_backend(&c.backend)? else {
+                    return Ok(Box::new(NullStorage) as BoxBlobStorage);
+                };
+                let find_missing_concurrency = c.find_missing_concurrency.unwrap_or_else(|| {
+                    NonZeroUsize::new(config::DEFAULT_REDIS_FIND_MISSING_CONCURRENCY).unwrap()
+                });
+                let storage = RedisStorage::new(
+                    pool,
+                    c.prefix.clone(),
+                    DefaultUuidGenerator,
+                    find_missing_concurrency,
+                )
+                .await
+                .map_err(|err| format!("Redis setup error: {err}"))?;
+                let storage = ChunkingStorage::with_strategy(storage, chunking_strategy);
                 let storage = MetricsMonitoredStorage::new(storage, "redis", purpose, true);
                 Box::new(storage) as BoxBlobStorage
             }
@@ -276,102 +390,125 @@ where
                 let storage = make_small_storage(
                     Box::new(SmallBlobStorageConfig::RedisDirect(c.clone())),
                     purpose,
-                    redis_backends,
-                    amberflo_emitter,
+                    ctx,
                 )
                 .await?;
                 let storage = SmallBlobStorageAdapter::new(storage);
                 Box::new(storage) as BoxBlobStorage
             }
             BlobStorageConfig::ExistenceCache(c) => {
-                let underlying = make_storage(
-                    c.underlying.clone(),
-                    false,
-                    purpose,
-                    redis_backends,
-                    amberflo_emitter,
-                )
-                .await?;
-                let storage = ExistenceCacheStorage::new(c.max_entries, underlying);
+                let underlying = make_storage(c.underlying.clone(), false, purpose, ctx).await?;
+                let missing_ttl = c.cache_missing.then(|| {
+                    Duration::from_millis(
+                        c.missing_ttl_ms
+                            .unwrap_or(config::DEFAULT_EXISTENCE_CACHE_MISSING_TTL_MS),
+                    )
+                });
+                let storage = ExistenceCacheStorage::new(
+                    c.max_entries,
+                    c.warmup_entries,
+                    missing_ttl,
+                    underlying,
+                );
+                if !ctx.is_dry_run() {
+                    for instance_name in &c.warmup_instances {
+                        storage.ensure_instance(
+                            &Instance::from(instance_name),
+                            DriverState::default(),
+                        );
+                    }
+                }
                 let storage =
                     MetricsMonitoredStorage::new(storage, "existence_cache", purpose, false);
                 Box::new(storage) as BoxBlobStorage
             }
             BlobStorageConfig::DarkLaunch(c) => {
-                let storage1 = make_storage(
-                    c.storage1.clone(),
-                    false,
-                    purpose,
-                    redis_backends,
-                    amberflo_emitter,
-                )
-                .await?;
-                let storage2 = make_storage(
-                    c.storage2.clone(),
-                    false,
-                    purpose,
-                    redis_backends,
-                    amberflo_emitter,
-                )
-                .await?;
+                c.validate_compare_probability()?;
+                let storage1 = make_storage(c.storage1.clone(), false, purpose, ctx).await?;
+                let storage2 = make_storage(c.storage2.clone(), false, purpose, ctx).await?;
                 let storage = DarkLaunchStorage::new(
                     storage1,
                     storage2,
                     c.storage2_instance_names.clone(),
                     c.write_to_secondary.unwrap_or(true),
+                    c.compare_probability,
                     purpose,
                 );
                 let storage = MetricsMonitoredStorage::new(storage, "dark_launch", purpose, false);
                 Box::new(storage) as BoxBlobStorage
             }
             BlobStorageConfig::ReadDigestVerifier(c) => {
-                let storage =
-                    make_storage(c.clone(), false, purpose, redis_backends, amberflo_emitter)
-                        .await?;
-                let storage = ReadDigestVerifier::new(storage);
+                let storage = make_storage(c.underlying.clone(), false, purpose, ctx).await?;
+                let storage = ReadDigestVerifier::new(storage, c.repair_on_mismatch);
                 Box::new(storage) as BoxBlobStorage
             }
             BlobStorageConfig::Metered(c) => {
-                let storage =
-                    make_storage(c.clone(), false, purpose, redis_backends, amberflo_emitter)
-                        .await?;
+                let storage = make_storage(c.clone(), false, purpose, ctx).await?;
                 let storage = MeteredStorage::new(
                     storage,
-                    amberflo_emitter
+                    ctx.amberflo_emitter
                         .ok_or_else(|| "Amberflo emitter must be configured".to_string())?
                         .sender(),
                 );
                 Box::new(storage) as BoxBlobStorage
             }
             BlobStorageConfig::Sharded(c) => {
-                Box::new(make_sharding_storage(c, purpose, redis_backends, amberflo_emitter).await?)
-                    as BoxBlobStorage
+                Box::new(make_sharding_storage(c, purpose, ctx).await?) as BoxBlobStorage
             }
             BlobStorageConfig::ReadCache(c) => {
-                let fast_storage =
-                    make_small_storage(c.fast.clone(), purpose, redis_backends, amberflo_emitter)
-                        .await?;
-                let slow_storage = make_storage(
-                    c.slow.clone(),
-                    false,
-                    purpose,
-                    redis_backends,
-                    amberflo_emitter,
-                )
-                .await?;
+                let fast_storage = make_small_storage(c.fast.clone(), purpose, ctx).await?;
+                let slow_storage = make_storage(c.slow.clone(), false, purpose, ctx).await?;
                 let storage = FastSlowReplicationStorage::new(fast_storage, slow_storage);
                 let storage = SmallBlobStorageAdapter::new(storage);
                 let storage = MetricsMonitoredStorage::new(storage, "fast_slow", purpose, false);
                 Box::new(storage) as BoxBlobStorage
             }
-            BlobStorageConfig::Null => {
-                let storage = make_small_storage(
-                    Box::new(SmallBlobStorageConfig::Null),
+            BlobStorageConfig::ConcurrencyLimit(c) => {
+                let underlying = make_storage(c.underlying.clone(), false, purpose, ctx).await?;
+                let storage = ConcurrencyLimitStorage::new(
+                    underlying,
+                    c.max_in_flight,
+                    Duration::from_millis(c.acquire_timeout_ms),
                     purpose,
-                    redis_backends,
-                    amberflo_emitter,
-                )
-                .await?;
+                );
+                let storage =
+                    MetricsMonitoredStorage::new(storage, "concurrency_limit", purpose, false);
+                Box::new(storage) as BoxBlobStorage
+            }
+            BlobStorageConfig::Retry(c) => {
+                let underlying = make_storage(c.underlying.clone(), false, purpose, ctx).await?;
+                let storage = RetryingStorage::new(
+                    underlying,
+                    c.max_attempts,
+                    Duration::from_millis(c.base_delay_ms),
+                    purpose,
+                );
+                let storage = MetricsMonitoredStorage::new(storage, "retry", purpose, false);
+                Box::new(storage) as BoxBlobStorage
+            }
+            BlobStorageConfig::Encrypted(c) => {
+                // Stored blobs are encrypted, and so would fail verification against their digests.
+                if c.underlying.verifies_stored_content() {
+                    return Err(
+                        "encrypted storage cannot wrap read_digest_verifier or local storage with \
+                         verify_reads"
+                            .to_owned(),
+                    );
+                }
+                let underlying = make_storage(c.underlying.clone(), false, purpose, ctx).await?;
+                let key = ctx
+                    .secret_provider
+                    .get_secret(&c.key_ref)
+                    .await
+                    .map_err(|err| format!("Failed to read encryption key secret: {err}"))?;
+                let storage = EncryptingStorage::new(underlying, &key)?;
+                let storage = MetricsMonitoredStorage::new(storage, "encrypted", purpose, false);
+                Box::new(storage) as BoxBlobStorage
+            }
+            BlobStorageConfig::Null => {
+                let storage =
+                    make_small_storage(Box::new(SmallBlobStorageConfig::Null), purpose, ctx)
+                        .await?;
                 let storage = SmallBlobStorageAdapter::new(storage);
                 Box::new(storage) as BoxBlobStorage
             }
@@ -379,8 +516,7 @@ where
                 let storage = make_small_storage(
                     Box::new(SmallBlobStorageConfig::AlwaysErrors),
                     purpose,
-                    redis_backends,
-                    amberflo_emitter,
+                    ctx,
                 )
                 .await?;
                 let storage = SmallBlobStorageAdapter::new(storage);
@@ -400,8 +536,7 @@ where
 fn make_small_storage<'a, P>(
     config: Box<SmallBlobStorageConfig>,
     purpose: &'static str,
-    redis_backends: &'a HashMap<String, P>,
-    amberflo_emitter: Option<&'a AmberfloEmitter>,
+    ctx: &'a StorageContext<'a, P>,
 ) -> BoxFuture<'a, Result<BoxSmallBlobStorage, String>>
 where
     P: ConnectionGetter + Clone + Send + Sync + 'static,
@@ -409,13 +544,15 @@ where
     (async move {
         let storage = match config.as_ref() {
             SmallBlobStorageConfig::RedisDirect(c) => {
-                let pool = redis_backends
-                    .get(&c.backend)
-                    .ok_or_else(|| format!("Redis setup error: unknown backend: {}", &c.backend))?
-                    .clone();
-                let storage = RedisDirectStorage::new(pool, c.prefix.clone())
+                let Some(pool) = ctx.redis_backend(&c.backend)? else {
+                    return Ok(Box::new(NullStorage) as BoxSmallBlobStorage);
+                };
+                let mut storage = RedisDirectStorage::new(pool, c.prefix.clone())
                     .await
                     .map_err(|err| format!("Redis setup error: {err}"))?;
+                if let Some(max_blob_size) = c.max_blob_size {
+                    storage = storage.with_max_blob_size(max_blob_size);
+                }
                 let storage = MetricsMonitoredStorage::new(storage, "redis_direct", purpose, true);
                 Box::new(storage) as BoxSmallBlobStorage
             }
@@ -423,7 +560,7 @@ where
                 // TODO: It will likely eventually make sense to directly
                 // `impl SmallBlobStorage for ShardingStorage`
                 Box::new(BlobStorageAdapter::new(
-                    make_sharding_storage(c, purpose, redis_backends, amberflo_emitter).await?,
+                    make_sharding_storage(c, purpose, ctx).await?,
                 )) as BoxSmallBlobStorage
             }
             SmallBlobStorageConfig::Null => {
@@ -443,6 +580,169 @@ where
     .boxed()
 }
 
+/// The sharded storages of the running server, and what is needed to construct the shards which
+/// a config reload adds to them.
+struct ShardedStorageAdmin {
+    sharded_storages: Vec<RegisteredShardedStorage>,
+    config_filename: String,
+    redis_backends: Arc<HashMap<String, RedisBackend<AsyncRedisConnectionPool>>>,
+    amberflo_emitter: Option<AmberfloEmitter>,
+    secret_provider: Arc<dyn SecretProvider>,
+}
+
+impl ShardedStorageAdmin {
+    /// Rebalance each sharded storage for every instance which it has served, returning a summary
+    /// of the blobs copied.
+    async fn rebalance(admin: &tokio::sync::Mutex<Self>) -> Result<String, String> {
+        // Don't hold the lock while copying blobs, so that shards may be reloaded meanwhile.
+        let sharded_storages = admin
+            .lock()
+            .await
+            .sharded_storages
+            .iter()
+            .map(|s| (s.purpose, s.storage.clone()))
+            .collect::<Vec<_>>();
+        let mut summary = Vec::new();
+        for (purpose, storage) in sharded_storages {
+            for (instance, state) in storage.instances() {
+                let copied = storage
+                    .rebalance(instance.clone(), REBALANCE_PAGE_SIZE, state)
+                    .await
+                    .map_err(|err| {
+                        format!(
+                            "Failed to rebalance {purpose} shards for instance `{}`: {err}",
+                            instance.name
+                        )
+                    })?;
+                summary.push(format!(
+                    "{purpose}: copied {copied} blobs for instance `{}`",
+                    instance.name
+                ));
+            }
+        }
+        Ok(summary.join("\n"))
+    }
+
+    /// Re-read the config file and add and remove shards of the sharded storages to match it,
+    /// returning a summary of the changes. Shards are identified by their `shard_key`. Any other
+    /// change to the storage config (including to the storage of an existing shard, or adding or
+    /// removing sharded storage) requires a restart. Nothing is changed unless the whole config
+    /// passes a dry run and every shard which was added could be constructed.
+    async fn reload(&mut self) -> Result<String, String> {
+        let config_str = tokio::fs::read_to_string(&self.config_filename)
+            .await
+            .map_err(|err| format!("Failed to read config from {}: {err}", self.config_filename))?;
+        let config = config::Config::from_str(&config_str)
+            .map_err(|err| format!("Invalid config file {}: {err}", self.config_filename))?;
+        // The dry run checks the whole storage config, and lists the sharded storages in it in the
+        // order in which they were constructed.
+        let configs = check_storage(
+            &config,
+            self.redis_backends.keys().cloned().collect(),
+            self.amberflo_emitter.as_ref(),
+            &*self.secret_provider,
+        )
+        .await?
+        .into_iter()
+        .map(|registered| registered.config)
+        .collect::<Vec<_>>();
+        if configs.len() != self.sharded_storages.len() {
+            return Err(format!(
+                "The config has {} sharded storages, but {} are running: restart to apply it",
+                configs.len(),
+                self.sharded_storages.len()
+            ));
+        }
+
+        // Construct every added shard before changing any sharded storage.
+        let mut changes = Vec::new();
+        for (registered, config) in self.sharded_storages.iter().zip(&configs) {
+            let purpose = registered.purpose;
+            if config.num_replicas != registered.config.num_replicas {
+                return Err(format!(
+                    "Changing num_replicas of the {purpose} sharded storage requires a restart"
+                ));
+            }
+            let is_running =
+                |key: &str| registered.config.shards.iter().any(|s| s.shard_key == key);
+            let is_configured = |key: &str| config.shards.iter().any(|s| s.shard_key == key);
+            let added = config
+                .shards
+                .iter()
+                .filter(|s| !is_running(&s.shard_key))
+                .collect::<Vec<_>>();
+            let removed = registered
+                .config
+                .shards
+                .iter()
+                .filter(|s| !is_configured(&s.shard_key))
+                .collect::<Vec<_>>();
+
+            let nested_sharded_storages = ShardedStorages::default();
+            let ctx = StorageContext {
+                redis_backends: RedisBackends::Connected(&*self.redis_backends),
+                amberflo_emitter: self.amberflo_emitter.as_ref(),
+                secret_provider: &*self.secret_provider,
+                sharded_storages: &nested_sharded_storages,
+            };
+            let mut added_shards = Vec::with_capacity(added.len());
+            let mut shard_descriptions = HashMap::new();
+            for shard_config in &added {
+                let storage = make_storage(shard_config.storage.clone(), false, purpose, &ctx)
+                    .await
+                    .map_err(|err| {
+                        format!(
+                            "Shard {} failed to construct: {err}",
+                            &shard_config.shard_key
+                        )
+                    })?;
+                // Sharded storages nested in a shard are not registered for the admin actions,
+                // and would be miscounted by later reloads.
+                if !nested_sharded_storages.lock().is_empty() {
+                    return Err(format!(
+                        "Shard {} of the {purpose} sharded storage contains sharded storage, so \
+                         adding it requires a restart",
+                        shard_config.shard_key
+                    ));
+                }
+                let shard_key = shard_key_digest(&shard_config.shard_key)?;
+                added_shards.push((shard_key, storage));
+                shard_descriptions.insert(shard_key, shard_config.shard_key.clone());
+            }
+            let removed_shards = removed
+                .iter()
+                .map(|s| shard_key_digest(&s.shard_key))
+                .collect::<Result<Vec<_>, _>>()?;
+            let summary = format!(
+                "{purpose}: added shards [{}], removed shards [{}]",
+                added.iter().map(|s| &s.shard_key).join(", "),
+                removed.iter().map(|s| &s.shard_key).join(", ")
+            );
+            changes.push((added_shards, removed_shards, shard_descriptions, summary));
+        }
+
+        let mut summary = Vec::new();
+        for (registered, (config, change)) in self
+            .sharded_storages
+            .iter_mut()
+            .zip(configs.into_iter().zip(changes))
+        {
+            let (added_shards, removed_shards, shard_descriptions, change_summary) = change;
+            if !added_shards.is_empty() || !removed_shards.is_empty() {
+                registered
+                    .storage
+                    .reconfigure(added_shards, &removed_shards, shard_descriptions);
+                summary.push(change_summary);
+            }
+            registered.config = config;
+        }
+        if summary.is_empty() {
+            return Ok("No shards were added or removed".to_owned());
+        }
+        Ok(summary.join("\n"))
+    }
+}
+
 fn scrape_redis_backend_metrics(
     redis_backends: &HashMap<String, RedisBackend<AsyncRedisConnectionPool>>,
 ) {
@@ -451,6 +751,121 @@ fn scrape_redis_backend_metrics(
     }
 }
 
+/// Create an Amberflo emitter if configured.
+async fn make_amberflo_emitter(
+    config: Option<&AmberfloBackendConfig>,
+    secret_provider: &dyn SecretProvider,
+) -> Result<Option<AmberfloEmitter>, String> {
+    let Some(c) = config else {
+        return Ok(None);
+    };
+    let aggregation_window_duration =
+        Duration::from_secs(c.aggregation_window_duration_secs as u64);
+    let api_key = load_amberflo_api_key(&c.api_key_file, secret_provider).await?;
+    Ok(Some(AmberfloEmitter::new(
+        aggregation_window_duration,
+        c.customer_id_prefix.clone(),
+        c.env_dimension.clone(),
+        api_key,
+        c.api_ingest_url.clone(),
+        c.queue_capacity.unwrap_or(DEFAULT_AMBERFLO_QUEUE_CAPACITY),
+    )))
+}
+
+/// Construct the CAS and then the Action Cache storage.
+async fn make_cas_and_action_cache<'a, P>(
+    config: &config::Config,
+    ctx: &'a StorageContext<'a, P>,
+) -> Result<(BoxBlobStorage, BoxBlobStorage), String>
+where
+    P: ConnectionGetter + Clone + Send + Sync + 'static,
+{
+    let cas = make_storage(Box::new(config.cas.clone()), true, "CAS", ctx)
+        .await
+        .map_err(|err| format!("Invalid cas storage: {err}"))?;
+    let action_cache = make_storage(Box::new(config.action_cache.clone()), false, "AC", ctx)
+        .await
+        .map_err(|err| format!("Invalid action_cache storage: {err}"))?;
+    Ok((cas, action_cache))
+}
+
+/// Check the CAS and Action Cache storage config by constructing it in a dry run (see
+/// `RedisBackends::DryRun`), returning the sharded storages which it would construct.
+async fn check_storage(
+    config: &config::Config,
+    redis_backend_names: HashSet<String>,
+    amberflo_emitter: Option<&AmberfloEmitter>,
+    secret_provider: &dyn SecretProvider,
+) -> Result<Vec<RegisteredShardedStorage>, String> {
+    let sharded_storages = ShardedStorages::default();
+    make_cas_and_action_cache(
+        config,
+        &StorageContext::<RedisBackend<AsyncRedisConnectionPool>> {
+            redis_backends: RedisBackends::DryRun(redis_backend_names),
+            amberflo_emitter,
+            secret_provider,
+            sharded_storages: &sharded_storages,
+        },
+    )
+    .await?;
+    Ok(sharded_storages.into_inner())
+}
+
+/// What the server is set up from besides the config file.
+struct Startup {
+    address: SocketAddr,
+    secret_provider: Arc<dyn SecretProvider>,
+    redis_passwords: HashMap<String, String>,
+    amberflo_emitter: Option<AmberfloEmitter>,
+    admin_secret: Option<String>,
+}
+
+/// Load everything which the config refers to, and check the storage config in a dry run. This
+/// validates the config as fully as possible without binding any ports or connecting to any
+/// backends, so it is all that `--config-check` does.
+async fn prepare_startup(config: &config::Config) -> Result<Startup, String> {
+    let address = config
+        .listen_address
+        .parse::<SocketAddr>()
+        .map_err(|err| format!("Invalid listen_address {}: {err}", config.listen_address))?;
+    let secret_provider = config.secrets.clone().unwrap_or_default().provider();
+    let redis_passwords =
+        load_redis_passwords(config.redis_backends.as_ref(), &*secret_provider).await?;
+    for (name, backend_config) in config.redis_backends.iter().flatten() {
+        parse_redis_addr(&backend_config.address, name, None)?;
+        if let Some(read_only_address) = &backend_config.read_only_address {
+            parse_redis_addr(read_only_address, name, None)?;
+        }
+    }
+    let amberflo_emitter =
+        make_amberflo_emitter(config.amberflo_backend.as_ref(), &*secret_provider).await?;
+    let admin_secret = match &config.admin_secret_path {
+        Some(path) => Some(grpc_util::secrets::parse_secret(
+            secret_provider.get_secret(path).await?,
+        )?),
+        None => None,
+    };
+    check_storage(
+        config,
+        config
+            .redis_backends
+            .iter()
+            .flatten()
+            .map(|(name, _)| name.clone())
+            .collect(),
+        amberflo_emitter.as_ref(),
+        &*secret_provider,
+    )
+    .await?;
+    Ok(Startup {
+        address,
+        secret_provider,
+        redis_passwords,
+        amberflo_emitter,
+        admin_secret,
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("storage_server")
@@ -460,93 +875,155 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .required(true)
                 .value_name("FILE"),
         )
+        .arg(
+            Arg::new("config-check")
+                .long("config-check")
+                .action(ArgAction::SetTrue)
+                .help("Validate the config file and exit without serving"),
+        )
         .get_matches();
 
     let config_filename = matches.get_one::<String>("config").unwrap();
-    let mut file = tokio::fs::File::open(config_filename).await?;
-    let mut config_str = String::new();
-    file.read_to_string(&mut config_str).await?;
-    let config = config::Config::from_str(&config_str).unwrap();
+    let config_str = tokio::fs::read_to_string(config_filename)
+        .await
+        .map_err(|err| format!("Failed to read config from {config_filename}: {err}"))?;
+    let config = config::Config::from_str(&config_str)
+        .map_err(|err| format!("Invalid config file {config_filename}: {err}"))?;
+    if matches.get_flag("config-check") {
+        prepare_startup(&config).await?;
+        println!("Config is valid.");
+        return Ok(());
+    }
+    let debug_info = DebugInfo::new(
+        env!("CARGO_PKG_VERSION"),
+        option_env!("GIT_SHA"),
+        &config_str,
+    )?;
 
-    setup_logging(config.infra.as_ref(), "storage_server");
+    let _tracing_guard = setup_logging(config.infra.as_ref(), "storage_server");
     log::info!("Storage server config: {config:?}");
     let _sentry_guard = setup_sentry(config.infra.as_ref(), "storage_server");
 
-    // Setup Redis backends and verify all connections to them. The unwrap call will panic if
-    // there is an error from `verify_redis_backends`.
-    let redis_backends = setup_redis_backends(config.redis_backends)?;
-    verify_redis_backends(&redis_backends).await.unwrap();
+    // Check the config before connecting to any backends.
+    let Startup {
+        address,
+        secret_provider,
+        redis_passwords,
+        amberflo_emitter,
+        admin_secret,
+    } = prepare_startup(&config).await?;
 
-    // Create an Amberflo emitter if configured.
-    let amberflo_backend = match config.amberflo_backend {
-        Some(c) => {
-            let aggregation_window_duration =
-                Duration::from_secs(c.aggregation_window_duration_secs as u64);
-            let mut api_key_file = tokio::fs::File::open(c.api_key_file)
-                .await
-                .expect("Open Amberflo api key secret");
-            let mut api_key_file_bytes = Vec::new();
-            api_key_file
-                .read_to_end(&mut api_key_file_bytes)
-                .await
-                .expect("read Amberflo api key secret");
-            let api_key_wrapper: AmberfloApiKeyFile =
-                serde_json::from_slice(&api_key_file_bytes).expect("Parse Amberflo api key file");
-            Some(AmberfloEmitter::new(
-                aggregation_window_duration,
-                c.customer_id_prefix,
-                c.env_dimension,
-                api_key_wrapper.api_key,
-                c.api_ingest_url,
-            ))
-        }
-        None => None,
-    };
+    // Setup Redis backends, warm up their connection pools, and verify all connections to them.
+    // The unwrap call will panic if there is an error from `verify_redis_backends`.
+    let redis_backends = setup_redis_backends(config.redis_backends.clone(), &redis_passwords)?;
+    verify_redis_backends(&redis_backends).await.unwrap();
 
-    let cas = make_storage(
-        Box::new(config.cas),
-        true,
-        "CAS",
-        &redis_backends,
-        amberflo_backend.as_ref(),
-    )
-    .await?;
-    let action_cache = make_storage(
-        Box::new(config.action_cache),
-        false,
-        "AC",
-        &redis_backends,
-        amberflo_backend.as_ref(),
+    let sharded_storages = ShardedStorages::default();
+    let (cas, action_cache) = make_cas_and_action_cache(
+        &config,
+        &StorageContext {
+            redis_backends: RedisBackends::Connected(&redis_backends),
+            amberflo_emitter: amberflo_emitter.as_ref(),
+            secret_provider: &*secret_provider,
+            sharded_storages: &sharded_storages,
+        },
     )
     .await?;
-    let address: SocketAddr = config.listen_address.parse().unwrap();
+    let redis_backends = Arc::new(redis_backends);
+    let sharded_storage_admin = Arc::new(tokio::sync::Mutex::new(ShardedStorageAdmin {
+        sharded_storages: sharded_storages.into_inner(),
+        config_filename: config_filename.clone(),
+        redis_backends: redis_backends.clone(),
+        amberflo_emitter,
+        secret_provider,
+    }));
     let server = Server::new(
         cas,
         action_cache,
-        config.check_action_cache_completeness.unwrap_or_default(),
-        config.completeness_check_probability.unwrap_or(1000),
+        ServerConfig {
+            max_batch_total_size_bytes: config
+                .max_batch_total_size_bytes
+                .unwrap_or(Server::DEFAULT_MAX_BATCH_TOTAL_SIZE_BYTES),
+            check_action_cache_completeness: config
+                .check_action_cache_completeness
+                .unwrap_or_default(),
+            completeness_check_probability: config.completeness_check_probability.unwrap_or(1000),
+            access_log: config.access_log,
+            admin_api: config.admin_api,
+            write_limits: ByteStreamWriteLimits {
+                max_buffered_bytes: config
+                    .max_write_buffered_bytes
+                    .unwrap_or(ByteStreamWriteLimits::DEFAULT_MAX_BUFFERED_BYTES),
+                max_blob_size_bytes: config.max_write_blob_size_bytes,
+            },
+            read_only_instances: config.read_only_instances.into_iter().collect(),
+        },
     );
 
     let incoming = AddrIncoming::bind(&address).expect("failed to bind port");
     log::info!("Serving storage on {}", &address);
 
-    // Setup infra endpoints.
+    // Setup infra endpoints. Admin actions run on the admin thread, so storage work is spawned onto
+    // the main runtime which the storage drivers' connections belong to.
+    let admin_actions = {
+        let runtime = tokio::runtime::Handle::current();
+        let runtime_2 = runtime.clone();
+        let sharded_storage_admin_2 = sharded_storage_admin.clone();
+        AdminActions::new(admin_secret)
+            .with_action("rebalance_shards", move || {
+                let admin = sharded_storage_admin.clone();
+                let rebalance =
+                    runtime.spawn(async move { ShardedStorageAdmin::rebalance(&admin).await });
+                async move {
+                    rebalance
+                        .await
+                        .map_err(|err| format!("Rebalance task failed: {err}"))?
+                }
+            })
+            .with_action("reload_shards", move || {
+                let admin = sharded_storage_admin_2.clone();
+                let reload = runtime_2.spawn(async move { admin.lock().await.reload().await });
+                async move {
+                    reload
+                        .await
+                        .map_err(|err| format!("Reload task failed: {err}"))?
+                }
+            })
+    };
     let in_flight_requests_counter = InFlightRequestsCounter::new();
     let in_flight_requests_counter_2 = in_flight_requests_counter.clone();
-    let mut shutdown_receiver = setup_infra_endpoints(config.infra.unwrap_or_default(), move || {
-        let count = in_flight_requests_counter_2.get();
-         metrics::gauge!("toolchain_grpc_inflight_requests", count as f64, "service" => "storage_server");
-        scrape_redis_backend_metrics(&redis_backends);
-    })
+    let shutdown_receiver = setup_infra_endpoints(
+        config.infra.unwrap_or_default(),
+        debug_info,
+        admin_actions,
+        move || {
+            let count = in_flight_requests_counter_2.get();
+            metrics::gauge!(
+                "toolchain_grpc_inflight_requests",
+                count as f64,
+                "service" => "storage_server",
+            );
+            scrape_redis_backend_metrics(&redis_backends);
+        },
+    )
     .expect("setup infra endpoints");
-    server
-        .serve_with_incoming_shutdown(
+    let drain_deadline = config
+        .grpc
+        .clone()
+        .unwrap_or_default()
+        .shutdown_drain_deadline();
+    serve_with_drain_deadline(
+        server.serve_with_incoming_shutdown(
             AddrIncomingWithStream(incoming),
-            async move { while shutdown_receiver.changed().await.is_ok() {} },
+            wait_for_shutdown(shutdown_receiver.clone()),
             config.grpc,
-            in_flight_requests_counter,
-        )
-        .await?;
+            in_flight_requests_counter.clone(),
+        ),
+        shutdown_receiver,
+        drain_deadline,
+        in_flight_requests_counter,
+    )
+    .await?;
 
     Ok(())
 }
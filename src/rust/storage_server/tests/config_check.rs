@@ -0,0 +1,153 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn config_check(dir: &Path, config: &str) -> Output {
+    let config_path = dir.join("config.yaml");
+    std::fs::write(&config_path, config).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_storage_server"))
+        .arg("-c")
+        .arg(&config_path)
+        .arg("--config-check")
+        .output()
+        .unwrap()
+}
+
+fn config(password_secret: &Path, action_cache_backend: &str) -> String {
+    format!(
+        r#"
+listen_address: "127.0.0.1:0"
+redis_backends:
+  main:
+    address: "redis.invalid:6379"
+    password_secret: "{}"
+cas: memory
+action_cache:
+  redis_direct:
+    backend: "{action_cache_backend}"
+"#,
+        password_secret.display()
+    )
+}
+
+#[test]
+fn config_check_accepts_valid_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let password_secret = dir.path().join("redis-password");
+    std::fs::write(&password_secret, "hunter2").unwrap();
+
+    let output = config_check(dir.path(), &config(&password_secret, "main"));
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn config_check_rejects_unknown_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    let password_secret = dir.path().join("redis-password");
+    std::fs::write(&password_secret, "hunter2").unwrap();
+
+    let output = config_check(dir.path(), &config(&password_secret, "other"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown Redis backend: other"), "{stderr}");
+}
+
+#[test]
+fn config_check_rejects_missing_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    let password_secret = dir.path().join("redis-password");
+
+    let output = config_check(dir.path(), &config(&password_secret, "main"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("redis-password"), "{stderr}");
+}
+
+#[test]
+fn config_check_reports_parse_errors() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = config_check(
+        dir.path(),
+        "listen_address: \"127.0.0.1:0\"\ncas: [memory\n",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let config_path = dir.path().join("config.yaml");
+    assert!(
+        stderr.contains(&format!("Invalid config file {}", config_path.display())),
+        "{stderr}"
+    );
+    assert!(stderr.contains("line 2 column"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+fn config_check_rejects_invalid_encryption_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_secret = dir.path().join("encryption-key");
+    let config = format!(
+        r#"
+listen_address: "127.0.0.1:0"
+cas:
+  encrypted:
+    key_ref: "{}"
+    underlying: memory
+action_cache: memory
+"#,
+        key_secret.display()
+    );
+
+    std::fs::write(&key_secret, "00".repeat(32)).unwrap();
+    let output = config_check(dir.path(), &config);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    std::fs::write(&key_secret, "00".repeat(16)).unwrap();
+    let output = config_check(dir.path(), &config);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Encryption key must be 32 bytes"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn config_check_rejects_encryption_over_verified_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_ref = dir.path().join("encryption-key");
+    std::fs::write(&key_ref, "00".repeat(32)).unwrap();
+    let config = format!(
+        r#"
+listen_address: "127.0.0.1:0"
+cas:
+  encrypted:
+    key_ref: "{}"
+    underlying:
+      local:
+        base_path: "{}"
+        verify_reads: true
+action_cache: memory
+"#,
+        key_ref.display(),
+        dir.path().display()
+    );
+
+    let output = config_check(dir.path(), &config);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("encrypted storage cannot wrap read_digest_verifier"),
+        "{stderr}"
+    );
+}
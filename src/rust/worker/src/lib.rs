@@ -3,6 +3,9 @@
 
 #![deny(warnings)]
 
+pub mod selftest;
+
+use std::io::Write;
 use std::path::Path;
 
 use futures::future::try_join_all;
@@ -24,6 +27,27 @@ fn log_level_to_buildbox_level(level: Level) -> &'static str {
     }
 }
 
+/// Read an auth token from the given file, ignoring surrounding whitespace (such as the trailing
+/// newline of a mounted secret).
+pub fn read_auth_token_file(path: &Path) -> Result<String, String> {
+    let token = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read auth token file {}: {e}", path.display()))?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(format!("Auth token file {} is empty", path.display()));
+    }
+    Ok(token.to_owned())
+}
+
+/// Write the given auth token to a temporary file, which is passed to buildbox processes.
+pub fn auth_token_file(token: &str) -> Result<tempfile::NamedTempFile, String> {
+    let mut file = tempfile::NamedTempFile::new()
+        .map_err(|e| format!("Failed to create auth token file: {e}"))?;
+    file.write_all(token.as_bytes())
+        .map_err(|e| format!("Failed to write auth token file: {e}"))?;
+    Ok(file)
+}
+
 #[derive(Clone, Debug)]
 pub struct ProcessSpec {
     name: String,
@@ -38,11 +62,31 @@ impl ProcessSpec {
     }
 }
 
-/// Generate the list of processes which will be managed by the worker.
+/// A pool of identical workers, which register with the given platform properties.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolSpec {
+    /// The number of workers in the pool.
+    pub concurrency: u16,
+    /// The platform properties, as `(name, value)` pairs, which the workers register with.
+    pub platform_properties: Vec<(String, String)>,
+}
+
+impl PoolSpec {
+    /// A pool of Linux workers with no other platform properties.
+    pub fn linux(concurrency: u16) -> Self {
+        Self {
+            concurrency,
+            platform_properties: vec![("OSFamily".to_owned(), "linux".to_owned())],
+        }
+    }
+}
+
+/// Generate the list of processes which will be managed by the worker: a single casd, and the
+/// workers of each of the given pools.
 #[allow(clippy::too_many_arguments)]
 pub fn processes(
     instance: &str,
-    worker_concurrency: u16,
+    pools: Vec<PoolSpec>,
     worker_timeout: u64,
     workers_endpoint: &str,
     log_level: Level,
@@ -74,7 +118,15 @@ pub fn processes(
         args_factory: Vec::new,
     }];
 
-    processes.extend((0..worker_concurrency).map(|worker_num| {
+    let workers = pools.into_iter().flat_map(|pool| {
+        let platform_args: Vec<String> = pool
+            .platform_properties
+            .iter()
+            .map(|(name, value)| format!("--platform={name}={value}"))
+            .collect();
+        (0..pool.concurrency).map(move |_| platform_args.clone())
+    });
+    processes.extend(workers.enumerate().map(|(worker_num, platform_args)| {
         let mut args = vec![
             "--buildbox-run=buildbox-run-hosttools".to_owned(),
             "--cas-remote=http://127.0.0.1:50011".to_owned(),
@@ -82,9 +134,9 @@ pub fn processes(
             format!("--instance={instance}"),
             format!("--bots-request-timeout={worker_timeout}"),
             format!("--runner-arg=--log-level={buildbox_level}"),
-            "--platform=OSFamily=linux".to_owned(),
-            format!("--log-level={buildbox_level}"),
         ];
+        args.extend(platform_args);
+        args.push(format!("--log-level={buildbox_level}"));
 
         if let Some(auth_token) = auth_token {
             args.push(format!(
@@ -106,6 +158,25 @@ pub fn processes(
     processes
 }
 
+/// Render the command line which each of the given processes would be spawned with, prefixed by
+/// the process name. Processes which generate some of their arguments each time they are spawned
+/// are rendered with one sample of those arguments.
+///
+/// NB: Auth tokens are only ever passed to processes as the path of a file containing the token,
+/// so the token itself is never rendered.
+pub fn command_lines(processes: &[ProcessSpec]) -> Vec<String> {
+    processes
+        .iter()
+        .map(|process| {
+            let command_line = std::iter::once(process.arg0.clone())
+                .chain(process.args())
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("{}: {command_line}", process.name)
+        })
+        .collect()
+}
+
 /// Spawns and parents the given list of processes, exiting for SIGINT or SIGTERM.
 pub async fn spawn_and_manage_processes(processes: Vec<ProcessSpec>) -> Result<(), String> {
     let _ = try_join_all(
@@ -146,3 +217,141 @@ async fn spawn_and_manage_process(process_spec: ProcessSpec) {
         sleep(delay).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+
+    use log::Level;
+
+    use super::{auth_token_file, command_lines, processes, read_auth_token_file, PoolSpec};
+
+    #[test]
+    fn renders_command_lines() {
+        let mut auth_token = tempfile::NamedTempFile::new().unwrap();
+        auth_token.write_all(b"secret-token").unwrap();
+        let token_path = auth_token.path().display().to_string();
+
+        let lines = command_lines(&processes(
+            "my-org",
+            vec![PoolSpec::linux(2)],
+            30,
+            "grpcs://workers.example.com:8981",
+            Level::Warn,
+            Some(&auth_token),
+            Path::new("/toolchain/cache"),
+            "30G",
+        ));
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            format!(
+                "casd: buildbox-casd --bind=127.0.0.1:50011 \
+                 --cas-remote=grpcs://workers.example.com:8981 --instance=my-org \
+                 --cas-instance=my-org --quota-high=30G --cas-access-token={token_path} \
+                 /toolchain/cache"
+            )
+        );
+        for (worker_num, line) in lines[1..].iter().enumerate() {
+            let expected_prefix = format!(
+                "worker {worker_num}: buildbox-worker --buildbox-run=buildbox-run-hosttools \
+                 --cas-remote=http://127.0.0.1:50011 \
+                 --bots-remote=grpcs://workers.example.com:8981 --instance=my-org \
+                 --bots-request-timeout=30 --runner-arg=--log-level=warning \
+                 --platform=OSFamily=linux --log-level=warning \
+                 --bots-access-token={token_path} worker-"
+            );
+            assert!(
+                line.starts_with(&expected_prefix),
+                "{line} did not start with {expected_prefix}"
+            );
+        }
+        assert!(lines.iter().all(|line| !line.contains("secret-token")));
+    }
+
+    #[test]
+    fn auth_token_from_file() {
+        let mut mounted_secret = tempfile::NamedTempFile::new().unwrap();
+        mounted_secret.write_all(b"secret-token\n").unwrap();
+
+        let token = read_auth_token_file(mounted_secret.path()).unwrap();
+        assert_eq!(token, "secret-token");
+        let auth_token = auth_token_file(&token).unwrap();
+
+        // Both buildbox processes are pointed at a file containing exactly the token.
+        let processes = processes(
+            "my-org",
+            vec![PoolSpec::linux(1)],
+            30,
+            "grpcs://workers.example.com:8981",
+            Level::Info,
+            Some(&auth_token),
+            Path::new("/toolchain/cache"),
+            "30G",
+        );
+        let token_paths: Vec<String> = processes
+            .iter()
+            .flat_map(|process| process.args())
+            .filter_map(|arg| {
+                arg.strip_prefix("--cas-access-token=")
+                    .or_else(|| arg.strip_prefix("--bots-access-token="))
+                    .map(str::to_owned)
+            })
+            .collect();
+        assert_eq!(token_paths.len(), 2);
+        for token_path in token_paths {
+            assert_eq!(std::fs::read_to_string(token_path).unwrap(), "secret-token");
+        }
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        assert!(read_auth_token_file(empty.path()).is_err());
+        assert!(read_auth_token_file(Path::new("/does/not/exist")).is_err());
+    }
+
+    #[test]
+    fn generates_workers_per_pool() {
+        let gpu_pool = PoolSpec {
+            concurrency: 2,
+            platform_properties: vec![
+                ("OSFamily".to_owned(), "linux".to_owned()),
+                ("gpu".to_owned(), "nvidia-t4".to_owned()),
+            ],
+        };
+        let processes = processes(
+            "my-org",
+            vec![PoolSpec::linux(1), gpu_pool],
+            30,
+            "grpcs://workers.example.com:8981",
+            Level::Info,
+            None,
+            Path::new("/toolchain/cache"),
+            "30G",
+        );
+
+        // A single casd, followed by the workers of each pool in order.
+        let names: Vec<&str> = processes.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["casd", "worker 0", "worker 1", "worker 2"]);
+        let platform_args: Vec<Vec<String>> = processes[1..]
+            .iter()
+            .map(|process| {
+                process
+                    .args()
+                    .into_iter()
+                    .filter(|arg| arg.starts_with("--platform="))
+                    .collect()
+            })
+            .collect();
+        let linux = "--platform=OSFamily=linux".to_owned();
+        let gpu = "--platform=gpu=nvidia-t4".to_owned();
+        assert_eq!(
+            platform_args,
+            vec![
+                vec![linux.clone()],
+                vec![linux.clone(), gpu.clone()],
+                vec![linux, gpu],
+            ]
+        );
+    }
+}
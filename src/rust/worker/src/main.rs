@@ -3,12 +3,12 @@
 
 #![deny(warnings)]
 
-use std::io::Write;
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use log::Level;
 use tokio::fs::create_dir_all;
+use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Parser)]
 #[command(name = "Toolchain Remote Execution Worker")]
@@ -21,21 +21,33 @@ struct WorkerCommand {
     /// asking Toolchain for the value.
     #[arg(short, long, env, required = true)]
     org_id: String,
+    /// The REAPI instance name to execute and cache under. Defaults to the `--org-id`.
+    #[arg(long, env)]
+    instance: Option<String>,
     /// The address to the remote execution controller. If grpcs is used (highly recommended), an
-    /// auth token must be set; see `--auth-token-env-var-name`.
+    /// auth token must be set; see `--auth-token-env-var-name` and `--auth-token-file`.
     #[arg(long, env, default_value = "grpcs://workers.toolchain.com:8981")]
     endpoint: String,
     /// The environment variable name to read for the auth token. This is used to authenticate to
     /// the `--endpoint`.
     ///
-    /// If not set, will default to looking at the env var `AUTH_TOKEN`. If `AUTH_TOKEN` is set,
-    /// then it will be used; else, the auth token mechanism will be ignored.
+    /// If neither this nor `--auth-token-file` is set, will default to looking at the env var
+    /// `AUTH_TOKEN`. If `AUTH_TOKEN` is set, then it will be used; else, the auth token mechanism
+    /// will be ignored.
     #[arg(long, env)]
     auth_token_env_var_name: Option<String>,
+    /// A file to read the auth token from, such as a mounted Kubernetes secret. This is used to
+    /// authenticate to the `--endpoint`, and may not be combined with `--auth-token-env-var-name`.
+    #[arg(long, env, conflicts_with = "auth_token_env_var_name")]
+    auth_token_file: Option<PathBuf>,
     /// The log level to use for this process's own logging: `info`, `warn`, `error`, `debug`, or
     /// `trace`.
     #[arg(short, long, env, default_value = "info")]
     log_level: String,
+    /// The format of this process's own logging: `text`, or `json` for one structured object per
+    /// line (with `timestamp`, `level`, `target` and `fields` keys).
+    #[arg(long, env, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
     /// The number of workers processes to spawn. Each worker is able to execute one remote
     /// execution process at a time.
     #[arg(short, long, env, default_value_t = 1)]
@@ -50,62 +62,192 @@ struct WorkerCommand {
     /// How much storage the cache can use. Expects a value in the format `30G`.
     #[arg(long, env, default_value = "30G")]
     max_cache_size: String,
+    /// Print the command line of each process which would be spawned, and then exit without
+    /// spawning them.
+    #[arg(long)]
+    dry_run: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cmd = WorkerCommand::parse();
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
 
-    let log_level: Level = cmd.log_level.parse()?;
-    stderrlog::new()
-        .show_module_names(true)
-        .timestamp(stderrlog::Timestamp::Second)
-        .verbosity(log_level)
-        .init()?;
-
-    let cache_directory = {
-        let d = cmd.cache_directory.as_path();
-        log::debug!("Setting up cache directory at {}", d.display());
-        create_dir_all(d).await?;
-        d
-    };
-
-    let auth_token_file = {
-        let maybe_token = match cmd.auth_token_env_var_name {
-            Some(env_var) => {
-                let res = std::env::var(&env_var).map_err(|e| {
+#[derive(Subcommand)]
+enum Command {
+    /// Verify that the `--endpoint` is reachable and accepts the auth token, and then exit
+    /// without spawning any processes. Exits non-zero on failure, e.g. for use as a startup probe.
+    Selftest,
+}
+
+impl WorkerCommand {
+    fn instance_name(&self) -> &str {
+        self.instance.as_deref().unwrap_or(&self.org_id)
+    }
+
+    /// Read the auth token from the configured source, if any.
+    fn auth_token(&self) -> Result<Option<String>, String> {
+        match (&self.auth_token_env_var_name, &self.auth_token_file) {
+            (Some(env_var), _) => {
+                let res = std::env::var(env_var).map_err(|e| {
                     format!(
                         "Issue evaluating the option `--auth-token-env-var-name={env_var}`: {e}"
                     )
                 })?;
-                Some(res)
+                log::info!("Using auth token from environment variable");
+                Ok(Some(res))
+            }
+            (None, Some(path)) => {
+                let res = worker::read_auth_token_file(path)?;
+                log::info!("Using auth token from file");
+                Ok(Some(res))
+            }
+            // If neither option is set, use AUTH_TOKEN if defined. Else, don't use auth tokens.
+            (None, None) => {
+                let res = std::env::var("AUTH_TOKEN").ok();
+                if res.is_some() {
+                    log::info!("Using auth token from environment variable");
+                }
+                Ok(res)
             }
-            // If the option is unset, use AUTH_TOKEN if defined. Else, don't use auth tokens.
-            None => std::env::var("AUTH_TOKEN").ok(),
-        };
-        if let Some(token) = maybe_token {
-            log::info!("Using auth token from environment variable");
-            let mut file = tempfile::NamedTempFile::new()?;
-            file.write_all(token.as_bytes())?;
-            Some(file)
-        } else {
-            None
         }
-    };
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cmd = WorkerCommand::parse();
+
+    let log_level: Level = cmd.log_level.parse()?;
+    match cmd.log_format {
+        LogFormat::Text => stderrlog::new()
+            .show_module_names(true)
+            .timestamp(stderrlog::Timestamp::Second)
+            .verbosity(log_level)
+            .init()?,
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_writer(std::io::stderr)
+            .with_max_level(cmd.log_level.parse::<LevelFilter>()?)
+            .try_init()
+            .map_err(|err| format!("Failed to set up logging: {err}"))?,
+    }
+
+    let auth_token = cmd.auth_token()?;
+
+    if let Some(Command::Selftest) = cmd.command {
+        worker::selftest::selftest(&cmd.endpoint, cmd.instance_name(), auth_token.as_deref())
+            .await?;
+        log::info!("Self-test against {} succeeded.", cmd.endpoint);
+        return Ok(());
+    }
+
+    let auth_token_file = auth_token
+        .map(|token| worker::auth_token_file(&token))
+        .transpose()?;
 
     let processes = worker::processes(
-        &cmd.org_id,
-        cmd.worker_concurrency,
+        cmd.instance_name(),
+        vec![worker::PoolSpec::linux(cmd.worker_concurrency)],
         cmd.request_timeout,
         &cmd.endpoint,
         log_level,
         auth_token_file.as_ref(),
-        cache_directory,
+        &cmd.cache_directory,
         &cmd.max_cache_size,
     );
 
+    if cmd.dry_run {
+        for command_line in worker::command_lines(&processes) {
+            println!("{command_line}");
+        }
+        return Ok(());
+    }
+
+    log::debug!(
+        "Setting up cache directory at {}",
+        cmd.cache_directory.display()
+    );
+    create_dir_all(&cmd.cache_directory).await?;
+
     log::info!("Starting {} worker(s).", cmd.worker_concurrency);
     worker::spawn_and_manage_processes(processes).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use log::Level;
+
+    use super::{LogFormat, WorkerCommand};
+
+    fn command_lines(args: &[&str]) -> Vec<String> {
+        let cmd = WorkerCommand::try_parse_from(args).unwrap();
+        worker::command_lines(&worker::processes(
+            cmd.instance_name(),
+            vec![worker::PoolSpec::linux(cmd.worker_concurrency)],
+            cmd.request_timeout,
+            &cmd.endpoint,
+            Level::Info,
+            None,
+            &cmd.cache_directory,
+            &cmd.max_cache_size,
+        ))
+    }
+
+    #[test]
+    fn instance_defaults_to_org_id() {
+        for line in command_lines(&["worker", "--org-id=my-org"]) {
+            assert!(line.contains(" --instance=my-org "), "{line}");
+        }
+    }
+
+    #[test]
+    fn instance_overrides_org_id() {
+        let lines = command_lines(&["worker", "--org-id=my-org", "--instance=reapi-instance"]);
+        assert!(lines[0].contains(" --cas-instance=reapi-instance "));
+        for line in lines {
+            assert!(line.contains(" --instance=reapi-instance "), "{line}");
+            assert!(!line.contains("my-org"), "{line}");
+        }
+    }
+
+    #[test]
+    fn log_format_defaults_to_text() {
+        let cmd = WorkerCommand::try_parse_from(["worker", "--org-id=my-org"]).unwrap();
+        assert_eq!(cmd.log_format, LogFormat::Text);
+
+        let cmd = WorkerCommand::try_parse_from(["worker", "--org-id=my-org", "--log-format=json"])
+            .unwrap();
+        assert_eq!(cmd.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn auth_token_options_are_mutually_exclusive() {
+        let cmd = WorkerCommand::try_parse_from([
+            "worker",
+            "--org-id=my-org",
+            "--auth-token-file=/secrets/token",
+        ])
+        .unwrap();
+        assert_eq!(
+            cmd.auth_token_file.as_deref(),
+            Some(std::path::Path::new("/secrets/token"))
+        );
+
+        let err = WorkerCommand::try_parse_from([
+            "worker",
+            "--org-id=my-org",
+            "--auth-token-env-var-name=MY_TOKEN",
+            "--auth-token-file=/secrets/token",
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2023 Toolchain Labs, Inc. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use protos::build::bazel::remote::execution::v2::capabilities_client::CapabilitiesClient;
+use protos::build::bazel::remote::execution::v2::GetCapabilitiesRequest;
+use tokio::time::Duration;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{ClientTlsConfig, Endpoint};
+use tonic::Request;
+
+/// How long the self-test waits to connect to the endpoint, and then for its response.
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Convert a `grpc://` or `grpcs://` endpoint, as passed to buildbox, into an Endpoint which uses
+/// TLS for `grpcs://`.
+pub fn endpoint(endpoint: &str) -> Result<Endpoint, String> {
+    let (scheme, authority) = endpoint
+        .split_once("://")
+        .ok_or_else(|| format!("Endpoint `{endpoint}` did not start with a scheme"))?;
+    let use_tls = match scheme {
+        "grpc" | "http" => false,
+        "grpcs" | "https" => true,
+        _ => {
+            return Err(format!(
+            "Endpoint `{endpoint}` has unsupported scheme `{scheme}`: expected `grpc` or `grpcs`"
+        ))
+        }
+    };
+    let uri = format!("{}://{authority}", if use_tls { "https" } else { "http" });
+    let endpoint = Endpoint::from_shared(uri)
+        .map_err(|e| format!("Invalid endpoint `{endpoint}`: {e}"))?
+        .connect_timeout(SELFTEST_TIMEOUT)
+        .timeout(SELFTEST_TIMEOUT);
+    if !use_tls {
+        return Ok(endpoint);
+    }
+
+    let domain_name = endpoint
+        .uri()
+        .host()
+        .ok_or_else(|| format!("Endpoint `{authority}` did not include a host"))?
+        .to_owned();
+    endpoint
+        .tls_config(ClientTlsConfig::new().domain_name(domain_name))
+        .map_err(|e| format!("Failed to configure TLS for `{authority}`: {e}"))
+}
+
+/// The `authorization` header value to authenticate with the given auth token.
+pub fn authorization_header(auth_token: &str) -> Result<MetadataValue<Ascii>, String> {
+    format!("Bearer {auth_token}")
+        .parse()
+        .map_err(|_| "Auth token contains characters which are not valid in a header".to_owned())
+}
+
+/// Verify that the endpoint is reachable, and accepts the auth token (if any) for the instance, by
+/// requesting its capabilities.
+pub async fn selftest(
+    endpoint_address: &str,
+    instance: &str,
+    auth_token: Option<&str>,
+) -> Result<(), String> {
+    let authorization = auth_token.map(authorization_header).transpose()?;
+    let channel = endpoint(endpoint_address)?
+        .connect()
+        .await
+        .map_err(|e| format!("Failed to connect to `{endpoint_address}`: {e}"))?;
+    let mut request = Request::new(GetCapabilitiesRequest {
+        instance_name: instance.to_owned(),
+    });
+    if let Some(authorization) = authorization {
+        request
+            .metadata_mut()
+            .insert("authorization", authorization);
+    }
+    CapabilitiesClient::new(channel)
+        .get_capabilities(request)
+        .await
+        .map_err(|status| {
+            format!(
+                "`GetCapabilities` for instance `{instance}` failed against `{endpoint_address}`: {status}"
+            )
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+
+    use protos::build::bazel::remote::execution::v2::capabilities_server::{
+        Capabilities, CapabilitiesServer,
+    };
+    use protos::build::bazel::remote::execution::v2::{GetCapabilitiesRequest, ServerCapabilities};
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+
+    use super::{authorization_header, endpoint, selftest};
+
+    /// Accepts only requests for `my-org` which are authenticated with `valid-token`.
+    struct MockCapabilitiesService;
+
+    #[tonic::async_trait]
+    impl Capabilities for MockCapabilitiesService {
+        async fn get_capabilities(
+            &self,
+            request: Request<GetCapabilitiesRequest>,
+        ) -> Result<Response<ServerCapabilities>, Status> {
+            let authorization = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok());
+            if authorization != Some("Bearer valid-token") {
+                return Err(Status::unauthenticated("invalid auth token"));
+            }
+            if request.get_ref().instance_name != "my-org" {
+                return Err(Status::permission_denied("unknown instance"));
+            }
+            Ok(Response::new(ServerCapabilities::default()))
+        }
+    }
+
+    /// Find a port which nothing is listening on (yet).
+    fn unused_addr() -> SocketAddr {
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn converts_endpoints() {
+        assert_eq!(
+            endpoint("grpcs://workers.toolchain.com:8981")
+                .unwrap()
+                .uri()
+                .to_string(),
+            "https://workers.toolchain.com:8981/"
+        );
+        assert_eq!(
+            endpoint("grpc://127.0.0.1:8980").unwrap().uri().to_string(),
+            "http://127.0.0.1:8980/"
+        );
+        assert!(endpoint("workers.toolchain.com:8981").is_err());
+        assert!(endpoint("ftp://workers.toolchain.com:8981").is_err());
+    }
+
+    #[test]
+    fn formats_authorization_header() {
+        assert_eq!(
+            authorization_header("valid-token").unwrap(),
+            "Bearer valid-token"
+        );
+        assert!(authorization_header("invalid\ntoken").is_err());
+    }
+
+    #[tokio::test]
+    async fn checks_reachability_and_auth() {
+        let addr = unused_addr();
+        tokio::spawn(
+            Server::builder()
+                .add_service(CapabilitiesServer::new(MockCapabilitiesService))
+                .serve(addr),
+        );
+        let endpoint = format!("grpc://{addr}");
+
+        // Retry while the server starts.
+        let mut attempts = 0;
+        while let Err(e) = selftest(&endpoint, "my-org", Some("valid-token")).await {
+            attempts += 1;
+            assert!(attempts < 50, "selftest did not succeed: {e}");
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+
+        let err = selftest(&endpoint, "my-org", Some("invalid-token"))
+            .await
+            .unwrap_err();
+        assert!(err.contains("invalid auth token"), "{err}");
+        let err = selftest(&endpoint, "my-org", None).await.unwrap_err();
+        assert!(err.contains("invalid auth token"), "{err}");
+        let err = selftest(&endpoint, "other-org", Some("valid-token"))
+            .await
+            .unwrap_err();
+        assert!(err.contains("unknown instance"), "{err}");
+
+        let err = selftest(&format!("grpc://{}", unused_addr()), "my-org", None)
+            .await
+            .unwrap_err();
+        assert!(err.contains("Failed to connect"), "{err}");
+    }
+}